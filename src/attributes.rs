@@ -0,0 +1,541 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io;
+use std::path;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use anyhow::anyhow;
+
+use crate::config;
+use crate::diff;
+use crate::ignore;
+
+/// Attributes of paths, from `.gitattributes` files, `.git/info/attributes`,
+/// and `core.attributesFile`, like `git`.
+///
+/// For each attribute, `.git/info/attributes` takes precedence, then the
+/// `.gitattributes` in the path's own directory and those of each ancestor
+/// in turn, and finally `core.attributesFile`. Within a file, later lines
+/// take precedence over earlier ones.
+///
+/// Macros, defined with `[attr]<name> <attributes>...` in the top-level
+/// files, assign their attributes wherever they are set. The `binary` macro
+/// is built in as `-diff -merge -text`.
+#[derive(Debug, Default)]
+pub struct Attributes {
+    /// Workspace root, or `None` if no attributes files should be read.
+    root: Option<path::PathBuf>,
+    config: config::Config,
+    /// Line ending that `core.autocrlf` converts text files to, if enabled.
+    autocrlf: Option<Eol>,
+    /// Line ending of text files from `core.eol`.
+    eol: Eol,
+    safecrlf: SafeCrlf,
+    macros: HashMap<String, Vec<(String, State)>>,
+    /// Rules from `core.attributesFile`.
+    global: Vec<Rule>,
+    /// Rules from `.git/info/attributes`.
+    info: Vec<Rule>,
+    /// Rules from `.gitattributes` files, keyed by their relative directory
+    /// and loaded as paths beneath them are checked.
+    directories: Mutex<HashMap<path::PathBuf, Arc<Vec<Rule>>>>,
+}
+
+/// State of one attribute for a path.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum State {
+    /// Written as `<name>`.
+    Set,
+    /// Written as `-<name>`.
+    Unset,
+    /// Written as `<name>=<value>`.
+    Value(String),
+    /// Written as `!<name>`, or not assigned by any matching line.
+    Unspecified,
+}
+
+/// Attributes assigned to one path.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Values(HashMap<String, State>);
+
+impl Values {
+    pub fn get(&self, name: &str) -> &State {
+        self.0.get(name).unwrap_or(&State::Unspecified)
+    }
+}
+
+/// A line of an attributes file: a pattern and the attributes it assigns.
+#[derive(Clone, Debug)]
+struct Rule {
+    pattern: ignore::Pattern,
+    states: Vec<(String, State)>,
+}
+
+/// Line ending of text files in the workspace.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+enum Eol {
+    #[default]
+    Lf,
+    Crlf,
+}
+
+/// Line endings that would change if a file were stored and checked out
+/// again.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Lossy {
+    CrlfToLf,
+    LfToCrlf,
+}
+
+impl Lossy {
+    /// Line endings before and after the round trip.
+    pub fn endings(&self) -> (&'static str, &'static str) {
+        match self {
+            Lossy::CrlfToLf => ("CRLF", "LF"),
+            Lossy::LfToCrlf => ("LF", "CRLF"),
+        }
+    }
+}
+
+/// How to handle files whose line endings wouldn't survive a round trip.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum SafeCrlf {
+    Off,
+    #[default]
+    Warn,
+    Fail,
+}
+
+/// Whether to normalize line endings, from the `text` attribute.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Text {
+    Always,
+    /// Only for files that don't look binary.
+    Auto,
+    Never,
+}
+
+impl Attributes {
+    /// Read the repository- and user-wide attributes files, along with
+    /// `.gitattributes` at `root`, which may define macros. Files in other
+    /// directories are loaded as needed by [`Attributes::get`].
+    pub fn open(root: &path::Path, config: &config::Config) -> anyhow::Result<Self> {
+        let autocrlf = match config.get("core.autocrlf") {
+            Some(value) if value.eq_ignore_ascii_case("input") => Some(Eol::Lf),
+            _ => match config.get_bool("core.autocrlf")? {
+                Some(true) => Some(Eol::Crlf),
+                Some(false) | None => None,
+            },
+        };
+        let eol = match config.get("core.eol") {
+            None | Some("lf") | Some("native") => Eol::Lf,
+            Some("crlf") => Eol::Crlf,
+            Some(other) => return Err(anyhow!("Invalid core.eol: {}", other)),
+        };
+        let safecrlf = match config.get("core.safecrlf") {
+            Some(value) if value.eq_ignore_ascii_case("warn") => SafeCrlf::Warn,
+            _ => match config.get_bool("core.safecrlf")? {
+                Some(true) => SafeCrlf::Fail,
+                Some(false) => SafeCrlf::Off,
+                None => SafeCrlf::Warn,
+            },
+        };
+
+        let mut attributes = Attributes {
+            root: Some(root.to_path_buf()),
+            config: config.clone(),
+            autocrlf,
+            eol,
+            safecrlf,
+            ..Attributes::default()
+        };
+        attributes.macros.insert(
+            String::from("binary"),
+            parse_states(&["-diff", "-merge", "-text"]),
+        );
+
+        let mut read = |path: Option<path::PathBuf>, base: &path::Path| -> io::Result<Vec<Rule>> {
+            let text = match path {
+                Some(path) => ignore::read(&path)?.unwrap_or_default(),
+                None => String::new(),
+            };
+            Ok(parse(&text, base, Some(&mut attributes.macros)))
+        };
+
+        let base = path::Path::new("");
+        let global = read(
+            ignore::user_file(config, "core.attributesFile", "attributes"),
+            base,
+        )?;
+        let top = read(Some(root.join(".gitattributes")), base)?;
+        let info = read(Some(root.join(".git/info/attributes")), base)?;
+
+        attributes.global = global;
+        attributes.info = info;
+        attributes
+            .directories
+            .get_mut()
+            .expect("not shared yet")
+            .insert(base.to_path_buf(), Arc::new(top));
+        Ok(attributes)
+    }
+
+    /// Look up the attributes of `path`, relative to the workspace root.
+    pub fn get(&self, path: &path::Path) -> io::Result<Values> {
+        let mut values = Values::default();
+        if self.root.is_none() {
+            return Ok(values);
+        }
+
+        let mut directories = path
+            .ancestors()
+            .skip(1)
+            .map(|directory| self.load(directory))
+            .collect::<io::Result<Vec<_>>>()?;
+        directories.reverse();
+
+        let rules = self
+            .global
+            .iter()
+            .chain(directories.iter().flat_map(|rules| rules.iter()))
+            .chain(&self.info);
+
+        for rule in rules {
+            if rule.pattern.matches(path, false) {
+                for (name, state) in &rule.states {
+                    self.assign(&mut values, name, state, 0);
+                }
+            }
+        }
+        Ok(values)
+    }
+
+    /// Assign `state` to attribute `name`, expanding macros that are set,
+    /// up to a fixed depth in case they refer to each other.
+    fn assign(&self, values: &mut Values, name: &str, state: &State, depth: usize) {
+        if let (State::Set, Some(states)) = (state, self.macros.get(name)) {
+            if depth < 8 {
+                for (name, state) in states {
+                    self.assign(values, name, state, depth + 1);
+                }
+            }
+        }
+        values.0.insert(name.to_owned(), state.clone());
+    }
+
+    /// Rules from the `.gitattributes` in `directory`, reading it if needed.
+    fn load(&self, directory: &path::Path) -> io::Result<Arc<Vec<Rule>>> {
+        let root = match &self.root {
+            Some(root) => root,
+            None => return Ok(Arc::default()),
+        };
+        if let Some(rules) = self.directories().get(directory) {
+            return Ok(Arc::clone(rules));
+        }
+
+        let text = ignore::read(&root.join(directory).join(".gitattributes"))?;
+        let rules = Arc::new(parse(&text.unwrap_or_default(), directory, None));
+        self.directories()
+            .insert(directory.to_path_buf(), Arc::clone(&rules));
+        Ok(rules)
+    }
+
+    fn directories(&self) -> std::sync::MutexGuard<'_, HashMap<path::PathBuf, Arc<Vec<Rule>>>> {
+        self.directories
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Whether to show `path` as binary in diffs: `Some(true)` if its `diff`
+    /// attribute is unset or names a driver with `diff.<driver>.binary`,
+    /// `Some(false)` if it is otherwise set, and `None` to detect binary
+    /// files from their contents.
+    pub fn is_binary(&self, path: &path::Path) -> anyhow::Result<Option<bool>> {
+        match self.get(path)?.get("diff") {
+            State::Set => Ok(Some(false)),
+            State::Unset => Ok(Some(true)),
+            State::Value(driver) => Ok(self
+                .config
+                .get_bool(&format!("diff.{}.binary", driver))?
+                .or(Some(false))),
+            State::Unspecified => Ok(None),
+        }
+    }
+
+    /// Program to compare versions of `path` with instead of the built-in
+    /// diff, from `diff.<driver>.command` if its `diff` attribute names a
+    /// driver.
+    pub fn diff_command(&self, path: &path::Path) -> io::Result<Option<String>> {
+        Ok(self.diff_driver(path)?.and_then(|driver| {
+            self.config
+                .get(&format!("diff.{}.command", driver))
+                .map(String::from)
+        }))
+    }
+
+    /// Diff driver named by the `diff` attribute of `path`, if any.
+    pub fn diff_driver(&self, path: &path::Path) -> io::Result<Option<String>> {
+        match self.get(path)?.get("diff") {
+            State::Value(driver) => Ok(Some(driver.clone())),
+            _ => Ok(None),
+        }
+    }
+
+    /// Convert the workspace contents `data` of `path` for storage in the
+    /// repository, normalizing CRLF line endings to LF in text files.
+    pub fn clean<'a>(&self, path: &path::Path, data: &'a [u8]) -> io::Result<Cow<'a, [u8]>> {
+        if self.root.is_none() {
+            return Ok(Cow::Borrowed(data));
+        }
+        let values = self.get(path)?;
+        let normalize = match self.text(&values) {
+            Text::Always => data.contains(&b'\r'),
+            Text::Auto => data.contains(&b'\r') && !is_binary(data),
+            Text::Never => false,
+        };
+        match normalize {
+            false => Ok(Cow::Borrowed(data)),
+            true => Ok(Cow::Owned(to_lf(data))),
+        }
+    }
+
+    /// Check whether [`Attributes::clean`] or [`Attributes::smudge`] may
+    /// change the contents of `path`, i.e. whether it may be treated as text.
+    pub fn converts(&self, path: &path::Path) -> io::Result<bool> {
+        if self.root.is_none() {
+            return Ok(false);
+        }
+        let values = self.get(path)?;
+        Ok(self.text(&values) != Text::Never)
+    }
+
+    /// Check whether storing the workspace contents `data` of `path` and
+    /// checking them out again would change their line endings.
+    pub fn check_round_trip(&self, path: &path::Path, data: &[u8]) -> io::Result<Option<Lossy>> {
+        let clean = self.clean(path, data)?;
+        if *self.smudge(path, &clean)? == *data {
+            return Ok(None);
+        }
+        match data.windows(2).any(|pair| pair == b"\r\n") {
+            true => Ok(Some(Lossy::CrlfToLf)),
+            false => Ok(Some(Lossy::LfToCrlf)),
+        }
+    }
+
+    /// How to handle files whose line endings wouldn't survive a round trip,
+    /// from `core.safecrlf`.
+    pub fn safecrlf(&self) -> SafeCrlf {
+        self.safecrlf
+    }
+
+    /// Convert the stored contents `data` of `path` for the workspace,
+    /// changing LF line endings in text files to CRLF if configured by the
+    /// `eol` attribute, `core.autocrlf`, or `core.eol`.
+    pub fn smudge<'a>(&self, path: &path::Path, data: &'a [u8]) -> io::Result<Cow<'a, [u8]>> {
+        if self.root.is_none() {
+            return Ok(Cow::Borrowed(data));
+        }
+        let values = self.get(path)?;
+        let convert = match self.text(&values) {
+            Text::Always => true,
+            Text::Auto => !is_binary(data),
+            Text::Never => false,
+        };
+        match convert && self.eol(&values) == Eol::Crlf {
+            false => Ok(Cow::Borrowed(data)),
+            true => Ok(Cow::Owned(to_crlf(data))),
+        }
+    }
+
+    fn text(&self, values: &Values) -> Text {
+        match values.get("text") {
+            State::Set => Text::Always,
+            State::Unset => Text::Never,
+            State::Value(value) if value == "auto" => Text::Auto,
+            // Like `git`, setting `eol` implies `text`.
+            _ if matches!(values.get("eol"), State::Value(_)) => Text::Always,
+            _ if self.autocrlf.is_some() => Text::Auto,
+            _ => Text::Never,
+        }
+    }
+
+    fn eol(&self, values: &Values) -> Eol {
+        match values.get("eol") {
+            State::Value(value) if value == "crlf" => Eol::Crlf,
+            State::Value(value) if value == "lf" => Eol::Lf,
+            _ => self.autocrlf.unwrap_or(self.eol),
+        }
+    }
+}
+
+/// Parse every line of an attributes file in `base`, collecting macro
+/// definitions into `macros` if they're allowed there.
+fn parse(
+    text: &str,
+    base: &path::Path,
+    mut macros: Option<&mut HashMap<String, Vec<(String, State)>>>,
+) -> Vec<Rule> {
+    let mut rules = Vec::new();
+    for line in text.lines() {
+        let mut words = line.split_whitespace();
+        let pattern = match words.next() {
+            None => continue,
+            Some(pattern) if pattern.starts_with('#') => continue,
+            Some(pattern) => pattern,
+        };
+        let states = parse_states(&words.collect::<Vec<_>>());
+
+        if let Some(name) = pattern.strip_prefix("[attr]") {
+            if let Some(macros) = macros.as_deref_mut() {
+                macros.insert(name.to_owned(), states);
+            }
+            continue;
+        }
+
+        // Like `git`, ignore negative patterns, which would be confusing.
+        if pattern.starts_with('!') {
+            continue;
+        }
+        if let Some(pattern) = ignore::Pattern::parse(pattern, base) {
+            rules.push(Rule { pattern, states });
+        }
+    }
+    rules
+}
+
+fn parse_states(words: &[&str]) -> Vec<(String, State)> {
+    words
+        .iter()
+        .map(|word| {
+            if let Some(name) = word.strip_prefix('-') {
+                (name.to_owned(), State::Unset)
+            } else if let Some(name) = word.strip_prefix('!') {
+                (name.to_owned(), State::Unspecified)
+            } else if let Some((name, value)) = word.split_once('=') {
+                (name.to_owned(), State::Value(value.to_owned()))
+            } else {
+                (word.to_string(), State::Set)
+            }
+        })
+        .collect()
+}
+
+/// Whether `text=auto` should leave `data` alone: it looks binary or has a
+/// carriage return outside of a CRLF pair.
+fn is_binary(data: &[u8]) -> bool {
+    diff::is_binary(data)
+        || data
+            .iter()
+            .enumerate()
+            .any(|(index, byte)| *byte == b'\r' && data.get(index + 1) != Some(&b'\n'))
+}
+
+fn to_lf(data: &[u8]) -> Vec<u8> {
+    let mut lf = Vec::with_capacity(data.len());
+    for (index, byte) in data.iter().enumerate() {
+        if *byte != b'\r' || data.get(index + 1) != Some(&b'\n') {
+            lf.push(*byte);
+        }
+    }
+    lf
+}
+
+fn to_crlf(data: &[u8]) -> Vec<u8> {
+    let mut crlf = Vec::with_capacity(data.len() + data.len() / 32);
+    for (index, byte) in data.iter().enumerate() {
+        if *byte == b'\n' && (index == 0 || data[index - 1] != b'\r') {
+            crlf.push(b'\r');
+        }
+        crlf.push(*byte);
+    }
+    crlf
+}
+
+#[test]
+fn precedence() {
+    let mut macros = HashMap::new();
+    macros.insert(String::from("binary"), parse_states(&["-diff", "-text"]));
+    let top = parse(
+        "[attr]docs text eol=crlf\n*.txt text\n*.png binary\n*.md docs\n# *.rs -text\n",
+        path::Path::new(""),
+        Some(&mut macros),
+    );
+    let sub = parse(
+        "*.txt -text\n[attr]ignored -text\n",
+        path::Path::new("sub"),
+        None,
+    );
+
+    let attributes = Attributes {
+        root: Some(path::PathBuf::new()),
+        macros,
+        info: parse("special.txt !text diff=hex\n", path::Path::new(""), None),
+        directories: Mutex::new(
+            vec![
+                (path::PathBuf::new(), Arc::new(top)),
+                (path::PathBuf::from("sub"), Arc::new(sub)),
+                (path::PathBuf::from("sub/deeper"), Arc::default()),
+            ]
+            .into_iter()
+            .collect(),
+        ),
+        ..Attributes::default()
+    };
+    let get = |path: &str, name: &str| -> State {
+        attributes
+            .get(path::Path::new(path))
+            .expect("loaded above")
+            .get(name)
+            .clone()
+    };
+
+    assert_eq!(get("a.txt", "text"), State::Set);
+    assert_eq!(get("sub/a.txt", "text"), State::Unset);
+    assert_eq!(get("sub/deeper/a.txt", "text"), State::Unset);
+    assert_eq!(get("special.txt", "text"), State::Unspecified);
+    assert_eq!(
+        get("special.txt", "diff"),
+        State::Value(String::from("hex"))
+    );
+    assert_eq!(get("image.png", "diff"), State::Unset);
+    assert_eq!(get("image.png", "binary"), State::Set);
+    assert_eq!(get("README.md", "eol"), State::Value(String::from("crlf")));
+    assert_eq!(get("main.rs", "text"), State::Unspecified);
+    assert!(!attributes.macros.contains_key("ignored"));
+}
+
+#[test]
+fn conversion() {
+    assert_eq!(to_lf(b"a\r\nb\rc\r\n"), b"a\nb\rc\n");
+    assert_eq!(to_crlf(b"a\nb\r\nc"), b"a\r\nb\r\nc");
+    assert!(is_binary(b"a\rb\n"));
+
+    let attributes = Attributes {
+        root: Some(path::PathBuf::new()),
+        directories: Mutex::new(
+            vec![(
+                path::PathBuf::new(),
+                Arc::new(parse(
+                    "*.txt text\n*.bat eol=crlf\n",
+                    path::Path::new(""),
+                    None,
+                )),
+            )]
+            .into_iter()
+            .collect(),
+        ),
+        ..Attributes::default()
+    };
+    let check = |path: &str, data: &[u8]| {
+        attributes
+            .check_round_trip(path::Path::new(path), data)
+            .expect("loaded above")
+    };
+    assert_eq!(check("a.txt", b"a\r\nb\n"), Some(Lossy::CrlfToLf));
+    assert_eq!(check("a.txt", b"a\nb\n"), None);
+    assert_eq!(check("a.bat", b"a\nb\n"), Some(Lossy::LfToCrlf));
+    assert_eq!(check("a.bat", b"a\r\nb\r\n"), None);
+    assert_eq!(check("a.rs", b"a\r\nb\n"), None);
+    assert!(!is_binary(b"a\r\nb\n"));
+}
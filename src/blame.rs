@@ -0,0 +1,525 @@
+//! Attribution of each line of a file to the commit that last changed it.
+
+use std::collections::BTreeMap;
+use std::collections::BinaryHeap;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::io::Write as _;
+use std::ops;
+use std::path;
+
+use anyhow::anyhow;
+
+use crate::diff;
+use crate::object;
+use crate::platform::OsStrExt as _;
+
+/// A run of consecutive lines last changed by the same commit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    /// Commit that last changed the lines, or `None` for changes in the
+    /// workspace that haven't been committed yet.
+    pub commit: Option<object::Id>,
+    /// First parent of `commit` (or `HEAD`, for uncommitted changes) that
+    /// contains the file, if any.
+    pub previous: Option<object::Id>,
+    /// Zero-based index of the first line in `commit`'s version of the file.
+    pub source: usize,
+    /// Zero-based index of the first line in the blamed version of the file.
+    pub target: usize,
+    /// Number of lines.
+    pub len: usize,
+}
+
+/// A line of the blamed version whose origin is still being searched for.
+#[derive(Copy, Clone, Debug)]
+struct Line {
+    /// Index of the line in the version of the file currently examined.
+    source: usize,
+    /// Index of the line in the blamed version.
+    target: usize,
+}
+
+/// Attribute the lines within `ranges` (zero-based and exclusive) of `path`
+/// as of commit `start`, or as of `contents` if given (usually the file in
+/// the workspace, built on `start`).
+///
+/// Lines are passed from each commit to every parent that contains them
+/// unchanged, newest commits first, until a commit is found that changed
+/// them. Entries are returned in the order they are found.
+///
+/// With a [`Cache`], the whole file is blamed and saved for `start`, and
+/// walking stops early at any commit blamed before.
+pub fn blame(
+    database: &crate::Database,
+    cache: &Cache,
+    start: object::Id,
+    path: &path::Path,
+    contents: Option<&[u8]>,
+    ranges: &[ops::Range<usize>],
+) -> anyhow::Result<Vec<Entry>> {
+    let mut blame = Blame::new(database, cache, path);
+    let blob = find(database, database.load_commit(&start)?.tree(), path)?;
+
+    let lines = match (contents, blob) {
+        (Some(contents), None) => {
+            blame.found(None, None, select(contents, ranges));
+            return Ok(blame.entries);
+        }
+        (Some(contents), Some(blob)) => {
+            let lines = select(contents, ranges);
+            let (passed, kept) = split(database.load_blob(&blob)?.data(), contents, lines);
+            blame.found(None, Some(start), kept);
+            passed
+        }
+        (None, Some(blob)) => select(database.load_blob(&blob)?.data(), ranges),
+        (None, None) => {
+            return Err(anyhow!(
+                "No such path '{}' in commit {}",
+                path.display(),
+                start
+            ))
+        }
+    };
+
+    if let (Some(blob), true) = (blob, cache.load(&start, path).is_none()) {
+        let data = database.load_blob(&blob)?.into_data();
+        let mut full = Blame::new(database, cache, path);
+        full.pass(start, select(&data, std::slice::from_ref(&(0..usize::MAX))))?;
+        let mut entries = full.finish()?;
+        entries.sort_by_key(|entry| entry.target);
+        if let Err(error) = cache.store(&start, path, &entries) {
+            log::warn!("Failed to cache blame of {}: {}", path.display(), error);
+        }
+    }
+
+    blame.pass(start, lines)?;
+    blame.finish()
+}
+
+/// Blames of whole files, saved by commit and path.
+///
+/// A commit's blame never changes, so saved blames are never invalidated.
+#[derive(Clone, Debug, Default)]
+pub struct Cache {
+    /// Directory to save blames in, or `None` to not save them.
+    root: Option<path::PathBuf>,
+}
+
+impl Cache {
+    /// Save blames in `.git/grit/cache/blame` of the repository at `git`.
+    pub fn open(git: &path::Path) -> Self {
+        Cache {
+            root: Some(git.join("grit").join("cache").join("blame")),
+        }
+    }
+
+    fn file(&self, commit: &object::Id, path: &path::Path) -> Option<path::PathBuf> {
+        let mut key = commit.to_string().into_bytes();
+        key.push(0);
+        key.extend_from_slice(path.as_os_str().as_bytes());
+
+        let key = object::Id::hash(&key).to_string();
+        self.root
+            .as_ref()
+            .map(|root| root.join(&key[..2]).join(&key[2..]))
+    }
+
+    /// Load the blame of `path` as of `commit`, sorted by line, if saved.
+    fn load(&self, commit: &object::Id, path: &path::Path) -> Option<Vec<Entry>> {
+        let file = self.file(commit, path)?;
+        let text = fs::read_to_string(&file).ok()?;
+
+        let parse = |line: &str| -> anyhow::Result<Entry> {
+            let id = |field: &str| match field {
+                "-" => Ok(None),
+                field => field.parse().map(Some),
+            };
+            match line.split(' ').collect::<Vec<_>>().as_slice() {
+                [commit, previous, source, target, len] => Ok(Entry {
+                    commit: id(commit)?,
+                    previous: id(previous)?,
+                    source: source.parse()?,
+                    target: target.parse()?,
+                    len: len.parse()?,
+                }),
+                _ => Err(anyhow!("Invalid entry: {}", line)),
+            }
+        };
+
+        match text.lines().map(parse).collect() {
+            Ok(entries) => Some(entries),
+            Err(error) => {
+                log::warn!("Ignoring corrupt blame cache {}: {}", file.display(), error);
+                None
+            }
+        }
+    }
+
+    /// Save the blame of `path` as of `commit`, sorted by line.
+    fn store(&self, commit: &object::Id, path: &path::Path, entries: &[Entry]) -> io::Result<()> {
+        let file = match self.file(commit, path) {
+            Some(file) => file,
+            None => return Ok(()),
+        };
+
+        let id = |id: Option<object::Id>| match id {
+            None => String::from("-"),
+            Some(id) => id.to_string(),
+        };
+
+        let mut temp = crate::fs::Temp::new(file)?;
+        for entry in entries {
+            writeln!(
+                temp,
+                "{} {} {} {} {}",
+                id(entry.commit),
+                id(entry.previous),
+                entry.source,
+                entry.target,
+                entry.len
+            )?;
+        }
+        temp.commit()
+    }
+}
+
+struct Blame<'a> {
+    database: &'a crate::Database,
+    cache: &'a Cache,
+    path: &'a path::Path,
+    entries: Vec<Entry>,
+    /// Lines passed to each queued commit.
+    pending: HashMap<object::Id, Vec<Line>>,
+    /// Commits with pending lines, newest first.
+    queue: BinaryHeap<(i64, object::Id)>,
+}
+
+impl<'a> Blame<'a> {
+    fn new(database: &'a crate::Database, cache: &'a Cache, path: &'a path::Path) -> Self {
+        Blame {
+            database,
+            cache,
+            path,
+            entries: Vec::new(),
+            pending: HashMap::new(),
+            queue: BinaryHeap::new(),
+        }
+    }
+
+    /// Attribute every pending line.
+    fn finish(mut self) -> anyhow::Result<Vec<Entry>> {
+        while let Some((_, id)) = self.queue.pop() {
+            self.step(id)?;
+        }
+        Ok(self.entries)
+    }
+
+    /// Pass responsibility for `lines` to commit `id`.
+    fn pass(&mut self, id: object::Id, lines: Vec<Line>) -> anyhow::Result<()> {
+        if lines.is_empty() {
+            return Ok(());
+        }
+        if !self.pending.contains_key(&id) {
+            let time = self
+                .database
+                .load_commit(&id)?
+                .committer()
+                .time()
+                .timestamp();
+            self.queue.push((time, id));
+        }
+        self.pending.entry(id).or_default().extend(lines);
+        Ok(())
+    }
+
+    /// Pass the lines pending for commit `id` on to its parents, keeping
+    /// those that none of them contain.
+    fn step(&mut self, id: object::Id) -> anyhow::Result<()> {
+        let mut lines = match self.pending.remove(&id) {
+            Some(lines) => lines,
+            None => return Ok(()),
+        };
+
+        if let Some(cached) = self.cache.load(&id, self.path) {
+            self.resolve(&cached, lines);
+            return Ok(());
+        }
+
+        let commit = self.database.load_commit(&id)?;
+        let blob = find(self.database, commit.tree(), self.path)?
+            .ok_or_else(|| anyhow!("No such path '{}' in commit {}", self.path.display(), id))?;
+
+        let mut parents = Vec::new();
+        for parent in commit.parents() {
+            let tree = *self.database.load_commit(parent)?.tree();
+            if let Some(blob) = find(self.database, &tree, self.path)? {
+                parents.push((*parent, blob));
+            }
+        }
+
+        // Like `git`, follow an unchanged file to a single parent.
+        if let Some((parent, _)) = parents.iter().find(|(_, parent)| *parent == blob) {
+            return self.pass(*parent, lines);
+        }
+
+        let data = self.database.load_blob(&blob)?.into_data();
+        for (parent, blob) in &parents {
+            if lines.is_empty() {
+                break;
+            }
+            let (passed, kept) = split(self.database.load_blob(blob)?.data(), &data, lines);
+            self.pass(*parent, passed)?;
+            lines = kept;
+        }
+
+        self.found(Some(id), parents.first().map(|(parent, _)| *parent), lines);
+        Ok(())
+    }
+
+    /// Attribute `lines` like the `cached` blame of the commit they were
+    /// passed to.
+    fn resolve(&mut self, cached: &[Entry], lines: Vec<Line>) {
+        let mut found = BTreeMap::<_, (_, Vec<_>)>::new();
+        for line in lines {
+            let index = cached.partition_point(|entry| entry.target + entry.len <= line.source);
+            let entry = &cached[index];
+            found
+                .entry(entry.commit)
+                .or_insert_with(|| (entry.previous, Vec::new()))
+                .1
+                .push(Line {
+                    source: entry.source + line.source - entry.target,
+                    target: line.target,
+                });
+        }
+        for (commit, (previous, lines)) in found {
+            self.found(commit, previous, lines);
+        }
+    }
+
+    /// Record that `lines` were last changed by `commit`.
+    fn found(
+        &mut self,
+        commit: Option<object::Id>,
+        previous: Option<object::Id>,
+        mut lines: Vec<Line>,
+    ) {
+        lines.sort_by_key(|line| line.target);
+
+        let mut lines = lines.into_iter();
+        let mut entry = match lines.next() {
+            Some(line) => Entry {
+                commit,
+                previous,
+                source: line.source,
+                target: line.target,
+                len: 1,
+            },
+            None => return,
+        };
+
+        for line in lines {
+            if line.source == entry.source + entry.len && line.target == entry.target + entry.len {
+                entry.len += 1;
+                continue;
+            }
+            let next = Entry {
+                source: line.source,
+                target: line.target,
+                len: 1,
+                ..entry.clone()
+            };
+            self.entries.push(std::mem::replace(&mut entry, next));
+        }
+        self.entries.push(entry);
+    }
+}
+
+/// Every line of `data` within `ranges`, not yet attributed.
+fn select(data: &[u8], ranges: &[ops::Range<usize>]) -> Vec<Line> {
+    (0..diff::lines(data).len())
+        .filter(|index| ranges.iter().any(|range| range.contains(index)))
+        .map(|index| Line {
+            source: index,
+            target: index,
+        })
+        .collect()
+}
+
+/// Split `lines` of `new` into those that are unchanged from `old`, renumbered
+/// to their positions in `old`, and the rest.
+fn split(old: &[u8], new: &[u8], lines: Vec<Line>) -> (Vec<Line>, Vec<Line>) {
+    let unchanged = diff::edits(&diff::lines(old), &diff::lines(new))
+        .into_iter()
+        .filter_map(|edit| match edit {
+            diff::Edit::Equal { a, b } => Some((b, a)),
+            _ => None,
+        })
+        .collect::<HashMap<_, _>>();
+
+    let mut passed = Vec::new();
+    let mut kept = Vec::new();
+    for line in lines {
+        match unchanged.get(&line.source) {
+            Some(source) => passed.push(Line {
+                source: *source,
+                target: line.target,
+            }),
+            None => kept.push(line),
+        }
+    }
+    (passed, kept)
+}
+
+/// Look up the blob at `path` in `tree`.
+pub fn find(
+    database: &crate::Database,
+    tree: &object::Id,
+    path: &path::Path,
+) -> anyhow::Result<Option<object::Id>> {
+    let mut id = *tree;
+    for component in path.components() {
+        if database.kind(&id)? != object::Type::Tree {
+            return Ok(None);
+        }
+        match database
+            .load_tree(&id)?
+            .into_iter()
+            .find(|node| node.path.as_os_str() == component.as_os_str())
+        {
+            Some(node) => id = node.id,
+            None => return Ok(None),
+        }
+    }
+    match database.kind(&id)? {
+        object::Type::Blob => Ok(Some(id)),
+        _ => Ok(None),
+    }
+}
+
+#[test]
+fn attribute() -> anyhow::Result<()> {
+    let mut repository = crate::Repository::memory(path::PathBuf::new());
+    repository.init()?;
+    let database = repository.database()?;
+
+    let commit = |data: &str, parents: Vec<object::Id>, time: i64| {
+        let blob = database.store(&crate::Object::Blob(object::Blob::new(
+            data.as_bytes().to_vec(),
+        )))?;
+        let tree = database.store(&crate::Object::Tree(object::tree::Root::new(vec![
+            object::tree::Node::new(
+                path::PathBuf::from("file"),
+                blob,
+                crate::meta::Mode::Regular,
+            ),
+        ])))?;
+        let person = object::Person::new(
+            String::from("A U Thor"),
+            String::from("author@example.com"),
+            object::Person::parse_time(&format!("@{} +0000", time))?,
+        );
+        database
+            .store(&crate::Object::Commit(object::Commit::new(
+                tree,
+                parents,
+                person.clone(),
+                person,
+                String::from("message\n"),
+            )))
+            .map_err(anyhow::Error::from)
+    };
+
+    // root <- left <- merge
+    //      <- right <-
+    let root = commit("a\nb\nc\n", Vec::new(), 1)?;
+    let left = commit("a\nB\nc\n", vec![root], 2)?;
+    let right = commit("a\nb\nc\nd\n", vec![root], 3)?;
+    let merge = commit("a\nB\nc\nd\n", vec![left, right], 4)?;
+
+    let entry = |commit, previous, source, target, len| Entry {
+        commit,
+        previous,
+        source,
+        target,
+        len,
+    };
+
+    let mut entries = blame(
+        &database,
+        &Cache::default(),
+        merge,
+        "file".as_ref(),
+        None,
+        std::slice::from_ref(&(0..4)),
+    )?;
+    entries.sort_by_key(|entry| entry.target);
+    assert_eq!(
+        entries,
+        [
+            entry(Some(root), None, 0, 0, 1),
+            entry(Some(left), Some(root), 1, 1, 1),
+            entry(Some(root), None, 2, 2, 1),
+            entry(Some(right), Some(root), 3, 3, 1),
+        ]
+    );
+
+    let entries = blame(
+        &database,
+        &Cache::default(),
+        left,
+        "file".as_ref(),
+        Some(b"x\na\nB\nc\n"),
+        std::slice::from_ref(&(0..4)),
+    )?;
+    assert_eq!(entries[0], entry(None, Some(left), 0, 0, 1));
+    assert_eq!(entries.len(), 4);
+
+    let mut entries = blame(
+        &database,
+        &Cache::default(),
+        merge,
+        "file".as_ref(),
+        None,
+        &[3..4, 1..2],
+    )?;
+    entries.sort_by_key(|entry| entry.target);
+    assert_eq!(
+        entries,
+        [
+            entry(Some(left), Some(root), 1, 1, 1),
+            entry(Some(right), Some(root), 3, 3, 1),
+        ]
+    );
+
+    assert!(blame(
+        &database,
+        &Cache::default(),
+        root,
+        "missing".as_ref(),
+        None,
+        std::slice::from_ref(&(0..1))
+    )
+    .is_err());
+
+    // Walking stops at commits blamed before, trusting their saved blame.
+    let git = crate::util::TempDir::new();
+    let cache = Cache::open(&git);
+    let path = path::Path::new("file");
+    cache.store(&left, path, &[entry(Some(root), None, 0, 0, 3)])?;
+
+    let mut entries = blame(&database, &cache, merge, path, None, &[0..2, 2..4])?;
+    entries.sort_by_key(|entry| entry.target);
+    assert_eq!(
+        entries,
+        [
+            entry(Some(root), None, 0, 0, 3),
+            entry(Some(right), Some(root), 3, 3, 1),
+        ]
+    );
+    assert_eq!(cache.load(&merge, path), Some(entries));
+
+    Ok(())
+}
@@ -0,0 +1,104 @@
+//! The `git bundle` file format, which packages references and a packfile
+//! of the objects they reach so that history can move without a live
+//! remote, e.g. as pre-generated bundles that speed up clones.
+//!
+//! A bundle starts with a `# v2 git bundle` or `# v3 git bundle` line,
+//! then (in version 3) `@<capability>` lines, then `-<id>` lines naming
+//! prerequisite commits that the pack leaves out, then `<id> <name>` lines
+//! for its references. An empty line separates this header from the pack.
+//! See `git help gitformat-bundle` for the full format.
+
+use std::str;
+
+use anyhow::anyhow;
+
+use crate::object;
+
+const SIGNATURE_V2: &[u8] = b"# v2 git bundle\n";
+const SIGNATURE_V3: &[u8] = b"# v3 git bundle\n";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Bundle {
+    /// Commits the reader must already have, along with their history.
+    pub prerequisites: Vec<object::Id>,
+    /// Full reference names and their ids, in the order listed.
+    pub refs: Vec<(String, object::Id)>,
+    /// Packfile of every object the references reach, except those the
+    /// prerequisites reach.
+    pub pack: Vec<u8>,
+}
+
+impl Bundle {
+    pub fn read(mut bytes: Vec<u8>) -> anyhow::Result<Self> {
+        let v3 = if bytes.starts_with(SIGNATURE_V2) {
+            false
+        } else if bytes.starts_with(SIGNATURE_V3) {
+            true
+        } else {
+            return Err(anyhow!("Not a bundle"));
+        };
+
+        let mut bundle = Bundle {
+            prerequisites: Vec::new(),
+            refs: Vec::new(),
+            pack: Vec::new(),
+        };
+
+        let mut offset = SIGNATURE_V2.len();
+        loop {
+            let end = bytes[offset..]
+                .iter()
+                .position(|byte| *byte == b'\n')
+                .map(|end| offset + end)
+                .ok_or_else(|| anyhow!("Truncated bundle header"))?;
+            let line = str::from_utf8(&bytes[offset..end])?;
+            offset = end + 1;
+
+            if line.is_empty() {
+                break;
+            } else if let Some(capability) = line.strip_prefix('@').filter(|_| v3) {
+                match capability.split_once('=') {
+                    Some(("object-format", "sha1")) => (),
+                    _ => return Err(anyhow!("Unsupported bundle capability: {}", capability)),
+                }
+            } else if let Some(prerequisite) = line.strip_prefix('-') {
+                // A comment, usually the commit's subject, may follow the id.
+                let id = prerequisite.split(' ').next().unwrap_or_default();
+                bundle.prerequisites.push(id.parse()?);
+            } else {
+                let (id, name) = line
+                    .split_once(' ')
+                    .ok_or_else(|| anyhow!("Invalid bundle reference: {}", line))?;
+                bundle.refs.push((name.to_owned(), id.parse()?));
+            }
+        }
+
+        bundle.pack = bytes.split_off(offset);
+        Ok(bundle)
+    }
+}
+
+#[test]
+fn read() -> anyhow::Result<()> {
+    let blob = crate::Object::Blob(object::Blob::new(b"hello\n".to_vec())).to_bytes();
+    let id = object::Id::hash(&blob);
+    let pack = crate::database::pack::Packfile::build(&[(id, blob)])?.pack;
+    let prerequisite = "ce013625030ba8dba906f756967f9e9ca394464a";
+
+    let mut bytes = format!(
+        "# v3 git bundle\n@object-format=sha1\n-{} subject\n{} refs/tags/blob\n\n",
+        prerequisite, id,
+    )
+    .into_bytes();
+    bytes.extend_from_slice(&pack);
+
+    let bundle = Bundle::read(bytes)?;
+    assert_eq!(bundle.prerequisites, vec![prerequisite.parse()?]);
+    assert_eq!(bundle.refs, vec![(String::from("refs/tags/blob"), id)]);
+    assert_eq!(bundle.pack, pack);
+
+    assert!(Bundle::read(b"# v3 git bundle\n@filter=blob:none\n\n".to_vec()).is_err());
+    assert!(Bundle::read(b"# v2 git bundle\n@object-format=sha1\n\n".to_vec()).is_err());
+    assert!(Bundle::read(b"PACK".to_vec()).is_err());
+    Ok(())
+}
@@ -1,11 +1,81 @@
 mod add;
+mod blame;
+mod branch;
+mod cat_file;
+mod checkout;
+mod clone;
 mod commit;
+mod commit_graph;
+mod config;
+mod diff;
+mod doctor;
+mod dump_index;
+mod fast_export;
+mod fast_import;
+mod fetch;
+mod filter;
+mod fsck;
+mod gc;
+mod grep;
+mod help;
 mod init;
+mod log;
+mod ls_files;
+mod ls_tree;
+mod merge;
+mod merge_tree;
+mod pack_objects;
+mod push;
+mod reflog;
+mod remote;
+mod reset;
+mod restore;
+mod rev_list;
+mod rev_parse;
+mod rm;
 mod show;
+mod show_branch;
+mod stash;
 mod status;
+mod tag;
 
 pub use add::Configuration as Add;
+pub use blame::Configuration as Blame;
+pub use branch::Configuration as Branch;
+pub use cat_file::Configuration as CatFile;
+pub use checkout::Configuration as Checkout;
+pub use clone::Configuration as Clone;
 pub use commit::Configuration as Commit;
+pub use commit_graph::Configuration as CommitGraph;
+pub use config::Configuration as Config;
+pub use diff::Configuration as Diff;
+pub use doctor::Configuration as Doctor;
+pub use dump_index::Configuration as DumpIndex;
+pub use fast_export::Configuration as FastExport;
+pub use fast_import::Configuration as FastImport;
+pub use fetch::Configuration as Fetch;
+pub use filter::Configuration as Filter;
+pub use fsck::Configuration as Fsck;
+pub use gc::Configuration as Gc;
+pub use grep::Configuration as Grep;
+pub use help::Configuration as Help;
 pub use init::Configuration as Init;
+pub use log::Configuration as Log;
+pub use ls_files::Configuration as LsFiles;
+pub use ls_tree::Configuration as LsTree;
+pub use merge::Configuration as Merge;
+pub use merge_tree::Configuration as MergeTree;
+pub use pack_objects::Configuration as PackObjects;
+pub use push::Configuration as Push;
+pub use reflog::Configuration as Reflog;
+pub use remote::Configuration as Remote;
+pub use reset::Configuration as Reset;
+pub use restore::Configuration as Restore;
+pub use rev_list::Configuration as RevList;
+pub use rev_parse::Configuration as RevParse;
+pub use rm::Configuration as Rm;
 pub use show::Configuration as Show;
+pub use show_branch::Configuration as ShowBranch;
+pub use stash::Configuration as Stash;
 pub use status::Configuration as Status;
+pub use tag::Configuration as Tag;
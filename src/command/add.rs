@@ -1,25 +1,44 @@
+use std::cmp;
 use std::env;
 use std::fs;
+use std::io::Read as _;
+use std::io::Seek as _;
+use std::mem;
+use std::num;
+use std::panic;
 use std::path;
+use std::sync::atomic;
+use std::thread;
 
+use anyhow::anyhow;
 use structopt::StructOpt;
 
+use crate::attributes;
+use crate::meta;
 use crate::object;
 
 #[derive(StructOpt)]
 pub struct Configuration {
+    /// Files or directories to add, relative to the current directory.
     paths: Vec<path::PathBuf>,
 }
 
 impl Configuration {
     pub fn run(self) -> anyhow::Result<()> {
-        let root = env::current_dir()?;
-        let repository = crate::Repository::new(root);
+        let (repository, prefix) = crate::Repository::discover(&env::current_dir()?)?;
         let add = Add {
-            database: repository.database(),
+            threshold: repository
+                .config()?
+                .get_size("core.bigFileThreshold")?
+                .unwrap_or(BIG_FILE_THRESHOLD),
+            database: repository.database()?,
             index: repository.index()?,
-            workspace: repository.workspace(),
-            paths: self.paths,
+            workspace: repository.workspace()?,
+            paths: self
+                .paths
+                .iter()
+                .map(|path| prefix.resolve(path))
+                .collect::<anyhow::Result<_>>()?,
         };
         add.run()?;
         Ok(())
@@ -27,35 +46,227 @@ impl Configuration {
 }
 
 struct Add {
+    /// Size past which files are streamed into the database.
+    threshold: u64,
     database: crate::Database,
     index: crate::Index,
     workspace: crate::Workspace,
     paths: Vec<path::PathBuf>,
 }
 
+/// Default for `core.bigFileThreshold`, as in `git`.
+const BIG_FILE_THRESHOLD: u64 = 512 << 20;
+
+/// Fewest files worth splitting between workers.
+const PARALLEL_THRESHOLD: usize = 64;
+
 impl Add {
     fn run(mut self) -> anyhow::Result<()> {
-        for path in self.paths {
+        let mut files = Vec::new();
+        for path in mem::take(&mut self.paths) {
             for entry in self.workspace.walk_tree(&path)? {
                 let entry = entry?;
-                let relative = entry.relative_path();
-
-                if entry.metadata.mode.is_directory() {
-                    continue;
+                if !entry.metadata.mode.is_directory() {
+                    files.push((entry.relative_path().to_path_buf(), entry.metadata));
                 }
+            }
+        }
 
-                let blob = fs::read(entry.path())
-                    .map(object::Blob::new)
-                    .map(crate::Object::Blob)?;
-
-                let id = self.database.store(&blob)?;
+        // Flush the new blobs together before the index refers to them.
+        let batch = self.database.batch();
+        let ids = self.store(&files)?;
+        for ((relative, metadata), id) in files.into_iter().zip(ids) {
+            // Like `git`, new files are regular files when the executable
+            // bit can't be trusted.
+            let metadata = match self.index.get(&relative) {
+                Some(indexed) => self.workspace.normalize(metadata, indexed.metadata().mode),
+                None => self.workspace.normalize(metadata, meta::Mode::Regular),
+            };
 
-                self.index
-                    .insert(entry.metadata, id, relative.to_path_buf());
-            }
+            self.index.insert(metadata, id, relative);
         }
 
+        batch.commit()?;
         self.index.commit()?;
         Ok(())
     }
+
+    /// Read, clean, hash, and store each of `files` as a blob, returning
+    /// their ids in order.
+    ///
+    /// Many files are split between one worker per logical core, which
+    /// each open their own handle to the database and batch their writes
+    /// like the caller's. Blobs already stored are never compressed again,
+    /// and files larger than `threshold` whose contents aren't converted are
+    /// streamed, so that they're never fully in memory.
+    fn store(&self, files: &[(path::PathBuf, meta::Metadata)]) -> anyhow::Result<Vec<object::Id>> {
+        let (workspace, threshold) = (&self.workspace, self.threshold);
+        let store = |database: &crate::Database,
+                     (relative, metadata): &(path::PathBuf, meta::Metadata)|
+         -> anyhow::Result<object::Id> {
+            let data = match metadata.mode.is_symlink() {
+                true => workspace.read(relative)?,
+                false => {
+                    let mut file = fs::File::open(workspace.root().join(relative))?;
+                    let len = file.metadata()?.len();
+                    if len > threshold && !workspace.attributes().converts(relative)? {
+                        // Hashing is much cheaper than compressing, so read
+                        // the file twice rather than compress a blob that's
+                        // already stored, e.g. when re-adding it unchanged.
+                        let id = crate::Database::hash_stream(len, &mut file)?;
+                        if database.contains(&id)? {
+                            return Ok(id);
+                        }
+                        file.rewind()?;
+                        return Ok(database.store_stream(len, file)?);
+                    }
+
+                    let mut data = Vec::new();
+                    file.read_to_end(&mut data)?;
+                    check_round_trip(workspace, relative, &data)?;
+                    workspace.clean(relative, data)?
+                }
+            };
+            let blob = crate::Object::Blob(object::Blob::new(data));
+            Ok(database.store(&blob)?)
+        };
+
+        let workers = cmp::min(
+            thread::available_parallelism().map_or(1, num::NonZeroUsize::get),
+            files.len(),
+        );
+        let root = match self.database.root() {
+            Some(root) if workers > 1 && files.len() >= PARALLEL_THRESHOLD => root,
+            _ => {
+                return files
+                    .iter()
+                    .map(|file| store(&self.database, file))
+                    .collect()
+            }
+        };
+
+        let fsync = self.database.fsync();
+        let next = atomic::AtomicUsize::new(0);
+        let (next, store) = (&next, &store);
+        let stored = thread::scope(|scope| {
+            (0..workers)
+                .map(|_| {
+                    scope.spawn(move || {
+                        let database = crate::Database::open_with_fsync(root.to_path_buf(), fsync);
+                        let batch = database.batch();
+                        let mut stored = Vec::new();
+                        loop {
+                            let index = next.fetch_add(1, atomic::Ordering::Relaxed);
+                            let file = match files.get(index) {
+                                None => break,
+                                Some(file) => file,
+                            };
+                            match store(&database, file) {
+                                Ok(id) => stored.push((index, id)),
+                                Err(error) => {
+                                    // Stop the other workers early.
+                                    next.store(files.len(), atomic::Ordering::Relaxed);
+                                    return Err(error);
+                                }
+                            }
+                        }
+                        batch.commit()?;
+                        Ok(stored)
+                    })
+                })
+                .collect::<Vec<_>>()
+                .into_iter()
+                .map(|worker| {
+                    worker
+                        .join()
+                        .unwrap_or_else(|panic| panic::resume_unwind(panic))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()
+        })?;
+
+        let mut ordered = vec![None; files.len()];
+        for (index, id) in stored.into_iter().flatten() {
+            ordered[index] = Some(id);
+        }
+        ordered
+            .into_iter()
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| anyhow!("[INTERNAL ERROR]: add worker skipped a file"))
+    }
+}
+
+/// Warn about or refuse files whose line endings would change if they
+/// were checked out again, as configured by `core.safecrlf`.
+fn check_round_trip(
+    workspace: &crate::Workspace,
+    path: &path::Path,
+    data: &[u8],
+) -> anyhow::Result<()> {
+    let attributes = workspace.attributes();
+    if attributes.safecrlf() == attributes::SafeCrlf::Off {
+        return Ok(());
+    }
+    let (old, new) = match attributes.check_round_trip(path, data)? {
+        None => return Ok(()),
+        Some(lossy) => lossy.endings(),
+    };
+    match attributes.safecrlf() {
+        attributes::SafeCrlf::Fail => Err(anyhow!(
+            "{} would be replaced by {} in {}",
+            old,
+            new,
+            path.display()
+        )),
+        _ => {
+            eprintln!(
+                "warning: in the working copy of '{}', {} will be replaced by {} the next time grit touches it",
+                path.display(),
+                old,
+                new
+            );
+            Ok(())
+        }
+    }
+}
+
+#[test]
+fn unchanged() -> anyhow::Result<()> {
+    use std::time;
+
+    let root = crate::util::TempDir::new();
+    let mut repository = crate::Repository::new(root.to_path_buf());
+    repository.init()?;
+
+    let add = || -> anyhow::Result<()> {
+        Add {
+            // Stream `big` but not `small`.
+            threshold: 4,
+            database: repository.database()?,
+            index: repository.index()?,
+            workspace: repository.workspace()?,
+            paths: vec![path::PathBuf::new()],
+        }
+        .run()
+    };
+
+    let mut objects = Vec::new();
+    for (name, data) in [("small", "1"), ("big", "12345678")].iter() {
+        fs::write(root.join(name), data)?;
+        let id = crate::Database::hash_stream(data.len() as u64, data.as_bytes())?;
+        objects.push(root.join(".git/objects").join(id.to_path_buf()));
+    }
+
+    // Rewriting an object would reset its modification time.
+    add()?;
+    for object in &objects {
+        fs::File::open(object)?.set_modified(time::UNIX_EPOCH)?;
+    }
+    add()?;
+    let modified = objects
+        .iter()
+        .map(|object| fs::metadata(object)?.modified())
+        .collect::<Result<Vec<_>, _>>()?;
+
+    assert_eq!(modified, vec![time::UNIX_EPOCH; 2]);
+    Ok(())
 }
@@ -0,0 +1,374 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::io::Write as _;
+use std::iter;
+use std::ops;
+use std::path;
+
+use anyhow::anyhow;
+use structopt::clap::ArgGroup;
+use structopt::StructOpt;
+
+use crate::blame;
+use crate::diff;
+use crate::object;
+use crate::revision;
+
+/// Show the commit that last changed each line of a file, like `git blame`.
+///
+/// Without a revision, the file in the workspace is blamed, and lines that
+/// differ from `HEAD` are attributed to `Not Committed Yet`.
+#[derive(StructOpt)]
+#[structopt(group = ArgGroup::with_name("format"))]
+pub struct Configuration {
+    /// Print a machine-readable format, with each commit described once
+    /// before its first line.
+    #[structopt(short, long, group = "format")]
+    porcelain: bool,
+
+    /// Like `--porcelain`, but describe the commit before every line.
+    #[structopt(long, group = "format")]
+    line_porcelain: bool,
+
+    /// Print each group of lines as soon as its commit is found, described
+    /// like `--porcelain` but without the lines' contents.
+    #[structopt(long, group = "format")]
+    incremental: bool,
+
+    /// Only blame lines within a range, given as `<start>,<end>`, where
+    /// `<start>` defaults to the first line and `<end>` to the last, or as
+    /// `<start>,+<count>` or `<start>,-<count>`. May be repeated.
+    #[structopt(short = "L", number_of_values = 1)]
+    ranges: Vec<String>,
+
+    /// An optional revision to blame the file at, followed by the file.
+    #[structopt(required = true, max_values = 2)]
+    arguments: Vec<String>,
+}
+
+impl Configuration {
+    pub fn run(self) -> anyhow::Result<()> {
+        let (repository, prefix) = crate::Repository::discover(&env::current_dir()?)?;
+        let database = repository.database()?;
+
+        let (revision, path) = match self.arguments.as_slice() {
+            [path] => (None, path),
+            [revision, path] => (Some(revision.as_str()), path),
+            _ => unreachable!(),
+        };
+        let path = prefix.resolve(path.as_ref())?;
+
+        let start = revision
+            .unwrap_or("HEAD")
+            .parse::<revision::Revision>()?
+            .resolve(&repository)?
+            .ok_or_else(|| anyhow!("Unknown revision: `{}`", revision.unwrap_or("HEAD")))?
+            .peel_to_commit(&database)?;
+
+        let (data, workspace) = match revision {
+            Some(revision) => {
+                let tree = *database.load_commit(&start)?.tree();
+                let blob = blame::find(&database, &tree, &path)?
+                    .ok_or_else(|| anyhow!("No such path '{}' in {}", path.display(), revision))?;
+                (database.load_blob(&blob)?.into_data(), false)
+            }
+            None => {
+                let data = fs::read(repository.root().join(&path))
+                    .map_err(|error| anyhow!("Cannot read '{}': {}", path.display(), error))?;
+                (data, true)
+            }
+        };
+
+        let count = diff::lines(&data).len();
+        let ranges: Vec<_> = match self.ranges.is_empty() {
+            true => iter::once(0..count).collect(),
+            false => self
+                .ranges
+                .iter()
+                .map(|text| parse_range(text, count))
+                .collect::<anyhow::Result<_>>()?,
+        };
+
+        let contents = Some(data.as_slice()).filter(|_| workspace);
+        let cache = blame::Cache::open(&repository.root().join(".git"));
+        let entries = blame::blame(&database, &cache, start, &path, contents, &ranges)?;
+
+        let stdout = io::stdout();
+        let mut blame = Blame {
+            database,
+            path,
+            lines: diff::lines(&data),
+            details: HashMap::new(),
+            stdout: stdout.lock(),
+        };
+
+        if self.incremental {
+            blame.incremental(&entries)
+        } else if self.porcelain || self.line_porcelain {
+            blame.porcelain(entries, self.line_porcelain)
+        } else {
+            blame.annotate(entries)
+        }
+    }
+}
+
+struct Blame<'a> {
+    database: crate::Database,
+    path: path::PathBuf,
+    lines: Vec<&'a [u8]>,
+    details: HashMap<Option<object::Id>, Details>,
+    stdout: io::StdoutLock<'a>,
+}
+
+/// What's printed about a commit, which is `None` for uncommitted changes.
+struct Details {
+    author: object::Person,
+    committer: object::Person,
+    summary: String,
+    boundary: bool,
+    /// Whether these details were printed in the porcelain formats yet.
+    shown: bool,
+}
+
+impl<'a> Blame<'a> {
+    fn details(&mut self, commit: Option<object::Id>) -> anyhow::Result<&mut Details> {
+        if !self.details.contains_key(&commit) {
+            let details = match commit {
+                None => {
+                    let person = object::Person::new(
+                        String::from("Not Committed Yet"),
+                        String::from("not.committed.yet"),
+                        chrono::Local::now().into(),
+                    );
+                    Details {
+                        author: person.clone(),
+                        committer: person,
+                        summary: format!(
+                            "Version of {} from {}",
+                            self.path.display(),
+                            self.path.display()
+                        ),
+                        boundary: false,
+                        shown: false,
+                    }
+                }
+                Some(id) => {
+                    let commit = self.database.load_commit(&id)?;
+                    Details {
+                        author: commit.author().clone(),
+                        committer: commit.committer().clone(),
+                        summary: commit.title().to_owned(),
+                        boundary: commit.parents().is_empty(),
+                        shown: false,
+                    }
+                }
+            };
+            self.details.insert(commit, details);
+        }
+        Ok(self.details.get_mut(&commit).expect("inserted above"))
+    }
+
+    /// Print each line with its commit, author, and date, like `git blame`.
+    fn annotate(&mut self, mut entries: Vec<blame::Entry>) -> anyhow::Result<()> {
+        entries.sort_by_key(|entry| entry.target);
+
+        let mut author_width = 0;
+        for entry in &entries {
+            let name = self.details(entry.commit)?.author.name().chars().count();
+            author_width = author_width.max(name);
+        }
+        let number_width = entries
+            .iter()
+            .map(|entry| entry.target + entry.len)
+            .max()
+            .unwrap_or(0)
+            .to_string()
+            .len();
+
+        for entry in &entries {
+            let details = self.details(entry.commit)?;
+            let id = match entry.commit {
+                None => String::from("00000000"),
+                Some(id) if details.boundary => format!("^{}", &id.to_string()[..7]),
+                Some(id) => id.to_string()[..8].to_owned(),
+            };
+            let prefix = format!(
+                "{} ({:author_width$} {} ",
+                id,
+                details.author.name(),
+                details.author.time().format("%Y-%m-%d %H:%M:%S %z"),
+                author_width = author_width,
+            );
+
+            for target in entry.target..entry.target + entry.len {
+                write!(
+                    self.stdout,
+                    "{}{:>number_width$}) ",
+                    prefix,
+                    target + 1,
+                    number_width = number_width,
+                )?;
+                self.line(target)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Print each line after a header naming its commit, like
+    /// `git blame --porcelain` and `git blame --line-porcelain`.
+    fn porcelain(&mut self, mut entries: Vec<blame::Entry>, repeat: bool) -> anyhow::Result<()> {
+        entries.sort_by_key(|entry| entry.target);
+
+        for entry in &entries {
+            let id = id(entry.commit);
+            for offset in 0..entry.len {
+                match offset {
+                    0 => writeln!(
+                        self.stdout,
+                        "{} {} {} {}",
+                        id,
+                        entry.source + 1,
+                        entry.target + 1,
+                        entry.len
+                    )?,
+                    _ => writeln!(
+                        self.stdout,
+                        "{} {} {}",
+                        id,
+                        entry.source + offset + 1,
+                        entry.target + offset + 1
+                    )?,
+                }
+                if (offset == 0 || repeat) && self.describe(entry.commit, repeat)? {
+                    self.filename(entry)?;
+                }
+                write!(self.stdout, "\t")?;
+                self.line(entry.target + offset)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Print each group of lines in the order their commits were found,
+    /// like `git blame --incremental`.
+    fn incremental(&mut self, entries: &[blame::Entry]) -> anyhow::Result<()> {
+        for entry in entries {
+            writeln!(
+                self.stdout,
+                "{} {} {} {}",
+                id(entry.commit),
+                entry.source + 1,
+                entry.target + 1,
+                entry.len
+            )?;
+            self.describe(entry.commit, false)?;
+            self.filename(entry)?;
+        }
+        Ok(())
+    }
+
+    /// Describe `commit`, unless it was already described and `repeat` is
+    /// false. Returns whether anything was printed.
+    fn describe(&mut self, commit: Option<object::Id>, repeat: bool) -> anyhow::Result<bool> {
+        let details = self.details(commit)?;
+        if details.shown && !repeat {
+            return Ok(false);
+        }
+        details.shown = true;
+
+        let mut output = Vec::new();
+        for (role, person) in [
+            ("author", &details.author),
+            ("committer", &details.committer),
+        ] {
+            writeln!(output, "{} {}", role, person.name())?;
+            writeln!(output, "{}-mail <{}>", role, person.email())?;
+            writeln!(output, "{}-time {}", role, person.time().timestamp())?;
+            writeln!(output, "{}-tz {}", role, person.time().format("%z"))?;
+        }
+        writeln!(output, "summary {}", details.summary)?;
+        if details.boundary {
+            writeln!(output, "boundary")?;
+        }
+
+        self.stdout.write_all(&output)?;
+        Ok(true)
+    }
+
+    fn filename(&mut self, entry: &blame::Entry) -> io::Result<()> {
+        if let Some(previous) = entry.previous {
+            writeln!(self.stdout, "previous {} {}", previous, self.path.display())?;
+        }
+        writeln!(self.stdout, "filename {}", self.path.display())
+    }
+
+    /// Print line `index` of the blamed file, ending it with a newline even
+    /// if the file doesn't.
+    fn line(&mut self, index: usize) -> io::Result<()> {
+        let line = self.lines[index];
+        self.stdout.write_all(line)?;
+        if !line.ends_with(b"\n") {
+            writeln!(self.stdout)?;
+        }
+        Ok(())
+    }
+}
+
+/// Full id of `commit`, or all zeros for uncommitted changes.
+fn id(commit: Option<object::Id>) -> String {
+    match commit {
+        None => "0".repeat(40),
+        Some(id) => id.to_string(),
+    }
+}
+
+/// Parse a line range given to `-L` for a file with `count` lines into
+/// zero-based indices, like `git`: out-of-order bounds are swapped, and the
+/// end is clamped to the last line.
+fn parse_range(text: &str, count: usize) -> anyhow::Result<ops::Range<usize>> {
+    let invalid = || anyhow!("Invalid line range: {}", text);
+    let number = |text: &str| match text.parse::<usize>() {
+        Ok(0) => Err(anyhow!("Invalid line number: 0")),
+        Ok(number) => Ok(number),
+        Err(_) => Err(invalid()),
+    };
+
+    let (start, end) = text.split_once(',').unwrap_or((text, ""));
+    let start = match start {
+        "" => 1,
+        start => number(start)?,
+    };
+    let (start, end) = if end.is_empty() {
+        (start, count)
+    } else if let Some(offset) = end.strip_prefix('+') {
+        (start, (start + number(offset)?).saturating_sub(1))
+    } else if let Some(offset) = end.strip_prefix('-') {
+        ((start + 1).saturating_sub(number(offset)?).max(1), start)
+    } else {
+        let end = number(end)?;
+        (start.min(end), start.max(end))
+    };
+
+    if start > count {
+        return Err(anyhow!("File has only {} lines", count));
+    }
+    Ok(start - 1..end.min(count))
+}
+
+#[test]
+fn ranges() -> anyhow::Result<()> {
+    assert_eq!(parse_range("2,4", 12)?, 1..4);
+    assert_eq!(parse_range("4,2", 12)?, 1..4);
+    assert_eq!(parse_range("9,+2", 12)?, 8..10);
+    assert_eq!(parse_range("5,-2", 12)?, 3..5);
+    assert_eq!(parse_range("2,-5", 12)?, 0..2);
+    assert_eq!(parse_range("11", 12)?, 10..12);
+    assert_eq!(parse_range(",2", 12)?, 0..2);
+    assert_eq!(parse_range("1,20", 12)?, 0..12);
+    assert!(parse_range("20", 12).is_err());
+    assert!(parse_range("0,1", 12).is_err());
+    assert!(parse_range("a,b", 12).is_err());
+    Ok(())
+}
@@ -0,0 +1,126 @@
+use std::env;
+use std::io::Write as _;
+
+use anyhow::anyhow;
+use structopt::StructOpt;
+use termcolor::WriteColor as _;
+
+use crate::object;
+use crate::references;
+use crate::revision;
+
+/// List, create, or delete branches.
+#[derive(StructOpt)]
+pub struct Configuration {
+    /// Delete the named branch.
+    #[structopt(short, long)]
+    delete: bool,
+
+    /// List branches (the default when no name is given).
+    #[structopt(short, long)]
+    list: bool,
+
+    /// Name of the branch to create or delete.
+    name: Option<String>,
+
+    /// Revision for the new branch to point at.
+    ///
+    /// Defaults to `HEAD` if not provided.
+    start: Option<String>,
+}
+
+impl Configuration {
+    pub fn run(self) -> anyhow::Result<()> {
+        let root = env::current_dir()?;
+        let repository = crate::Repository::new(root);
+        let stdout = termcolor::StandardStream::stdout(match isatty::stdout_isatty() {
+            true => termcolor::ColorChoice::Always,
+            false => termcolor::ColorChoice::Never,
+        });
+
+        let mut branch = Branch {
+            references: repository.references(),
+            stdout: stdout.lock(),
+        };
+
+        match (self.delete, self.list, self.name) {
+            (true, _, None) => Err(anyhow!("Branch name required")),
+            (true, _, Some(name)) => branch.delete(&name),
+            (false, false, Some(name)) => {
+                let start = self.start.as_deref().unwrap_or("HEAD");
+                let id = start
+                    .parse::<revision::Revision>()?
+                    .resolve(&repository)?
+                    .ok_or_else(|| anyhow!("Not a valid object name: '{}'", start))?
+                    .peel_to_commit(&repository.database()?)?;
+                let committer = repository.config()?.committer()?;
+                branch.create(&name, &id, &committer, start)
+            }
+            (false, _, _) => branch.list(),
+        }
+    }
+}
+
+struct Branch<'a> {
+    references: crate::References,
+    stdout: termcolor::StandardStreamLock<'a>,
+}
+
+impl Branch<'_> {
+    fn list(&mut self) -> anyhow::Result<()> {
+        let current = self.references.current_branch()?;
+
+        for branch in self.references.branches()? {
+            let (name, _) = branch?;
+            if current.as_ref() == Some(&name) {
+                write!(&mut self.stdout, "* ")?;
+                self.stdout
+                    .set_color(termcolor::ColorSpec::new().set_fg(Some(termcolor::Color::Green)))?;
+                write!(&mut self.stdout, "{}", name)?;
+                self.stdout.reset()?;
+                writeln!(&mut self.stdout)?;
+            } else {
+                writeln!(&mut self.stdout, "  {}", name)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn create(
+        &mut self,
+        name: &str,
+        id: &object::Id,
+        committer: &object::Person,
+        start: &str,
+    ) -> anyhow::Result<()> {
+        self.references.create_branch(name, id)?;
+        self.references.store().append_log(
+            &format!("{}{}", crate::References::HEADS, name),
+            &references::LogEntry {
+                old: None,
+                new: *id,
+                committer: committer.clone(),
+                message: format!("branch: Created from {}", start),
+            },
+        )
+    }
+
+    fn delete(&mut self, name: &str) -> anyhow::Result<()> {
+        if self.references.current_branch()?.as_deref() == Some(name) {
+            return Err(anyhow!(
+                "Cannot delete branch '{}' checked out at HEAD",
+                name
+            ));
+        }
+
+        let id = self.references.delete_branch(name)?;
+        writeln!(
+            &mut self.stdout,
+            "Deleted branch {} (was {}).",
+            name,
+            &id.to_string()[..7],
+        )?;
+        Ok(())
+    }
+}
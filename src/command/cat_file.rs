@@ -0,0 +1,99 @@
+use std::env;
+use std::io;
+use std::io::Write as _;
+
+use anyhow::anyhow;
+use structopt::clap::ArgGroup;
+use structopt::StructOpt;
+
+use crate::object;
+use crate::revision;
+
+/// Print the type, size, or contents of an object.
+#[derive(StructOpt)]
+#[structopt(group = ArgGroup::with_name("query").required(true))]
+pub struct Configuration {
+    /// Print the object type.
+    #[structopt(short = "t", group = "query")]
+    r#type: bool,
+
+    /// Print the object size in bytes.
+    #[structopt(short = "s", group = "query")]
+    size: bool,
+
+    /// Pretty-print the object contents, listing trees like `ls-tree`.
+    #[structopt(short = "p", group = "query")]
+    pretty: bool,
+
+    /// Print nothing, but fail if the object does not exist.
+    #[structopt(short = "e", group = "query")]
+    exists: bool,
+
+    /// Revision naming the object.
+    object: String,
+}
+
+impl Configuration {
+    pub fn run(self) -> anyhow::Result<()> {
+        let root = env::current_dir()?;
+        let repository = crate::Repository::new(root);
+        let id = self
+            .object
+            .parse::<revision::Revision>()?
+            .resolve(&repository)?
+            .ok_or_else(|| anyhow!("Not a valid object name {}", self.object))?;
+        let cat_file = CatFile {
+            database: repository.database()?,
+        };
+
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+
+        if self.pretty {
+            cat_file.pretty(&id, &mut stdout)
+        } else if self.r#type {
+            writeln!(stdout, "{}", cat_file.database.kind(&id)?)?;
+            Ok(())
+        } else if self.size {
+            writeln!(stdout, "{}", cat_file.database.read_header(&id)?.len)?;
+            Ok(())
+        } else {
+            debug_assert!(self.exists);
+            match cat_file.database.contains(&id)? {
+                true => Ok(()),
+                false => Err(anyhow!("Object not found: {}", id)),
+            }
+        }
+    }
+}
+
+struct CatFile {
+    database: crate::Database,
+}
+
+impl CatFile {
+    fn pretty<W: io::Write>(&self, id: &object::Id, writer: &mut W) -> anyhow::Result<()> {
+        let (header, mut payload) = self.database.stream(id)?;
+
+        if header.r#type != object::Type::Tree {
+            // Blobs, commits, and tags are already human-readable.
+            io::copy(&mut payload, writer)?;
+            return Ok(());
+        }
+
+        for node in self.database.load_tree(id)? {
+            writeln!(
+                writer,
+                "{:06o} {} {}\t{}",
+                node.mode.as_u32(),
+                match node.mode.is_directory() {
+                    true => object::Type::Tree,
+                    false => object::Type::Blob,
+                },
+                node.id,
+                node.path.display(),
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,115 @@
+use std::env;
+
+use anyhow::anyhow;
+use structopt::StructOpt;
+
+use crate::diff;
+use crate::meta;
+use crate::migration;
+use crate::object;
+use crate::references;
+use crate::revision;
+
+/// Switch branches, or detach HEAD at a commit, updating the index and
+/// workspace to match.
+#[derive(StructOpt)]
+pub struct Configuration {
+    /// Branch name or revision to check out.
+    target: String,
+}
+
+impl Configuration {
+    pub fn run(self) -> anyhow::Result<()> {
+        let root = env::current_dir()?;
+        let repository = crate::Repository::new(root);
+
+        // Resolve the target before locking the index, which `:path`
+        // revisions read.
+        let branch = format!("{}{}", crate::References::HEADS, self.target);
+        let (id, head) = match repository.references().read(&branch)? {
+            Some(id) => (id, references::Target::Symbolic(branch)),
+            None => match self
+                .target
+                .parse::<revision::Revision>()?
+                .resolve(&repository)?
+            {
+                Some(id) => {
+                    let id = id.peel_to_commit(&repository.database()?)?;
+                    (id, references::Target::Direct(id))
+                }
+                None => {
+                    return Err(anyhow!(
+                        "pathspec '{}' did not match any file(s) known to grit",
+                        self.target,
+                    ))
+                }
+            },
+        };
+
+        let config = repository.config()?;
+        let checkout = Checkout {
+            check_stat: config.parse("core.checkStat")?.unwrap_or_default(),
+            committer: config.committer()?,
+            database: repository.database()?,
+            index: repository.index()?,
+            references: repository.references(),
+            workspace: repository.workspace()?,
+        };
+        checkout.run(&self.target, id, head)
+    }
+}
+
+struct Checkout {
+    check_stat: meta::CheckStat,
+    committer: object::Person,
+    database: crate::Database,
+    index: crate::Index,
+    references: crate::References,
+    workspace: crate::Workspace,
+}
+
+impl Checkout {
+    fn run(mut self, target: &str, id: object::Id, head: references::Target) -> anyhow::Result<()> {
+        let current = self.references.read_head()?;
+        let old = current
+            .map(|id| id.peel_to_tree(&self.database))
+            .transpose()?;
+        let new = id.peel_to_tree(&self.database)?;
+        let changes = diff::tree::diff(&self.database, old.as_ref(), Some(&new))?;
+
+        migration::Migration::new(
+            &self.database,
+            &mut self.index,
+            &self.workspace,
+            self.check_stat,
+            changes,
+        )
+        .apply()?;
+        self.index.commit()?;
+
+        let previous = self.references.current_branch()?;
+        let from = match (&previous, current) {
+            (Some(branch), _) => branch.clone(),
+            (None, Some(id)) => id.to_string(),
+            (None, None) => String::from("HEAD"),
+        };
+        self.references.switch_head(
+            &head,
+            &self.committer,
+            &format!("checkout: moving from {} to {}", from, target),
+        )?;
+
+        match head {
+            references::Target::Symbolic(_) if previous.as_deref() == Some(target) => {
+                eprintln!("Already on '{}'", target)
+            }
+            references::Target::Symbolic(_) => eprintln!("Switched to branch '{}'", target),
+            references::Target::Direct(id) => {
+                let commit = self.database.load_commit(&id)?;
+                eprintln!("HEAD is now at {} {}", &id.to_string()[..7], commit.title());
+            }
+        }
+
+        Ok(())
+    }
+}
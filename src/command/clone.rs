@@ -0,0 +1,354 @@
+use std::fs;
+use std::io;
+use std::path;
+
+use anyhow::anyhow;
+use structopt::StructOpt;
+
+use crate::bundle;
+use crate::config;
+use crate::migration;
+use crate::object;
+use crate::references;
+use crate::refspec;
+use crate::transport;
+
+/// Clone a repository into a new directory, over smart HTTP, over `ssh`
+/// for `ssh://` and `host:path` URLs, or from a path or `file://` URL on
+/// the same filesystem.
+///
+/// Every remote branch gets a remote-tracking branch under
+/// `refs/remotes/origin`, tags are copied, and the remote's current branch
+/// is checked out.
+///
+/// With `--mirror`, creates a bare repository instead, copying every remote
+/// reference to the same name, and configures `origin` so that `fetch` and
+/// `push` keep all references in sync, including deletions.
+///
+/// History can come from bundles first, given by `--bundle-uri` or, with
+/// `transfer.bundleURI`, advertised by the remote, leaving only the rest to
+/// fetch.
+#[derive(StructOpt)]
+pub struct Configuration {
+    /// Create a bare mirror of the remote repository.
+    #[structopt(long)]
+    mirror: bool,
+
+    /// Unpack the bundle at this URL or path before fetching the rest.
+    #[structopt(long)]
+    bundle_uri: Option<String>,
+
+    /// URL or path of the repository, e.g.
+    /// `https://github.com/nwtnni/grit.git`.
+    url: String,
+
+    /// Directory to clone into, named after the repository by default.
+    directory: Option<path::PathBuf>,
+}
+
+const REMOTE: &str = "origin";
+
+impl Configuration {
+    pub fn run(self) -> anyhow::Result<()> {
+        let url = self.url;
+        let directory = match self.directory {
+            Some(directory) => directory,
+            None if self.mirror => path::PathBuf::from(format!("{}.git", humanish(&url))),
+            None => path::PathBuf::from(humanish(&url)),
+        };
+
+        let created = match fs::read_dir(&directory).map(|mut entries| entries.next()) {
+            Ok(Some(_)) => {
+                return Err(anyhow!(
+                    "destination path '{}' already exists and is not an empty directory",
+                    directory.display(),
+                ))
+            }
+            Ok(_) => false,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                fs::create_dir_all(&directory)?;
+                true
+            }
+            Err(error) => return Err(error.into()),
+        };
+
+        match self.mirror {
+            true => eprintln!("Cloning into bare repository '{}'...", directory.display()),
+            false => eprintln!("Cloning into '{}'...", directory.display()),
+        }
+        let root = directory.canonicalize()?;
+
+        // Don't leave a half-cloned repository behind. A bare repository
+        // fills the whole directory.
+        let partial = match created || self.mirror {
+            true => root.clone(),
+            false => root.join(".git"),
+        };
+        let removal = crate::interrupt::remove_on_interrupt(partial.clone());
+        let bundle = self.bundle_uri.as_deref();
+        let result = match self.mirror {
+            true => mirror(&root, &url, bundle),
+            false => clone(&root, &url, bundle),
+        };
+        drop(removal);
+
+        if result.is_err() {
+            let _ = fs::remove_dir_all(&partial);
+            if !created && self.mirror {
+                let _ = fs::create_dir(&root);
+            }
+        }
+        result
+    }
+}
+
+fn clone(root: &path::Path, url: &str, bundle: Option<&str>) -> anyhow::Result<()> {
+    let mut repository = crate::Repository::new(root.to_path_buf());
+    repository.init()?;
+
+    let remote = transport::Remote::new(url, &repository.config()?)?;
+    let url = remote.url();
+    let advertisement = remote.advertise()?;
+
+    let mut document = config::Document::open(root.join(".git/config"))?;
+    document.set(&format!("remote.{}.url", REMOTE), url)?;
+    let refspec = refspec::Refspec::tracking(REMOTE);
+    document.set(&format!("remote.{}.fetch", REMOTE), &refspec.to_string())?;
+
+    let mut wants = advertisement
+        .refs
+        .iter()
+        .filter(|(name, _)| {
+            name.starts_with(crate::References::HEADS) || name.starts_with(crate::References::TAGS)
+        })
+        .map(|(_, id)| *id)
+        .collect::<Vec<_>>();
+    wants.sort();
+    wants.dedup();
+
+    if wants.is_empty() {
+        document.commit()?;
+        eprintln!("warning: You appear to have cloned an empty repository.");
+        return Ok(());
+    }
+
+    download(&repository, &remote, &advertisement, &wants, bundle)?;
+    let database = repository.database()?;
+
+    let references = repository.references();
+    let committer = repository.config()?.committer()?;
+    let message = format!("clone: from {}", url);
+    for (name, id) in &advertisement.refs {
+        if let Some(tracking) = refspec.map(name) {
+            references.update_ref(&tracking, id, &committer, &message)?;
+        } else if name.starts_with(crate::References::TAGS) {
+            references
+                .store()
+                .write(name, &references::Target::Direct(*id))?;
+        }
+    }
+
+    let head = advertisement
+        .head()
+        .and_then(|head| head.strip_prefix(crate::References::HEADS));
+    let id = match head {
+        Some(branch) => {
+            let full = format!("{}{}", crate::References::HEADS, branch);
+            let tracking = refspec
+                .map(&full)
+                .expect("[INTERNAL ERROR]: default refspec maps every branch");
+            references.store().write(
+                &format!("{}{}/HEAD", crate::References::REMOTES, REMOTE),
+                &references::Target::Symbolic(tracking),
+            )?;
+            document.set(&format!("branch.{}.remote", branch), REMOTE)?;
+            document.set(&format!("branch.{}.merge", branch), &full)?;
+
+            let id = advertisement
+                .refs
+                .iter()
+                .find(|(name, _)| *name == full)
+                .map(|(_, id)| *id)
+                .ok_or_else(|| anyhow!("Remote HEAD refers to missing branch {}", branch))?;
+            references.set_head(&references::Target::Symbolic(full))?;
+            references.update_head(&id, &committer, &message)?;
+            id
+        }
+        // Detach at the remote's `HEAD`, if it has one.
+        None => match advertisement.refs.iter().find(|(name, _)| name == "HEAD") {
+            None => {
+                document.commit()?;
+                eprintln!("warning: remote HEAD refers to nonexistent ref, unable to checkout");
+                return Ok(());
+            }
+            Some((_, id)) => {
+                references.set_head(&references::Target::Direct(*id))?;
+                *id
+            }
+        },
+    };
+    document.commit()?;
+
+    let check_stat = repository
+        .config()?
+        .parse("core.checkStat")?
+        .unwrap_or_default();
+    let mut index = repository.index()?;
+    let tree = id.peel_to_tree(&database)?;
+    migration::reset(
+        &database,
+        &mut index,
+        &repository.workspace()?,
+        check_stat,
+        &tree,
+    )?;
+    index.commit()?;
+    Ok(())
+}
+
+/// Clone `url` into the bare repository `root`, copying every reference.
+fn mirror(root: &path::Path, url: &str, bundle: Option<&str>) -> anyhow::Result<()> {
+    let mut repository = crate::Repository::bare(root.to_path_buf());
+    repository.init()?;
+
+    let remote = transport::Remote::new(url, &repository.config()?)?;
+    let url = remote.url();
+    let advertisement = remote.advertise()?;
+
+    let mut document = config::Document::open(root.join("config"))?;
+    document.set(&format!("remote.{}.url", REMOTE), url)?;
+    document.set(
+        &format!("remote.{}.fetch", REMOTE),
+        &refspec::Refspec::mirror().to_string(),
+    )?;
+    document.set(&format!("remote.{}.mirror", REMOTE), "true")?;
+    document.commit()?;
+
+    let mut wants = advertisement
+        .refs
+        .iter()
+        .filter(|(name, _)| name.starts_with("refs/"))
+        .map(|(_, id)| *id)
+        .collect::<Vec<_>>();
+    wants.sort();
+    wants.dedup();
+
+    if wants.is_empty() {
+        eprintln!("warning: You appear to have cloned an empty repository.");
+        return Ok(());
+    }
+
+    download(&repository, &remote, &advertisement, &wants, bundle)?;
+
+    let updates = advertisement
+        .refs
+        .iter()
+        .filter(|(name, _)| name.starts_with("refs/"))
+        .map(|(name, id)| references::Update {
+            name: name.clone(),
+            old: None,
+            new: Some(*id),
+        })
+        .collect::<Vec<_>>();
+    let references = repository.references();
+    let committer = repository.config()?.committer()?;
+    references.transaction(&updates, &committer, &format!("clone: from {}", url))?;
+
+    if let Some(head) = advertisement.head() {
+        references.set_head(&references::Target::Symbolic(head.to_owned()))?;
+    }
+    Ok(())
+}
+
+/// Download `wants` and every object they reach into `repository`,
+/// unpacking bundles first if there are any and fetching what they lack.
+fn download(
+    repository: &crate::Repository,
+    remote: &transport::Remote,
+    advertisement: &transport::Advertisement,
+    wants: &[object::Id],
+    bundle: Option<&str>,
+) -> anyhow::Result<()> {
+    let uris = match bundle {
+        Some(uri) => vec![uri.to_owned()],
+        None if repository.config()?.get_bool("transfer.bundleURI")? == Some(true) => {
+            remote.bundle_uris().unwrap_or_else(|error| {
+                eprintln!("warning: failed to list bundles: {:#}", error);
+                Vec::new()
+            })
+        }
+        None => Vec::new(),
+    };
+
+    // Bundles only save time, so failing to use one isn't fatal.
+    let mut haves = Vec::new();
+    for uri in &uris {
+        eprintln!("Unbundling {}...", uri);
+        match unbundle(repository, remote, uri) {
+            Ok(tips) => haves.extend(tips),
+            Err(error) => eprintln!("warning: failed to unbundle '{}': {:#}", uri, error),
+        }
+    }
+
+    // Reopen the database, which only sees packs that existed when it
+    // first read one.
+    let database = repository.database()?;
+    let mut missing = Vec::new();
+    for want in wants {
+        if !database.contains(want)? {
+            missing.push(*want);
+        }
+    }
+    if !missing.is_empty() {
+        remote.fetch(
+            &database,
+            advertisement,
+            &missing,
+            &haves,
+            &mut io::stderr(),
+        )?;
+    }
+    Ok(())
+}
+
+/// Download the bundle at `uri` and unpack it into `repository`, returning
+/// the commits its references point to.
+fn unbundle(
+    repository: &crate::Repository,
+    remote: &transport::Remote,
+    uri: &str,
+) -> anyhow::Result<Vec<object::Id>> {
+    let bundle = bundle::Bundle::read(remote.download(uri)?)?;
+    let database = repository.database()?;
+    for prerequisite in &bundle.prerequisites {
+        if !database.contains(prerequisite)? {
+            return Err(anyhow!("Missing prerequisite commit {}", prerequisite));
+        }
+    }
+    database.index_pack(bundle.pack)?;
+
+    let database = repository.database()?;
+    let mut tips = Vec::new();
+    for (_, id) in &bundle.refs {
+        if let Ok(commit) = id.peel_to_commit(&database) {
+            tips.push(commit);
+        }
+    }
+    Ok(tips)
+}
+
+/// Guess a directory name from `url`, like `grit` for
+/// `https://github.com/nwtnni/grit.git`.
+fn humanish(url: &str) -> &str {
+    let url = url.trim_end_matches('/');
+    let url = url.strip_suffix("/.git").unwrap_or(url);
+    let name = url.rsplit(&['/', ':'][..]).next().unwrap_or(url);
+    name.strip_suffix(".git").unwrap_or(name)
+}
+
+#[test]
+fn directory() {
+    assert_eq!(humanish("https://github.com/nwtnni/grit.git"), "grit");
+    assert_eq!(humanish("https://github.com/nwtnni/grit/"), "grit");
+    assert_eq!(humanish("http://localhost:8000/repo/.git"), "repo");
+}
@@ -1,31 +1,80 @@
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 use std::env;
+use std::fs;
 use std::io;
 use std::io::Read as _;
+use std::io::Write as _;
 use std::path;
 
+use anyhow::anyhow;
 use structopt::StructOpt;
 
-use crate::index;
+use crate::diff;
+use crate::diff::rename;
+use crate::gc;
 use crate::object;
-use crate::object::tree;
-use crate::util::Tap as _;
+use crate::util;
 
 #[derive(StructOpt)]
 pub struct Configuration {
+    /// Defaults to `user.name`.
     #[structopt(long, env = "GIT_AUTHOR_NAME")]
-    author_name: String,
+    author_name: Option<String>,
 
+    /// Defaults to `user.email`.
     #[structopt(long, env = "GIT_AUTHOR_EMAIL")]
-    author_email: String,
+    author_email: Option<String>,
+
+    /// Defaults to the current time.
+    #[structopt(
+        long,
+        env = "GIT_AUTHOR_DATE",
+        parse(try_from_str = object::Person::parse_time)
+    )]
+    author_date: Option<chrono::DateTime<chrono::FixedOffset>>,
+
+    /// Defaults to `user.name`.
+    #[structopt(long, env = "GIT_COMMITTER_NAME")]
+    committer_name: Option<String>,
+
+    /// Defaults to `user.email`.
+    #[structopt(long, env = "GIT_COMMITTER_EMAIL")]
+    committer_email: Option<String>,
+
+    /// Defaults to the current time.
+    #[structopt(
+        long,
+        env = "GIT_COMMITTER_DATE",
+        parse(try_from_str = object::Person::parse_time)
+    )]
+    committer_date: Option<chrono::DateTime<chrono::FixedOffset>>,
 
     #[structopt(short, long)]
     message: Option<String>,
+
+    /// Don't summarize the files changed by the new commit.
+    #[structopt(short, long)]
+    quiet: bool,
+
+    /// Print the new commit's full id, then one line per changed file:
+    /// `<status>\t<insertions>\t<deletions>\t<path>`, where `<status>` is
+    /// `A`, `D`, `M`, or `R<similarity>` followed by the old path.
+    #[structopt(long, conflicts_with = "quiet")]
+    porcelain: bool,
 }
 
 impl Configuration {
     pub fn run(self) -> anyhow::Result<()> {
+        let root = env::current_dir()?;
+        let repository = crate::Repository::new(root);
+        let git = repository.root().join(".git");
+
         let message = match self.message {
             Some(message) => message,
+            // Conclude a conflicted merge, cherry-pick, or revert with its
+            // prepared message.
+            None if git.join(MERGE_MSG).exists() => fs::read_to_string(git.join(MERGE_MSG))?,
             None => {
                 let stdin = io::stdin();
                 let mut stdin = stdin.lock();
@@ -35,34 +84,82 @@ impl Configuration {
             }
         };
 
-        let root = env::current_dir()?;
-        let repository = crate::Repository::new(root);
+        let config = repository.config()?;
+        let now = chrono::Local::now().into();
+        let (name, email) = config.identity(self.author_name, self.author_email)?;
+        let author = object::Person::new(name, email, self.author_date.unwrap_or(now));
+        let (name, email) = config.identity(self.committer_name, self.committer_email)?;
+        let committer = object::Person::new(name, email, self.committer_date.unwrap_or(now));
+
         let commit = Commit {
-            database: repository.database(),
+            output: match (self.quiet, self.porcelain) {
+                (true, _) => Output::Quiet,
+                (_, true) => Output::Porcelain,
+                (false, false) => Output::Summary,
+            },
+            renames: rename::Options::configure(&config, "diff", None, false)?,
+            git,
+            database: repository.database()?,
             index: repository.index()?,
             references: repository.references(),
-            author_name: self.author_name,
-            author_email: self.author_email,
+            author,
+            committer,
             message,
         };
 
         commit.run()?;
-        Ok(())
+        gc::auto(&repository)
     }
 }
 
+const MERGE_MSG: &str = "MERGE_MSG";
+const CHERRY_PICK_HEAD: &str = "CHERRY_PICK_HEAD";
+const REVERT_HEAD: &str = "REVERT_HEAD";
+
+/// What to print after committing.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Output {
+    Quiet,
+    Summary,
+    Porcelain,
+}
+
+/// A file changed by the new commit.
+struct Change {
+    path: util::PathBuf,
+    old: Option<diff::tree::Entry>,
+    new: Option<diff::tree::Entry>,
+    /// Previous path and similarity, if renamed.
+    renamed: Option<(util::PathBuf, rename::Score)>,
+    insertions: usize,
+    deletions: usize,
+}
+
 struct Commit {
+    output: Output,
+    renames: Option<rename::Options>,
+    git: path::PathBuf,
     database: crate::Database,
     index: crate::Index,
     references: crate::References,
-    author_name: String,
-    author_email: String,
+    author: object::Person,
+    committer: object::Person,
     message: String,
 }
 
 impl Commit {
-    pub fn run(self) -> anyhow::Result<()> {
-        let commit_tree = self.walk_index()?;
+    pub fn run(mut self) -> anyhow::Result<()> {
+        if self.index.is_conflicted() {
+            return Err(anyhow!(
+                "Committing is not possible because you have unmerged files.\n\
+                 hint: Fix them up in the work tree, and then use 'grit add <file>'\n\
+                 hint: as appropriate to mark resolution and make a commit."
+            ));
+        }
+
+        // Flush the new trees and commit together before `HEAD` refers to them.
+        let batch = self.database.batch();
+        let commit_tree = self.index.write_tree(&self.database)?;
         let commit_header = self
             .message
             .split('\n')
@@ -70,82 +167,288 @@ impl Commit {
             .unwrap_or_default()
             .to_owned();
 
-        let author = object::Person::new(self.author_name, self.author_email, chrono::Local::now());
         let parent = self.references.read_head()?;
+        let merge = self.references.read("MERGE_HEAD")?;
+        let cherry_pick = self.references.read(CHERRY_PICK_HEAD)?;
+        let revert = self.references.read(REVERT_HEAD)?;
+
+        // Like `git`, keep the original author of a cherry-picked commit.
+        let author = match cherry_pick {
+            Some(id) => self.database.load_commit(&id)?.author().clone(),
+            None => self.author,
+        };
+
         let commit = crate::Object::Commit(object::Commit::new(
             commit_tree,
-            parent,
+            parent.into_iter().chain(merge).collect(),
             author,
+            self.committer.clone(),
             self.message,
         ));
         let commit_id = self.database.store(&commit)?;
+        batch.commit()?;
 
-        self.references.write_head(&commit_id)?;
+        let kind = match (parent, merge, cherry_pick) {
+            (None, _, _) => " (initial)",
+            (Some(_), Some(_), _) => " (merge)",
+            (Some(_), None, Some(_)) => " (cherry-pick)",
+            (Some(_), None, None) => "",
+        };
+        self.references.update_head(
+            &commit_id,
+            &self.committer,
+            &format!("commit{}: {}", kind, commit_header),
+        )?;
+
+        // Save the cached trees, so unchanged directories aren't stored again
+        // by the next commit.
+        self.index.commit()?;
 
-        println!(
-            "[{}{}] {}",
+        // Conclude the merge, cherry-pick, or revert in progress.
+        for (name, id) in [
+            ("MERGE_HEAD", merge),
+            (CHERRY_PICK_HEAD, cherry_pick),
+            (REVERT_HEAD, revert),
+        ] {
+            if id.is_some() {
+                self.references.store().delete(name)?;
+            }
+        }
+        if merge.or(cherry_pick).or(revert).is_some() {
+            for file in [MERGE_MSG, "MERGE_MODE"] {
+                match fs::remove_file(self.git.join(file)) {
+                    Err(error) if error.kind() != io::ErrorKind::NotFound => {
+                        return Err(error.into())
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        // Like `git`, merge commits have no summary.
+        let changes = match (self.output, merge) {
+            (Output::Quiet, _) | (_, Some(_)) => Vec::new(),
+            (_, None) => changes(&self.database, self.renames, parent.as_ref(), &commit_tree)?,
+        };
+
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        if self.output == Output::Porcelain {
+            writeln!(stdout, "{}", commit_id)?;
+            for change in &changes {
+                let status = match (&change.renamed, change.old, change.new) {
+                    (Some((old, score)), _, _) => {
+                        format!("R{:03}\t{}", score.percent(), old.display())
+                    }
+                    (None, None, _) => String::from("A"),
+                    (None, _, None) => String::from("D"),
+                    (None, Some(_), Some(_)) => String::from("M"),
+                };
+                writeln!(
+                    stdout,
+                    "{}\t{}\t{}\t{}",
+                    status,
+                    change.insertions,
+                    change.deletions,
+                    change.path.display()
+                )?;
+            }
+            return Ok(());
+        }
+
+        let branch = self
+            .references
+            .current_branch()?
+            .unwrap_or_else(|| String::from("detached HEAD"));
+
+        writeln!(
+            stdout,
+            "[{} {}{}] {}",
+            branch,
             if parent.is_some() {
                 ""
             } else {
-                "(root-commit)"
+                "(root-commit) "
             },
-            commit_id,
+            &commit_id.to_string()[..7],
             commit_header
-        );
+        )?;
+
+        if changes.is_empty() {
+            return Ok(());
+        }
+
+        let insertions = changes.iter().map(|change| change.insertions).sum();
+        let deletions = changes.iter().map(|change| change.deletions).sum();
+        writeln!(
+            stdout,
+            "{}",
+            diff::shortstat(changes.len(), insertions, deletions)
+        )?;
+
+        let mode = |entry: &diff::tree::Entry| format!("{:0>6}", entry.mode.as_str());
+        for change in &changes {
+            match (&change.renamed, change.old, change.new) {
+                (Some((old, score)), _, _) => writeln!(
+                    stdout,
+                    " rename {} ({}%)",
+                    pretty_rename(
+                        &old.display().to_string(),
+                        &change.path.display().to_string()
+                    ),
+                    score.percent(),
+                )?,
+                (None, None, Some(new)) => writeln!(
+                    stdout,
+                    " create mode {} {}",
+                    mode(&new),
+                    change.path.display()
+                )?,
+                (None, Some(old), None) => writeln!(
+                    stdout,
+                    " delete mode {} {}",
+                    mode(&old),
+                    change.path.display()
+                )?,
+                _ => (),
+            }
+
+            match (change.old, change.new) {
+                (Some(old), Some(new)) if old.mode != new.mode => match change.renamed {
+                    Some(_) => writeln!(stdout, " mode change {} => {}", mode(&old), mode(&new))?,
+                    None => writeln!(
+                        stdout,
+                        " mode change {} => {} {}",
+                        mode(&old),
+                        mode(&new),
+                        change.path.display()
+                    )?,
+                },
+                _ => (),
+            }
+        }
 
         Ok(())
     }
+}
 
-    fn walk_index(&self) -> anyhow::Result<object::Id> {
-        let mut stack = Vec::new();
-        let mut count = Vec::new();
-
-        for node in &self.index {
-            let path = node.path();
-            let depth = path.components().count();
-            let name = path
-                .file_name()
-                .unwrap_or_default()
-                .to_os_string()
-                .tap(path::PathBuf::from);
-
-            let id = match node {
-                index::Node::File(entry) => {
-                    count.resize(depth, 0);
-                    *entry.id()
-                }
-                index::Node::Directory(_) => {
-                    count.resize(depth + 1, 0);
-                    let index = match count.pop() {
-                        None => unreachable!(),
-                        Some(0) => continue,
-                        Some(count) => stack.len() - count,
-                    };
-                    stack
-                        .split_off(index)
-                        .tap(tree::Root::new)
-                        .tap(crate::Object::Tree)
-                        .tap(|tree| self.database.store(&tree))?
-                }
-            };
+/// Compare `tree` against the tree of `parent`, or an empty tree for a
+/// root commit, pairing up renamed files.
+fn changes(
+    database: &crate::Database,
+    renames: Option<rename::Options>,
+    parent: Option<&object::Id>,
+    tree: &object::Id,
+) -> anyhow::Result<Vec<Change>> {
+    let old = parent
+        .map(|parent| parent.peel_to_tree(database))
+        .transpose()?;
+    let changes = diff::tree::diff(database, old.as_ref(), Some(tree))?;
 
-            let mode = node.mode();
-            let node = tree::Node::new(name, id, *mode);
+    let mut renamed = BTreeMap::new();
+    if let Some(options) = renames {
+        let (deleted, added) = changes.iter().fold(
+            (Vec::new(), Vec::new()),
+            |(mut deleted, mut added), (path, change)| {
+                match change {
+                    (Some(old), None) => deleted.push((path.clone(), old.id)),
+                    (None, Some(new)) => added.push((path.clone(), new.id)),
+                    _ => (),
+                }
+                (deleted, added)
+            },
+        );
+        for rename in rename::detect(database, &deleted, &added, options)? {
+            renamed.insert(rename.new, (rename.old, rename.score));
+        }
+    }
+    let sources = renamed
+        .values()
+        .map(|(old, _)| old.clone())
+        .collect::<BTreeSet<_>>();
 
-            stack.push(node);
+    let load = |entry: Option<diff::tree::Entry>| match entry {
+        None => Ok(Vec::new()),
+        Some(entry) => database
+            .load_blob(&entry.id)
+            .map(|blob| blob.data().to_vec()),
+    };
 
-            match count.last_mut() {
-                None if path == path::Path::new("") => (),
-                None => unreachable!(),
-                Some(count) => *count += 1,
-            }
+    let mut summary = Vec::new();
+    for (path, (old, new)) in &changes {
+        if sources.contains(path) {
+            continue;
         }
+        let renamed = renamed.remove(path);
+        let old = match &renamed {
+            Some((source, _)) => changes[source].0,
+            None => *old,
+        };
+        let (insertions, deletions) = diff::count(&load(old)?, &load(*new)?);
+        summary.push(Change {
+            path: path.clone(),
+            old,
+            new: *new,
+            renamed,
+            insertions,
+            deletions,
+        });
+    }
+    Ok(summary)
+}
+
+/// Abbreviate a rename like `git`, factoring out the directories shared by
+/// `old` and `new`: `dir/{a => b}`, or `{a => b}/file`.
+fn pretty_rename(old: &str, new: &str) -> String {
+    let (a, b) = (old.as_bytes(), new.as_bytes());
 
-        let tree_id = stack
-            .pop()
-            .expect("[INTERNAL ERROR]: index must contain at least root directory")
-            .id;
+    let prefix = a
+        .iter()
+        .zip(b)
+        .take_while(|(a, b)| a == b)
+        .enumerate()
+        .filter(|(_, (byte, _))| **byte == b'/')
+        .last()
+        .map_or(0, |(index, _)| index + 1);
 
-        Ok(tree_id)
+    // The common suffix starts at a slash, which may be the one ending the
+    // common prefix.
+    let overlap = if prefix > 0 { 1 } else { 0 };
+    let mut suffix = 0;
+    for offset in 1..=(a.len().min(b.len()) + overlap - prefix) {
+        let byte = a[a.len() - offset];
+        if byte != b[b.len() - offset] {
+            break;
+        }
+        if byte == b'/' {
+            suffix = offset;
+        }
+    }
+
+    if prefix + suffix == 0 {
+        return format!("{} => {}", old, new);
     }
+
+    let middle = |path: &str| {
+        let end = path.len() - suffix;
+        path.get(prefix..end.max(prefix))
+            .unwrap_or_default()
+            .to_owned()
+    };
+    format!(
+        "{}{{{} => {}}}{}",
+        &old[..prefix],
+        middle(old),
+        middle(new),
+        &old[old.len() - suffix..],
+    )
+}
+
+#[test]
+fn rename() {
+    assert_eq!(pretty_rename("a", "b"), "a => b");
+    assert_eq!(pretty_rename("d/a", "d/b"), "d/{a => b}");
+    assert_eq!(pretty_rename("d/b", "e/f/b"), "{d => e/f}/b");
+    assert_eq!(pretty_rename("a/b", "a/c/b"), "a/{ => c}/b");
+    assert_eq!(pretty_rename("x/a/y", "x/b/y"), "x/{a => b}/y");
 }
@@ -0,0 +1,58 @@
+use std::env;
+use std::io;
+use std::io::Write as _;
+
+use structopt::StructOpt;
+
+use crate::database::commit_graph;
+
+/// Write and inspect the commit-graph, which speeds up walking history.
+#[derive(StructOpt)]
+pub enum Configuration {
+    /// Write a commit-graph containing every commit reachable from `HEAD`
+    /// and all references.
+    Write {
+        /// Only write commits missing from the existing graph, as a new
+        /// layer in a chain, merging it with smaller layers above it.
+        #[structopt(long)]
+        split: bool,
+    },
+}
+
+impl Configuration {
+    pub fn run(self) -> anyhow::Result<()> {
+        let root = env::current_dir()?;
+        let repository = crate::Repository::new(root);
+        let database = repository.database()?;
+        let references = repository.references();
+        let info = repository.root().join(".git/objects/info");
+
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        match self {
+            Configuration::Write { split } => {
+                let mut tips = Vec::new();
+                let heads = references
+                    .iter_prefix("refs/")?
+                    .map(|reference| reference.map(|(_, id)| id))
+                    .chain(references.read_head().transpose());
+                for id in heads {
+                    // Tags may point at trees or blobs, which have no history.
+                    if let Ok(id) = id?.peel_to_commit(&database) {
+                        tips.push(id);
+                    }
+                }
+
+                let graph =
+                    commit_graph::Graph::open(&info)?.write(&database, &info, &tips, split)?;
+                writeln!(
+                    stdout,
+                    "Wrote {} commits in {} layer(s)",
+                    graph.len(),
+                    graph.layers().len(),
+                )?;
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use std::env;
+use std::io;
+use std::io::Write as _;
+
+use anyhow::anyhow;
+use structopt::clap::ArgGroup;
+use structopt::StructOpt;
+
+use crate::config;
+
+/// Get and set repository or global options.
+///
+/// With only a key, prints its value. With a key and a value, sets it in the
+/// repository's `.git/config` unless another scope is given.
+#[derive(StructOpt)]
+#[structopt(group = ArgGroup::with_name("scope"), group = ArgGroup::with_name("action"))]
+pub struct Configuration {
+    /// Use the system-wide configuration file.
+    #[structopt(long, group = "scope")]
+    system: bool,
+
+    /// Use the per-user configuration file.
+    #[structopt(long, group = "scope")]
+    global: bool,
+
+    /// Use the repository configuration file.
+    #[structopt(long, group = "scope")]
+    local: bool,
+
+    /// Print the last value of the key.
+    #[structopt(long, group = "action")]
+    get: bool,
+
+    /// Print every value of the key.
+    #[structopt(long, group = "action")]
+    get_all: bool,
+
+    /// Set the key to the value, replacing its single existing value.
+    #[structopt(long, group = "action")]
+    set: bool,
+
+    /// Add another value for the key.
+    #[structopt(long, group = "action")]
+    add: bool,
+
+    /// Remove the single value of the key.
+    #[structopt(long, group = "action")]
+    unset: bool,
+
+    /// Print every key and value.
+    #[structopt(short, long, group = "action")]
+    list: bool,
+
+    key: Option<String>,
+
+    value: Option<String>,
+}
+
+impl Configuration {
+    pub fn run(self) -> anyhow::Result<()> {
+        let root = env::current_dir()?;
+        let repository = crate::Repository::new(root);
+        let git = repository.root().join(".git");
+
+        let scope = match (self.system, self.global, self.local) {
+            (true, _, _) => Some(config::Scope::System),
+            (_, true, _) => Some(config::Scope::Global),
+            (_, _, true) => Some(config::Scope::Local),
+            _ => None,
+        };
+
+        let read = || match scope {
+            None => repository.config(),
+            Some(scope) => config::Config::scoped(scope, &git),
+        };
+
+        let key = || self.key.as_deref().ok_or_else(|| anyhow!("Missing key"));
+        let value = || {
+            self.value
+                .as_deref()
+                .ok_or_else(|| anyhow!("Missing value"))
+        };
+
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        if self.list {
+            for (key, value) in read()?.iter() {
+                writeln!(stdout, "{}={}", key, value)?;
+            }
+            Ok(())
+        } else if self.get_all {
+            let config = read()?;
+            let values = config.get_all(key()?);
+            if values.is_empty() {
+                return Err(anyhow!("Key not found: {}", key()?));
+            }
+            for value in values {
+                writeln!(stdout, "{}", value)?;
+            }
+            Ok(())
+        } else if self.unset || self.add || self.set || (!self.get && self.value.is_some()) {
+            let path = scope
+                .unwrap_or(config::Scope::Local)
+                .paths(&git)
+                .pop()
+                .ok_or_else(|| anyhow!("No configuration file in this scope"))?;
+            let mut document = config::Document::open(path)?;
+            match (self.unset, self.add) {
+                (true, _) => document.unset(key()?)?,
+                (_, true) => document.add(key()?, value()?)?,
+                _ => document.set(key()?, value()?)?,
+            }
+            document.commit()
+        } else {
+            match read()?.get(key()?) {
+                None => Err(anyhow!("Key not found: {}", key()?)),
+                Some(value) => {
+                    writeln!(stdout, "{}", value)?;
+                    Ok(())
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,549 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::io::Write as _;
+use std::path;
+use std::process;
+
+use anyhow::anyhow;
+use structopt::StructOpt;
+
+use crate::attributes;
+use crate::config;
+use crate::diff;
+use crate::diff::rename;
+use crate::meta;
+use crate::object;
+use crate::util;
+
+/// Show changes between the workspace and the index, or between the index
+/// and the HEAD commit.
+#[derive(StructOpt)]
+pub struct Configuration {
+    /// Compare the index against the HEAD commit instead of the workspace.
+    #[structopt(long, alias = "staged")]
+    cached: bool,
+
+    /// Print files in the order given by this file, which lists one glob
+    /// per line. Overrides `diff.orderFile`.
+    #[structopt(short = "O", value_name = "orderfile")]
+    order_file: Option<path::PathBuf>,
+
+    /// Print every diff with the built-in diff, even for files configured
+    /// to use an external program.
+    #[structopt(long)]
+    no_ext_diff: bool,
+
+    /// Detect renamed files with `--cached`, optionally with a minimum
+    /// similarity such as `-M90%`. Overrides `diff.renames`.
+    #[structopt(short = "M", long)]
+    find_renames: Option<Option<rename::Score>>,
+
+    /// Do not detect renames. Overrides `diff.renames`.
+    #[structopt(long, conflicts_with = "find-renames")]
+    no_renames: bool,
+
+    /// Only compare files under these paths, relative to the current
+    /// directory.
+    paths: Vec<path::PathBuf>,
+}
+
+impl Configuration {
+    pub fn run(self) -> anyhow::Result<()> {
+        let (repository, prefix) = crate::Repository::discover(&env::current_dir()?)?;
+        let stdout = termcolor::StandardStream::stdout(match isatty::stdout_isatty() {
+            true => termcolor::ColorChoice::Always,
+            false => termcolor::ColorChoice::Never,
+        });
+
+        let diff = Diff {
+            paths: self
+                .paths
+                .iter()
+                .map(|path| prefix.resolve(path))
+                .collect::<anyhow::Result<_>>()?,
+            order: diff::order::Order::open(&repository, self.order_file.as_deref())?,
+            renames: rename::Options::configure(
+                &repository.config()?,
+                "diff",
+                self.find_renames,
+                self.no_renames,
+            )?,
+            textconv: diff::textconv::Textconv::new(&repository)?,
+            external: match self.no_ext_diff {
+                true => None,
+                false => Some(External::new(&repository.config()?)),
+            },
+            database: repository.database()?,
+            index: repository.index()?,
+            references: repository.references(),
+            workspace: repository.workspace()?,
+            check_stat: repository
+                .config()?
+                .parse("core.checkStat")?
+                .unwrap_or_default(),
+            stdout: stdout.lock(),
+        };
+
+        if self.cached {
+            diff.run_cached()
+        } else {
+            diff.run_workspace()
+        }
+    }
+}
+
+struct Diff<'a> {
+    /// Paths relative to the workspace root to limit the comparison to, or
+    /// empty to compare everything.
+    paths: Vec<path::PathBuf>,
+    order: diff::order::Order,
+    renames: Option<rename::Options>,
+    textconv: diff::textconv::Textconv,
+    external: Option<External>,
+    database: crate::Database,
+    index: crate::Index,
+    references: crate::References,
+    workspace: crate::Workspace,
+    check_stat: meta::CheckStat,
+    stdout: termcolor::StandardStreamLock<'a>,
+}
+
+impl Diff<'_> {
+    /// Whether `path` is under one of the requested paths.
+    fn is_selected(&self, path: &path::Path) -> bool {
+        self.paths.is_empty() || self.paths.iter().any(|prefix| path.starts_with(prefix))
+    }
+
+    fn run_workspace(mut self) -> anyhow::Result<()> {
+        let mut changes = Vec::new();
+        for entry in self.index.entries() {
+            if !self.is_selected(entry.path()) {
+                continue;
+            }
+
+            let metadata = match self.workspace.metadata(entry.path()) {
+                Ok(metadata) => Some(self.workspace.normalize(metadata, entry.metadata().mode)),
+                Err(error) if error.kind() == io::ErrorKind::NotFound => None,
+                Err(error) => return Err(error.into()),
+            };
+
+            let old = entry.metadata();
+            let metadata = match metadata {
+                Some(new)
+                    if new.is_stat_clean(old, self.check_stat)
+                        && !entry.is_racy(self.index.timestamp()) =>
+                {
+                    continue
+                }
+                Some(new) => new,
+                None => {
+                    let a = Side::load(&self.database, entry.path(), *entry.id(), old.mode)?;
+                    changes.push((Some(a), None));
+                    continue;
+                }
+            };
+
+            let a = Side::load(&self.database, entry.path(), *entry.id(), old.mode)?;
+            let data = self.workspace.read(entry.path())?;
+            let b = Side::new(entry.path(), data, metadata.mode);
+
+            if a.id != b.id || a.mode != b.mode {
+                changes.push((Some(a), Some(b)));
+            }
+        }
+
+        self.print(changes)
+    }
+
+    fn run_cached(mut self) -> anyhow::Result<()> {
+        let head = match self.references.read_head()? {
+            None => BTreeMap::new(),
+            Some(head) => diff::tree::flatten(&self.database, &head.peel_to_tree(&self.database)?)?,
+        };
+
+        let head = head
+            .into_iter()
+            .filter(|(path, _)| self.is_selected(path))
+            .collect();
+        let index = self
+            .index
+            .entries()
+            .filter(|entry| self.is_selected(entry.path()))
+            .map(|entry| {
+                (
+                    util::PathBuf(entry.path().to_path_buf()),
+                    diff::tree::Entry {
+                        id: *entry.id(),
+                        mode: *entry.metadata().mode(),
+                    },
+                )
+            })
+            .collect();
+
+        let mut changes = Vec::new();
+        for (path, (old, new)) in diff::tree::diff_files(&head, &index) {
+            let load = |entry: Option<diff::tree::Entry>| {
+                entry
+                    .map(|entry| Side::load(&self.database, &path, entry.id, entry.mode))
+                    .transpose()
+            };
+            changes.push((load(old)?, load(new)?));
+        }
+
+        self.print(changes)
+    }
+
+    /// Print `changes`, sorted by path, in the configured order.
+    fn print(&mut self, changes: Vec<(Option<Side>, Option<Side>)>) -> anyhow::Result<()> {
+        let mut changes = find_renames(&self.database, changes, self.renames)?;
+        self.order.sort(&mut changes, |(a, b)| {
+            &b.as_ref().or(a.as_ref()).expect("at least one side").path
+        });
+        let total = changes.len();
+        for (index, (mut a, mut b)) in changes.into_iter().enumerate() {
+            let path = a
+                .as_ref()
+                .or(b.as_ref())
+                .expect("at least one side")
+                .path
+                .clone();
+            let attributes = self.workspace.attributes();
+            if let Some(external) = &self.external {
+                if let Some(program) = external.program(attributes, &path)? {
+                    self.stdout.flush()?;
+                    external.run(&program, a.as_ref(), b.as_ref(), index, total)?;
+                    continue;
+                }
+            }
+            let binary = match textconv(&mut self.textconv, attributes, &mut a, &mut b)? {
+                true => Some(false),
+                false => attributes.is_binary(&path)?,
+            };
+            print(&mut self.stdout, a.as_ref(), b.as_ref(), binary)?;
+        }
+        self.textconv.save()
+    }
+}
+
+/// One side of a file comparison.
+#[derive(Clone, Debug)]
+pub(crate) struct Side {
+    pub path: path::PathBuf,
+    pub id: object::Id,
+    pub mode: meta::Mode,
+    pub data: Vec<u8>,
+}
+
+impl Side {
+    /// Hash `data` as a blob to describe a file that may not be in the database.
+    pub fn new(path: &path::Path, data: Vec<u8>, mode: meta::Mode) -> Self {
+        let blob = crate::Object::Blob(object::Blob::new(data));
+        let id = object::Id::hash(&blob.to_bytes());
+        let data = match blob {
+            crate::Object::Blob(blob) => blob.into_data(),
+            _ => unreachable!(),
+        };
+        Side {
+            path: path.to_path_buf(),
+            id,
+            mode,
+            data,
+        }
+    }
+
+    pub fn load(
+        database: &crate::Database,
+        path: &path::Path,
+        id: object::Id,
+        mode: meta::Mode,
+    ) -> anyhow::Result<Self> {
+        let data = database.load_blob(&id)?.into_data();
+        Ok(Side {
+            path: path.to_path_buf(),
+            id,
+            mode,
+            data,
+        })
+    }
+}
+
+/// Programs that compare files instead of the built-in diff: the
+/// `diff.<driver>.command` named by a file's `diff` attribute, or else
+/// `GIT_EXTERNAL_DIFF` or `diff.external` for every file.
+pub(crate) struct External {
+    fallback: Option<String>,
+}
+
+impl External {
+    pub fn new(config: &config::Config) -> Self {
+        External {
+            fallback: env::var("GIT_EXTERNAL_DIFF")
+                .ok()
+                .or_else(|| config.get("diff.external").map(String::from)),
+        }
+    }
+
+    /// Program to compare versions of `path` with, if any.
+    pub fn program(
+        &self,
+        attributes: &attributes::Attributes,
+        path: &path::Path,
+    ) -> anyhow::Result<Option<String>> {
+        Ok(attributes
+            .diff_command(path)?
+            .or_else(|| self.fallback.clone()))
+    }
+
+    /// Run `program` to compare `a` to `b`, which is file number `index`
+    /// of `total`, like `git`: its arguments are the path, then the
+    /// temporary file, id, and mode of each side, where a missing side is
+    /// `/dev/null` with `.` for its id and mode.
+    pub fn run(
+        &self,
+        program: &str,
+        a: Option<&Side>,
+        b: Option<&Side>,
+        index: usize,
+        total: usize,
+    ) -> anyhow::Result<()> {
+        let path = &a.or(b).expect("at least one side").path;
+        let mut temps = Vec::new();
+        let mut arguments = vec![path.as_os_str().to_os_string()];
+        for side in [a, b] {
+            match side {
+                None => arguments.extend(["/dev/null", ".", "."].iter().map(Into::into)),
+                Some(side) => {
+                    let temp = diff::temp(path);
+                    fs::write(&temp, &side.data)?;
+                    arguments.push(temp.clone().into_os_string());
+                    arguments.push(side.id.to_string().into());
+                    arguments.push(side.mode.as_str().into());
+                    temps.push(temp);
+                }
+            }
+        }
+
+        // Like `git`, renames add the new path and a description of them.
+        if let (Some(a), Some(b)) = (a, b) {
+            if a.path != b.path {
+                let score = rename::similarity(&a.data, &b.data);
+                arguments.push(b.path.clone().into_os_string());
+                arguments.push(
+                    format!(
+                        "similarity index {}%\nrename from {}\nrename to {}\n",
+                        score.percent(),
+                        a.path.display(),
+                        b.path.display(),
+                    )
+                    .into(),
+                );
+            }
+        }
+
+        // Pass the arguments as positional parameters to the shell.
+        let status = process::Command::new("sh")
+            .arg("-c")
+            .arg(format!("{} \"$@\"", program))
+            .arg(program)
+            .args(&arguments)
+            .env("GIT_DIFF_PATH_COUNTER", (index + 1).to_string())
+            .env("GIT_DIFF_PATH_TOTAL", total.to_string())
+            .status();
+
+        for temp in &temps {
+            let _ = fs::remove_file(temp);
+        }
+        match status {
+            Ok(status) if status.success() => Ok(()),
+            Ok(_) => Err(anyhow!(
+                "External diff died, stopping at {}",
+                path.display()
+            )),
+            Err(error) => Err(anyhow!("Unable to run {}: {}", program, error)),
+        }
+    }
+}
+
+/// Merge each deleted file in `changes` with the added file it was renamed
+/// to, if `options` allow detecting renames, keeping the pair where the
+/// added file was.
+pub(crate) fn find_renames(
+    database: &crate::Database,
+    changes: Vec<(Option<Side>, Option<Side>)>,
+    options: Option<rename::Options>,
+) -> anyhow::Result<Vec<(Option<Side>, Option<Side>)>> {
+    let options = match options {
+        None => return Ok(changes),
+        Some(options) => options,
+    };
+
+    let mut deleted = Vec::new();
+    let mut added = Vec::new();
+    for (index, change) in changes.iter().enumerate() {
+        match change {
+            (Some(a), None) => deleted.push((index, a.id)),
+            (None, Some(b)) => added.push((index, b.id)),
+            _ => (),
+        }
+    }
+
+    let renames = rename::detect(database, &deleted, &added, options)?;
+    let mut changes = changes.into_iter().map(Some).collect::<Vec<_>>();
+    for rename in renames {
+        let (old, _) = changes[rename.old]
+            .take()
+            .expect("each file is renamed once");
+        changes[rename.new]
+            .as_mut()
+            .expect("each file is renamed once")
+            .0 = old;
+    }
+    Ok(changes.into_iter().flatten().collect())
+}
+
+/// Replace the contents of `a` and `b` with their text from the textconv
+/// program of their diff driver, returning whether there is one.
+pub(crate) fn textconv(
+    textconv: &mut diff::textconv::Textconv,
+    attributes: &attributes::Attributes,
+    a: &mut Option<Side>,
+    b: &mut Option<Side>,
+) -> anyhow::Result<bool> {
+    let path = a
+        .as_ref()
+        .or(b.as_ref())
+        .expect("at least one side")
+        .path
+        .clone();
+    let driver = match attributes.diff_driver(&path)? {
+        None => return Ok(false),
+        Some(driver) => driver,
+    };
+
+    let mut converted = false;
+    for side in a.iter_mut().chain(b.iter_mut()) {
+        if let Some(text) = textconv.convert(&driver, &path, &side.id, &side.data)? {
+            side.data = text;
+            converted = true;
+        }
+    }
+    Ok(converted)
+}
+
+/// Print a `git`-style unified diff from `a` to `b`, where `None` represents
+/// a missing file. Files are compared as binary if `binary` says so, as
+/// from [`crate::attributes::Attributes::is_binary`], or else if either
+/// side looks binary.
+pub(crate) fn print<W: termcolor::WriteColor>(
+    writer: &mut W,
+    a: Option<&Side>,
+    b: Option<&Side>,
+    binary: Option<bool>,
+) -> io::Result<()> {
+    let old_path = a
+        .or(b)
+        .expect("at least one side")
+        .path
+        .display()
+        .to_string();
+    let new_path = b
+        .or(a)
+        .expect("at least one side")
+        .path
+        .display()
+        .to_string();
+
+    writer.set_color(termcolor::ColorSpec::new().set_bold(true))?;
+    writeln!(writer, "diff --git a/{} b/{}", old_path, new_path)?;
+
+    let short = |side: Option<&Side>| match side {
+        None => String::from("0000000"),
+        Some(side) => side.id.to_string()[..7].to_owned(),
+    };
+
+    match (a, b) {
+        (None, Some(b)) => writeln!(writer, "new file mode {}", b.mode.as_str())?,
+        (Some(a), None) => writeln!(writer, "deleted file mode {}", a.mode.as_str())?,
+        (Some(a), Some(b)) if a.mode != b.mode => {
+            writeln!(writer, "old mode {}", a.mode.as_str())?;
+            writeln!(writer, "new mode {}", b.mode.as_str())?;
+        }
+        _ => (),
+    }
+
+    if let (Some(a), Some(b)) = (a, b) {
+        if a.path != b.path {
+            let score = rename::similarity(&a.data, &b.data);
+            writeln!(writer, "similarity index {}%", score.percent())?;
+            writeln!(writer, "rename from {}", old_path)?;
+            writeln!(writer, "rename to {}", new_path)?;
+        }
+    }
+
+    if a.map(|a| a.id) == b.map(|b| b.id) {
+        writer.reset()?;
+        return Ok(());
+    }
+
+    write!(writer, "index {}..{}", short(a), short(b))?;
+    match (a, b) {
+        (Some(a), Some(b)) if a.mode == b.mode => writeln!(writer, " {}", a.mode.as_str())?,
+        _ => writeln!(writer)?,
+    }
+
+    let old = match a {
+        None => String::from("/dev/null"),
+        Some(_) => format!("a/{}", old_path),
+    };
+    let new = match b {
+        None => String::from("/dev/null"),
+        Some(_) => format!("b/{}", new_path),
+    };
+
+    let binary = binary.unwrap_or_else(|| {
+        [a, b]
+            .iter()
+            .flatten()
+            .any(|side| diff::is_binary(&side.data))
+    });
+    if binary {
+        writer.reset()?;
+        writeln!(writer, "Binary files {} and {} differ", old, new)?;
+        return Ok(());
+    }
+
+    writeln!(writer, "--- {}", old)?;
+    writeln!(writer, "+++ {}", new)?;
+    writer.reset()?;
+
+    let a = diff::lines(a.map(|a| &*a.data).unwrap_or_default());
+    let b = diff::lines(b.map(|b| &*b.data).unwrap_or_default());
+
+    for hunk in diff::hunks(&diff::edits(&a, &b)) {
+        writer.set_color(termcolor::ColorSpec::new().set_fg(Some(termcolor::Color::Cyan)))?;
+        writeln!(writer, "{}", hunk.header())?;
+        writer.reset()?;
+
+        for edit in &hunk.edits {
+            let (color, prefix, line) = match *edit {
+                diff::Edit::Equal { a: index, .. } => (None, ' ', a[index]),
+                diff::Edit::Delete { a: index } => (Some(termcolor::Color::Red), '-', a[index]),
+                diff::Edit::Insert { b: index } => (Some(termcolor::Color::Green), '+', b[index]),
+            };
+
+            writer.set_color(termcolor::ColorSpec::new().set_fg(color))?;
+            write!(writer, "{}", prefix)?;
+            writer.write_all(line)?;
+            writer.reset()?;
+
+            if !line.ends_with(b"\n") {
+                writeln!(writer)?;
+                writeln!(writer, "\\ No newline at end of file")?;
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,62 @@
+use std::env;
+use std::io::Write as _;
+
+use structopt::StructOpt;
+use termcolor::WriteColor as _;
+
+/// Explain state left behind by interrupted operations, such as leftover
+/// merges, rebases, and lock files, and optionally clean it up.
+#[derive(StructOpt)]
+pub struct Configuration {
+    /// Remove leftover state. Recent lock files are kept unless `--force`
+    /// is also given, since another process may still hold them.
+    #[structopt(long)]
+    clean: bool,
+
+    /// Also remove lock files that may still be in use.
+    #[structopt(short, long)]
+    force: bool,
+}
+
+impl Configuration {
+    pub fn run(self) -> anyhow::Result<()> {
+        let root = env::current_dir()?;
+        let repository = crate::Repository::new(root);
+        let stdout = termcolor::StandardStream::stdout(match isatty::stdout_isatty() {
+            true => termcolor::ColorChoice::Always,
+            false => termcolor::ColorChoice::Never,
+        });
+        let mut stdout = stdout.lock();
+
+        let leftovers = repository.audit()?;
+        if leftovers.is_empty() {
+            writeln!(stdout, "No leftover state found.")?;
+            return Ok(());
+        }
+
+        let git = repository.root().join(".git");
+        for leftover in &leftovers {
+            if !self.clean {
+                writeln!(stdout, "{}", leftover)?;
+                writeln!(stdout, "  {}", leftover.hint())?;
+                continue;
+            }
+
+            if leftover.is_stale() || self.force {
+                leftover.clean(&git)?;
+                stdout
+                    .set_color(termcolor::ColorSpec::new().set_fg(Some(termcolor::Color::Green)))?;
+                write!(stdout, "Cleaned: ")?;
+            } else {
+                stdout.set_color(
+                    termcolor::ColorSpec::new().set_fg(Some(termcolor::Color::Yellow)),
+                )?;
+                write!(stdout, "Skipped (use --force to remove): ")?;
+            }
+            stdout.reset()?;
+            writeln!(stdout, "{}", leftover)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,145 @@
+use std::env;
+use std::fmt::Write as _;
+use std::io;
+use std::io::Write as _;
+
+use structopt::StructOpt;
+
+use crate::object;
+use crate::util;
+
+/// Print every entry of the index with its full stat information and flags,
+/// followed by the contents of each extension, for diagnosing index
+/// corruption and stat mismatches.
+#[derive(StructOpt)]
+pub struct Configuration {}
+
+impl Configuration {
+    pub fn run(self) -> anyhow::Result<()> {
+        let root = env::current_dir()?;
+        let repository = crate::Repository::new(root);
+
+        // Leave sparse directories collapsed, as they are on disk.
+        let index = repository.sparse_index()?;
+        let mut entries = index
+            .entries()
+            .chain(
+                index
+                    .conflicts()
+                    .flat_map(|(_, stages)| stages.iter().flatten()),
+            )
+            .collect::<Vec<_>>();
+        entries.sort_by(|a, b| {
+            util::PathBuf(a.path().to_path_buf())
+                .cmp(&util::PathBuf(b.path().to_path_buf()))
+                .then(a.stage().cmp(&b.stage()))
+        });
+
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        writeln!(
+            stdout,
+            "version {}, {} entries",
+            index.version(),
+            entries.len()
+        )?;
+        for entry in entries {
+            writeln!(
+                stdout,
+                "{} {} {}\t{}",
+                entry.metadata().mode().as_str(),
+                entry.id(),
+                entry.stage(),
+                entry.path().display(),
+            )?;
+            write!(stdout, "{}", super::ls_files::debug(entry))?;
+        }
+
+        for extension in index.extensions() {
+            let data = extension.data();
+            writeln!(
+                stdout,
+                "extension {}, {} bytes",
+                String::from_utf8_lossy(extension.signature()),
+                data.len(),
+            )?;
+            match extension.signature() {
+                b"TREE" => write!(stdout, "{}", cache_tree(data))?,
+                _ => write!(stdout, "{}", hex_dump(data))?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Describe each directory in a `TREE` extension, whose entries are a
+/// NUL-terminated path, ASCII counts of entries and subtrees, and the tree
+/// id unless the entry count is negative, meaning it was invalidated.
+///
+/// Falls back to a hex dump of anything that doesn't parse.
+fn cache_tree(mut data: &[u8]) -> String {
+    let mut dump = String::new();
+    while !data.is_empty() {
+        let parsed = (|| {
+            let nul = data.iter().position(|byte| *byte == 0)?;
+            let newline = nul + data[nul..].iter().position(|byte| *byte == b'\n')?;
+            let path = String::from_utf8_lossy(&data[..nul]);
+            let counts = std::str::from_utf8(&data[nul + 1..newline]).ok()?;
+            let (entries, subtrees) = counts.split_once(' ')?;
+            let entries = entries.parse::<i64>().ok()?;
+            let subtrees = subtrees.parse::<u64>().ok()?;
+            let (id, rest) = match entries {
+                entries if entries < 0 => (None, &data[newline + 1..]),
+                _ => {
+                    let mut rest = &data[newline + 1..];
+                    let id = object::Id::read_bytes(&mut rest).ok()?;
+                    (Some(id), rest)
+                }
+            };
+            let line = match id {
+                Some(id) => format!(
+                    "  {} ({} entries, {} subtrees) {}/\n",
+                    id, entries, subtrees, path
+                ),
+                None => format!("  invalid ({} subtrees) {}/\n", subtrees, path),
+            };
+            Some((line, rest))
+        })();
+
+        match parsed {
+            Some((line, rest)) => {
+                dump.push_str(&line);
+                data = rest;
+            }
+            None => {
+                dump.push_str(&hex_dump(data));
+                break;
+            }
+        }
+    }
+    dump
+}
+
+/// Sixteen bytes per line, prefixed by their offset.
+fn hex_dump(data: &[u8]) -> String {
+    let mut dump = String::new();
+    for (line, chunk) in data.chunks(16).enumerate() {
+        write!(dump, "  {:08x}:", line * 16).expect("writing to string");
+        for byte in chunk {
+            write!(dump, " {:02x}", byte).expect("writing to string");
+        }
+        dump.push('\n');
+    }
+    dump
+}
+
+#[test]
+fn cache_tree_invalid() {
+    let data = b"\x001 1\n\x01\x01\x01\x01\x01\x01\x01\x01\x01\x01\x01\x01\x01\x01\x01\x01\x01\x01\x01\x01d\0-1 0\n";
+    assert_eq!(
+        cache_tree(data),
+        "  0101010101010101010101010101010101010101 (1 entries, 1 subtrees) /\n  invalid (0 subtrees) d/\n",
+    );
+    assert_eq!(hex_dump(b"\x00\xff"), "  00000000: 00 ff\n");
+}
@@ -0,0 +1,54 @@
+use std::env;
+use std::io;
+use std::io::Write as _;
+
+use anyhow::anyhow;
+use structopt::StructOpt;
+
+use crate::fast_import;
+
+/// Write history as a `git fast-import` stream on standard output, e.g. to
+/// move it to another version control tool.
+#[derive(StructOpt)]
+pub struct Configuration {
+    /// Export every reference under `refs/`.
+    #[structopt(long)]
+    all: bool,
+
+    /// References to export, e.g. `main` or `refs/tags/v1.0`.
+    #[structopt(required_unless = "all")]
+    references: Vec<String>,
+}
+
+impl Configuration {
+    pub fn run(self) -> anyhow::Result<()> {
+        let root = env::current_dir()?;
+        let repository = crate::Repository::new(root);
+        let database = repository.database()?;
+        let references = repository.references();
+
+        let mut tips = Vec::new();
+        if self.all {
+            for reference in references.iter_prefix("refs/")? {
+                tips.push(reference?);
+            }
+        }
+        for name in &self.references {
+            let full = references
+                .expand(name)?
+                .ok_or_else(|| anyhow!("No such reference: {}", name))?;
+            let id = references
+                .read(&full)?
+                .ok_or_else(|| anyhow!("No such reference: {}", name))?;
+            tips.push((full, id));
+        }
+
+        let stdout = io::stdout();
+        let mut stdout = io::BufWriter::new(stdout.lock());
+        fast_import::export(&database, &tips, |command| {
+            command.write(&mut stdout).map_err(anyhow::Error::from)
+        })?;
+        stdout.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,41 @@
+use std::env;
+use std::io;
+
+use structopt::StructOpt;
+
+use crate::fast_import;
+
+/// Read a `git fast-import` stream from standard input into the repository,
+/// e.g. as produced by `grit fast-export` or another version control tool.
+#[derive(StructOpt)]
+pub struct Configuration {
+    /// Move references even if their new commit doesn't contain the old one.
+    #[structopt(long)]
+    force: bool,
+}
+
+impl Configuration {
+    pub fn run(self) -> anyhow::Result<()> {
+        let root = env::current_dir()?;
+        let repository = crate::Repository::new(root);
+        let database = repository.database()?;
+        let references = repository.references();
+
+        let stdin = io::stdin();
+        let mut import = fast_import::Import::new(&database, &references);
+        let mut objects = 0;
+        for command in fast_import::Reader::new(stdin.lock()) {
+            if import.apply(command?)?.is_some() {
+                objects += 1;
+            }
+        }
+
+        let updated = import.finish(self.force)?;
+        eprintln!(
+            "Imported {} objects and updated {} references",
+            objects,
+            updated.len()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,750 @@
+use std::cmp;
+use std::collections::BTreeMap;
+use std::collections::BinaryHeap;
+use std::collections::HashSet;
+use std::env;
+use std::io;
+use std::io::Write as _;
+use std::iter;
+use std::num;
+use std::panic;
+use std::path;
+use std::sync;
+use std::sync::atomic;
+use std::thread;
+
+use anyhow::anyhow;
+use structopt::StructOpt;
+
+use crate::gc;
+use crate::merge;
+use crate::object;
+use crate::references;
+use crate::refspec;
+use crate::transport;
+
+/// Download objects and references from a remote repository.
+///
+/// Remote references are mapped to local ones by the remote's
+/// `remote.<name>.fetch` refspecs, which by default update the
+/// remote-tracking branches under `refs/remotes/<name>`, except for those
+/// excluded by negative refspecs like `^refs/heads/wip/*`. A refspec
+/// without a destination, like `refs/heads/main:`, downloads what it
+/// matches without storing it, and one without a source, like
+/// `:refs/heads/upstream`, stores the remote's `HEAD`. All references are
+/// updated together or not at all.
+///
+/// With `--prune`, local references that a refspec maps from remote
+/// references that no longer exist are deleted in the same update. Mirrors
+/// made by `clone --mirror` prune by default.
+///
+/// With `--multiple` or `--all`, several remotes are fetched from, up to
+/// `--jobs` or `fetch.parallel` at a time. One fetch at a time shows its
+/// progress, while the others' output waits until it finishes.
+#[derive(StructOpt)]
+pub struct Configuration {
+    /// Delete local references whose remote references no longer exist.
+    /// Overrides `remote.<name>.prune`, `fetch.prune`, and
+    /// `remote.<name>.mirror`.
+    #[structopt(short, long, overrides_with = "no-prune")]
+    prune: bool,
+
+    /// Keep local references whose remote references no longer exist.
+    #[structopt(long, overrides_with = "prune")]
+    no_prune: bool,
+
+    /// Fetch from every remote.
+    #[structopt(long, conflicts_with = "remotes")]
+    all: bool,
+
+    /// Allow several remotes to be named.
+    #[structopt(long)]
+    multiple: bool,
+
+    /// Fetch from at most this many remotes at once, or one per logical
+    /// core if 0. Overrides `fetch.parallel`.
+    #[structopt(short, long)]
+    jobs: Option<usize>,
+
+    /// Names of the remotes to fetch from, `origin` by default.
+    remotes: Vec<String>,
+}
+
+/// Most local commits offered to the remote as `have` lines.
+const MAX_HAVES: usize = 256;
+
+impl Configuration {
+    pub fn run(self) -> anyhow::Result<()> {
+        let root = env::current_dir()?;
+        let repository = crate::Repository::open(root.clone());
+        let config = repository.config()?;
+
+        let remotes = match (self.all, self.multiple, self.remotes.len()) {
+            (true, _, _) => {
+                let mut remotes = Vec::new();
+                for (key, _) in config.iter() {
+                    let remote = key
+                        .strip_prefix("remote.")
+                        .and_then(|key| key.strip_suffix(".url"));
+                    match remote {
+                        Some(remote) if !remotes.iter().any(|known| known == remote) => {
+                            remotes.push(remote.to_owned())
+                        }
+                        _ => (),
+                    }
+                }
+                remotes
+            }
+            (false, _, 0) => vec![String::from("origin")],
+            (false, true, _) | (false, false, 1) => self.remotes,
+            (false, false, _) => {
+                return Err(anyhow!("Fetching from several remotes requires --multiple"))
+            }
+        };
+        let prune = match (self.prune, self.no_prune) {
+            (true, _) => Some(true),
+            (_, true) => Some(false),
+            (false, false) => None,
+        };
+
+        // Reference updates are serialized, since they may rewrite shared
+        // files like `packed-refs`.
+        let transaction = sync::Mutex::new(());
+        let results = match &*remotes {
+            [remote] => vec![fetch(
+                &repository,
+                remote,
+                prune,
+                &transaction,
+                &mut io::stderr(),
+            )],
+            remotes => {
+                let jobs = match config.option(self.jobs, "fetch.parallel")? {
+                    None => 1,
+                    Some(0) => thread::available_parallelism().map_or(1, num::NonZeroUsize::get),
+                    Some(jobs) => jobs,
+                };
+                parallel(&root, remotes, prune, jobs, &transaction)
+            }
+        };
+
+        if results.iter().any(Result::is_ok) {
+            gc::auto(&repository)?;
+        }
+
+        let mut rejected = false;
+        let mut failed = false;
+        for (remote, result) in remotes.iter().zip(results) {
+            match result {
+                Ok(rejections) => rejected |= rejections,
+                Err(error) if remotes.len() == 1 => return Err(error),
+                Err(error) => {
+                    eprintln!("error: could not fetch {}: {:#}", remote, error);
+                    failed = true;
+                }
+            }
+        }
+
+        match (rejected, failed) {
+            (true, _) => Err(anyhow!("Some local refs could not be updated")),
+            (false, true) => Err(anyhow!("Some remotes could not be fetched")),
+            (false, false) => Ok(()),
+        }
+    }
+}
+
+/// Fetch from each of `remotes` with up to `jobs` workers, which each open
+/// their own handle to the repository at `root`, returning the results in
+/// order.
+fn parallel(
+    root: &path::Path,
+    remotes: &[String],
+    prune: Option<bool>,
+    jobs: usize,
+    transaction: &sync::Mutex<()>,
+) -> Vec<anyhow::Result<bool>> {
+    let console = Console::default();
+    let next = atomic::AtomicUsize::new(0);
+    let (console, next) = (&console, &next);
+    let finished = thread::scope(|scope| {
+        (0..cmp::min(jobs, remotes.len()).max(1))
+            .map(|_| {
+                scope.spawn(move || {
+                    let repository = crate::Repository::open(root.to_path_buf());
+                    let mut finished = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, atomic::Ordering::Relaxed);
+                        let remote = match remotes.get(index) {
+                            None => break,
+                            Some(remote) => remote,
+                        };
+                        let mut output = console.writer(index);
+                        writeln!(output, "Fetching {}", remote).ok();
+                        let result = fetch(&repository, remote, prune, transaction, &mut output);
+                        console.finish(index);
+                        finished.push((index, result));
+                    }
+                    finished
+                })
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .flat_map(|worker| {
+                worker
+                    .join()
+                    .unwrap_or_else(|panic| panic::resume_unwind(panic))
+            })
+            .collect::<BTreeMap<_, _>>()
+    });
+    finished.into_values().collect()
+}
+
+/// Fetch from `remote` into `repository`, writing progress and a summary of
+/// updated references to `output`, and returning whether any updates were
+/// rejected. `prune` overrides the remote's configuration if given.
+fn fetch(
+    repository: &crate::Repository,
+    remote: &str,
+    prune: Option<bool>,
+    transaction: &sync::Mutex<()>,
+    output: &mut dyn io::Write,
+) -> anyhow::Result<bool> {
+    let config = repository.config()?;
+
+    let url = config
+        .get(&format!("remote.{}.url", remote))
+        .ok_or_else(|| anyhow!("'{}' does not appear to be a remote", remote))?;
+    let refspecs = refspec::Refspecs::parse(config.get_all(&format!("remote.{}.fetch", remote)))?;
+
+    let prune = match prune {
+        Some(prune) => prune,
+        None => match config.get_bool(&format!("remote.{}.prune", remote))? {
+            Some(prune) => prune,
+            None => match config.get_bool("fetch.prune")? {
+                Some(prune) => prune,
+                None => config
+                    .get_bool(&format!("remote.{}.mirror", remote))?
+                    .unwrap_or(false),
+            },
+        },
+    };
+
+    let database = repository.database()?;
+    let references = repository.references();
+    let remote = transport::Remote::new(url, &config)?;
+    let advertisement = remote.advertise()?;
+
+    let mut fetches = Vec::new();
+    for (name, id) in &advertisement.refs {
+        for (refspec, destination) in refspecs.map(name) {
+            let old = references.read(&destination)?;
+            if old != Some(*id) {
+                fetches.push(Fetch {
+                    source: name.clone(),
+                    destination,
+                    old,
+                    new: *id,
+                    force: refspec.force,
+                });
+            }
+        }
+    }
+
+    // References matched by a refspec without a destination are downloaded
+    // but not stored.
+    let unstored = advertisement
+        .refs
+        .iter()
+        .filter(|(name, _)| refspecs.fetches(name))
+        .map(|(_, id)| *id);
+
+    let mut wants = Vec::new();
+    for id in fetches.iter().map(|fetch| fetch.new).chain(unstored) {
+        if !wants.contains(&id) && !database.contains(&id)? {
+            wants.push(id);
+        }
+    }
+    if !wants.is_empty() {
+        let haves = haves(&database, &references)?;
+        remote.fetch(&database, &advertisement, &wants, &haves, output)?;
+    }
+
+    // Reopen the database, which only sees packs that existed when it
+    // first read one.
+    let database = repository.database()?;
+
+    let mut pruned = match prune {
+        true => stale(&refspecs, &references, &advertisement.refs)?,
+        false => Vec::new(),
+    };
+    pruned.retain(|(name, _)| fetches.iter().all(|fetch| fetch.destination != *name));
+
+    if fetches.is_empty() && pruned.is_empty() {
+        return Ok(false);
+    }
+
+    let mut updates = Vec::new();
+    let mut rejected = false;
+    let mut lines = Vec::new();
+    for fetch in &fetches {
+        let status = fetch.status(&database)?;
+        rejected |= status == Status::Rejected;
+        if status != Status::Rejected {
+            updates.push(references::Update {
+                name: fetch.destination.clone(),
+                old: fetch.old,
+                new: Some(fetch.new),
+            });
+        }
+        lines.push((fetch, status));
+    }
+    for (name, id) in &pruned {
+        updates.push(references::Update {
+            name: name.clone(),
+            old: Some(*id),
+            new: None,
+        });
+    }
+
+    let committer = config.committer()?;
+    {
+        let _transaction = transaction
+            .lock()
+            .unwrap_or_else(sync::PoisonError::into_inner);
+        references.transaction(&updates, &committer, &format!("fetch: from {}", url))?;
+    }
+
+    writeln!(output, "From {}", url)?;
+    let width = lines
+        .iter()
+        .map(|(fetch, _)| shorten(&fetch.source).len())
+        .chain(iter::once("(none)".len()))
+        .max()
+        .unwrap_or_default();
+    for (name, _) in &pruned {
+        writeln!(
+            output,
+            " - {:<17} {:<width$} -> {}",
+            "[deleted]",
+            "(none)",
+            shorten(name),
+            width = width,
+        )?;
+    }
+    for (fetch, status) in lines {
+        writeln!(output, " {}", fetch.describe(status, width))?;
+    }
+    Ok(rejected)
+}
+
+/// Standard error shared by fetches running in parallel, like in `git`:
+/// one fetch at a time writes to it directly, while the others buffer
+/// their output until it's their turn, so that progress meters never
+/// overwrite each other.
+#[derive(Default)]
+struct Console(sync::Mutex<Shared>);
+
+#[derive(Default)]
+struct Shared {
+    /// Fetch writing directly to standard error, if any.
+    foreground: Option<usize>,
+    /// Output waiting to be written, by fetch.
+    buffered: BTreeMap<usize, Vec<u8>>,
+    /// Fetches that have finished.
+    finished: HashSet<usize>,
+}
+
+impl Console {
+    fn writer(&self, fetch: usize) -> Writer<'_> {
+        Writer {
+            console: self,
+            fetch,
+        }
+    }
+
+    fn lock(&self) -> sync::MutexGuard<'_, Shared> {
+        self.0.lock().unwrap_or_else(sync::PoisonError::into_inner)
+    }
+
+    fn write(&self, fetch: usize, bytes: &[u8]) -> io::Result<()> {
+        let mut shared = self.lock();
+        let foreground = *shared.foreground.get_or_insert(fetch);
+        match foreground == fetch {
+            true => io::stderr().write_all(bytes),
+            false => {
+                shared
+                    .buffered
+                    .entry(fetch)
+                    .or_default()
+                    .extend_from_slice(bytes);
+                Ok(())
+            }
+        }
+    }
+
+    /// Note that `fetch` is done. If it was in the foreground, write out
+    /// fetches that finished in the meantime, then hand standard error to
+    /// the first one still running.
+    fn finish(&self, fetch: usize) {
+        let mut shared = self.lock();
+        shared.finished.insert(fetch);
+        if shared.foreground != Some(fetch) {
+            return;
+        }
+
+        shared.foreground = None;
+        let mut stderr = io::stderr();
+        while let Some((next, bytes)) = shared.buffered.pop_first() {
+            stderr.write_all(&bytes).ok();
+            if !shared.finished.contains(&next) {
+                shared.foreground = Some(next);
+                break;
+            }
+        }
+    }
+}
+
+/// Output of one fetch, written through a [`Console`].
+struct Writer<'a> {
+    console: &'a Console,
+    fetch: usize,
+}
+
+impl io::Write for Writer<'_> {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        self.console.write(self.fetch, buffer)?;
+        Ok(buffer.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+/// A local reference to be pointed at a remote reference's id.
+struct Fetch {
+    source: String,
+    destination: String,
+    old: Option<object::Id>,
+    new: object::Id,
+    force: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Status {
+    New,
+    FastForward,
+    Forced,
+    Rejected,
+}
+
+impl Fetch {
+    fn status(&self, database: &crate::Database) -> anyhow::Result<Status> {
+        let old = match self.old {
+            None => return Ok(Status::New),
+            Some(old) => old,
+        };
+
+        // Tags are never expected to move.
+        let fast_forward = !self.destination.starts_with(crate::References::TAGS) && {
+            let old = old.peel_to_commit(database)?;
+            merge::base(database, &old, &self.new.peel_to_commit(database)?)? == Some(old)
+        };
+
+        Ok(match (fast_forward, self.force) {
+            (true, _) => Status::FastForward,
+            (false, true) => Status::Forced,
+            (false, false) => Status::Rejected,
+        })
+    }
+
+    /// Summarize this update like `git fetch`, padding the source name to
+    /// `width` characters.
+    fn describe(&self, status: Status, width: usize) -> String {
+        let short = |id: Option<object::Id>| {
+            id.map(|id| id.to_string()[..7].to_owned())
+                .unwrap_or_default()
+        };
+        let (flag, summary, reason) = match status {
+            Status::New if self.source.starts_with(crate::References::TAGS) => {
+                ('*', String::from("[new tag]"), "")
+            }
+            Status::New if self.source.starts_with(crate::References::HEADS) => {
+                ('*', String::from("[new branch]"), "")
+            }
+            Status::New => ('*', String::from("[new ref]"), ""),
+            Status::FastForward => (
+                ' ',
+                format!("{}..{}", short(self.old), short(Some(self.new))),
+                "",
+            ),
+            Status::Forced => (
+                '+',
+                format!("{}...{}", short(self.old), short(Some(self.new))),
+                "  (forced update)",
+            ),
+            Status::Rejected if self.destination.starts_with(crate::References::TAGS) => (
+                '!',
+                String::from("[rejected]"),
+                "  (would clobber existing tag)",
+            ),
+            Status::Rejected => ('!', String::from("[rejected]"), "  (non-fast-forward)"),
+        };
+        format!(
+            "{} {:<17} {:<width$} -> {}{}",
+            flag,
+            summary,
+            shorten(&self.source),
+            shorten(&self.destination),
+            reason,
+            width = width,
+        )
+    }
+}
+
+/// Local references that `refspecs` map from remote references, but whose
+/// remote references are no longer in `advertised`, along with their ids.
+pub(crate) fn stale(
+    refspecs: &refspec::Refspecs,
+    references: &crate::References,
+    advertised: &[(String, object::Id)],
+) -> anyhow::Result<Vec<(String, object::Id)>> {
+    let advertised = advertised
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect::<HashSet<_>>();
+
+    let mut stale = Vec::new();
+    for refspec in refspecs.iter() {
+        let destination = match &refspec.destination {
+            Some(destination) => destination,
+            None => continue,
+        };
+        let prefix = destination.split('*').next().unwrap_or_default();
+        for reference in references.iter_prefix(prefix)? {
+            let (name, id) = reference?;
+            let source = match refspec.unmap(&name) {
+                Some(source) => source,
+                None => continue,
+            };
+            if !advertised.contains(source.as_str())
+                && !refspecs.excludes(&source)
+                && !stale.iter().any(|(stale, _)| *stale == name)
+            {
+                stale.push((name, id));
+            }
+        }
+    }
+    Ok(stale)
+}
+
+/// Local commits to offer the remote, newest first, starting from every
+/// reference.
+fn haves(
+    database: &crate::Database,
+    references: &crate::References,
+) -> anyhow::Result<Vec<object::Id>> {
+    let mut queue = BinaryHeap::new();
+    let mut seen = HashSet::new();
+    let mut push =
+        |queue: &mut BinaryHeap<(i64, object::Id)>, id: object::Id| -> anyhow::Result<()> {
+            if seen.insert(id) {
+                queue.push((
+                    database.load_commit(&id)?.committer().time().timestamp(),
+                    id,
+                ));
+            }
+            Ok(())
+        };
+
+    for reference in references.iter_prefix("refs/")? {
+        let (_, id) = reference?;
+        // Skip references to trees and blobs.
+        if let Ok(id) = id.peel_to_commit(database) {
+            push(&mut queue, id)?;
+        }
+    }
+
+    let mut haves = Vec::new();
+    while let Some((_, id)) = queue.pop() {
+        if haves.len() == MAX_HAVES {
+            break;
+        }
+        for parent in database.load_commit(&id)?.parents() {
+            push(&mut queue, *parent)?;
+        }
+        haves.push(id);
+    }
+    Ok(haves)
+}
+
+/// Shorten a full reference name for display, like `origin/main` for
+/// `refs/remotes/origin/main`.
+fn shorten(name: &str) -> &str {
+    [
+        crate::References::HEADS,
+        crate::References::TAGS,
+        crate::References::REMOTES,
+    ]
+    .iter()
+    .find_map(|prefix| name.strip_prefix(prefix))
+    .unwrap_or(name)
+}
+
+#[test]
+fn prune() -> anyhow::Result<()> {
+    let root = crate::util::TempDir::new();
+    let mut source = crate::Repository::new(root.join("source"));
+    let mut target = crate::Repository::new(root.join("target"));
+    source.init()?;
+    target.init()?;
+
+    let database = source.database()?;
+    let blob = database.store(&crate::Object::Blob(object::Blob::new(b"1".to_vec())))?;
+    let tree = database.store(&crate::Object::Tree(object::tree::Root::new(vec![
+        object::tree::Node {
+            path: path::PathBuf::from("file"),
+            mode: crate::meta::Mode::Regular,
+            id: blob,
+        },
+    ])))?;
+    let person = object::Person::new(
+        String::from("A U Thor"),
+        String::from("author@example.com"),
+        object::Person::parse_time("@1600000000 +0000")?,
+    );
+    let commit = object::Commit::new(tree, vec![], person.clone(), person, String::from("1\n"));
+    let commit = database.store(&crate::Object::Commit(commit))?;
+    source.references().write_head(&commit)?;
+    source.references().create_branch("gone", &commit)?;
+
+    let mut document = crate::config::Document::open(root.join("target/.git/config"))?;
+    document.set(
+        "remote.origin.url",
+        &format!("file://{}", root.join("source").display()),
+    )?;
+    document.set("remote.origin.fetch", "+refs/heads/*:refs/remotes/origin/*")?;
+    document.commit()?;
+
+    let fetch = |prune: Option<bool>| -> anyhow::Result<String> {
+        let mut output = Vec::new();
+        fetch(&target, "origin", prune, &sync::Mutex::new(()), &mut output)?;
+        Ok(String::from_utf8(output)?)
+    };
+    let remotes = || -> anyhow::Result<Vec<String>> {
+        target
+            .references()
+            .iter_prefix("refs/remotes/")?
+            .map(|reference| reference.map(|(name, _)| name))
+            .collect()
+    };
+
+    fetch(None)?;
+    let fetched = remotes()?;
+
+    // Only references the refspec maps from the remote are pruned.
+    source.references().store().delete("refs/heads/gone")?;
+    target.references().store().write(
+        "refs/remotes/upstream/gone",
+        &references::Target::Direct(commit),
+    )?;
+    fetch(None)?;
+    let kept = remotes()?;
+    let output = fetch(Some(true))?;
+    let pruned = remotes()?;
+
+    assert_eq!(
+        fetched,
+        vec!["refs/remotes/origin/gone", "refs/remotes/origin/master"],
+    );
+    assert_eq!(kept.len(), 3);
+    assert!(output.contains("[deleted]"));
+    assert_eq!(
+        pruned,
+        vec!["refs/remotes/origin/master", "refs/remotes/upstream/gone"],
+    );
+    Ok(())
+}
+
+#[test]
+fn several() -> anyhow::Result<()> {
+    let root = crate::util::TempDir::new();
+    let mut target = crate::Repository::new(root.join("target"));
+    target.init()?;
+
+    let person = object::Person::new(
+        String::from("A U Thor"),
+        String::from("author@example.com"),
+        object::Person::parse_time("@1600000000 +0000")?,
+    );
+    let mut document = crate::config::Document::open(root.join("target/.git/config"))?;
+    let mut commits = Vec::new();
+    for name in ["a", "b", "c"].iter() {
+        let mut source = crate::Repository::new(root.join(name));
+        source.init()?;
+        let database = source.database()?;
+        let blob = database.store(&crate::Object::Blob(object::Blob::new(
+            name.as_bytes().to_vec(),
+        )))?;
+        let tree = database.store(&crate::Object::Tree(object::tree::Root::new(vec![
+            object::tree::Node {
+                path: path::PathBuf::from("file"),
+                mode: crate::meta::Mode::Regular,
+                id: blob,
+            },
+        ])))?;
+        let commit = object::Commit::new(
+            tree,
+            vec![],
+            person.clone(),
+            person.clone(),
+            name.to_string(),
+        );
+        let commit = database.store(&crate::Object::Commit(commit))?;
+        source.references().write_head(&commit)?;
+        commits.push(commit);
+
+        document.set(
+            &format!("remote.{}.url", name),
+            &format!("file://{}", root.join(name).display()),
+        )?;
+        document.set(
+            &format!("remote.{}.fetch", name),
+            &format!("+refs/heads/*:refs/remotes/{}/*", name),
+        )?;
+    }
+    document.commit()?;
+
+    // A missing remote fails on its own without stopping the others.
+    let remotes = ["a", "missing", "b", "c"]
+        .iter()
+        .map(|name| name.to_string())
+        .collect::<Vec<_>>();
+    let results = parallel(
+        &root.join("target"),
+        &remotes,
+        None,
+        2,
+        &sync::Mutex::new(()),
+    );
+    let results = results
+        .into_iter()
+        .map(|result| result.ok())
+        .collect::<Vec<_>>();
+
+    let database = target.database()?;
+    let references = target.references();
+    let mut fetched = Vec::new();
+    for name in ["a", "b", "c"].iter() {
+        fetched.push(references.read(&format!("refs/remotes/{}/master", name))?);
+    }
+    let stored = commits
+        .iter()
+        .map(|id| database.contains(id))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    assert_eq!(results, vec![Some(false), None, Some(false), Some(false)]);
+    assert_eq!(fetched, commits.into_iter().map(Some).collect::<Vec<_>>());
+    assert_eq!(stored, vec![true; 3]);
+    Ok(())
+}
@@ -0,0 +1,572 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::io::Write as _;
+use std::path;
+
+use anyhow::anyhow;
+use structopt::StructOpt;
+
+use crate::fast_import;
+use crate::fast_import::Command;
+use crate::fast_import::Dataref;
+use crate::object;
+use crate::references::Target;
+
+/// Rewrite the history of every reference, e.g. to extract a subdirectory
+/// into its own history or to scrub files and text from it.
+///
+/// Authorship is preserved, and commits left without changes are dropped.
+/// Like `git filter-branch`, the old value of every rewritten reference is
+/// kept under `refs/original/`. Only references are rewritten: run
+/// `grit reset --hard` afterwards to update the index and workspace.
+#[derive(StructOpt)]
+pub struct Configuration {
+    /// Keep only the files under this directory, moving them to the root.
+    /// With `--invert-paths`, may be given several times.
+    #[structopt(long = "path")]
+    paths: Vec<path::PathBuf>,
+
+    /// Remove the files under `--path` instead of keeping them.
+    #[structopt(long, requires = "paths")]
+    invert_paths: bool,
+
+    /// File of replacements to make in commit and tag messages, one per line
+    /// as `text==>replacement`, or just `text` to replace with
+    /// `***REMOVED***`.
+    #[structopt(long)]
+    replace_message: Option<path::PathBuf>,
+
+    /// Replace the backup under `refs/original/` left by an earlier run.
+    #[structopt(short, long)]
+    force: bool,
+}
+
+/// Where the old values of rewritten references are backed up.
+const ORIGINAL: &str = "refs/original/";
+
+impl Configuration {
+    pub fn run(self) -> anyhow::Result<()> {
+        if self.paths.len() > 1 && !self.invert_paths {
+            return Err(anyhow!("Only one path can be extracted at a time"));
+        }
+
+        let replacements = match &self.replace_message {
+            None => Vec::new(),
+            Some(path) => fs::read_to_string(path)?
+                .lines()
+                .filter(|line| !line.is_empty())
+                .map(|line| match line.split_once("==>") {
+                    Some((text, replacement)) => (text.to_owned(), replacement.to_owned()),
+                    None => (line.to_owned(), String::from("***REMOVED***")),
+                })
+                .collect(),
+        };
+
+        let root = env::current_dir()?;
+        let repository = crate::Repository::new(root);
+        let filter = Filter {
+            paths: self.paths,
+            invert: self.invert_paths,
+            replacements,
+            pruned: HashMap::new(),
+        };
+
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        for (name, new) in filter.apply(&repository, self.force)? {
+            match new {
+                Some(_) => writeln!(stdout, "Ref '{}' was rewritten", name)?,
+                None => writeln!(stdout, "Ref '{}' was deleted", name)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Filter {
+    /// Paths to keep, or to remove if `invert` is set.
+    paths: Vec<path::PathBuf>,
+    invert: bool,
+    replacements: Vec<(String, String)>,
+    /// Marks of dropped commits, mapped to the commit replacing them, if any.
+    pruned: HashMap<usize, Option<Dataref>>,
+}
+
+impl Filter {
+    /// Rewrite every reference in `repository` but the backups, returning
+    /// those that changed with their new values, or `None` if nothing
+    /// remains of their history and they were deleted.
+    ///
+    /// Old values are backed up under [`ORIGINAL`] first, and an earlier
+    /// backup is only replaced if `force` is set.
+    fn apply(
+        mut self,
+        repository: &crate::Repository,
+        force: bool,
+    ) -> anyhow::Result<Vec<(String, Option<object::Id>)>> {
+        let database = repository.database()?;
+        let references = repository.references();
+        let store = references.store();
+
+        let (backups, tips): (Vec<_>, Vec<_>) = references
+            .iter_prefix("refs/")?
+            .collect::<anyhow::Result<Vec<_>>>()?
+            .into_iter()
+            .partition(|(name, _)| name.starts_with(ORIGINAL));
+        if !backups.is_empty() && !force {
+            return Err(anyhow!(
+                "A previous backup already exists in {}. Force overwriting the backup with -f",
+                ORIGINAL,
+            ));
+        }
+        for (name, _) in &backups {
+            store.delete(name)?;
+        }
+        for (name, id) in &tips {
+            store.write(&format!("{}{}", ORIGINAL, name), &Target::Direct(*id))?;
+        }
+
+        let mut import = fast_import::Import::new(&database, &references);
+        fast_import::export(&database, &tips, |command| {
+            for command in self.rewrite(command, &import)? {
+                import.apply(command)?;
+            }
+            Ok(())
+        })?;
+
+        let rewritten = import.finish(true)?.into_iter().collect::<HashMap<_, _>>();
+        let mut changed = Vec::new();
+        for (name, old) in tips {
+            match rewritten.get(&name) {
+                Some(new) if *new == old => {
+                    store.delete(&format!("{}{}", ORIGINAL, name))?;
+                }
+                Some(new) => changed.push((name, Some(*new))),
+                // Nothing remains of its history.
+                None => {
+                    store.delete(&name)?;
+                    changed.push((name, None));
+                }
+            }
+        }
+        Ok(changed)
+    }
+
+    /// Rewrite a single command, which may produce none or several.
+    fn rewrite(
+        &mut self,
+        command: Command,
+        import: &fast_import::Import,
+    ) -> anyhow::Result<Vec<Command>> {
+        let commands = match command {
+            Command::Blob { .. } => vec![command],
+            Command::Commit(commit) => self.commit(commit, import)?,
+            Command::Tag(mut tag) => match self.remap(tag.from) {
+                Some(from) => {
+                    tag.from = from;
+                    tag.message = self.message(tag.message);
+                    vec![Command::Tag(tag)]
+                }
+                None => Vec::new(),
+            },
+            Command::Reset { reference, from } => vec![Command::Reset {
+                reference,
+                from: from.and_then(|from| self.remap(from)),
+            }],
+        };
+        Ok(commands)
+    }
+
+    fn commit(
+        &mut self,
+        mut commit: fast_import::Commit,
+        import: &fast_import::Import,
+    ) -> anyhow::Result<Vec<Command>> {
+        let changed = !commit.changes.is_empty();
+        let parents = commit.from.iter().chain(&commit.merges).count();
+
+        commit.changes = commit
+            .changes
+            .into_iter()
+            .filter_map(|change| match change {
+                fast_import::Change::Modify {
+                    mode,
+                    content,
+                    path,
+                } => self.path(&path).map(|path| fast_import::Change::Modify {
+                    mode,
+                    content,
+                    path,
+                }),
+                fast_import::Change::Delete(path) => {
+                    self.path(&path).map(fast_import::Change::Delete)
+                }
+                fast_import::Change::DeleteAll => Some(fast_import::Change::DeleteAll),
+            })
+            .collect();
+
+        let from = commit.from.and_then(|from| self.remap(from));
+        let mut merges = Vec::new();
+        for merge in commit.merges.iter().filter_map(|merge| self.remap(*merge)) {
+            if Some(merge) != from && !merges.contains(&merge) {
+                merges.push(merge);
+            }
+        }
+
+        // After a `deleteall`, the changes list every file left in scope,
+        // so only the parent can tell whether any of them changed.
+        let unchanged = match commit.changes.contains(&fast_import::Change::DeleteAll) {
+            true => import.is_unchanged(from.as_ref(), &commit.changes)?,
+            false => commit.changes.is_empty(),
+        };
+
+        // Drop commits whose changes were all filtered out, or merges that
+        // no longer join separate histories, but keep deliberately empty
+        // commits.
+        let remaining = from.iter().chain(&merges).count();
+        if unchanged && remaining <= 1 && (changed || remaining < parents) {
+            if let Some(mark) = commit.mark {
+                self.pruned
+                    .insert(mark, from.or_else(|| merges.first().copied()));
+            }
+            return Ok(Vec::new());
+        }
+
+        let mut commands = Vec::new();
+        // Keep a commit whose parents were all dropped from inheriting the
+        // reference's current value.
+        if commit.from.is_some() && from.is_none() {
+            commands.push(Command::Reset {
+                reference: commit.reference.clone(),
+                from: None,
+            });
+        }
+
+        commit.from = from;
+        commit.merges = merges;
+        commit.message = self.message(commit.message);
+        commands.push(Command::Commit(commit));
+        Ok(commands)
+    }
+
+    /// Map `path` to its place in the rewritten tree, or `None` if removed.
+    fn path(&self, path: &path::Path) -> Option<path::PathBuf> {
+        if self.paths.is_empty() {
+            return Some(path.to_path_buf());
+        }
+
+        let prefix = self.paths.iter().find(|prefix| path.starts_with(prefix));
+        match (prefix, self.invert) {
+            (Some(_), true) | (None, false) => None,
+            (None, true) => Some(path.to_path_buf()),
+            (Some(prefix), false) => path
+                .strip_prefix(prefix)
+                .ok()
+                .filter(|path| !path.as_os_str().is_empty())
+                .map(path::Path::to_path_buf),
+        }
+    }
+
+    /// Follow `dataref` past any dropped commits.
+    fn remap(&self, dataref: Dataref) -> Option<Dataref> {
+        match dataref {
+            Dataref::Mark(mark) => match self.pruned.get(&mark) {
+                Some(replacement) => *replacement,
+                None => Some(dataref),
+            },
+            Dataref::Id(_) => Some(dataref),
+        }
+    }
+
+    fn message(&self, message: String) -> String {
+        self.replacements
+            .iter()
+            .fold(message, |message, (text, replacement)| {
+                message.replace(text, replacement)
+            })
+    }
+}
+
+#[cfg(test)]
+fn filter(paths: &[&str], invert: bool, replacements: &[(&str, &str)]) -> Filter {
+    Filter {
+        paths: paths.iter().map(path::PathBuf::from).collect(),
+        invert,
+        replacements: replacements
+            .iter()
+            .map(|(text, replacement)| (text.to_string(), replacement.to_string()))
+            .collect(),
+        pruned: HashMap::new(),
+    }
+}
+
+#[cfg(test)]
+fn commit(
+    database: &crate::Database,
+    parents: Vec<object::Id>,
+    files: &[(&str, &[u8])],
+    message: &str,
+) -> anyhow::Result<object::Id> {
+    let person = object::Person::new(
+        String::from("A U Thor"),
+        String::from("author@example.com"),
+        object::Person::parse_time("@1600000000 +0000")?,
+    );
+    let mut tree = std::collections::BTreeMap::new();
+    for (path, data) in files {
+        let id = database.store(&crate::Object::Blob(object::Blob::new(data.to_vec())))?;
+        tree.insert(
+            crate::util::PathBuf(path::PathBuf::from(path)),
+            crate::diff::tree::Entry {
+                id,
+                mode: crate::meta::Mode::Regular,
+            },
+        );
+    }
+    let commit = object::Commit::new(
+        crate::diff::tree::unflatten(database, &tree)?,
+        parents,
+        person.clone(),
+        person,
+        message.to_owned(),
+    );
+    Ok(database.store(&crate::Object::Commit(commit))?)
+}
+
+/// The messages of every commit reachable from `tip`, newest first.
+#[cfg(test)]
+fn messages(database: &crate::Database, tip: &object::Id) -> anyhow::Result<Vec<String>> {
+    let mut messages = Vec::new();
+    let mut queue = std::collections::VecDeque::from(vec![*tip]);
+    let mut seen = std::collections::HashSet::new();
+    while let Some(id) = queue.pop_front() {
+        if seen.insert(id) {
+            let commit = database.load_commit(&id)?;
+            messages.push(commit.message().to_owned());
+            queue.extend(commit.parents().iter().copied());
+        }
+    }
+    Ok(messages)
+}
+
+#[cfg(test)]
+fn files(
+    database: &crate::Database,
+    commit: &object::Id,
+) -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+    let tree = database.load_commit(commit)?.tree().to_owned();
+    let mut files = Vec::new();
+    for (path, entry) in crate::diff::tree::flatten(database, &tree)? {
+        let data = database.load_blob(&entry.id)?.data().to_vec();
+        files.push((path.0.display().to_string(), data));
+    }
+    Ok(files)
+}
+
+#[test]
+fn extract_path() -> anyhow::Result<()> {
+    let repository = crate::Repository::memory(path::PathBuf::new());
+    let database = repository.database()?;
+    let references = repository.references();
+
+    let root = commit(&database, vec![], &[("a", b"1"), ("dir/b", b"1")], "root")?;
+    let outside = commit(
+        &database,
+        vec![root],
+        &[("a", b"2"), ("dir/b", b"1")],
+        "outside",
+    )?;
+    let inside = commit(
+        &database,
+        vec![outside],
+        &[("a", b"2"), ("dir/b", b"1"), ("dir/c", b"1")],
+        "inside",
+    )?;
+    references.create_branch("main", &inside)?;
+
+    let changed = filter(&["dir"], false, &[]).apply(&repository, false)?;
+    let main = references.read("refs/heads/main")?.unwrap();
+
+    assert_eq!(changed, vec![(String::from("refs/heads/main"), Some(main))]);
+    assert_eq!(messages(&database, &main)?, vec!["inside", "root"]);
+    assert_eq!(
+        files(&database, &main)?,
+        vec![
+            (String::from("b"), b"1".to_vec()),
+            (String::from("c"), b"1".to_vec()),
+        ],
+    );
+    assert_eq!(
+        references.read("refs/original/refs/heads/main")?,
+        Some(inside),
+    );
+    Ok(())
+}
+
+#[test]
+fn invert_paths() -> anyhow::Result<()> {
+    let repository = crate::Repository::memory(path::PathBuf::new());
+    let database = repository.database()?;
+    let references = repository.references();
+
+    let root = commit(&database, vec![], &[("a", b"1"), ("dir/b", b"1")], "root")?;
+    let inside = commit(
+        &database,
+        vec![root],
+        &[("a", b"1"), ("dir/b", b"2")],
+        "inside",
+    )?;
+    references.create_branch("main", &inside)?;
+    references.create_branch("root", &root)?;
+
+    let changed = filter(&["dir"], true, &[]).apply(&repository, false)?;
+    let main = references.read("refs/heads/main")?.unwrap();
+
+    assert_eq!(
+        changed
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>(),
+        vec!["refs/heads/main", "refs/heads/root"],
+    );
+    assert_eq!(references.read("refs/heads/root")?, Some(main));
+    assert_eq!(messages(&database, &main)?, vec!["root"]);
+    assert_eq!(
+        files(&database, &main)?,
+        vec![(String::from("a"), b"1".to_vec())],
+    );
+    Ok(())
+}
+
+#[test]
+fn drop_merges() -> anyhow::Result<()> {
+    let repository = crate::Repository::memory(path::PathBuf::new());
+    let database = repository.database()?;
+    let references = repository.references();
+
+    let root = commit(&database, vec![], &[("a", b"1"), ("dir/b", b"1")], "root")?;
+    let outside = commit(
+        &database,
+        vec![root],
+        &[("a", b"2"), ("dir/b", b"1")],
+        "outside",
+    )?;
+    // Only joins `root` with itself once `outside` is dropped.
+    let degenerate = commit(
+        &database,
+        vec![root, outside],
+        &[("a", b"2"), ("dir/b", b"1")],
+        "degenerate",
+    )?;
+    let left = commit(
+        &database,
+        vec![degenerate],
+        &[("a", b"2"), ("dir/b", b"2")],
+        "left",
+    )?;
+    let right = commit(
+        &database,
+        vec![degenerate],
+        &[("a", b"2"), ("dir/b", b"1"), ("dir/c", b"1")],
+        "right",
+    )?;
+    let merge = commit(
+        &database,
+        vec![left, right],
+        &[("a", b"2"), ("dir/b", b"2"), ("dir/c", b"1")],
+        "merge",
+    )?;
+    // Deliberately empty, so kept.
+    let empty = commit(
+        &database,
+        vec![merge],
+        &[("a", b"2"), ("dir/b", b"2"), ("dir/c", b"1")],
+        "empty",
+    )?;
+    references.create_branch("main", &empty)?;
+
+    filter(&["dir"], false, &[]).apply(&repository, false)?;
+    let main = references.read("refs/heads/main")?.unwrap();
+    let merge = database.load_commit(&main)?.parents()[0];
+
+    assert_eq!(
+        messages(&database, &main)?,
+        vec!["empty", "merge", "left", "right", "root"],
+    );
+    assert_eq!(
+        files(&database, &main)?,
+        vec![
+            (String::from("b"), b"2".to_vec()),
+            (String::from("c"), b"1".to_vec()),
+        ],
+    );
+    assert_eq!(database.load_commit(&merge)?.parents().len(), 2);
+    Ok(())
+}
+
+#[test]
+fn replace_message() -> anyhow::Result<()> {
+    let repository = crate::Repository::memory(path::PathBuf::new());
+    let database = repository.database()?;
+    let references = repository.references();
+
+    let root = commit(&database, vec![], &[("a", b"1")], "add password hunter2\n")?;
+    references.create_branch("main", &root)?;
+
+    filter(&[], false, &[("hunter2", "***REMOVED***")]).apply(&repository, false)?;
+    let main = references.read("refs/heads/main")?.unwrap();
+
+    assert_eq!(
+        messages(&database, &main)?,
+        vec!["add password ***REMOVED***\n"],
+    );
+    assert!(filter(&[], false, &[]).apply(&repository, false).is_err());
+    Ok(())
+}
+
+#[test]
+fn delete_all() -> anyhow::Result<()> {
+    let repository = crate::Repository::memory(path::PathBuf::new());
+    let database = repository.database()?;
+    let references = repository.references();
+    let import = fast_import::Import::new(&database, &references);
+
+    // Already rewritten, with `dir` extracted.
+    let parent = commit(&database, vec![], &[("b", b"1")], "parent")?;
+    let person = object::Person::new(
+        String::from("A U Thor"),
+        String::from("author@example.com"),
+        object::Person::parse_time("@1600000000 +0000")?,
+    );
+    let snapshot = |a: &[u8], b: &[u8]| {
+        let modify = |path: &str, data: &[u8]| fast_import::Change::Modify {
+            mode: crate::meta::Mode::Regular,
+            content: fast_import::Content::Inline(data.to_vec()),
+            path: path::PathBuf::from(path),
+        };
+        fast_import::Commit {
+            reference: String::from("refs/heads/main"),
+            mark: None,
+            author: None,
+            committer: person.clone(),
+            message: String::from("snapshot"),
+            from: Some(Dataref::Id(parent)),
+            merges: Vec::new(),
+            changes: vec![
+                fast_import::Change::DeleteAll,
+                modify("a", a),
+                modify("dir/b", b),
+            ],
+        }
+    };
+
+    let mut filter = filter(&["dir"], false, &[]);
+    let outside = filter.commit(snapshot(b"2", b"1"), &import)?;
+    let inside = filter.commit(snapshot(b"1", b"2"), &import)?;
+
+    assert!(outside.is_empty());
+    assert_eq!(inside.len(), 1);
+    Ok(())
+}
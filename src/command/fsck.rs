@@ -0,0 +1,43 @@
+use std::env;
+use std::io;
+use std::io::Write as _;
+
+use anyhow::anyhow;
+use structopt::StructOpt;
+
+/// Verify the connectivity and validity of every object.
+///
+/// Every object is hashed again and parsed, and everything that commits,
+/// trees, tags, references, reflogs, and the index point at is checked to
+/// exist. Objects nothing points at are reported as dangling.
+#[derive(StructOpt)]
+pub struct Configuration {
+    /// Don't report dangling objects.
+    #[structopt(long)]
+    no_dangling: bool,
+}
+
+impl Configuration {
+    pub fn run(self) -> anyhow::Result<()> {
+        let root = env::current_dir()?;
+        let repository = crate::Repository::open(root);
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+
+        let mut errors = 0;
+        for problem in repository.verify()? {
+            match problem.is_error() {
+                true => errors += 1,
+                false if self.no_dangling => continue,
+                false => (),
+            }
+            writeln!(stdout, "{}", problem)?;
+        }
+
+        match errors {
+            0 => Ok(()),
+            1 => Err(anyhow!("Found 1 problem")),
+            errors => Err(anyhow!("Found {} problems", errors)),
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use std::env;
+
+use anyhow::anyhow;
+use structopt::StructOpt;
+
+use crate::gc;
+
+/// Pack loose objects, consolidate packfiles into one, expire old reflog
+/// entries, and prune unreachable objects.
+///
+/// Unreachable objects are only pruned once they're older than
+/// `gc.pruneExpire`, two weeks by default. Porcelain commands that create
+/// objects run `grit gc --auto` on their own once there are more
+/// than `gc.auto` loose objects or `gc.autoPackLimit` packfiles, in the
+/// background unless `gc.autoDetach` is false.
+#[derive(StructOpt)]
+pub struct Configuration {
+    /// Do nothing unless the repository is over the `gc.auto` or
+    /// `gc.autoPackLimit` thresholds, or another gc is running.
+    #[structopt(long)]
+    auto: bool,
+
+    /// Prune unreachable objects older than `<date>` instead of
+    /// `gc.pruneExpire`, e.g. `now` to prune every unreachable object.
+    #[structopt(long, value_name = "date")]
+    prune: Option<String>,
+
+    /// Keep unreachable objects, however old.
+    #[structopt(long, conflicts_with = "prune")]
+    no_prune: bool,
+}
+
+impl Configuration {
+    pub fn run(self) -> anyhow::Result<()> {
+        let root = env::current_dir()?;
+        let repository = crate::Repository::open(root);
+
+        if self.auto && !gc::needed(&repository.config()?, &repository.database()?)? {
+            return Ok(());
+        }
+
+        let prune = match self.no_prune {
+            true => Some("never"),
+            false => self.prune.as_deref(),
+        };
+        match gc::run(&repository, prune)? || self.auto {
+            true => Ok(()),
+            false => Err(anyhow!("gc is already running")),
+        }
+    }
+}
@@ -0,0 +1,442 @@
+use std::cmp;
+use std::env;
+use std::io;
+use std::io::Write as _;
+use std::num;
+use std::panic;
+use std::path;
+use std::sync::atomic;
+use std::thread;
+
+use anyhow::anyhow;
+use structopt::clap::ArgGroup;
+use structopt::StructOpt;
+use termcolor::WriteColor as _;
+
+use crate::diff;
+use crate::object;
+use crate::platform::OsStrExt as _;
+use crate::revision;
+
+/// Search files for lines matching a regular expression, like `git grep`.
+///
+/// Tracked files are searched in the workspace, in the index with
+/// `--cached`, or in the tree of a revision if one is given. Without paths,
+/// only files under the current directory are searched. Paths are printed
+/// relative to the current directory.
+#[derive(StructOpt)]
+#[structopt(group = ArgGroup::with_name("summary"))]
+pub struct Configuration {
+    /// Search the contents staged in the index instead of the workspace.
+    #[structopt(long)]
+    cached: bool,
+
+    /// Prefix matching lines with their line numbers.
+    #[structopt(short = "n", long)]
+    line_number: bool,
+
+    /// Ignore case differences between the pattern and the files.
+    #[structopt(short, long)]
+    ignore_case: bool,
+
+    /// Select lines that don't match.
+    #[structopt(short = "v", long)]
+    invert_match: bool,
+
+    /// Match the pattern as a literal string, not a regular expression.
+    #[structopt(short = "F", long)]
+    fixed_strings: bool,
+
+    /// Only match the pattern at word boundaries.
+    #[structopt(short, long)]
+    word_regexp: bool,
+
+    /// Print only the names of files with matching lines.
+    #[structopt(short = "l", long, group = "summary")]
+    files_with_matches: bool,
+
+    /// Print the number of matching lines in each file.
+    #[structopt(short, long, group = "summary")]
+    count: bool,
+
+    /// Regular expression to search for.
+    pattern: String,
+
+    /// A revision to search, followed by paths or globs to limit the search
+    /// to. Paths can also be given after `--`.
+    arguments: Vec<String>,
+
+    #[structopt(last = true)]
+    paths: Vec<String>,
+}
+
+impl Configuration {
+    pub fn run(self) -> anyhow::Result<()> {
+        let (repository, prefix) = crate::Repository::discover(&env::current_dir()?)?;
+        let database = repository.database()?;
+        let workspace = repository.workspace()?;
+
+        // Like `git`, take the first argument as a revision unless it names
+        // a file.
+        let exists = |name: &str| {
+            prefix
+                .resolve(name.as_ref())
+                .is_ok_and(|path| repository.root().join(path).exists())
+        };
+        let revision = match self.arguments.first() {
+            Some(name) if !exists(name) => name
+                .parse::<revision::Revision>()
+                .ok()
+                .and_then(|revision| revision.resolve(&repository).ok().flatten())
+                .map(|id| (name.clone(), id)),
+            _ => None,
+        };
+        if revision.is_some() && self.cached {
+            return Err(anyhow!("--cached cannot be used with a revision"));
+        }
+
+        let pathspecs = self
+            .arguments
+            .iter()
+            .skip(revision.iter().count())
+            .chain(&self.paths)
+            .map(|path| Pathspec::new(&prefix, path))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let is_selected = |path: &path::Path| match pathspecs.is_empty() {
+            true => prefix.contains(path),
+            false => pathspecs.iter().any(|pathspec| pathspec.matches(path)),
+        };
+
+        let mut files = Vec::new();
+        match &revision {
+            Some((_, id)) => {
+                let tree = id.peel_to_tree(&database)?;
+                for (path, entry) in diff::tree::flatten(&database, &tree)? {
+                    if entry.mode.is_file() && is_selected(&path.0) {
+                        files.push((path.0, Source::Blob(entry.id)));
+                    }
+                }
+            }
+            None => {
+                let index = repository.index()?;
+                for entry in index.entries() {
+                    let path = entry.path();
+                    let duplicate = files.last().is_some_and(|(last, _)| last == path);
+                    if duplicate || !entry.metadata().mode.is_file() || !is_selected(path) {
+                        continue;
+                    }
+                    match self.cached {
+                        true => files.push((path.to_path_buf(), Source::Blob(*entry.id()))),
+                        false => files.push((path.to_path_buf(), Source::Workspace)),
+                    }
+                }
+            }
+        }
+
+        let pattern = match self.fixed_strings {
+            true => regex::escape(&self.pattern),
+            false => self.pattern.clone(),
+        };
+        let pattern = match self.word_regexp {
+            true => format!(r"\b(?:{})\b", pattern),
+            false => pattern,
+        };
+        let color = isatty::stdout_isatty();
+
+        let grep = Grep {
+            regex: regex::bytes::RegexBuilder::new(&pattern)
+                .case_insensitive(self.ignore_case)
+                .build()?,
+            invert: self.invert_match,
+            line_number: self.line_number,
+            summary: match (self.files_with_matches, self.count) {
+                (true, _) => Some(Summary::Files),
+                (_, true) => Some(Summary::Count),
+                _ => None,
+            },
+            color,
+            revision: revision.map(|(name, _)| name),
+            prefix,
+        };
+
+        let workers = match repository.config()?.parse::<i64>("grep.threads")? {
+            Some(workers) if workers >= 1 => workers as usize,
+            _ => thread::available_parallelism().map_or(1, num::NonZeroUsize::get),
+        };
+
+        let output = termcolor::BufferWriter::stdout(match color {
+            true => termcolor::ColorChoice::Always,
+            false => termcolor::ColorChoice::Never,
+        });
+
+        let mut found = false;
+        for buffer in grep.run(&database, &workspace, &files, workers)? {
+            found |= !buffer.is_empty();
+            output.print(&buffer)?;
+        }
+
+        match found {
+            true => Ok(()),
+            false => Err(anyhow!("No matches")),
+        }
+    }
+}
+
+/// Path to search, relative to the workspace root, or a glob matching
+/// them, where `*` also matches `/`.
+enum Pathspec {
+    Path(path::PathBuf),
+    Glob(Vec<u8>),
+}
+
+impl Pathspec {
+    fn new(prefix: &crate::prefix::Prefix, pathspec: &str) -> anyhow::Result<Self> {
+        let resolved = prefix.resolve(pathspec.as_ref())?;
+        match pathspec.contains(['*', '?', '[']) {
+            false => Ok(Pathspec::Path(resolved)),
+            true => Ok(Pathspec::Glob(crate::ignore::crossing(
+                resolved.as_os_str().as_bytes(),
+            ))),
+        }
+    }
+
+    fn matches(&self, path: &path::Path) -> bool {
+        match self {
+            Pathspec::Path(prefix) => path.starts_with(prefix),
+            Pathspec::Glob(glob) => crate::ignore::wildmatch(glob, path.as_os_str().as_bytes()),
+        }
+    }
+}
+
+/// Where to read a file's contents from.
+#[derive(Copy, Clone, Debug)]
+enum Source {
+    Blob(object::Id),
+    Workspace,
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Summary {
+    Files,
+    Count,
+}
+
+/// Below this many files, searching in parallel isn't worth starting
+/// threads for.
+const PARALLEL_THRESHOLD: usize = 64;
+
+struct Grep {
+    regex: regex::bytes::Regex,
+    invert: bool,
+    line_number: bool,
+    summary: Option<Summary>,
+    color: bool,
+    /// Revision being searched, printed before each path.
+    revision: Option<String>,
+    prefix: crate::prefix::Prefix,
+}
+
+impl Grep {
+    /// Search `files`, split between `workers` threads that each load and
+    /// search one file at a time, returning each file's output in order.
+    fn run(
+        &self,
+        database: &crate::Database,
+        workspace: &crate::Workspace,
+        files: &[(path::PathBuf, Source)],
+        workers: usize,
+    ) -> anyhow::Result<Vec<termcolor::Buffer>> {
+        let search = |database: &crate::Database,
+                      (path, source): &(path::PathBuf, Source)|
+         -> anyhow::Result<termcolor::Buffer> {
+            let data = match source {
+                Source::Blob(id) => database.load_blob(id)?.into_data(),
+                Source::Workspace => match workspace.read(path) {
+                    Ok(data) => data,
+                    // Like `git`, skip tracked files deleted from the workspace.
+                    Err(error) if error.kind() == io::ErrorKind::NotFound => Vec::new(),
+                    Err(error) => return Err(error.into()),
+                },
+            };
+            Ok(self.search(path, &data)?)
+        };
+
+        let workers = cmp::min(workers, files.len());
+        let root = match database.root() {
+            Some(root) if workers > 1 && files.len() >= PARALLEL_THRESHOLD => root,
+            _ => return files.iter().map(|file| search(database, file)).collect(),
+        };
+
+        let next = atomic::AtomicUsize::new(0);
+        let (next, search) = (&next, &search);
+        let searched = thread::scope(|scope| {
+            (0..workers)
+                .map(|_| {
+                    scope.spawn(move || {
+                        let database = crate::Database::open(root.to_path_buf());
+                        let mut searched = Vec::new();
+                        loop {
+                            let index = next.fetch_add(1, atomic::Ordering::Relaxed);
+                            let file = match files.get(index) {
+                                None => return Ok(searched),
+                                Some(file) => file,
+                            };
+                            match search(&database, file) {
+                                Ok(buffer) => searched.push((index, buffer)),
+                                Err(error) => {
+                                    // Stop the other workers early.
+                                    next.store(files.len(), atomic::Ordering::Relaxed);
+                                    return Err(error);
+                                }
+                            }
+                        }
+                    })
+                })
+                .collect::<Vec<_>>()
+                .into_iter()
+                .map(|worker| {
+                    worker
+                        .join()
+                        .unwrap_or_else(|panic| panic::resume_unwind(panic))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()
+        })?;
+
+        let mut searched = searched.into_iter().flatten().collect::<Vec<_>>();
+        searched.sort_by_key(|(index, _)| *index);
+        Ok(searched.into_iter().map(|(_, buffer)| buffer).collect())
+    }
+
+    /// Search `data`, the contents of `path`, returning what to print.
+    fn search(&self, path: &path::Path, data: &[u8]) -> io::Result<termcolor::Buffer> {
+        let mut buffer = match self.color {
+            true => termcolor::Buffer::ansi(),
+            false => termcolor::Buffer::no_color(),
+        };
+
+        let name = match &self.revision {
+            None => self.prefix.display(path).display().to_string(),
+            Some(revision) => format!("{}:{}", revision, self.prefix.display(path).display()),
+        };
+
+        let binary = diff::is_binary(data);
+        let mut count = 0;
+        for (index, line) in diff::lines(data).into_iter().enumerate() {
+            let line = line.strip_suffix(b"\n").unwrap_or(line);
+            if self.regex.is_match(line) == self.invert {
+                continue;
+            }
+            count += 1;
+            if binary || self.summary.is_some() {
+                continue;
+            }
+
+            self.name(&mut buffer, &name)?;
+            self.separator(&mut buffer)?;
+            if self.line_number {
+                buffer
+                    .set_color(termcolor::ColorSpec::new().set_fg(Some(termcolor::Color::Green)))?;
+                write!(buffer, "{}", index + 1)?;
+                self.separator(&mut buffer)?;
+            }
+
+            let mut end = 0;
+            if !self.invert {
+                for found in self.regex.find_iter(line) {
+                    buffer.write_all(&line[end..found.start()])?;
+                    buffer.set_color(
+                        termcolor::ColorSpec::new()
+                            .set_fg(Some(termcolor::Color::Red))
+                            .set_bold(true),
+                    )?;
+                    buffer.write_all(found.as_bytes())?;
+                    buffer.reset()?;
+                    end = found.end();
+                }
+            }
+            buffer.write_all(&line[end..])?;
+            writeln!(buffer)?;
+        }
+
+        match (count, self.summary) {
+            (0, _) => (),
+            (_, Some(Summary::Files)) => {
+                self.name(&mut buffer, &name)?;
+                writeln!(buffer)?;
+            }
+            (count, Some(Summary::Count)) => {
+                self.name(&mut buffer, &name)?;
+                self.separator(&mut buffer)?;
+                writeln!(buffer, "{}", count)?;
+            }
+            (_, None) if binary => writeln!(buffer, "Binary file {} matches", name)?,
+            (_, None) => (),
+        }
+        Ok(buffer)
+    }
+
+    fn name(&self, buffer: &mut termcolor::Buffer, name: &str) -> io::Result<()> {
+        buffer.set_color(termcolor::ColorSpec::new().set_fg(Some(termcolor::Color::Magenta)))?;
+        write!(buffer, "{}", name)?;
+        buffer.reset()
+    }
+
+    fn separator(&self, buffer: &mut termcolor::Buffer) -> io::Result<()> {
+        buffer.set_color(termcolor::ColorSpec::new().set_fg(Some(termcolor::Color::Cyan)))?;
+        write!(buffer, ":")?;
+        buffer.reset()
+    }
+}
+
+#[test]
+fn search() -> anyhow::Result<()> {
+    let repository = crate::Repository::memory(path::PathBuf::new());
+    let database = repository.database()?;
+    let workspace = crate::Workspace::new(path::PathBuf::from("/repo"));
+    let prefix = || crate::prefix::Prefix::new("/repo".as_ref(), "/repo/dir".as_ref());
+
+    let mut files = Vec::new();
+    for (path, data) in [
+        ("dir/a", &b"foo\nbar\nFoo bar\n"[..]),
+        ("dir/binary", b"foo\0"),
+        ("x", b"bar\n"),
+    ]
+    .iter()
+    {
+        let id = database.store(&crate::Object::Blob(object::Blob::new(data.to_vec())))?;
+        files.push((path::PathBuf::from(path), Source::Blob(id)));
+    }
+    let grep = |pattern: &str, invert: bool, summary: Option<Summary>| -> anyhow::Result<String> {
+        let grep = Grep {
+            regex: regex::bytes::RegexBuilder::new(pattern)
+                .case_insensitive(true)
+                .build()?,
+            invert,
+            line_number: true,
+            summary,
+            color: false,
+            revision: Some(String::from("HEAD")),
+            prefix: prefix()?,
+        };
+        let output = grep
+            .run(&database, &workspace, &files, 1)?
+            .into_iter()
+            .flat_map(termcolor::Buffer::into_inner)
+            .collect();
+        Ok(String::from_utf8(output)?)
+    };
+
+    let matches = grep("foo", false, None)?;
+    let counts = grep("foo", true, Some(Summary::Count))?;
+    let names = grep("bar", false, Some(Summary::Files))?;
+
+    assert_eq!(
+        matches,
+        "HEAD:a:1:foo\nHEAD:a:3:Foo bar\nBinary file HEAD:binary matches\n",
+    );
+    assert_eq!(counts, "HEAD:a:1\nHEAD:../x:1\n");
+    assert_eq!(names, "HEAD:a\nHEAD:../x\n");
+    assert!(Pathspec::new(&prefix()?, "*.rs")?.matches("dir/src/main.rs".as_ref()));
+    assert!(!Pathspec::new(&prefix()?, "a")?.matches("dir/ab".as_ref()));
+    Ok(())
+}
@@ -0,0 +1,53 @@
+use std::io;
+use std::io::Write as _;
+
+use anyhow::anyhow;
+use structopt::StructOpt;
+
+use crate::help;
+
+/// Show the manual page for a command, or list every command.
+#[derive(StructOpt)]
+pub struct Configuration {
+    /// Command to describe, e.g. `commit`.
+    command: Option<String>,
+}
+
+impl Configuration {
+    pub fn run(self) -> anyhow::Result<()> {
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+
+        let name = match self.command {
+            Some(name) => name,
+            None => {
+                let width = help::PAGES
+                    .iter()
+                    .map(|page| page.name.len())
+                    .max()
+                    .unwrap_or(0);
+
+                writeln!(stdout, "usage: grit <command> [<args>]\n")?;
+                writeln!(stdout, "COMMANDS:")?;
+                for page in help::PAGES {
+                    writeln!(
+                        stdout,
+                        "    {:<width$}    {}",
+                        page.name,
+                        page.summary,
+                        width = width
+                    )?;
+                }
+                writeln!(
+                    stdout,
+                    "\nSee `grit help <command>` to read about a command."
+                )?;
+                return Ok(());
+            }
+        };
+
+        let page = help::find(&name).ok_or_else(|| anyhow!("No manual entry for '{}'", name))?;
+        write!(stdout, "{}", page)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,465 @@
+use std::collections::hash_map::Entry;
+use std::collections::BinaryHeap;
+use std::collections::HashMap;
+use std::env;
+use std::io::Write as _;
+use std::path;
+
+use anyhow::anyhow;
+use structopt::clap::ArgGroup;
+use structopt::StructOpt;
+use termcolor::WriteColor as _;
+
+use crate::attributes;
+use crate::diff;
+use crate::object;
+use crate::revision;
+use crate::revwalk;
+use crate::util;
+
+/// Width of `--stat` output, matching `git` when not writing to a terminal.
+const STAT_WIDTH: usize = 80;
+
+/// Show commit history, starting from `HEAD`, the given reference, or the
+/// references selected by `--all`, `--branches`, `--tags`, and `--remotes`.
+#[derive(StructOpt)]
+#[structopt(group = ArgGroup::with_name("summary"))]
+pub struct Configuration {
+    /// Print each commit on a single line as `<abbreviated id> <title>`.
+    #[structopt(long)]
+    oneline: bool,
+
+    /// Summarize the lines added and removed in each changed file.
+    #[structopt(long, group = "summary")]
+    stat: bool,
+
+    /// List the paths of changed files.
+    #[structopt(long, group = "summary")]
+    name_only: bool,
+
+    /// List the paths of changed files, prefixed by whether they were
+    /// added (`A`), deleted (`D`), or modified (`M`).
+    #[structopt(long, group = "summary")]
+    name_status: bool,
+
+    /// List changed files in the order given by this file, which lists one
+    /// glob per line. Overrides `diff.orderFile`.
+    #[structopt(short = "O", value_name = "orderfile")]
+    order_file: Option<path::PathBuf>,
+
+    /// Show dates as `default`, `local`, `iso`, `iso-strict`, `rfc`,
+    /// `short`, `raw`, `unix`, or `relative`. Overrides `log.date`.
+    #[structopt(long, require_equals = true, value_name = "format")]
+    date: Option<object::DateFormat>,
+
+    /// Start from `HEAD` and every reference.
+    #[structopt(long)]
+    all: bool,
+
+    /// Start from local branches, or only those matching a glob.
+    #[structopt(long, require_equals = true)]
+    branches: Option<Option<String>>,
+
+    /// Start from tags, or only those matching a glob.
+    #[structopt(long, require_equals = true)]
+    tags: Option<Option<String>>,
+
+    /// Start from remote-tracking branches, or only those matching a glob.
+    #[structopt(long, require_equals = true)]
+    remotes: Option<Option<String>>,
+
+    /// Start from references matching a glob, e.g. `heads/feature/*`.
+    #[structopt(long)]
+    glob: Vec<String>,
+
+    /// Revision to start from.
+    ///
+    /// Defaults to `HEAD` if neither this nor any references are selected.
+    start: Option<String>,
+}
+
+impl Configuration {
+    pub fn run(self) -> anyhow::Result<()> {
+        let root = env::current_dir()?;
+        let repository = crate::Repository::new(root);
+        let stdout = termcolor::StandardStream::stdout(match isatty::stdout_isatty() {
+            true => termcolor::ColorChoice::Always,
+            false => termcolor::ColorChoice::Never,
+        });
+
+        let database = repository.database()?;
+        let references = repository.references();
+        let mut refs = Vec::new();
+        if self.all {
+            refs.push(revwalk::Refs::All);
+        }
+        if let Some(pattern) = self.branches {
+            refs.push(revwalk::Refs::Branches(pattern));
+        }
+        if let Some(pattern) = self.tags {
+            refs.push(revwalk::Refs::Tags(pattern));
+        }
+        if let Some(pattern) = self.remotes {
+            refs.push(revwalk::Refs::Remotes(pattern));
+        }
+        refs.extend(self.glob.into_iter().map(revwalk::Refs::Glob));
+
+        let mut starts = Vec::new();
+        for refs in &refs {
+            for (_, id) in refs.resolve(&references)? {
+                // Like `git log`, skip references to trees and blobs.
+                if let Ok(id) = id.peel_to_commit(&database) {
+                    starts.push(id);
+                }
+            }
+        }
+
+        let start = match (&self.start, refs.is_empty()) {
+            (Some(start), _) => Some(start.as_str()),
+            (None, true) => Some("HEAD"),
+            (None, false) => None,
+        };
+        if let Some(start) = start {
+            match start.parse::<revision::Revision>()?.resolve(&repository)? {
+                Some(id) => starts.push(id.peel_to_commit(&database)?),
+                None if start == "HEAD" => (),
+                None => return Err(anyhow!("Unknown revision: `{}`", start)),
+            }
+        }
+
+        let log = Log {
+            date: repository
+                .config()?
+                .option(self.date, "log.date")?
+                .unwrap_or_default(),
+            order: diff::order::Order::open(&repository, self.order_file.as_deref())?,
+            attributes: repository.attributes()?,
+            database,
+            stdout: stdout.lock(),
+            oneline: self.oneline,
+            summary: match (self.stat, self.name_only, self.name_status) {
+                (true, _, _) => Some(Summary::Stat),
+                (_, true, _) => Some(Summary::NameOnly),
+                (_, _, true) => Some(Summary::NameStatus),
+                _ => None,
+            },
+        };
+
+        log.run(starts)
+    }
+}
+
+struct Log<'a> {
+    date: object::DateFormat,
+    order: diff::order::Order,
+    attributes: attributes::Attributes,
+    database: crate::Database,
+    stdout: termcolor::StandardStreamLock<'a>,
+    oneline: bool,
+    summary: Option<Summary>,
+}
+
+/// Per-commit list of changed files.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Summary {
+    Stat,
+    NameOnly,
+    NameStatus,
+}
+
+impl Log<'_> {
+    fn run(mut self, starts: Vec<object::Id>) -> anyhow::Result<()> {
+        // Visit commits newest first, so that merged histories interleave
+        // by date rather than one parent at a time.
+        let mut queue = BinaryHeap::new();
+        let mut commits = HashMap::new();
+
+        for start in starts {
+            self.push(&mut queue, &mut commits, start)?;
+        }
+
+        let mut first = true;
+
+        while let Some((_, id)) = queue.pop() {
+            // Leave `None` behind to mark the commit as visited.
+            let commit = commits
+                .get_mut(&id)
+                .and_then(Option::take)
+                .expect("[INTERNAL ERROR]: queued commit not loaded");
+
+            if self.oneline {
+                self.print_oneline(&id, &commit)?;
+            } else {
+                if !first {
+                    writeln!(&mut self.stdout)?;
+                }
+                self.print_medium(&id, &commit)?;
+            }
+
+            if let Some(summary) = self.summary {
+                self.print_summary(&commit, summary)?;
+            }
+
+            first = false;
+            for parent in commit.parents() {
+                self.push(&mut queue, &mut commits, *parent)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Queue commit `id` unless it has already been visited.
+    fn push(
+        &self,
+        queue: &mut BinaryHeap<(i64, object::Id)>,
+        commits: &mut HashMap<object::Id, Option<object::Commit>>,
+        id: object::Id,
+    ) -> anyhow::Result<()> {
+        if let Entry::Vacant(entry) = commits.entry(id) {
+            let commit = self.database.load_commit(&id)?;
+            queue.push((commit.committer().time().timestamp(), id));
+            entry.insert(Some(commit));
+        }
+        Ok(())
+    }
+
+    fn print_oneline(&mut self, id: &object::Id, commit: &object::Commit) -> anyhow::Result<()> {
+        self.stdout
+            .set_color(termcolor::ColorSpec::new().set_fg(Some(termcolor::Color::Yellow)))?;
+        write!(&mut self.stdout, "{}", &id.to_string()[..7])?;
+        self.stdout.reset()?;
+        writeln!(&mut self.stdout, " {}", commit.title())?;
+        Ok(())
+    }
+
+    fn print_medium(&mut self, id: &object::Id, commit: &object::Commit) -> anyhow::Result<()> {
+        let author = commit.author();
+
+        self.stdout
+            .set_color(termcolor::ColorSpec::new().set_fg(Some(termcolor::Color::Yellow)))?;
+        writeln!(&mut self.stdout, "commit {}", id)?;
+        self.stdout.reset()?;
+
+        if commit.parents().len() > 1 {
+            write!(&mut self.stdout, "Merge:")?;
+            for parent in commit.parents() {
+                write!(&mut self.stdout, " {}", &parent.to_string()[..7])?;
+            }
+            writeln!(&mut self.stdout)?;
+        }
+
+        writeln!(
+            &mut self.stdout,
+            "Author: {} <{}>",
+            author.name(),
+            author.email()
+        )?;
+        writeln!(
+            &mut self.stdout,
+            "Date:   {}",
+            self.date.format(author.time()),
+        )?;
+        writeln!(&mut self.stdout)?;
+
+        for line in commit.message().trim_end().lines() {
+            writeln!(&mut self.stdout, "    {}", line)?;
+        }
+
+        Ok(())
+    }
+
+    /// List the files changed relative to the first parent, or to an empty
+    /// tree for root commits. Like `git`, merge commits list nothing.
+    fn print_summary(&mut self, commit: &object::Commit, summary: Summary) -> anyhow::Result<()> {
+        if commit.parents().len() > 1 {
+            return Ok(());
+        }
+
+        let old = commit
+            .parent()
+            .map(|parent| parent.peel_to_tree(&self.database))
+            .transpose()?;
+        let changes = diff::tree::diff(&self.database, old.as_ref(), Some(commit.tree()))?;
+        if changes.is_empty() {
+            return Ok(());
+        }
+
+        let mut changes = changes.into_iter().collect::<Vec<_>>();
+        self.order.sort(&mut changes, |(path, _)| path);
+
+        if !self.oneline {
+            writeln!(&mut self.stdout)?;
+        }
+
+        match summary {
+            Summary::Stat => self.print_stat(&changes),
+            Summary::NameOnly => {
+                for (path, _) in &changes {
+                    writeln!(&mut self.stdout, "{}", path.display())?;
+                }
+                Ok(())
+            }
+            Summary::NameStatus => {
+                for (path, change) in &changes {
+                    let status = match change {
+                        (None, _) => 'A',
+                        (_, None) => 'D',
+                        _ => 'M',
+                    };
+                    writeln!(&mut self.stdout, "{}\t{}", status, path.display())?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Print a `git diff --stat` style histogram of changed lines, scaled to
+    /// fit within `STAT_WIDTH` columns.
+    fn print_stat(
+        &mut self,
+        changes: &[(util::PathBuf, diff::tree::Change)],
+    ) -> anyhow::Result<()> {
+        let load = |entry: &Option<diff::tree::Entry>| match entry {
+            None => Ok(Vec::new()),
+            Some(entry) => self
+                .database
+                .load_blob(&entry.id)
+                .map(|blob| blob.data().to_vec()),
+        };
+
+        // Binary files are summarized by their sizes in bytes instead.
+        let mut rows = Vec::with_capacity(changes.len());
+        for (path, (old, new)) in changes {
+            let (old, new) = (load(old)?, load(new)?);
+            let binary = self
+                .attributes
+                .is_binary(path)?
+                .unwrap_or_else(|| diff::is_binary(&old) || diff::is_binary(&new));
+            let (added, deleted, bytes) = match binary {
+                false => {
+                    let (added, deleted) = diff::count(&old, &new);
+                    (added, deleted, None)
+                }
+                true => (0, 0, Some((old.len(), new.len()))),
+            };
+            rows.push((path.display().to_string(), added, deleted, bytes));
+        }
+
+        let max_name = rows
+            .iter()
+            .map(|(name, _, _, _)| name.chars().count())
+            .max()
+            .unwrap_or(0);
+        let max_change = rows
+            .iter()
+            .map(|(_, added, deleted, _)| added + deleted)
+            .max()
+            .unwrap_or(0);
+
+        // Like `git`, leave room for `Bin <old> -> <new> bytes` in the graph.
+        let binary_width = rows
+            .iter()
+            .filter_map(|(_, _, _, bytes)| *bytes)
+            .map(|(old, new)| 14 + old.to_string().len() + new.to_string().len())
+            .max();
+
+        // Each row is laid out as ` <name> | <count> <graph>`.
+        let mut number_width = max_change.to_string().len();
+        let mut name_width = max_name;
+        let mut graph_width = max_change;
+        if let Some(binary_width) = binary_width {
+            number_width = number_width.max(3);
+            graph_width = graph_width.max(binary_width.saturating_sub(4));
+        }
+        if name_width + number_width + 6 + graph_width > STAT_WIDTH {
+            if graph_width + number_width + 6 > STAT_WIDTH * 3 / 8 {
+                graph_width = (STAT_WIDTH * 3 / 8).saturating_sub(number_width + 6).max(6);
+            }
+            if name_width + number_width + 6 + graph_width > STAT_WIDTH {
+                name_width = STAT_WIDTH - number_width - 6 - graph_width;
+            } else {
+                graph_width = STAT_WIDTH - number_width - 6 - name_width;
+            }
+        }
+
+        let scale = |count: usize| match count {
+            0 => 0,
+            _ if graph_width > max_change => count,
+            _ => 1 + count * (graph_width - 1) / max_change,
+        };
+
+        let (mut insertions, mut deletions) = (0, 0);
+        for (name, added, deleted, bytes) in &rows {
+            insertions += added;
+            deletions += deleted;
+
+            // Truncate long names from the left, at a directory boundary if
+            // possible.
+            let length = name.chars().count();
+            let name = match length > name_width {
+                false => name.clone(),
+                true => {
+                    let suffix = name
+                        .chars()
+                        .skip(length - (name_width - 3))
+                        .collect::<String>();
+                    match suffix.find('/') {
+                        Some(slash) => format!("...{}", &suffix[slash..]),
+                        None => format!("...{}", suffix),
+                    }
+                }
+            };
+
+            let total = match scale(added + deleted) {
+                1 if *added > 0 && *deleted > 0 => 2,
+                total => total,
+            };
+            let (plus, minus) = match added < deleted {
+                true => (scale(*added), total - scale(*added)),
+                false => (total - scale(*deleted), scale(*deleted)),
+            };
+
+            if let Some((old, new)) = bytes {
+                write!(
+                    &mut self.stdout,
+                    " {:name_width$} | {:>number_width$}",
+                    name,
+                    "Bin",
+                    name_width = name_width,
+                    number_width = number_width,
+                )?;
+                writeln!(&mut self.stdout, " {} -> {} bytes", old, new)?;
+                continue;
+            }
+
+            write!(
+                &mut self.stdout,
+                " {:name_width$} | {:>number_width$}",
+                name,
+                added + deleted,
+                name_width = name_width,
+                number_width = number_width,
+            )?;
+            if plus + minus > 0 {
+                write!(&mut self.stdout, " ")?;
+                self.stdout
+                    .set_color(termcolor::ColorSpec::new().set_fg(Some(termcolor::Color::Green)))?;
+                write!(&mut self.stdout, "{}", "+".repeat(plus))?;
+                self.stdout
+                    .set_color(termcolor::ColorSpec::new().set_fg(Some(termcolor::Color::Red)))?;
+                write!(&mut self.stdout, "{}", "-".repeat(minus))?;
+                self.stdout.reset()?;
+            }
+            writeln!(&mut self.stdout)?;
+        }
+
+        write!(
+            &mut self.stdout,
+            "{}",
+            diff::shortstat(rows.len(), insertions, deletions)
+        )?;
+        writeln!(&mut self.stdout)?;
+        Ok(())
+    }
+}
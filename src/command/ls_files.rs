@@ -0,0 +1,289 @@
+use std::collections::BTreeSet;
+use std::env;
+use std::io;
+use std::io::Write as _;
+use std::path;
+
+use structopt::StructOpt;
+
+use crate::ignore;
+use crate::index;
+use crate::meta;
+use crate::object;
+use crate::prefix;
+use crate::util;
+
+/// List files in the index and, optionally, untracked files in the
+/// workspace, under and relative to the current directory.
+#[derive(StructOpt)]
+pub struct Configuration {
+    /// List files in the index. This is the default if no other listing
+    /// is requested.
+    #[structopt(short, long)]
+    cached: bool,
+
+    /// List index entries with their mode, object id, and stage number,
+    /// including every stage of unmerged paths.
+    #[structopt(short, long)]
+    stage: bool,
+
+    /// List files in the index that are missing from the workspace.
+    #[structopt(short, long)]
+    deleted: bool,
+
+    /// List files in the index whose workspace copy differs, including
+    /// deleted and unmerged files.
+    #[structopt(short, long)]
+    modified: bool,
+
+    /// List untracked files in the workspace.
+    #[structopt(short, long)]
+    others: bool,
+
+    /// Skip untracked files excluded by `.gitignore`, `.git/info/exclude`,
+    /// or `core.excludesFile`.
+    #[structopt(long)]
+    exclude_standard: bool,
+
+    /// Show directories outside the sparse-checkout cone as single `dir/`
+    /// entries, instead of expanding them.
+    #[structopt(long)]
+    sparse: bool,
+
+    /// After each file in the index, show its stat information and flags,
+    /// for diagnosing index problems.
+    #[structopt(long)]
+    debug: bool,
+}
+
+impl Configuration {
+    pub fn run(self) -> anyhow::Result<()> {
+        let (repository, prefix) = crate::Repository::discover(&env::current_dir()?)?;
+        let ls_files = LsFiles {
+            prefix,
+            cached: self.cached || !(self.stage || self.deleted || self.modified || self.others),
+            stage: self.stage,
+            debug: self.debug,
+            deleted: self.deleted,
+            modified: self.modified,
+            others: self.others,
+            check_stat: repository
+                .config()?
+                .parse("core.checkStat")?
+                .unwrap_or_default(),
+            ignore: match self.exclude_standard {
+                true => Some(ignore::Ignore::standard(
+                    repository.root(),
+                    &repository.config()?,
+                )?),
+                false => None,
+            },
+            index: match self.sparse {
+                true => repository.sparse_index()?,
+                false => repository.index()?,
+            },
+            workspace: repository.workspace()?,
+        };
+        ls_files.run()
+    }
+}
+
+struct LsFiles {
+    prefix: prefix::Prefix,
+    cached: bool,
+    stage: bool,
+    debug: bool,
+    deleted: bool,
+    modified: bool,
+    others: bool,
+    check_stat: meta::CheckStat,
+    /// Standard exclude patterns, if requested.
+    ignore: Option<ignore::Ignore>,
+    index: crate::Index,
+    workspace: crate::Workspace,
+}
+
+impl LsFiles {
+    fn run(mut self) -> anyhow::Result<()> {
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+
+        // Like `git`, untracked files come first.
+        if self.others {
+            // Patterns from the directories above the current one still
+            // apply below it.
+            let start = self.prefix.path().to_path_buf();
+            if let Some(ignore) = &mut self.ignore {
+                for directory in start.ancestors().skip(1).collect::<Vec<_>>().iter().rev() {
+                    ignore.load(self.workspace.root(), directory)?;
+                }
+            }
+
+            let mut untracked = BTreeSet::new();
+            self.walk(&start, &mut untracked)?;
+            for path in &untracked {
+                writeln!(stdout, "{}", self.prefix.display(path).display())?;
+            }
+        }
+
+        let mut entries = self
+            .index
+            .entries()
+            .map(|entry| (entry.path(), 0, entry))
+            .chain(self.index.conflicts().flat_map(|(path, stages)| {
+                stages
+                    .iter()
+                    .zip(1..)
+                    .filter_map(move |(entry, stage)| Some((path, stage, entry.as_ref()?)))
+            }))
+            .filter(|(path, _, _)| self.prefix.contains(path))
+            .collect::<Vec<_>>();
+        entries.sort_by(|(a, a_stage, _), (b, b_stage, _)| {
+            util::PathBuf(a.to_path_buf())
+                .cmp(&util::PathBuf(b.to_path_buf()))
+                .then(a_stage.cmp(b_stage))
+        });
+
+        // With `--stage`, every listing shows the entry's details.
+        let mut show = |path: &path::Path, stage: u8, entry: &index::Entry| -> io::Result<()> {
+            match self.stage {
+                true => {
+                    let mode = entry.metadata().mode();
+                    writeln!(
+                        stdout,
+                        "{} {} {}\t{}",
+                        mode.as_str(),
+                        entry.id(),
+                        stage,
+                        self.prefix.display(path).display()
+                    )?;
+                }
+                false => writeln!(stdout, "{}", self.prefix.display(path).display())?,
+            }
+            if self.debug {
+                write!(stdout, "{}", debug(entry))?;
+            }
+            Ok(())
+        };
+
+        for (path, stage, entry) in entries {
+            if self.cached || self.stage {
+                show(path, stage, entry)?;
+            }
+
+            // Files outside the sparse-checkout cone are missing on purpose.
+            if entry.skip_worktree() || !(self.deleted || self.modified) {
+                continue;
+            }
+
+            let metadata = match self.workspace.metadata(path) {
+                Ok(metadata) => Some(metadata),
+                Err(error)
+                    if error.kind() == io::ErrorKind::NotFound
+                        || error.kind() == io::ErrorKind::NotADirectory =>
+                {
+                    None
+                }
+                Err(error) => return Err(error.into()),
+            };
+
+            if self.deleted && metadata.is_none() {
+                show(path, stage, entry)?;
+            }
+            if self.modified && (stage > 0 || self.is_modified(entry, metadata)?) {
+                show(path, stage, entry)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether the workspace copy of `entry`, with `metadata`, differs from
+    /// the index.
+    fn is_modified(
+        &self,
+        entry: &index::Entry,
+        metadata: Option<meta::Metadata>,
+    ) -> anyhow::Result<bool> {
+        let old = entry.metadata();
+        let new = match metadata {
+            None => return Ok(true),
+            Some(metadata) => self.workspace.normalize(metadata, old.mode),
+        };
+
+        // A size of zero may have been smudged by a racy write.
+        if new.mode.is_directory()
+            || new.mode != old.mode
+            || (new.size != old.size && old.size != 0)
+        {
+            return Ok(true);
+        }
+        if new.is_stat_clean(old, self.check_stat) && !entry.is_racy(self.index.timestamp()) {
+            return Ok(false);
+        }
+
+        let id = self
+            .workspace
+            .read(entry.path())
+            .map(object::Blob::new)
+            .map(crate::Object::Blob)
+            .map(|object| object.to_bytes())
+            .map(|bytes| object::Id::hash(&bytes))?;
+        Ok(id != *entry.id())
+    }
+
+    /// Collect untracked files under `directory`, without descending into
+    /// ignored directories.
+    fn walk(
+        &mut self,
+        directory: &path::Path,
+        untracked: &mut BTreeSet<util::PathBuf>,
+    ) -> anyhow::Result<()> {
+        if let Some(ignore) = &mut self.ignore {
+            ignore.load(self.workspace.root(), directory)?;
+        }
+
+        for entry in self.workspace.walk_list(directory)? {
+            let entry = entry?;
+            let relative = entry.relative_path();
+            let is_directory = entry.metadata().mode.is_directory();
+
+            if self.index.contains_file(relative)
+                || self
+                    .ignore
+                    .as_ref()
+                    .is_some_and(|ignore| ignore.is_ignored(relative, is_directory))
+            {
+                continue;
+            }
+
+            match is_directory {
+                true => self.walk(relative, untracked)?,
+                false => {
+                    untracked.insert(util::PathBuf(relative.to_path_buf()));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Stat information and flags of `entry`, in the format of
+/// `git ls-files --debug`.
+pub(crate) fn debug(entry: &index::Entry) -> String {
+    let metadata = entry.metadata();
+    format!(
+        "  ctime: {}:{}\n  mtime: {}:{}\n  dev: {}\tino: {}\n  uid: {}\tgid: {}\n  size: {}\tflags: {:x}\n",
+        metadata.ctime,
+        metadata.ctime_nsec,
+        metadata.mtime,
+        metadata.mtime_nsec,
+        metadata.dev,
+        metadata.ino,
+        metadata.uid,
+        metadata.gid,
+        metadata.size,
+        entry.flags(),
+    )
+}
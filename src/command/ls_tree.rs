@@ -0,0 +1,100 @@
+use std::env;
+use std::io;
+use std::path;
+
+use anyhow::anyhow;
+use structopt::StructOpt;
+
+use crate::object;
+use crate::revision;
+
+/// List the contents of a tree object, like `git ls-tree`.
+#[derive(StructOpt)]
+pub struct Configuration {
+    /// Recurse into subtrees, listing only the files inside them.
+    #[structopt(short)]
+    recursive: bool,
+
+    /// Also list subtrees when recursing.
+    #[structopt(short = "t")]
+    trees: bool,
+
+    /// List only paths, without modes, types, or ids.
+    #[structopt(long, alias = "name-status")]
+    name_only: bool,
+
+    /// Tree, or commit or tag pointing to one, whose contents to list.
+    tree_ish: String,
+}
+
+impl Configuration {
+    pub fn run(self) -> anyhow::Result<()> {
+        let root = env::current_dir()?;
+        let repository = crate::Repository::new(root);
+        let database = repository.database()?;
+        let id = self
+            .tree_ish
+            .parse::<revision::Revision>()?
+            .resolve(&repository)?
+            .ok_or_else(|| anyhow!("Not a valid object name {}", self.tree_ish))?;
+
+        let ls_tree = LsTree {
+            database,
+            recursive: self.recursive,
+            trees: self.trees || !self.recursive,
+            name_only: self.name_only,
+        };
+
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        ls_tree.run(
+            &id.peel_to_tree(&ls_tree.database)?,
+            path::Path::new(""),
+            &mut stdout,
+        )
+    }
+}
+
+struct LsTree {
+    database: crate::Database,
+    recursive: bool,
+    /// Whether to list subtrees themselves.
+    trees: bool,
+    name_only: bool,
+}
+
+impl LsTree {
+    fn run(
+        &self,
+        tree: &object::Id,
+        prefix: &path::Path,
+        writer: &mut dyn io::Write,
+    ) -> anyhow::Result<()> {
+        for node in &self.database.load_tree(tree)? {
+            let path = prefix.join(&node.path);
+            let directory = node.mode.is_directory();
+
+            if !directory || self.trees {
+                match self.name_only {
+                    true => writeln!(writer, "{}", path.display())?,
+                    false => writeln!(
+                        writer,
+                        "{:0>6} {} {}\t{}",
+                        node.mode.as_str(),
+                        match directory {
+                            true => object::Type::Tree,
+                            false => object::Type::Blob,
+                        },
+                        node.id,
+                        path.display(),
+                    )?,
+                }
+            }
+
+            if directory && self.recursive {
+                self.run(&node.id, &path, writer)?;
+            }
+        }
+        Ok(())
+    }
+}
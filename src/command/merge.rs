@@ -0,0 +1,264 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::io::Write as _;
+use std::path;
+
+use anyhow::anyhow;
+use structopt::StructOpt;
+
+use crate::diff;
+use crate::gc;
+use crate::merge;
+use crate::meta;
+use crate::migration;
+use crate::object;
+use crate::references;
+use crate::revision;
+use crate::state;
+
+/// Join another branch or commit into the current branch, creating a merge
+/// commit unless it can be fast-forwarded.
+#[derive(StructOpt)]
+pub struct Configuration {
+    /// Defaults to `user.name`.
+    #[structopt(long, env = "GIT_AUTHOR_NAME")]
+    author_name: Option<String>,
+
+    /// Defaults to `user.email`.
+    #[structopt(long, env = "GIT_AUTHOR_EMAIL")]
+    author_email: Option<String>,
+
+    /// Defaults to the current time.
+    #[structopt(
+        long,
+        env = "GIT_AUTHOR_DATE",
+        parse(try_from_str = object::Person::parse_time)
+    )]
+    author_date: Option<chrono::DateTime<chrono::FixedOffset>>,
+
+    /// Defaults to `user.name`.
+    #[structopt(long, env = "GIT_COMMITTER_NAME")]
+    committer_name: Option<String>,
+
+    /// Defaults to `user.email`.
+    #[structopt(long, env = "GIT_COMMITTER_EMAIL")]
+    committer_email: Option<String>,
+
+    /// Defaults to the current time.
+    #[structopt(
+        long,
+        env = "GIT_COMMITTER_DATE",
+        parse(try_from_str = object::Person::parse_time)
+    )]
+    committer_date: Option<chrono::DateTime<chrono::FixedOffset>>,
+
+    /// Message for the merge commit.
+    #[structopt(short, long)]
+    message: Option<String>,
+
+    /// Branch name or revision to merge.
+    target: String,
+}
+
+impl Configuration {
+    pub fn run(self) -> anyhow::Result<()> {
+        let root = env::current_dir()?;
+        let repository = crate::Repository::new(root);
+
+        if repository.audit()?.contains(&state::Leftover::Merge) {
+            return Err(anyhow!(
+                "You have not concluded your merge (MERGE_HEAD exists).\n\
+                 Please, commit your changes before you merge."
+            ));
+        }
+
+        let theirs = self
+            .target
+            .parse::<revision::Revision>()?
+            .resolve(&repository)?
+            .ok_or_else(|| anyhow!("{} - not something we can merge", self.target))?;
+
+        let config = repository.config()?;
+        let now = chrono::Local::now().into();
+        let (name, email) = config.identity(self.author_name, self.author_email)?;
+        let author = object::Person::new(name, email, self.author_date.unwrap_or(now));
+        let (name, email) = config.identity(self.committer_name, self.committer_email)?;
+        let committer = object::Person::new(name, email, self.committer_date.unwrap_or(now));
+
+        let merge = Merge {
+            git: repository.root().join(".git"),
+            check_stat: config.parse("core.checkStat")?.unwrap_or_default(),
+            database: repository.database()?,
+            index: repository.index()?,
+            references: repository.references(),
+            workspace: repository.workspace()?,
+            author,
+            committer,
+            message: self.message,
+        };
+        merge.run(theirs, &self.target)?;
+        gc::auto(&repository)
+    }
+}
+
+struct Merge {
+    git: path::PathBuf,
+    check_stat: meta::CheckStat,
+    database: crate::Database,
+    index: crate::Index,
+    references: crate::References,
+    workspace: crate::Workspace,
+    author: object::Person,
+    committer: object::Person,
+    message: Option<String>,
+}
+
+impl Merge {
+    fn run(mut self, theirs: object::Id, target: &str) -> anyhow::Result<()> {
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        if self.index.is_conflicted() {
+            return Err(anyhow!(
+                "Merging is not possible because you have unmerged files."
+            ));
+        }
+
+        let theirs = theirs.peel_to_commit(&self.database)?;
+
+        let ours = match self.references.read_head()? {
+            Some(ours) => ours,
+            // Nothing to merge into, so just take their history.
+            None => return self.fast_forward(None, theirs, target),
+        };
+
+        let base = merge::base(&self.database, &ours, &theirs)?;
+        if base == Some(theirs) {
+            writeln!(stdout, "Already up to date.")?;
+            return Ok(());
+        } else if base == Some(ours) {
+            return self.fast_forward(Some(ours), theirs, target);
+        }
+
+        let base_tree = base
+            .map(|base| base.peel_to_tree(&self.database))
+            .transpose()?;
+        let ours_tree = ours.peel_to_tree(&self.database)?;
+        let outcome = merge::trees(
+            &self.database,
+            base_tree.as_ref(),
+            &ours_tree,
+            &theirs.peel_to_tree(&self.database)?,
+            "HEAD",
+            target,
+        )?;
+
+        let changes = diff::tree::diff_files(
+            &diff::tree::flatten(&self.database, &ours_tree)?,
+            &outcome.files,
+        );
+        self.migrate(changes)?;
+
+        for path in &outcome.merged {
+            writeln!(stdout, "Auto-merging {}", path.display())?;
+        }
+
+        let message = self.message.take().unwrap_or_else(|| {
+            match self
+                .references
+                .read(&format!("{}{}", crate::References::HEADS, target))
+            {
+                Ok(Some(_)) => format!("Merge branch '{}'\n", target),
+                _ => format!("Merge commit '{}'\n", target),
+            }
+        });
+
+        if !outcome.is_clean() {
+            for (path, conflict) in &outcome.conflicts {
+                writeln!(stdout, "{}", conflict.describe(path, "HEAD", target))?;
+                self.index.insert_conflict(
+                    path.to_path_buf(),
+                    conflict
+                        .stages()
+                        .map(|stage| stage.map(|entry| (entry.id, entry.mode))),
+                );
+            }
+            self.index.commit()?;
+
+            // Record the merge so that `grit commit` can conclude it.
+            self.references
+                .store()
+                .write("MERGE_HEAD", &references::Target::Direct(theirs))?;
+            fs::write(self.git.join("MERGE_MSG"), &message)?;
+
+            return Err(anyhow!(
+                "Automatic merge failed; fix conflicts and then commit the result."
+            ));
+        }
+
+        let tree = self.index.write_tree(&self.database)?;
+        self.index.commit()?;
+
+        let commit = object::Commit::new(
+            tree,
+            vec![ours, theirs],
+            self.author,
+            self.committer.clone(),
+            message,
+        );
+        let id = self.database.store(&crate::Object::Commit(commit))?;
+        self.references.update_head(
+            &id,
+            &self.committer,
+            &format!("merge {}: Merge made by the 'three-way' strategy.", target),
+        )?;
+
+        writeln!(stdout, "Merge made by the 'three-way' strategy.")?;
+        Ok(())
+    }
+
+    fn fast_forward(
+        mut self,
+        ours: Option<object::Id>,
+        theirs: object::Id,
+        target: &str,
+    ) -> anyhow::Result<()> {
+        let old = ours
+            .map(|ours| ours.peel_to_tree(&self.database))
+            .transpose()?;
+        let new = theirs.peel_to_tree(&self.database)?;
+        let changes = diff::tree::diff(&self.database, old.as_ref(), Some(&new))?;
+        self.migrate(changes)?;
+        self.index.commit()?;
+        self.references.update_head(
+            &theirs,
+            &self.committer,
+            &format!("merge {}: Fast-forward", target),
+        )?;
+
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        if let Some(ours) = ours {
+            writeln!(
+                stdout,
+                "Updating {}..{}",
+                &ours.to_string()[..7],
+                &theirs.to_string()[..7],
+            )?;
+        }
+        writeln!(stdout, "Fast-forward")?;
+        Ok(())
+    }
+
+    fn migrate(&mut self, changes: diff::tree::Changes) -> anyhow::Result<()> {
+        migration::Migration::new(
+            &self.database,
+            &mut self.index,
+            &self.workspace,
+            self.check_stat,
+            changes,
+        )
+        .operation(migration::Operation::Merge)
+        .apply()
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::BTreeSet;
+use std::env;
+use std::io;
+use std::io::Write as _;
+
+use anyhow::anyhow;
+use structopt::StructOpt;
+
+use crate::merge;
+use crate::object;
+use crate::revision;
+
+/// Merge two commits without touching the index or workspace, printing the
+/// id of the merged tree.
+///
+/// If there are conflicts, the tree contains conflicted files with their
+/// conflict markers, and is followed by each conflicted stage as
+/// `<mode> <id> <stage>\t<path>`, a blank line, and messages describing the
+/// merge.
+#[derive(StructOpt)]
+pub struct Configuration {
+    /// Only list the names of conflicted files, without their stages.
+    #[structopt(long)]
+    name_only: bool,
+
+    /// Don't print messages after the conflicted files.
+    #[structopt(long)]
+    no_messages: bool,
+
+    /// Use this commit or tree as the merge base instead of computing one.
+    /// The branches may then be any trees.
+    #[structopt(long)]
+    merge_base: Option<String>,
+
+    /// Revision to merge into.
+    ours: String,
+
+    /// Revision to merge in.
+    theirs: String,
+}
+
+impl Configuration {
+    pub fn run(self) -> anyhow::Result<()> {
+        let root = env::current_dir()?;
+        let repository = crate::Repository::new(root);
+        let database = repository.database()?;
+
+        let resolve = |name: &str| {
+            name.parse::<revision::Revision>()?
+                .resolve(&repository)?
+                .ok_or_else(|| anyhow!("Not a valid object name {}", name))
+        };
+
+        let ours = resolve(&self.ours)?;
+        let theirs = resolve(&self.theirs)?;
+        let base = match &self.merge_base {
+            Some(base) => Some(resolve(base)?),
+            None => merge::base(
+                &database,
+                &ours.peel_to_commit(&database)?,
+                &theirs.peel_to_commit(&database)?,
+            )?,
+        };
+
+        let merge_tree = MergeTree {
+            database,
+            name_only: self.name_only,
+            messages: !self.no_messages,
+        };
+        merge_tree.run(base, (&self.ours, ours), (&self.theirs, theirs))
+    }
+}
+
+struct MergeTree {
+    database: crate::Database,
+    name_only: bool,
+    messages: bool,
+}
+
+impl MergeTree {
+    fn run(
+        &self,
+        base: Option<object::Id>,
+        (ours_label, ours): (&str, object::Id),
+        (theirs_label, theirs): (&str, object::Id),
+    ) -> anyhow::Result<()> {
+        let base = base
+            .map(|base| base.peel_to_tree(&self.database))
+            .transpose()?;
+        let outcome = merge::trees(
+            &self.database,
+            base.as_ref(),
+            &ours.peel_to_tree(&self.database)?,
+            &theirs.peel_to_tree(&self.database)?,
+            ours_label,
+            theirs_label,
+        )?;
+
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        writeln!(stdout, "{}", outcome.write_tree(&self.database)?)?;
+        if outcome.is_clean() {
+            return Ok(());
+        }
+
+        for (path, conflict) in &outcome.conflicts {
+            if self.name_only {
+                writeln!(stdout, "{}", path.display())?;
+                continue;
+            }
+            for (stage, entry) in conflict.stages().iter().enumerate() {
+                if let Some(entry) = entry {
+                    writeln!(
+                        stdout,
+                        "{} {} {}\t{}",
+                        entry.mode.as_str(),
+                        entry.id,
+                        stage + 1,
+                        path.display(),
+                    )?;
+                }
+            }
+        }
+
+        if self.messages {
+            writeln!(stdout)?;
+            let paths = outcome
+                .merged
+                .iter()
+                .chain(outcome.conflicts.keys())
+                .collect::<BTreeSet<_>>();
+            for path in paths {
+                if outcome.merged.contains(path) {
+                    writeln!(stdout, "Auto-merging {}", path.display())?;
+                }
+                if let Some(conflict) = outcome.conflicts.get(path) {
+                    writeln!(
+                        stdout,
+                        "{}",
+                        conflict.describe(path, ours_label, theirs_label)
+                    )?;
+                }
+            }
+        }
+
+        Err(anyhow!("Merge produced conflicts"))
+    }
+}
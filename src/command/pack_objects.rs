@@ -0,0 +1,48 @@
+use std::env;
+use std::io;
+use std::io::BufRead as _;
+use std::io::Write as _;
+
+use anyhow::anyhow;
+use structopt::StructOpt;
+
+use crate::object;
+
+/// Write a packfile containing the object ids read from standard input,
+/// one per line.
+#[derive(StructOpt)]
+pub struct Configuration {
+    /// Write the packfile to standard output instead of `.git/objects/pack`.
+    #[structopt(long)]
+    stdout: bool,
+}
+
+impl Configuration {
+    pub fn run(self) -> anyhow::Result<()> {
+        let root = env::current_dir()?;
+        let repository = crate::Repository::new(root);
+        let database = repository.database()?;
+
+        let mut ids = Vec::new();
+        for line in io::stdin().lock().lines() {
+            let line = line?;
+            // Like `git`, ignore anything after the id, such as a path.
+            match line.split_whitespace().next() {
+                None => continue,
+                Some(id) if id.len() == 40 => ids.push(id.parse::<object::Id>()?),
+                Some(id) => return Err(anyhow!("Expected object id, but found `{}`", id)),
+            }
+        }
+
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        if self.stdout {
+            let packfile = database.build_pack(&ids)?;
+            stdout.write_all(&packfile.pack)?;
+        } else {
+            writeln!(stdout, "{}", database.pack(&ids)?)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,495 @@
+use std::collections::HashMap;
+use std::env;
+use std::io;
+
+use anyhow::anyhow;
+use structopt::StructOpt;
+
+use crate::merge;
+use crate::object;
+use crate::references;
+use crate::refspec;
+use crate::transport;
+
+/// Update references in a remote repository, sending the objects they need.
+///
+/// Each refspec names a local reference and, after a `:`, the remote
+/// reference to update, which defaults to the same name. A leading `+`
+/// allows updates that aren't fast-forwards, like `--force`, and an empty
+/// local reference, like `:feature`, deletes the remote one. Without
+/// refspecs, pushes the current branch to the remote branch of the same
+/// name. A pattern like `refs/heads/*:refs/heads/*` pushes every matching
+/// reference. Remote-tracking branches are updated to match what was pushed.
+///
+/// With `--mirror`, or by default for remotes with `remote.<name>.mirror`,
+/// every reference is force-pushed to the same name, and remote references
+/// that no longer exist locally are deleted.
+#[derive(StructOpt)]
+pub struct Configuration {
+    /// Allow updates that discard commits on the remote.
+    #[structopt(short, long)]
+    force: bool,
+
+    /// Delete remote references matched by a pattern refspec whose local
+    /// references no longer exist.
+    #[structopt(long)]
+    prune: bool,
+
+    /// Make every remote reference match the local one of the same name,
+    /// like `--force --prune 'refs/*:refs/*'`.
+    #[structopt(long, conflicts_with = "refspecs")]
+    mirror: bool,
+
+    /// Name of the remote to push to.
+    #[structopt(default_value = "origin")]
+    remote: String,
+
+    /// References to push, as `[+]<local>[:<remote>]`, or `:<remote>` to
+    /// delete.
+    refspecs: Vec<String>,
+}
+
+impl Configuration {
+    pub fn run(self) -> anyhow::Result<()> {
+        let root = env::current_dir()?;
+        let repository = crate::Repository::open(root);
+        let config = repository.config()?;
+        let database = repository.database()?;
+        let references = repository.references();
+
+        let url = config
+            .get(&format!("remote.{}.url", self.remote))
+            .ok_or_else(|| anyhow!("'{}' does not appear to be a remote", self.remote))?;
+
+        let mirror = self.mirror
+            || (self.refspecs.is_empty()
+                && config
+                    .get_bool(&format!("remote.{}.mirror", self.remote))?
+                    .unwrap_or(false));
+        let pruning = self.prune || mirror;
+
+        let refspecs = match self.refspecs.is_empty() {
+            true if mirror => vec![refspec::Refspec::mirror().to_string()],
+            true => {
+                let branch = references
+                    .current_branch()?
+                    .ok_or_else(|| anyhow!("You are not currently on a branch"))?;
+                vec![branch]
+            }
+            false => self.refspecs,
+        };
+
+        let remote = transport::Remote::new(url, &config)?;
+        let advertisement = remote.advertise_push()?;
+        if !advertisement.supports("ofs-delta") {
+            return Err(anyhow!("'{}' does not accept offset deltas", url));
+        }
+
+        let mut pushes = Vec::new();
+        for refspec in &refspecs {
+            pushes.extend(resolve(&references, refspec, &advertisement, self.force)?);
+        }
+
+        if pruning {
+            for refspec in &refspecs {
+                pushes.extend(prune(&references, refspec, &advertisement, self.force)?);
+            }
+        }
+
+        let mut lines = Vec::new();
+        let mut updates = Vec::new();
+        for push in &mut pushes {
+            push.old = advertisement
+                .refs
+                .iter()
+                .find(|(name, _)| *name == push.destination)
+                .map(|(_, id)| *id);
+            let status = push.status(&database)?;
+            if let Status::New | Status::FastForward | Status::Forced | Status::Deleted = status {
+                updates.push(references::Update {
+                    name: push.destination.clone(),
+                    old: push.old,
+                    new: push.new,
+                });
+            }
+            lines.push(status);
+        }
+
+        if !updates.is_empty() {
+            let mut haves = Vec::new();
+            for (_, id) in &advertisement.refs {
+                if database.contains(id)? {
+                    haves.push(*id);
+                }
+            }
+            let wants = updates
+                .iter()
+                .filter_map(|update| update.new)
+                .collect::<Vec<_>>();
+            let (objects, mut bases) = transport::missing(&database, &wants, &haves)?;
+            if advertisement.supports("no-thin") {
+                bases.clear();
+            }
+
+            let pack = database.build_thin_pack(&objects, &bases)?;
+            let reported = remote
+                .push(&advertisement, &updates, &pack.pack, &mut io::stderr())?
+                .into_iter()
+                .collect::<HashMap<_, _>>();
+
+            for (push, status) in pushes.iter().zip(&mut lines) {
+                match reported.get(&push.destination) {
+                    Some(Ok(())) => (),
+                    Some(Err(reason)) => *status = Status::RemoteRejected(reason.clone()),
+                    None if updates.iter().any(|update| update.name == push.destination) => {
+                        *status = Status::RemoteRejected(String::from("no status reported"))
+                    }
+                    None => (),
+                }
+            }
+
+            let fetch =
+                refspec::Refspecs::parse(config.get_all(&format!("remote.{}.fetch", self.remote)))?;
+            let tracking = tracking(&fetch, &references, &pushes, &lines)?;
+            let committer = config.committer()?;
+            references.transaction(&tracking, &committer, "update by push")?;
+        }
+
+        eprintln!("To {}", url);
+        if lines.iter().all(|status| *status == Status::UpToDate) {
+            eprintln!("Everything up-to-date");
+            return Ok(());
+        }
+
+        let mut failed = false;
+        for (push, status) in pushes.iter().zip(&lines) {
+            failed |= matches!(status, Status::Rejected(_) | Status::RemoteRejected(_));
+            if *status != Status::UpToDate {
+                eprintln!(" {}", push.describe(status));
+            }
+        }
+
+        match failed {
+            true => Err(anyhow!("failed to push some refs to '{}'", url)),
+            false => Ok(()),
+        }
+    }
+}
+
+/// Parse `refspec` as `[+]<local>[:<remote>]`, expanding both sides to full
+/// reference names. A pattern like `refs/heads/*` pushes every matching
+/// local reference, and an empty source deletes the remote reference.
+fn resolve(
+    references: &crate::References,
+    refspec: &str,
+    advertisement: &transport::Advertisement,
+    force: bool,
+) -> anyhow::Result<Vec<Push>> {
+    let refspec::Refspec {
+        force: forced,
+        source,
+        destination,
+    } = refspec.parse()?;
+    let force = force || forced;
+
+    if source.is_empty() {
+        let destination = destination.unwrap_or_default();
+        // Like `git`, a short name is expanded against the remote's
+        // references rather than ours.
+        let (name, old) = crate::References::expansions(&destination)
+            .find_map(|candidate| {
+                advertisement
+                    .refs
+                    .iter()
+                    .find(|(name, _)| *name == candidate)
+            })
+            .ok_or_else(|| {
+                anyhow!(
+                    "unable to delete '{}': remote ref does not exist",
+                    destination
+                )
+            })?;
+        return Ok(vec![Push {
+            source: String::new(),
+            destination: name.clone(),
+            old: Some(*old),
+            new: None,
+            force,
+        }]);
+    }
+
+    if source.contains('*') {
+        let refspec = refspec::Refspec {
+            force,
+            destination: Some(destination.unwrap_or_else(|| source.clone())),
+            source,
+        };
+        let prefix = refspec.source.split('*').next().unwrap_or_default();
+        let mut pushes = Vec::new();
+        for reference in references.iter_prefix(prefix)? {
+            let (name, new) = reference?;
+            if let Some(destination) = refspec.map(&name) {
+                pushes.push(Push {
+                    source: name,
+                    destination,
+                    old: None,
+                    new: Some(new),
+                    force,
+                });
+            }
+        }
+        return Ok(pushes);
+    }
+
+    let source = references
+        .expand(&source)?
+        .ok_or_else(|| anyhow!("src refspec {} does not match any", source))?;
+    let new = references
+        .read(&source)?
+        .ok_or_else(|| anyhow!("src refspec {} does not match any", source))?;
+
+    // Like `git`, a short destination is taken to be of the same kind as the
+    // source, e.g. a branch for `main:feature`.
+    let destination = match destination {
+        None if source == "HEAD" => match references.store().read("HEAD")? {
+            Some(references::Target::Symbolic(branch)) => branch,
+            _ => return Err(anyhow!("You are not currently on a branch")),
+        },
+        None => source.clone(),
+        Some(destination) if destination.starts_with("refs/") => destination,
+        Some(destination) if source.starts_with(crate::References::TAGS) => {
+            format!("{}{}", crate::References::TAGS, destination)
+        }
+        Some(destination) if source.starts_with(crate::References::HEADS) || source == "HEAD" => {
+            format!("{}{}", crate::References::HEADS, destination)
+        }
+        Some(destination) => {
+            return Err(anyhow!(
+                "The destination you provided is not a full refname: {}",
+                destination
+            ))
+        }
+    };
+
+    Ok(vec![Push {
+        source,
+        destination,
+        old: None,
+        new: Some(new),
+        force,
+    }])
+}
+
+/// Deletions of the remote references that the pattern `refspec` maps
+/// from local references that no longer exist, for `--prune`.
+fn prune(
+    references: &crate::References,
+    refspec: &str,
+    advertisement: &transport::Advertisement,
+    force: bool,
+) -> anyhow::Result<Vec<Push>> {
+    let refspec::Refspec {
+        force: forced,
+        source,
+        destination,
+    } = refspec.parse()?;
+    if !source.contains('*') {
+        return Ok(Vec::new());
+    }
+    let refspec = refspec::Refspec {
+        force: force || forced,
+        destination: Some(destination.unwrap_or_else(|| source.clone())),
+        source,
+    };
+
+    let mut pushes = Vec::new();
+    for (name, _) in &advertisement.refs {
+        if let Some(source) = refspec.unmap(name) {
+            if references.read(&source)?.is_none() {
+                pushes.push(Push {
+                    source,
+                    destination: name.clone(),
+                    old: None,
+                    new: None,
+                    force: refspec.force,
+                });
+            }
+        }
+    }
+    Ok(pushes)
+}
+
+/// A remote reference to be pointed at a local reference's id.
+struct Push {
+    source: String,
+    destination: String,
+    /// Current value on the remote, if it exists.
+    old: Option<object::Id>,
+    /// `None` to delete the remote reference.
+    new: Option<object::Id>,
+    force: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Status {
+    UpToDate,
+    New,
+    FastForward,
+    Forced,
+    Deleted,
+    /// Refused locally, for the given reason.
+    Rejected(&'static str),
+    /// Refused by the remote, for the given reason.
+    RemoteRejected(String),
+}
+
+impl Push {
+    fn status(&self, database: &crate::Database) -> anyhow::Result<Status> {
+        let new = match (self.old, self.new) {
+            (None, None) => return Ok(Status::UpToDate),
+            (Some(_), None) => return Ok(Status::Deleted),
+            (_, Some(new)) => new,
+        };
+        let old = match self.old {
+            None => return Ok(Status::New),
+            Some(old) if old == new => return Ok(Status::UpToDate),
+            Some(old) => old,
+        };
+
+        if self.destination.starts_with(crate::References::TAGS) {
+            return Ok(match self.force {
+                true => Status::Forced,
+                false => Status::Rejected("already exists"),
+            });
+        }
+
+        // Commits we've never seen can't be ancestors of ours.
+        if !database.contains(&old)? {
+            return Ok(match self.force {
+                true => Status::Forced,
+                false => Status::Rejected("fetch first"),
+            });
+        }
+
+        let new = new.peel_to_commit(database)?;
+        let old = old.peel_to_commit(database)?;
+        Ok(
+            match (merge::base(database, &old, &new)? == Some(old), self.force) {
+                (true, _) => Status::FastForward,
+                (false, true) => Status::Forced,
+                (false, false) => Status::Rejected("non-fast-forward"),
+            },
+        )
+    }
+
+    /// Summarize this update like `git push`.
+    fn describe(&self, status: &Status) -> String {
+        let short = |id: &object::Id| id.to_string()[..7].to_owned();
+        let old = self.old.as_ref().map(short).unwrap_or_default();
+        let new = self.new.as_ref().map(short).unwrap_or_default();
+        let (flag, summary, reason) = match status {
+            Status::Deleted => {
+                return format!("- {:<17} {}", "[deleted]", shorten(&self.destination))
+            }
+            Status::UpToDate => ('=', String::from("[up to date]"), String::new()),
+            Status::New if self.destination.starts_with(crate::References::TAGS) => {
+                ('*', String::from("[new tag]"), String::new())
+            }
+            Status::New if self.destination.starts_with(crate::References::HEADS) => {
+                ('*', String::from("[new branch]"), String::new())
+            }
+            Status::New => ('*', String::from("[new reference]"), String::new()),
+            Status::FastForward => (' ', format!("{}..{}", old, new), String::new()),
+            Status::Forced => (
+                '+',
+                format!("{}...{}", old, new),
+                String::from(" (forced update)"),
+            ),
+            Status::Rejected(reason) => ('!', String::from("[rejected]"), format!(" ({})", reason)),
+            Status::RemoteRejected(reason) => (
+                '!',
+                String::from("[remote rejected]"),
+                format!(" ({})", reason),
+            ),
+        };
+        format!(
+            "{} {:<17} {} -> {}{}",
+            flag,
+            summary,
+            shorten(&self.source),
+            shorten(&self.destination),
+            reason,
+        )
+    }
+}
+
+/// Updates to the remote-tracking references that the `fetch` refspecs map
+/// each successfully pushed reference to.
+fn tracking(
+    fetch: &refspec::Refspecs,
+    references: &crate::References,
+    pushes: &[Push],
+    statuses: &[Status],
+) -> anyhow::Result<Vec<references::Update>> {
+    let mut updates = Vec::new();
+    for (push, status) in pushes.iter().zip(statuses) {
+        if let Status::New | Status::FastForward | Status::Forced | Status::Deleted = status {
+            for (_, name) in fetch.map(&push.destination) {
+                let old = references.read(&name)?;
+                if old != push.new {
+                    updates.push(references::Update {
+                        name,
+                        old,
+                        new: push.new,
+                    });
+                }
+            }
+        }
+    }
+    Ok(updates)
+}
+
+/// Shorten a full reference name for display, like `main` for
+/// `refs/heads/main`.
+fn shorten(name: &str) -> &str {
+    [crate::References::HEADS, crate::References::TAGS]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name)
+}
+
+#[test]
+fn pattern_prune() -> anyhow::Result<()> {
+    let repository = crate::Repository::memory(std::path::PathBuf::new());
+    let database = repository.database()?;
+    let references = repository.references();
+
+    let blob = database.store(&crate::Object::Blob(object::Blob::new(Vec::new())))?;
+    references.create_branch("kept", &blob)?;
+    let advertisement = transport::Advertisement {
+        refs: ["refs/heads/kept", "refs/heads/gone", "refs/tags/gone"]
+            .iter()
+            .map(|name| (name.to_string(), blob))
+            .collect(),
+        capabilities: Vec::new(),
+    };
+
+    let pushes = |refspec: &str| -> anyhow::Result<Vec<(String, Option<object::Id>, bool)>> {
+        Ok(prune(&references, refspec, &advertisement, false)?
+            .into_iter()
+            .map(|push| (push.destination, push.new, push.force))
+            .collect())
+    };
+
+    // Only remote references the pattern maps from missing local ones.
+    assert_eq!(
+        pushes("refs/heads/*")?,
+        vec![(String::from("refs/heads/gone"), None, false)],
+    );
+    assert_eq!(
+        pushes("+refs/heads/*:refs/tags/*")?,
+        vec![(String::from("refs/tags/gone"), None, true)],
+    );
+    assert!(pushes("refs/heads/gone")?.is_empty());
+    Ok(())
+}
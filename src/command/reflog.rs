@@ -0,0 +1,42 @@
+use std::env;
+use std::io;
+use std::io::Write as _;
+
+use anyhow::anyhow;
+use structopt::StructOpt;
+
+/// Show the history of where a reference has pointed, most recent first.
+///
+/// Entries can be named in revisions as `<reference>@{<n>}`.
+#[derive(StructOpt)]
+pub struct Configuration {
+    /// Reference whose log to show.
+    #[structopt(default_value = "HEAD")]
+    reference: String,
+}
+
+impl Configuration {
+    pub fn run(self) -> anyhow::Result<()> {
+        let root = env::current_dir()?;
+        let repository = crate::Repository::new(root);
+        let references = repository.references();
+
+        let full = references
+            .expand(&self.reference)?
+            .ok_or_else(|| anyhow!("No such reference: {}", self.reference))?;
+
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        for (n, entry) in references.read_log(&full)?.iter().rev().enumerate() {
+            writeln!(
+                stdout,
+                "{} {}@{{{}}}: {}",
+                &entry.new.to_string()[..7],
+                self.reference,
+                n,
+                entry.message,
+            )?;
+        }
+        Ok(())
+    }
+}
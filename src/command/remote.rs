@@ -0,0 +1,287 @@
+use std::env;
+use std::io;
+
+use anyhow::anyhow;
+use structopt::StructOpt;
+
+use crate::references;
+use crate::refspec;
+use crate::transport;
+
+/// Inspect remotes and clean up their remote-tracking branches.
+#[derive(StructOpt)]
+pub enum Configuration {
+    /// Show a remote's URLs and branches, which remote-tracking branches
+    /// are stale, and which local branches merge with its branches.
+    Show {
+        /// Name of the remote.
+        #[structopt(default_value = "origin")]
+        remote: String,
+    },
+
+    /// Delete remote-tracking branches whose branches no longer exist on
+    /// the remote, like `fetch --prune` without fetching.
+    Prune {
+        /// Only report which references would be deleted.
+        #[structopt(short = "n", long)]
+        dry_run: bool,
+
+        /// Name of the remote.
+        #[structopt(default_value = "origin")]
+        remote: String,
+    },
+}
+
+impl Configuration {
+    pub fn run(self) -> anyhow::Result<()> {
+        let root = env::current_dir()?;
+        let repository = crate::Repository::open(root);
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        self.apply(&repository, &mut stdout)
+    }
+
+    /// Run against `repository`, writing the report to `stdout`.
+    fn apply(
+        self,
+        repository: &crate::Repository,
+        stdout: &mut impl io::Write,
+    ) -> anyhow::Result<()> {
+        let config = repository.config()?;
+        let references = repository.references();
+
+        let remote = match &self {
+            Configuration::Show { remote } | Configuration::Prune { remote, .. } => remote,
+        };
+        let url = config
+            .get(&format!("remote.{}.url", remote))
+            .ok_or_else(|| anyhow!("No such remote '{}'", remote))?;
+        let refspecs =
+            refspec::Refspecs::parse(config.get_all(&format!("remote.{}.fetch", remote)))?;
+        let advertisement = transport::Remote::new(url, &config)?.advertise()?;
+        let stale = super::fetch::stale(&refspecs, &references, &advertisement.refs)?;
+
+        match &self {
+            Configuration::Show { remote } => {
+                writeln!(stdout, "* remote {}", remote)?;
+                writeln!(stdout, "  Fetch URL: {}", url)?;
+                let push = config
+                    .get(&format!("remote.{}.pushurl", remote))
+                    .unwrap_or(url);
+                writeln!(stdout, "  Push  URL: {}", push)?;
+                match advertisement.head() {
+                    Some(head) => writeln!(stdout, "  HEAD branch: {}", shorten(head))?,
+                    None => writeln!(stdout, "  HEAD branch: (unknown)")?,
+                }
+
+                let mut branches = Vec::new();
+                for (name, _) in &advertisement.refs {
+                    if !name.starts_with(crate::References::HEADS) {
+                        continue;
+                    }
+                    let tracking = refspecs
+                        .map(name)
+                        .into_iter()
+                        .map(|(_, destination)| destination)
+                        .next();
+                    let state = match tracking {
+                        None => String::from("skipped"),
+                        Some(tracking) if references.read(&tracking)?.is_some() => {
+                            String::from("tracked")
+                        }
+                        Some(tracking) => format!(
+                            "new (next fetch will store in {})",
+                            tracking.strip_prefix("refs/").unwrap_or(&tracking),
+                        ),
+                    };
+                    branches.push((shorten(name).to_owned(), state));
+                }
+                for (name, _) in &stale {
+                    branches.push((
+                        name.clone(),
+                        String::from("stale (use 'grit remote prune' to remove)"),
+                    ));
+                }
+                describe(stdout, "Remote branch", &branches)?;
+
+                let mut merges = Vec::new();
+                for (key, value) in config.iter() {
+                    let branch = match key
+                        .strip_prefix("branch.")
+                        .and_then(|key| key.strip_suffix(".remote"))
+                    {
+                        Some(branch) if value == remote => branch,
+                        _ => continue,
+                    };
+                    if let Some(merge) = config.get(&format!("branch.{}.merge", branch)) {
+                        merges.push((
+                            branch.to_owned(),
+                            format!("merges with remote {}", shorten(merge)),
+                        ));
+                    }
+                }
+                describe(stdout, "Local branch configured for 'grit pull'", &merges)?;
+                Ok(())
+            }
+            Configuration::Prune { remote, dry_run } => {
+                if stale.is_empty() {
+                    return Ok(());
+                }
+
+                writeln!(stdout, "Pruning {}", remote)?;
+                writeln!(stdout, "URL: {}", url)?;
+                let label = match dry_run {
+                    true => "[would prune]",
+                    false => "[pruned]",
+                };
+                for (name, _) in &stale {
+                    writeln!(stdout, " * {} {}", label, shorten(name))?;
+                }
+
+                if !dry_run {
+                    let updates = stale
+                        .into_iter()
+                        .map(|(name, id)| references::Update {
+                            name,
+                            old: Some(id),
+                            new: None,
+                        })
+                        .collect::<Vec<_>>();
+                    let committer = config.committer()?;
+                    references.transaction(&updates, &committer, "remote: prune")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Print `items` as an aligned list under a heading made from `noun`,
+/// pluralized like `git remote show` when there is more than one.
+fn describe(stdout: &mut impl io::Write, noun: &str, items: &[(String, String)]) -> io::Result<()> {
+    if items.is_empty() {
+        return Ok(());
+    }
+    match items.len() {
+        1 => writeln!(stdout, "  {}:", noun)?,
+        _ => writeln!(stdout, "  {}:", noun.replacen("branch", "branches", 1))?,
+    }
+    let width = items
+        .iter()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or_default();
+    for (name, description) in items {
+        writeln!(
+            stdout,
+            "    {:<width$} {}",
+            name,
+            description,
+            width = width
+        )?;
+    }
+    Ok(())
+}
+
+/// Shorten a full reference name for display, like `main` for
+/// `refs/heads/main` or `origin/main` for `refs/remotes/origin/main`.
+fn shorten(name: &str) -> &str {
+    [crate::References::HEADS, crate::References::REMOTES]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name)
+}
+
+#[test]
+fn prune() -> anyhow::Result<()> {
+    use crate::object;
+
+    let root = crate::util::TempDir::new();
+    let mut source = crate::Repository::new(root.join("source"));
+    let mut target = crate::Repository::new(root.join("target"));
+    source.init()?;
+    target.init()?;
+
+    let person = object::Person::new(
+        String::from("A U Thor"),
+        String::from("author@example.com"),
+        object::Person::parse_time("@1600000000 +0000")?,
+    );
+    let mut commit = None;
+    for repository in [&source, &target].iter() {
+        let database = repository.database()?;
+        let blob = database.store(&crate::Object::Blob(object::Blob::new(b"1".to_vec())))?;
+        let tree = database.store(&crate::Object::Tree(object::tree::Root::new(vec![
+            object::tree::Node {
+                path: std::path::PathBuf::from("file"),
+                mode: crate::meta::Mode::Regular,
+                id: blob,
+            },
+        ])))?;
+        let id = database.store(&crate::Object::Commit(object::Commit::new(
+            tree,
+            vec![],
+            person.clone(),
+            person.clone(),
+            String::from("1\n"),
+        )))?;
+        repository.references().write_head(&id)?;
+        commit = Some(id);
+    }
+    let commit = commit.expect("stored");
+
+    let mut document = crate::config::Document::open(root.join("target/.git/config"))?;
+    document.set(
+        "remote.origin.url",
+        &format!("file://{}", root.join("source").display()),
+    )?;
+    document.set("remote.origin.fetch", "+refs/heads/*:refs/remotes/origin/*")?;
+    document.commit()?;
+
+    // Only `origin/gone` no longer exists on the remote, and the rest are
+    // either still there or not mapped from it.
+    let references = target.references();
+    for name in [
+        "refs/heads/gone",
+        "refs/remotes/origin/gone",
+        "refs/remotes/origin/master",
+        "refs/remotes/upstream/gone",
+    ]
+    .iter()
+    {
+        references
+            .store()
+            .write(name, &references::Target::Direct(commit))?;
+    }
+
+    let prune = |dry_run: bool| -> anyhow::Result<(String, Vec<String>)> {
+        let mut output = Vec::new();
+        Configuration::Prune {
+            dry_run,
+            remote: String::from("origin"),
+        }
+        .apply(&target, &mut output)?;
+        let remaining = references
+            .iter_prefix("refs/")?
+            .map(|reference| reference.map(|(name, _)| name))
+            .collect::<anyhow::Result<_>>()?;
+        Ok((String::from_utf8(output)?, remaining))
+    };
+
+    let (reported, kept) = prune(true)?;
+    let (output, pruned) = prune(false)?;
+
+    assert!(reported.contains(" * [would prune] origin/gone\n"));
+    assert_eq!(kept.len(), 5);
+    assert!(output.contains(" * [pruned] origin/gone\n"));
+    assert_eq!(
+        pruned,
+        vec![
+            "refs/heads/gone",
+            "refs/heads/master",
+            "refs/remotes/origin/master",
+            "refs/remotes/upstream/gone",
+        ],
+    );
+    Ok(())
+}
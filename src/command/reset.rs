@@ -0,0 +1,143 @@
+use std::env;
+use std::io;
+use std::io::Write as _;
+use std::path;
+
+use anyhow::anyhow;
+use structopt::clap::ArgGroup;
+use structopt::StructOpt;
+
+use crate::meta;
+use crate::migration;
+use crate::object;
+use crate::revision;
+use crate::state;
+
+/// Move the current branch to a commit, resetting the index and, with
+/// `--hard`, the workspace to match.
+#[derive(StructOpt)]
+#[structopt(group = ArgGroup::with_name("mode"))]
+pub struct Configuration {
+    /// Only move the current branch, leaving the index and workspace as
+    /// they are.
+    #[structopt(long, group = "mode")]
+    soft: bool,
+
+    /// Also reset the index, keeping changes in the workspace. This is the
+    /// default.
+    #[structopt(long, group = "mode")]
+    mixed: bool,
+
+    /// Also reset the workspace, discarding all changes to tracked files.
+    #[structopt(long, group = "mode")]
+    hard: bool,
+
+    /// Commit to reset to. Defaults to `HEAD`.
+    #[structopt(default_value = "HEAD")]
+    revision: String,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Mode {
+    Soft,
+    Mixed,
+    Hard,
+}
+
+impl Configuration {
+    pub fn run(self) -> anyhow::Result<()> {
+        let root = env::current_dir()?;
+        let repository = crate::Repository::new(root);
+
+        let mode = match (self.soft, self.mixed, self.hard) {
+            (true, _, _) => Mode::Soft,
+            (_, false, true) => Mode::Hard,
+            _ => Mode::Mixed,
+        };
+
+        // Resolve the target before locking the index, which `:path`
+        // revisions read.
+        let id = self
+            .revision
+            .parse::<revision::Revision>()?
+            .resolve(&repository)?
+            .ok_or_else(|| anyhow!("Failed to resolve '{}' as a valid revision", self.revision))?
+            .peel_to_commit(&repository.database()?)?;
+
+        let merging = repository.audit()?.contains(&state::Leftover::Merge);
+        if merging && mode == Mode::Soft {
+            return Err(anyhow!("Cannot do a soft reset in the middle of a merge"));
+        }
+
+        let config = repository.config()?;
+        let reset = Reset {
+            git: repository.root().join(".git"),
+            check_stat: config.parse("core.checkStat")?.unwrap_or_default(),
+            committer: config.committer()?,
+            database: repository.database()?,
+            index: repository.index()?,
+            references: repository.references(),
+            workspace: repository.workspace()?,
+        };
+        reset.run(id, mode, merging, &self.revision)
+    }
+}
+
+struct Reset {
+    git: path::PathBuf,
+    check_stat: meta::CheckStat,
+    committer: object::Person,
+    database: crate::Database,
+    index: crate::Index,
+    references: crate::References,
+    workspace: crate::Workspace,
+}
+
+impl Reset {
+    fn run(
+        mut self,
+        id: object::Id,
+        mode: Mode,
+        merging: bool,
+        revision: &str,
+    ) -> anyhow::Result<()> {
+        let message = format!("reset: moving to {}", revision);
+        if mode == Mode::Soft {
+            return self.references.update_head(&id, &self.committer, &message);
+        }
+
+        let tree = id.peel_to_tree(&self.database)?;
+        match mode {
+            Mode::Hard => migration::reset(
+                &self.database,
+                &mut self.index,
+                &self.workspace,
+                self.check_stat,
+                &tree,
+            )?,
+            _ => self.index.load_tree(&self.database, &tree)?,
+        }
+
+        self.index.commit()?;
+        self.references
+            .update_head(&id, &self.committer, &message)?;
+
+        if merging {
+            state::Leftover::Merge.clean(&self.git)?;
+        }
+
+        if mode == Mode::Hard {
+            let stdout = io::stdout();
+            let mut stdout = stdout.lock();
+            let commit = self.database.load_commit(&id)?;
+            writeln!(
+                stdout,
+                "HEAD is now at {} {}",
+                &id.to_string()[..7],
+                commit.title()
+            )?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,234 @@
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::env;
+use std::path;
+
+use anyhow::anyhow;
+use structopt::StructOpt;
+
+use crate::diff;
+use crate::util;
+
+/// Discard changes to files, restoring the workspace from the index, or the
+/// index from `HEAD` with `--staged`.
+#[derive(StructOpt)]
+pub struct Configuration {
+    /// Restore the index from `HEAD`, unstaging changes.
+    #[structopt(short = "S", long)]
+    staged: bool,
+
+    /// Restore the workspace. This is the default unless `--staged` is
+    /// given; with both, the workspace is also restored from `HEAD`.
+    #[structopt(short = "W", long)]
+    worktree: bool,
+
+    /// Files or directories to restore.
+    #[structopt(required = true)]
+    paths: Vec<path::PathBuf>,
+}
+
+impl Configuration {
+    pub fn run(self) -> anyhow::Result<()> {
+        let root = env::current_dir()?;
+        let repository = crate::Repository::new(root);
+        let restore = Restore {
+            database: repository.database()?,
+            index: repository.index()?,
+            references: repository.references(),
+            workspace: repository.workspace()?,
+        };
+        restore.run(&self.paths, self.staged, self.worktree || !self.staged)
+    }
+}
+
+struct Restore {
+    database: crate::Database,
+    index: crate::Index,
+    references: crate::References,
+    workspace: crate::Workspace,
+}
+
+impl Restore {
+    fn run(mut self, paths: &[path::PathBuf], staged: bool, worktree: bool) -> anyhow::Result<()> {
+        let head = match self.references.read_head()? {
+            None => BTreeMap::new(),
+            Some(id) => diff::tree::flatten(&self.database, &id.peel_to_tree(&self.database)?)?,
+        };
+
+        let mut restored = BTreeSet::new();
+        for path in paths {
+            // Treat `.` and `./foo` like the workspace root and `foo`.
+            let path = path
+                .components()
+                .filter(|component| *component != path::Component::CurDir)
+                .collect::<path::PathBuf>();
+
+            let tracked = self
+                .index
+                .entries()
+                .map(|entry| entry.path())
+                .chain(self.index.conflicts().map(|(path, _)| path))
+                .map(path::Path::to_path_buf)
+                .map(util::PathBuf);
+            let committed = head.keys().filter(|_| staged).cloned();
+            let matched = tracked
+                .chain(committed)
+                .filter(|tracked| tracked.starts_with(&path))
+                .collect::<Vec<_>>();
+
+            if matched.is_empty() {
+                return Err(anyhow!(
+                    "pathspec '{}' did not match any file(s) known to grit",
+                    path.display(),
+                ));
+            }
+            restored.extend(matched);
+        }
+
+        if staged {
+            for path in &restored {
+                match head.get(path) {
+                    None => {
+                        self.index.remove(path);
+                    }
+                    Some(entry) => {
+                        let size = self.database.read_header(&entry.id)?.len;
+                        self.index
+                            .reset(path.to_path_buf(), entry.id, entry.mode, size as u32);
+                    }
+                }
+            }
+        } else if let Some(path) = restored.iter().find(|path| {
+            self.index
+                .conflicts()
+                .any(|(conflict, _)| conflict == path.as_path())
+        }) {
+            return Err(anyhow!("path '{}' is unmerged", path.display()));
+        }
+
+        if worktree {
+            for path in &restored {
+                let (id, mode) = match self.index.get(path) {
+                    Some(entry) => (*entry.id(), *entry.metadata().mode()),
+                    // Only reachable with `--staged`, for files added since
+                    // `HEAD`.
+                    None => {
+                        self.workspace.remove(path)?;
+                        if let Some(parent) = path.parent() {
+                            self.workspace.remove_empty_directories(parent);
+                        }
+                        continue;
+                    }
+                };
+
+                let data = self.database.load_blob(&id)?.into_data();
+                self.workspace.write(path, &data, mode)?;
+                let metadata = self.workspace.metadata(path)?;
+                let metadata = self.workspace.normalize(metadata, mode);
+                self.index.insert(metadata, id, path.to_path_buf());
+            }
+        }
+
+        self.index.commit()?;
+        Ok(())
+    }
+}
+
+#[test]
+fn staged_and_worktree() -> anyhow::Result<()> {
+    use std::fs;
+
+    use crate::meta;
+    use crate::object;
+
+    let root = crate::util::TempDir::new();
+    let mut repository = crate::Repository::new(root.to_path_buf());
+    repository.init()?;
+
+    let database = repository.database()?;
+    let workspace = repository.workspace()?;
+    let blob = |data: &[u8]| database.store(&crate::Object::Blob(object::Blob::new(data.to_vec())));
+    let (one, two) = (blob(b"1")?, blob(b"2")?);
+
+    let mut files = BTreeMap::new();
+    files.insert(
+        util::PathBuf(path::PathBuf::from("a")),
+        diff::tree::Entry {
+            id: one,
+            mode: meta::Mode::Regular,
+        },
+    );
+    let tree = diff::tree::unflatten(&database, &files)?;
+    let person = object::Person::new(
+        String::from("A U Thor"),
+        String::from("author@example.com"),
+        object::Person::parse_time("@1600000000 +0000")?,
+    );
+    let commit = object::Commit::new(tree, vec![], person.clone(), person, String::from("a"));
+    repository
+        .references()
+        .write_head(&database.store(&crate::Object::Commit(commit))?)?;
+
+    // `a` is committed as 1, staged as 2, and changed to 3, and `b` is
+    // only staged.
+    let mut index = repository.index()?;
+    crate::migration::reset(
+        &database,
+        &mut index,
+        &workspace,
+        meta::CheckStat::default(),
+        &tree,
+    )?;
+    for (name, id) in [("a", two), ("b", one)].iter() {
+        fs::write(root.join(name), b"3")?;
+        index.insert(
+            workspace.metadata(path::Path::new(name))?,
+            *id,
+            path::PathBuf::from(name),
+        );
+    }
+    index.commit()?;
+
+    let restore = |paths: &[&str], staged: bool, worktree: bool| -> anyhow::Result<()> {
+        Restore {
+            database: repository.database()?,
+            index: repository.index()?,
+            references: repository.references(),
+            workspace: repository.workspace()?,
+        }
+        .run(
+            &paths.iter().map(path::PathBuf::from).collect::<Vec<_>>(),
+            staged,
+            worktree,
+        )
+    };
+    let staged = |name: &str| -> anyhow::Result<Option<object::Id>> {
+        Ok(repository
+            .index()?
+            .get(path::Path::new(name))
+            .map(|entry| *entry.id()))
+    };
+
+    // From the index, leaving it alone.
+    restore(&["a"], false, true)?;
+    let worktree = (fs::read(root.join("a"))?, staged("a")?);
+
+    // From `HEAD`, leaving the workspace alone.
+    fs::write(root.join("a"), b"3")?;
+    restore(&["."], true, false)?;
+    let unstaged = (fs::read(root.join("a"))?, staged("a")?, staged("b")?);
+    let kept = root.join("b").exists();
+
+    // From `HEAD` into both.
+    restore(&["a"], true, true)?;
+    let both = (fs::read(root.join("a"))?, staged("a")?);
+
+    let missing = restore(&["c"], false, true).is_err();
+
+    assert_eq!(worktree, (b"2".to_vec(), Some(two)));
+    assert_eq!(unstaged, (b"3".to_vec(), Some(one), None));
+    assert!(kept);
+    assert_eq!(both, (b"1".to_vec(), Some(one)));
+    assert!(missing);
+    Ok(())
+}
@@ -0,0 +1,152 @@
+use std::env;
+use std::io;
+use std::io::Write as _;
+
+use anyhow::anyhow;
+use structopt::StructOpt;
+
+use crate::merge;
+use crate::object;
+use crate::revision;
+use crate::revwalk;
+
+/// List commits reachable from some revisions but not others, newest first,
+/// like `git rev-list`.
+#[derive(StructOpt)]
+pub struct Configuration {
+    /// Print only the number of commits (and objects, with `--objects`)
+    /// that would be listed.
+    #[structopt(long)]
+    count: bool,
+
+    /// Also list the trees and blobs reachable from the selected commits,
+    /// but not from the excluded ones, with their paths.
+    #[structopt(long)]
+    objects: bool,
+
+    /// Include `HEAD` and every reference.
+    #[structopt(long)]
+    all: bool,
+
+    /// Include local branches, or only those matching a glob.
+    #[structopt(long, require_equals = true)]
+    branches: Option<Option<String>>,
+
+    /// Include tags, or only those matching a glob.
+    #[structopt(long, require_equals = true)]
+    tags: Option<Option<String>>,
+
+    /// Include remote-tracking branches, or only those matching a glob.
+    #[structopt(long, require_equals = true)]
+    remotes: Option<Option<String>>,
+
+    /// Include references matching a glob, e.g. `heads/feature/*`.
+    #[structopt(long)]
+    glob: Vec<String>,
+
+    /// Revisions to include, or to exclude when prefixed with `^`.
+    ///
+    /// `a..b` means `^a b`, and `a...b` means the commits reachable from
+    /// either but not both. Either side defaults to `HEAD` when empty.
+    revisions: Vec<String>,
+}
+
+impl Configuration {
+    pub fn run(self) -> anyhow::Result<()> {
+        let root = env::current_dir()?;
+        let repository = crate::Repository::new(root);
+        let database = repository.database()?;
+
+        let resolve = |name: &str| -> anyhow::Result<object::Id> {
+            let name = if name.is_empty() { "HEAD" } else { name };
+            name.parse::<revision::Revision>()?
+                .resolve(&repository)?
+                .ok_or_else(|| anyhow!("Unknown revision: `{}`", name))
+        };
+
+        let mut refs = Vec::new();
+        if self.all {
+            refs.push(revwalk::Refs::All);
+        }
+        if let Some(pattern) = self.branches {
+            refs.push(revwalk::Refs::Branches(pattern));
+        }
+        if let Some(pattern) = self.tags {
+            refs.push(revwalk::Refs::Tags(pattern));
+        }
+        if let Some(pattern) = self.remotes {
+            refs.push(revwalk::Refs::Remotes(pattern));
+        }
+        refs.extend(self.glob.into_iter().map(revwalk::Refs::Glob));
+
+        if refs.is_empty() && self.revisions.is_empty() {
+            return Err(anyhow!("No revisions given"));
+        }
+
+        let mut walk = revwalk::Walk::new(&database);
+        let mut tags = Vec::new();
+
+        for refs in &refs {
+            for (_, id) in refs.resolve(&repository.references())? {
+                // Skip references to trees and blobs.
+                if let Ok(id) = id.peel_to_commit(&database) {
+                    walk.include(id)?;
+                }
+            }
+        }
+
+        for name in &self.revisions {
+            if let Some((a, b)) = name.split_once("...") {
+                let a = resolve(a)?.peel_to_commit(&database)?;
+                let b = resolve(b)?.peel_to_commit(&database)?;
+                walk.include(a)?;
+                walk.include(b)?;
+                for base in merge::bases(&database, &a, &b)? {
+                    walk.exclude(base)?;
+                }
+            } else if let Some((a, b)) = name.split_once("..") {
+                walk.exclude(resolve(a)?.peel_to_commit(&database)?)?;
+                walk.include(resolve(b)?.peel_to_commit(&database)?)?;
+            } else if let Some(name) = name.strip_prefix('^') {
+                walk.exclude(resolve(name)?.peel_to_commit(&database)?)?;
+            } else {
+                // Like `git`, list annotated tags named directly with the
+                // other objects.
+                let mut id = resolve(name)?;
+                while let object::Type::Tag = database.kind(&id)? {
+                    tags.push((id, name.clone()));
+                    id = *database.load_tag(&id)?.object();
+                }
+                walk.include(id.peel_to_commit(&database)?)?;
+            }
+        }
+
+        let selection = walk.run()?;
+        let objects = match self.objects {
+            false => Vec::new(),
+            true => revwalk::objects(&database, &selection)?,
+        };
+
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+
+        if self.count {
+            let tags = if self.objects { tags.len() } else { 0 };
+            writeln!(stdout, "{}", selection.commits.len() + tags + objects.len())?;
+            return Ok(());
+        }
+
+        for id in &selection.commits {
+            writeln!(stdout, "{}", id)?;
+        }
+        if self.objects {
+            for (id, name) in &tags {
+                writeln!(stdout, "{} {}", id, name)?;
+            }
+            for (id, path) in &objects {
+                writeln!(stdout, "{} {}", id, path.display())?;
+            }
+        }
+        Ok(())
+    }
+}
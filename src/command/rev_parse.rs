@@ -0,0 +1,91 @@
+use std::env;
+use std::io;
+use std::io::Write as _;
+
+use anyhow::anyhow;
+use structopt::StructOpt;
+
+use crate::revision;
+
+/// Resolve revisions to full object ids, and answer questions about the
+/// repository, like `git rev-parse`.
+#[derive(StructOpt)]
+pub struct Configuration {
+    /// Print the path of the `.git` directory.
+    #[structopt(long)]
+    git_dir: bool,
+
+    /// Print the absolute path of the top-level directory of the workspace.
+    #[structopt(long)]
+    show_toplevel: bool,
+
+    /// Print the short names of the references that revisions name, e.g.
+    /// the current branch for `HEAD`, instead of object ids.
+    ///
+    /// Revisions that don't name a reference are skipped.
+    #[structopt(long)]
+    abbrev_ref: bool,
+
+    /// Require exactly one revision that resolves to an object.
+    #[structopt(long)]
+    verify: bool,
+
+    revisions: Vec<String>,
+}
+
+impl Configuration {
+    pub fn run(self) -> anyhow::Result<()> {
+        let root = env::current_dir()?;
+        let repository = crate::Repository::new(root);
+
+        if !repository.root().join(".git").exists() {
+            return Err(anyhow!(
+                "Not a git repository: {}",
+                repository.root().display()
+            ));
+        }
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        if self.git_dir {
+            writeln!(stdout, ".git")?;
+        }
+        if self.show_toplevel {
+            writeln!(stdout, "{}", repository.root().display())?;
+        }
+
+        if self.verify {
+            let id = match self.revisions.as_slice() {
+                [revision] => revision
+                    .parse::<revision::Revision>()
+                    .ok()
+                    .and_then(|revision| revision.resolve(&repository).ok().flatten()),
+                _ => None,
+            };
+            let id = id.ok_or_else(|| anyhow!("Needed a single revision"))?;
+            writeln!(stdout, "{}", id)?;
+            return Ok(());
+        }
+
+        let mut output = Vec::with_capacity(self.revisions.len());
+        for name in &self.revisions {
+            let revision = name.parse::<revision::Revision>()?;
+            let id = revision
+                .resolve(&repository)?
+                .ok_or_else(|| anyhow!("Ambiguous argument '{}': unknown revision", name))?;
+
+            match self.abbrev_ref {
+                false => output.push(id.to_string()),
+                true => output.extend(
+                    revision
+                        .reference(&repository)?
+                        .map(|name| crate::References::shorten(&name).to_owned()),
+                ),
+            }
+        }
+
+        for line in output {
+            writeln!(stdout, "{}", line)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,240 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::io;
+use std::io::Write as _;
+use std::path;
+
+use anyhow::anyhow;
+use structopt::StructOpt;
+
+use crate::diff;
+use crate::meta;
+use crate::object;
+use crate::util;
+
+/// Remove files from the index, and from the workspace unless `--cached`
+/// is given.
+#[derive(StructOpt)]
+pub struct Configuration {
+    /// Only remove files from the index, leaving them in the workspace.
+    #[structopt(long)]
+    cached: bool,
+
+    /// Remove files even if they have staged or unstaged changes.
+    #[structopt(short, long)]
+    force: bool,
+
+    /// Allow recursive removal when a directory is given.
+    #[structopt(short)]
+    recursive: bool,
+
+    /// Files or directories to remove.
+    #[structopt(required = true)]
+    paths: Vec<path::PathBuf>,
+}
+
+impl Configuration {
+    pub fn run(self) -> anyhow::Result<()> {
+        let root = env::current_dir()?;
+        let repository = crate::Repository::new(root);
+        let rm = Rm {
+            check_stat: repository
+                .config()?
+                .parse("core.checkStat")?
+                .unwrap_or_default(),
+            database: repository.database()?,
+            index: repository.index()?,
+            references: repository.references(),
+            workspace: repository.workspace()?,
+            cached: self.cached,
+            force: self.force,
+            recursive: self.recursive,
+        };
+        rm.run(&self.paths)
+    }
+}
+
+struct Rm {
+    check_stat: meta::CheckStat,
+    database: crate::Database,
+    index: crate::Index,
+    references: crate::References,
+    workspace: crate::Workspace,
+    cached: bool,
+    force: bool,
+    recursive: bool,
+}
+
+#[derive(Debug, Default)]
+struct Errors {
+    /// Files whose index contents differ from both HEAD and the workspace.
+    both: Vec<path::PathBuf>,
+    /// Files whose index contents differ from HEAD.
+    staged: Vec<path::PathBuf>,
+    /// Files whose workspace contents differ from the index.
+    local: Vec<path::PathBuf>,
+}
+
+impl Rm {
+    fn run(mut self, paths: &[path::PathBuf]) -> anyhow::Result<()> {
+        let mut removed = Vec::new();
+
+        for path in paths {
+            if self.index.contains_file(path) {
+                removed.push(path.clone());
+            } else if self.index.contains_directory(path) {
+                if !self.recursive {
+                    return Err(anyhow!(
+                        "not removing '{}' recursively without -r",
+                        path.display(),
+                    ));
+                }
+                removed.extend(
+                    self.index
+                        .entries()
+                        .map(|entry| entry.path())
+                        .filter(|entry| entry.starts_with(path))
+                        .map(path::Path::to_path_buf),
+                );
+            } else {
+                return Err(anyhow!(
+                    "pathspec '{}' did not match any files",
+                    path.display(),
+                ));
+            }
+        }
+
+        if !self.force {
+            self.check(&removed)?;
+        }
+
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        for path in &removed {
+            self.index.remove(path);
+            writeln!(stdout, "rm '{}'", path.display())?;
+
+            if !self.cached {
+                self.workspace.remove(path)?;
+                if let Some(parent) = path.parent() {
+                    self.workspace.remove_empty_directories(parent);
+                }
+            }
+        }
+
+        self.index.commit()?;
+        Ok(())
+    }
+
+    /// Refuse to remove files whose changes would be lost.
+    fn check(&self, paths: &[path::PathBuf]) -> anyhow::Result<()> {
+        let head = match self.references.read_head()? {
+            None => BTreeMap::new(),
+            Some(id) => diff::tree::flatten(&self.database, &id.peel_to_tree(&self.database)?)?,
+        };
+
+        let mut errors = Errors::default();
+
+        for path in paths {
+            // Removing an unmerged file resolves its conflict.
+            let entry = match self.index.get(path) {
+                None => continue,
+                Some(entry) => entry,
+            };
+
+            let staged = !head
+                .get(&entry.path() as &dyn util::Key)
+                .is_some_and(|head| {
+                    head.id == *entry.id() && head.mode == *entry.metadata().mode()
+                });
+            let local = !self.is_clean(entry)?;
+
+            match (staged, local) {
+                (true, true) => errors.both.push(path.clone()),
+                _ if self.cached => (),
+                (true, false) => errors.staged.push(path.clone()),
+                (false, true) => errors.local.push(path.clone()),
+                (false, false) => (),
+            }
+        }
+
+        let mut message = String::new();
+        let mut describe = |paths: &[path::PathBuf], singular: &str, plural: &str, hint: &str| {
+            if paths.is_empty() {
+                return;
+            }
+            if !message.is_empty() {
+                message.push_str("\nerror: ");
+            }
+            message.push_str(match paths.len() {
+                1 => singular,
+                _ => plural,
+            });
+            message.push('\n');
+            for path in paths {
+                message.push_str("    ");
+                message.push_str(&path.display().to_string());
+                message.push('\n');
+            }
+            message.push_str(hint);
+        };
+
+        describe(
+            &errors.both,
+            "the following file has staged content different from both the\nfile and the HEAD:",
+            "the following files have staged content different from both the\nfile and the HEAD:",
+            "(use -f to force removal)",
+        );
+        describe(
+            &errors.staged,
+            "the following file has changes staged in the index:",
+            "the following files have changes staged in the index:",
+            "(use --cached to keep the file, or -f to force removal)",
+        );
+        describe(
+            &errors.local,
+            "the following file has local modifications:",
+            "the following files have local modifications:",
+            "(use --cached to keep the file, or -f to force removal)",
+        );
+
+        match message.is_empty() {
+            true => Ok(()),
+            false => Err(anyhow!(message)),
+        }
+    }
+
+    /// Check whether the workspace file for `entry` matches the index,
+    /// treating a missing file as clean.
+    fn is_clean(&self, entry: &crate::index::Entry) -> anyhow::Result<bool> {
+        let metadata = match self.workspace.metadata(entry.path()) {
+            Ok(metadata) => metadata,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(true),
+            Err(error) => return Err(error.into()),
+        };
+
+        let old = entry.metadata();
+        let metadata = self.workspace.normalize(metadata, old.mode);
+        // A size of zero may have been smudged by a racy write.
+        if metadata.mode.is_directory()
+            || metadata.mode != old.mode
+            || (metadata.size != old.size && old.size != 0)
+        {
+            return Ok(false);
+        }
+
+        if metadata.is_stat_clean(old, self.check_stat) {
+            return Ok(true);
+        }
+
+        let id = self
+            .workspace
+            .read(entry.path())
+            .map(object::Blob::new)
+            .map(crate::Object::Blob)
+            .map(|object| object.to_bytes())
+            .map(|bytes| object::Id::hash(&bytes))?;
+
+        Ok(id == *entry.id())
+    }
+}
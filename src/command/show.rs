@@ -1,70 +1,277 @@
 use std::env;
+use std::io;
+use std::io::Write as _;
+use std::path;
 
 use anyhow::anyhow;
 use structopt::StructOpt;
+use termcolor::WriteColor as _;
 
+use crate::attributes;
+use crate::diff;
+use crate::diff::rename;
 use crate::object;
-use crate::object::Object;
+use crate::revision;
 
+use super::diff::find_renames;
+use super::diff::print;
+use super::diff::textconv;
+use super::diff::External;
+use super::diff::Side;
+
+/// Show objects like `git show`: commits with their changes, annotated tags
+/// followed by the object they point to, trees as lists of names, and blobs
+/// as their contents.
 #[derive(StructOpt)]
 pub struct Configuration {
-    id: Option<object::Id>,
+    /// Print changed files in the order given by this file, which lists
+    /// one glob per line. Overrides `diff.orderFile`.
+    #[structopt(short = "O", value_name = "orderfile")]
+    order_file: Option<path::PathBuf>,
+
+    /// Compare files configured to use an external program with it, instead
+    /// of the built-in diff.
+    #[structopt(long)]
+    ext_diff: bool,
+
+    /// Show dates as `default`, `local`, `iso`, `iso-strict`, `rfc`,
+    /// `short`, `raw`, `unix`, or `relative`. Overrides `log.date`.
+    #[structopt(long, require_equals = true, value_name = "format")]
+    date: Option<object::DateFormat>,
+
+    /// Detect renamed files, optionally with a minimum similarity such as
+    /// `-M90%`. Overrides `diff.renames`.
+    #[structopt(short = "M", long)]
+    find_renames: Option<Option<rename::Score>>,
+
+    /// Do not detect renames. Overrides `diff.renames`.
+    #[structopt(long, conflicts_with = "find-renames")]
+    no_renames: bool,
+
+    /// Objects to show.
+    ///
+    /// Defaults to `HEAD` if not provided.
+    objects: Vec<String>,
 }
 
 impl Configuration {
     pub fn run(self) -> anyhow::Result<()> {
         let root = env::current_dir()?;
         let repository = crate::Repository::new(root);
-        let show = Show {
-            database: repository.database(),
-            references: repository.references(),
-            id: self.id,
+        let stdout = termcolor::StandardStream::stdout(match isatty::stdout_isatty() {
+            true => termcolor::ColorChoice::Always,
+            false => termcolor::ColorChoice::Never,
+        });
+
+        let names = match self.objects.is_empty() {
+            true => vec![String::from("HEAD")],
+            false => self.objects,
+        };
+        let mut objects = Vec::with_capacity(names.len());
+        for name in names {
+            let id = name
+                .parse::<revision::Revision>()?
+                .resolve(&repository)?
+                .ok_or_else(|| anyhow!("Not a valid object name {}", name))?;
+            objects.push((name, id));
+        }
+
+        let mut show = Show {
+            date: repository
+                .config()?
+                .option(self.date, "log.date")?
+                .unwrap_or_default(),
+            order: diff::order::Order::open(&repository, self.order_file.as_deref())?,
+            renames: rename::Options::configure(
+                &repository.config()?,
+                "diff",
+                self.find_renames,
+                self.no_renames,
+            )?,
+            attributes: repository.attributes()?,
+            textconv: diff::textconv::Textconv::new(&repository)?,
+            external: match self.ext_diff {
+                true => Some(External::new(&repository.config()?)),
+                false => None,
+            },
+            database: repository.database()?,
+            stdout: stdout.lock(),
+            separate: false,
         };
-        show.run()?;
+        for (name, id) in &objects {
+            show.show(name, id)?;
+        }
         Ok(())
     }
 }
 
-struct Show {
+struct Show<'a> {
+    date: object::DateFormat,
+    order: diff::order::Order,
+    renames: Option<rename::Options>,
+    attributes: attributes::Attributes,
+    textconv: diff::textconv::Textconv,
+    external: Option<External>,
     database: crate::Database,
-    references: crate::References,
-    id: Option<object::Id>,
+    stdout: termcolor::StandardStreamLock<'a>,
+    /// Whether to print a blank line before the next commit or tag.
+    separate: bool,
 }
 
-impl Show {
-    fn run(self) -> anyhow::Result<()> {
-        if let Some(id) = &self.id {
-            return self.show_tree(id);
+impl Show<'_> {
+    /// Show object `id`, which was named `name` on the command line,
+    /// dispatching on its type without loading it first.
+    fn show(&mut self, name: &str, id: &object::Id) -> anyhow::Result<()> {
+        match self.database.kind(id)? {
+            object::Type::Commit => self.show_commit(id),
+            object::Type::Tag => self.show_tag(id),
+            object::Type::Tree => self.show_tree(name, id),
+            object::Type::Blob => self.show_blob(id),
         }
+    }
 
-        let head = self
-            .references
-            .read_head()?
-            .ok_or_else(|| anyhow!("Expected HEAD commit"))?;
+    fn show_commit(&mut self, id: &object::Id) -> anyhow::Result<()> {
+        let commit = self.database.load_commit(id)?;
+        let author = commit.author();
 
-        let commit = match self.database.load(&head)? {
-            Object::Blob(_) | Object::Tree(_) => unreachable!(),
-            Object::Commit(commit) => commit,
-        };
+        if self.separate {
+            writeln!(&mut self.stdout)?;
+        }
+        self.separate = true;
+
+        self.stdout
+            .set_color(termcolor::ColorSpec::new().set_fg(Some(termcolor::Color::Yellow)))?;
+        writeln!(&mut self.stdout, "commit {}", id)?;
+        self.stdout.reset()?;
 
-        self.show_tree(commit.tree())
+        if commit.parents().len() > 1 {
+            write!(&mut self.stdout, "Merge:")?;
+            for parent in commit.parents() {
+                write!(&mut self.stdout, " {}", &parent.to_string()[..7])?;
+            }
+            writeln!(&mut self.stdout)?;
+        }
+
+        writeln!(
+            &mut self.stdout,
+            "Author: {} <{}>",
+            author.name(),
+            author.email()
+        )?;
+        writeln!(
+            &mut self.stdout,
+            "Date:   {}",
+            self.date.format(author.time()),
+        )?;
+        writeln!(&mut self.stdout)?;
+
+        for line in commit.message().trim_end().lines() {
+            writeln!(&mut self.stdout, "    {}", line)?;
+        }
+
+        // Like `git log`, merge commits show no changes by default.
+        if commit.parents().len() > 1 {
+            return Ok(());
+        }
+
+        let old = commit
+            .parent()
+            .map(|parent| parent.peel_to_tree(&self.database))
+            .transpose()?;
+        let changes = diff::tree::diff(&self.database, old.as_ref(), Some(commit.tree()))?;
+        if changes.is_empty() {
+            return Ok(());
+        }
+
+        let mut sides = Vec::with_capacity(changes.len());
+        for (path, (old, new)) in &changes {
+            let load = |entry: &Option<diff::tree::Entry>| {
+                entry
+                    .map(|entry| Side::load(&self.database, path, entry.id, entry.mode))
+                    .transpose()
+            };
+            sides.push((load(old)?, load(new)?));
+        }
+        let mut sides = find_renames(&self.database, sides, self.renames)?;
+        self.order.sort(&mut sides, |(a, b)| {
+            &b.as_ref().or(a.as_ref()).expect("at least one side").path
+        });
+
+        writeln!(&mut self.stdout)?;
+        let total = sides.len();
+        for (index, (mut a, mut b)) in sides.into_iter().enumerate() {
+            let path = a
+                .as_ref()
+                .or(b.as_ref())
+                .expect("at least one side")
+                .path
+                .clone();
+            if let Some(external) = &self.external {
+                if let Some(program) = external.program(&self.attributes, &path)? {
+                    self.stdout.flush()?;
+                    external.run(&program, a.as_ref(), b.as_ref(), index, total)?;
+                    continue;
+                }
+            }
+            let binary = match textconv(&mut self.textconv, &self.attributes, &mut a, &mut b)? {
+                true => Some(false),
+                false => self.attributes.is_binary(&path)?,
+            };
+            print(&mut self.stdout, a.as_ref(), b.as_ref(), binary)?;
+        }
+        self.textconv.save()
     }
 
-    fn show_tree(&self, id: &object::Id) -> anyhow::Result<()> {
-        let tree = match self.database.load(id)? {
-            Object::Blob(_) => unreachable!(),
-            Object::Commit(_) => unreachable!(),
-            Object::Tree(tree) => tree,
-        };
+    /// Show an annotated tag, then the object it points to.
+    fn show_tag(&mut self, id: &object::Id) -> anyhow::Result<()> {
+        let tag = self.database.load_tag(id)?;
 
-        for node in &tree {
-            if node.mode.is_directory() {
-                self.show_tree(&node.id)?;
-            } else {
-                println!("{} {} {}", node.mode.as_str(), node.id, node.path.display());
+        if self.separate {
+            writeln!(&mut self.stdout)?;
+        }
+
+        self.stdout
+            .set_color(termcolor::ColorSpec::new().set_fg(Some(termcolor::Color::Yellow)))?;
+        writeln!(&mut self.stdout, "tag {}", tag.name())?;
+        self.stdout.reset()?;
+
+        if let Some(tagger) = tag.tagger() {
+            writeln!(
+                &mut self.stdout,
+                "Tagger: {} <{}>",
+                tagger.name(),
+                tagger.email()
+            )?;
+            writeln!(
+                &mut self.stdout,
+                "Date:   {}",
+                self.date.format(tagger.time()),
+            )?;
+        }
+        writeln!(&mut self.stdout)?;
+        writeln!(&mut self.stdout, "{}", tag.message().trim_end())?;
+
+        self.separate = true;
+        self.show(tag.name(), tag.object())
+    }
+
+    /// List the names in a tree, marking subtrees with a trailing `/`.
+    fn show_tree(&mut self, name: &str, id: &object::Id) -> anyhow::Result<()> {
+        writeln!(&mut self.stdout, "tree {}", name)?;
+        writeln!(&mut self.stdout)?;
+        for node in &self.database.load_tree(id)? {
+            match node.mode.is_directory() {
+                true => writeln!(&mut self.stdout, "{}/", node.path.display())?,
+                false => writeln!(&mut self.stdout, "{}", node.path.display())?,
             }
         }
+        Ok(())
+    }
 
+    /// Copy a blob's contents, without loading it all into memory.
+    fn show_blob(&mut self, id: &object::Id) -> anyhow::Result<()> {
+        let (_, mut reader) = self.database.stream(id)?;
+        io::copy(&mut reader, &mut self.stdout)?;
         Ok(())
     }
 }
@@ -0,0 +1,422 @@
+use std::collections::hash_map::Entry;
+use std::collections::BinaryHeap;
+use std::collections::HashMap;
+use std::env;
+use std::io;
+use std::io::Write as _;
+
+use anyhow::anyhow;
+use structopt::StructOpt;
+
+use crate::object;
+use crate::revision;
+
+/// Maximum number of branches compared at once, one per bit of a `u32`.
+const MAX_BRANCHES: usize = 32;
+
+/// Show which of several branches contain each of their recent commits,
+/// back to the first commit they all share.
+///
+/// Each commit is listed with one column per branch, marked `*` for the
+/// current branch, `+` for other branches, and `-` for merge commits.
+#[derive(StructOpt)]
+pub struct Configuration {
+    /// Branches or revisions to compare.
+    ///
+    /// Defaults to all local branches.
+    revisions: Vec<String>,
+}
+
+impl Configuration {
+    pub fn run(self) -> anyhow::Result<()> {
+        let root = env::current_dir()?;
+        let repository = crate::Repository::new(root);
+        let references = repository.references();
+
+        let names = match self.revisions.is_empty() {
+            false => self.revisions,
+            true => references
+                .branches()?
+                .map(|branch| branch.map(|(name, _)| name))
+                .collect::<anyhow::Result<_>>()?,
+        };
+
+        if names.len() > MAX_BRANCHES {
+            return Err(anyhow!(
+                "Cannot compare more than {} branches",
+                MAX_BRANCHES
+            ));
+        }
+
+        let mut heads = Vec::with_capacity(names.len());
+        for name in names {
+            let id = name
+                .parse::<revision::Revision>()?
+                .resolve(&repository)?
+                .ok_or_else(|| anyhow!("Not a valid object name {}", name))?
+                .peel_to_commit(&repository.database()?)?;
+            heads.push((name, id));
+        }
+
+        let show_branch = ShowBranch {
+            database: repository.database()?,
+            current: references.current_branch()?,
+        };
+        show_branch.run(&heads)
+    }
+}
+
+struct ShowBranch {
+    database: crate::Database,
+    /// Name of the branch `HEAD` points to, if any.
+    current: Option<String>,
+}
+
+/// Name of a commit relative to a branch, e.g. `main~2^2`.
+#[derive(Clone, Debug)]
+struct Name {
+    head: String,
+    /// Number of first parents followed from `head`.
+    generation: usize,
+}
+
+impl Name {
+    fn display(&self) -> String {
+        match self.generation {
+            0 => self.head.clone(),
+            1 => format!("{}^", self.head),
+            generation => format!("{}~{}", self.head, generation),
+        }
+    }
+}
+
+impl ShowBranch {
+    fn run(&self, heads: &[(String, object::Id)]) -> anyhow::Result<()> {
+        let all = match heads.len() {
+            MAX_BRANCHES => u32::MAX,
+            len => (1 << len) - 1,
+        };
+
+        let mut commits = HashMap::new();
+        for (_, id) in heads {
+            if let Entry::Vacant(entry) = commits.entry(*id) {
+                entry.insert(self.database.load_commit(id)?);
+            }
+        }
+
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        let column = heads
+            .iter()
+            .position(|(name, _)| Some(name) == self.current.as_ref());
+
+        // A single branch is listed without a header or columns.
+        let columns = heads.len() > 1;
+
+        for (index, (name, id)) in heads.iter().enumerate().filter(|_| columns) {
+            writeln!(
+                stdout,
+                "{}{} [{}] {}",
+                " ".repeat(index),
+                match Some(index) == column {
+                    true => '*',
+                    false => '!',
+                },
+                name,
+                commits[id].title(),
+            )?;
+        }
+        if columns {
+            writeln!(stdout, "{}", "-".repeat(heads.len()))?;
+        }
+
+        let rows = self.walk(heads, all, &mut commits)?;
+        let names = Self::name(heads, &rows, &commits);
+
+        for (id, flags) in &rows {
+            let commit = &commits[id];
+            let is_merge_point = *flags == all;
+            let is_merge = commit.parents().len() > 1;
+
+            // Like `git`, skip merges that only one branch contains, unless
+            // they are the tip of a branch.
+            if columns
+                && is_merge
+                && flags.count_ones() == 1
+                && !heads.iter().any(|(_, head)| head == id)
+            {
+                continue;
+            }
+
+            for index in (0..heads.len()).filter(|_| columns) {
+                let mark = match (flags >> index & 1 == 1, is_merge) {
+                    (false, _) => ' ',
+                    (true, true) => '-',
+                    (true, false) if Some(index) == column => '*',
+                    (true, false) => '+',
+                };
+                write!(stdout, "{}", mark)?;
+            }
+            if columns {
+                write!(stdout, " ")?;
+            }
+
+            match names.get(id) {
+                Some(name) => writeln!(stdout, "[{}] {}", name.display(), commit.title())?,
+                None => writeln!(stdout, "[{}] {}", &id.to_string()[..7], commit.title())?,
+            }
+
+            if is_merge_point {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// List commits children first, along with the set of branches
+    /// containing each, stopping once every remaining commit is contained by
+    /// all branches.
+    fn walk(
+        &self,
+        heads: &[(String, object::Id)],
+        all: u32,
+        commits: &mut HashMap<object::Id, object::Commit>,
+    ) -> anyhow::Result<Vec<(object::Id, u32)>> {
+        let mut flags = HashMap::<object::Id, u32>::new();
+        let mut queue = BinaryHeap::new();
+        let mut visited = Vec::new();
+
+        for (index, (_, id)) in heads.iter().enumerate() {
+            *flags.entry(*id).or_default() |= 1 << index;
+            queue.push((commits[id].committer().time().timestamp(), *id));
+        }
+
+        while queue.iter().any(|(_, id)| flags[id] != all) {
+            let (_, id) = queue.pop().expect("[INTERNAL ERROR]: non-empty queue");
+            if visited.contains(&id) {
+                continue;
+            }
+            visited.push(id);
+
+            // Commits are visited newest first, but clock skew can mean a
+            // visited commit picks up more flags later, so pass them all the
+            // way down through visited ancestors.
+            let mut stack = vec![id];
+            while let Some(id) = stack.pop() {
+                let flag = flags[&id];
+                for parent in commits[&id].parents().to_vec() {
+                    let current = flags.entry(parent).or_default();
+                    if *current & flag == flag {
+                        continue;
+                    }
+                    *current |= flag;
+                    if let Entry::Vacant(entry) = commits.entry(parent) {
+                        entry.insert(self.database.load_commit(&parent)?);
+                    }
+                    match visited.contains(&parent) {
+                        true => stack.push(parent),
+                        false => {
+                            queue.push((commits[&parent].committer().time().timestamp(), parent))
+                        }
+                    }
+                }
+            }
+        }
+
+        // Keep the remaining common commits, so the output can end with the
+        // newest of them.
+        while let Some((_, id)) = queue.pop() {
+            if !visited.contains(&id) {
+                visited.push(id);
+            }
+        }
+
+        let sorted = Self::sort(&visited, commits);
+        Ok(sorted.into_iter().map(|id| (id, flags[&id])).collect())
+    }
+
+    /// Sort `ids` so that children come before their parents. Like `git`'s
+    /// graph order, each commit's newly unblocked parents are shown next,
+    /// which keeps the commits of each branch together.
+    fn sort(ids: &[object::Id], commits: &HashMap<object::Id, object::Commit>) -> Vec<object::Id> {
+        let mut children = ids.iter().map(|id| (*id, 0)).collect::<HashMap<_, usize>>();
+        for id in ids {
+            for parent in commits[id].parents() {
+                if let Some(count) = children.get_mut(parent) {
+                    *count += 1;
+                }
+            }
+        }
+
+        // Pop tips in their original order.
+        let mut stack = ids
+            .iter()
+            .rev()
+            .filter(|id| children[id] == 0)
+            .copied()
+            .collect::<Vec<_>>();
+        let mut sorted = Vec::with_capacity(ids.len());
+
+        while let Some(id) = stack.pop() {
+            sorted.push(id);
+            for parent in commits[&id].parents() {
+                if let Some(count) = children.get_mut(parent) {
+                    *count -= 1;
+                    if *count == 0 {
+                        stack.push(*parent);
+                    }
+                }
+            }
+        }
+
+        sorted
+    }
+
+    /// Name each listed commit after the first listed commit it descends
+    /// from, preferring first-parent chains, in the same order as `git`.
+    fn name(
+        heads: &[(String, object::Id)],
+        rows: &[(object::Id, u32)],
+        commits: &HashMap<object::Id, object::Commit>,
+    ) -> HashMap<object::Id, Name> {
+        let mut names = HashMap::new();
+        for (head, id) in heads {
+            names.entry(*id).or_insert_with(|| Name {
+                head: head.clone(),
+                generation: 0,
+            });
+        }
+
+        // Extend names down first-parent chains, returning how many new
+        // commits were named.
+        let follow = |names: &mut HashMap<object::Id, Name>, mut id: object::Id| {
+            let mut count = 0;
+            while let (Some(name), Some(parent)) = (
+                names.get(&id).cloned(),
+                commits.get(&id).and_then(|commit| commit.parent()),
+            ) {
+                if names.contains_key(parent) {
+                    break;
+                }
+                names.insert(
+                    *parent,
+                    Name {
+                        head: name.head,
+                        generation: name.generation + 1,
+                    },
+                );
+                count += 1;
+                id = *parent;
+            }
+            count
+        };
+
+        while rows
+            .iter()
+            .map(|(id, _)| follow(&mut names, *id))
+            .sum::<usize>()
+            > 0
+        {}
+
+        // Then name other parents of merges as e.g. `main~2^2`, and follow
+        // their first-parent chains in turn.
+        loop {
+            let mut count = 0;
+            for (id, _) in rows {
+                let name = match names.get(id) {
+                    Some(name) => name.display(),
+                    None => continue,
+                };
+                let parents = commits.get(id).map(|commit| commit.parents().to_vec());
+                for (nth, parent) in parents.into_iter().flatten().enumerate() {
+                    if names.contains_key(&parent) {
+                        continue;
+                    }
+                    let head = match nth {
+                        0 => format!("{}^", name),
+                        nth => format!("{}^{}", name, nth + 1),
+                    };
+                    names.insert(
+                        parent,
+                        Name {
+                            head,
+                            generation: 0,
+                        },
+                    );
+                    count += 1 + follow(&mut names, parent);
+                }
+            }
+            if count == 0 {
+                break;
+            }
+        }
+
+        names
+    }
+}
+
+#[test]
+fn walk() -> anyhow::Result<()> {
+    let repository = crate::Repository::memory(std::path::PathBuf::new());
+    let database = repository.database()?;
+
+    let mut files = std::collections::BTreeMap::new();
+    files.insert(
+        crate::util::PathBuf(std::path::PathBuf::from("a")),
+        crate::diff::tree::Entry {
+            id: database.store(&crate::Object::Blob(object::Blob::new(Vec::new())))?,
+            mode: crate::meta::Mode::Regular,
+        },
+    );
+    let tree = crate::diff::tree::unflatten(&database, &files)?;
+    let mut time = 1600000000;
+    let mut commit = |parents: Vec<object::Id>, message: &str| -> anyhow::Result<object::Id> {
+        time += 1;
+        let person = object::Person::new(
+            String::from("A U Thor"),
+            String::from("author@example.com"),
+            object::Person::parse_time(&format!("@{} +0000", time))?,
+        );
+        let commit = object::Commit::new(tree, parents, person.clone(), person, message.to_owned());
+        Ok(database.store(&crate::Object::Commit(commit))?)
+    };
+
+    let base = commit(vec![], "base")?;
+    let shared = commit(vec![base], "shared")?;
+    let topic = commit(vec![shared], "topic")?;
+    let main = commit(vec![shared], "main")?;
+    let merge = commit(vec![main, topic], "merge")?;
+    let heads = vec![
+        (String::from("main"), merge),
+        (String::from("topic"), topic),
+    ];
+
+    let show_branch = ShowBranch {
+        database: repository.database()?,
+        current: Some(String::from("main")),
+    };
+    let mut commits = HashMap::new();
+    for (_, id) in &heads {
+        commits.insert(*id, database.load_commit(id)?);
+    }
+    let rows = show_branch.walk(&heads, 0b11, &mut commits)?;
+    let names = ShowBranch::name(&heads, &rows, &commits);
+    let named = rows
+        .iter()
+        .map(|(id, flags)| (names[id].display(), *flags))
+        .collect::<Vec<_>>();
+
+    // The merged branch comes first, and the walk stops at the newest
+    // commit both contain, leaving out `base`.
+    assert_eq!(
+        named,
+        vec![
+            (String::from("main"), 0b01),
+            (String::from("topic"), 0b11),
+            (String::from("main^"), 0b01),
+            (String::from("main~2"), 0b11),
+        ],
+    );
+    Ok(())
+}
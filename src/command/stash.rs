@@ -0,0 +1,385 @@
+use std::env;
+use std::io;
+use std::io::Write as _;
+
+use anyhow::anyhow;
+use structopt::StructOpt;
+
+use crate::diff;
+use crate::merge;
+use crate::meta;
+use crate::migration;
+use crate::object;
+
+/// Save changes to tracked files away, resetting the index and workspace to
+/// `HEAD`, and restore them later.
+///
+/// Each stash entry is a commit whose tree holds the workspace, with `HEAD`
+/// and a commit of the index as parents. The newest is `refs/stash`, and
+/// older ones are found through its reflog as `stash@{<n>}`.
+#[derive(StructOpt)]
+pub struct Configuration {
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(StructOpt)]
+enum Command {
+    /// Save local changes as a new stash entry (the default).
+    Push {
+        /// Describe the entry, instead of naming the commit it was made on.
+        #[structopt(short, long)]
+        message: Option<String>,
+    },
+    /// Apply a stash entry to the workspace, then drop it.
+    Pop {
+        /// Defaults to `stash@{0}`.
+        stash: Option<String>,
+    },
+    /// List stash entries, most recent first.
+    List,
+    /// Remove a stash entry without applying it.
+    Drop {
+        /// Defaults to `stash@{0}`.
+        stash: Option<String>,
+    },
+}
+
+const STASH: &str = "refs/stash";
+
+impl Configuration {
+    pub fn run(self) -> anyhow::Result<()> {
+        let root = env::current_dir()?;
+        let repository = crate::Repository::new(root);
+        let stash = Stash {
+            database: repository.database()?,
+            references: repository.references(),
+        };
+
+        match self.command.unwrap_or(Command::Push { message: None }) {
+            Command::List => stash.list(),
+            Command::Drop { stash: name } => stash.drop(name.as_deref()),
+            Command::Push { message } => {
+                let config = repository.config()?;
+                stash.push(
+                    repository.index()?,
+                    &repository.workspace()?,
+                    config.parse("core.checkStat")?.unwrap_or_default(),
+                    &config.committer()?,
+                    message.as_deref(),
+                )
+            }
+            Command::Pop { stash: name } => {
+                let check_stat = repository
+                    .config()?
+                    .parse("core.checkStat")?
+                    .unwrap_or_default();
+                stash.pop(
+                    repository.index()?,
+                    &repository.workspace()?,
+                    check_stat,
+                    name.as_deref(),
+                )
+            }
+        }
+    }
+}
+
+struct Stash {
+    database: crate::Database,
+    references: crate::References,
+}
+
+impl Stash {
+    fn list(&self) -> anyhow::Result<()> {
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        for (n, entry) in self.references.read_log(STASH)?.iter().rev().enumerate() {
+            writeln!(stdout, "stash@{{{}}}: {}", n, entry.message)?;
+        }
+        Ok(())
+    }
+
+    fn push(
+        &self,
+        mut index: crate::Index,
+        workspace: &crate::Workspace,
+        check_stat: meta::CheckStat,
+        committer: &object::Person,
+        message: Option<&str>,
+    ) -> anyhow::Result<()> {
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        if index.is_conflicted() {
+            return Err(anyhow!("Cannot save the current state with unmerged files"));
+        }
+
+        let head = self
+            .references
+            .read_head()?
+            .ok_or_else(|| anyhow!("You do not have the initial commit yet"))?;
+        let commit = self.database.load_commit(&head)?;
+
+        let staged = index.write_tree(&self.database)?;
+        let unstaged = migration::snapshot(&self.database, &index, workspace, check_stat)?;
+        if staged == *commit.tree() && unstaged == *commit.tree() {
+            writeln!(stdout, "No local changes to save")?;
+            return Ok(());
+        }
+
+        let branch = self
+            .references
+            .current_branch()?
+            .unwrap_or_else(|| String::from("(no branch)"));
+        let title = format!("{}: {} {}", branch, &head.to_string()[..7], commit.title());
+        let message = match message {
+            Some(message) => format!("On {}: {}", branch, message),
+            None => format!("WIP on {}", title),
+        };
+
+        let snapshot = |tree, parents, message: String| {
+            self.database
+                .store(&crate::Object::Commit(object::Commit::new(
+                    tree,
+                    parents,
+                    committer.clone(),
+                    committer.clone(),
+                    message,
+                )))
+                .map_err(anyhow::Error::from)
+        };
+        let staged = snapshot(staged, vec![head], format!("index on {}\n", title))?;
+        let id = snapshot(unstaged, vec![head, staged], message.clone())?;
+        self.references
+            .update_ref(STASH, &id, committer, &message)?;
+
+        migration::reset(
+            &self.database,
+            &mut index,
+            workspace,
+            check_stat,
+            commit.tree(),
+        )?;
+        index.commit()?;
+
+        writeln!(
+            stdout,
+            "Saved working directory and index state {}",
+            message
+        )?;
+        Ok(())
+    }
+
+    fn pop(
+        &self,
+        mut index: crate::Index,
+        workspace: &crate::Workspace,
+        check_stat: meta::CheckStat,
+        name: Option<&str>,
+    ) -> anyhow::Result<()> {
+        let (_, id) = self.find(name)?;
+        if index.is_conflicted() {
+            return Err(anyhow!("Cannot apply a stash with unmerged files"));
+        }
+
+        // Merge the stashed workspace into the index, relative to the commit
+        // it was made on.
+        let stash = self.database.load_commit(&id)?;
+        let base = stash
+            .parent()
+            .ok_or_else(|| anyhow!("{} is not a stash-like commit", id))?
+            .peel_to_tree(&self.database)?;
+        let ours = index.write_tree(&self.database)?;
+        let outcome = merge::trees(
+            &self.database,
+            Some(&base),
+            &ours,
+            stash.tree(),
+            "Updated upstream",
+            "Stashed changes",
+        )?;
+
+        let ours = diff::tree::flatten(&self.database, &ours)?;
+        let changes = diff::tree::diff_files(&ours, &outcome.files);
+        let changed = changes.keys().cloned().collect::<Vec<_>>();
+        migration::Migration::new(&self.database, &mut index, workspace, check_stat, changes)
+            .operation(migration::Operation::Merge)
+            .apply()?;
+
+        // Like `git stash apply`, leave changes unstaged, except that new
+        // files stay in the index so they aren't forgotten.
+        for path in changed {
+            if let (Some(entry), false) = (ours.get(&path), outcome.conflicts.contains_key(&path)) {
+                let size = self.database.read_header(&entry.id)?.len;
+                index.reset(path.to_path_buf(), entry.id, entry.mode, size as u32);
+            }
+        }
+
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        for path in &outcome.merged {
+            writeln!(stdout, "Auto-merging {}", path.display())?;
+        }
+        for (path, conflict) in &outcome.conflicts {
+            writeln!(
+                stdout,
+                "{}",
+                conflict.describe(path, "Updated upstream", "Stashed changes")
+            )?;
+            index.insert_conflict(
+                path.to_path_buf(),
+                conflict
+                    .stages()
+                    .map(|stage| stage.map(|entry| (entry.id, entry.mode))),
+            );
+        }
+        index.commit()?;
+
+        if !outcome.is_clean() {
+            return Err(anyhow!(
+                "The stash entry is kept in case you need it again."
+            ));
+        }
+        self.drop(name)
+    }
+
+    fn drop(&self, name: Option<&str>) -> anyhow::Result<()> {
+        let (n, id) = self.find(name)?;
+
+        let mut log = self.references.read_log(STASH)?;
+        log.remove(log.len() - 1 - n);
+        match log.last() {
+            None => {
+                self.references.store().delete(STASH)?;
+            }
+            Some(newest) => {
+                self.references
+                    .store()
+                    .write(STASH, &crate::references::Target::Direct(newest.new))?;
+                self.references.store().write_log(STASH, &log)?;
+            }
+        }
+
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        writeln!(
+            stdout,
+            "Dropped {} ({})",
+            name.unwrap_or("refs/stash@{0}"),
+            id
+        )?;
+        Ok(())
+    }
+
+    /// Find stash entry `name`, given as `stash@{<n>}` or just `<n>`,
+    /// returning its position and id.
+    fn find(&self, name: Option<&str>) -> anyhow::Result<(usize, object::Id)> {
+        let log = self.references.read_log(STASH)?;
+        if log.is_empty() {
+            return Err(anyhow!("No stash entries found."));
+        }
+
+        let n = match name {
+            None => 0,
+            Some(name) => name
+                .strip_prefix("refs/")
+                .unwrap_or(name)
+                .strip_prefix("stash@{")
+                .and_then(|name| name.strip_suffix('}'))
+                .unwrap_or(name)
+                .parse::<usize>()
+                .map_err(|_| anyhow!("{} is not a valid reference", name))?,
+        };
+
+        match log.len().checked_sub(n + 1) {
+            Some(index) => Ok((n, log[index].new)),
+            None => Err(anyhow!("stash@{{{}}} is not a valid reference", n)),
+        }
+    }
+}
+
+#[test]
+fn round_trip() -> anyhow::Result<()> {
+    use std::fs;
+    use std::path;
+
+    let root = crate::util::TempDir::new();
+    let mut repository = crate::Repository::new(root.to_path_buf());
+    repository.init()?;
+
+    let database = repository.database()?;
+    let workspace = repository.workspace()?;
+    let check_stat = meta::CheckStat::default();
+    let person = object::Person::new(
+        String::from("A U Thor"),
+        String::from("author@example.com"),
+        object::Person::parse_time("@1600000000 +0000")?,
+    );
+    let one = database.store(&crate::Object::Blob(object::Blob::new(b"1".to_vec())))?;
+
+    let mut files = std::collections::BTreeMap::new();
+    files.insert(
+        crate::util::PathBuf(path::PathBuf::from("a")),
+        diff::tree::Entry {
+            id: one,
+            mode: meta::Mode::Regular,
+        },
+    );
+    let tree = diff::tree::unflatten(&database, &files)?;
+    let commit = object::Commit::new(
+        tree,
+        vec![],
+        person.clone(),
+        person.clone(),
+        String::from("a"),
+    );
+    repository
+        .references()
+        .write_head(&database.store(&crate::Object::Commit(commit))?)?;
+
+    // `a` is changed but not staged, and `b` is new and staged.
+    let mut index = repository.index()?;
+    migration::reset(&database, &mut index, &workspace, check_stat, &tree)?;
+    fs::write(root.join("a"), b"2")?;
+    fs::write(root.join("b"), b"1")?;
+    index.insert(
+        workspace.metadata(path::Path::new("b"))?,
+        one,
+        path::PathBuf::from("b"),
+    );
+    index.commit()?;
+
+    let stash = Stash {
+        database: repository.database()?,
+        references: repository.references(),
+    };
+    let state = || -> anyhow::Result<_> {
+        let index = repository.index()?;
+        let staged = ["a", "b"]
+            .iter()
+            .map(|name| index.get(path::Path::new(name)).map(|entry| *entry.id()))
+            .collect::<Vec<_>>();
+        Ok((fs::read(root.join("a"))?, root.join("b").exists(), staged))
+    };
+
+    stash.push(
+        repository.index()?,
+        &workspace,
+        check_stat,
+        &person,
+        Some("wip"),
+    )?;
+    let pushed = state()?;
+    let entries = repository.references().read_log(STASH)?.len();
+
+    stash.pop(repository.index()?, &workspace, check_stat, None)?;
+    let popped = state()?;
+    let dropped = repository.references().read(STASH)?;
+
+    assert_eq!(pushed, (b"1".to_vec(), false, vec![Some(one), None]));
+    assert_eq!(entries, 1);
+    // Changes come back unstaged, except for new files.
+    assert_eq!(popped, (b"2".to_vec(), true, vec![Some(one), Some(one)]));
+    assert_eq!(dropped, None);
+    Ok(())
+}
@@ -3,16 +3,24 @@ use std::collections::btree_map;
 use std::collections::BTreeMap;
 use std::collections::BTreeSet;
 use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
 use std::io::Write as _;
 use std::iter;
 use std::ops;
 use std::path;
 
+use anyhow::anyhow;
 use structopt::StructOpt;
 use termcolor::WriteColor as _;
 
+use crate::diff::rename;
 use crate::meta;
 use crate::object;
+use crate::platform::OsStrExt as _;
+use crate::prefix;
+use crate::state;
 use crate::util;
 use crate::util::Tap as _;
 use crate::workspace;
@@ -21,80 +29,194 @@ use crate::workspace;
 pub struct Configuration {
     #[structopt(long)]
     porcelain: bool,
+
+    /// Show the status in the short format of `--porcelain`, but in color
+    /// and with paths relative to the current directory. Overrides
+    /// `status.short`.
+    #[structopt(short, long, overrides_with = "long")]
+    short: bool,
+
+    /// Show the status in the long format. Overrides `status.short`.
+    #[structopt(long, overrides_with = "short")]
+    long: bool,
+
+    /// Detect staged renames, optionally with a minimum similarity such as
+    /// `-M90%`. Overrides `status.renames`.
+    #[structopt(short = "M", long)]
+    find_renames: Option<Option<rename::Score>>,
+
+    /// Do not detect renames. Overrides `status.renames`.
+    #[structopt(long, conflicts_with = "find-renames")]
+    no_renames: bool,
 }
 
 impl Configuration {
     pub fn run(self) -> anyhow::Result<()> {
-        let root = env::current_dir()?;
-        let repository = crate::Repository::new(root);
+        let (repository, prefix) = crate::Repository::discover(&env::current_dir()?)?;
         let stdout = termcolor::StandardStream::stdout(match isatty::stdout_isatty() {
             true => termcolor::ColorChoice::Always,
             false => termcolor::ColorChoice::Never,
         });
 
+        // Check for leftover state first, since a stale `index.lock` would
+        // otherwise fail with a less helpful error below.
+        let leftovers = repository.audit()?;
+        if let Some(lock) = leftovers.iter().find(|leftover| {
+            matches!(leftover, state::Leftover::Lock { path, .. } if path == path::Path::new("index.lock"))
+        }) {
+            return Err(anyhow!(
+                "Unable to lock the index. {}\n{}",
+                lock,
+                lock.hint()
+            ));
+        }
+
+        let config = repository.config()?;
         let status = Status {
-            database: repository.database(),
+            git: repository.root().join(".git"),
+            prefix,
+            database: repository.database()?,
             index: repository.index()?,
             references: repository.references(),
-            workspace: repository.workspace(),
+            workspace: repository.workspace()?,
+            leftovers,
+            check_stat: config.parse("core.checkStat")?.unwrap_or_default(),
+            renames: rename::Options::configure(
+                &config,
+                "status",
+                self.find_renames,
+                self.no_renames,
+            )?,
             stdout: stdout.lock(),
         };
 
-        status.run(self.porcelain)?;
+        let format = match self.porcelain {
+            true => Format::Porcelain,
+            false => match config.flag("status.short", self.short, self.long)? {
+                Some(true) => Format::Short,
+                Some(false) | None => Format::Long,
+            },
+        };
+        status.run(format)?;
 
         Ok(())
     }
 }
 
+/// Write `text` in `color`, or plainly without one.
+fn paint<W: termcolor::WriteColor>(
+    writer: &mut W,
+    color: Option<termcolor::Color>,
+    text: &str,
+) -> io::Result<()> {
+    match color {
+        None => write!(writer, "{}", text),
+        Some(color) => {
+            writer.set_color(termcolor::ColorSpec::new().set_fg(Some(color)))?;
+            write!(writer, "{}", text)?;
+            writer.reset()
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Format {
+    Long,
+    Short,
+    Porcelain,
+}
+
 struct Status<'a> {
+    git: path::PathBuf,
+    /// Location of the current directory, which paths are shown relative to
+    /// in the long format.
+    prefix: prefix::Prefix,
     database: crate::Database,
     index: crate::Index,
     workspace: crate::Workspace,
     references: crate::References,
+    leftovers: Vec<state::Leftover>,
+    check_stat: meta::CheckStat,
+    /// How to detect staged renames, if at all.
+    renames: Option<rename::Options>,
     stdout: termcolor::StandardStreamLock<'a>,
 }
 
 impl Status<'_> {
-    fn run(mut self, porcelain: bool) -> anyhow::Result<()> {
+    fn run(mut self, format: Format) -> anyhow::Result<()> {
         let head_commit = match self.references.read_head()? {
             None => return Ok(()),
             Some(head_commit) => head_commit,
         };
 
-        let head = self.walk_head(&head_commit)?;
+        let head = self.walk_head(&head_commit.peel_to_tree(&self.database)?)?;
         let workspace = self.walk_workspace(path::Path::new("."))?;
         let changes = self.detect_changes(&head, &workspace)?;
 
-        if porcelain {
-            self.print_porcelain(&changes, &workspace)?;
-        } else {
-            self.print_pretty(&changes, &workspace)?;
+        match format {
+            Format::Long => self.print_pretty(&changes, &workspace)?,
+            Format::Short => self.print_short(&changes, &workspace, true)?,
+            Format::Porcelain => self.print_short(&changes, &workspace, false)?,
         }
 
+        // Save refreshed stat information so files aren't hashed again.
+        self.index.commit()?;
         Ok(())
     }
 
-    fn print_porcelain(
+    /// Print one line per changed file with a two-letter status code. For
+    /// people, `relative` colors the codes and shows paths relative to the
+    /// current directory; for scripts, paths are relative to the root.
+    fn print_short(
         &mut self,
         changes: &Changes,
         workspace: &WorkspaceState,
+        relative: bool,
     ) -> anyhow::Result<()> {
-        for (path, index_head_change, workspace_index_change) in changes {
-            writeln!(
-                &mut self.stdout,
-                "{}{} {}",
-                index_head_change
-                    .map(IndexHeadChange::into_porcelain)
-                    .unwrap_or(" "),
-                workspace_index_change
-                    .map(WorkspaceIndexChange::into_porcelain)
-                    .unwrap_or(" "),
-                path.display(),
-            )?;
+        let mut lines = changes
+            .into_iter()
+            .map(|(path, index_head_change, workspace_index_change)| {
+                let code = [
+                    index_head_change
+                        .map(IndexHeadChange::into_porcelain)
+                        .unwrap_or(" "),
+                    workspace_index_change
+                        .map(WorkspaceIndexChange::into_porcelain)
+                        .unwrap_or(" "),
+                ];
+                (path, code)
+            })
+            .chain(changes.unmerged.iter().map(|(path, unmerged)| {
+                let code = unmerged.into_porcelain();
+                (path.as_path(), [&code[..1], &code[1..]])
+            }))
+            .collect::<Vec<_>>();
+        lines.sort_by_key(|(path, _)| path.as_os_str().as_bytes());
+
+        let prefix = self.prefix.clone();
+        let display = |path: &path::Path| match relative {
+            true => prefix.display(path),
+            false => path.to_path_buf(),
+        };
+        let (green, red) = match relative {
+            true => (Some(termcolor::Color::Green), Some(termcolor::Color::Red)),
+            false => (None, None),
+        };
+        let unmerged = |path: &path::Path| changes.unmerged.contains_key(&path as &dyn util::Key);
+
+        for (path, [staged, unstaged]) in lines {
+            let staged_color = match unmerged(path) {
+                true => red,
+                false => green,
+            };
+            paint(&mut self.stdout, staged_color, staged)?;
+            paint(&mut self.stdout, red, unstaged)?;
+            writeln!(&mut self.stdout, " {}", changes.label(path, display))?;
         }
 
         for path in &workspace.untracked {
-            writeln!(&mut self.stdout, "?? {}", path.display())?;
+            paint(&mut self.stdout, red, "??")?;
+            writeln!(&mut self.stdout, " {}", display(path).display())?;
         }
 
         Ok(())
@@ -105,12 +227,42 @@ impl Status<'_> {
         changes: &Changes,
         workspace: &WorkspaceState,
     ) -> anyhow::Result<()> {
+        let prefix = self.prefix.clone();
+        let conflicted = !changes.unmerged.is_empty();
+        for leftover in &self.leftovers {
+            let (message, hints) = match leftover {
+                state::Leftover::Lock { .. } => {
+                    (leftover.to_string(), vec![leftover.hint().to_owned()])
+                }
+                _ => describe(&self.git, &self.references, leftover, conflicted)?,
+            };
+            writeln!(&mut self.stdout, "{}", message)?;
+            for hint in hints {
+                writeln!(&mut self.stdout, "  {}", hint)?;
+            }
+            writeln!(&mut self.stdout)?;
+        }
+
         self.print_change_set(
             termcolor::Color::Green,
             |change| Some(change.into_pretty()),
             "Changes to be committed:\n  \
                 (use \"git restore --staged <file>...\" to unstage)",
-            &changes.index_head,
+            changes
+                .index_head
+                .iter()
+                .map(|(path, change)| (changes.label(path, |path| prefix.display(path)), change)),
+        )?;
+
+        self.print_change_set(
+            termcolor::Color::Red,
+            |unmerged| Some(unmerged.into_pretty()),
+            "Unmerged paths:\n  \
+                (use \"git add <file>...\" to mark resolution)",
+            changes
+                .unmerged
+                .iter()
+                .map(|(path, unmerged)| (prefix.display(path).display().to_string(), unmerged)),
         )?;
 
         self.print_change_set(
@@ -119,7 +271,10 @@ impl Status<'_> {
             "Changes not staged for commit:\n  \
                 (use \"git add/rm <file>...\" to update what will be committed)\n  \
                 (use \"git restore <file>...\" to discard changes in working directory)",
-            &changes.workspace_index,
+            changes
+                .workspace_index
+                .iter()
+                .map(|(path, change)| (prefix.display(path).display().to_string(), change)),
         )?;
 
         self.print_change_set(
@@ -127,14 +282,17 @@ impl Status<'_> {
             |()| None,
             "Untracked files:\n  \
                 (use \"git add <file>...\" to include in what will be committed)",
-            workspace.untracked.iter().map(|path| (path, ())),
+            workspace
+                .untracked
+                .iter()
+                .map(|path| (prefix.display(path).display().to_string(), ())),
         )?;
 
         if !changes.index_head.is_empty() {
             return Ok(());
         }
 
-        if !changes.workspace_index.is_empty() {
+        if !changes.workspace_index.is_empty() || !changes.unmerged.is_empty() {
             writeln!(
                 &mut self.stdout,
                 "no changes added to commit (use \"git add\" and/or \"git commit -a\")"
@@ -151,15 +309,16 @@ impl Status<'_> {
         Ok(())
     }
 
-    fn print_change_set<'a, 'b, I, T>(
+    fn print_change_set<I, D, T>(
         &mut self,
         color: termcolor::Color,
         display: fn(T) -> Option<&'static str>,
-        message: &'a str,
+        message: &str,
         into_iter: I,
     ) -> anyhow::Result<()>
     where
-        I: IntoIterator<Item = (&'b util::PathBuf, T)>,
+        I: IntoIterator<Item = (D, T)>,
+        D: fmt::Display,
     {
         let mut iter = into_iter.into_iter().peekable();
         if iter.peek().is_none() {
@@ -168,14 +327,14 @@ impl Status<'_> {
 
         writeln!(&mut self.stdout, "{}", message)?;
         self.stdout
-            .set_color(&termcolor::ColorSpec::new().set_fg(Some(color)))?;
+            .set_color(termcolor::ColorSpec::new().set_fg(Some(color)))?;
 
         for (path, status) in iter {
             match display(status) {
                 Some(status) => write!(&mut self.stdout, "\t{:12}", status)?,
                 None => write!(&mut self.stdout, "\t")?,
             }
-            writeln!(&mut self.stdout, "{}", path.display())?;
+            writeln!(&mut self.stdout, "{}", path)?;
         }
 
         writeln!(&mut self.stdout)?;
@@ -186,34 +345,38 @@ impl Status<'_> {
     fn walk_head(&self, tree: &object::Id) -> anyhow::Result<HeadState> {
         fn recurse(
             database: &crate::Database,
+            index: &crate::Index,
             tree: &object::Id,
             state: &mut HeadState,
             prefix: &mut path::PathBuf,
         ) -> anyhow::Result<()> {
-            match database.load(tree)? {
-                crate::Object::Blob(_) => unreachable!(),
-                crate::Object::Commit(commit) => recurse(database, commit.tree(), state, prefix),
-                crate::Object::Tree(tree) => {
-                    for node in tree {
-                        if node.mode.is_directory() {
-                            prefix.push(&node.path);
-                            recurse(database, &node.id, state, prefix)?;
-                            prefix.pop();
-                        } else {
-                            state.insert(
-                                util::PathBuf(prefix.join(node.path)),
-                                (node.id, node.mode),
-                            );
-                        }
-                    }
-                    Ok(())
+            // A directory whose cached tree matches has the same files as the
+            // index, so there's no need to load its trees.
+            if index.cached_tree(prefix) == Some(tree) {
+                for entry in index.entries_under(prefix) {
+                    state.insert(
+                        util::PathBuf(entry.path().to_path_buf()),
+                        (*entry.id(), *entry.metadata().mode()),
+                    );
                 }
+                return Ok(());
             }
+
+            for node in database.load_tree(tree)? {
+                if node.mode.is_directory() {
+                    prefix.push(&node.path);
+                    recurse(database, index, &node.id, state, prefix)?;
+                    prefix.pop();
+                } else {
+                    state.insert(util::PathBuf(prefix.join(node.path)), (node.id, node.mode));
+                }
+            }
+            Ok(())
         }
 
         let mut state = HeadState::default();
         let mut prefix = path::PathBuf::default();
-        recurse(&self.database, tree, &mut state, &mut prefix)?;
+        recurse(&self.database, &self.index, tree, &mut state, &mut prefix)?;
         Ok(state)
     }
 
@@ -291,15 +454,21 @@ impl Status<'_> {
         workspace: &WorkspaceState,
     ) -> anyhow::Result<Changes> {
         let mut changes = Changes::default();
-        let mut dirty = false;
+        let mut refreshed = Vec::new();
+        let timestamp = self.index.timestamp();
 
-        for entry in self.index.entries_mut() {
+        for entry in self.index.entries() {
             match head.get(&entry.path() as &dyn util::Key) {
                 Some((id, mode)) if mode == entry.metadata().mode() && id == entry.id() => (),
                 Some(_) => changes.insert_index_head(entry.path(), IndexHeadChange::Modified),
                 None => changes.insert_index_head(entry.path(), IndexHeadChange::Added),
             }
 
+            // Files outside the sparse-checkout cone are missing on purpose.
+            if entry.skip_worktree() {
+                continue;
+            }
+
             let metadata = match workspace.tracked.get(&entry.path() as &dyn util::Key) {
                 Some(metadata) => metadata,
                 None => {
@@ -309,18 +478,15 @@ impl Status<'_> {
             };
 
             let old = entry.metadata();
-            let new = metadata;
+            let new = &self.workspace.normalize(*metadata, old.mode);
 
-            if new.mode != old.mode || new.size != old.size {
+            // A size of zero may have been smudged by a racy write.
+            if new.mode != old.mode || (new.size != old.size && old.size != 0) {
                 changes.insert_workspace_index(entry.path(), WorkspaceIndexChange::Modified);
                 continue;
             }
 
-            if new.ctime == old.ctime
-                && new.ctime_nsec == old.ctime_nsec
-                && new.mtime == old.mtime
-                && new.mtime_nsec == old.mtime_nsec
-            {
+            if new.is_stat_clean(old, self.check_stat) && !entry.is_racy(timestamp) {
                 continue;
             }
 
@@ -333,8 +499,7 @@ impl Status<'_> {
                 .map(|bytes| object::Id::hash(&bytes))?;
 
             if id == *entry.id() {
-                entry.touch(*new);
-                dirty = true;
+                refreshed.push((entry.path().to_path_buf(), *new));
             } else {
                 changes.insert_workspace_index(entry.path(), WorkspaceIndexChange::Modified);
             }
@@ -345,13 +510,68 @@ impl Status<'_> {
             .filter(|path| !self.index.contains_file(path))
             .for_each(|path| changes.insert_index_head(path, IndexHeadChange::Deleted));
 
-        // TODO: can we hide this in `Index`?
-        if dirty {
-            self.index.touch();
+        if let Some(options) = self.renames {
+            self.detect_renames(head, &mut changes, options)?;
+        }
+
+        for (path, stages) in self.index.conflicts() {
+            let unmerged = match [&stages[0], &stages[1], &stages[2]].map(Option::is_some) {
+                [true, false, false] => Unmerged::BothDeleted,
+                [false, true, false] => Unmerged::AddedByUs,
+                [true, false, true] => Unmerged::DeletedByUs,
+                [false, false, true] => Unmerged::AddedByThem,
+                [true, true, false] => Unmerged::DeletedByThem,
+                [false, true, true] => Unmerged::BothAdded,
+                [true, true, true] | [false, false, false] => Unmerged::BothModified,
+            };
+            changes
+                .unmerged
+                .insert(path.to_path_buf().tap(util::PathBuf), unmerged);
+        }
+
+        for (path, metadata) in refreshed {
+            self.index.update_metadata(&path, metadata);
         }
 
         Ok(changes)
     }
+
+    /// Replace staged deletions and additions that look like the same file
+    /// with renames.
+    fn detect_renames(
+        &self,
+        head: &HeadState,
+        changes: &mut Changes,
+        options: rename::Options,
+    ) -> anyhow::Result<()> {
+        let staged = |expected: IndexHeadChange| {
+            changes
+                .index_head
+                .iter()
+                .filter(move |(_, change)| **change == expected)
+                .map(|(path, _)| path)
+        };
+
+        let deleted = staged(IndexHeadChange::Deleted)
+            .filter_map(|path| head.get(path).map(|(id, _)| (path.clone(), *id)))
+            .collect::<Vec<_>>();
+        let added = staged(IndexHeadChange::Added)
+            .filter_map(|path| {
+                self.index
+                    .get(path)
+                    .map(|entry| (path.clone(), *entry.id()))
+            })
+            .collect::<Vec<_>>();
+
+        for rename in rename::detect(&self.database, &deleted, &added, options)? {
+            changes.index_head.remove(&rename.old);
+            changes
+                .index_head
+                .insert(rename.new.clone(), IndexHeadChange::Renamed);
+            changes.renames.insert(rename.new, rename.old);
+        }
+        Ok(())
+    }
 }
 
 #[derive(Clone, Debug, Default)]
@@ -376,6 +596,116 @@ struct WorkspaceState {
     untracked: BTreeSet<util::PathBuf>,
 }
 
+/// Describe an operation in progress like `git status`, with hints on how to
+/// continue depending on whether conflicts remain.
+///
+/// Merges, cherry-picks, and reverts are concluded by `grit commit`, while
+/// rebases and bisects must be finished with `git`.
+fn describe(
+    git: &path::Path,
+    references: &crate::References,
+    leftover: &state::Leftover,
+    conflicted: bool,
+) -> anyhow::Result<(String, Vec<String>)> {
+    let short = |name: &str| -> anyhow::Result<String> {
+        Ok(references
+            .read(name)?
+            .map(|id| id.to_string()[..7].to_owned())
+            .unwrap_or_default())
+    };
+    let continuation = match conflicted {
+        true => "(fix conflicts and run \"grit commit\")",
+        false => "(all conflicts fixed: run \"grit commit\")",
+    };
+
+    let (message, hints) = match leftover {
+        state::Leftover::Merge if conflicted => (
+            String::from("You have unmerged paths."),
+            vec![
+                "(fix conflicts and run \"grit commit\")",
+                "(use \"grit doctor --clean\" to abandon the merge)",
+            ],
+        ),
+        state::Leftover::Merge => (
+            String::from("All conflicts fixed but you are still merging."),
+            vec!["(use \"grit commit\" to conclude merge)"],
+        ),
+        state::Leftover::CherryPick => (
+            format!(
+                "You are currently cherry-picking commit {}.",
+                short("CHERRY_PICK_HEAD")?
+            ),
+            vec![
+                continuation,
+                "(use \"grit doctor --clean\" to cancel the cherry-pick operation)",
+            ],
+        ),
+        state::Leftover::Revert => (
+            format!(
+                "You are currently reverting commit {}.",
+                short("REVERT_HEAD")?
+            ),
+            vec![
+                continuation,
+                "(use \"grit doctor --clean\" to cancel the revert operation)",
+            ],
+        ),
+        state::Leftover::Rebase { interactive } => {
+            let directory = git.join(match interactive {
+                true => "rebase-merge",
+                false => "rebase-apply",
+            });
+            let read = |file: &str| {
+                fs::read_to_string(directory.join(file))
+                    .map(|text| text.trim_end().to_owned())
+                    .ok()
+            };
+            let branch = read("head-name").and_then(|name| {
+                name.strip_prefix(crate::References::HEADS)
+                    .map(String::from)
+            });
+            let onto = read("onto").map(|onto| onto.chars().take(7).collect::<String>());
+            let message = match (branch, onto) {
+                (Some(branch), Some(onto)) => {
+                    format!(
+                        "You are currently rebasing branch '{}' on '{}'.",
+                        branch, onto
+                    )
+                }
+                _ => String::from("You are currently rebasing."),
+            };
+            let hints = match conflicted {
+                true => vec![
+                    "(fix conflicts and then run \"git rebase --continue\")",
+                    "(use \"git rebase --skip\" to skip this patch)",
+                    "(use \"git rebase --abort\" to check out the original branch)",
+                ],
+                false => vec!["(all conflicts fixed: run \"git rebase --continue\")"],
+            };
+            (message, hints)
+        }
+        state::Leftover::Bisect => {
+            let start = fs::read_to_string(git.join("BISECT_START"))?;
+            let message = match start.trim_end() {
+                start if start.parse::<object::Id>().is_ok() => {
+                    String::from("You are currently bisecting.")
+                }
+                start => format!(
+                    "You are currently bisecting, started from branch '{}'.",
+                    start
+                ),
+            };
+            (
+                message,
+                vec!["(use \"git bisect reset\" to get back to the original branch)"],
+            )
+        }
+        state::Leftover::Lock { .. } => unreachable!(),
+    };
+
+    Ok((message, hints.into_iter().map(String::from).collect()))
+}
+
 #[derive(Clone, Debug, Default)]
 struct Changes {
     /// Changes between the index and the HEAD commit.
@@ -383,6 +713,12 @@ struct Changes {
 
     /// Changes between the workspace and the index.
     workspace_index: BTreeMap<util::PathBuf, WorkspaceIndexChange>,
+
+    /// Files with unresolved merge conflicts.
+    unmerged: BTreeMap<util::PathBuf, Unmerged>,
+
+    /// Original paths of files staged as renamed, keyed by their new paths.
+    renames: BTreeMap<util::PathBuf, util::PathBuf>,
 }
 
 impl Changes {
@@ -395,6 +731,16 @@ impl Changes {
         self.workspace_index
             .insert(path.to_path_buf().tap(util::PathBuf), change);
     }
+
+    /// Display `path`, along with its original path if it was renamed.
+    /// Show `path`, and the path it was renamed from if any, translated by
+    /// `display`.
+    fn label<F: Fn(&path::Path) -> path::PathBuf>(&self, path: &path::Path, display: F) -> String {
+        match self.renames.get(&path as &dyn util::Key) {
+            Some(old) => format!("{} -> {}", display(old).display(), display(path).display()),
+            None => display(path).display().to_string(),
+        }
+    }
 }
 
 impl<'a> IntoIterator for &'a Changes {
@@ -439,14 +785,14 @@ impl<'a> Iterator for ChangesIter<'a> {
                     Some((index_head_path, index_head_change)),
                     Some((workspace_index_path, workspace_index_change)),
                 ) => (
-                    &*index_head_path,
+                    index_head_path,
                     *index_head_change,
-                    &*workspace_index_path,
+                    workspace_index_path,
                     *workspace_index_change,
                 ),
             };
 
-        match index_head_path.cmp(&workspace_index_path) {
+        match index_head_path.cmp(workspace_index_path) {
             cmp::Ordering::Less => {
                 self.index_head.next();
                 Some((index_head_path, Some(index_head_change), None))
@@ -473,6 +819,7 @@ enum IndexHeadChange {
     Added,
     Deleted,
     Modified,
+    Renamed,
 }
 
 impl IndexHeadChange {
@@ -481,6 +828,7 @@ impl IndexHeadChange {
             IndexHeadChange::Added => "A",
             IndexHeadChange::Deleted => "D",
             IndexHeadChange::Modified => "M",
+            IndexHeadChange::Renamed => "R",
         }
     }
 
@@ -489,6 +837,7 @@ impl IndexHeadChange {
             IndexHeadChange::Added => "new file:",
             IndexHeadChange::Deleted => "deleted:",
             IndexHeadChange::Modified => "modified:",
+            IndexHeadChange::Renamed => "renamed:",
         }
     }
 }
@@ -514,3 +863,40 @@ impl WorkspaceIndexChange {
         }
     }
 }
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+enum Unmerged {
+    BothDeleted,
+    AddedByUs,
+    DeletedByUs,
+    AddedByThem,
+    DeletedByThem,
+    BothAdded,
+    BothModified,
+}
+
+impl Unmerged {
+    fn into_porcelain(self) -> &'static str {
+        match self {
+            Unmerged::BothDeleted => "DD",
+            Unmerged::AddedByUs => "AU",
+            Unmerged::DeletedByUs => "DU",
+            Unmerged::AddedByThem => "UA",
+            Unmerged::DeletedByThem => "UD",
+            Unmerged::BothAdded => "AA",
+            Unmerged::BothModified => "UU",
+        }
+    }
+
+    fn into_pretty(self) -> &'static str {
+        match self {
+            Unmerged::BothDeleted => "both deleted:    ",
+            Unmerged::AddedByUs => "added by us:     ",
+            Unmerged::DeletedByUs => "deleted by us:   ",
+            Unmerged::AddedByThem => "added by them:   ",
+            Unmerged::DeletedByThem => "deleted by them: ",
+            Unmerged::BothAdded => "both added:      ",
+            Unmerged::BothModified => "both modified:   ",
+        }
+    }
+}
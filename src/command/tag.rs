@@ -0,0 +1,195 @@
+use std::env;
+use std::io;
+use std::io::Read as _;
+use std::io::Write as _;
+
+use anyhow::anyhow;
+use structopt::StructOpt;
+
+use crate::ignore;
+use crate::object;
+use crate::revision;
+
+/// Create, list, or delete tags.
+///
+/// With no arguments, or with `--list`, lists tags. Otherwise creates a
+/// lightweight tag, or an annotated tag object with `--annotate` or
+/// `--message`.
+#[derive(StructOpt)]
+pub struct Configuration {
+    /// List tags, optionally only those matching the given glob patterns.
+    #[structopt(short, long, conflicts_with_all = &["delete", "annotate", "message", "force"])]
+    list: bool,
+
+    /// Delete the given tags.
+    #[structopt(short, long, conflicts_with_all = &["annotate", "message", "force"])]
+    delete: bool,
+
+    /// Create an annotated tag object, reading its message from standard
+    /// input unless `--message` is given.
+    #[structopt(short, long)]
+    annotate: bool,
+
+    /// Message for an annotated tag. Implies `--annotate`.
+    #[structopt(short, long)]
+    message: Option<String>,
+
+    /// Replace an existing tag with the same name.
+    #[structopt(short, long)]
+    force: bool,
+
+    /// Defaults to `user.name`.
+    #[structopt(long, env = "GIT_COMMITTER_NAME")]
+    tagger_name: Option<String>,
+
+    /// Defaults to `user.email`.
+    #[structopt(long, env = "GIT_COMMITTER_EMAIL")]
+    tagger_email: Option<String>,
+
+    /// Defaults to the current time.
+    #[structopt(
+        long,
+        env = "GIT_COMMITTER_DATE",
+        parse(try_from_str = object::Person::parse_time)
+    )]
+    tagger_date: Option<chrono::DateTime<chrono::FixedOffset>>,
+
+    /// Tag name to create followed by the revision to tag (defaults to
+    /// `HEAD`), tags to delete, or patterns to list.
+    arguments: Vec<String>,
+}
+
+impl Configuration {
+    pub fn run(self) -> anyhow::Result<()> {
+        let root = env::current_dir()?;
+        let repository = crate::Repository::new(root);
+        let tag = Tag {
+            database: repository.database()?,
+            references: repository.references(),
+        };
+
+        if self.list || self.arguments.is_empty() {
+            return tag.list(&self.arguments);
+        }
+
+        if self.delete {
+            return self.arguments.iter().try_for_each(|name| tag.delete(name));
+        }
+
+        let (name, target) = match self.arguments.as_slice() {
+            [name] => (name, "HEAD"),
+            [name, target] => (name, target.as_str()),
+            _ => return Err(anyhow!("Too many arguments")),
+        };
+
+        let id = target
+            .parse::<revision::Revision>()?
+            .resolve(&repository)?
+            .ok_or_else(|| anyhow!("Failed to resolve '{}' as a valid ref", target))?;
+
+        let id = match (self.annotate, self.message) {
+            (false, None) => id,
+            (_, message) => {
+                let message = match message {
+                    Some(message) => message,
+                    None => {
+                        let mut buffer = String::new();
+                        io::stdin().lock().read_to_string(&mut buffer)?;
+                        buffer
+                    }
+                };
+
+                let config = repository.config()?;
+                let (tagger_name, tagger_email) =
+                    config.identity(self.tagger_name, self.tagger_email)?;
+                let tagger = object::Person::new(
+                    tagger_name,
+                    tagger_email,
+                    self.tagger_date
+                        .unwrap_or_else(|| chrono::Local::now().into()),
+                );
+                tag.annotate(name, &id, tagger, &message)?
+            }
+        };
+
+        tag.create(name, &id, self.force)
+    }
+}
+
+struct Tag {
+    database: crate::Database,
+    references: crate::References,
+}
+
+impl Tag {
+    /// Print tags in sorted order, keeping only those matching any of
+    /// `patterns` if given.
+    fn list(&self, patterns: &[String]) -> anyhow::Result<()> {
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        for tag in self.references.tags()? {
+            let (name, _) = tag?;
+            if patterns.is_empty()
+                || patterns
+                    .iter()
+                    .any(|pattern| ignore::wildmatch(pattern.as_bytes(), name.as_bytes()))
+            {
+                writeln!(stdout, "{}", name)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Store an annotated tag object for `id`, returning the tag's id.
+    fn annotate(
+        &self,
+        name: &str,
+        id: &object::Id,
+        tagger: object::Person,
+        message: &str,
+    ) -> anyhow::Result<object::Id> {
+        let message = message.trim_end();
+        if message.is_empty() {
+            return Err(anyhow!("Aborting tag due to empty tag message"));
+        }
+
+        let tag = object::Tag::new(
+            *id,
+            self.database.kind(id)?,
+            name.to_owned(),
+            Some(tagger),
+            format!("{}\n", message),
+        );
+        self.database
+            .store(&crate::Object::Tag(tag))
+            .map_err(anyhow::Error::from)
+    }
+
+    fn create(&self, name: &str, id: &object::Id, force: bool) -> anyhow::Result<()> {
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        match self.references.create_tag(name, id, force)? {
+            Some(previous) if previous != *id => writeln!(
+                stdout,
+                "Updated tag '{}' (was {})",
+                name,
+                &previous.to_string()[..7]
+            )?,
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn delete(&self, name: &str) -> anyhow::Result<()> {
+        let id = self.references.delete_tag(name)?;
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        writeln!(
+            stdout,
+            "Deleted tag '{}' (was {})",
+            name,
+            &id.to_string()[..7]
+        )?;
+        Ok(())
+    }
+}
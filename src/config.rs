@@ -0,0 +1,791 @@
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::Read as _;
+use std::io::Write as _;
+use std::ops;
+use std::path;
+use std::str;
+
+use anyhow::anyhow;
+
+use crate::file;
+
+/// Key-value settings parsed from one or more `git` configuration files.
+///
+/// Keys are written as `section.name` or `section.subsection.name`, where
+/// section and name are case-insensitive and the subsection is not. A key
+/// may have several values, of which the last one wins.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Config {
+    /// Normalized keys and values in file order.
+    entries: Vec<(String, String)>,
+}
+
+impl Config {
+    /// Maximum depth of nested `include.path` directives.
+    const MAX_INCLUDE_DEPTH: usize = 10;
+
+    /// Read the configuration file at `path`, which may not exist, along
+    /// with any files it includes.
+    pub fn open(path: &path::Path) -> anyhow::Result<Self> {
+        let mut config = Config::default();
+        config.include(path, 0)?;
+        Ok(config)
+    }
+
+    /// Read the system, global, and local configuration for the repository
+    /// at `git`, in increasing order of precedence.
+    pub fn layered(git: &path::Path) -> anyhow::Result<Self> {
+        let mut config = Config::default();
+        for scope in &[Scope::System, Scope::Global, Scope::Local] {
+            for path in scope.paths(git) {
+                config.include(&path, 0)?;
+            }
+        }
+        Ok(config)
+    }
+
+    /// Read only the configuration in `scope` for the repository at `git`.
+    pub fn scoped(scope: Scope, git: &path::Path) -> anyhow::Result<Self> {
+        let mut config = Config::default();
+        for path in scope.paths(git) {
+            config.include(&path, 0)?;
+        }
+        Ok(config)
+    }
+
+    /// Append the entries of the file at `path`, splicing in the contents of
+    /// each `include.path` where it appears.
+    fn include(&mut self, path: &path::Path, depth: usize) -> anyhow::Result<()> {
+        if depth > Self::MAX_INCLUDE_DEPTH {
+            return Err(anyhow!(
+                "Exceeded maximum include depth while including {}",
+                path.display(),
+            ));
+        }
+
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(error) => return Err(error.into()),
+        };
+
+        for entry in parse_lines(&text)
+            .map_err(|error| anyhow!("{} in {}", error, path.display()))?
+            .entries
+        {
+            let include = match entry.key == "include.path" {
+                false => None,
+                // Relative paths are relative to the including file.
+                true => Some(match entry.value.strip_prefix("~/") {
+                    Some(rest) => env::var_os("HOME")
+                        .map(path::PathBuf::from)
+                        .ok_or_else(|| anyhow!("Cannot expand ~ without $HOME"))?
+                        .join(rest),
+                    None => path
+                        .parent()
+                        .unwrap_or_else(|| path::Path::new(""))
+                        .join(&entry.value),
+                }),
+            };
+
+            self.entries.push((entry.key, entry.value));
+            if let Some(include) = include {
+                self.include(&include, depth + 1)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Iterate over all keys and values, in the order they were read.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    /// Look up the last value of `key`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        let key = normalize(key);
+        self.entries
+            .iter()
+            .rev()
+            .find(|(candidate, _)| *candidate == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Look up every value of `key`, in the order they were read.
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        let key = normalize(key);
+        self.entries
+            .iter()
+            .filter(|(candidate, _)| *candidate == key)
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    /// Look up the value of `section.name` that applies to `url`, preferring
+    /// a `section.<prefix>.name` key whose URL prefix matches `url`, the
+    /// longest prefix winning, over the plain key.
+    pub fn get_url(&self, section: &str, name: &str, url: &str) -> Option<&str> {
+        let mut best = None;
+        for (specificity, value) in self.matching(section, name, url) {
+            match best {
+                Some((longest, _)) if longest > specificity => (),
+                _ => best = Some((specificity, value)),
+            }
+        }
+        best.map(|(_, value)| value)
+    }
+
+    /// Look up every value of `section.name` and `section.<prefix>.name`
+    /// whose URL prefix matches `url`, in the order they were read. An empty
+    /// value clears those before it.
+    pub fn get_all_url(&self, section: &str, name: &str, url: &str) -> Vec<&str> {
+        let mut values = Vec::new();
+        for (_, value) in self.matching(section, name, url) {
+            match value {
+                "" => values.clear(),
+                value => values.push(value),
+            }
+        }
+        values
+    }
+
+    /// Values of `section.name` and of `section.<prefix>.name` keys whose
+    /// prefix matches `url`, along with the length of that prefix.
+    fn matching<'a>(
+        &'a self,
+        section: &str,
+        name: &str,
+        url: &str,
+    ) -> impl Iterator<Item = (usize, &'a str)> + 'a {
+        let section = format!("{}.", section.to_ascii_lowercase());
+        let name = name.to_owned();
+        let url = url.trim_end_matches('/').to_owned();
+        self.entries.iter().filter_map(move |(key, value)| {
+            let rest = key.strip_prefix(&section)?;
+            let specificity = match rest.rsplit_once('.') {
+                None if rest.eq_ignore_ascii_case(&name) => 0,
+                None => return None,
+                Some((prefix, key)) if key.eq_ignore_ascii_case(&name) => {
+                    let prefix = prefix.trim_end_matches('/');
+                    match url.strip_prefix(prefix) {
+                        Some("") => prefix.len(),
+                        Some(path) if path.starts_with('/') => prefix.len(),
+                        _ => return None,
+                    }
+                }
+                Some(_) => return None,
+            };
+            Some((specificity, value.as_str()))
+        })
+    }
+
+    /// Look up the last value of `key` as a boolean, accepting the same
+    /// spellings as `git` (`true`/`false`, `yes`/`no`, `on`/`off`, `1`/`0`).
+    pub fn get_bool(&self, key: &str) -> anyhow::Result<Option<bool>> {
+        match self.get(key).map(str::to_ascii_lowercase).as_deref() {
+            None => Ok(None),
+            Some("true") | Some("yes") | Some("on") | Some("1") => Ok(Some(true)),
+            Some("false") | Some("no") | Some("off") | Some("0") | Some("") => Ok(Some(false)),
+            Some(value) => Err(anyhow!("Invalid boolean value for {}: {}", key, value)),
+        }
+    }
+
+    /// Look up the last value of `key` as a size in bytes, which may end in
+    /// `k`, `m`, or `g` (case-insensitive) to multiply it by 1024, 1024², or
+    /// 1024³, like `git`.
+    pub fn get_size(&self, key: &str) -> anyhow::Result<Option<u64>> {
+        let value = match self.get(key) {
+            None => return Ok(None),
+            Some(value) => value,
+        };
+        let (digits, scale) = match value.chars().last().map(|unit| unit.to_ascii_lowercase()) {
+            Some('k') => (&value[..value.len() - 1], 1 << 10),
+            Some('m') => (&value[..value.len() - 1], 1 << 20),
+            Some('g') => (&value[..value.len() - 1], 1 << 30),
+            _ => (value, 1),
+        };
+        digits
+            .parse::<u64>()
+            .ok()
+            .and_then(|size| size.checked_mul(scale))
+            .map(Some)
+            .ok_or_else(|| anyhow!("Invalid size for {}: {}", key, value))
+    }
+
+    /// Fill in whichever of `name` and `email` are missing from `user.name`
+    /// and `user.email`.
+    pub fn identity(
+        &self,
+        name: Option<String>,
+        email: Option<String>,
+    ) -> anyhow::Result<(String, String)> {
+        let name = name.or_else(|| self.get("user.name").map(String::from));
+        let email = email.or_else(|| self.get("user.email").map(String::from));
+        match (name, email) {
+            (Some(name), Some(email)) => Ok((name, email)),
+            _ => Err(anyhow!(
+                "Author identity unknown\n\n\
+                 *** Please tell me who you are.\n\n\
+                 Run\n\n  \
+                 grit config --global user.email \"you@example.com\"\n  \
+                 grit config --global user.name \"Your Name\"\n\n\
+                 to set your account's default identity."
+            )),
+        }
+    }
+
+    /// Identity to record in reflogs for commands that don't otherwise ask
+    /// for one: `GIT_COMMITTER_*` or `user.*`, falling back to a placeholder
+    /// rather than failing like [`Config::identity`].
+    pub fn committer(&self) -> anyhow::Result<crate::object::Person> {
+        let (name, email) = self
+            .identity(
+                env::var("GIT_COMMITTER_NAME").ok(),
+                env::var("GIT_COMMITTER_EMAIL").ok(),
+            )
+            .unwrap_or_else(|_| (String::from("unknown"), String::from("unknown")));
+        let time = match env::var("GIT_COMMITTER_DATE") {
+            Ok(date) => crate::object::Person::parse_time(&date)?,
+            Err(_) => chrono::Local::now().into(),
+        };
+        Ok(crate::object::Person::new(name, email, time))
+    }
+
+    /// Resolve a boolean option turned on by `on` and off by `off`, the
+    /// command line flags for it, falling back to `key` if neither is given.
+    /// This lets configuration set a default that flags override.
+    pub fn flag(&self, key: &str, on: bool, off: bool) -> anyhow::Result<Option<bool>> {
+        match (on, off) {
+            (true, _) => Ok(Some(true)),
+            (_, true) => Ok(Some(false)),
+            (false, false) => self.get_bool(key),
+        }
+    }
+
+    /// Resolve an option given on the command line as `given`, falling back
+    /// to parsing `key` if it wasn't.
+    pub fn option<T>(&self, given: Option<T>, key: &str) -> anyhow::Result<Option<T>>
+    where
+        T: str::FromStr,
+        T::Err: Into<anyhow::Error>,
+    {
+        match given {
+            Some(given) => Ok(Some(given)),
+            None => self.parse(key),
+        }
+    }
+
+    /// Look up and parse the last value of `key`.
+    pub fn parse<T>(&self, key: &str) -> anyhow::Result<Option<T>>
+    where
+        T: str::FromStr,
+        T::Err: Into<anyhow::Error>,
+    {
+        self.get(key)
+            .map(|value| value.parse::<T>().map_err(Into::into))
+            .transpose()
+    }
+}
+
+impl str::FromStr for Config {
+    type Err = anyhow::Error;
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let entries = parse_lines(text)?
+            .entries
+            .into_iter()
+            .map(|entry| (entry.key, entry.value))
+            .collect();
+        Ok(Config { entries })
+    }
+}
+
+/// Scope of a configuration file, from least to most specific.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Scope {
+    /// `/etc/gitconfig`, or `$GIT_CONFIG_SYSTEM`.
+    System,
+    /// `~/.gitconfig` and `$XDG_CONFIG_HOME/git/config`, or
+    /// `$GIT_CONFIG_GLOBAL`.
+    Global,
+    /// `.git/config` in the repository.
+    Local,
+}
+
+impl Scope {
+    /// Files in this scope, in the order they are read. Values set in this
+    /// scope are written to the last one.
+    pub fn paths(self, git: &path::Path) -> Vec<path::PathBuf> {
+        match self {
+            Scope::System if env::var_os("GIT_CONFIG_NOSYSTEM").is_some() => Vec::new(),
+            Scope::System => match env::var_os("GIT_CONFIG_SYSTEM") {
+                Some(path) => vec![path::PathBuf::from(path)],
+                None => vec![path::PathBuf::from("/etc/gitconfig")],
+            },
+            Scope::Global => {
+                if let Some(path) = env::var_os("GIT_CONFIG_GLOBAL") {
+                    return vec![path::PathBuf::from(path)];
+                }
+                let home = env::var_os("HOME").map(path::PathBuf::from);
+                let xdg = env::var_os("XDG_CONFIG_HOME")
+                    .map(path::PathBuf::from)
+                    .or_else(|| home.as_ref().map(|home| home.join(".config")))
+                    .map(|config| config.join("git/config"));
+                xdg.into_iter()
+                    .chain(home.map(|home| home.join(".gitconfig")))
+                    .collect()
+            }
+            Scope::Local => vec![git.join("config")],
+        }
+    }
+}
+
+/// Parsed lines of a configuration file.
+#[derive(Clone, Debug, Default)]
+struct Parsed {
+    sections: Vec<Section>,
+    entries: Vec<Entry>,
+}
+
+#[derive(Clone, Debug)]
+struct Section {
+    /// Normalized `section` or `section.subsection`.
+    name: String,
+    line: usize,
+}
+
+#[derive(Clone, Debug)]
+struct Entry {
+    key: String,
+    value: String,
+    /// Index into `Parsed::sections`.
+    section: usize,
+    /// Lines spanned by this entry, including continuations.
+    lines: ops::Range<usize>,
+}
+
+fn parse_lines(text: &str) -> anyhow::Result<Parsed> {
+    let mut parsed = Parsed::default();
+    let mut lines = text.lines().enumerate();
+
+    while let Some((number, line)) = lines.next() {
+        let line = line.trim_start();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        if let Some(header) = line.strip_prefix('[') {
+            let header = header
+                .split_once(']')
+                .map(|(header, _)| header)
+                .ok_or_else(|| anyhow!("Unterminated section header on line {}", number + 1))?;
+            let name = match header.split_once(char::is_whitespace) {
+                None => header.to_ascii_lowercase(),
+                Some((name, subsection)) => {
+                    let subsection = subsection
+                        .trim()
+                        .strip_prefix('"')
+                        .and_then(|subsection| subsection.strip_suffix('"'))
+                        .ok_or_else(|| anyhow!("Invalid subsection on line {}", number + 1))?
+                        .replace("\\\"", "\"")
+                        .replace("\\\\", "\\");
+                    format!("{}.{}", name.to_ascii_lowercase(), subsection)
+                }
+            };
+            parsed.sections.push(Section { name, line: number });
+            continue;
+        }
+
+        let section = parsed
+            .sections
+            .len()
+            .checked_sub(1)
+            .ok_or_else(|| anyhow!("Key outside of section on line {}", number + 1))?;
+
+        let (name, mut raw) = match line.split_once('=') {
+            // A bare key is shorthand for `true`.
+            None => (line.trim(), String::from("true")),
+            Some((name, value)) => (name.trim(), value.to_owned()),
+        };
+
+        // A trailing backslash continues the value on the next line.
+        let mut end = number + 1;
+        while raw.ends_with('\\') && !raw.ends_with("\\\\") {
+            raw.pop();
+            match lines.next() {
+                Some((_, next)) => {
+                    raw.push_str(next);
+                    end += 1;
+                }
+                None => break,
+            }
+        }
+
+        let value = unquote(&raw).ok_or_else(|| anyhow!("Invalid value on line {}", number + 1))?;
+        parsed.entries.push(Entry {
+            key: format!(
+                "{}.{}",
+                parsed.sections[section].name,
+                name.to_ascii_lowercase()
+            ),
+            value,
+            section,
+            lines: number..end,
+        });
+    }
+
+    Ok(parsed)
+}
+
+/// A single configuration file opened for editing, which keeps the file
+/// locked and preserves the formatting and comments of untouched lines.
+#[derive(Debug)]
+pub struct Document {
+    lock: file::WriteLock,
+    lines: Vec<String>,
+    parsed: Parsed,
+}
+
+impl Document {
+    /// Lock the configuration file at `path`, which may not exist.
+    pub fn open(path: path::PathBuf) -> anyhow::Result<Self> {
+        let (text, lock) = match file::WriteLock::new(path)?.upgrade()? {
+            file::Lock::Write(lock) => (String::new(), lock),
+            file::Lock::ReadWrite(mut lock) => {
+                let mut text = String::new();
+                lock.read_to_string(&mut text)?;
+                (text, lock.downgrade())
+            }
+        };
+
+        Ok(Document {
+            lock,
+            parsed: parse_lines(&text)?,
+            lines: text.lines().map(String::from).collect(),
+        })
+    }
+
+    /// Look up every value of `key`, in file order.
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        let key = normalize(key);
+        self.parsed
+            .entries
+            .iter()
+            .filter(|entry| entry.key == key)
+            .map(|entry| entry.value.as_str())
+            .collect()
+    }
+
+    /// Set `key` to `value`, replacing its existing value if any.
+    pub fn set(&mut self, key: &str, value: &str) -> anyhow::Result<()> {
+        let (section, name) = split(key)?;
+        let key = format!("{}.{}", section, name.to_ascii_lowercase());
+        let line = format!("\t{} = {}", name, quote(value));
+
+        let mut existing = self.parsed.entries.iter().filter(|entry| entry.key == key);
+        match (existing.next(), existing.next()) {
+            (None, _) => self.insert(&section, line),
+            (Some(entry), None) => {
+                let lines = entry.lines.clone();
+                self.lines.splice(lines, Some(line));
+                self.reparse()
+            }
+            (Some(_), Some(_)) => Err(anyhow!(
+                "cannot overwrite multiple values of {} with a single value",
+                key,
+            )),
+        }
+    }
+
+    /// Add another value for `key`, keeping any existing ones.
+    pub fn add(&mut self, key: &str, value: &str) -> anyhow::Result<()> {
+        let (section, name) = split(key)?;
+        self.insert(&section, format!("\t{} = {}", name, quote(value)))
+    }
+
+    /// Remove the single value of `key`.
+    pub fn unset(&mut self, key: &str) -> anyhow::Result<()> {
+        let key = normalize(key);
+        let mut existing = self.parsed.entries.iter().filter(|entry| entry.key == key);
+        match (existing.next(), existing.next()) {
+            (None, _) => Err(anyhow!("key does not exist: {}", key)),
+            (Some(entry), None) => {
+                let lines = entry.lines.clone();
+                self.lines.drain(lines);
+                self.reparse()
+            }
+            (Some(_), Some(_)) => Err(anyhow!("{} has multiple values", key)),
+        }
+    }
+
+    /// Write the edited file and release the lock.
+    pub fn commit(mut self) -> anyhow::Result<()> {
+        self.lock.write_all(self.to_string().as_bytes())?;
+        self.lock.commit()?;
+        Ok(())
+    }
+
+    /// Insert `line` at the end of the last `section`, creating it if
+    /// necessary.
+    fn insert(&mut self, section: &str, line: String) -> anyhow::Result<()> {
+        let index = self
+            .parsed
+            .sections
+            .iter()
+            .rposition(|candidate| candidate.name == section);
+
+        match index {
+            None => {
+                self.lines.push(header(section));
+                self.lines.push(line);
+            }
+            Some(index) => {
+                let at = self
+                    .parsed
+                    .entries
+                    .iter()
+                    .filter(|entry| entry.section == index)
+                    .map(|entry| entry.lines.end)
+                    .max()
+                    .unwrap_or(self.parsed.sections[index].line + 1);
+                self.lines.insert(at, line);
+            }
+        }
+
+        self.reparse()
+    }
+
+    fn reparse(&mut self) -> anyhow::Result<()> {
+        self.parsed = parse_lines(&self.to_string())?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for Document {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        self.lines
+            .iter()
+            .try_for_each(|line| writeln!(fmt, "{}", line))
+    }
+}
+
+/// Split `key` into its normalized section and its name, as given.
+fn split(key: &str) -> anyhow::Result<(String, &str)> {
+    let (section, name) = key
+        .rsplit_once('.')
+        .filter(|(section, _)| !section.is_empty())
+        .ok_or_else(|| anyhow!("key does not contain a section: {}", key))?;
+
+    let valid = name.starts_with(|char: char| char.is_ascii_alphabetic())
+        && name
+            .chars()
+            .all(|char| char.is_ascii_alphanumeric() || char == '-');
+    if !valid {
+        return Err(anyhow!("invalid key: {}", key));
+    }
+
+    let section = match section.split_once('.') {
+        None => section.to_ascii_lowercase(),
+        Some((section, subsection)) => format!("{}.{}", section.to_ascii_lowercase(), subsection),
+    };
+    Ok((section, name))
+}
+
+/// Format the header line for normalized `section`.
+fn header(section: &str) -> String {
+    match section.split_once('.') {
+        None => format!("[{}]", section),
+        Some((section, subsection)) => format!(
+            "[{} \"{}\"]",
+            section,
+            subsection.replace('\\', "\\\\").replace('"', "\\\""),
+        ),
+    }
+}
+
+/// Escape `value` so that `unquote` reads it back unchanged.
+fn quote(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for char in value.chars() {
+        match char {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            char => escaped.push(char),
+        }
+    }
+
+    if value.starts_with(char::is_whitespace)
+        || value.ends_with(char::is_whitespace)
+        || value.contains(&['#', ';'][..])
+    {
+        format!("\"{}\"", escaped)
+    } else {
+        escaped
+    }
+}
+
+/// Lower-case the section and name of `key`, leaving any subsection as is.
+fn normalize(key: &str) -> String {
+    match (key.find('.'), key.rfind('.')) {
+        (Some(first), Some(last)) => format!(
+            "{}{}{}",
+            key[..first].to_ascii_lowercase(),
+            &key[first..last],
+            key[last..].to_ascii_lowercase(),
+        ),
+        _ => key.to_ascii_lowercase(),
+    }
+}
+
+/// Strip comments, surrounding whitespace, and quotes from a raw value,
+/// and interpret escape sequences.
+fn unquote(raw: &str) -> Option<String> {
+    let mut value = String::new();
+    let mut quoted = false;
+    // Length of `value` excluding trailing unquoted whitespace.
+    let mut end = 0;
+    let mut chars = raw.trim_start().chars();
+
+    while let Some(char) = chars.next() {
+        match char {
+            '"' => quoted = !quoted,
+            '#' | ';' if !quoted => break,
+            '\\' => match chars.next()? {
+                'n' => value.push('\n'),
+                't' => value.push('\t'),
+                'b' => {
+                    value.pop();
+                }
+                char @ ('"' | '\\') => value.push(char),
+                _ => return None,
+            },
+            char => value.push(char),
+        }
+        if quoted || !char.is_whitespace() {
+            end = value.len();
+        }
+    }
+
+    if quoted {
+        return None;
+    }
+
+    value.truncate(end);
+    Some(value)
+}
+
+#[test]
+fn parse() -> anyhow::Result<()> {
+    let config = r#"
+        # comment
+        [core]
+            checkStat = minimal ; trailing comment
+            bare
+            bigFileThreshold = 512M
+            packSizeLimit = 2x
+        [remote "Origin"]
+            url = "https://example.com/a b" # quoted
+        [user]
+            name = A \
+U Thor
+    "#
+    .parse::<Config>()?;
+
+    assert_eq!(config.get("core.checkstat"), Some("minimal"));
+    assert_eq!(config.get("CORE.CHECKSTAT"), Some("minimal"));
+    assert_eq!(config.get_bool("core.bare")?, Some(true));
+    assert_eq!(config.get_size("core.bigFileThreshold")?, Some(512 << 20));
+    assert!(config.get_size("core.packSizeLimit").is_err());
+    assert_eq!(
+        config.get("remote.Origin.url"),
+        Some("https://example.com/a b")
+    );
+    assert_eq!(config.get("remote.origin.url"), None);
+    assert_eq!(config.get("user.name"), Some("A U Thor"));
+    Ok(())
+}
+
+#[test]
+fn url() -> anyhow::Result<()> {
+    let config = r#"
+        [http]
+            proxy = http://default:8080
+            extraHeader = A: 1
+        [http "https://example.com/"]
+            proxy = http://example:8080
+            extraHeader =
+            extraHeader = B: 2
+        [http "https://example.com/team"]
+            proxy = http://team:8080
+    "#
+    .parse::<Config>()?;
+
+    let url = "https://example.com/team/repo.git";
+    assert_eq!(
+        config.get_url("http", "proxy", url),
+        Some("http://team:8080")
+    );
+    assert_eq!(
+        config.get_url("http", "proxy", "https://example.com/teams"),
+        Some("http://example:8080"),
+    );
+    assert_eq!(
+        config.get_url("http", "proxy", "https://example.org"),
+        Some("http://default:8080"),
+    );
+    assert_eq!(config.get_all_url("http", "extraheader", url), vec!["B: 2"]);
+    assert_eq!(
+        config.get_all_url("http", "extraheader", "https://example.org"),
+        vec!["A: 1"],
+    );
+    Ok(())
+}
+
+#[test]
+fn edit() -> anyhow::Result<()> {
+    let directory = crate::util::TempDir::new();
+    fs::create_dir_all(&directory)?;
+
+    let path = directory.join("config");
+    fs::write(
+        &path,
+        "# keep me\n[core]\n\tbare = false ; and me\n[include]\n\tpath = extra\n",
+    )?;
+    fs::write(
+        directory.join("extra"),
+        "[remote \"origin\"]\n\tfetch = a\n",
+    )?;
+
+    let mut document = Document::open(path.clone())?;
+    document.set("core.bare", "true")?;
+    document.set("user.name", " A U Thor ")?;
+    document.add("remote.origin.fetch", "b")?;
+    document.unset("core.bare")?;
+    document.set("Core.editor", "vi")?;
+    document.commit()?;
+
+    assert_eq!(
+        fs::read_to_string(&path)?,
+        "# keep me\n[core]\n\teditor = vi\n[include]\n\tpath = extra\n\
+         [user]\n\tname = \" A U Thor \"\n[remote \"origin\"]\n\tfetch = b\n",
+    );
+
+    let config = Config::open(&path)?;
+    assert_eq!(config.get("user.name"), Some(" A U Thor "));
+    assert_eq!(config.get_all("remote.origin.fetch"), vec!["a", "b"]);
+
+    // Only this file's own values count against a plain `set`.
+    let mut document = Document::open(path)?;
+    document.add("remote.origin.fetch", "c")?;
+    assert_eq!(document.get_all("remote.origin.fetch"), vec!["b", "c"]);
+    assert!(document.set("remote.origin.fetch", "d").is_err());
+    drop(document);
+
+    Ok(())
+}
@@ -1,62 +1,983 @@
+use std::cell;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::convert::TryFrom as _;
+use std::fmt;
 use std::fs;
 use std::io;
-use std::io::Write as _;
+use std::io::Read as _;
+use std::mem;
 use std::path;
+use std::rc::Rc;
+use std::time;
+
+use anyhow::anyhow;
 
-use crate::file;
 use crate::object;
-use crate::util::Tap as _;
 use crate::Object;
 
+pub mod commit_graph;
+mod loose;
+pub mod pack;
+
+pub use loose::Loose;
+pub use pack::Packs;
+
+/// Storage backend for serialized objects (`<type> <len>\0<payload>`),
+/// addressed by id.
+pub trait ObjectStore: fmt::Debug {
+    fn contains(&self, id: &object::Id) -> anyhow::Result<bool>;
+
+    /// Read the uncompressed, serialized object `id`, if it exists.
+    fn read(&self, id: &object::Id) -> anyhow::Result<Option<Vec<u8>>>;
+
+    /// Store serialized object `bytes`, which must hash to `id`, unless
+    /// this store already has it.
+    fn write(&self, id: &object::Id, bytes: &[u8]) -> io::Result<()>;
+
+    /// Store the serialized object read from `reader`, returning its id.
+    ///
+    /// The default implementation reads the whole object into memory, so
+    /// stores should override it if they can stream.
+    fn write_stream(&self, reader: &mut dyn io::Read) -> io::Result<object::Id> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        let id = object::Id::hash(&bytes);
+        self.write(&id, &bytes)?;
+        Ok(id)
+    }
+
+    /// List the ids of all objects in this store, in no particular order.
+    fn ids(&self) -> anyhow::Result<Vec<object::Id>>;
+
+    /// Open the uncompressed, serialized object `id` for reading, if it
+    /// exists.
+    ///
+    /// The default implementation reads the whole object into memory, so
+    /// stores should override it if they can stream.
+    fn open(&self, id: &object::Id) -> anyhow::Result<Option<Box<dyn io::BufRead + '_>>> {
+        Ok(self
+            .read(id)?
+            .map(io::Cursor::new)
+            .map(|cursor| Box::new(cursor) as Box<dyn io::BufRead>))
+    }
+
+    /// Read only the type and length of object `id`, if it exists.
+    ///
+    /// The default implementation reads the whole object, so stores should
+    /// override it if they can do better.
+    fn read_header(&self, id: &object::Id) -> anyhow::Result<Option<object::Header>> {
+        self.read(id)?
+            .map(|bytes| object::Header::read(&mut &*bytes))
+            .transpose()
+    }
+
+    /// Allow objects written from now on to reach disk lazily, until
+    /// [`ObjectStore::end_batch`]. See [`Database::batch`].
+    fn begin_batch(&self) {}
+
+    /// Make every object written since [`ObjectStore::begin_batch`]
+    /// durable.
+    fn end_batch(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// How loose objects are flushed to disk before being renamed into place,
+/// as configured by `core.fsync` and `core.fsyncMethod`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Default)]
+pub enum Fsync {
+    /// Leave flushing to the operating system.
+    #[default]
+    None,
+    /// Flush each object as it is written.
+    Each,
+    /// Like `Each`, except that objects written during a
+    /// [`Database::batch`] are flushed together when it ends.
+    Batch,
+}
+
+impl Fsync {
+    /// Combine `core.fsync`, a comma-separated list of the components to
+    /// flush (where `-<component>` removes one), with `core.fsyncMethod`.
+    ///
+    /// Only loose objects are affected: other components are accepted but
+    /// ignored.
+    pub fn new(components: Option<&str>, method: Option<&str>) -> anyhow::Result<Self> {
+        let batch = match method.unwrap_or("fsync") {
+            "fsync" | "writeout-only" => false,
+            "batch" => true,
+            method => return Err(anyhow!("Invalid value for core.fsyncMethod: {}", method)),
+        };
+
+        let mut objects = false;
+        for component in components
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|component| !component.is_empty())
+        {
+            let (include, name) = match component.strip_prefix('-') {
+                Some(name) => (false, name),
+                None => (true, component),
+            };
+            match name {
+                "none" => objects = false,
+                "loose-object" | "objects" | "added" | "committed" | "all" => objects = include,
+                "pack" | "pack-metadata" | "commit-graph" | "index" | "reference"
+                | "derived-metadata" => (),
+                _ => return Err(anyhow!("Invalid value for core.fsync: {}", component)),
+            }
+        }
+
+        Ok(match (objects, batch) {
+            (false, _) => Fsync::None,
+            (true, false) => Fsync::Each,
+            (true, true) => Fsync::Batch,
+        })
+    }
+}
+
+/// High-level object operations, layered over any [`ObjectStore`].
 #[derive(Debug)]
 pub struct Database {
-    root: path::PathBuf,
+    store: Box<dyn ObjectStore>,
+    /// The `.git/objects` directory, if this database lives on disk.
+    root: Option<path::PathBuf>,
+    fsync: Fsync,
 }
 
 impl Database {
-    pub fn new(root: path::PathBuf) -> Self {
-        Database { root }
+    pub fn new(store: Box<dyn ObjectStore>) -> Self {
+        Database {
+            store,
+            root: None,
+            fsync: Fsync::None,
+        }
+    }
+
+    /// Open the object store rooted at the `.git/objects` directory `root`,
+    /// which reads loose objects first and falls back to packfiles.
+    pub fn open(root: path::PathBuf) -> Self {
+        Self::open_with_fsync(root, Fsync::default())
+    }
+
+    /// Open the object store rooted at `root` like [`Database::open`],
+    /// flushing new loose objects to disk according to `fsync`.
+    pub fn open_with_fsync(root: path::PathBuf, fsync: Fsync) -> Self {
+        let mut store = Layered::new(Box::new(Loose::with_fsync(root.clone(), fsync)));
+        store.push(Box::new(Packs::new(root.join("pack"))));
+        Database {
+            store: Box::new(store),
+            root: Some(root),
+            fsync,
+        }
+    }
+
+    /// The `.git/objects` directory, from which other threads can open
+    /// their own handles, or `None` if this database lives in memory.
+    pub fn root(&self) -> Option<&path::Path> {
+        self.root.as_deref()
+    }
+
+    /// How new loose objects are flushed, for other threads' handles to
+    /// match.
+    pub fn fsync(&self) -> Fsync {
+        self.fsync
+    }
+
+    /// Load the commit-graph under `.git/objects/info`, which is empty if
+    /// there is none or this database lives in memory.
+    pub fn commit_graph(&self) -> anyhow::Result<commit_graph::Graph> {
+        match &self.root {
+            Some(root) => commit_graph::Graph::open(&root.join("info")),
+            None => Ok(commit_graph::Graph::default()),
+        }
+    }
+
+    pub fn backend(&self) -> &dyn ObjectStore {
+        &*self.store
     }
 
+    /// Check whether object `id` is stored, without reading it: loose
+    /// objects are only looked up on disk, and packed ones in pack indexes.
     pub fn contains(&self, id: &object::Id) -> anyhow::Result<bool> {
-        self.root
-            .join(id.to_path_buf())
-            .tap(fs::metadata)
-            .map(|_| true)
-            .map_err(anyhow::Error::from)
+        self.store.contains(id)
     }
 
     pub fn load(&self, id: &object::Id) -> anyhow::Result<Object> {
-        let path = self.root.join(id.to_path_buf());
+        let buffer = self
+            .store
+            .read(id)?
+            .ok_or_else(|| anyhow!("Object not found: {}", id))?;
 
-        let mut stream = fs::OpenOptions::new()
-            .read(true)
-            .write(false)
-            .open(&path)
-            .map(flate2::read::ZlibDecoder::new)
-            .map(io::BufReader::new)?;
+        Object::read(&mut &*buffer)
+    }
 
-        Object::read(&mut stream)
+    /// Load object `id`, which must be a blob.
+    pub fn load_blob(&self, id: &object::Id) -> anyhow::Result<object::Blob> {
+        match self.load(id)? {
+            Object::Blob(blob) => Ok(blob),
+            _ => Err(anyhow!("Expected blob object: {}", id)),
+        }
     }
 
-    pub fn store(&self, object: &Object) -> io::Result<object::Id> {
-        let buffer = object.to_bytes();
-        let id = object::Id::hash(&buffer);
-        let path = self.root.join(id.to_path_buf());
+    /// Load object `id`, which must be a commit.
+    pub fn load_commit(&self, id: &object::Id) -> anyhow::Result<object::Commit> {
+        match self.load(id)? {
+            Object::Commit(commit) => Ok(commit),
+            _ => Err(anyhow!("Expected commit object: {}", id)),
+        }
+    }
+
+    /// Load object `id`, which must be a tree.
+    pub fn load_tree(&self, id: &object::Id) -> anyhow::Result<object::tree::Root> {
+        match self.load(id)? {
+            Object::Tree(tree) => Ok(tree),
+            _ => Err(anyhow!("Expected tree object: {}", id)),
+        }
+    }
+
+    /// Load object `id`, which must be an annotated tag.
+    pub fn load_tag(&self, id: &object::Id) -> anyhow::Result<object::Tag> {
+        match self.load(id)? {
+            Object::Tag(tag) => Ok(tag),
+            _ => Err(anyhow!("Expected tag object: {}", id)),
+        }
+    }
 
-        let mut file = match file::Temp::new(path) {
-            Ok(file) => file,
-            // Object has already been written to disk.
-            Err(error) if error.kind() == io::ErrorKind::AlreadyExists => return Ok(id),
+    /// Read only the type and length of object `id`, without decompressing
+    /// or reconstructing its payload where possible.
+    pub fn read_header(&self, id: &object::Id) -> anyhow::Result<object::Header> {
+        self.store
+            .read_header(id)?
+            .ok_or_else(|| anyhow!("Object not found: {}", id))
+    }
+
+    /// Type of object `id`, decompressing only its header, so callers that
+    /// dispatch on type don't pay for loading large blobs.
+    pub fn kind(&self, id: &object::Id) -> anyhow::Result<object::Type> {
+        Ok(self.read_header(id)?.r#type)
+    }
+
+    /// Open the payload of object `id` for reading without materializing it,
+    /// e.g. to copy a large blob into the workspace.
+    pub fn stream(
+        &self,
+        id: &object::Id,
+    ) -> anyhow::Result<(object::Header, io::Take<Box<dyn io::BufRead + '_>>)> {
+        let mut reader = self
+            .store
+            .open(id)?
+            .ok_or_else(|| anyhow!("Object not found: {}", id))?;
+        let header = object::Header::read(&mut reader)?;
+        Ok((header, reader.take(header.len as u64)))
+    }
+
+    /// Iterate over the ids of all objects, loose and packed, in sorted
+    /// order and without duplicates.
+    pub fn iter(&self) -> anyhow::Result<impl Iterator<Item = object::Id>> {
+        Ok(self
+            .store
+            .ids()?
+            .into_iter()
+            .collect::<BTreeSet<_>>()
+            .into_iter())
+    }
+
+    /// Iterate over the ids of all objects along with their headers, which
+    /// is much cheaper than loading each object in full.
+    pub fn headers(
+        &self,
+    ) -> anyhow::Result<impl Iterator<Item = anyhow::Result<(object::Id, object::Header)>> + '_>
+    {
+        Ok(self
+            .iter()?
+            .map(move |id| self.read_header(&id).map(|header| (id, header))))
+    }
+
+    /// Build a packfile containing objects `ids`, without saving it.
+    pub fn build_pack(&self, ids: &[object::Id]) -> anyhow::Result<pack::Packfile> {
+        pack::Packfile::build(&self.read_all(ids)?)
+    }
+
+    /// Build a thin packfile containing objects `ids`, which may be stored
+    /// as deltas against objects `bases` that the reader already has. See
+    /// [`pack::Packfile::build_thin`].
+    pub fn build_thin_pack(
+        &self,
+        ids: &[object::Id],
+        bases: &[object::Id],
+    ) -> anyhow::Result<pack::Packfile> {
+        pack::Packfile::build_thin(&self.read_all(ids)?, &self.read_all(bases)?)
+    }
+
+    fn read_all(&self, ids: &[object::Id]) -> anyhow::Result<Vec<(object::Id, Vec<u8>)>> {
+        let mut objects = Vec::with_capacity(ids.len());
+        for id in ids {
+            let bytes = self
+                .store
+                .read(id)?
+                .ok_or_else(|| anyhow!("Object not found: {}", id))?;
+            objects.push((*id, bytes));
+        }
+        Ok(objects)
+    }
+
+    /// Write objects `ids` into a new packfile under `.git/objects/pack`.
+    pub fn pack(&self, ids: &[object::Id]) -> anyhow::Result<pack::PackId> {
+        let directory = self
+            .root
+            .as_ref()
+            .map(|root| root.join("pack"))
+            .ok_or_else(|| anyhow!("Database has no pack directory"))?;
+        let packfile = self.build_pack(ids)?;
+        packfile.save(&directory)?;
+        Ok(packfile.id)
+    }
+
+    /// Index and save packfile `pack`, e.g. one received from a remote,
+    /// under `.git/objects/pack`.
+    pub fn index_pack(&self, pack: Vec<u8>) -> anyhow::Result<pack::PackId> {
+        let directory = self
+            .root
+            .as_ref()
+            .map(|root| root.join("pack"))
+            .ok_or_else(|| anyhow!("Database has no pack directory"))?;
+        let packfile = pack::Packfile::index(pack)?;
+        packfile.save(&directory)?;
+        Ok(packfile.id)
+    }
+
+    /// Estimate the number of loose objects from the number in one of the
+    /// 256 fan-out directories, like `git gc --auto`.
+    pub fn estimate_loose(&self) -> io::Result<usize> {
+        let root = match &self.root {
+            None => return Ok(0),
+            Some(root) => root,
+        };
+        let entries = match fs::read_dir(root.join("17")) {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(error) => return Err(error),
+        };
+        let mut count = 0;
+        for entry in entries {
+            // Skips temporary files left behind by interrupted writes.
+            if entry?.file_name().len() == 38 {
+                count += 1;
+            }
+        }
+        Ok(count * 256)
+    }
+
+    /// Paths to the `.idx` files of every packfile under
+    /// `.git/objects/pack`.
+    pub fn pack_indexes(&self) -> io::Result<Vec<path::PathBuf>> {
+        let root = match &self.root {
+            None => return Ok(Vec::new()),
+            Some(root) => root,
+        };
+        let entries = match fs::read_dir(root.join("pack")) {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
             Err(error) => return Err(error),
         };
+        let mut indexes = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|extension| extension == "idx") {
+                indexes.push(path);
+            }
+        }
+        Ok(indexes)
+    }
 
-        let mut stream = flate2::write::ZlibEncoder::new(&mut file, flate2::Compression::default());
+    /// Write every object into a single new packfile, then delete the loose
+    /// objects and packfiles it replaces. Objects stored concurrently are
+    /// left alone. Returns `None` if there is nothing to pack.
+    ///
+    /// With `prune`, only reachable objects are packed. Unreachable ones
+    /// are deleted if they were last written before its expiry, and kept
+    /// loose otherwise, taking the modification time of their packfile if
+    /// they were packed.
+    ///
+    /// This database's view of packfiles is stale afterward, so callers
+    /// should reopen it before reading again.
+    pub fn repack(&self, prune: Option<&Prune>) -> anyhow::Result<Option<pack::PackId>> {
+        let root = self
+            .root
+            .as_ref()
+            .ok_or_else(|| anyhow!("Database has no pack directory"))?;
 
-        stream.write_all(&buffer)?;
-        stream.finish()?;
-        file.commit()?;
+        let loose = Loose::new(root.clone()).ids()?;
+        let indexes = self.pack_indexes()?;
+        let ids = self
+            .iter()?
+            .filter(|id| prune.is_none_or(|prune| prune.reachable.contains(id)))
+            .collect::<Vec<_>>();
+        if ids.is_empty() && prune.is_none() {
+            return Ok(None);
+        }
 
+        let id = match ids.is_empty() {
+            true => None,
+            false => Some(self.pack(&ids)?),
+        };
+        let kept = id.map(|id| root.join("pack").join(format!("pack-{}.idx", id)));
+
+        if let Some(prune) = prune {
+            for index in &indexes {
+                self.loosen(root, index, prune)?;
+            }
+        }
+
+        for id in loose {
+            let path = root.join(id.to_path_buf());
+            if let Some(prune) = prune.filter(|prune| !prune.reachable.contains(&id)) {
+                if fs::metadata(&path)?.modified()? >= prune.expiry {
+                    continue;
+                }
+            }
+            remove(&path)?;
+            if let Some(parent) = path.parent() {
+                // Fails if other objects remain, which is fine.
+                let _ = fs::remove_dir(parent);
+            }
+        }
+
+        // Remove each index before its pack, since readers discover packs
+        // through their `.idx` files.
+        for index in indexes
+            .into_iter()
+            .filter(|index| Some(index) != kept.as_ref())
+        {
+            remove(&index)?;
+            remove(&index.with_extension("pack"))?;
+        }
+
+        Ok(id)
+    }
+
+    /// Write the unreachable objects in the packfile indexed by `index` as
+    /// loose objects with the packfile's modification time, unless it's
+    /// older than `prune`'s expiry.
+    fn loosen(&self, root: &path::Path, index: &path::Path, prune: &Prune) -> anyhow::Result<()> {
+        let packed = fs::metadata(index.with_extension("pack"))?.modified()?;
+        if packed < prune.expiry {
+            return Ok(());
+        }
+
+        let pack = pack::Pack::open(index)?;
+        let loose = Loose::new(root.to_path_buf());
+        for id in pack.ids().filter(|id| !prune.reachable.contains(id)) {
+            let path = root.join(id.to_path_buf());
+            let modified = match fs::metadata(&path) {
+                Ok(metadata) => metadata.modified()?.max(packed),
+                Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                    let bytes = pack
+                        .read(&id)?
+                        .ok_or_else(|| anyhow!("Object not found: {}", id))?;
+                    loose.write(&id, &bytes)?;
+                    packed
+                }
+                Err(error) => return Err(error.into()),
+            };
+            fs::File::open(&path)?.set_modified(modified)?;
+        }
+        Ok(())
+    }
+
+    /// Start a batch of writes, e.g. all the blobs staged by one `add`.
+    ///
+    /// With [`Fsync::Batch`], objects stored before [`Batch::commit`] are
+    /// flushed to disk all at once, which is much faster than flushing each
+    /// one. Nothing durable (e.g. the index or a reference) should refer to
+    /// them until then.
+    pub fn batch(&self) -> Batch<'_> {
+        self.store.begin_batch();
+        Batch(self)
+    }
+
+    /// Store `object`, returning its id. Objects already stored are left
+    /// alone, without being compressed again.
+    pub fn store(&self, object: &Object) -> io::Result<object::Id> {
+        let buffer = object.to_bytes();
+        let id = object::Id::hash(&buffer);
+        if !self.contains(&id).map_err(io::Error::other)? {
+            self.store.write(&id, &buffer)?;
+        }
         Ok(id)
     }
+
+    /// Store a blob of `len` bytes read from `reader`, hashing and
+    /// compressing it as it's read rather than holding it in memory, e.g.
+    /// for large files. Fails if `reader` doesn't yield exactly `len` bytes,
+    /// e.g. if a file changes while being read.
+    pub fn store_stream(&self, len: u64, reader: impl io::Read) -> io::Result<object::Id> {
+        let header = format!("blob {}\0", len);
+        let mut reader = header.as_bytes().chain(Exact {
+            inner: reader,
+            remaining: len,
+        });
+        self.store.write_stream(&mut reader)
+    }
+
+    /// Hash a blob of `len` bytes read from `reader`, returning the id that
+    /// [`Database::store_stream`] would, without compressing or storing it.
+    pub fn hash_stream(len: u64, reader: impl io::Read) -> io::Result<object::Id> {
+        let header = format!("blob {}\0", len);
+        let mut reader = header.as_bytes().chain(Exact {
+            inner: reader,
+            remaining: len,
+        });
+        let mut hash = sha1::Sha1::new();
+        let mut buffer = vec![0; 1 << 16];
+        loop {
+            match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(len) => hash.update(&buffer[..len]),
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error),
+            }
+        }
+        Ok(object::Id::from_bytes(hash.digest().bytes()))
+    }
+}
+
+/// Reader that fails unless `inner` yields exactly `remaining` more bytes.
+struct Exact<R> {
+    inner: R,
+    remaining: u64,
+}
+
+impl<R: io::Read> io::Read for Exact<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 {
+            return match self.inner.read(&mut [0])? {
+                0 => Ok(0),
+                _ => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "More data than expected",
+                )),
+            };
+        }
+
+        let len = buffer
+            .len()
+            .min(usize::try_from(self.remaining).unwrap_or(usize::MAX));
+        match self.inner.read(&mut buffer[..len])? {
+            0 => Err(io::ErrorKind::UnexpectedEof.into()),
+            len => {
+                self.remaining -= len as u64;
+                Ok(len)
+            }
+        }
+    }
+}
+
+/// Unreachable objects to prune while repacking, given to
+/// [`Database::repack`].
+#[derive(Debug)]
+pub struct Prune<'a> {
+    /// Every object that should be kept, e.g. those reachable from any
+    /// reference, reflog, or the index.
+    pub reachable: &'a HashSet<object::Id>,
+    /// Unreachable objects last written before this are deleted. Newer ones
+    /// are kept, since something may be about to refer to them.
+    pub expiry: time::SystemTime,
+}
+
+/// Writes in progress, started by [`Database::batch`].
+///
+/// Dropping a batch without committing it still ends it, but ignores any
+/// error while flushing.
+#[derive(Debug)]
+pub struct Batch<'a>(&'a Database);
+
+impl Batch<'_> {
+    /// Flush the objects stored during this batch to disk.
+    pub fn commit(self) -> io::Result<()> {
+        let database = self.0;
+        mem::forget(self);
+        database.store.end_batch()
+    }
+}
+
+impl Drop for Batch<'_> {
+    fn drop(&mut self) {
+        self.0.store.end_batch().ok();
+    }
+}
+
+/// Volatile object storage for tests and embedding.
+///
+/// Clones share the same underlying objects.
+#[derive(Clone, Debug, Default)]
+pub struct Memory {
+    objects: Rc<cell::RefCell<HashMap<object::Id, Vec<u8>>>>,
+}
+
+impl Memory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ObjectStore for Memory {
+    fn contains(&self, id: &object::Id) -> anyhow::Result<bool> {
+        Ok(self.objects.borrow().contains_key(id))
+    }
+
+    fn read(&self, id: &object::Id) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(self.objects.borrow().get(id).cloned())
+    }
+
+    fn write(&self, id: &object::Id, bytes: &[u8]) -> io::Result<()> {
+        self.objects
+            .borrow_mut()
+            .entry(*id)
+            .or_insert_with(|| bytes.to_vec());
+        Ok(())
+    }
+
+    fn ids(&self) -> anyhow::Result<Vec<object::Id>> {
+        Ok(self.objects.borrow().keys().copied().collect())
+    }
+}
+
+/// Compound store that reads from each layer in order and writes to the
+/// first, e.g. local objects backed by alternates or a cache.
+#[derive(Debug)]
+pub struct Layered {
+    layers: Vec<Box<dyn ObjectStore>>,
+}
+
+impl Layered {
+    pub fn new(primary: Box<dyn ObjectStore>) -> Self {
+        Layered {
+            layers: vec![primary],
+        }
+    }
+
+    /// Add a read-only fallback layer, searched after all existing layers.
+    pub fn push(&mut self, layer: Box<dyn ObjectStore>) {
+        self.layers.push(layer);
+    }
+}
+
+impl ObjectStore for Layered {
+    fn contains(&self, id: &object::Id) -> anyhow::Result<bool> {
+        for layer in &self.layers {
+            if layer.contains(id)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn read(&self, id: &object::Id) -> anyhow::Result<Option<Vec<u8>>> {
+        for layer in &self.layers {
+            if let Some(buffer) = layer.read(id)? {
+                return Ok(Some(buffer));
+            }
+        }
+        Ok(None)
+    }
+
+    fn write(&self, id: &object::Id, bytes: &[u8]) -> io::Result<()> {
+        // Don't duplicate objects that a fallback layer (e.g. a packfile)
+        // already has. Errors just mean writing the object anyway.
+        for layer in &self.layers[1..] {
+            if layer.contains(id).unwrap_or(false) {
+                return Ok(());
+            }
+        }
+        self.layers[0].write(id, bytes)
+    }
+
+    fn write_stream(&self, reader: &mut dyn io::Read) -> io::Result<object::Id> {
+        self.layers[0].write_stream(reader)
+    }
+
+    fn ids(&self) -> anyhow::Result<Vec<object::Id>> {
+        let mut ids = Vec::new();
+        for layer in &self.layers {
+            ids.extend(layer.ids()?);
+        }
+        Ok(ids)
+    }
+
+    fn open(&self, id: &object::Id) -> anyhow::Result<Option<Box<dyn io::BufRead + '_>>> {
+        for layer in &self.layers {
+            if let Some(reader) = layer.open(id)? {
+                return Ok(Some(reader));
+            }
+        }
+        Ok(None)
+    }
+
+    fn read_header(&self, id: &object::Id) -> anyhow::Result<Option<object::Header>> {
+        for layer in &self.layers {
+            if let Some(header) = layer.read_header(id)? {
+                return Ok(Some(header));
+            }
+        }
+        Ok(None)
+    }
+
+    fn begin_batch(&self) {
+        self.layers[0].begin_batch()
+    }
+
+    fn end_batch(&self) -> io::Result<()> {
+        self.layers[0].end_batch()
+    }
+}
+
+/// Remove the file at `path`, ignoring files that are already gone.
+fn remove(path: &path::Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+        _ => Ok(()),
+    }
+}
+
+#[test]
+fn layered() -> anyhow::Result<()> {
+    let fallback = Memory::new();
+    let blob = Object::Blob(object::Blob::new(b"fallback".to_vec()));
+    let bytes = blob.to_bytes();
+    let fallback_id = object::Id::hash(&bytes);
+    fallback.write(&fallback_id, &bytes)?;
+
+    let mut layered = Layered::new(Box::new(Memory::new()));
+    layered.push(Box::new(fallback));
+    let database = Database::new(Box::new(layered));
+
+    let id = database.store(&Object::Blob(object::Blob::new(b"primary".to_vec())))?;
+
+    assert!(database.contains(&id)?);
+    assert!(database.contains(&fallback_id)?);
+    assert_eq!(database.iter()?.count(), 2);
+    assert_eq!(
+        database.read_header(&fallback_id)?,
+        object::Header {
+            r#type: object::Type::Blob,
+            len: 8,
+        }
+    );
+    assert!(!database.contains(&object::Id::hash(b""))?);
+    match database.load(&fallback_id)? {
+        Object::Blob(blob) => assert_eq!(blob.data(), b"fallback"),
+        _ => unreachable!(),
+    }
+
+    let (header, mut reader) = database.stream(&fallback_id)?;
+    let mut payload = Vec::new();
+    reader.read_to_end(&mut payload)?;
+    assert_eq!(header.len, payload.len());
+    assert_eq!(payload, b"fallback");
+    Ok(())
+}
+
+#[test]
+fn unsupported() {
+    let tag = b"tag 3\0tag";
+    let memory = Memory::new();
+    memory.write(&object::Id::hash(tag), tag).unwrap();
+    let database = Database::new(Box::new(memory));
+
+    assert!(database.load(&object::Id::hash(tag)).is_err());
+    assert_eq!(
+        database.read_header(&object::Id::hash(tag)).unwrap().r#type,
+        object::Type::Tag,
+    );
+    assert!(Object::read(&mut &b"blob 5\0data"[..]).is_err());
+}
+
+#[test]
+fn fsync() -> anyhow::Result<()> {
+    assert_eq!(Fsync::new(None, None)?, Fsync::None);
+    assert_eq!(Fsync::new(Some("loose-object"), None)?, Fsync::Each);
+    assert_eq!(
+        Fsync::new(Some("committed,-loose-object"), Some("batch"))?,
+        Fsync::None
+    );
+    assert_eq!(
+        Fsync::new(Some("reference, objects"), Some("batch"))?,
+        Fsync::Batch
+    );
+    assert_eq!(Fsync::new(Some("all,none,index"), None)?, Fsync::None);
+    assert!(Fsync::new(Some("everything"), None).is_err());
+    assert!(Fsync::new(None, Some("sometimes")).is_err());
+    Ok(())
+}
+
+#[test]
+fn repack() -> anyhow::Result<()> {
+    let root = crate::util::TempDir::new();
+
+    let database = Database::open(root.to_path_buf());
+    let ids = ["a", "b", "c"]
+        .iter()
+        .map(|data| database.store(&Object::Blob(object::Blob::new(data.as_bytes().to_vec()))))
+        .collect::<io::Result<Vec<_>>>()?;
+    database.pack(&ids[..1])?;
+    database.repack(None)?;
+
+    let database = Database::open(root.to_path_buf());
+    // Already packed, so not written again.
+    database.store(&Object::Blob(object::Blob::new(b"a".to_vec())))?;
+    let loose = Loose::new(root.to_path_buf()).ids()?;
+    let packs = database.pack_indexes()?;
+    let found = ids
+        .iter()
+        .map(|id| database.contains(id))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    assert!(loose.is_empty());
+    assert_eq!(packs.len(), 1);
+    assert_eq!(found, vec![true; 3]);
+    Ok(())
+}
+
+#[test]
+fn prune() -> anyhow::Result<()> {
+    let root = crate::util::TempDir::new();
+
+    let database = Database::open(root.to_path_buf());
+    let [reachable, old, recent, old_packed, recent_packed] =
+        ["reachable", "old", "recent", "old packed", "recent packed"].map(|data| {
+            database
+                .store(&Object::Blob(object::Blob::new(data.as_bytes().to_vec())))
+                .unwrap()
+        });
+    let expiry = time::SystemTime::now() - time::Duration::from_secs(60);
+    let age = |path: path::PathBuf| -> io::Result<()> {
+        fs::File::open(path)?.set_modified(expiry - time::Duration::from_secs(60))
+    };
+
+    let old_pack = database.pack(&[old_packed])?;
+    database.pack(&[recent_packed])?;
+    for id in [old_packed, recent_packed] {
+        remove(&root.join(id.to_path_buf()))?;
+    }
+    age(root.join(old.to_path_buf()))?;
+    age(root.join("pack").join(format!("pack-{}.pack", old_pack)))?;
+
+    let database = Database::open(root.to_path_buf());
+    let kept = HashSet::from([reachable]);
+    database.repack(Some(&Prune {
+        reachable: &kept,
+        expiry,
+    }))?;
+
+    let database = Database::open(root.to_path_buf());
+    let loose = Loose::new(root.to_path_buf())
+        .ids()?
+        .into_iter()
+        .collect::<HashSet<_>>();
+    let packed = database.pack_indexes()?;
+    let found = [reachable, old, recent, old_packed, recent_packed]
+        .iter()
+        .map(|id| database.contains(id))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    assert_eq!(loose, HashSet::from([recent, recent_packed]));
+    assert_eq!(packed.len(), 1);
+    assert_eq!(found, [true, false, true, false, true]);
+    Ok(())
+}
+
+#[test]
+fn kind() -> anyhow::Result<()> {
+    use std::io::Write as _;
+
+    let root = crate::util::TempDir::new();
+    let database = Database::open(root.to_path_buf());
+
+    let blob = database.store(&Object::Blob(object::Blob::new(b"data".to_vec())))?;
+    let tree = database.store(&Object::Tree(object::tree::Root::new(Vec::new())))?;
+    database.pack(&[tree])?;
+
+    // A loose object truncated after its header still has a kind, since
+    // nothing past the header is decompressed.
+    let truncated = object::Id::hash(b"truncated");
+    let path = root.join(truncated.to_path_buf());
+    fs::create_dir_all(path.parent().unwrap())?;
+    let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(b"blob 1048576\0")?;
+    fs::write(&path, encoder.finish()?)?;
+
+    let database = Database::open(root.to_path_buf());
+    let kinds = [
+        database.kind(&blob)?,
+        database.kind(&tree)?,
+        database.kind(&truncated)?,
+    ];
+    let loaded = database.load(&truncated).is_err();
+    let missing = database.kind(&object::Id::hash(b"missing")).is_err();
+
+    assert_eq!(
+        kinds,
+        [object::Type::Blob, object::Type::Tree, object::Type::Blob]
+    );
+    assert!(loaded);
+    assert!(missing);
+    Ok(())
+}
+
+#[test]
+fn stream() -> anyhow::Result<()> {
+    let root = crate::util::TempDir::new();
+
+    let data = vec![b'a'; 100_000];
+    let database = Database::open(root.to_path_buf());
+    let hashed = Database::hash_stream(data.len() as u64, &*data)?;
+    let id = database.store_stream(data.len() as u64, &*data)?;
+    let again = database.store_stream(data.len() as u64, &*data)?;
+    let short = database.store_stream(data.len() as u64 + 1, &*data);
+    let long = database.store_stream(data.len() as u64 - 1, &*data);
+    let blob = database.load_blob(&id)?.into_data();
+    let files = fs::read_dir(&root)?.count();
+
+    assert_eq!(
+        id,
+        object::Id::hash(&Object::Blob(object::Blob::new(data.clone())).to_bytes())
+    );
+    assert_eq!(hashed, id);
+    assert_eq!(again, id);
+    assert!(short.is_err());
+    assert!(long.is_err());
+    assert_eq!(blob, data);
+    // Only the object's directory is left.
+    assert_eq!(files, 1);
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn existing() -> anyhow::Result<()> {
+    use std::os::unix::fs::MetadataExt as _;
+
+    let root = crate::util::TempDir::new();
+
+    let database = Database::open(root.to_path_buf());
+    let blob = Object::Blob(object::Blob::new(b"blob".to_vec()));
+    let id = database.store(&blob)?;
+    let path = root.join(id.to_path_buf());
+    let inode = fs::metadata(&path)?.ino();
+
+    // Rewriting the object would replace its file.
+    database.store(&blob)?;
+    let rewritten = fs::metadata(&path)?.ino() != inode;
+    let found = database.contains(&id)?;
+    let missing = database.contains(&object::Id::hash(b"missing"))?;
+
+    assert!(!rewritten);
+    assert!(found);
+    assert!(!missing);
+    Ok(())
 }
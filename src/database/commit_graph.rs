@@ -0,0 +1,557 @@
+//! Commit-graph files, which cache each commit's tree, parents, date, and
+//! generation number under `.git/objects/info` so that history can be walked
+//! without inflating commit objects.
+//!
+//! A graph is either a single `commit-graph` file, or a chain of layers
+//! listed in `commit-graphs/commit-graph-chain`, where each layer only holds
+//! commits missing from the layers below it. Appending a small layer is much
+//! cheaper than rewriting every commit, and layers are merged as they grow.
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::convert::TryFrom as _;
+use std::fs;
+use std::io;
+use std::io::Write as _;
+use std::path;
+
+use anyhow::anyhow;
+use byteorder::BigEndian;
+use byteorder::ReadBytesExt as _;
+use byteorder::WriteBytesExt as _;
+
+use crate::file;
+use crate::object;
+
+const SIGNATURE: &[u8; 4] = b"CGPH";
+const VERSION: u8 = 1;
+/// SHA-1.
+const HASH_VERSION: u8 = 1;
+
+const FANOUT: &[u8; 4] = b"OIDF";
+const LOOKUP: &[u8; 4] = b"OIDL";
+const DATA: &[u8; 4] = b"CDAT";
+const EDGES: &[u8; 4] = b"EDGE";
+const BASES: &[u8; 4] = b"BASE";
+
+const PARENT_NONE: u32 = 0x7000_0000;
+/// Set on the second parent of an octopus merge, whose remaining parents are
+/// listed in the `EDGE` chunk, and on the last of those edges.
+const EXTRA_EDGE: u32 = 0x8000_0000;
+
+const MAX_GENERATION: u32 = 0x3fff_ffff;
+const MAX_TIME: u64 = (1 << 34) - 1;
+
+/// Merge the newest layer into a new one unless the new layer has less than
+/// `1 / SIZE_MULTIPLE` as many commits, like `git`'s default.
+const SIZE_MULTIPLE: usize = 2;
+
+/// Cached information about a single commit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub tree: object::Id,
+    pub parents: Vec<object::Id>,
+    /// Length of the longest path to a root commit, counting the commit
+    /// itself, so root commits have generation 1.
+    pub generation: u32,
+    /// Committer time, in seconds since the epoch.
+    pub time: u64,
+}
+
+/// A single commit-graph file.
+#[derive(Clone, Debug)]
+pub struct Layer {
+    /// Checksum of the file, which names it within a chain.
+    checksum: object::Id,
+    /// Sorted commit ids.
+    ids: Vec<object::Id>,
+    entries: Vec<Entry>,
+}
+
+impl Layer {
+    pub fn checksum(&self) -> &object::Id {
+        &self.checksum
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    fn get(&self, id: &object::Id) -> Option<&Entry> {
+        self.ids
+            .binary_search(id)
+            .ok()
+            .map(|index| &self.entries[index])
+    }
+
+    /// Parse a layer on top of `bases`, which its parent positions may
+    /// refer to.
+    fn read(bytes: &[u8], bases: &[Layer]) -> anyhow::Result<Self> {
+        let invalid = |reason: &str| anyhow!("Invalid commit-graph: {}", reason);
+
+        if bytes.len() < 8 + 20 || &bytes[..4] != SIGNATURE {
+            return Err(invalid("bad signature"));
+        }
+        let (body, checksum) = bytes.split_at(bytes.len() - 20);
+        if sha1::Sha1::from(body).digest().bytes() != checksum {
+            return Err(invalid("bad checksum"));
+        }
+        if bytes[4] != VERSION || bytes[5] != HASH_VERSION {
+            return Err(invalid("unsupported version"));
+        }
+        if bytes[7] as usize != bases.len() {
+            return Err(invalid("wrong number of base graphs"));
+        }
+
+        let mut chunks = HashMap::new();
+        let mut table = &bytes[8..];
+        let mut previous = None::<([u8; 4], usize)>;
+        for _ in 0..=bytes[6] {
+            let mut id = [0; 4];
+            io::Read::read_exact(&mut table, &mut id)?;
+            let offset = usize::try_from(table.read_u64::<BigEndian>()?)?;
+            if let Some((previous, start)) = previous {
+                let chunk = body
+                    .get(start..offset)
+                    .ok_or_else(|| invalid("chunk out of bounds"))?;
+                chunks.insert(previous, chunk);
+            }
+            previous = Some((id, offset));
+        }
+
+        let chunk = |id: &[u8; 4]| {
+            chunks
+                .get(id)
+                .copied()
+                .ok_or_else(|| invalid("missing required chunk"))
+        };
+
+        let mut lookup = chunk(LOOKUP)?;
+        let mut ids = Vec::with_capacity(lookup.len() / 20);
+        while !lookup.is_empty() {
+            ids.push(object::Id::read_bytes(&mut lookup)?);
+        }
+
+        let position = |position: u32| -> anyhow::Result<object::Id> {
+            let mut position = position as usize;
+            for layer in bases {
+                match layer.ids.get(position) {
+                    Some(id) => return Ok(*id),
+                    None => position -= layer.len(),
+                }
+            }
+            ids.get(position)
+                .copied()
+                .ok_or_else(|| invalid("parent out of bounds"))
+        };
+
+        let edges = chunks.get(EDGES).copied().unwrap_or_default();
+        let mut data = chunk(DATA)?;
+        let mut entries = Vec::with_capacity(ids.len());
+        for _ in 0..ids.len() {
+            let tree = object::Id::read_bytes(&mut data)?;
+            let mut parents = Vec::new();
+            match data.read_u32::<BigEndian>()? {
+                PARENT_NONE => (),
+                first => parents.push(position(first)?),
+            }
+            match data.read_u32::<BigEndian>()? {
+                PARENT_NONE => (),
+                second if second & EXTRA_EDGE == 0 => parents.push(position(second)?),
+                extra => {
+                    let mut edges = edges
+                        .get((extra & !EXTRA_EDGE) as usize * 4..)
+                        .ok_or_else(|| invalid("edge out of bounds"))?;
+                    loop {
+                        let edge = edges.read_u32::<BigEndian>()?;
+                        parents.push(position(edge & !EXTRA_EDGE)?);
+                        if edge & EXTRA_EDGE != 0 {
+                            break;
+                        }
+                    }
+                }
+            }
+            let high = data.read_u32::<BigEndian>()?;
+            let low = data.read_u32::<BigEndian>()?;
+            entries.push(Entry {
+                tree,
+                parents,
+                generation: high >> 2,
+                time: (((high & 0b11) as u64) << 32) | low as u64,
+            });
+        }
+
+        Ok(Layer {
+            checksum: object::Id::read_bytes(&mut &*checksum)?,
+            ids,
+            entries,
+        })
+    }
+
+    /// Serialize `entries` as a layer on top of `bases`.
+    fn write(entries: &BTreeMap<object::Id, Entry>, bases: &[Layer]) -> anyhow::Result<Vec<u8>> {
+        let base = bases.iter().map(Layer::len).sum::<usize>();
+        let positions = entries
+            .keys()
+            .enumerate()
+            .map(|(index, id)| (*id, base + index))
+            .collect::<HashMap<_, _>>();
+        let position = |id: &object::Id| -> anyhow::Result<u32> {
+            let mut offset = 0;
+            for layer in bases {
+                if let Ok(index) = layer.ids.binary_search(id) {
+                    return Ok((offset + index) as u32);
+                }
+                offset += layer.len();
+            }
+            positions
+                .get(id)
+                .map(|position| *position as u32)
+                .ok_or_else(|| anyhow!("Parent {} is missing from the commit-graph", id))
+        };
+
+        let mut fanout = Vec::with_capacity(256 * 4);
+        let mut count = 0;
+        for byte in 0..=255u8 {
+            count += entries.keys().filter(|id| id.as_bytes()[0] == byte).count();
+            fanout.write_u32::<BigEndian>(count as u32)?;
+        }
+
+        let mut lookup = Vec::with_capacity(entries.len() * 20);
+        let mut data = Vec::with_capacity(entries.len() * 36);
+        let mut edges = Vec::new();
+        for (id, entry) in entries {
+            id.write_bytes(&mut lookup)?;
+            entry.tree.write_bytes(&mut data)?;
+
+            let (first, second) = match entry.parents.as_slice() {
+                [] => (PARENT_NONE, PARENT_NONE),
+                [first] => (position(first)?, PARENT_NONE),
+                [first, second] => (position(first)?, position(second)?),
+                [first, rest @ ..] => {
+                    let extra = EXTRA_EDGE | (edges.len() / 4) as u32;
+                    for (index, parent) in rest.iter().enumerate() {
+                        let last = match index + 1 == rest.len() {
+                            true => EXTRA_EDGE,
+                            false => 0,
+                        };
+                        edges.write_u32::<BigEndian>(position(parent)? | last)?;
+                    }
+                    (position(first)?, extra)
+                }
+            };
+            data.write_u32::<BigEndian>(first)?;
+            data.write_u32::<BigEndian>(second)?;
+
+            let time = entry.time.min(MAX_TIME);
+            data.write_u32::<BigEndian>(
+                (entry.generation.min(MAX_GENERATION) << 2) | (time >> 32) as u32,
+            )?;
+            data.write_u32::<BigEndian>(time as u32)?;
+        }
+
+        let mut base_ids = Vec::with_capacity(bases.len() * 20);
+        for layer in bases {
+            layer.checksum.write_bytes(&mut base_ids)?;
+        }
+
+        let mut chunks = vec![(FANOUT, fanout), (LOOKUP, lookup), (DATA, data)];
+        if !edges.is_empty() {
+            chunks.push((EDGES, edges));
+        }
+        if !bases.is_empty() {
+            chunks.push((BASES, base_ids));
+        }
+
+        let mut buffer = Vec::new();
+        buffer.write_all(SIGNATURE)?;
+        buffer.write_u8(VERSION)?;
+        buffer.write_u8(HASH_VERSION)?;
+        buffer.write_u8(chunks.len() as u8)?;
+        buffer.write_u8(u8::try_from(bases.len())?)?;
+
+        let mut offset = (8 + (chunks.len() + 1) * 12) as u64;
+        for (id, chunk) in &chunks {
+            buffer.write_all(*id)?;
+            buffer.write_u64::<BigEndian>(offset)?;
+            offset += chunk.len() as u64;
+        }
+        buffer.write_all(&[0; 4])?;
+        buffer.write_u64::<BigEndian>(offset)?;
+
+        for (_, chunk) in &chunks {
+            buffer.write_all(chunk)?;
+        }
+
+        let checksum = sha1::Sha1::from(&buffer).digest().bytes();
+        buffer.write_all(&checksum)?;
+        Ok(buffer)
+    }
+}
+
+/// Every layer of a repository's commit-graph, base first.
+#[derive(Clone, Debug, Default)]
+pub struct Graph {
+    layers: Vec<Layer>,
+}
+
+impl Graph {
+    /// Load the commit-graph under the `.git/objects/info` directory `info`,
+    /// preferring a single file to a chain of layers, like `git`. A
+    /// repository without a commit-graph has an empty one.
+    pub fn open(info: &path::Path) -> anyhow::Result<Self> {
+        if let Some(bytes) = read(&info.join("commit-graph"))? {
+            return Ok(Graph {
+                layers: vec![Layer::read(&bytes, &[])?],
+            });
+        }
+
+        let mut layers = Vec::new();
+        if let Some(chain) = read(&chain_path(info))? {
+            for line in String::from_utf8(chain)?.lines() {
+                let checksum = line.parse::<object::Id>()?;
+                let bytes = read(&layer_path(info, &checksum))?
+                    .ok_or_else(|| anyhow!("Missing commit-graph layer {}", checksum))?;
+                let layer = Layer::read(&bytes, &layers)?;
+                layers.push(layer);
+            }
+        }
+
+        Ok(Graph { layers })
+    }
+
+    pub fn layers(&self) -> &[Layer] {
+        &self.layers
+    }
+
+    /// Total number of commits in every layer.
+    pub fn len(&self) -> usize {
+        self.layers.iter().map(Layer::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, id: &object::Id) -> Option<&Entry> {
+        self.layers.iter().find_map(|layer| layer.get(id))
+    }
+
+    /// Write a commit-graph containing every commit reachable from `tips`
+    /// under the `.git/objects/info` directory `info`.
+    ///
+    /// With `split`, commits already in the graph are kept, and only the
+    /// missing ones are written to a new layer, which absorbs any layers
+    /// above it that are not at least twice its size. Otherwise, the whole
+    /// graph is rewritten as a single file.
+    pub fn write(
+        mut self,
+        database: &crate::Database,
+        info: &path::Path,
+        tips: &[object::Id],
+        split: bool,
+    ) -> anyhow::Result<Self> {
+        let mut entries = BTreeMap::new();
+        let mut stack = tips.to_vec();
+        while let Some(id) = stack.pop() {
+            if entries.contains_key(&id) {
+                continue;
+            }
+            let entry = match self.get(&id) {
+                // Layers are closed under ancestry, so there is no need to
+                // walk further.
+                Some(_) if split => continue,
+                Some(entry) => entry.clone(),
+                None => {
+                    let commit = database.load_commit(&id)?;
+                    Entry {
+                        tree: *commit.tree(),
+                        parents: commit.parents().to_vec(),
+                        generation: 0,
+                        time: commit.committer().time().timestamp().max(0) as u64,
+                    }
+                }
+            };
+            stack.extend(&entry.parents);
+            entries.insert(id, entry);
+        }
+
+        self.number(&mut entries);
+
+        if !split {
+            let bytes = Layer::write(&entries, &[])?;
+            let mut lock = file::WriteLock::new(info.join("commit-graph"))?;
+            lock.write_all(&bytes)?;
+            lock.commit()?;
+            self.layers = vec![Layer::read(&bytes, &[])?];
+            self.clean(info)?;
+            remove(&chain_path(info))?;
+            return Ok(self);
+        }
+
+        if entries.is_empty() {
+            return Ok(self);
+        }
+
+        while let Some(layer) = self.layers.pop() {
+            if layer.len() > entries.len() * SIZE_MULTIPLE {
+                self.layers.push(layer);
+                break;
+            }
+            entries.extend(layer.ids.into_iter().zip(layer.entries));
+        }
+
+        let bytes = Layer::write(&entries, &self.layers)?;
+        let layer = Layer::read(&bytes, &self.layers)?;
+        let path = layer_path(info, &layer.checksum);
+        if !path.exists() {
+            let mut file = file::Temp::new(path)?;
+            file.write_all(&bytes)?;
+            file.commit()?;
+        }
+        self.layers.push(layer);
+
+        let mut lock = file::WriteLock::new(chain_path(info))?;
+        for layer in &self.layers {
+            // The base layer may have been a single file until now.
+            let path = layer_path(info, &layer.checksum);
+            if !path.exists() {
+                fs::copy(info.join("commit-graph"), path)?;
+            }
+            writeln!(lock, "{}", layer.checksum)?;
+        }
+        lock.commit()?;
+
+        // A single file takes precedence over the chain, so it must go.
+        remove(&info.join("commit-graph"))?;
+        self.clean(info)?;
+        Ok(self)
+    }
+
+    /// Fill in generation numbers for `entries`, whose parents are either
+    /// in `entries` or already in this graph.
+    fn number(&self, entries: &mut BTreeMap<object::Id, Entry>) {
+        let ids = entries.keys().copied().collect::<Vec<_>>();
+        for id in ids {
+            let mut stack = vec![id];
+            while let Some(&id) = stack.last() {
+                if entries[&id].generation > 0 {
+                    stack.pop();
+                    continue;
+                }
+
+                let mut generation = 0;
+                let mut pending = false;
+                for parent in &entries[&id].parents {
+                    match entries
+                        .get(parent)
+                        .or_else(|| self.get(parent))
+                        .map(|entry| entry.generation)
+                    {
+                        Some(0) => {
+                            stack.push(*parent);
+                            pending = true;
+                        }
+                        Some(parent) => generation = generation.max(parent),
+                        None => unreachable!("[INTERNAL ERROR]: parent outside commit-graph"),
+                    }
+                }
+
+                if !pending {
+                    stack.pop();
+                    if let Some(entry) = entries.get_mut(&id) {
+                        entry.generation = (generation + 1).min(MAX_GENERATION);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Remove layer files under `info` that are no longer in this graph.
+    fn clean(&self, info: &path::Path) -> io::Result<()> {
+        let directory = info.join("commit-graphs");
+        let entries = match fs::read_dir(&directory) {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(error) => return Err(error),
+        };
+
+        for entry in entries {
+            let path = entry?.path();
+            let stale = path
+                .extension()
+                .is_some_and(|extension| extension == "graph")
+                && !self
+                    .layers
+                    .iter()
+                    .any(|layer| path == layer_path(info, &layer.checksum));
+            if stale {
+                remove(&path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn chain_path(info: &path::Path) -> path::PathBuf {
+    info.join("commit-graphs/commit-graph-chain")
+}
+
+fn layer_path(info: &path::Path, checksum: &object::Id) -> path::PathBuf {
+    info.join(format!("commit-graphs/graph-{}.graph", checksum))
+}
+
+fn read(path: &path::Path) -> io::Result<Option<Vec<u8>>> {
+    match fs::read(path) {
+        Ok(bytes) => Ok(Some(bytes)),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+fn remove(path: &path::Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+        _ => Ok(()),
+    }
+}
+
+#[test]
+fn layers() -> anyhow::Result<()> {
+    let id = |byte: u8| object::Id::hash(&[byte]);
+    let entry = |parents: Vec<object::Id>, generation: u32| Entry {
+        tree: id(0),
+        parents,
+        generation,
+        time: 1 << 33,
+    };
+
+    let base = vec![(id(1), entry(vec![], 1)), (id(2), entry(vec![id(1)], 2))]
+        .into_iter()
+        .collect::<BTreeMap<_, _>>();
+    let base = Layer::read(&Layer::write(&base, &[])?, &[])?;
+    assert_eq!(base.get(&id(2)), Some(&entry(vec![id(1)], 2)));
+
+    // An octopus merge on top, with parents in both layers.
+    let top = vec![
+        (id(3), entry(vec![id(1)], 2)),
+        (id(4), entry(vec![id(2), id(3), id(1)], 3)),
+    ]
+    .into_iter()
+    .collect::<BTreeMap<_, _>>();
+    let bases = [base];
+    let bytes = Layer::write(&top, &bases)?;
+    let layer = Layer::read(&bytes, &bases)?;
+    assert_eq!(layer.len(), 2);
+    assert_eq!(layer.get(&id(4)), top.get(&id(4)));
+    assert!(Layer::read(&bytes, &[]).is_err());
+
+    let mut corrupt = bytes;
+    corrupt[100] ^= 1;
+    assert!(Layer::read(&corrupt, &bases).is_err());
+    Ok(())
+}
@@ -0,0 +1,230 @@
+use std::cell;
+use std::fs;
+use std::io;
+use std::io::Read as _;
+use std::io::Write as _;
+use std::path;
+use std::time;
+
+use crate::database::Fsync;
+use crate::database::ObjectStore;
+use crate::file;
+use crate::object;
+
+/// Default object storage, using one zlib-compressed file per object under
+/// `.git/objects/<2 hex digits>/<38 hex digits>`.
+#[derive(Clone, Debug)]
+pub struct Loose {
+    root: path::PathBuf,
+    fsync: Fsync,
+    /// During a batch, whether any object still needs flushing.
+    batch: cell::Cell<Option<bool>>,
+}
+
+impl Loose {
+    /// Create a store rooted at the `.git/objects` directory `root`.
+    pub fn new(root: path::PathBuf) -> Self {
+        Self::with_fsync(root, Fsync::default())
+    }
+
+    /// Create a store rooted at `root` that flushes new objects to disk
+    /// according to `fsync`.
+    pub fn with_fsync(root: path::PathBuf, fsync: Fsync) -> Self {
+        Loose {
+            root,
+            fsync,
+            batch: cell::Cell::new(None),
+        }
+    }
+
+    /// Whether to flush the next object written before renaming it into
+    /// place, noting that the batch needs flushing otherwise.
+    fn fsync_write(&self) -> bool {
+        match (self.fsync, self.batch.get()) {
+            (Fsync::None, _) => false,
+            // Without `sync`, flush each object as it's written instead.
+            (Fsync::Batch, Some(_)) if cfg!(unix) => {
+                self.batch.set(Some(true));
+                false
+            }
+            (Fsync::Each, _) | (Fsync::Batch, _) => true,
+        }
+    }
+
+    fn file(&self, id: &object::Id) -> io::Result<Option<fs::File>> {
+        match fs::File::open(self.root.join(id.to_path_buf())) {
+            Ok(file) => Ok(Some(file)),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+fn is_hex(name: &str) -> bool {
+    name.bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
+impl ObjectStore for Loose {
+    fn contains(&self, id: &object::Id) -> anyhow::Result<bool> {
+        match fs::metadata(self.root.join(id.to_path_buf())) {
+            Ok(_) => Ok(true),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    fn read(&self, id: &object::Id) -> anyhow::Result<Option<Vec<u8>>> {
+        let file = match self.file(id)? {
+            None => return Ok(None),
+            Some(file) => file,
+        };
+
+        let mut buffer = Vec::new();
+        flate2::read::ZlibDecoder::new(file).read_to_end(&mut buffer)?;
+        Ok(Some(buffer))
+    }
+
+    fn open(&self, id: &object::Id) -> anyhow::Result<Option<Box<dyn io::BufRead + '_>>> {
+        Ok(self
+            .file(id)?
+            .map(flate2::read::ZlibDecoder::new)
+            .map(io::BufReader::new)
+            .map(|reader| Box::new(reader) as Box<dyn io::BufRead>))
+    }
+
+    fn read_header(&self, id: &object::Id) -> anyhow::Result<Option<object::Header>> {
+        // Only inflate as much of the object as the header needs.
+        self.file(id)?
+            .map(flate2::read::ZlibDecoder::new)
+            .map(io::BufReader::new)
+            .map(|mut reader| object::Header::read(&mut reader))
+            .transpose()
+    }
+
+    fn ids(&self) -> anyhow::Result<Vec<object::Id>> {
+        let directories = match fs::read_dir(&self.root) {
+            Ok(directories) => directories,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(error) => return Err(error.into()),
+        };
+
+        let mut ids = Vec::new();
+        for directory in directories {
+            let directory = directory?;
+            let prefix = directory.file_name();
+            let prefix = match prefix.to_str() {
+                Some(prefix) if prefix.len() == 2 && is_hex(prefix) => prefix,
+                // Skips `info` and `pack`, among others.
+                _ => continue,
+            };
+
+            for file in fs::read_dir(directory.path())? {
+                let suffix = file?.file_name();
+                match suffix.to_str() {
+                    Some(suffix) if suffix.len() == 38 && is_hex(suffix) => {
+                        ids.push(format!("{}{}", prefix, suffix).parse()?)
+                    }
+                    // Skips temporary files left behind by interrupted writes.
+                    _ => continue,
+                }
+            }
+        }
+        Ok(ids)
+    }
+
+    fn write(&self, id: &object::Id, bytes: &[u8]) -> io::Result<()> {
+        let path = self.root.join(id.to_path_buf());
+
+        // Objects never change, so one already on disk needn't be
+        // compressed and written again, only freshened.
+        if path.exists() && freshen(&path) {
+            return Ok(());
+        }
+
+        let mut file = file::Options::new().fsync(self.fsync_write()).temp(path)?;
+
+        let mut stream = flate2::write::ZlibEncoder::new(&mut file, flate2::Compression::default());
+
+        stream.write_all(bytes)?;
+        stream.finish()?;
+        file.commit()
+    }
+
+    fn write_stream(&self, reader: &mut dyn io::Read) -> io::Result<object::Id> {
+        // The object's path isn't known until it's hashed, so compress it
+        // under `objects` and move it into place afterwards.
+        let mut file = file::Options::new()
+            .fsync(self.fsync_write())
+            .temp(self.root.join("object"))?;
+
+        let mut stream = flate2::write::ZlibEncoder::new(&mut file, flate2::Compression::default());
+        let mut hash = sha1::Sha1::new();
+        let mut buffer = vec![0; 1 << 16];
+        loop {
+            let len = match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(len) => len,
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error),
+            };
+            hash.update(&buffer[..len]);
+            stream.write_all(&buffer[..len])?;
+        }
+        stream.finish()?;
+
+        let id = object::Id::from_bytes(hash.digest().bytes());
+        let path = self.root.join(id.to_path_buf());
+        match path.exists() && freshen(&path) {
+            // Dropping the file removes it.
+            true => Ok(id),
+            false => file.commit_to(path).map(|_| id),
+        }
+    }
+
+    fn begin_batch(&self) {
+        self.batch.set(Some(false));
+    }
+
+    fn end_batch(&self) -> io::Result<()> {
+        match self.batch.take() {
+            Some(true) => syncfs(&fs::File::open(&self.root)?),
+            Some(false) | None => Ok(()),
+        }
+    }
+}
+
+/// Mark the object at `path` as just written, so that `gc` treats it as
+/// recent rather than pruning it if it's unreachable. Returns `false` if
+/// that failed, e.g. for objects owned by another user, in which case the
+/// object should be written again.
+fn freshen(path: &path::Path) -> bool {
+    fs::File::open(path)
+        .and_then(|file| file.set_modified(time::SystemTime::now()))
+        .is_ok()
+}
+
+/// Flush every file on the filesystem containing `file` to disk at once.
+#[cfg(target_os = "linux")]
+fn syncfs(file: &fs::File) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd as _;
+
+    // SAFETY: `file` keeps its descriptor open for the duration of the call.
+    match unsafe { libc::syncfs(file.as_raw_fd()) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// Flush every file to disk at once, for platforms without `syncfs`.
+#[cfg(all(unix, not(target_os = "linux")))]
+fn syncfs(_: &fs::File) -> io::Result<()> {
+    // SAFETY: `sync` takes no arguments and cannot fail.
+    unsafe { libc::sync() };
+    Ok(())
+}
+
+/// Objects were already flushed as they were written; see `fsync_write`.
+#[cfg(not(unix))]
+fn syncfs(_: &fs::File) -> io::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,1292 @@
+use std::cell;
+use std::cmp;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::convert::TryFrom as _;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::Read as _;
+use std::path;
+use std::rc::Rc;
+
+use anyhow::anyhow;
+use anyhow::Context as _;
+use byteorder::BigEndian;
+use byteorder::ByteOrder as _;
+use byteorder::ReadBytesExt as _;
+use byteorder::WriteBytesExt as _;
+
+use crate::database::ObjectStore;
+use crate::file;
+use crate::object;
+
+/// Magic bytes at the start of a version 2 (or later) pack index.
+const IDX_SIGNATURE: &[u8; 4] = b"\xfftOc";
+
+/// Number of preceding objects of the same type considered as delta bases.
+const WINDOW: usize = 10;
+
+/// Maximum length of a chain of deltas.
+const MAX_DEPTH: usize = 50;
+
+/// Length of the base chunks indexed when searching for copies.
+const BLOCK: usize = 16;
+
+/// Read-only object storage backed by the packfiles under
+/// `.git/objects/pack`.
+///
+/// Pack indices are loaded lazily on first access, so packs created after
+/// that point are not visible to this store.
+#[derive(Debug)]
+pub struct Packs {
+    directory: path::PathBuf,
+    packs: cell::RefCell<Option<Vec<Pack>>>,
+}
+
+impl Packs {
+    /// Create a store over the `.git/objects/pack` directory `directory`.
+    pub fn new(directory: path::PathBuf) -> Self {
+        Packs {
+            directory,
+            packs: cell::RefCell::new(None),
+        }
+    }
+
+    fn with_packs<T>(&self, apply: impl FnOnce(&[Pack]) -> anyhow::Result<T>) -> anyhow::Result<T> {
+        let mut packs = self.packs.borrow_mut();
+        if packs.is_none() {
+            *packs = Some(self.load()?);
+        }
+        apply(packs.as_deref().unwrap_or_default())
+    }
+
+    fn load(&self) -> anyhow::Result<Vec<Pack>> {
+        let entries = match fs::read_dir(&self.directory) {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(error) => return Err(error.into()),
+        };
+
+        let mut packs = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().and_then(|extension| extension.to_str()) == Some("idx") {
+                packs.push(Pack::open(&path)?);
+            }
+        }
+        Ok(packs)
+    }
+}
+
+impl ObjectStore for Packs {
+    fn contains(&self, id: &object::Id) -> anyhow::Result<bool> {
+        self.with_packs(|packs| Ok(packs.iter().any(|pack| pack.find(id).is_some())))
+    }
+
+    fn read(&self, id: &object::Id) -> anyhow::Result<Option<Vec<u8>>> {
+        self.with_packs(|packs| {
+            for pack in packs {
+                if let Some(bytes) = pack.read(id)? {
+                    return Ok(Some(bytes));
+                }
+            }
+            Ok(None)
+        })
+    }
+
+    fn write(&self, _: &object::Id, _: &[u8]) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Packfiles are read-only",
+        ))
+    }
+
+    fn ids(&self) -> anyhow::Result<Vec<object::Id>> {
+        self.with_packs(|packs| Ok(packs.iter().flat_map(Pack::ids).collect()))
+    }
+
+    fn read_header(&self, id: &object::Id) -> anyhow::Result<Option<object::Header>> {
+        self.with_packs(|packs| {
+            for pack in packs {
+                if let Some(header) = pack.read_header(id)? {
+                    return Ok(Some(header));
+                }
+            }
+            Ok(None)
+        })
+    }
+}
+
+/// A single `.pack` file and its `.idx`, both mapped into memory so that
+/// lookups and reads only touch the pages they need.
+#[derive(Debug)]
+pub struct Pack {
+    idx: memmap2::Mmap,
+    pack: memmap2::Mmap,
+    /// Version of the `.idx` format, 1 or 2.
+    version: u32,
+    /// Number of objects in the pack.
+    count: usize,
+    /// Recently inflated delta bases.
+    bases: cell::RefCell<Bases>,
+}
+
+/// Checksum of a packfile's contents, which also names its files as
+/// `pack-<id>.pack` and `pack-<id>.idx`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PackId(object::Id);
+
+impl fmt::Display for PackId {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", self.0)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Kind {
+    Commit,
+    Tree,
+    Blob,
+    Tag,
+    OfsDelta,
+    RefDelta,
+}
+
+impl Kind {
+    fn parse(r#type: &[u8]) -> Option<Self> {
+        match r#type {
+            b"commit" => Some(Kind::Commit),
+            b"tree" => Some(Kind::Tree),
+            b"blob" => Some(Kind::Blob),
+            b"tag" => Some(Kind::Tag),
+            _ => None,
+        }
+    }
+
+    fn as_u8(&self) -> u8 {
+        match self {
+            Kind::Commit => 1,
+            Kind::Tree => 2,
+            Kind::Blob => 3,
+            Kind::Tag => 4,
+            Kind::OfsDelta => 6,
+            Kind::RefDelta => 7,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Kind::Commit => "commit",
+            Kind::Tree => "tree",
+            Kind::Blob => "blob",
+            Kind::Tag => "tag",
+            Kind::OfsDelta | Kind::RefDelta => unreachable!(),
+        }
+    }
+
+    fn as_type(&self) -> object::Type {
+        match self {
+            Kind::Commit => object::Type::Commit,
+            Kind::Tree => object::Type::Tree,
+            Kind::Blob => object::Type::Blob,
+            Kind::Tag => object::Type::Tag,
+            Kind::OfsDelta | Kind::RefDelta => unreachable!(),
+        }
+    }
+}
+
+impl Pack {
+    /// Map the version 1 or 2 pack index at `idx`, which must sit next to
+    /// its `.pack` file.
+    pub fn open(idx: &path::Path) -> anyhow::Result<Self> {
+        let pack = map(&idx.with_extension("pack"))?;
+        let map = map(idx)?;
+
+        let version = match map.get(..8) {
+            Some(header) if header[..4] == IDX_SIGNATURE[..] => BigEndian::read_u32(&header[4..]),
+            _ => 1,
+        };
+        let (fanout, entry) = match version {
+            1 => (0, 24),
+            // Ids, CRC32 checksums of packed data, and offsets.
+            2 => (8, 28),
+            version => return Err(anyhow!("Unsupported pack index version: {}", version)),
+        };
+
+        let truncated = || anyhow!("Truncated pack index: {}", idx.display());
+        let count = map
+            .get(fanout + 1020..fanout + 1024)
+            .map(BigEndian::read_u32)
+            .ok_or_else(truncated)? as usize;
+        // Both checksums follow the tables.
+        if map.len() < fanout + 1024 + entry * count + 40 {
+            return Err(truncated());
+        }
+
+        Ok(Pack {
+            idx: map,
+            pack,
+            version,
+            count,
+            bases: cell::RefCell::default(),
+        })
+    }
+
+    /// Look up the offset of object `id` within the `.pack` file.
+    pub fn find(&self, id: &object::Id) -> Option<u64> {
+        let first = id.as_bytes()[0] as usize;
+        let mut lo = match first {
+            0 => 0,
+            _ => self.fanout(first - 1),
+        };
+        let mut hi = self.fanout(first).min(self.count);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            match self.id(mid).cmp(id) {
+                cmp::Ordering::Less => lo = mid + 1,
+                cmp::Ordering::Greater => hi = mid,
+                cmp::Ordering::Equal => return self.offset(mid),
+            }
+        }
+        None
+    }
+
+    /// Ids of all objects in this pack, in sorted order.
+    pub fn ids(&self) -> impl Iterator<Item = object::Id> + '_ {
+        (0..self.count).map(move |index| self.id(index))
+    }
+
+    /// Number of ids whose first byte is at most `byte`.
+    fn fanout(&self, byte: usize) -> usize {
+        let start = match self.version {
+            1 => 0,
+            _ => 8,
+        };
+        BigEndian::read_u32(&self.idx[start + 4 * byte..]) as usize
+    }
+
+    /// Id of the object at `index` in sorted order.
+    fn id(&self, index: usize) -> object::Id {
+        let start = match self.version {
+            1 => 1024 + 24 * index + 4,
+            _ => 8 + 1024 + 20 * index,
+        };
+        let mut id = [0; 20];
+        id.copy_from_slice(&self.idx[start..start + 20]);
+        object::Id::from_bytes(id)
+    }
+
+    /// Offset within the `.pack` file of the object at `index` in sorted
+    /// order, if the index isn't corrupt.
+    fn offset(&self, index: usize) -> Option<u64> {
+        if self.version == 1 {
+            return Some(BigEndian::read_u32(&self.idx[1024 + 24 * index..]) as u64);
+        }
+
+        let table = 8 + 1024 + 24 * self.count;
+        let offset = BigEndian::read_u32(&self.idx[table + 4 * index..]);
+        if offset & 0x8000_0000 == 0 {
+            return Some(offset as u64);
+        }
+
+        // Offsets past 2 GiB are stored in a separate 64-bit table.
+        let large = table + 4 * self.count + 8 * (offset & 0x7fff_ffff) as usize;
+        self.idx.get(large..large + 8).map(BigEndian::read_u64)
+    }
+
+    /// Read the type and length of object `id`, inflating only the first
+    /// bytes of a delta to find its result size.
+    pub fn read_header(&self, id: &object::Id) -> anyhow::Result<Option<object::Header>> {
+        let offset = match self.find(id) {
+            None => return Ok(None),
+            Some(offset) => offset,
+        };
+
+        let (kind, len) = self.read_header_at(offset)?;
+        Ok(Some(object::Header {
+            r#type: kind.as_type(),
+            len,
+        }))
+    }
+
+    /// Read object `id` in the same serialized form as a loose object,
+    /// resolving any deltas along the way.
+    pub fn read(&self, id: &object::Id) -> anyhow::Result<Option<Vec<u8>>> {
+        let offset = match self.find(id) {
+            None => return Ok(None),
+            Some(offset) => offset,
+        };
+
+        let (kind, data) = self.read_at(offset)?;
+
+        let mut buffer = format!("{} {}\0", kind.as_str(), data.len()).into_bytes();
+        buffer.extend_from_slice(&data);
+        Ok(Some(buffer))
+    }
+
+    fn read_header_at(&self, offset: u64) -> anyhow::Result<(Kind, usize)> {
+        let (kind, size, base, data) = self.read_entry(offset)?;
+        let base = match base {
+            None => return Ok((kind, size)),
+            Some(base) => base,
+        };
+
+        // Two sizes of at most 10 bytes each start the delta.
+        let mut prefix = Vec::with_capacity(20);
+        flate2::bufread::ZlibDecoder::new(data)
+            .take(20)
+            .read_to_end(&mut prefix)?;
+
+        let mut delta = &*prefix;
+        read_size(&mut delta)?;
+        let size = read_size(&mut delta)?;
+        let (kind, _) = self.read_header_at(base)?;
+        Ok((kind, size))
+    }
+
+    fn read_at(&self, offset: u64) -> anyhow::Result<(Kind, Vec<u8>)> {
+        let (kind, size, base, data) = self.read_entry(offset)?;
+
+        let mut buffer = Vec::with_capacity(size);
+        flate2::bufread::ZlibDecoder::new(data).read_to_end(&mut buffer)?;
+
+        if buffer.len() != size {
+            return Err(anyhow!(
+                "Expected {} bytes for packed object at {}, but found {}",
+                size,
+                offset,
+                buffer.len(),
+            ));
+        }
+
+        match base {
+            None => Ok((kind, buffer)),
+            Some(base) => {
+                let (kind, base) = self.read_base(base)?;
+                Ok((kind, apply_delta(&base, &buffer)?))
+            }
+        }
+    }
+
+    /// Read the delta base at `offset`, which neighboring objects in a delta
+    /// chain likely share, so it's cached.
+    fn read_base(&self, offset: u64) -> anyhow::Result<(Kind, Rc<[u8]>)> {
+        if let Some(base) = self.bases.borrow_mut().get(offset) {
+            return Ok(base);
+        }
+        let (kind, data) = self.read_at(offset)?;
+        let data = Rc::<[u8]>::from(data);
+        self.bases.borrow_mut().insert(offset, kind, data.clone());
+        Ok((kind, data))
+    }
+
+    /// Parse the entry header at `offset`, returning its kind, inflated size,
+    /// the offset of its delta base (if any), and its compressed data.
+    fn read_entry(&self, offset: u64) -> anyhow::Result<(Kind, usize, Option<u64>, &[u8])> {
+        let mut reader = usize::try_from(offset)
+            .ok()
+            .and_then(|offset| self.pack.get(offset..))
+            .ok_or_else(|| anyhow!("Packed object offset {} is past the end", offset))?;
+        let (kind, size) = read_header(&mut reader, offset)?;
+        let base = match kind {
+            Kind::OfsDelta => Some(
+                offset
+                    .checked_sub(read_offset(&mut reader)?)
+                    .ok_or_else(|| anyhow!("Invalid delta base offset at {}", offset))?,
+            ),
+            Kind::RefDelta => {
+                let id = object::Id::read_bytes(&mut reader)?;
+                Some(
+                    self.find(&id)
+                        .ok_or_else(|| anyhow!("Missing delta base: {}", id))?,
+                )
+            }
+            _ => None,
+        };
+
+        Ok((kind, size, base, reader))
+    }
+}
+
+/// Map the file at `path` into memory.
+fn map(path: &path::Path) -> anyhow::Result<memmap2::Mmap> {
+    let file =
+        fs::File::open(path).with_context(|| format!("Unable to open '{}'", path.display()))?;
+    // SAFETY: packs and their indices are never modified once renamed into
+    // place, only deleted, which leaves existing mappings intact.
+    Ok(unsafe { memmap2::Mmap::map(&file)? })
+}
+
+/// Most recently used delta bases of a pack, by offset, bounded in both
+/// number and total size.
+#[derive(Debug, Default)]
+struct Bases {
+    /// Most recently used first.
+    entries: VecDeque<(u64, Kind, Rc<[u8]>)>,
+    size: usize,
+}
+
+impl Bases {
+    /// Most delta bases cached per pack.
+    const MAX_COUNT: usize = 256;
+
+    /// Most bytes of delta bases cached per pack.
+    const MAX_SIZE: usize = 16 << 20;
+
+    fn get(&mut self, offset: u64) -> Option<(Kind, Rc<[u8]>)> {
+        let index = self
+            .entries
+            .iter()
+            .position(|(cached, _, _)| *cached == offset)?;
+        let entry = self.entries.remove(index)?;
+        let base = (entry.1, entry.2.clone());
+        self.entries.push_front(entry);
+        Some(base)
+    }
+
+    fn insert(&mut self, offset: u64, kind: Kind, data: Rc<[u8]>) {
+        if data.len() > Self::MAX_SIZE {
+            return;
+        }
+        self.size += data.len();
+        self.entries.push_front((offset, kind, data));
+        while self.entries.len() > Self::MAX_COUNT || self.size > Self::MAX_SIZE {
+            match self.entries.pop_back() {
+                Some((_, _, evicted)) => self.size -= evicted.len(),
+                None => break,
+            }
+        }
+    }
+}
+
+/// Read the type and inflated size of the packed object at `offset`, with
+/// the size continued in little-endian groups of 7 bits.
+fn read_header<R: io::Read>(reader: &mut R, offset: u64) -> anyhow::Result<(Kind, usize)> {
+    let byte = reader.read_u8()?;
+    let kind = match (byte >> 4) & 0b111 {
+        1 => Kind::Commit,
+        2 => Kind::Tree,
+        3 => Kind::Blob,
+        4 => Kind::Tag,
+        6 => Kind::OfsDelta,
+        7 => Kind::RefDelta,
+        kind => return Err(anyhow!("Invalid packed object type {} at {}", kind, offset)),
+    };
+
+    let mut size = (byte & 0x0f) as usize;
+    let mut shift = 4;
+    let mut more = byte & 0x80 != 0;
+    while more {
+        let byte = reader.read_u8()?;
+        size |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+        more = byte & 0x80 != 0;
+    }
+    Ok((kind, size))
+}
+
+/// Read the negative offset of an `OFS_DELTA` base, which adds one before
+/// each shift so that every offset has a unique encoding.
+fn read_offset<R: io::Read>(reader: &mut R) -> io::Result<u64> {
+    let mut byte = reader.read_u8()?;
+    let mut offset = (byte & 0x7f) as u64;
+    while byte & 0x80 != 0 {
+        byte = reader.read_u8()?;
+        offset = ((offset + 1) << 7) | (byte & 0x7f) as u64;
+    }
+    Ok(offset)
+}
+
+/// Read a little-endian base-128 size from the start of a delta.
+fn read_size(delta: &mut &[u8]) -> anyhow::Result<usize> {
+    let mut size = 0;
+    let mut shift = 0;
+    loop {
+        let byte = delta.read_u8()?;
+        size |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(size);
+        }
+    }
+}
+
+/// Reconstruct an object from its `base` and a sequence of copy and insert
+/// instructions.
+fn apply_delta(base: &[u8], mut delta: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let base_size = read_size(&mut delta)?;
+    if base_size != base.len() {
+        return Err(anyhow!(
+            "Expected delta base of {} bytes, but found {}",
+            base_size,
+            base.len(),
+        ));
+    }
+
+    let size = read_size(&mut delta)?;
+    let mut target = Vec::with_capacity(size);
+
+    while let Some((&instruction, rest)) = delta.split_first() {
+        delta = rest;
+        match instruction {
+            0 => return Err(anyhow!("Invalid delta instruction: 0")),
+            // Insert the next `instruction` bytes of the delta.
+            1..=0x7f => {
+                let len = instruction as usize;
+                if delta.len() < len {
+                    return Err(anyhow!("Truncated delta insert"));
+                }
+                target.extend_from_slice(&delta[..len]);
+                delta = &delta[len..];
+            }
+            // Copy a range of the base, whose offset and size bytes are
+            // present only if their bit is set.
+            _ => {
+                let mut offset = 0;
+                for bit in 0..4 {
+                    if instruction & (1 << bit) != 0 {
+                        offset |= (delta.read_u8()? as usize) << (8 * bit);
+                    }
+                }
+                let mut len = 0;
+                for bit in 0..3 {
+                    if instruction & (1 << (4 + bit)) != 0 {
+                        len |= (delta.read_u8()? as usize) << (8 * bit);
+                    }
+                }
+                if len == 0 {
+                    len = 0x10000;
+                }
+                let copy = base
+                    .get(offset..offset + len)
+                    .ok_or_else(|| anyhow!("Delta copy out of bounds"))?;
+                target.extend_from_slice(copy);
+            }
+        }
+    }
+
+    if target.len() != size {
+        return Err(anyhow!(
+            "Expected delta result of {} bytes, but found {}",
+            size,
+            target.len(),
+        ));
+    }
+
+    Ok(target)
+}
+
+/// A packfile and its index, built in memory.
+#[derive(Clone, Debug)]
+pub struct Packfile {
+    pub id: PackId,
+    pub pack: Vec<u8>,
+    pub index: Vec<u8>,
+}
+
+impl Packfile {
+    /// Build a version 2 packfile from serialized loose `objects`, storing
+    /// each object as a delta against a similar object where that is smaller.
+    pub fn build(objects: &[(object::Id, Vec<u8>)]) -> anyhow::Result<Self> {
+        Self::build_thin(objects, &[])
+    }
+
+    /// Build a packfile like [`Packfile::build`], but also consider deltas
+    /// against serialized `bases`, which the reader already has and which
+    /// are left out of the pack.
+    ///
+    /// Such a thin pack can't be indexed on its own: the reader must add the
+    /// missing bases, like `git index-pack --fix-thin`.
+    pub fn build_thin(
+        objects: &[(object::Id, Vec<u8>)],
+        bases: &[(object::Id, Vec<u8>)],
+    ) -> anyhow::Result<Self> {
+        let mut parsed = Vec::with_capacity(objects.len() + bases.len());
+        for (external, (id, bytes)) in objects
+            .iter()
+            .map(|object| (false, object))
+            .chain(bases.iter().map(|base| (true, base)))
+        {
+            let (r#type, rest) = split_once(bytes, b' ')
+                .ok_or_else(|| anyhow!("Malformed object header: {}", id))?;
+            let (_, data) =
+                split_once(rest, 0).ok_or_else(|| anyhow!("Malformed object header: {}", id))?;
+            let kind = Kind::parse(r#type).ok_or_else(|| anyhow!("Unknown object type: {}", id))?;
+            parsed.push((*id, kind, data, external));
+        }
+
+        // Like `git`, visit objects of the same type from largest to smallest,
+        // so that deltas tend to remove data rather than add it. Duplicates
+        // end up adjacent, with any copy to be written first.
+        parsed.sort_by(
+            |(a_id, a_kind, a, a_external), (b_id, b_kind, b, b_external)| {
+                a_kind
+                    .as_u8()
+                    .cmp(&b_kind.as_u8())
+                    .then(b.len().cmp(&a.len()))
+                    .then(a_id.cmp(b_id))
+                    .then(a_external.cmp(b_external))
+            },
+        );
+        parsed.dedup_by_key(|(id, _, _, _)| *id);
+
+        let count = parsed
+            .iter()
+            .filter(|(_, _, _, external)| !external)
+            .count();
+        let mut pack = Vec::new();
+        pack.extend_from_slice(b"PACK");
+        pack.write_u32::<BigEndian>(2)?;
+        pack.write_u32::<BigEndian>(count as u32)?;
+
+        let mut entries: Vec<(object::Id, u64, u32)> = Vec::with_capacity(count);
+        // Offset of each written object, or `None` for external bases.
+        let mut offsets = Vec::with_capacity(parsed.len());
+        let mut depths = Vec::with_capacity(parsed.len());
+
+        for (index, (id, kind, data, external)) in parsed.iter().enumerate() {
+            if *external {
+                offsets.push(None);
+                depths.push(0);
+                continue;
+            }
+
+            let mut best: Option<(usize, Vec<u8>)> = None;
+
+            for base in index.saturating_sub(WINDOW)..index {
+                let (_, base_kind, base_data, _) = parsed[base];
+                if base_kind != *kind || depths[base] >= MAX_DEPTH {
+                    continue;
+                }
+                let delta = encode_delta(base_data, data);
+                let limit = best.as_ref().map_or(data.len() / 2, |(_, best)| best.len());
+                if delta.len() < limit {
+                    best = Some((base, delta));
+                }
+            }
+
+            let offset = pack.len() as u64;
+            let start = pack.len();
+
+            match &best {
+                None => {
+                    write_header(&mut pack, *kind, data.len())?;
+                    depths.push(0);
+                }
+                Some((base, delta)) => {
+                    match offsets[*base] {
+                        Some(base_offset) => {
+                            write_header(&mut pack, Kind::OfsDelta, delta.len())?;
+                            write_offset(&mut pack, offset - base_offset);
+                        }
+                        None => {
+                            write_header(&mut pack, Kind::RefDelta, delta.len())?;
+                            parsed[*base].0.write_bytes(&mut pack)?;
+                        }
+                    }
+                    depths.push(depths[*base] + 1);
+                }
+            }
+
+            let payload = best.as_ref().map_or(*data, |(_, delta)| &delta[..]);
+            let mut encoder =
+                flate2::write::ZlibEncoder::new(&mut pack, flate2::Compression::default());
+            io::Write::write_all(&mut encoder, payload)?;
+            encoder.finish()?;
+
+            let mut crc = flate2::Crc::new();
+            crc.update(&pack[start..]);
+            entries.push((*id, offset, crc.sum()));
+            offsets.push(Some(offset));
+        }
+
+        let id = PackId(object::Id::hash(&pack));
+        id.0.write_bytes(&mut pack)?;
+
+        entries.sort_by_key(|(id, _, _)| *id);
+        let index = write_index(&entries, &id)?;
+
+        Ok(Packfile { id, pack, index })
+    }
+
+    /// Index a complete packfile `pack`, e.g. one received from a remote,
+    /// resolving its deltas to find the id of every object.
+    pub fn index(pack: Vec<u8>) -> anyhow::Result<Self> {
+        if pack.len() < 32 || &pack[..4] != b"PACK" {
+            return Err(anyhow!("Not a packfile"));
+        }
+
+        let end = pack.len() - 20;
+        let id = PackId(object::Id::hash(&pack[..end]));
+        if id.0.as_bytes()[..] != pack[end..] {
+            return Err(anyhow!("Packfile checksum mismatch"));
+        }
+
+        let mut reader = io::Cursor::new(&pack[..end]);
+        reader.set_position(4);
+        match reader.read_u32::<BigEndian>()? {
+            2 | 3 => (),
+            version => return Err(anyhow!("Unsupported packfile version: {}", version)),
+        }
+        let count = reader.read_u32::<BigEndian>()? as usize;
+
+        let mut entries = Vec::with_capacity(count);
+        let mut ids = vec![None; count];
+        scan(&mut reader, count, &mut entries, &mut |index, id, _| {
+            ids[index] = Some(id)
+        })?;
+        if reader.position() != end as u64 {
+            return Err(anyhow!("Unexpected data after {} packed objects", count));
+        }
+
+        resolve(&pack, &entries, &mut |index, id, _| ids[index] = Some(id));
+        let unresolved = ids.iter().filter(|id| id.is_none()).count();
+        if unresolved > 0 {
+            return Err(anyhow!("Packfile has {} unresolved deltas", unresolved));
+        }
+
+        let mut indexed = entries
+            .iter()
+            .zip(&ids)
+            .filter_map(|(entry, id)| {
+                let mut crc = flate2::Crc::new();
+                crc.update(&pack[entry.offset as usize..entry.end as usize]);
+                id.map(|id| (id, entry.offset, crc.sum()))
+            })
+            .collect::<Vec<_>>();
+        indexed.sort_by_key(|(id, _, _)| *id);
+        let index = write_index(&indexed, &id)?;
+
+        Ok(Packfile { id, pack, index })
+    }
+
+    /// Save this packfile into the `.git/objects/pack` directory `directory`.
+    pub fn save(&self, directory: &path::Path) -> io::Result<()> {
+        // Write the pack before its index, since readers discover packs
+        // through their `.idx` files.
+        for (extension, bytes) in &[("pack", &self.pack), ("idx", &self.index)] {
+            let path = directory.join(format!("pack-{}.{}", self.id, extension));
+            if path.exists() {
+                continue;
+            }
+            let mut file = file::Temp::new(path)?;
+            io::Write::write_all(&mut file, bytes)?;
+            file.commit()?;
+        }
+        Ok(())
+    }
+}
+
+/// Recover the objects at the start of `pack`, a packfile cut off while
+/// it was being received, as ids and serialized objects in pack order.
+///
+/// Stops at the first entry that is incomplete or corrupt, and skips deltas
+/// whose bases weren't received.
+pub fn salvage(pack: &[u8]) -> Vec<(object::Id, Vec<u8>)> {
+    if pack.len() < 12 || &pack[..4] != b"PACK" {
+        return Vec::new();
+    }
+
+    let count = u32::from_be_bytes([pack[8], pack[9], pack[10], pack[11]]) as usize;
+    let mut reader = io::Cursor::new(pack);
+    reader.set_position(12);
+
+    let mut entries = Vec::new();
+    let mut objects = Vec::new();
+    let mut keep = |index, id, bytes: &[u8]| objects.push((index, id, bytes.to_vec()));
+    scan(&mut reader, count, &mut entries, &mut keep).ok();
+    resolve(pack, &entries, &mut keep);
+
+    objects.sort_by_key(|(index, _, _)| *index);
+    objects
+        .into_iter()
+        .map(|(_, id, bytes)| (id, bytes))
+        .collect()
+}
+
+/// Delta base of a packed object, by id or by offset in the same pack.
+type Base = Result<object::Id, u64>;
+
+/// Location of a packed object found by [`scan`].
+struct Entry {
+    kind: Kind,
+    /// Inflated size of its data.
+    size: usize,
+    offset: u64,
+    /// Offset of its compressed data, after the header and any base.
+    data: u64,
+    /// Offset just past its compressed data.
+    end: u64,
+    base: Option<Base>,
+    /// Id of an object that isn't a delta.
+    id: Option<object::Id>,
+}
+
+impl Entry {
+    /// Inflate this entry's data from `pack`.
+    fn inflate(&self, pack: &[u8]) -> Option<Vec<u8>> {
+        let compressed = pack.get(self.data as usize..self.end as usize)?;
+        let mut data = Vec::with_capacity(self.size);
+        flate2::bufread::ZlibDecoder::new(compressed)
+            .read_to_end(&mut data)
+            .ok()?;
+        Some(data).filter(|data| data.len() == self.size)
+    }
+}
+
+/// Read up to `count` packed objects from `reader` into `entries`, passing
+/// the index, id, and serialized bytes of each one that isn't a delta to
+/// `visit`. Entries read before any error are kept.
+fn scan(
+    reader: &mut io::Cursor<&[u8]>,
+    count: usize,
+    entries: &mut Vec<Entry>,
+    visit: &mut dyn FnMut(usize, object::Id, &[u8]),
+) -> anyhow::Result<()> {
+    for index in 0..count {
+        let offset = reader.position();
+        let (kind, size) = read_header(reader, offset)?;
+        let base = match kind {
+            Kind::OfsDelta => Some(Err(offset.checked_sub(read_offset(reader)?).ok_or_else(
+                || anyhow!("Invalid delta base for packed object at {}", offset),
+            )?)),
+            Kind::RefDelta => Some(Ok(object::Id::read_bytes(reader)?)),
+            _ => None,
+        };
+
+        // Only objects that aren't deltas can be hashed now, so delta data
+        // is inflated just to find where it ends.
+        let data = reader.position();
+        let mut id = None;
+        let inflated = match base {
+            Some(_) => io::copy(
+                &mut flate2::bufread::ZlibDecoder::new(&mut *reader),
+                &mut io::sink(),
+            )? as usize,
+            None => {
+                let mut bytes = format!("{} {}\0", kind.as_str(), size).into_bytes();
+                let header = bytes.len();
+                flate2::bufread::ZlibDecoder::new(&mut *reader).read_to_end(&mut bytes)?;
+                if bytes.len() - header == size {
+                    let hashed = object::Id::hash(&bytes);
+                    visit(index, hashed, &bytes);
+                    id = Some(hashed);
+                }
+                bytes.len() - header
+            }
+        };
+        if inflated != size {
+            return Err(anyhow!(
+                "Expected {} bytes for packed object at {}, but found {}",
+                size,
+                offset,
+                inflated,
+            ));
+        }
+
+        entries.push(Entry {
+            kind,
+            size,
+            offset,
+            data,
+            end: reader.position(),
+            base,
+            id,
+        });
+    }
+    Ok(())
+}
+
+/// Resolve the deltas among `entries`, passing the index, id, and serialized
+/// bytes of each one to `visit`, and skipping those whose bases are missing
+/// or corrupt.
+///
+/// Each tree of deltas is walked depth-first from the object at its root,
+/// so only the chain of bases leading to the current delta is kept in
+/// memory. Objects that aren't deltas are only inflated again if they're
+/// bases.
+fn resolve(pack: &[u8], entries: &[Entry], visit: &mut dyn FnMut(usize, object::Id, &[u8])) {
+    let mut by_offset: HashMap<u64, Vec<usize>> = HashMap::new();
+    let mut by_id: HashMap<object::Id, Vec<usize>> = HashMap::new();
+    for (index, entry) in entries.iter().enumerate() {
+        match entry.base {
+            None => (),
+            Some(Err(offset)) => by_offset.entry(offset).or_default().push(index),
+            Some(Ok(id)) => by_id.entry(id).or_default().push(index),
+        }
+    }
+
+    // Removing deltas once visited means each is resolved at most once,
+    // even if its base appears in the pack twice.
+    let mut children = |entry: &Entry, id: &object::Id| {
+        let mut children = by_offset.remove(&entry.offset).unwrap_or_default();
+        children.extend(by_id.remove(id).unwrap_or_default());
+        children
+    };
+
+    // Resolved bases from the root to the current delta, each as its kind,
+    // serialized bytes, header length, and deltas still to be resolved.
+    let mut chain: Vec<(Kind, Vec<u8>, usize, Vec<usize>)> = Vec::new();
+    for root in entries.iter().filter(|entry| entry.base.is_none()) {
+        let deltas = match &root.id {
+            Some(id) => children(root, id),
+            None => continue,
+        };
+        if deltas.is_empty() {
+            continue;
+        }
+        let data = match root.inflate(pack) {
+            Some(data) => data,
+            None => continue,
+        };
+        let (bytes, header) = serialize(root.kind, &data);
+        chain.push((root.kind, bytes, header, deltas));
+
+        while let Some((kind, base, header, deltas)) = chain.last_mut() {
+            let index = match deltas.pop() {
+                Some(index) => index,
+                None => {
+                    chain.pop();
+                    continue;
+                }
+            };
+
+            let entry = &entries[index];
+            let data = match entry
+                .inflate(pack)
+                .and_then(|delta| apply_delta(&base[*header..], &delta).ok())
+            {
+                Some(data) => data,
+                None => continue,
+            };
+            let kind = *kind;
+            let (bytes, header) = serialize(kind, &data);
+            let id = object::Id::hash(&bytes);
+            visit(index, id, &bytes);
+
+            let deltas = children(entry, &id);
+            if !deltas.is_empty() {
+                chain.push((kind, bytes, header, deltas));
+            }
+        }
+    }
+}
+
+/// Serialize an object of kind `kind` like a loose object, returning its
+/// bytes and the length of its header.
+fn serialize(kind: Kind, data: &[u8]) -> (Vec<u8>, usize) {
+    let mut bytes = format!("{} {}\0", kind.as_str(), data.len()).into_bytes();
+    let header = bytes.len();
+    bytes.extend_from_slice(data);
+    (bytes, header)
+}
+
+fn split_once(bytes: &[u8], separator: u8) -> Option<(&[u8], &[u8])> {
+    let index = bytes.iter().position(|byte| *byte == separator)?;
+    Some((&bytes[..index], &bytes[index + 1..]))
+}
+
+/// Write the type and inflated size of a packed object.
+fn write_header(pack: &mut Vec<u8>, kind: Kind, size: usize) -> io::Result<()> {
+    let mut byte = (kind.as_u8() << 4) | (size & 0x0f) as u8;
+    let mut size = size >> 4;
+    while size > 0 {
+        pack.write_u8(byte | 0x80)?;
+        byte = (size & 0x7f) as u8;
+        size >>= 7;
+    }
+    pack.write_u8(byte)
+}
+
+/// Inverse of [`read_offset`].
+fn write_offset(pack: &mut Vec<u8>, mut offset: u64) {
+    let mut bytes = vec![(offset & 0x7f) as u8];
+    offset >>= 7;
+    while offset > 0 {
+        offset -= 1;
+        bytes.push(0x80 | (offset & 0x7f) as u8);
+        offset >>= 7;
+    }
+    bytes.reverse();
+    pack.extend_from_slice(&bytes);
+}
+
+/// Write a version 2 index for `entries` of `(id, offset, crc)`, sorted by id.
+fn write_index(entries: &[(object::Id, u64, u32)], pack: &PackId) -> io::Result<Vec<u8>> {
+    let mut index = Vec::new();
+    index.extend_from_slice(IDX_SIGNATURE);
+    index.write_u32::<BigEndian>(2)?;
+
+    let mut count = 0;
+    for first in 0..=255u8 {
+        count += entries
+            .iter()
+            .filter(|(id, _, _)| id.as_bytes()[0] == first)
+            .count();
+        index.write_u32::<BigEndian>(count as u32)?;
+    }
+
+    for (id, _, _) in entries {
+        id.write_bytes(&mut index)?;
+    }
+    for (_, _, crc) in entries {
+        index.write_u32::<BigEndian>(*crc)?;
+    }
+
+    let mut large = Vec::new();
+    for (_, offset, _) in entries {
+        match u32::try_from(*offset) {
+            Ok(offset) if offset & 0x8000_0000 == 0 => index.write_u32::<BigEndian>(offset)?,
+            _ => {
+                index.write_u32::<BigEndian>(0x8000_0000 | large.len() as u32)?;
+                large.push(*offset);
+            }
+        }
+    }
+    for offset in large {
+        index.write_u64::<BigEndian>(offset)?;
+    }
+
+    pack.0.write_bytes(&mut index)?;
+    object::Id::hash(&index).write_bytes(&mut index)?;
+    Ok(index)
+}
+
+/// Inverse of [`read_size`].
+fn write_size(delta: &mut Vec<u8>, mut size: usize) {
+    while size >= 0x80 {
+        delta.push(0x80 | (size & 0x7f) as u8);
+        size >>= 7;
+    }
+    delta.push(size as u8);
+}
+
+/// Encode `target` as copies from `base` and inserts, finding copies by
+/// matching fixed-size chunks of `base` and extending them in both directions.
+fn encode_delta(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut delta = Vec::new();
+    write_size(&mut delta, base.len());
+    write_size(&mut delta, target.len());
+
+    let mut chunks = HashMap::new();
+    for offset in (0..base.len().saturating_sub(BLOCK - 1)).step_by(BLOCK) {
+        chunks
+            .entry(&base[offset..offset + BLOCK])
+            .or_insert(offset);
+    }
+
+    let mut pending = 0;
+    let mut cursor = 0;
+    while cursor + BLOCK <= target.len() {
+        let mut source = match chunks.get(&target[cursor..cursor + BLOCK]) {
+            None => {
+                cursor += 1;
+                continue;
+            }
+            Some(source) => *source,
+        };
+
+        let mut start = cursor;
+        while source > 0 && start > pending && base[source - 1] == target[start - 1] {
+            source -= 1;
+            start -= 1;
+        }
+
+        let mut len = cursor + BLOCK - start;
+        while source + len < base.len()
+            && start + len < target.len()
+            && base[source + len] == target[start + len]
+        {
+            len += 1;
+        }
+
+        encode_insert(&mut delta, &target[pending..start]);
+        encode_copy(&mut delta, source, len);
+        cursor = start + len;
+        pending = cursor;
+    }
+
+    encode_insert(&mut delta, &target[pending..]);
+    delta
+}
+
+fn encode_insert(delta: &mut Vec<u8>, data: &[u8]) {
+    for chunk in data.chunks(0x7f) {
+        delta.push(chunk.len() as u8);
+        delta.extend_from_slice(chunk);
+    }
+}
+
+fn encode_copy(delta: &mut Vec<u8>, mut offset: usize, mut len: usize) {
+    while len > 0 {
+        let size = len.min(0xff_ffff);
+        let instruction = delta.len();
+        delta.push(0x80);
+        for bit in 0..4 {
+            let byte = (offset >> (8 * bit)) as u8;
+            if byte != 0 {
+                delta[instruction] |= 1 << bit;
+                delta.push(byte);
+            }
+        }
+        for bit in 0..3 {
+            let byte = (size >> (8 * bit)) as u8;
+            if byte != 0 {
+                delta[instruction] |= 1 << (4 + bit);
+                delta.push(byte);
+            }
+        }
+        offset += size;
+        len -= size;
+    }
+}
+
+#[test]
+fn delta() -> anyhow::Result<()> {
+    let base = b"hello, world\n";
+    // Sizes, copy `hello` (offset 0, size 5), insert `, grit`, copy `\n`.
+    let delta = [
+        13, 12, 0x90, 5, 6, b',', b' ', b'g', b'r', b'i', b't', 0x91, 12, 1,
+    ];
+    assert_eq!(apply_delta(base, &delta)?, b"hello, grit\n");
+    assert!(apply_delta(b"short", &delta).is_err());
+    Ok(())
+}
+
+#[test]
+fn offset() -> io::Result<()> {
+    assert_eq!(read_offset(&mut &[0x05][..])?, 5);
+    assert_eq!(read_offset(&mut &[0x80, 0x00][..])?, 128);
+    assert_eq!(read_offset(&mut &[0x81, 0x7f][..])?, 383);
+    Ok(())
+}
+
+#[test]
+fn bases() {
+    let mut bases = Bases::default();
+    for offset in 0..Bases::MAX_COUNT as u64 {
+        bases.insert(offset, Kind::Blob, Rc::from(&b"base"[..]));
+    }
+    assert!(bases.get(0).is_some());
+
+    // The least recently used base is evicted first.
+    bases.insert(1000, Kind::Blob, Rc::from(&b"base"[..]));
+    assert!(bases.get(0).is_some());
+    assert!(bases.get(1).is_none());
+
+    bases.insert(2000, Kind::Blob, Rc::from(vec![0; Bases::MAX_SIZE - 1]));
+    assert!(bases.get(2000).is_some());
+    assert!(bases.get(0).is_none());
+    assert!(bases.size <= Bases::MAX_SIZE);
+
+    bases.insert(3000, Kind::Blob, Rc::from(vec![0; Bases::MAX_SIZE + 1]));
+    assert!(bases.get(3000).is_none());
+}
+
+#[test]
+fn round_trip() -> anyhow::Result<()> {
+    let base = (0..2000)
+        .map(|line| format!("line {}\n", line))
+        .collect::<String>();
+    let edited = base.replace("line 1000\n", "edited\n");
+    assert!(encode_delta(base.as_bytes(), edited.as_bytes()).len() < 100);
+    assert_eq!(
+        apply_delta(
+            base.as_bytes(),
+            &encode_delta(base.as_bytes(), edited.as_bytes())
+        )?,
+        edited.as_bytes(),
+    );
+
+    let objects = [base, edited, String::from("small")]
+        .iter()
+        .map(|data| {
+            let blob = crate::Object::Blob(object::Blob::new(data.as_bytes().to_vec()));
+            let bytes = blob.to_bytes();
+            (object::Id::hash(&bytes), bytes)
+        })
+        .collect::<Vec<_>>();
+
+    let directory = crate::util::TempDir::new();
+
+    let packfile = Packfile::build(&objects)?;
+    packfile.save(&directory)?;
+    let packs = Packs::new(directory.to_path_buf());
+    let read = objects
+        .iter()
+        .map(|(id, _)| packs.read(id))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let headers = objects
+        .iter()
+        .map(|(id, _)| packs.read_header(id))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let ids = packs.ids()?;
+
+    assert!(packfile.pack.len() < objects[0].1.len());
+    assert_eq!(
+        Packfile::index(packfile.pack.clone())?.index,
+        packfile.index
+    );
+    assert_eq!(ids.len(), objects.len());
+    for (((_, expected), actual), header) in objects.iter().zip(read).zip(headers) {
+        assert_eq!(actual.as_ref(), Some(expected));
+        assert_eq!(header, Some(object::Header::read(&mut &**expected)?));
+    }
+    Ok(())
+}
+
+#[test]
+fn thin() -> anyhow::Result<()> {
+    let blob = |data: String| {
+        let bytes = crate::Object::Blob(object::Blob::new(data.into_bytes())).to_bytes();
+        (object::Id::hash(&bytes), bytes)
+    };
+    let base = (0..2000)
+        .map(|line| format!("line {}\n", line))
+        .collect::<String>();
+    let edited = base.replace("line 1000\n", "edited\n");
+    let (base, edited) = (blob(base), blob(edited));
+
+    let (base_id, edited_len) = (base.0, edited.1.len());
+
+    let packfile = Packfile::build_thin(&[edited], &[base])?;
+    let mut reader = &packfile.pack[12..];
+    let (kind, _) = read_header(&mut reader, 12)?;
+
+    assert_eq!(&packfile.pack[8..12], &[0, 0, 0, 1]);
+    assert!(matches!(kind, Kind::RefDelta));
+    assert_eq!(object::Id::read_bytes(&mut reader)?, base_id);
+    assert!(packfile.pack.len() < edited_len / 10);
+    assert!(Packfile::index(packfile.pack).is_err());
+    Ok(())
+}
+
+#[test]
+fn truncated() -> anyhow::Result<()> {
+    let base = (0..2000)
+        .map(|line| format!("line {}\n", line))
+        .collect::<String>();
+    let edited = base.replace("line 1000\n", "edited\n");
+    let objects = [base, edited, String::from("small")]
+        .iter()
+        .map(|data| {
+            let bytes = crate::Object::Blob(object::Blob::new(data.as_bytes().to_vec())).to_bytes();
+            (object::Id::hash(&bytes), bytes)
+        })
+        .collect::<Vec<_>>();
+
+    let packfile = Packfile::build(&objects)?;
+    let mut complete = salvage(&packfile.pack[..packfile.pack.len() - 20]);
+    let mut expected = objects.clone();
+    complete.sort();
+    expected.sort();
+    assert_eq!(complete, expected);
+
+    // The smallest object is written last.
+    let truncated = salvage(&packfile.pack[..packfile.pack.len() - 22]);
+    assert_eq!(truncated.len(), 2);
+    assert!(truncated.iter().all(|object| objects.contains(object)));
+    assert!(salvage(b"PACK").is_empty());
+    Ok(())
+}
@@ -1,34 +1,195 @@
+use std::cmp;
+use std::env;
 use std::ops;
+use std::path;
 
+use rand::Rng as _;
+
+pub mod order;
+pub mod rename;
+pub mod textconv;
+pub mod tree;
+
+/// Number of unchanged lines to show around each change.
+pub const CONTEXT: usize = 3;
+
+/// A single step in an edit script transforming `a` into `b`.
+///
+/// Each variant stores zero-based indices into the original sequences.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Edit {
+    Equal { a: usize, b: usize },
+    Delete { a: usize },
+    Insert { b: usize },
+}
+
+impl Edit {
+    pub fn is_equal(&self) -> bool {
+        matches!(self, Edit::Equal { .. })
+    }
+}
+
+/// Compute the length of the shortest edit script between `a` and `b`.
 pub fn myers<A, B>(a: &[A], b: &[B]) -> usize
+where
+    A: PartialEq<B>,
+{
+    edits(a, b).iter().filter(|edit| !edit.is_equal()).count()
+}
+
+/// Compute the shortest edit script between `a` and `b` with the linear
+/// space variant of the Myers algorithm, which splits the problem at the
+/// middle of an optimal path and solves each half recursively.
+pub fn edits<A, B>(a: &[A], b: &[B]) -> Vec<Edit>
+where
+    A: PartialEq<B>,
+{
+    // Diagonals searched from either end, shared by every subproblem.
+    let size = 2 * (a.len() + b.len()) + 3;
+    let mut forward = Ring(vec![0; size]);
+    let mut backward = Ring(vec![0; size]);
+    let mut edits = Vec::with_capacity(cmp::max(a.len(), b.len()));
+    compare(a, b, 0, 0, &mut forward, &mut backward, &mut edits);
+
+    // Like `git`, show each group of changes as deletions, then insertions.
+    for changes in edits.split_mut(|edit| edit.is_equal()) {
+        changes.sort_by_key(|edit| matches!(edit, Edit::Insert { .. }));
+    }
+    edits
+}
+
+/// Append the shortest edit script between `a` and `b`, which start at
+/// `a_offset` and `b_offset` in the original sequences, to `edits`.
+fn compare<A, B>(
+    mut a: &[A],
+    mut b: &[B],
+    mut a_offset: usize,
+    mut b_offset: usize,
+    forward: &mut Ring<isize>,
+    backward: &mut Ring<isize>,
+    edits: &mut Vec<Edit>,
+) where
+    A: PartialEq<B>,
+{
+    let prefix = a.iter().zip(b).take_while(|(a, b)| a == b).count();
+    edits.extend((0..prefix).map(|i| Edit::Equal {
+        a: a_offset + i,
+        b: b_offset + i,
+    }));
+    a = &a[prefix..];
+    b = &b[prefix..];
+    a_offset += prefix;
+    b_offset += prefix;
+
+    let suffix = a
+        .iter()
+        .rev()
+        .zip(b.iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    a = &a[..a.len() - suffix];
+    b = &b[..b.len() - suffix];
+
+    if a.is_empty() {
+        edits.extend((0..b.len()).map(|i| Edit::Insert { b: b_offset + i }));
+    } else if b.is_empty() {
+        edits.extend((0..a.len()).map(|i| Edit::Delete { a: a_offset + i }));
+    } else {
+        // With no common prefix or suffix, both halves are strictly smaller.
+        let (x, y) = middle(a, b, forward, backward);
+        compare(
+            &a[..x],
+            &b[..y],
+            a_offset,
+            b_offset,
+            forward,
+            backward,
+            edits,
+        );
+        compare(
+            &a[x..],
+            &b[y..],
+            a_offset + x,
+            b_offset + y,
+            forward,
+            backward,
+            edits,
+        );
+    }
+
+    let (a_end, b_end) = (a_offset + a.len(), b_offset + b.len());
+    edits.extend((0..suffix).map(|i| Edit::Equal {
+        a: a_end + i,
+        b: b_end + i,
+    }));
+}
+
+/// Find a point on a shortest path from the start to the end of `a` and
+/// `b` that splits its edits in half, by searching from both ends at once
+/// until the furthest-reaching paths overlap.
+///
+/// `forward` holds the furthest `x` reached on each diagonal `x - y` from
+/// the start, and `backward` the least `x` reached from the end, indexed
+/// relative to the diagonal of the end.
+fn middle<A, B>(
+    a: &[A],
+    b: &[B],
+    forward: &mut Ring<isize>,
+    backward: &mut Ring<isize>,
+) -> (usize, usize)
 where
     A: PartialEq<B>,
 {
     let n = a.len() as isize;
     let m = b.len() as isize;
-    let max = n + m;
+    let delta = n - m;
+    let odd = delta % 2 != 0;
 
-    let mut v = Ring(vec![0; 2 * max as usize + 1]);
+    // Each round `d` reads diagonals `-d - 1` through `d + 1`, so only the
+    // two read by the first round need to be reset.
+    forward[1] = 0;
+    backward[-1] = n;
 
-    for d in 0..max {
+    for d in 0..=(n + m + 1) / 2 {
         for k in (-d..=d).step_by(2) {
-            let mut x = if k == -d || (k != d && v[k - 1] < v[k + 1]) {
-                v[k + 1]
+            let mut x = if k == -d || (k != d && forward[k - 1] < forward[k + 1]) {
+                forward[k + 1]
             } else {
-                v[k - 1] + 1
+                forward[k - 1] + 1
             };
-
             let mut y = x - k;
+            let start = (x, y);
 
             while x < n && y < m && a[x as usize] == b[y as usize] {
                 x += 1;
                 y += 1;
             }
+            forward[k] = x;
+
+            let c = k - delta;
+            if odd && -d < c && c < d && backward[c] <= x {
+                return (start.0 as usize, start.1 as usize);
+            }
+        }
+
+        for c in (-d..=d).step_by(2) {
+            let k = c + delta;
+            let mut x = if c == d || (c != -d && backward[c - 1] < backward[c + 1]) {
+                backward[c - 1]
+            } else {
+                backward[c + 1] - 1
+            };
+            let mut y = x - k;
+            let start = (x, y);
 
-            v[k] = x;
+            while x > 0 && y > 0 && a[x as usize - 1] == b[y as usize - 1] {
+                x -= 1;
+                y -= 1;
+            }
+            backward[c] = x;
 
-            if x >= n && y >= m {
-                return d as usize;
+            if !odd && -d <= k && k <= d && forward[k] >= x {
+                return (start.0 as usize, start.1 as usize);
             }
         }
     }
@@ -36,6 +197,171 @@ where
     unreachable!()
 }
 
+/// A contiguous group of edits, padded with up to `CONTEXT` unchanged lines
+/// on either side.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Hunk {
+    /// Number of lines in `a` preceding this hunk.
+    pub a_offset: usize,
+    /// Number of lines in `b` preceding this hunk.
+    pub b_offset: usize,
+    pub edits: Vec<Edit>,
+}
+
+impl Hunk {
+    /// Render the `@@ -a,b +c,d @@` header line for this hunk.
+    pub fn header(&self) -> String {
+        let a_len = self
+            .edits
+            .iter()
+            .filter(|edit| !matches!(edit, Edit::Insert { .. }))
+            .count();
+        let b_len = self
+            .edits
+            .iter()
+            .filter(|edit| !matches!(edit, Edit::Delete { .. }))
+            .count();
+        format!(
+            "@@ -{} +{} @@",
+            Self::range(self.a_offset, a_len),
+            Self::range(self.b_offset, b_len),
+        )
+    }
+
+    fn range(offset: usize, len: usize) -> String {
+        match len {
+            0 => format!("{},0", offset),
+            1 => format!("{}", offset + 1),
+            _ => format!("{},{}", offset + 1, len),
+        }
+    }
+}
+
+/// Group an edit script into hunks, merging changes separated by at most
+/// `2 * CONTEXT` unchanged lines.
+pub fn hunks(edits: &[Edit]) -> Vec<Hunk> {
+    // Number of lines in `a` and `b` preceding each edit.
+    let mut offsets = Vec::with_capacity(edits.len());
+    let (mut a, mut b) = (0, 0);
+    for edit in edits {
+        offsets.push((a, b));
+        match edit {
+            Edit::Equal { .. } => {
+                a += 1;
+                b += 1;
+            }
+            Edit::Delete { .. } => a += 1,
+            Edit::Insert { .. } => b += 1,
+        }
+    }
+
+    let changes = edits
+        .iter()
+        .enumerate()
+        .filter(|(_, edit)| !edit.is_equal())
+        .map(|(index, _)| index)
+        .collect::<Vec<_>>();
+
+    let mut hunks = Vec::new();
+    let mut iter = changes.iter().copied().peekable();
+
+    while let Some(first) = iter.next() {
+        let mut last = first;
+        while let Some(next) = iter.peek().copied() {
+            if next - last > 2 * CONTEXT + 1 {
+                break;
+            }
+            last = next;
+            iter.next();
+        }
+
+        let lo = first.saturating_sub(CONTEXT);
+        let hi = (last + CONTEXT + 1).min(edits.len());
+        let (a_offset, b_offset) = offsets[lo];
+
+        hunks.push(Hunk {
+            a_offset,
+            b_offset,
+            edits: edits[lo..hi].to_vec(),
+        });
+    }
+
+    hunks
+}
+
+/// Split `data` into lines, keeping the trailing newline (if any) on each.
+pub fn lines(data: &[u8]) -> Vec<&[u8]> {
+    data.split_inclusive(|byte| *byte == b'\n').collect()
+}
+
+/// A temporary file to hold a version of `path` for an external program,
+/// keeping its name so that the program can recognize its type.
+pub fn temp(path: &path::Path) -> path::PathBuf {
+    let random = rand::thread_rng()
+        .sample_iter(rand::distributions::Alphanumeric)
+        .take(6)
+        .map(char::from)
+        .collect::<String>();
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    env::temp_dir().join(format!("{}_{}", random, name))
+}
+
+/// Number of leading bytes inspected by [`is_binary`], like `git`.
+const BINARY_PREFIX: usize = 8000;
+
+/// Guess whether `data` is binary rather than text, like `git` does: by
+/// looking for a NUL byte near the start.
+///
+/// Binary files are not split into lines, since their diffs would be
+/// meaningless and could corrupt the terminal.
+pub fn is_binary(data: &[u8]) -> bool {
+    data[..data.len().min(BINARY_PREFIX)].contains(&0)
+}
+
+/// Count the lines inserted and deleted to turn `a` into `b`, which is
+/// zero for both if either is binary.
+pub fn count(a: &[u8], b: &[u8]) -> (usize, usize) {
+    if is_binary(a) || is_binary(b) {
+        return (0, 0);
+    }
+    let (mut insertions, mut deletions) = (0, 0);
+    for edit in edits(&lines(a), &lines(b)) {
+        match edit {
+            Edit::Equal { .. } => (),
+            Edit::Delete { .. } => deletions += 1,
+            Edit::Insert { .. } => insertions += 1,
+        }
+    }
+    (insertions, deletions)
+}
+
+/// Describe the totals of a diff like `git diff --shortstat`, e.g.
+/// ` 2 files changed, 3 insertions(+), 1 deletion(-)`. Zero counts are left
+/// out, unless both are zero.
+pub fn shortstat(files: usize, insertions: usize, deletions: usize) -> String {
+    let plural = |count: usize, singular: &'static str, plural: &'static str| match count {
+        1 => singular,
+        _ => plural,
+    };
+
+    let mut shortstat = format!(" {} {} changed", files, plural(files, "file", "files"));
+    if insertions > 0 || deletions == 0 {
+        shortstat.push_str(&format!(
+            ", {} {}",
+            insertions,
+            plural(insertions, "insertion(+)", "insertions(+)"),
+        ));
+    }
+    if deletions > 0 || insertions == 0 {
+        shortstat.push_str(&format!(
+            ", {} {}",
+            deletions,
+            plural(deletions, "deletion(-)", "deletions(-)"),
+        ));
+    }
+    shortstat
+}
+
 #[derive(Clone, Debug)]
 struct Ring<T>(Vec<T>);
 
@@ -54,7 +380,117 @@ impl<T> ops::IndexMut<isize> for Ring<T> {
     }
 }
 
+#[test]
+fn binary() {
+    assert!(!is_binary(b""));
+    assert!(!is_binary(b"text\n"));
+    assert!(is_binary(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"));
+
+    let mut late = vec![b'a'; BINARY_PREFIX];
+    late.push(0);
+    assert!(!is_binary(&late));
+
+    assert_eq!(count(b"a\n", b"a\nb\n"), (1, 0));
+    assert_eq!(count(b"a\n", b"a\0b\n"), (0, 0));
+}
+
 #[test]
 fn smoke() {
     assert_eq!(myers(b"ABCABBA", b"CBABAC"), 5);
 }
+
+#[test]
+fn empty() {
+    assert_eq!(myers(b"", b""), 0);
+    assert!(edits(b"", b"").is_empty());
+    assert_eq!(edits(b"", b"A"), vec![Edit::Insert { b: 0 }]);
+    assert_eq!(edits(b"A", b""), vec![Edit::Delete { a: 0 }]);
+}
+
+#[test]
+fn backtrack() {
+    let a = b"ABCABBA";
+    let b = b"CBABAC";
+    let edits = edits(a, b);
+
+    assert_eq!(edits.iter().filter(|edit| !edit.is_equal()).count(), 5);
+
+    // Replaying the edit script must reproduce `b` from `a`.
+    let mut replay = Vec::new();
+    for edit in &edits {
+        match edit {
+            Edit::Equal { a: i, b: j } => {
+                assert_eq!(a[*i], b[*j]);
+                replay.push(a[*i]);
+            }
+            Edit::Insert { b: j } => replay.push(b[*j]),
+            Edit::Delete { .. } => (),
+        }
+    }
+    assert_eq!(replay, b);
+}
+
+#[test]
+fn random() {
+    // Compare against the length of the longest common subsequence.
+    let mut rng = rand::thread_rng();
+    for _ in 0..2000 {
+        let a = (0..rng.gen_range(0..24))
+            .map(|_| rng.gen_range(b'a'..b'e'))
+            .collect::<Vec<_>>();
+        let b = (0..rng.gen_range(0..24))
+            .map(|_| rng.gen_range(b'a'..b'e'))
+            .collect::<Vec<_>>();
+
+        let mut lcs = vec![vec![0; b.len() + 1]; a.len() + 1];
+        for i in 0..a.len() {
+            for j in 0..b.len() {
+                lcs[i + 1][j + 1] = match a[i] == b[j] {
+                    true => lcs[i][j] + 1,
+                    false => cmp::max(lcs[i][j + 1], lcs[i + 1][j]),
+                };
+            }
+        }
+
+        let edits = edits(&a, &b);
+        let changed = edits.iter().filter(|edit| !edit.is_equal()).count();
+        assert_eq!(changed, a.len() + b.len() - 2 * lcs[a.len()][b.len()]);
+
+        assert!(edits
+            .windows(2)
+            .all(|pair| !matches!(pair, [Edit::Insert { .. }, Edit::Delete { .. }])));
+
+        let (mut i, mut j) = (0, 0);
+        for edit in &edits {
+            match *edit {
+                Edit::Equal { a: x, b: y } => {
+                    assert_eq!((x, y), (i, j));
+                    assert_eq!(a[x], b[y]);
+                    i += 1;
+                    j += 1;
+                }
+                Edit::Delete { a: x } => {
+                    assert_eq!(x, i);
+                    i += 1;
+                }
+                Edit::Insert { b: y } => {
+                    assert_eq!(y, j);
+                    j += 1;
+                }
+            }
+        }
+        assert_eq!((i, j), (a.len(), b.len()));
+    }
+}
+
+#[test]
+fn hunk_header() {
+    let a = lines(b"1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n");
+    let b = lines(b"1\n2\n3\n4\nfive\n6\n7\n8\n9\n10\n");
+    let changed = hunks(&edits(&a, &b));
+    assert_eq!(changed.len(), 1);
+    assert_eq!(changed[0].header(), "@@ -2,7 +2,7 @@");
+
+    let created = hunks(&edits(&[] as &[&[u8]], &a[..1]));
+    assert_eq!(created[0].header(), "@@ -0,0 +1 @@");
+}
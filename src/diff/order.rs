@@ -0,0 +1,87 @@
+use std::fs;
+use std::path;
+
+use anyhow::anyhow;
+
+use crate::ignore;
+use crate::platform::OsStrExt as _;
+
+/// Order to print changed files in, read from an order file like
+/// `git diff -O`: one glob per line, where blank lines and lines starting
+/// with `#` are ignored.
+///
+/// Files matching an earlier glob, or under a directory that does, come
+/// first, and files matching none come last. Files that tie keep their
+/// original order, which is sorted by path.
+#[derive(Clone, Debug, Default)]
+pub struct Order {
+    globs: Vec<Vec<u8>>,
+}
+
+impl Order {
+    /// Read the order file given by `-O`, resolved against the current
+    /// directory, or else by `diff.orderFile`, resolved against the
+    /// workspace root. Without either, files keep their original order.
+    pub fn open(repository: &crate::Repository, file: Option<&path::Path>) -> anyhow::Result<Self> {
+        let file = match file {
+            Some(file) => file.to_path_buf(),
+            None => match repository.config()?.get("diff.orderFile") {
+                Some(file) => repository.root().join(file),
+                None => return Ok(Order::default()),
+            },
+        };
+        fs::read(&file)
+            .map(|data| Order::parse(&data))
+            .map_err(|error| anyhow!("Failed to read order file '{}': {}", file.display(), error))
+    }
+
+    pub fn parse(data: &[u8]) -> Self {
+        let globs = data
+            .split(|byte| *byte == b'\n')
+            .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+            .filter(|line| !line.is_empty() && !line.starts_with(b"#"))
+            .map(ignore::crossing)
+            .collect();
+        Order { globs }
+    }
+
+    /// Index of the first glob matching `path` or one of its parent
+    /// directories, or the number of globs if none do.
+    pub fn rank(&self, path: &path::Path) -> usize {
+        self.globs
+            .iter()
+            .position(|glob| {
+                path.ancestors()
+                    .filter(|ancestor| !ancestor.as_os_str().is_empty())
+                    .any(|ancestor| ignore::wildmatch(glob, ancestor.as_os_str().as_bytes()))
+            })
+            .unwrap_or(self.globs.len())
+    }
+
+    /// Stably sort `items` by the rank of the path `key` returns for each.
+    pub fn sort<T, F>(&self, items: &mut [T], key: F)
+    where
+        F: Fn(&T) -> &path::Path,
+    {
+        if !self.globs.is_empty() {
+            items.sort_by_cached_key(|item| self.rank(key(item)));
+        }
+    }
+}
+
+#[test]
+fn order() {
+    let order = Order::parse(b"# headers first\n*.h\n\nsrc\n*test*\n");
+    let mut paths = ["a.c", "b/test.c", "inc/a.h", "src/main.c", "src/x.h", "z.c"]
+        .iter()
+        .map(path::Path::new)
+        .collect::<Vec<_>>();
+    order.sort(&mut paths, |path| path);
+    assert_eq!(
+        paths,
+        ["inc/a.h", "src/x.h", "src/main.c", "b/test.c", "a.c", "z.c"]
+            .iter()
+            .map(path::Path::new)
+            .collect::<Vec<_>>(),
+    );
+}
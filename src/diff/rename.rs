@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+use std::str;
+
+use anyhow::anyhow;
+
+use crate::config;
+use crate::diff;
+use crate::object;
+
+/// Similarity between two files, out of [`Score::MAX`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Score(u32);
+
+impl Score {
+    pub const MAX: Score = Score(60000);
+
+    /// Minimum similarity for a rename when no threshold is given.
+    pub const DEFAULT: Score = Score(Score::MAX.0 / 2);
+
+    /// Round down to a whole percentage, as shown by e.g. `R050`.
+    pub fn percent(&self) -> u32 {
+        self.0 * 100 / Score::MAX.0
+    }
+}
+
+impl Default for Score {
+    fn default() -> Self {
+        Score::DEFAULT
+    }
+}
+
+/// Parse a threshold in `git`'s `-M<n>` syntax: either a percentage like
+/// `50%`, or digits read as a decimal fraction, so `5` and `50` both mean
+/// 50% while `05` means 5%.
+impl str::FromStr for Score {
+    type Err = anyhow::Error;
+    fn from_str(text: &str) -> anyhow::Result<Self> {
+        let invalid = || anyhow!("Invalid rename threshold: {}", text);
+
+        if let Some(percent) = text.strip_suffix('%') {
+            let percent = percent.parse::<f64>().map_err(|_| invalid())?;
+            return match percent {
+                percent if (0.0..=100.0).contains(&percent) => {
+                    Ok(Score((percent / 100.0 * Score::MAX.0 as f64) as u32))
+                }
+                _ => Err(invalid()),
+            };
+        }
+
+        let digits = text.strip_prefix('.').unwrap_or(text);
+        if digits.is_empty() || !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+            return Err(invalid());
+        }
+
+        let fraction = format!("0.{}", digits)
+            .parse::<f64>()
+            .map_err(|_| invalid())?;
+        Ok(Score((fraction * Score::MAX.0 as f64) as u32))
+    }
+}
+
+/// Limits on how hard to look for renames, since comparing every deleted
+/// file against every added file is quadratic.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Options {
+    /// Minimum similarity for a rename.
+    pub threshold: Score,
+    /// Skip inexact detection when there are more than `limit * limit`
+    /// candidate pairs. Zero means no limit.
+    pub limit: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            threshold: Score::DEFAULT,
+            limit: 1000,
+        }
+    }
+}
+
+impl Options {
+    /// Resolve whether and how `command` detects renames: `-M` or
+    /// `--no-renames` if given, and otherwise `<command>.renames` or
+    /// `diff.renames`, which default to on. Copy detection is not
+    /// supported, so `copies` only finds renames. The limit comes from
+    /// `<command>.renameLimit` or `diff.renameLimit`.
+    pub fn configure(
+        config: &config::Config,
+        command: &str,
+        find: Option<Option<Score>>,
+        no: bool,
+    ) -> anyhow::Result<Option<Self>> {
+        let key = |name: &str| match config.get(&format!("{}.{}", command, name)) {
+            Some(_) => format!("{}.{}", command, name),
+            None => format!("diff.{}", name),
+        };
+
+        let renames = key("renames");
+        let copies = config.get(&renames).is_some_and(|value| {
+            value.eq_ignore_ascii_case("copies") || value.eq_ignore_ascii_case("copy")
+        });
+        let enabled = match copies && find.is_none() && !no {
+            true => true,
+            false => config.flag(&renames, find.is_some(), no)?.unwrap_or(true),
+        };
+        if !enabled {
+            return Ok(None);
+        }
+
+        Ok(Some(Options {
+            threshold: find.flatten().unwrap_or_default(),
+            limit: config
+                .parse(&key("renameLimit"))?
+                .unwrap_or(Options::default().limit),
+        }))
+    }
+}
+
+/// A deleted file paired with the added file it most resembles.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rename<P> {
+    pub old: P,
+    pub new: P,
+    pub score: Score,
+}
+
+/// Pair each of `deleted` with at most one of `added`, preferring identical
+/// contents and then the most similar file above the threshold.
+pub fn detect<P: Clone>(
+    database: &crate::Database,
+    deleted: &[(P, object::Id)],
+    added: &[(P, object::Id)],
+    options: Options,
+) -> anyhow::Result<Vec<Rename<P>>> {
+    let empty = object::Id::hash(&object::Object::Blob(object::Blob::new(Vec::new())).to_bytes());
+    let mut renames = Vec::new();
+    let mut sources = vec![false; deleted.len()];
+    let mut targets = vec![false; added.len()];
+
+    let mut exact = HashMap::new();
+    for (index, (_, id)) in deleted.iter().enumerate().rev() {
+        exact.insert(*id, index);
+    }
+
+    for (target, (new, id)) in added.iter().enumerate() {
+        if let Some(source) = exact.remove(id) {
+            sources[source] = true;
+            targets[target] = true;
+            renames.push(Rename {
+                old: deleted[source].0.clone(),
+                new: new.clone(),
+                score: Score::MAX,
+            });
+        }
+    }
+
+    let remaining = |used: &[bool]| used.iter().filter(|used| !**used).count();
+    let pairs = remaining(&sources) * remaining(&targets);
+    if pairs == 0 || (options.limit > 0 && pairs > options.limit * options.limit) {
+        return Ok(renames);
+    }
+
+    // Every empty file is equally similar to every other, so only identical
+    // empty files count as renames.
+    let load = |(index, (_, id)): (usize, &(P, object::Id)), used: &[bool]| {
+        if used[index] || *id == empty {
+            Ok(None)
+        } else {
+            database.load_blob(id).map(|blob| Some((index, blob)))
+        }
+    };
+
+    let sources_data = deleted
+        .iter()
+        .enumerate()
+        .filter_map(|entry| load(entry, &sources).transpose())
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let targets_data = added
+        .iter()
+        .enumerate()
+        .filter_map(|entry| load(entry, &targets).transpose())
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut candidates = Vec::new();
+    for (target, new) in &targets_data {
+        for (source, old) in &sources_data {
+            let score = similarity(old.data(), new.data());
+            if score >= options.threshold {
+                candidates.push((score, *source, *target));
+            }
+        }
+    }
+
+    // Claim the best pairs first, breaking ties by path order.
+    candidates.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)).then(a.2.cmp(&b.2)));
+    for (score, source, target) in candidates {
+        if sources[source] || targets[target] {
+            continue;
+        }
+        sources[source] = true;
+        targets[target] = true;
+        renames.push(Rename {
+            old: deleted[source].0.clone(),
+            new: added[target].0.clone(),
+            score,
+        });
+    }
+
+    Ok(renames)
+}
+
+/// Estimate how much of `b` was kept from `a`: the bytes in lines common to
+/// both, relative to the larger file.
+///
+/// Binary files have no lines to compare, so they only match themselves.
+pub fn similarity(a: &[u8], b: &[u8]) -> Score {
+    let larger = a.len().max(b.len());
+    if larger == 0 || a == b {
+        return Score::MAX;
+    }
+    if diff::is_binary(a) || diff::is_binary(b) {
+        return Score(0);
+    }
+
+    let b_lines = diff::lines(b);
+    let kept = diff::edits(&diff::lines(a), &b_lines)
+        .into_iter()
+        .filter_map(|edit| match edit {
+            diff::Edit::Equal { b, .. } => Some(b_lines[b].len()),
+            _ => None,
+        })
+        .sum::<usize>();
+
+    Score((kept as u64 * Score::MAX.0 as u64 / larger as u64) as u32)
+}
+
+#[test]
+fn scores() -> anyhow::Result<()> {
+    assert_eq!("5".parse::<Score>()?.percent(), 50);
+    assert_eq!("50".parse::<Score>()?.percent(), 50);
+    assert_eq!("05".parse::<Score>()?.percent(), 5);
+    assert_eq!("90%".parse::<Score>()?.percent(), 90);
+    assert_eq!("100%".parse::<Score>()?, Score::MAX);
+    assert!("x".parse::<Score>().is_err());
+    assert!("150%".parse::<Score>().is_err());
+
+    assert_eq!(similarity(b"a\nb\n", b"a\nb\n"), Score::MAX);
+    assert_eq!(similarity(b"a\nb\n", b"c\nd\n"), Score(0));
+    assert_eq!(similarity(b"a\nb\nc\nd\n", b"a\nb\nc\nx\n").percent(), 75);
+    assert_eq!(similarity(b"a\0b\n", b"a\0c\n"), Score(0));
+    Ok(())
+}
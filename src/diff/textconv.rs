@@ -0,0 +1,175 @@
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fs;
+use std::path;
+use std::process;
+
+use anyhow::anyhow;
+
+use crate::config;
+use crate::diff;
+use crate::meta;
+use crate::object;
+use crate::references;
+use crate::util;
+
+/// Converts files to text for diffing with the `diff.<driver>.textconv`
+/// program of their `diff=<driver>` attribute, like `git`.
+///
+/// With `diff.<driver>.cachetextconv`, conversions are cached by blob id in
+/// the notes tree of `refs/notes/textconv/<driver>`, whose commit message
+/// records the program, so that changing it invalidates the cache.
+pub struct Textconv {
+    database: crate::Database,
+    references: crate::References,
+    config: config::Config,
+    /// Caches loaded so far, keyed by driver.
+    caches: HashMap<String, Cache>,
+}
+
+struct Cache {
+    /// Original blob id mapped to the id of the converted blob.
+    notes: BTreeMap<object::Id, object::Id>,
+    /// Whether `notes` gained entries since they were loaded.
+    dirty: bool,
+}
+
+impl Textconv {
+    pub fn new(repository: &crate::Repository) -> anyhow::Result<Self> {
+        Ok(Textconv {
+            database: repository.database()?,
+            references: repository.references(),
+            config: repository.config()?,
+            caches: HashMap::new(),
+        })
+    }
+
+    /// Convert `data`, the contents of blob `id` at `path`, with the program
+    /// configured for `driver`, or return `None` if there isn't one.
+    pub fn convert(
+        &mut self,
+        driver: &str,
+        path: &path::Path,
+        id: &object::Id,
+        data: &[u8],
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        let program = match self.config.get(&format!("diff.{}.textconv", driver)) {
+            None => return Ok(None),
+            Some(program) => program.to_owned(),
+        };
+        let cached = self
+            .config
+            .get_bool(&format!("diff.{}.cachetextconv", driver))?
+            .unwrap_or(false);
+
+        if cached {
+            let cache = self.cache(driver, &program)?;
+            if let Some(note) = cache.notes.get(id).copied() {
+                return Ok(Some(self.database.load_blob(&note)?.into_data()));
+            }
+        }
+
+        let temp = diff::temp(path);
+        fs::write(&temp, data)?;
+        // Pass the file as a positional parameter to the shell.
+        let output = process::Command::new("sh")
+            .arg("-c")
+            .arg(format!("{} \"$@\"", program))
+            .arg(&program)
+            .arg(&temp)
+            .stderr(process::Stdio::inherit())
+            .output();
+        let _ = fs::remove_file(&temp);
+
+        let text = match output {
+            Ok(output) if output.status.success() => output.stdout,
+            Ok(_) => return Err(anyhow!("Textconv failed on {}", path.display())),
+            Err(error) => return Err(anyhow!("Unable to run {}: {}", program, error)),
+        };
+
+        if cached {
+            let note = self
+                .database
+                .store(&crate::Object::Blob(object::Blob::new(text.clone())))?;
+            let cache = self.cache(driver, &program)?;
+            cache.notes.insert(*id, note);
+            cache.dirty = true;
+        }
+        Ok(Some(text))
+    }
+
+    /// Load the cache for `driver`, or start an empty one if it was made by
+    /// a different `program`.
+    fn cache(&mut self, driver: &str, program: &str) -> anyhow::Result<&mut Cache> {
+        if !self.caches.contains_key(driver) {
+            let mut notes = BTreeMap::new();
+            if let Some(commit) = self.references.read(&reference(driver))? {
+                let commit = self.database.load_commit(&commit)?;
+                if commit.title() == program {
+                    for (path, entry) in diff::tree::flatten(&self.database, commit.tree())? {
+                        // Notes may be split into fanout directories.
+                        let name = path.to_string_lossy().replace('/', "");
+                        if let Ok(id) = name.parse::<object::Id>() {
+                            notes.insert(id, entry.id);
+                        }
+                    }
+                }
+            }
+            let cache = Cache {
+                notes,
+                dirty: false,
+            };
+            self.caches.insert(driver.to_owned(), cache);
+        }
+        Ok(self.caches.get_mut(driver).expect("inserted above"))
+    }
+
+    /// Record new conversions in each driver's cache. Like `git`, caching
+    /// is skipped without a configured identity.
+    pub fn save(&self) -> anyhow::Result<()> {
+        let committer = match self.config.committer() {
+            Ok(committer) => committer,
+            Err(_) => return Ok(()),
+        };
+
+        for (driver, cache) in &self.caches {
+            if !cache.dirty {
+                continue;
+            }
+            let program = self
+                .config
+                .get(&format!("diff.{}.textconv", driver))
+                .unwrap_or_default();
+
+            let files = cache
+                .notes
+                .iter()
+                .map(|(id, note)| {
+                    let entry = diff::tree::Entry {
+                        id: *note,
+                        mode: meta::Mode::Regular,
+                    };
+                    (util::PathBuf(path::PathBuf::from(id.to_string())), entry)
+                })
+                .collect();
+            let tree = diff::tree::unflatten(&self.database, &files)?;
+            let commit = self
+                .database
+                .store(&crate::Object::Commit(object::Commit::new(
+                    tree,
+                    Vec::new(),
+                    committer.clone(),
+                    committer.clone(),
+                    program.to_owned(),
+                )))?;
+            self.references
+                .store()
+                .write(&reference(driver), &references::Target::Direct(commit))?;
+        }
+        Ok(())
+    }
+}
+
+fn reference(driver: &str) -> String {
+    format!("refs/notes/textconv/{}", driver)
+}
@@ -0,0 +1,167 @@
+use std::collections::BTreeMap;
+use std::path;
+use std::rc::Rc;
+
+use crate::meta;
+use crate::object;
+use crate::util;
+
+/// A file as recorded in a tree.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Entry {
+    pub id: object::Id,
+    pub mode: meta::Mode,
+}
+
+/// The `(old, new)` states of a changed file. A missing side means the file
+/// does not exist in that tree.
+pub type Change = (Option<Entry>, Option<Entry>);
+
+/// Files that differ between two trees, mapped to their states.
+pub type Changes = BTreeMap<util::PathBuf, Change>;
+
+/// Compute the files that differ between trees `a` and `b`, where `None`
+/// represents an empty tree.
+pub fn diff(
+    database: &crate::Database,
+    a: Option<&object::Id>,
+    b: Option<&object::Id>,
+) -> anyhow::Result<Changes> {
+    let mut changes = Changes::new();
+    compare(database, a, b, &mut path::PathBuf::new(), &mut changes)?;
+    Ok(changes)
+}
+
+/// Flatten tree `tree` into a map from file path to entry.
+pub fn flatten(
+    database: &crate::Database,
+    tree: &object::Id,
+) -> anyhow::Result<BTreeMap<util::PathBuf, Entry>> {
+    Ok(diff(database, None, Some(tree))?
+        .into_iter()
+        .filter_map(|(path, (_, new))| new.map(|new| (path, new)))
+        .collect())
+}
+
+/// Store the tree containing exactly `files`, the inverse of [`flatten`],
+/// returning its id.
+pub fn unflatten(
+    database: &crate::Database,
+    files: &BTreeMap<util::PathBuf, Entry>,
+) -> anyhow::Result<object::Id> {
+    // A scratch index that is never committed, so sizes don't matter.
+    let mut index = crate::Index::memory(Rc::default())?;
+    for (path, entry) in files {
+        index.reset(path.to_path_buf(), entry.id, entry.mode, 0);
+    }
+    index.write_tree(database)
+}
+
+/// Compute the files that differ between flattened trees `a` and `b`.
+pub fn diff_files(
+    a: &BTreeMap<util::PathBuf, Entry>,
+    b: &BTreeMap<util::PathBuf, Entry>,
+) -> Changes {
+    let mut changes = Changes::new();
+    for (path, old) in a {
+        match b.get(path) {
+            Some(new) if new == old => (),
+            new => {
+                changes.insert(path.clone(), (Some(*old), new.copied()));
+            }
+        }
+    }
+    for (path, new) in b {
+        if !a.contains_key(path) {
+            changes.insert(path.clone(), (None, Some(*new)));
+        }
+    }
+    changes
+}
+
+fn compare(
+    database: &crate::Database,
+    a: Option<&object::Id>,
+    b: Option<&object::Id>,
+    prefix: &mut path::PathBuf,
+    changes: &mut Changes,
+) -> anyhow::Result<()> {
+    if a == b {
+        return Ok(());
+    }
+
+    let a = load(database, a)?;
+    let mut b = load(database, b)?;
+
+    for (name, old) in a {
+        let new = b.remove(&name);
+        if new == Some(old) {
+            continue;
+        }
+
+        prefix.push(&name);
+        let (old_tree, old_file) = split(Some(old));
+        let (new_tree, new_file) = split(new);
+
+        if old_tree.is_some() || new_tree.is_some() {
+            compare(
+                database,
+                old_tree.as_ref(),
+                new_tree.as_ref(),
+                prefix,
+                changes,
+            )?;
+        }
+        if old_file.is_some() || new_file.is_some() {
+            changes.insert(util::PathBuf(prefix.clone()), (old_file, new_file));
+        }
+        prefix.pop();
+    }
+
+    for (name, new) in b {
+        prefix.push(&name);
+        match split(Some(new)) {
+            (Some(tree), _) => compare(database, None, Some(&tree), prefix, changes)?,
+            (_, Some(file)) => {
+                changes.insert(util::PathBuf(prefix.clone()), (None, Some(file)));
+            }
+            (None, None) => unreachable!(),
+        }
+        prefix.pop();
+    }
+
+    Ok(())
+}
+
+/// Separate an entry into its subtree id or file entry.
+fn split(entry: Option<Entry>) -> (Option<object::Id>, Option<Entry>) {
+    match entry {
+        None => (None, None),
+        Some(entry) if entry.mode.is_directory() => (Some(entry.id), None),
+        Some(entry) => (None, Some(entry)),
+    }
+}
+
+fn load(
+    database: &crate::Database,
+    tree: Option<&object::Id>,
+) -> anyhow::Result<BTreeMap<path::PathBuf, Entry>> {
+    let tree = match tree {
+        None => return Ok(BTreeMap::new()),
+        Some(tree) => tree,
+    };
+
+    Ok(database
+        .load_tree(tree)?
+        .into_iter()
+        .map(|node| {
+            (
+                node.path,
+                Entry {
+                    id: node.id,
+                    mode: node.mode,
+                },
+            )
+        })
+        .collect())
+}
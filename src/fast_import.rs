@@ -0,0 +1,975 @@
+//! The `git fast-import` stream format, for moving history between `grit`
+//! and other version control tools, or rewriting it along the way.
+//!
+//! Supports the subset of the format that `git fast-export` produces:
+//! blobs, commits with file modifications and deletions, annotated tags,
+//! and resets. See `git help fast-import` for the full format.
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::convert::TryFrom as _;
+use std::ffi;
+use std::fmt;
+use std::io;
+use std::path;
+use std::str;
+
+use anyhow::anyhow;
+
+use crate::diff;
+use crate::merge;
+use crate::meta;
+use crate::object;
+use crate::platform::OsStrExt as _;
+use crate::platform::OsStringExt as _;
+use crate::util;
+
+/// Object named in a stream: either by a mark (`:<n>`) assigned earlier in
+/// the same stream, or by full id.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Dataref {
+    Mark(usize),
+    Id(object::Id),
+}
+
+impl fmt::Display for Dataref {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Dataref::Mark(mark) => write!(fmt, ":{}", mark),
+            Dataref::Id(id) => write!(fmt, "{}", id),
+        }
+    }
+}
+
+impl str::FromStr for Dataref {
+    type Err = anyhow::Error;
+    fn from_str(string: &str) -> anyhow::Result<Self> {
+        match string.strip_prefix(':') {
+            Some(mark) => mark
+                .parse()
+                .map(Dataref::Mark)
+                .map_err(|_| anyhow!("Invalid mark: {}", string)),
+            None => string.parse().map(Dataref::Id),
+        }
+    }
+}
+
+/// Contents of a modified file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Content {
+    /// Blob stored earlier.
+    Ref(Dataref),
+    /// Data given directly in the stream.
+    Inline(Vec<u8>),
+}
+
+/// A single change to the files of a commit, relative to its first parent.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Change {
+    Modify {
+        mode: meta::Mode,
+        content: Content,
+        path: path::PathBuf,
+    },
+    /// Remove a file, or every file within a directory.
+    Delete(path::PathBuf),
+    /// Remove every file, so that the following changes describe the whole
+    /// tree.
+    DeleteAll,
+}
+
+#[derive(Clone, Debug)]
+pub struct Commit {
+    /// Reference to advance to this commit, e.g. `refs/heads/main`.
+    pub reference: String,
+    pub mark: Option<usize>,
+    /// Defaults to the committer.
+    pub author: Option<object::Person>,
+    pub committer: object::Person,
+    pub message: String,
+    /// First parent. Defaults to the current value of `reference`.
+    pub from: Option<Dataref>,
+    /// Remaining parents.
+    pub merges: Vec<Dataref>,
+    pub changes: Vec<Change>,
+}
+
+/// An annotated tag, always stored at `refs/tags/<name>`.
+#[derive(Clone, Debug)]
+pub struct Tag {
+    pub name: String,
+    pub mark: Option<usize>,
+    pub from: Dataref,
+    pub tagger: Option<object::Person>,
+    pub message: String,
+}
+
+#[derive(Clone, Debug)]
+pub enum Command {
+    Blob {
+        mark: Option<usize>,
+        data: Vec<u8>,
+    },
+    Commit(Commit),
+    Tag(Tag),
+    /// Point `reference` at `from`, or forget its value so that the next
+    /// commit to it starts a new history.
+    Reset {
+        reference: String,
+        from: Option<Dataref>,
+    },
+}
+
+impl Command {
+    pub fn write<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
+        match self {
+            Command::Blob { mark, data } => {
+                writer.write_all(b"blob\n")?;
+                write_mark(writer, *mark)?;
+                write_data(writer, data)?;
+            }
+            Command::Commit(commit) => {
+                writeln!(writer, "commit {}", commit.reference)?;
+                write_mark(writer, commit.mark)?;
+                if let Some(author) = &commit.author {
+                    write_person(writer, "author", author)?;
+                }
+                write_person(writer, "committer", &commit.committer)?;
+                write_message(writer, &commit.message)?;
+                if let Some(from) = commit.from {
+                    writeln!(writer, "from {}", from)?;
+                }
+                for merge in &commit.merges {
+                    writeln!(writer, "merge {}", merge)?;
+                }
+                for change in &commit.changes {
+                    match change {
+                        Change::Modify {
+                            mode,
+                            content: Content::Ref(dataref),
+                            path,
+                        } => {
+                            write!(writer, "M {} {} ", mode.as_str(), dataref)?;
+                            write_path(writer, path)?;
+                        }
+                        Change::Modify {
+                            mode,
+                            content: Content::Inline(data),
+                            path,
+                        } => {
+                            write!(writer, "M {} inline ", mode.as_str())?;
+                            write_path(writer, path)?;
+                            write_data(writer, data)?;
+                            writer.write_all(b"\n")?;
+                        }
+                        Change::Delete(path) => {
+                            writer.write_all(b"D ")?;
+                            write_path(writer, path)?;
+                        }
+                        Change::DeleteAll => writer.write_all(b"deleteall\n")?,
+                    }
+                }
+            }
+            Command::Tag(tag) => {
+                writeln!(writer, "tag {}", tag.name)?;
+                write_mark(writer, tag.mark)?;
+                writeln!(writer, "from {}", tag.from)?;
+                if let Some(tagger) = &tag.tagger {
+                    write_person(writer, "tagger", tagger)?;
+                }
+                write_message(writer, &tag.message)?;
+            }
+            Command::Reset { reference, from } => {
+                writeln!(writer, "reset {}", reference)?;
+                if let Some(from) = from {
+                    writeln!(writer, "from {}", from)?;
+                }
+            }
+        }
+        writer.write_all(b"\n")
+    }
+}
+
+fn write_mark<W: io::Write>(writer: &mut W, mark: Option<usize>) -> io::Result<()> {
+    match mark {
+        Some(mark) => writeln!(writer, "mark :{}", mark),
+        None => Ok(()),
+    }
+}
+
+fn write_person<W: io::Write>(
+    writer: &mut W,
+    keyword: &str,
+    person: &object::Person,
+) -> io::Result<()> {
+    write!(writer, "{} ", keyword)?;
+    person.write(writer)?;
+    writer.write_all(b"\n")
+}
+
+fn write_data<W: io::Write>(writer: &mut W, data: &[u8]) -> io::Result<()> {
+    writeln!(writer, "data {}", data.len())?;
+    writer.write_all(data)
+}
+
+/// Write `message` as data, followed by a newline if it lacks one so the
+/// next command starts on its own line.
+fn write_message<W: io::Write>(writer: &mut W, message: &str) -> io::Result<()> {
+    write_data(writer, message.as_bytes())?;
+    match message.is_empty() || message.ends_with('\n') {
+        true => Ok(()),
+        false => writer.write_all(b"\n"),
+    }
+}
+
+/// Write `path` followed by a newline, C-style quoted if it contains
+/// special characters, like `git`.
+fn write_path<W: io::Write>(writer: &mut W, path: &path::Path) -> io::Result<()> {
+    let bytes = path.as_os_str().as_bytes();
+    let special = |byte: u8| !(0x20..0x7f).contains(&byte) || byte == b'"' || byte == b'\\';
+
+    if !bytes.iter().copied().any(special) {
+        writer.write_all(bytes)?;
+        return writer.write_all(b"\n");
+    }
+
+    writer.write_all(b"\"")?;
+    for byte in bytes.iter().copied() {
+        match byte {
+            b'"' => writer.write_all(b"\\\"")?,
+            b'\\' => writer.write_all(b"\\\\")?,
+            b'\n' => writer.write_all(b"\\n")?,
+            b'\t' => writer.write_all(b"\\t")?,
+            byte if special(byte) => write!(writer, "\\{:03o}", byte)?,
+            byte => writer.write_all(&[byte])?,
+        }
+    }
+    writer.write_all(b"\"\n")
+}
+
+/// Parse a path written by [`write_path`], without its trailing newline.
+fn read_path(bytes: &[u8]) -> anyhow::Result<path::PathBuf> {
+    let quoted = match bytes.strip_prefix(b"\"") {
+        Some(quoted) => quoted,
+        None => return Ok(ffi::OsString::from_vec(bytes.to_vec()).into()),
+    };
+
+    let invalid = || anyhow!("Invalid quoted path: {}", String::from_utf8_lossy(bytes));
+    let mut path = Vec::new();
+    let mut iter = quoted.iter().copied();
+    loop {
+        match iter.next().ok_or_else(invalid)? {
+            b'"' if iter.next().is_none() => break,
+            b'"' => return Err(invalid()),
+            b'\\' => path.push(match iter.next().ok_or_else(invalid)? {
+                b'n' => b'\n',
+                b't' => b'\t',
+                b'a' => 0x07,
+                b'b' => 0x08,
+                b'f' => 0x0c,
+                b'r' => b'\r',
+                b'v' => 0x0b,
+                high @ b'0'..=b'3' => {
+                    let mut byte = high - b'0';
+                    for _ in 0..2 {
+                        match iter.next().ok_or_else(invalid)? {
+                            digit @ b'0'..=b'7' => byte = byte * 8 + (digit - b'0'),
+                            _ => return Err(invalid()),
+                        }
+                    }
+                    byte
+                }
+                byte => byte,
+            }),
+            byte => path.push(byte),
+        }
+    }
+    Ok(ffi::OsString::from_vec(path).into())
+}
+
+/// Streaming parser for [`Command`]s.
+pub struct Reader<R> {
+    reader: R,
+    /// Line read while looking for the end of the previous command.
+    peeked: Option<Vec<u8>>,
+    done: bool,
+}
+
+impl<R: io::BufRead> Reader<R> {
+    pub fn new(reader: R) -> Self {
+        Reader {
+            reader,
+            peeked: None,
+            done: false,
+        }
+    }
+
+    /// Read the next line without its newline, or `None` at the end of the
+    /// stream.
+    fn line(&mut self) -> anyhow::Result<Option<Vec<u8>>> {
+        if let Some(line) = self.peeked.take() {
+            return Ok(Some(line));
+        }
+        let mut line = Vec::new();
+        if self.reader.read_until(b'\n', &mut line)? == 0 {
+            return Ok(None);
+        }
+        if line.last() == Some(&b'\n') {
+            line.pop();
+        }
+        Ok(Some(line))
+    }
+
+    /// Read the next line, which must exist.
+    fn expect(&mut self) -> anyhow::Result<Vec<u8>> {
+        self.line()?
+            .ok_or_else(|| anyhow!("Unexpected end of fast-import stream"))
+    }
+
+    /// Consume the next line if it starts with `keyword` and a space,
+    /// returning the rest of it.
+    fn optional(&mut self, keyword: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let line = match self.line()? {
+            Some(line) => line,
+            None => return Ok(None),
+        };
+        match line
+            .strip_prefix(keyword.as_bytes())
+            .and_then(|rest| rest.strip_prefix(b" "))
+        {
+            Some(rest) => Ok(Some(rest.to_vec())),
+            None => {
+                self.peeked = Some(line);
+                Ok(None)
+            }
+        }
+    }
+
+    fn mark(&mut self) -> anyhow::Result<Option<usize>> {
+        let mark = match self.optional("mark")? {
+            Some(mark) => match utf8(&mark)?.parse()? {
+                Dataref::Mark(mark) => Some(mark),
+                Dataref::Id(_) => return Err(anyhow!("Invalid mark: {}", utf8(&mark)?)),
+            },
+            None => None,
+        };
+        // Only meaningful to tools that map between object ids.
+        self.optional("original-oid")?;
+        Ok(mark)
+    }
+
+    fn person(&mut self, keyword: &str) -> anyhow::Result<Option<object::Person>> {
+        self.optional(keyword)?
+            .map(|person| object::Person::read(&mut &*person))
+            .transpose()
+    }
+
+    /// Read a `data` command and its contents, in either the exact byte
+    /// count or the delimited format.
+    fn data(&mut self) -> anyhow::Result<Vec<u8>> {
+        let line = self.expect()?;
+        let header = line
+            .strip_prefix(b"data ")
+            .ok_or_else(|| anyhow!("Expected data, found: {}", String::from_utf8_lossy(&line)))?;
+
+        if let Some(delimiter) = header.strip_prefix(b"<<") {
+            let mut data = Vec::new();
+            loop {
+                let line = self.expect()?;
+                if line == delimiter {
+                    return Ok(data);
+                }
+                data.extend_from_slice(&line);
+                data.push(b'\n');
+            }
+        }
+
+        let len = utf8(header)?.parse::<usize>()?;
+        let mut data = vec![0; len];
+        io::Read::read_exact(&mut self.reader, &mut data)?;
+
+        // The newline after data is optional.
+        if self.reader.fill_buf()?.first() == Some(&b'\n') {
+            self.reader.consume(1);
+        }
+        Ok(data)
+    }
+
+    fn commit(&mut self, reference: &[u8]) -> anyhow::Result<Command> {
+        let reference = utf8(reference)?.to_owned();
+        let mark = self.mark()?;
+        let author = self.person("author")?;
+        let committer = self
+            .person("committer")?
+            .ok_or_else(|| anyhow!("Missing committer in commit to {}", reference))?;
+        // Messages are always stored as UTF-8.
+        self.optional("encoding")?;
+        let message = String::from_utf8(self.data()?)?;
+
+        let from = self
+            .optional("from")?
+            .map(|from| utf8(&from)?.parse())
+            .transpose()?;
+        let mut merges = Vec::new();
+        while let Some(merge) = self.optional("merge")? {
+            merges.push(utf8(&merge)?.parse()?);
+        }
+
+        let mut changes = Vec::new();
+        while let Some(line) = self.line()? {
+            let change = if let Some(rest) = line.strip_prefix(b"M ") {
+                let mut fields = rest.splitn(3, |byte| *byte == b' ');
+                let (mode, dataref, path) = match (fields.next(), fields.next(), fields.next()) {
+                    (Some(mode), Some(dataref), Some(path)) => (mode, dataref, path),
+                    _ => return Err(anyhow!("Invalid file modification: {}", utf8(rest)?)),
+                };
+                let mode = match utf8(mode)? {
+                    "644" => meta::Mode::Regular,
+                    "755" => meta::Mode::Executable,
+                    mode => meta::Mode::try_from(mode)?,
+                };
+                if mode.is_directory() {
+                    return Err(anyhow!("Unsupported file mode: {}", mode.as_str()));
+                }
+                let path = read_path(path)?;
+                let content = match dataref {
+                    b"inline" => Content::Inline(self.data()?),
+                    dataref => Content::Ref(utf8(dataref)?.parse()?),
+                };
+                Change::Modify {
+                    mode,
+                    content,
+                    path,
+                }
+            } else if let Some(path) = line.strip_prefix(b"D ") {
+                Change::Delete(read_path(path)?)
+            } else if line == b"deleteall" {
+                Change::DeleteAll
+            } else {
+                // Blank lines end commits, but are otherwise insignificant.
+                if !line.is_empty() {
+                    self.peeked = Some(line);
+                }
+                break;
+            };
+            changes.push(change);
+        }
+
+        Ok(Command::Commit(Commit {
+            reference,
+            mark,
+            author,
+            committer,
+            message,
+            from,
+            merges,
+            changes,
+        }))
+    }
+
+    fn tag(&mut self, name: &[u8]) -> anyhow::Result<Command> {
+        let name = utf8(name)?.to_owned();
+        let mark = self.mark()?;
+        let from = self
+            .optional("from")?
+            .ok_or_else(|| anyhow!("Missing from in tag {}", name))?;
+        let from = utf8(&from)?.parse()?;
+        self.optional("original-oid")?;
+        let tagger = self.person("tagger")?;
+        let message = String::from_utf8(self.data()?)?;
+        Ok(Command::Tag(Tag {
+            name,
+            mark,
+            from,
+            tagger,
+            message,
+        }))
+    }
+
+    fn read(&mut self) -> anyhow::Result<Option<Command>> {
+        while !self.done {
+            let line = match self.line()? {
+                Some(line) => line,
+                None => return Ok(None),
+            };
+
+            let (keyword, rest) = match line.iter().position(|byte| *byte == b' ') {
+                Some(space) => (&line[..space], &line[space + 1..]),
+                None => (&line[..], &[][..]),
+            };
+
+            match keyword {
+                b"blob" => {
+                    let mark = self.mark()?;
+                    let data = self.data()?;
+                    return Ok(Some(Command::Blob { mark, data }));
+                }
+                b"commit" => return self.commit(rest).map(Some),
+                b"tag" => return self.tag(rest).map(Some),
+                b"reset" => {
+                    let reference = utf8(rest)?.to_owned();
+                    let from = self
+                        .optional("from")?
+                        .map(|from| utf8(&from)?.parse())
+                        .transpose()?;
+                    return Ok(Some(Command::Reset { reference, from }));
+                }
+                b"done" => self.done = true,
+                // Nothing to do for commands that only affect how `git`
+                // runs the import, or for comments.
+                b"" | b"checkpoint" | b"progress" | b"feature" | b"option" => (),
+                _ if keyword.starts_with(b"#") => (),
+                _ => {
+                    return Err(anyhow!(
+                        "Unsupported fast-import command: {}",
+                        String::from_utf8_lossy(&line)
+                    ))
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<R: io::BufRead> Iterator for Reader<R> {
+    type Item = anyhow::Result<Command>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read().transpose()
+    }
+}
+
+fn utf8(bytes: &[u8]) -> anyhow::Result<&str> {
+    str::from_utf8(bytes).map_err(anyhow::Error::from)
+}
+
+/// Export the history reachable from `references`, given as full names and
+/// ids, passing each command to `emit` in an order `git fast-import` can
+/// replay.
+///
+/// Commits appear after their parents, each describing its changes relative
+/// to its first parent, and blobs appear before the first commit to use
+/// them. Every reference is finally set with a `reset`, or a `tag` for
+/// annotated tags.
+pub fn export<F>(
+    database: &crate::Database,
+    references: &[(String, object::Id)],
+    mut emit: F,
+) -> anyhow::Result<()>
+where
+    F: FnMut(Command) -> anyhow::Result<()>,
+{
+    // Blobs and commits share a namespace, as do their ids.
+    let mut marks = HashMap::<object::Id, usize>::new();
+
+    for (reference, id) in references {
+        let tip = id.peel_to_commit(database)?;
+
+        for id in unmarked(database, &tip, &marks)? {
+            let commit = database.load_commit(&id)?;
+            let parent = commit
+                .parent()
+                .map(|parent| database.load_commit(parent))
+                .transpose()?;
+
+            let mut changes = Vec::new();
+            for (path, (_, new)) in diff::tree::diff(
+                database,
+                parent.as_ref().map(object::Commit::tree),
+                Some(commit.tree()),
+            )? {
+                let new = match new {
+                    Some(new) => new,
+                    None => {
+                        changes.push(Change::Delete(path.0));
+                        continue;
+                    }
+                };
+                let mark = match marks.get(&new.id) {
+                    Some(mark) => *mark,
+                    None => {
+                        let mark = marks.len() + 1;
+                        emit(Command::Blob {
+                            mark: Some(mark),
+                            data: database.load_blob(&new.id)?.into_data(),
+                        })?;
+                        marks.insert(new.id, mark);
+                        mark
+                    }
+                };
+                changes.push(Change::Modify {
+                    mode: new.mode,
+                    content: Content::Ref(Dataref::Mark(mark)),
+                    path: path.0,
+                });
+            }
+
+            // Keep root commits from inheriting whatever the reference
+            // pointed to earlier in the stream.
+            if parent.is_none() {
+                emit(Command::Reset {
+                    reference: reference.clone(),
+                    from: None,
+                })?;
+            }
+
+            let mut parents = commit
+                .parents()
+                .iter()
+                .map(|parent| Dataref::Mark(marks[parent]));
+            let mark = marks.len() + 1;
+            emit(Command::Commit(Commit {
+                reference: reference.clone(),
+                mark: Some(mark),
+                author: Some(commit.author().clone()),
+                committer: commit.committer().clone(),
+                message: commit.message().to_owned(),
+                from: parents.next(),
+                merges: parents.collect(),
+                changes,
+            }))?;
+            marks.insert(id, mark);
+        }
+
+        let from = Dataref::Mark(marks[&tip]);
+        match database.kind(id)? {
+            object::Type::Tag => {
+                let tag = database.load_tag(id)?;
+                if *tag.object() != tip {
+                    return Err(anyhow!(
+                        "Cannot export tag {} of another tag or non-commit",
+                        reference,
+                    ));
+                }
+                emit(Command::Tag(Tag {
+                    name: reference
+                        .strip_prefix(crate::References::TAGS)
+                        .unwrap_or_else(|| tag.name())
+                        .to_owned(),
+                    mark: None,
+                    from,
+                    tagger: tag.tagger().cloned(),
+                    message: tag.message().to_owned(),
+                }))?;
+            }
+            _ => emit(Command::Reset {
+                reference: reference.clone(),
+                from: Some(from),
+            })?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Find commits reachable from `tip` that haven't been exported yet,
+/// parents first.
+fn unmarked(
+    database: &crate::Database,
+    tip: &object::Id,
+    marks: &HashMap<object::Id, usize>,
+) -> anyhow::Result<Vec<object::Id>> {
+    let mut order = Vec::new();
+    let mut seen = HashSet::new();
+    let mut stack = vec![(*tip, false)];
+
+    // Iterative post-order traversal, since histories can be deep.
+    while let Some((id, visited)) = stack.pop() {
+        if visited {
+            order.push(id);
+        } else if !marks.contains_key(&id) && seen.insert(id) {
+            stack.push((id, true));
+            for parent in database.load_commit(&id)?.parents().iter().rev() {
+                stack.push((*parent, false));
+            }
+        }
+    }
+
+    Ok(order)
+}
+
+/// Applies [`Command`]s to a database, deferring reference updates until
+/// [`Import::finish`].
+pub struct Import<'a> {
+    database: &'a crate::Database,
+    references: &'a crate::References,
+    marks: HashMap<usize, object::Id>,
+    /// New values of references, or `None` if reset without a value.
+    updates: BTreeMap<String, Option<object::Id>>,
+}
+
+impl<'a> Import<'a> {
+    pub fn new(database: &'a crate::Database, references: &'a crate::References) -> Self {
+        Import {
+            database,
+            references,
+            marks: HashMap::new(),
+            updates: BTreeMap::new(),
+        }
+    }
+
+    /// Objects created so far, by mark.
+    pub fn marks(&self) -> &HashMap<usize, object::Id> {
+        &self.marks
+    }
+
+    fn resolve(&self, dataref: &Dataref) -> anyhow::Result<object::Id> {
+        match dataref {
+            Dataref::Mark(mark) => self
+                .marks
+                .get(mark)
+                .copied()
+                .ok_or_else(|| anyhow!("Mark :{} not declared", mark)),
+            Dataref::Id(id) => Ok(*id),
+        }
+    }
+
+    fn mark(&mut self, mark: Option<usize>, id: object::Id) {
+        if let Some(mark) = mark {
+            self.marks.insert(mark, id);
+        }
+    }
+
+    /// Store the objects described by `command`, returning the id of the
+    /// blob, commit, or tag created, if any.
+    pub fn apply(&mut self, command: Command) -> anyhow::Result<Option<object::Id>> {
+        let id = match command {
+            Command::Blob { mark, data } => {
+                let id = self
+                    .database
+                    .store(&crate::Object::Blob(object::Blob::new(data)))?;
+                self.mark(mark, id);
+                id
+            }
+            Command::Commit(commit) => {
+                let id = self.commit(&commit)?;
+                self.mark(commit.mark, id);
+                self.updates.insert(commit.reference, Some(id));
+                id
+            }
+            Command::Tag(tag) => {
+                let target = self.resolve(&tag.from)?;
+                let tag_id = self.database.store(&crate::Object::Tag(object::Tag::new(
+                    target,
+                    self.database.kind(&target)?,
+                    tag.name.clone(),
+                    tag.tagger,
+                    tag.message,
+                )))?;
+                self.mark(tag.mark, tag_id);
+                self.updates.insert(
+                    format!("{}{}", crate::References::TAGS, tag.name),
+                    Some(tag_id),
+                );
+                tag_id
+            }
+            Command::Reset { reference, from } => {
+                let from = from.map(|from| self.resolve(&from)).transpose()?;
+                self.updates.insert(reference, from);
+                return Ok(None);
+            }
+        };
+        Ok(Some(id))
+    }
+
+    fn commit(&self, commit: &Commit) -> anyhow::Result<object::Id> {
+        let parent = match &commit.from {
+            Some(from) => Some(self.resolve(from)?.peel_to_commit(self.database)?),
+            None => match self.updates.get(&commit.reference) {
+                Some(id) => *id,
+                None => self.references.read(&commit.reference)?,
+            },
+        };
+
+        let mut files = self.files(parent)?;
+        self.edit(&mut files, &commit.changes, true)?;
+
+        let mut parents = parent.into_iter().collect::<Vec<_>>();
+        for merge in &commit.merges {
+            parents.push(self.resolve(merge)?.peel_to_commit(self.database)?);
+        }
+
+        let commit = object::Commit::new(
+            diff::tree::unflatten(self.database, &files)?,
+            parents,
+            commit
+                .author
+                .clone()
+                .unwrap_or_else(|| commit.committer.clone()),
+            commit.committer.clone(),
+            commit.message.clone(),
+        );
+        self.database
+            .store(&crate::Object::Commit(commit))
+            .map_err(anyhow::Error::from)
+    }
+
+    /// Whether `changes` leave the files of commit `from`, or no files
+    /// without one, as they are. Nothing is stored.
+    pub fn is_unchanged(&self, from: Option<&Dataref>, changes: &[Change]) -> anyhow::Result<bool> {
+        let parent = from
+            .map(|from| self.resolve(from)?.peel_to_commit(self.database))
+            .transpose()?;
+        let old = self.files(parent)?;
+        let mut new = old.clone();
+        self.edit(&mut new, changes, false)?;
+        Ok(new == old)
+    }
+
+    fn files(
+        &self,
+        commit: Option<object::Id>,
+    ) -> anyhow::Result<BTreeMap<util::PathBuf, diff::tree::Entry>> {
+        match commit {
+            Some(commit) => {
+                diff::tree::flatten(self.database, self.database.load_commit(&commit)?.tree())
+            }
+            None => Ok(BTreeMap::new()),
+        }
+    }
+
+    /// Apply `changes` to `files`, storing inline contents if `store` is
+    /// set, or only hashing them otherwise.
+    fn edit(
+        &self,
+        files: &mut BTreeMap<util::PathBuf, diff::tree::Entry>,
+        changes: &[Change],
+        store: bool,
+    ) -> anyhow::Result<()> {
+        for change in changes {
+            match change {
+                Change::Modify {
+                    mode,
+                    content,
+                    path,
+                } => {
+                    let id = match content {
+                        Content::Ref(dataref) => self.resolve(dataref)?,
+                        Content::Inline(data) if store => self
+                            .database
+                            .store(&crate::Object::Blob(object::Blob::new(data.clone())))?,
+                        Content::Inline(data) => {
+                            crate::Database::hash_stream(data.len() as u64, data.as_slice())?
+                        }
+                    };
+                    // A file replaces any directory at the same path.
+                    files.retain(|file, _| !file.starts_with(path));
+                    files.insert(
+                        util::PathBuf(path.clone()),
+                        diff::tree::Entry { id, mode: *mode },
+                    );
+                }
+                Change::Delete(path) => files.retain(|file, _| !file.starts_with(path)),
+                Change::DeleteAll => files.clear(),
+            }
+        }
+        Ok(())
+    }
+
+    /// Write every updated reference, returning their full names and new
+    /// ids. Refuses to move a reference to a commit that doesn't contain
+    /// its old one unless `force` is set.
+    pub fn finish(self, force: bool) -> anyhow::Result<Vec<(String, object::Id)>> {
+        let updates = self
+            .updates
+            .into_iter()
+            .filter_map(|(name, id)| id.map(|id| (name, id)))
+            .collect::<Vec<_>>();
+
+        // Check everything first, so that a refusal leaves no reference moved.
+        for (name, id) in updates.iter().filter(|_| !force) {
+            let old = match self.references.read(name)? {
+                Some(old) => old,
+                None => continue,
+            };
+            let commits = (
+                old.peel_to_commit(self.database),
+                id.peel_to_commit(self.database),
+            );
+            if let (Ok(old), Ok(new)) = commits {
+                if merge::base(self.database, &old, &new)? != Some(old) {
+                    return Err(anyhow!(
+                        "Not updating {} (new tip {} does not contain {})",
+                        name,
+                        new,
+                        old,
+                    ));
+                }
+            }
+        }
+
+        for (name, id) in &updates {
+            self.references
+                .store()
+                .write(name, &crate::references::Target::Direct(*id))?;
+        }
+        Ok(updates)
+    }
+}
+
+#[test]
+fn round_trip() -> anyhow::Result<()> {
+    let source = crate::Repository::memory(path::PathBuf::new());
+    let database = source.database()?;
+    let references = source.references();
+
+    let person = object::Person::new(
+        String::from("A U Thor"),
+        String::from("author@example.com"),
+        object::Person::parse_time("@1600000000 +0000")?,
+    );
+    let commit = |parents: Vec<object::Id>, files: &[(&str, &[u8])], message: &str| {
+        let mut tree = BTreeMap::new();
+        for (path, data) in files {
+            let id = database.store(&crate::Object::Blob(object::Blob::new(data.to_vec())))?;
+            tree.insert(
+                util::PathBuf(path::PathBuf::from(path)),
+                diff::tree::Entry {
+                    id,
+                    mode: meta::Mode::Regular,
+                },
+            );
+        }
+        let commit = object::Commit::new(
+            diff::tree::unflatten(&database, &tree)?,
+            parents,
+            person.clone(),
+            person.clone(),
+            message.to_owned(),
+        );
+        database
+            .store(&crate::Object::Commit(commit))
+            .map_err(anyhow::Error::from)
+    };
+
+    let root = commit(vec![], &[("a", b"1"), ("dir/b", b"2")], "root\n")?;
+    let side = commit(vec![root], &[("a", b"1"), ("quo\"te\n", b"3")], "side")?;
+    let merge = commit(vec![root, side], &[("dir/b", b"4")], "merge\n")?;
+    let tag = database.store(&crate::Object::Tag(object::Tag::new(
+        side,
+        object::Type::Commit,
+        String::from("v1"),
+        Some(person.clone()),
+        String::from("tag\n"),
+    )))?;
+    references.create_branch("main", &merge)?;
+    references.create_tag("v1", &tag, false)?;
+
+    let tips = references
+        .iter_prefix("refs/")?
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let mut stream = Vec::new();
+    export(&database, &tips, |command| {
+        command.write(&mut stream).map_err(anyhow::Error::from)
+    })?;
+
+    let target = crate::Repository::memory(path::PathBuf::new());
+    let (database, references) = (target.database()?, target.references());
+    let mut import = Import::new(&database, &references);
+    for command in Reader::new(&*stream) {
+        import.apply(command?)?;
+    }
+    assert_eq!(import.finish(false)?, tips);
+    assert!(database.contains(&side)?);
+    Ok(())
+}
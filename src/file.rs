@@ -1,14 +1,28 @@
+//! Checksummed streams and atomically committed files, shared by the index,
+//! object, and reference stores. Also exported as `grit::fs`.
+//!
+//! Every writer here goes to a side file that is renamed over its target
+//! on `commit`, and removed if dropped without committing, so readers never
+//! observe a partial write. Use [`Options`] to make writes durable, set
+//! permissions, or wait for a lock held by another process.
+
 use std::fs;
 use std::io;
 use std::mem;
 use std::path;
+use std::thread;
+use std::time;
 
 use rand::distributions;
 use rand::Rng as _;
 use sha1::Sha1;
 
+use crate::interrupt;
+use crate::platform::OpenOptionsExt as _;
 use crate::util::Tap as _;
 
+/// Stream adapter that hashes everything read or written through it, for
+/// formats like the index and packfiles that end in a SHA-1 trailer.
 pub struct Checksum<T> {
     inner: T,
     hash: Sha1,
@@ -22,6 +36,7 @@ impl<T> Checksum<T> {
         }
     }
 
+    /// Forget everything hashed so far.
     pub fn clear_checksum(&mut self) {
         self.hash.reset()
     }
@@ -46,6 +61,8 @@ impl<T: io::Read> io::Read for Checksum<T> {
 }
 
 impl<T: io::Read> Checksum<T> {
+    /// Read the trailing checksum, failing if it does not match the data
+    /// read so far or if anything follows it.
     pub fn read_checksum(mut self) -> io::Result<T> {
         let mut buffer = [0u8; 20];
 
@@ -84,6 +101,7 @@ impl<T: io::Write> io::Write for Checksum<T> {
 }
 
 impl<T: io::Write> Checksum<T> {
+    /// Append the checksum of the data written so far.
     pub fn write_checksum(mut self) -> io::Result<T> {
         let digest = self.hash.digest().bytes();
         self.inner.write_all(&digest)?;
@@ -91,11 +109,68 @@ impl<T: io::Write> Checksum<T> {
     }
 }
 
-#[derive(Debug)]
-pub struct Temp(Atomic);
+/// Settings for creating a [`Temp`] file or [`WriteLock`].
+///
+/// ```no_run
+/// # fn main() -> std::io::Result<()> {
+/// use std::io::Write as _;
+///
+/// let mut lock = grit::fs::Options::new()
+///     .fsync(true)
+///     .mode(0o600)
+///     .timeout(std::time::Duration::from_secs(1))
+///     .lock("hooks.state".into())?;
+/// lock.write_all(b"ok\n")?;
+/// lock.commit()
+/// # }
+/// ```
+#[derive(Copy, Clone, Debug)]
+pub struct Options {
+    fsync: bool,
+    mode: u32,
+    timeout: time::Duration,
+}
 
-impl Temp {
-    pub fn new(target: path::PathBuf) -> io::Result<Self> {
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            fsync: false,
+            mode: 0o666,
+            timeout: time::Duration::from_secs(0),
+        }
+    }
+}
+
+impl Options {
+    /// Longest pause between attempts to acquire a lock.
+    const MAX_BACKOFF: time::Duration = time::Duration::from_millis(100);
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Flush file contents to disk before renaming over the target, so
+    /// that a crash cannot leave an empty or partial target behind.
+    pub fn fsync(mut self, fsync: bool) -> Self {
+        self.fsync = fsync;
+        self
+    }
+
+    /// Permissions for new files, before the umask is applied.
+    pub fn mode(mut self, mode: u32) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// How long [`Options::lock`] retries while another process holds the
+    /// lock. Defaults to failing immediately.
+    pub fn timeout(mut self, timeout: time::Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Create a temporary file for `target`, creating parent directories.
+    pub fn temp(&self, target: path::PathBuf) -> io::Result<Temp> {
         if let Some(parent) = target.parent() {
             fs::create_dir_all(parent)?;
         }
@@ -112,12 +187,66 @@ impl Temp {
             .collect::<String>()
             .tap(|name| target.with_file_name(name));
 
-        Atomic::new(source, target).map(Self)
+        Atomic::new(source, target, self).map(Temp)
+    }
+
+    /// Acquire the lock on `target` by creating `<target>.lock`, creating
+    /// parent directories. Fails with `AlreadyExists` if another process
+    /// still holds the lock after the timeout.
+    pub fn lock(&self, target: path::PathBuf) -> io::Result<WriteLock> {
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let source = target
+            .clone()
+            .into_os_string()
+            .tap_mut(|path| path.push(".lock"))
+            .tap(path::PathBuf::from);
+
+        let start = time::Instant::now();
+        let mut backoff = time::Duration::from_millis(1);
+        loop {
+            match Atomic::new(source.clone(), target.clone(), self) {
+                Err(error)
+                    if error.kind() == io::ErrorKind::AlreadyExists
+                        && start.elapsed() < self.timeout =>
+                {
+                    thread::sleep(backoff.min(self.timeout.saturating_sub(start.elapsed())));
+                    backoff = (backoff * 2).min(Self::MAX_BACKOFF);
+                }
+                result => return result.map(WriteLock),
+            }
+        }
+    }
+}
+
+/// File written under a random `tmp_obj_*` name next to its target, for
+/// content-addressed files that no two writers can disagree on.
+#[derive(Debug)]
+pub struct Temp(Atomic);
+
+impl Temp {
+    /// Create a temporary file for `target` with default [`Options`].
+    pub fn new(target: path::PathBuf) -> io::Result<Self> {
+        Options::default().temp(target)
     }
 
+    /// Rename the temporary file over `target`.
     pub fn commit(self) -> io::Result<()> {
         self.0.commit()
     }
+
+    /// Rename the temporary file over `target` instead of the one it was
+    /// created for, e.g. an object whose id is only known once written,
+    /// creating parent directories.
+    pub fn commit_to(mut self, target: path::PathBuf) -> io::Result<()> {
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        self.0.target = target;
+        self.0.commit()
+    }
 }
 
 impl io::Write for Temp {
@@ -130,30 +259,26 @@ impl io::Write for Temp {
     }
 }
 
+/// A [`WriteLock`], along with a reader for the existing target if any.
 #[derive(Debug)]
 pub enum Lock {
     Write(WriteLock),
     ReadWrite(ReadWriteLock),
 }
 
+/// Exclusive lock on a file, held by creating `<target>.lock`, which
+/// receives the new contents.
 #[derive(Debug)]
 pub struct WriteLock(Atomic);
 
 impl WriteLock {
+    /// Acquire the lock on `target` with default [`Options`], failing with
+    /// `AlreadyExists` if another process holds it.
     pub fn new(target: path::PathBuf) -> io::Result<Self> {
-        if let Some(parent) = target.parent() {
-            fs::create_dir_all(parent)?;
-        }
-
-        let source = target
-            .clone()
-            .into_os_string()
-            .tap_mut(|path| path.push(".lock"))
-            .tap(path::PathBuf::from);
-
-        Atomic::new(source, target).map(Self)
+        Options::default().lock(target)
     }
 
+    /// Open the current contents of the target for reading, if it exists.
     pub fn upgrade(self) -> io::Result<Lock> {
         let reader = match fs::OpenOptions::new()
             .read(true)
@@ -175,6 +300,7 @@ impl WriteLock {
         }
     }
 
+    /// Replace the target with the lock file, releasing the lock.
     pub fn commit(self) -> io::Result<()> {
         self.0.commit()
     }
@@ -190,6 +316,7 @@ impl io::Write for WriteLock {
     }
 }
 
+/// [`WriteLock`] that can also read the previous contents of its target.
 #[derive(Debug)]
 pub struct ReadWriteLock {
     reader: Option<io::BufReader<fs::File>>,
@@ -197,10 +324,12 @@ pub struct ReadWriteLock {
 }
 
 impl ReadWriteLock {
+    /// Stop reading the previous contents, keeping the lock.
     pub fn downgrade(self) -> WriteLock {
         WriteLock(self.writer)
     }
 
+    /// Replace the target with the lock file, releasing the lock.
     pub fn commit(mut self) -> io::Result<()> {
         mem::take(&mut self.reader);
         self.writer.commit()
@@ -242,20 +371,25 @@ impl io::Write for ReadWriteLock {
     }
 }
 
+/// File at `source` that is renamed to `target` on commit, and removed if
+/// dropped before then.
 #[derive(Debug)]
 pub struct Atomic {
     source: path::PathBuf,
     target: path::PathBuf,
     file: Option<fs::File>,
+    fsync: bool,
 }
 
 impl Atomic {
-    fn new(source: path::PathBuf, target: path::PathBuf) -> io::Result<Self> {
-        let file = match fs::OpenOptions::new()
-            .write(true)
-            .create_new(true)
-            .open(&source)
-        {
+    fn new(source: path::PathBuf, target: path::PathBuf, options: &Options) -> io::Result<Self> {
+        let file = match interrupt::register_after(&source, || {
+            fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(options.mode)
+                .open(&source)
+        }) {
             Ok(file) => file,
             Err(error) if error.kind() == io::ErrorKind::AlreadyExists => {
                 return Err(io::Error::new(
@@ -270,12 +404,18 @@ impl Atomic {
             source,
             target,
             file: Some(file),
+            fsync: options.fsync,
         })
     }
 
-    fn commit(mut self) -> io::Result<()> {
-        mem::take(&mut self.file);
-        fs::rename(&self.source, &self.target)?;
+    /// Rename the file over its target.
+    pub fn commit(mut self) -> io::Result<()> {
+        if let Some(file) = self.file.take() {
+            if self.fsync {
+                file.sync_all()?;
+            }
+        }
+        interrupt::unregister_after(&self.source, || fs::rename(&self.source, &self.target))?;
 
         // Once we've successfully renamed the file, we want to avoid running our
         // destructor in case some other process has created the lock file in
@@ -307,7 +447,7 @@ impl Drop for Atomic {
         // }
         // ```
         mem::take(&mut self.file);
-        fs::remove_file(&self.source)
+        interrupt::unregister_after(&self.source, || fs::remove_file(&self.source))
             .unwrap_or_else(|_| panic!("Failed to clean up file: {}", self.source.display()));
     }
 }
@@ -327,3 +467,33 @@ impl io::Write for Atomic {
             .flush()
     }
 }
+
+#[test]
+fn options() -> io::Result<()> {
+    use std::io::Write as _;
+
+    let directory = crate::util::TempDir::new();
+    let target = directory.join("file");
+
+    let options = Options::new()
+        .fsync(true)
+        .mode(0o600)
+        .timeout(time::Duration::from_millis(20));
+    let mut lock = options.lock(target.clone())?;
+
+    let start = time::Instant::now();
+    let error = options.lock(target.clone()).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+    assert!(start.elapsed() >= time::Duration::from_millis(20));
+
+    lock.write_all(b"data")?;
+    lock.commit()?;
+    assert_eq!(fs::read(&target)?, b"data");
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt as _;
+        assert_eq!(fs::metadata(&target)?.permissions().mode() & 0o777, 0o600);
+    }
+
+    Ok(())
+}
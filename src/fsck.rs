@@ -0,0 +1,406 @@
+//! Integrity checks over the whole repository, like `git fsck`.
+//!
+//! Every object is read back and hashed again to check that it's stored
+//! under the right id, and parsed to find the objects it refers to. Any of
+//! those that are missing or of the wrong type are reported, along with
+//! references, reflogs, and index entries that point at missing objects.
+//! Objects nothing refers to are reported as dangling, which is harmless.
+
+use std::cmp;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
+use std::io;
+use std::io::Read as _;
+use std::num;
+use std::panic;
+use std::sync::atomic;
+use std::thread;
+
+use crate::object;
+
+/// Fewest objects worth splitting between workers.
+const PARALLEL_THRESHOLD: usize = 256;
+
+/// Something wrong found by [`verify`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Problem {
+    /// Object `id` can't be read or parsed.
+    Corrupt { id: object::Id, error: String },
+    /// Object stored as `id` hashes to `actual` instead.
+    Hash { id: object::Id, actual: object::Id },
+    /// `referrer` refers to object `id`, which doesn't exist. `expected` is
+    /// the type it should have, if known.
+    Missing {
+        id: object::Id,
+        expected: Option<object::Type>,
+        referrer: String,
+    },
+    /// `referrer` refers to object `id` as one of type `expected`, but it's
+    /// of type `actual`.
+    Type {
+        id: object::Id,
+        expected: object::Type,
+        actual: object::Type,
+        referrer: String,
+    },
+    /// Object `id` exists, but nothing refers to it, e.g. a commit that was
+    /// amended after its reflog entry expired.
+    Dangling {
+        id: object::Id,
+        r#type: object::Type,
+    },
+}
+
+impl Problem {
+    /// Whether this is actual damage, rather than just a dangling object.
+    pub fn is_error(&self) -> bool {
+        !matches!(self, Problem::Dangling { .. })
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::Corrupt { id, error } => write!(fmt, "corrupt object {}: {}", id, error),
+            Problem::Hash { id, actual } => {
+                write!(fmt, "hash mismatch {}: contents hash to {}", id, actual)
+            }
+            Problem::Missing {
+                id,
+                expected: Some(expected),
+                referrer,
+            } => write!(fmt, "missing {} {} in {}", expected, id, referrer),
+            Problem::Missing {
+                id,
+                expected: None,
+                referrer,
+            } => write!(fmt, "missing object {} in {}", id, referrer),
+            Problem::Type {
+                id,
+                expected,
+                actual,
+                referrer,
+            } => write!(
+                fmt,
+                "expected {} {} in {}, but found a {}",
+                expected, id, referrer, actual,
+            ),
+            Problem::Dangling { id, r#type } => write!(fmt, "dangling {} {}", r#type, id),
+        }
+    }
+}
+
+/// Type and links of one object, or what's wrong with it.
+type Checked = Result<(object::Type, Vec<(object::Id, object::Type)>), Problem>;
+
+/// Check every object in `repository`, along with the references, reflogs,
+/// and index entries that point into them, returning the problems found.
+///
+/// Many objects are split between one worker per logical core, which each
+/// open their own handle to the database.
+pub fn verify(repository: &crate::Repository) -> anyhow::Result<Vec<Problem>> {
+    let database = repository.database()?;
+    let ids = database.iter()?.collect::<Vec<_>>();
+    let checked = check_all(&database, &ids);
+
+    let mut problems = Vec::new();
+    let mut types = HashMap::new();
+    let mut links = Vec::new();
+    for (id, checked) in ids.iter().zip(checked) {
+        match checked {
+            Err(problem) => problems.push(problem),
+            Ok((r#type, targets)) => {
+                types.insert(*id, r#type);
+                let referrer = format!("{} {}", r#type, id);
+                links.extend(
+                    targets
+                        .into_iter()
+                        .map(|(target, expected)| (target, Some(expected), referrer.clone())),
+                );
+            }
+        }
+    }
+    links.extend(roots(repository)?);
+    let referenced = links.iter().map(|(id, _, _)| *id).collect::<HashSet<_>>();
+
+    for (id, expected, referrer) in links {
+        match (types.get(&id), expected) {
+            (Some(actual), Some(expected)) if *actual != expected => problems.push(Problem::Type {
+                id,
+                expected,
+                actual: *actual,
+                referrer,
+            }),
+            (Some(_), _) => (),
+            // Objects that exist but couldn't be read are already reported.
+            (None, _) if database.contains(&id)? => (),
+            (None, expected) => problems.push(Problem::Missing {
+                id,
+                expected,
+                referrer,
+            }),
+        }
+    }
+
+    for id in &ids {
+        if let Some(r#type) = types.get(id).filter(|_| !referenced.contains(id)) {
+            problems.push(Problem::Dangling {
+                id: *id,
+                r#type: *r#type,
+            });
+        }
+    }
+    Ok(problems)
+}
+
+/// Check each of `ids`, in parallel if there are enough of them.
+fn check_all(database: &crate::Database, ids: &[object::Id]) -> Vec<Checked> {
+    let workers = cmp::min(
+        thread::available_parallelism().map_or(1, num::NonZeroUsize::get),
+        ids.len(),
+    );
+    let root = match database.root() {
+        Some(root) if workers > 1 && ids.len() >= PARALLEL_THRESHOLD => root,
+        _ => return ids.iter().map(|id| check(database, id)).collect(),
+    };
+
+    let next = atomic::AtomicUsize::new(0);
+    let next = &next;
+    let checked = thread::scope(|scope| {
+        (0..workers)
+            .map(|_| {
+                scope.spawn(move || {
+                    let database = crate::Database::open(root.to_path_buf());
+                    let mut checked = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, atomic::Ordering::Relaxed);
+                        match ids.get(index) {
+                            None => break checked,
+                            Some(id) => checked.push((index, check(&database, id))),
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .flat_map(|worker| {
+                worker
+                    .join()
+                    .unwrap_or_else(|panic| panic::resume_unwind(panic))
+            })
+            .collect::<Vec<_>>()
+    });
+
+    let mut ordered = checked;
+    ordered.sort_by_key(|(index, _)| *index);
+    ordered.into_iter().map(|(_, checked)| checked).collect()
+}
+
+/// Read object `id` back, hash it again, and parse it to find the objects
+/// it links to. Blobs are only streamed, since nothing in them is parsed.
+fn check(database: &crate::Database, id: &object::Id) -> Checked {
+    let corrupt = |error: &dyn fmt::Display| Problem::Corrupt {
+        id: *id,
+        error: error.to_string(),
+    };
+
+    let (header, mut reader) = match database.stream(id) {
+        Ok(stream) => stream,
+        Err(error) => return Err(corrupt(&error)),
+    };
+    let mut hash = sha1::Sha1::new();
+    hash.update(format!("{} {}\0", header.r#type, header.len).as_bytes());
+
+    let mut payload = Vec::new();
+    let mut buffer = vec![0; 1 << 16];
+    let mut len = 0;
+    loop {
+        match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => {
+                hash.update(&buffer[..read]);
+                if header.r#type != object::Type::Blob {
+                    payload.extend_from_slice(&buffer[..read]);
+                }
+                len += read;
+            }
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+            Err(error) => return Err(corrupt(&error)),
+        }
+    }
+    if len != header.len {
+        let error = format!("expected {} bytes, but found {}", header.len, len);
+        return Err(corrupt(&error));
+    }
+
+    let actual = object::Id::from_bytes(hash.digest().bytes());
+    if actual != *id {
+        return Err(Problem::Hash { id: *id, actual });
+    }
+
+    let links = match header.r#type {
+        object::Type::Blob => Vec::new(),
+        object::Type::Commit => match object::Commit::read(&mut &*payload) {
+            Err(error) => return Err(corrupt(&error)),
+            Ok(commit) => std::iter::once((*commit.tree(), object::Type::Tree))
+                .chain(
+                    commit
+                        .parents()
+                        .iter()
+                        .map(|parent| (*parent, object::Type::Commit)),
+                )
+                .collect(),
+        },
+        object::Type::Tree => match object::tree::Root::read(&mut &*payload) {
+            Err(error) => return Err(corrupt(&error)),
+            Ok(tree) => tree
+                .into_iter()
+                .map(|node| match node.mode.is_directory() {
+                    true => (node.id, object::Type::Tree),
+                    false => (node.id, object::Type::Blob),
+                })
+                .collect(),
+        },
+        object::Type::Tag => match object::Tag::read(&mut &*payload) {
+            Err(error) => return Err(corrupt(&error)),
+            Ok(tag) => vec![(*tag.object(), tag.r#type())],
+        },
+    };
+    Ok((header.r#type, links))
+}
+
+/// Objects that `HEAD`, other pseudo-references, references, reflogs, and
+/// the index point at, along with what points at them.
+#[allow(clippy::type_complexity)]
+fn roots(
+    repository: &crate::Repository,
+) -> anyhow::Result<Vec<(object::Id, Option<object::Type>, String)>> {
+    let references = repository.references();
+    let mut roots = Vec::new();
+    let mut names = vec![String::from("HEAD")];
+    for name in crate::gc::PSEUDO {
+        if let Some(id) = references.read(name)? {
+            roots.push((id, None, name.to_string()));
+        }
+    }
+    for reference in references.iter_prefix("refs/")? {
+        let (name, id) = reference?;
+        roots.push((id, None, name.clone()));
+        names.push(name);
+    }
+
+    for name in &names {
+        let referrer = format!("reflog of {}", name);
+        for entry in references.read_log(name)? {
+            if let Some(old) = entry.old {
+                roots.push((old, None, referrer.clone()));
+            }
+            roots.push((entry.new, None, referrer.clone()));
+        }
+    }
+
+    if !repository.is_bare() {
+        let index = repository.sparse_index()?;
+        for entry in index.entries() {
+            let expected = match entry.is_sparse_directory() {
+                true => object::Type::Tree,
+                false => object::Type::Blob,
+            };
+            let referrer = format!("index entry {}", entry.path().display());
+            roots.push((*entry.id(), Some(expected), referrer));
+        }
+        for (path, stages) in index.conflicts() {
+            let referrer = format!("index entry {}", path.display());
+            for entry in stages.iter().flatten() {
+                roots.push((*entry.id(), Some(object::Type::Blob), referrer.clone()));
+            }
+        }
+        for id in index.cached_trees() {
+            roots.push((
+                id,
+                Some(object::Type::Tree),
+                String::from("index cache tree"),
+            ));
+        }
+    }
+    Ok(roots)
+}
+
+#[test]
+fn problems() -> anyhow::Result<()> {
+    use std::path;
+
+    let mut repository = crate::Repository::memory(path::PathBuf::new());
+    repository.init()?;
+    let database = repository.database()?;
+    let references = repository.references();
+
+    let blob = database.store(&crate::Object::Blob(object::Blob::new(b"data".to_vec())))?;
+    let tree = database.store(&crate::Object::Tree(object::tree::Root::new(vec![
+        object::tree::Node::new(
+            path::PathBuf::from("file"),
+            blob,
+            crate::meta::Mode::Regular,
+        ),
+    ])))?;
+    let person = object::Person::new(
+        String::from("A U Thor"),
+        String::from("author@example.com"),
+        object::Person::parse_time("@1 +0000")?,
+    );
+    let commit = |tree, parents| {
+        database.store(&crate::Object::Commit(object::Commit::new(
+            tree,
+            parents,
+            person.clone(),
+            person.clone(),
+            String::from("message\n"),
+        )))
+    };
+    let root = commit(tree, Vec::new())?;
+    references.update_ref("refs/heads/master", &root, &person, "commit")?;
+    assert_eq!(repository.verify()?, Vec::new());
+
+    let missing = object::Id::hash(b"missing");
+    let orphan = commit(tree, vec![missing])?;
+    let dangling = database.store(&crate::Object::Blob(object::Blob::new(
+        b"dangling".to_vec(),
+    )))?;
+    let wrong = commit(blob, Vec::new())?;
+    references.update_ref("refs/heads/wrong", &wrong, &person, "commit")?;
+    let corrupt = object::Id::hash(b"corrupt");
+    database.backend().write(&corrupt, b"blob 4\0data")?;
+
+    let problems = repository.verify()?;
+    let errors = problems.iter().filter(|problem| problem.is_error()).count();
+    assert_eq!(errors, 3);
+    for problem in [
+        Problem::Hash {
+            id: corrupt,
+            actual: blob,
+        },
+        Problem::Missing {
+            id: missing,
+            expected: Some(object::Type::Commit),
+            referrer: format!("commit {}", orphan),
+        },
+        Problem::Type {
+            id: blob,
+            expected: object::Type::Tree,
+            actual: object::Type::Blob,
+            referrer: format!("commit {}", wrong),
+        },
+        Problem::Dangling {
+            id: orphan,
+            r#type: object::Type::Commit,
+        },
+        Problem::Dangling {
+            id: dangling,
+            r#type: object::Type::Blob,
+        },
+    ] {
+        assert!(problems.contains(&problem), "{}", problem);
+    }
+    Ok(())
+}
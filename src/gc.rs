@@ -0,0 +1,327 @@
+//! Housekeeping that packs loose objects, consolidates packfiles, expires
+//! old reflog entries, and prunes unreachable objects, run explicitly by
+//! `grit gc` or automatically after porcelain commands that create objects,
+//! like `git gc --auto`.
+//!
+//! Objects are reachable from `HEAD` and other pseudo-references like
+//! `MERGE_HEAD`, every reference, their reflogs, and the index. Unreachable
+//! objects are only pruned once they're older than a grace period, since
+//! concurrent commands may be about to refer to them. Only one gc runs in a
+//! repository at a time, guarded by `.git/gc.pid.lock`.
+
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::io;
+use std::io::Write as _;
+use std::process;
+use std::time;
+
+use anyhow::anyhow;
+
+use crate::config;
+use crate::database;
+use crate::file;
+use crate::object;
+use crate::revwalk;
+
+/// Default for `gc.auto`, the number of loose objects tolerated.
+const AUTO: usize = 6700;
+
+/// Default for `gc.autoPackLimit`, the number of packfiles tolerated.
+const AUTO_PACK_LIMIT: usize = 50;
+
+/// Default for `gc.pruneExpire`, the age of unreachable objects to prune.
+const PRUNE_EXPIRE: &str = "2.weeks.ago";
+
+/// Default for `gc.reflogExpire`, the age of reflog entries to expire.
+const REFLOG_EXPIRE: &str = "90.days.ago";
+
+/// Default for `gc.reflogExpireUnreachable`, the age of reflog entries to
+/// expire if they're no longer in their reference's history.
+const REFLOG_EXPIRE_UNREACHABLE: &str = "30.days.ago";
+
+/// Pseudo-references that keep the objects they point to, besides those
+/// under `refs/`.
+pub(crate) const PSEUDO: &[&str] = &["HEAD", "MERGE_HEAD", "CHERRY_PICK_HEAD", "REVERT_HEAD"];
+
+/// Stashes are never in each other's history, so their reflog entries only
+/// expire with `gc.reflogExpire`.
+const STASH: &str = "refs/stash";
+
+/// Age after which a gc lock is assumed to belong to a process that died.
+const STALE: time::Duration = time::Duration::from_secs(12 * 60 * 60);
+
+/// Whether the repository has more than `gc.auto` loose objects or
+/// `gc.autoPackLimit` packfiles. Setting either to 0 disables its check,
+/// and setting `gc.auto` to 0 disables both.
+pub fn needed(config: &config::Config, database: &crate::Database) -> anyhow::Result<bool> {
+    let loose = config.parse::<usize>("gc.auto")?.unwrap_or(AUTO);
+    if loose == 0 {
+        return Ok(false);
+    }
+    if database.estimate_loose()? > loose {
+        return Ok(true);
+    }
+
+    let packs = config
+        .parse::<usize>("gc.autoPackLimit")?
+        .unwrap_or(AUTO_PACK_LIMIT);
+    Ok(packs > 0 && database.pack_indexes()?.len() > packs)
+}
+
+/// Run [`run`] if [`needed`], in a detached `grit gc --auto` process unless
+/// `gc.autoDetach` is false.
+pub fn auto(repository: &crate::Repository) -> anyhow::Result<()> {
+    let config = repository.config()?;
+    if !needed(&config, &repository.database()?)? {
+        return Ok(());
+    }
+
+    if config.get_bool("gc.autoDetach")?.unwrap_or(true) {
+        eprintln!("Auto packing the repository in background for optimum performance.");
+        process::Command::new(env::current_exe()?)
+            .args(["gc", "--auto"])
+            .current_dir(repository.root())
+            .stdin(process::Stdio::null())
+            .stdout(process::Stdio::null())
+            .stderr(process::Stdio::null())
+            .spawn()?;
+    } else {
+        eprintln!("Auto packing the repository for optimum performance.");
+        run(repository, None)?;
+    }
+    Ok(())
+}
+
+/// Expire old reflog entries, then pack every reachable object into a
+/// single packfile and delete unreachable objects older than `prune`, a
+/// date as accepted by [`expiry`] that defaults to `gc.pruneExpire`.
+/// Returns `false` without doing anything if another gc is already running.
+pub fn run(repository: &crate::Repository, prune: Option<&str>) -> anyhow::Result<bool> {
+    let config = repository.config()?;
+    let now = time::SystemTime::now();
+    let prune = prune
+        .or_else(|| config.get("gc.pruneExpire"))
+        .unwrap_or(PRUNE_EXPIRE);
+    let prune = expiry(prune, now)?;
+    let reflog = config.get("gc.reflogExpire").unwrap_or(REFLOG_EXPIRE);
+    let reflog = expiry(reflog, now)?;
+    let unreachable = config
+        .get("gc.reflogExpireUnreachable")
+        .unwrap_or(REFLOG_EXPIRE_UNREACHABLE);
+    let unreachable = expiry(unreachable, now)?;
+
+    let path = repository.git().join("gc.pid");
+    let mut lock = match lock(&path)? {
+        None => return Ok(false),
+        Some(lock) => lock,
+    };
+    writeln!(lock, "{}", process::id())?;
+
+    let database = repository.database()?;
+    expire_reflogs(repository, &database, reflog, unreachable)?;
+
+    match prune {
+        None => database.repack(None)?,
+        Some(expiry) => {
+            let reachable = revwalk::reachable(&database, roots(repository, &database)?)?;
+            database.repack(Some(&database::Prune {
+                reachable: &reachable,
+                expiry,
+            }))?
+        }
+    };
+
+    // Dropping rather than committing the lock leaves no `gc.pid` behind.
+    drop(lock);
+    Ok(true)
+}
+
+/// Parse an expiry date like `git`'s: `now` (or `all`), `never`, a relative
+/// date like `2.weeks.ago` or `3 days ago`, or an absolute date in a format
+/// accepted by [`object::Person::parse_time`]. Returns `None` for `never`.
+pub fn expiry(value: &str, now: time::SystemTime) -> anyhow::Result<Option<time::SystemTime>> {
+    match value {
+        "never" => return Ok(None),
+        "now" | "all" => return Ok(Some(now)),
+        _ => (),
+    }
+
+    let words = value
+        .split(|char: char| char == '.' || char.is_whitespace())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>();
+    let (count, unit) = match words.as_slice() {
+        [count, unit] | [count, unit, "ago"] => match count.parse::<u64>() {
+            Ok(count) => (count, unit.strip_suffix('s').unwrap_or(unit)),
+            Err(_) => return absolute(value),
+        },
+        _ => return absolute(value),
+    };
+    let seconds = match unit {
+        "second" => 1,
+        "minute" => 60,
+        "hour" => 60 * 60,
+        "day" => 24 * 60 * 60,
+        "week" => 7 * 24 * 60 * 60,
+        "month" => 30 * 24 * 60 * 60,
+        "year" => 365 * 24 * 60 * 60,
+        _ => return Err(anyhow!("Invalid expiry date: {}", value)),
+    };
+    Ok(Some(
+        now.checked_sub(time::Duration::from_secs(count * seconds))
+            .unwrap_or(time::UNIX_EPOCH),
+    ))
+}
+
+fn absolute(value: &str) -> anyhow::Result<Option<time::SystemTime>> {
+    match object::Person::parse_time(value) {
+        Ok(time) => Ok(Some(time.into())),
+        Err(_) => Err(anyhow!("Invalid expiry date: {}", value)),
+    }
+}
+
+/// Drop reflog entries made before `expire`, and those made before
+/// `unreachable` whose commits are no longer in their reference's history,
+/// e.g. because they were amended or reset away.
+fn expire_reflogs(
+    repository: &crate::Repository,
+    database: &crate::Database,
+    expire: Option<time::SystemTime>,
+    unreachable: Option<time::SystemTime>,
+) -> anyhow::Result<()> {
+    let references = repository.references();
+    let mut tips = vec![(String::from("HEAD"), references.read_head()?)];
+    for reference in references.iter_prefix("refs/")? {
+        let (name, id) = reference?;
+        tips.push((name, Some(id)));
+    }
+
+    for (name, tip) in tips {
+        let entries = references.read_log(&name)?;
+        let mut history = None;
+        let mut kept = Vec::with_capacity(entries.len());
+        for entry in &entries {
+            let time = time::SystemTime::from(*entry.committer.time());
+            if expire.is_some_and(|expire| time < expire) {
+                continue;
+            }
+            if name != STASH && unreachable.is_some_and(|unreachable| time < unreachable) {
+                let history = match &mut history {
+                    Some(history) => history,
+                    None => history.insert(ancestors(database, tip)?),
+                };
+                if !history.contains(&entry.new) {
+                    continue;
+                }
+            }
+            kept.push(entry.clone());
+        }
+
+        if kept.len() < entries.len() {
+            references.store().write_log(&name, &kept)?;
+        }
+    }
+    Ok(())
+}
+
+/// Commits in the history of `tip`, which is empty if it isn't a commit.
+fn ancestors(
+    database: &crate::Database,
+    tip: Option<object::Id>,
+) -> anyhow::Result<HashSet<object::Id>> {
+    let tip = match tip.map(|tip| tip.peel_to_commit(database)) {
+        Some(Ok(tip)) => tip,
+        None | Some(Err(_)) => return Ok(HashSet::new()),
+    };
+    let mut walk = revwalk::Walk::new(database);
+    walk.include(tip)?;
+    Ok(walk.run()?.commits.into_iter().collect())
+}
+
+/// Objects to keep regardless of their age, and everything they reach.
+fn roots(
+    repository: &crate::Repository,
+    database: &crate::Database,
+) -> anyhow::Result<Vec<object::Id>> {
+    let references = repository.references();
+    let mut roots = Vec::new();
+    let mut names = vec![String::from("HEAD")];
+    for name in PSEUDO {
+        roots.extend(references.read(name)?);
+    }
+    for reference in references.iter_prefix("refs/")? {
+        let (name, id) = reference?;
+        roots.push(id);
+        names.push(name);
+    }
+
+    for name in &names {
+        for entry in references.read_log(name)? {
+            roots.extend(entry.old);
+            roots.push(entry.new);
+        }
+    }
+
+    if !repository.is_bare() {
+        let index = repository.sparse_index()?;
+        roots.extend(index.entries().map(|entry| *entry.id()));
+        for (_, stages) in index.conflicts() {
+            roots.extend(stages.iter().flatten().map(|entry| *entry.id()));
+        }
+        // Cached trees are only an optimization, so one that's somehow
+        // missing shouldn't stop gc.
+        for id in index.cached_trees() {
+            if database.contains(&id)? {
+                roots.push(id);
+            }
+        }
+    }
+    Ok(roots)
+}
+
+/// Acquire the lock on `path`, taking over locks abandoned long ago.
+fn lock(path: &std::path::Path) -> anyhow::Result<Option<file::WriteLock>> {
+    match file::WriteLock::new(path.to_path_buf()) {
+        Ok(lock) => return Ok(Some(lock)),
+        Err(error) if error.kind() == io::ErrorKind::AlreadyExists => (),
+        Err(error) => return Err(error.into()),
+    }
+
+    let held = path.with_extension("pid.lock");
+    let stale = fs::metadata(&held)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .is_some_and(|age| age > STALE);
+    if !stale {
+        return Ok(None);
+    }
+
+    fs::remove_file(&held).ok();
+    match file::WriteLock::new(path.to_path_buf()) {
+        Ok(lock) => Ok(Some(lock)),
+        Err(error) if error.kind() == io::ErrorKind::AlreadyExists => Ok(None),
+        Err(error) => Err(error.into()),
+    }
+}
+
+#[test]
+fn expire() -> anyhow::Result<()> {
+    let now = time::UNIX_EPOCH + time::Duration::from_secs(30 * 24 * 60 * 60);
+    let ago = |seconds| Some(now - time::Duration::from_secs(seconds));
+
+    assert_eq!(expiry("now", now)?, Some(now));
+    assert_eq!(expiry("never", now)?, None);
+    assert_eq!(expiry("2.weeks.ago", now)?, ago(14 * 24 * 60 * 60));
+    assert_eq!(expiry("1 hour ago", now)?, ago(60 * 60));
+    assert_eq!(expiry("30.days", now)?, Some(time::UNIX_EPOCH));
+    assert_eq!(
+        expiry("@86400 +0000", now)?,
+        Some(time::UNIX_EPOCH + time::Duration::from_secs(86400)),
+    );
+    assert!(expiry("2.fortnights.ago", now).is_err());
+    assert!(expiry("yesterday", now).is_err());
+    Ok(())
+}
@@ -0,0 +1,1216 @@
+//! Manual pages for each command, shown by `grit help <command>`.
+//!
+//! `structopt` already documents every flag in `--help`; these pages add a
+//! longer description, worked examples, and the configuration keys that
+//! change a command's behavior, which are also appended to its `--help`.
+
+use std::fmt;
+
+/// Expand to one entry of a configuration listing, in the layout `clap`
+/// uses for flags whose help is on the next line.
+macro_rules! key {
+    ($key:literal, $help:literal) => {
+        concat!("    ", $key, "\n            ", $help, "\n")
+    };
+}
+
+macro_rules! order_file {
+    () => {
+        key!(
+            "diff.orderFile",
+            "Order file to sort changed files by when `-O` isn't given."
+        )
+    };
+}
+
+macro_rules! renames {
+    () => {
+        concat!(
+            key!(
+                "diff.renames",
+                "Whether renamed files are detected when neither `-M` nor `--no-renames` is given."
+            ),
+            key!(
+                "diff.renameLimit",
+                "Most files compared when detecting renames."
+            ),
+        )
+    };
+}
+
+macro_rules! log_date {
+    () => {
+        key!("log.date", "Date format to use when `--date` isn't given.")
+    };
+}
+
+macro_rules! attributes {
+    () => {
+        concat!(
+            key!(
+                "core.attributesFile",
+                "User-wide attributes file, below `.gitattributes`."
+            ),
+            key!(
+                "core.autocrlf",
+                "Convert line endings of text files: `true`, `input`, or `false`."
+            ),
+            key!(
+                "core.eol",
+                "Line ending of text files in the workspace: `lf` or `crlf`."
+            ),
+        )
+    };
+}
+
+macro_rules! external_diff {
+    () => {
+        concat!(
+            key!("diff.external", "Program comparing every file."),
+            key!(
+                "diff.<driver>.command",
+                "Program comparing files with the `diff=<driver>` attribute."
+            ),
+            key!(
+                "diff.<driver>.binary",
+                "Whether files with the `diff=<driver>` attribute are binary."
+            ),
+            key!(
+                "diff.<driver>.textconv",
+                "Program converting files with the `diff=<driver>` attribute to text for diffs."
+            ),
+            key!(
+                "diff.<driver>.cachetextconv",
+                "Whether to cache textconv output in `refs/notes/textconv/<driver>`."
+            ),
+        )
+    };
+}
+
+macro_rules! check_stat {
+    () => {
+        concat!(
+            key!(
+                "core.checkStat",
+                "Which file metadata detects changes: `default` or `minimal`."
+            ),
+            file_mode!(),
+        )
+    };
+}
+
+macro_rules! file_mode {
+    () => {
+        key!(
+            "core.fileMode",
+            "Whether the executable bit of files is trusted. Set by `grit init`."
+        )
+    };
+}
+
+macro_rules! identity {
+    () => {
+        concat!(
+            key!("user.name", "Name recorded as author and committer."),
+            key!("user.email", "Email recorded as author and committer."),
+        )
+    };
+}
+
+macro_rules! fsync {
+    () => {
+        concat!(
+            key!(
+                "core.fsync",
+                "Which files are flushed to disk before being renamed into place."
+            ),
+            key!("core.fsyncMethod", "How files are flushed to disk."),
+        )
+    };
+}
+
+macro_rules! workspace {
+    () => {
+        concat!(
+            key!(
+                "core.symlinks",
+                "Whether symbolic links are checked out as links."
+            ),
+            key!(
+                "checkout.workers",
+                "Number of threads writing files to the workspace."
+            ),
+        )
+    };
+}
+
+macro_rules! gc {
+    () => {
+        concat!(
+            key!("gc.auto", "Loose objects tolerated before packing them."),
+            key!(
+                "gc.autoPackLimit",
+                "Packfiles tolerated before combining them."
+            ),
+            key!(
+                "gc.autoDetach",
+                "Whether automatic packing runs in the background."
+            ),
+        )
+    };
+}
+
+macro_rules! remote {
+    () => {
+        concat!(
+            key!("remote.<name>.url", "URL of the remote repository."),
+            key!(
+                "remote.<name>.fetch",
+                "Refspecs mapping remote references to local ones."
+            ),
+            key!(
+                "transfer.retries",
+                "Times to retry a request that failed transiently, resuming cut-off fetches."
+            ),
+            key!(
+                "http.proxy",
+                "Proxy for HTTP remotes, overriding `http_proxy` and the like."
+            ),
+            key!(
+                "http.sslCAInfo",
+                "File of certificate authorities to trust instead of the built-in ones."
+            ),
+            key!(
+                "http.extraHeader",
+                "Header to send with every HTTP request; may be given more than once."
+            ),
+            key!(
+                "http.<url>.*",
+                "Any of the above, for remotes whose URL starts with <url>."
+            ),
+        )
+    };
+}
+
+/// Manual page for a single command.
+#[derive(Copy, Clone, Debug)]
+pub struct Page {
+    /// Name of the command, as typed after `grit`.
+    pub name: &'static str,
+    /// One line describing the command.
+    pub summary: &'static str,
+    /// Ways of invoking the command, one per line.
+    pub synopsis: &'static [&'static str],
+    /// Paragraphs describing the command in more depth.
+    pub description: &'static str,
+    /// Pairs of explanation and command line.
+    pub examples: &'static [(&'static str, &'static str)],
+    /// Configuration keys read by the command, formatted as a section for
+    /// `--help`, or empty if there are none.
+    pub config: &'static str,
+}
+
+impl Page {
+    pub const fn new(name: &'static str, summary: &'static str) -> Self {
+        Page {
+            name,
+            summary,
+            synopsis: &[],
+            description: "",
+            examples: &[],
+            config: "",
+        }
+    }
+}
+
+impl fmt::Display for Page {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(fmt, "NAME:")?;
+        writeln!(fmt, "    grit-{} - {}", self.name, self.summary)?;
+
+        writeln!(fmt, "\nSYNOPSIS:")?;
+        for line in self.synopsis {
+            writeln!(fmt, "    {}", line)?;
+        }
+
+        writeln!(fmt, "\nDESCRIPTION:")?;
+        for line in self.description.lines() {
+            match line {
+                "" => writeln!(fmt)?,
+                line => writeln!(fmt, "    {}", line)?,
+            }
+        }
+
+        if !self.examples.is_empty() {
+            writeln!(fmt, "\nEXAMPLES:")?;
+            for (index, (explanation, command)) in self.examples.iter().enumerate() {
+                if index > 0 {
+                    writeln!(fmt)?;
+                }
+                writeln!(fmt, "    {}", explanation)?;
+                writeln!(fmt, "        $ {}", command)?;
+            }
+        }
+
+        if !self.config.is_empty() {
+            write!(fmt, "\n{}", self.config)?;
+        }
+        Ok(())
+    }
+}
+
+/// Look up the page for `name`.
+pub fn find(name: &str) -> Option<&'static Page> {
+    PAGES.iter().find(|page| page.name == name)
+}
+
+/// Every page, sorted by command name.
+pub const PAGES: &[Page] = &[
+    ADD,
+    BLAME,
+    BRANCH,
+    CAT_FILE,
+    CHECKOUT,
+    CLONE,
+    COMMIT,
+    COMMIT_GRAPH,
+    CONFIG,
+    DIFF,
+    DOCTOR,
+    DUMP_INDEX,
+    FAST_EXPORT,
+    FAST_IMPORT,
+    FETCH,
+    FILTER,
+    FSCK,
+    GC,
+    GREP,
+    HELP,
+    INIT,
+    LOG,
+    LS_FILES,
+    LS_TREE,
+    MERGE,
+    MERGE_TREE,
+    PACK_OBJECTS,
+    PUSH,
+    REFLOG,
+    REMOTE,
+    RESET,
+    RESTORE,
+    REV_LIST,
+    REV_PARSE,
+    RM,
+    SHOW,
+    SHOW_BRANCH,
+    STASH,
+    STATUS,
+    TAG,
+];
+
+pub const ADD: Page = Page {
+    synopsis: &["grit add <path>..."],
+    description: "\
+Stage the current contents of files for the next commit. Directories are
+added recursively. Use `grit rm` to stage the removal of a file. Line
+endings of text files are normalized to LF, as selected by the `text` and
+`eol` attributes in `.gitattributes` or by `core.autocrlf`, and converted
+back when files are checked out. Files whose line endings would change
+on the way back are reported. Many files are read, hashed, and stored in
+parallel, one thread per core. Files larger than `core.bigFileThreshold`
+that aren't treated as text are streamed into the database instead of
+being read into memory.",
+    examples: &[
+        ("Stage a single file:", "grit add src/main.rs"),
+        (
+            "Stage everything under the current directory:",
+            "grit add .",
+        ),
+    ],
+    config: concat!(
+        "CONFIGURATION:\n",
+        fsync!(),
+        file_mode!(),
+        attributes!(),
+        key!(
+            "core.safecrlf",
+            "Whether to `warn` about or refuse (`true`) irreversible conversions."
+        ),
+        key!(
+            "core.bigFileThreshold",
+            "Size past which files are streamed, e.g. `512m` (the default)."
+        ),
+    ),
+    ..Page::new("add", "Add file contents to the index")
+};
+
+pub const BLAME: Page = Page {
+    synopsis: &[
+        "grit blame [--porcelain | --line-porcelain | --incremental] [-L <range>]... [<revision>] <path>",
+    ],
+    description: "\
+Show the commit that last changed each line of a file, with its author and
+date. Without a revision, the file in the workspace is blamed, and lines
+changed since `HEAD` are attributed to `Not Committed Yet`.
+
+The porcelain formats are for editors and other tools: `--porcelain`
+describes each commit once, `--line-porcelain` before every line, and
+`--incremental` prints groups of lines as their commits are found.
+
+`-L` limits blame to ranges of lines, like `10,20`, `10,+5` for five lines
+starting at line 10, or `,20` for the first 20 lines.
+
+Blames are saved in `.git/grit/cache/blame`, so blaming a file again, or
+at a later commit, only examines history that wasn't blamed before.",
+    examples: &[
+        ("Find who changed each line:", "grit blame src/main.rs"),
+        ("Blame one function:", "grit blame -L 40,+12 src/main.rs"),
+        (
+            "Feed an editor integration:",
+            "grit blame --incremental HEAD src/main.rs",
+        ),
+    ],
+    ..Page::new("blame", "Show what commit last changed each line")
+};
+
+pub const BRANCH: Page = Page {
+    synopsis: &[
+        "grit branch [-l]",
+        "grit branch <name> [<start>]",
+        "grit branch -d <name>",
+    ],
+    description: "\
+List branches, marking the current one with `*`, create a branch pointing
+at `<start>` (by default `HEAD`), or delete a branch. Creating a branch
+does not switch to it: use `grit checkout` for that.",
+    examples: &[
+        (
+            "Start a topic branch at the current commit:",
+            "grit branch topic",
+        ),
+        ("Start a branch from another one:", "grit branch fix main~2"),
+        ("Delete a branch:", "grit branch -d topic"),
+    ],
+    config: concat!("CONFIGURATION:\n", identity!()),
+    ..Page::new("branch", "List, create, or delete branches")
+};
+
+pub const CAT_FILE: Page = Page {
+    synopsis: &["grit cat-file (-t | -s | -p | -e) <object>"],
+    description: "\
+Inspect a single object in the database by revision or id: its type with
+`-t`, its size in bytes with `-s`, or its contents with `-p`. With `-e`,
+nothing is printed, and the exit status tells whether the object exists.",
+    examples: &[
+        ("Print the commit at HEAD:", "grit cat-file -p HEAD"),
+        ("Print the type of an object:", "grit cat-file -t HEAD:src"),
+    ],
+    ..Page::new("cat-file", "Print the type, size, or contents of an object")
+};
+
+pub const CHECKOUT: Page = Page {
+    synopsis: &["grit checkout <target>"],
+    description: "\
+Switch to a branch, or detach `HEAD` at any other revision, updating the
+index and workspace to match. Local changes are carried over when they
+don't conflict with the switch; otherwise nothing is changed.",
+    examples: &[
+        ("Switch to a branch:", "grit checkout topic"),
+        ("Look at an older commit:", "grit checkout HEAD~3"),
+    ],
+    config: concat!("CONFIGURATION:\n", check_stat!(), workspace!()),
+    ..Page::new("checkout", "Switch branches or detach HEAD")
+};
+
+pub const CLONE: Page = Page {
+    synopsis: &["grit clone [--mirror] [--bundle-uri <uri>] <repository> [<directory>]"],
+    description: "\
+Create a new repository from another, served over smart HTTP, over `ssh`
+for `ssh://` and `host:path` URLs, or on the same filesystem for paths and
+`file://` URLs. Every remote branch gets a remote-tracking branch under
+`refs/remotes/origin`, tags are copied, and the remote's current branch
+is checked out.
+
+`--mirror` instead creates a bare repository with a copy of every remote
+reference, for backups. Later `fetch` and `push` keep all references in
+sync with `origin`, including deletions.
+
+`--bundle-uri` first unpacks the bundle (see `git bundle`) at the given URL
+or path, then fetches only what it lacks from the remote. With
+`transfer.bundleURI` set, bundles the remote advertises are used instead.
+
+The directory defaults to the last component of the URL or path, with
+`.git` appended for mirrors.",
+    examples: &[
+        (
+            "Clone a repository:",
+            "grit clone https://github.com/nwtnni/grit.git",
+        ),
+        (
+            "Clone a repository over ssh:",
+            "grit clone git@github.com:nwtnni/grit.git",
+        ),
+        ("Clone a local repository:", "grit clone ../grit copy"),
+        (
+            "Mirror a repository:",
+            "grit clone --mirror https://github.com/nwtnni/grit.git",
+        ),
+        (
+            "Clone starting from a pre-generated bundle:",
+            "grit clone --bundle-uri https://example.com/grit.bundle https://github.com/nwtnni/grit.git",
+        ),
+    ],
+    config: concat!(
+        "CONFIGURATION:\n",
+        remote!(),
+        key!(
+            "transfer.bundleURI",
+            "Whether to unpack bundles the remote advertises before fetching."
+        ),
+        workspace!(),
+    ),
+    ..Page::new("clone", "Clone a repository into a new directory")
+};
+
+pub const COMMIT: Page = Page {
+    synopsis: &["grit commit [-m <message>] [-q | --porcelain]"],
+    description: "\
+Record the index as a new commit on the current branch, and print a
+summary of what changed. Without `-m`, the message is read from standard
+input.
+
+When a merge, cherry-pick, or revert stopped for conflicts to be resolved,
+committing concludes it, recording every parent and, for a cherry-pick,
+the original author.
+
+The author and committer are taken from `GIT_AUTHOR_NAME`,
+`GIT_AUTHOR_EMAIL`, and `GIT_AUTHOR_DATE`, and their `GIT_COMMITTER_*`
+counterparts, falling back to `user.name`, `user.email`, and the current
+time.",
+    examples: &[
+        ("Commit with a message:", "grit commit -m \"Fix typo\""),
+        (
+            "Commit as someone else:",
+            "grit commit --author-name Ada --author-email ada@example.com -m Import",
+        ),
+        (
+            "Commit with a fixed date:",
+            "GIT_AUTHOR_DATE='@1600000000 +0000' grit commit -m Import",
+        ),
+    ],
+    config: concat!(
+        "CONFIGURATION:\n",
+        identity!(),
+        key!("diff.renames", "Whether the summary detects renamed files."),
+        key!(
+            "diff.renameLimit",
+            "Most files compared when detecting renames."
+        ),
+        fsync!(),
+        gc!(),
+    ),
+    ..Page::new("commit", "Record changes to the repository")
+};
+
+pub const COMMIT_GRAPH: Page = Page {
+    synopsis: &["grit commit-graph write [--split]"],
+    description: "\
+Write the commit-graph, a file in `.git/objects/info` caching the parents,
+generation, and time of every commit, so that `log`, `rev-list`, and other
+commands walking history read it instead of each commit object.
+
+With `--split`, only commits missing from the existing graph are written,
+as a new layer in a chain.",
+    examples: &[
+        ("Write a complete commit-graph:", "grit commit-graph write"),
+        (
+            "Add new commits to the graph:",
+            "grit commit-graph write --split",
+        ),
+    ],
+    ..Page::new("commit-graph", "Write and inspect the commit-graph")
+};
+
+pub const CONFIG: Page = Page {
+    synopsis: &[
+        "grit config [--system | --global | --local] [--get | --get-all] <key>",
+        "grit config [--system | --global | --local] [--set | --add] <key> <value>",
+        "grit config [--system | --global | --local] --unset <key>",
+        "grit config [--system | --global | --local] -l",
+    ],
+    description: "\
+Read and write configuration files. Reads merge every scope, with the
+repository's `.git/config` taking precedence over `~/.gitconfig`, which
+takes precedence over the system file. Writes go to `.git/config` unless
+another scope is given.
+
+`grit help <command>` lists the keys each command reads.",
+    examples: &[
+        (
+            "Set your name for every repository:",
+            "grit config --global user.name \"Ada Lovelace\"",
+        ),
+        ("Show every setting:", "grit config -l"),
+    ],
+    ..Page::new("config", "Get and set repository or global options")
+};
+
+pub const DIFF: Page = Page {
+    synopsis: &[
+        "grit diff [--cached] [-O <orderfile>] [--no-ext-diff] [-M[<score>] | --no-renames] [<path>...]",
+    ],
+    description: "\
+Show changes in the workspace that are not yet staged, or with `--cached`,
+staged changes that are not yet committed, as a unified diff. Paths limit
+the output to files under them. Files are sorted by path, or by the globs
+listed one per line in an order file; files matching an earlier glob come
+first, and files matching none come last. Staged renames are detected
+unless `--no-renames` or `diff.renames` says otherwise. Files can be
+compared by an
+external program instead, given `GIT_EXTERNAL_DIFF`, `diff.external`, or
+the `diff.<driver>.command` of their `diff=<driver>` attribute, which is
+passed the path, then the temporary file, id, and mode of each side.",
+    examples: &[
+        ("Review what will be committed:", "grit diff --cached"),
+        ("Show unstaged changes in one directory:", "grit diff src"),
+        (
+            "Sort files by the globs in `.gitorder`:",
+            "grit diff -O .gitorder",
+        ),
+    ],
+    config: concat!(
+        "CONFIGURATION:\n",
+        check_stat!(),
+        order_file!(),
+        renames!(),
+        external_diff!(),
+    ),
+    ..Page::new(
+        "diff",
+        "Show changes between the workspace, index, and HEAD",
+    )
+};
+
+pub const DOCTOR: Page = Page {
+    synopsis: &["grit doctor [--clean [-f]]"],
+    description: "\
+Explain state left behind by interrupted operations, such as unfinished
+merges, rebases, and bisects, or stale lock files, and how to deal with
+each. With `--clean`, remove it; lock files that may still be in use are
+only removed with `-f`.",
+    examples: &[
+        ("Check for leftovers:", "grit doctor"),
+        ("Abandon an unfinished merge:", "grit doctor --clean"),
+    ],
+    ..Page::new("doctor", "Explain and clean up interrupted operations")
+};
+
+pub const DUMP_INDEX: Page = Page {
+    synopsis: &["grit dump-index"],
+    description: "\
+Print the format version and every entry of the index, with its mode,
+object id, stage, and full stat information and flags, followed by the
+contents of each extension. The cache tree is decoded, and other
+extensions are shown in hex. Sparse directories stay collapsed.",
+    examples: &[(
+        "Find why a file keeps showing as modified:",
+        "grit dump-index && stat <file>",
+    )],
+    ..Page::new("dump-index", "Print the raw contents of the index")
+};
+
+pub const FAST_EXPORT: Page = Page {
+    synopsis: &["grit fast-export (--all | <reference>...)"],
+    description: "\
+Write history as a `git fast-import` stream on standard output, e.g. to
+move it to another version control tool or to rewrite it with a script.",
+    examples: &[(
+        "Copy a repository's history into another:",
+        "grit fast-export --all | (cd ../copy && grit fast-import)",
+    )],
+    ..Page::new("fast-export", "Export history as a fast-import stream")
+};
+
+pub const FAST_IMPORT: Page = Page {
+    synopsis: &["grit fast-import [--force]"],
+    description: "\
+Read a `git fast-import` stream from standard input into the repository.
+Branches are only moved forward unless `--force` is given.",
+    examples: &[(
+        "Import history exported by another tool:",
+        "grit fast-import < history.stream",
+    )],
+    ..Page::new("fast-import", "Import history from a fast-import stream")
+};
+
+pub const FETCH: Page = Page {
+    synopsis: &[
+        "grit fetch [-p | --no-prune] [<remote>]",
+        "grit fetch [-p | --no-prune] [-j <n>] (--multiple <remote>... | --all)",
+    ],
+    description: "\
+Download objects and references from a remote, `origin` by default,
+updating the references its `remote.<name>.fetch` refspecs map them to.
+A refspec like `src:` downloads without storing a reference. All
+references are updated together or not at all.
+
+`-p` also deletes references mapped from remote references that no
+longer exist, like remote-tracking branches of deleted branches.
+
+`--multiple` fetches from each remote named, and `--all` from every remote,
+up to `-j` at a time. Only one fetch shows its output as it happens; the
+others' output follows once it finishes.",
+    examples: &[
+        ("Update remote-tracking branches:", "grit fetch"),
+        ("Also drop deleted branches:", "grit fetch --prune"),
+        (
+            "Update every remote, four at a time:",
+            "grit fetch --all -j 4",
+        ),
+    ],
+    config: concat!(
+        "CONFIGURATION:\n",
+        remote!(),
+        key!(
+            "remote.<name>.prune",
+            "Whether fetching from the remote prunes by default."
+        ),
+        key!(
+            "remote.<name>.mirror",
+            "Whether the remote is mirrored, which prunes by default."
+        ),
+        key!("fetch.prune", "Whether fetching prunes by default."),
+        key!(
+            "fetch.parallel",
+            "Remotes to fetch from at once, or one per core if 0; defaults to 1."
+        ),
+        gc!()
+    ),
+    ..Page::new("fetch", "Download objects and references from a remote")
+};
+
+pub const FILTER: Page = Page {
+    synopsis: &["grit filter [-f] [--path <path>... [--invert-paths]] [--replace-message <file>]"],
+    description: "\
+Rewrite every commit reachable from a reference, keeping only the given
+paths, or dropping them with `--invert-paths`, and replacing text in
+commit messages. Commits left empty are pruned.
+
+The old value of every rewritten reference is kept under
+`refs/original/`, and `grit filter` refuses to run again until that
+backup is deleted or `-f` is given to replace it.
+
+Only references are rewritten: run `grit reset --hard` afterwards to
+update the index and workspace.",
+    examples: &[
+        (
+            "Extract a subdirectory's history:",
+            "grit filter --path lib",
+        ),
+        (
+            "Purge a file from history:",
+            "grit filter --path secrets.txt --invert-paths",
+        ),
+    ],
+    ..Page::new("filter", "Rewrite history to keep or drop paths")
+};
+
+pub const FSCK: Page = Page {
+    synopsis: &["grit fsck [--no-dangling]"],
+    description: "\
+Check that every object hashes to its id and parses, and that every object
+that commits, trees, tags, references, reflogs, and the index point at
+exists and has the right type. Objects nothing points at are reported as
+dangling, which is harmless. Exits with an error if anything else is
+wrong.",
+    examples: &[
+        ("Check the repository:", "grit fsck"),
+        ("Only report damage:", "grit fsck --no-dangling"),
+    ],
+    ..Page::new("fsck", "Verify the integrity of the object database")
+};
+
+pub const GC: Page = Page {
+    synopsis: &["grit gc [--auto] [--prune=<date> | --no-prune]"],
+    description: "\
+Pack loose objects and combine packfiles. With `--auto`, only do so when
+there are more loose objects or packfiles than configured, which `commit`,
+`merge`, and `fetch` check after they finish.
+
+Old reflog entries are expired first. Objects that no reference, reflog,
+or the index can reach are then deleted once they're older than the prune
+date, and kept loose until then.",
+    examples: &[
+        ("Pack the repository:", "grit gc"),
+        (
+            "Delete every unreachable object right away:",
+            "grit gc --prune=now",
+        ),
+    ],
+    config: concat!(
+        "CONFIGURATION:\n",
+        gc!(),
+        key!(
+            "gc.pruneExpire",
+            "Age of unreachable objects to delete, or `never`."
+        ),
+        key!("gc.reflogExpire", "Age of reflog entries to expire."),
+        key!(
+            "gc.reflogExpireUnreachable",
+            "Age of reflog entries to expire if no longer in history."
+        ),
+    ),
+    ..Page::new("gc", "Pack objects to save space")
+};
+
+pub const GREP: Page = Page {
+    synopsis: &[
+        "grit grep [<options>] <pattern> [<revision>] [<path>...]",
+        "grit grep [<options>] --cached <pattern> [--] [<path>...]",
+    ],
+    description: "\
+Print lines of tracked files matching a regular expression, searching the
+workspace, the index with `--cached`, or the tree of a revision. Paths and
+globs limit the search; without them, only files under the current
+directory are searched. Large searches are split between threads. Exits
+with an error if nothing matches.",
+    examples: &[
+        (
+            "Find a function with line numbers:",
+            "grit grep -n 'fn main'",
+        ),
+        (
+            "List files mentioning a word in the last release:",
+            "grit grep -lw todo v1.0",
+        ),
+        (
+            "Count matches in staged Rust files:",
+            "grit grep -c --cached unwrap '*.rs'",
+        ),
+    ],
+    config: concat!(
+        "CONFIGURATION:\n",
+        key!("grep.threads", "Number of threads searching files.")
+    ),
+    ..Page::new("grep", "Search files for a pattern")
+};
+
+pub const HELP: Page = Page {
+    synopsis: &["grit help [<command>]"],
+    description: "\
+Show the manual page for a command, or list every command. Each command
+also accepts `--help`, which lists its flags.",
+    examples: &[("Read about commit:", "grit help commit")],
+    ..Page::new("help", "Show manual pages")
+};
+
+pub const INIT: Page = Page {
+    synopsis: &["grit init [<directory>]"],
+    description: "\
+Create an empty repository in the given directory, or the current one.",
+    examples: &[("Start a new project:", "grit init project")],
+    ..Page::new("init", "Create an empty repository")
+};
+
+pub const LOG: Page = Page {
+    synopsis: &[
+        "grit log [--oneline] [--stat | --name-only | --name-status] [-O <orderfile>] [--date=<format>] [<revision>]",
+        "grit log [--all] [--branches[=<glob>]] [--tags[=<glob>]] [--remotes[=<glob>]] [--glob <glob>]",
+    ],
+    description: "\
+Show commits reachable from `HEAD`, the given revision, or the selected
+references, newest first. Each commit can be followed by a summary of the
+files it changed. Dates are shown in the format given by `--date` or
+`log.date`: `default`, `local`, `iso`, `iso-strict`, `rfc`, `short`,
+`raw`, `unix`, or `relative`.",
+    examples: &[
+        ("Show one line per commit:", "grit log --oneline"),
+        ("Show how long ago each commit was made:", "grit log --date=relative"),
+        ("Show which files each commit touched:", "grit log --name-status"),
+        ("Show history of every branch:", "grit log --branches"),
+    ],
+    config: concat!("CONFIGURATION:\n", order_file!(), log_date!()),
+    ..Page::new("log", "Show commit history")
+};
+
+pub const LS_FILES: Page = Page {
+    synopsis: &["grit ls-files [-c] [-s] [-d] [-m] [-o [--exclude-standard]] [--sparse] [--debug]"],
+    description: "\
+List files in the index under the current directory, relative to it.
+Flags select deleted or modified files instead, or untracked files with
+`-o`, which `--exclude-standard` filters through `.gitignore`. With
+`--debug`, each file in the index is followed by its stat information and
+flags.",
+    examples: &[
+        ("List tracked files with their stages:", "grit ls-files -s"),
+        (
+            "List untracked files that aren't ignored:",
+            "grit ls-files -o --exclude-standard",
+        ),
+    ],
+    config: concat!(
+        "CONFIGURATION:\n",
+        check_stat!(),
+        key!(
+            "core.excludesFile",
+            "Ignore file applying to every repository."
+        ),
+    ),
+    ..Page::new("ls-files", "List files in the index and workspace")
+};
+
+pub const LS_TREE: Page = Page {
+    synopsis: &["grit ls-tree [-r] [-t] [--name-only] <tree-ish>"],
+    description: "\
+List the entries of a tree, or of a commit's root tree, with their mode,
+type, and id. With `-r`, subtrees are listed recursively.",
+    examples: &[(
+        "List every file at HEAD:",
+        "grit ls-tree -r --name-only HEAD",
+    )],
+    ..Page::new("ls-tree", "List the contents of a tree")
+};
+
+pub const MERGE: Page = Page {
+    synopsis: &["grit merge [-m <message>] <target>"],
+    description: "\
+Join another branch or commit into the current branch, fast-forwarding if
+possible and creating a merge commit otherwise. Conflicts are left in the
+index and workspace: resolve them, `grit add` the files, and
+`grit commit` to conclude the merge.",
+    examples: &[("Merge a topic branch:", "grit merge topic")],
+    config: concat!("CONFIGURATION:\n", identity!(), check_stat!(), gc!()),
+    ..Page::new("merge", "Join two histories together")
+};
+
+pub const MERGE_TREE: Page = Page {
+    synopsis: &[
+        "grit merge-tree [--name-only] [--no-messages] [--merge-base <base>] <ours> <theirs>",
+    ],
+    description: "\
+Merge two commits without touching the index or workspace, and print the
+id of the resulting tree. On conflicts, the conflicted files and messages
+describing the merge follow, and the exit status is 1.",
+    examples: &[(
+        "Check whether a branch merges cleanly:",
+        "grit merge-tree HEAD topic",
+    )],
+    ..Page::new("merge-tree", "Merge without touching the workspace")
+};
+
+pub const PACK_OBJECTS: Page = Page {
+    synopsis: &["grit pack-objects [--stdout]"],
+    description: "\
+Write a packfile containing the object ids read from standard input, one
+per line, into `.git/objects/pack` or to standard output.",
+    examples: &[(
+        "Pack every object reachable from HEAD:",
+        "grit rev-list --objects HEAD | cut -c1-40 | grit pack-objects",
+    )],
+    ..Page::new("pack-objects", "Create a packfile of objects")
+};
+
+pub const PUSH: Page = Page {
+    synopsis: &[
+        "grit push [-f] [--prune] [<remote> [<refspec>...]]",
+        "grit push --mirror [<remote>]",
+    ],
+    description: "\
+Update references on a remote, `origin` by default, sending the objects
+they need. A refspec like `main` pushes the local branch to the same
+name, `src:dst` to another, and `:dst` deletes the remote reference; a
+pattern like `refs/heads/*:refs/heads/*` pushes every matching reference.
+
+Updates that aren't fast-forwards are refused unless forced with `-f` or
+a `+` prefix. With `--prune`, remote references matched by a pattern
+whose local references no longer exist are deleted. `--mirror` makes every
+remote reference match the local one, and is the default for remotes with
+`remote.<name>.mirror`.",
+    examples: &[
+        ("Push the main branch:", "grit push origin main"),
+        ("Delete a remote branch:", "grit push origin :feature"),
+        (
+            "Push every branch:",
+            "grit push origin 'refs/heads/*:refs/heads/*'",
+        ),
+        (
+            "Mirror branches, deleting those removed locally:",
+            "grit push --prune origin 'refs/heads/*:refs/heads/*'",
+        ),
+    ],
+    config: concat!(
+        "CONFIGURATION:\n",
+        remote!(),
+        key!(
+            "remote.<name>.mirror",
+            "Whether pushing to the remote mirrors by default."
+        )
+    ),
+    ..Page::new("push", "Update remote references")
+};
+
+pub const REFLOG: Page = Page {
+    synopsis: &["grit reflog [<reference>]"],
+    description: "\
+Show where a reference, `HEAD` by default, has pointed over time, most
+recent first. Entries can be named in revisions as `<reference>@{<n>}`.",
+    examples: &[
+        ("Show recent positions of HEAD:", "grit reflog"),
+        ("Undo the last reset:", "grit reset --hard HEAD@{1}"),
+    ],
+    ..Page::new("reflog", "Show the history of a reference")
+};
+
+pub const REMOTE: Page = Page {
+    synopsis: &[
+        "grit remote show [<remote>]",
+        "grit remote prune [-n] [<remote>]",
+    ],
+    description: "\
+`show` lists a remote's URLs, its current branch, and its branches, noting
+which have remote-tracking branches and which remote-tracking branches are
+stale because their branches were deleted. It also lists the local
+branches set to merge with the remote's branches.
+
+`prune` deletes the stale remote-tracking branches, like `fetch --prune`
+without fetching. With `-n`, it only reports them.",
+    examples: &[
+        ("Describe the origin remote:", "grit remote show origin"),
+        (
+            "Drop remote-tracking branches of deleted branches:",
+            "grit remote prune origin",
+        ),
+    ],
+    config: concat!(
+        "CONFIGURATION:\n",
+        remote!(),
+        key!(
+            "remote.<name>.pushurl",
+            "URL to push to instead of the URL."
+        ),
+        key!(
+            "branch.<name>.merge",
+            "Remote branch that the local branch merges with."
+        )
+    ),
+    ..Page::new("remote", "Inspect and prune remotes")
+};
+
+pub const RESET: Page = Page {
+    synopsis: &["grit reset [--soft | --mixed | --hard] [<revision>]"],
+    description: "\
+Move the current branch to a revision, `HEAD` by default. The index is
+reset to match, unless `--soft` is given, and with `--hard`, the
+workspace too, discarding local changes.",
+    examples: &[
+        ("Unstage everything:", "grit reset"),
+        (
+            "Drop the last commit, keeping its changes staged:",
+            "grit reset --soft HEAD~1",
+        ),
+    ],
+    config: concat!("CONFIGURATION:\n", check_stat!(), workspace!()),
+    ..Page::new("reset", "Move the current branch")
+};
+
+pub const RESTORE: Page = Page {
+    synopsis: &["grit restore [-S] [-W] <path>..."],
+    description: "\
+Discard changes to files, restoring the workspace from the index, or the
+index from `HEAD` with `--staged`. Give both `--staged` and `--worktree`
+to restore both from `HEAD`.",
+    examples: &[
+        ("Discard unstaged edits:", "grit restore src/main.rs"),
+        ("Unstage a file:", "grit restore --staged src/main.rs"),
+    ],
+    ..Page::new("restore", "Restore files in the workspace or index")
+};
+
+pub const REV_LIST: Page = Page {
+    synopsis: &[
+        "grit rev-list [--count] [--objects] <revision>...",
+        "grit rev-list [--all] [--branches[=<glob>]] [--tags[=<glob>]] [--remotes[=<glob>]] [--glob <glob>]",
+    ],
+    description: "\
+List the ids of commits reachable from some revisions but not others,
+newest first. A revision prefixed with `^` is excluded, `a..b` means
+`^a b`, and `a...b` means commits reachable from either but not both.",
+    examples: &[
+        ("Count commits on a branch but not main:", "grit rev-list --count main..topic"),
+        ("List every reachable object:", "grit rev-list --objects --all"),
+    ],
+    ..Page::new("rev-list", "List commits in reverse chronological order")
+};
+
+pub const REV_PARSE: Page = Page {
+    synopsis: &[
+        "grit rev-parse [--verify] [--abbrev-ref] <revision>...",
+        "grit rev-parse [--git-dir] [--show-toplevel]",
+    ],
+    description: "\
+Resolve revisions to full object ids, or with `--abbrev-ref`, to the
+short names of the references they name. Also answers questions about
+the repository for scripts.",
+    examples: &[
+        (
+            "Print the current branch:",
+            "grit rev-parse --abbrev-ref HEAD",
+        ),
+        ("Resolve a revision:", "grit rev-parse --verify main~2"),
+    ],
+    config: concat!(
+        "CONFIGURATION:\n",
+        key!(
+            "branch.<name>.remote",
+            "Remote that `<name>@{upstream}` is on."
+        ),
+        key!(
+            "branch.<name>.merge",
+            "Remote branch that `<name>@{upstream}` tracks."
+        ),
+    ),
+    ..Page::new("rev-parse", "Resolve revisions and describe the repository")
+};
+
+pub const RM: Page = Page {
+    synopsis: &["grit rm [--cached] [-f] [-r] <path>..."],
+    description: "\
+Remove files from the index, and from the workspace unless `--cached` is
+given. Files with changes that would be lost are refused unless `-f` is
+given, and directories need `-r`.",
+    examples: &[
+        (
+            "Stop tracking a file but keep it:",
+            "grit rm --cached notes.txt",
+        ),
+        ("Remove a directory:", "grit rm -r build"),
+    ],
+    config: concat!("CONFIGURATION:\n", check_stat!()),
+    ..Page::new("rm", "Remove files from the workspace and index")
+};
+
+pub const SHOW: Page = Page {
+    synopsis: &[
+        "grit show [-O <orderfile>] [--ext-diff] [--date=<format>] [-M[<score>] | --no-renames] [<object>...]",
+    ],
+    description: "\
+Show objects, `HEAD` by default: commits with their changes, annotated
+tags followed by the object they point to, trees as lists of names, and
+blobs as their contents. With `--ext-diff`, changes to files configured to
+use an external diff program are shown by it, like `grit diff`.",
+    examples: &[
+        ("Show the last commit:", "grit show"),
+        (
+            "Print a file as of an older commit:",
+            "grit show HEAD~2:README.md",
+        ),
+    ],
+    config: concat!(
+        "CONFIGURATION:\n",
+        order_file!(),
+        renames!(),
+        external_diff!(),
+        log_date!(),
+    ),
+    ..Page::new("show", "Show objects")
+};
+
+pub const SHOW_BRANCH: Page = Page {
+    synopsis: &["grit show-branch [<revision>...]"],
+    description: "\
+Show commits on several branches, every local branch by default, until
+they meet. Each commit is listed with one column per branch, marked `*`
+for the current branch, `+` for other branches, and `-` for merges.",
+    examples: &[("Compare two branches:", "grit show-branch main topic")],
+    ..Page::new("show-branch", "Show branches and their commits")
+};
+
+pub const STASH: Page = Page {
+    synopsis: &[
+        "grit stash [push [-m <message>]]",
+        "grit stash (pop | drop) [<stash>]",
+        "grit stash list",
+    ],
+    description: "\
+Save local changes to the index and workspace away, resetting both to
+`HEAD`, and bring them back later with `pop`. The newest entry is
+`refs/stash`, and older ones are found through its reflog as
+`stash@{<n>}`.",
+    examples: &[
+        ("Set changes aside:", "grit stash -m \"Half-done refactor\""),
+        ("Bring them back:", "grit stash pop"),
+    ],
+    config: concat!("CONFIGURATION:\n", identity!(), check_stat!()),
+    ..Page::new("stash", "Set local changes aside")
+};
+
+pub const STATUS: Page = Page {
+    synopsis: &["grit status [--porcelain | -s | --long] [-M[<score>] | --no-renames]"],
+    description: "\
+Show staged, unstaged, untracked, and conflicted files, with paths
+relative to the current directory, and explain any merge, cherry-pick,
+revert, rebase, or bisect in progress.
+
+`--porcelain` prints a stable format for scripts, with paths relative to
+the workspace root. `-s` prints the same format in color, with paths
+relative to the current directory; `status.short` makes it the default,
+and `--long` overrides that.",
+    examples: &[
+        ("Show the status:", "grit status"),
+        (
+            "Detect renames of files at least 80% similar:",
+            "grit status -M80%",
+        ),
+    ],
+    config: concat!(
+        "CONFIGURATION:\n",
+        check_stat!(),
+        key!(
+            "status.short",
+            "Whether to use the short format by default."
+        ),
+        key!("status.renames", "Whether renamed files are detected."),
+        key!(
+            "status.renameLimit",
+            "Most files compared when detecting renames."
+        ),
+        key!("diff.renames", "Fallback for `status.renames`."),
+        key!("diff.renameLimit", "Fallback for `status.renameLimit`."),
+    ),
+    ..Page::new("status", "Show the workspace status")
+};
+
+pub const TAG: Page = Page {
+    synopsis: &[
+        "grit tag [-l] [<pattern>...]",
+        "grit tag [-f] [-a] [-m <message>] <name> [<revision>]",
+        "grit tag -d <name>...",
+    ],
+    description: "\
+List tags, create a lightweight tag pointing at a revision, `HEAD` by
+default, or an annotated tag object with `--annotate` or `--message`, or
+delete a tag. Existing tags are only replaced with `--force`.",
+    examples: &[
+        ("Tag a release:", "grit tag -m \"Version 1.0\" v1.0"),
+        ("List tags:", "grit tag"),
+    ],
+    config: concat!("CONFIGURATION:\n", identity!()),
+    ..Page::new("tag", "Create, list, or delete tags")
+};
+
+#[test]
+fn pages() {
+    assert!(PAGES.windows(2).all(|pair| pair[0].name < pair[1].name));
+    assert_eq!(find("commit").map(|page| page.name), Some("commit"));
+    assert!(find("frobnicate").is_none());
+
+    for page in PAGES {
+        assert!(!page.synopsis.is_empty(), "{}", page.name);
+        assert!(!page.description.is_empty(), "{}", page.name);
+        assert!(page.config.is_empty() || page.config.starts_with("CONFIGURATION:\n"));
+    }
+
+    let page = COMMIT.to_string();
+    assert!(page.starts_with("NAME:\n    grit-commit - Record changes to the repository\n"));
+    assert!(page.contains("\n    user.email\n"));
+}
@@ -0,0 +1,420 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path;
+
+use crate::config;
+use crate::platform::OsStrExt as _;
+
+/// Patterns that exclude untracked files, from `.gitignore` files and the
+/// repository- and user-wide exclude files.
+///
+/// A path is checked against the `.gitignore` in its own directory first,
+/// then those of each ancestor, then `.git/info/exclude`, and finally
+/// `core.excludesFile`. The last matching pattern in the first source with
+/// any match decides.
+///
+/// Like `git`, a path inside an excluded directory is always excluded,
+/// since `git` never looks inside the directory to find `!` patterns that
+/// would re-include it.
+#[derive(Clone, Debug, Default)]
+pub struct Ignore {
+    /// Patterns from `.gitignore` files, keyed by their relative directory.
+    directories: HashMap<path::PathBuf, Vec<Pattern>>,
+    /// Patterns from `.git/info/exclude`, then `core.excludesFile`.
+    excludes: Vec<Vec<Pattern>>,
+}
+
+impl Ignore {
+    /// Load the exclude files for the repository at `root`. Per-directory
+    /// `.gitignore` files are loaded separately by [`Ignore::load`] as the
+    /// workspace is walked.
+    pub fn standard(root: &path::Path, config: &config::Config) -> anyhow::Result<Self> {
+        let excludes_file = user_file(config, "core.excludesFile", "ignore");
+
+        let mut ignore = Ignore::default();
+        for path in Some(root.join(".git/info/exclude"))
+            .into_iter()
+            .chain(excludes_file)
+        {
+            let patterns = read(&path)?
+                .map(|text| Pattern::parse_all(&text, path::Path::new("")))
+                .unwrap_or_default();
+            ignore.excludes.push(patterns);
+        }
+        Ok(ignore)
+    }
+
+    /// Load the `.gitignore` in `directory`, relative to `root`, if any.
+    pub fn load(&mut self, root: &path::Path, directory: &path::Path) -> io::Result<()> {
+        if let Some(text) = read(&root.join(directory).join(".gitignore"))? {
+            self.directories.insert(
+                directory.to_path_buf(),
+                Pattern::parse_all(&text, directory),
+            );
+        }
+        Ok(())
+    }
+
+    /// Check whether `path`, relative to the workspace root, is excluded,
+    /// either directly or by one of its ancestors.
+    pub fn is_ignored(&self, path: &path::Path, is_directory: bool) -> bool {
+        path.ancestors()
+            .skip(1)
+            .take_while(|ancestor| *ancestor != path::Path::new(""))
+            .any(|ancestor| self.is_excluded(ancestor, true))
+            || self.is_excluded(path, is_directory)
+    }
+
+    /// Check whether the patterns exclude `path` itself.
+    fn is_excluded(&self, path: &path::Path, is_directory: bool) -> bool {
+        let directories = path
+            .ancestors()
+            .skip(1)
+            .filter_map(|directory| self.directories.get(directory));
+
+        directories
+            .chain(&self.excludes)
+            .find_map(|patterns| {
+                patterns
+                    .iter()
+                    .rev()
+                    .find(|pattern| pattern.matches(path, is_directory))
+            })
+            .is_some_and(|pattern| !pattern.negated)
+    }
+}
+
+/// Path of a user-wide file configured by `key`, expanding a leading `~/`,
+/// or else `$XDG_CONFIG_HOME/git/<name>`, defaulting to `~/.config/git/<name>`.
+pub(crate) fn user_file(config: &config::Config, key: &str, name: &str) -> Option<path::PathBuf> {
+    match config.get(key) {
+        Some(path) => match path.strip_prefix("~/") {
+            Some(rest) => env::var_os("HOME").map(|home| path::Path::new(&home).join(rest)),
+            None => Some(path::PathBuf::from(path)),
+        },
+        None => env::var_os("XDG_CONFIG_HOME")
+            .map(path::PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| path::Path::new(&home).join(".config")))
+            .map(|config| config.join("git").join(name)),
+    }
+}
+
+pub(crate) fn read(path: &path::Path) -> io::Result<Option<String>> {
+    match fs::read(path) {
+        Ok(bytes) => Ok(Some(String::from_utf8_lossy(&bytes).into_owned())),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+/// A single line of an ignore file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pattern {
+    glob: Vec<u8>,
+    /// Re-includes matching paths, written with a leading `!`.
+    negated: bool,
+    /// Only matches directories, written with a trailing `/`.
+    directory: bool,
+    /// Matches the path relative to `base` instead of any basename, when
+    /// written with a `/` anywhere but the end.
+    anchored: bool,
+    /// Directory of the file this pattern came from.
+    base: path::PathBuf,
+}
+
+impl Pattern {
+    /// Parse every pattern in the contents of an ignore file in `base`.
+    pub fn parse_all(text: &str, base: &path::Path) -> Vec<Self> {
+        text.lines()
+            .filter_map(|line| Pattern::parse(line, base))
+            .collect()
+    }
+
+    /// Parse a single line, which may be blank or a comment.
+    pub fn parse(line: &str, base: &path::Path) -> Option<Self> {
+        if line.starts_with('#') {
+            return None;
+        }
+
+        // Trailing spaces are ignored unless escaped.
+        let trimmed = line.trim_end_matches(' ');
+        let line = match trimmed.ends_with('\\') && trimmed.len() < line.len() {
+            true => &line[..trimmed.len() + 1],
+            false => trimmed,
+        };
+
+        let negated = line.starts_with('!');
+        let mut glob = match negated {
+            true => &line[1..],
+            false => line,
+        };
+
+        let directory = glob.ends_with('/');
+        glob = glob.trim_end_matches('/');
+        let anchored = glob.contains('/');
+        glob = glob.strip_prefix('/').unwrap_or(glob);
+
+        if glob.is_empty() {
+            return None;
+        }
+
+        Some(Pattern {
+            glob: glob.as_bytes().to_vec(),
+            negated,
+            directory,
+            anchored,
+            base: base.to_path_buf(),
+        })
+    }
+
+    /// Check whether this pattern matches `path`, relative to the workspace
+    /// root.
+    pub fn matches(&self, path: &path::Path, is_directory: bool) -> bool {
+        if self.directory && !is_directory {
+            return false;
+        }
+
+        let relative = match path.strip_prefix(&self.base) {
+            Ok(relative) => relative,
+            Err(_) => return false,
+        };
+
+        let text = match self.anchored {
+            true => relative.as_os_str(),
+            false => match relative.file_name() {
+                Some(name) => name,
+                None => return false,
+            },
+        };
+
+        wildmatch(&self.glob, text.as_bytes())
+    }
+}
+
+/// Convert a glob where `*` also matches `/`, as in `git` pathspecs, to
+/// one [`wildmatch`] understands.
+pub(crate) fn crossing(glob: &[u8]) -> Vec<u8> {
+    glob.iter()
+        .flat_map(|byte| match byte {
+            b'*' => &b"**"[..],
+            byte => std::slice::from_ref(byte),
+        })
+        .copied()
+        .collect()
+}
+
+/// Match `text` against a glob, where `*` and `?` do not match `/`, `**`
+/// matches across directories, and `[...]` matches a character class.
+pub(crate) fn wildmatch(glob: &[u8], text: &[u8]) -> bool {
+    match glob {
+        [] => text.is_empty(),
+        [b'*', b'*', b'/', rest @ ..] => {
+            // Matches zero or more leading directories.
+            wildmatch(rest, text)
+                || (0..text.len())
+                    .filter(|index| text[*index] == b'/')
+                    .any(|index| wildmatch(rest, &text[index + 1..]))
+        }
+        [b'*', b'*', rest @ ..] => (0..=text.len()).any(|index| wildmatch(rest, &text[index..])),
+        [b'*', rest @ ..] => (0..=text.len())
+            .take_while(|index| *index == 0 || text[index - 1] != b'/')
+            .any(|index| wildmatch(rest, &text[index..])),
+        [b'?', rest @ ..] => match text {
+            [char, text @ ..] if *char != b'/' => wildmatch(rest, text),
+            _ => false,
+        },
+        [b'[', class @ ..] => match (text, parse_class(class)) {
+            ([char, text @ ..], Some((matches, rest))) => {
+                *char != b'/' && matches(*char) && wildmatch(rest, text)
+            }
+            // An unterminated class is a literal `[`.
+            (_, None) => literal(b'[', class, text),
+            ([], Some(_)) => false,
+        },
+        [b'\\', escaped, rest @ ..] => literal(*escaped, rest, text),
+        [char, rest @ ..] => literal(*char, rest, text),
+    }
+}
+
+fn literal(char: u8, glob: &[u8], text: &[u8]) -> bool {
+    match text {
+        [first, text @ ..] if *first == char => wildmatch(glob, text),
+        _ => false,
+    }
+}
+
+/// Parse a character class after its opening `[`, returning a predicate
+/// and the rest of the glob after the closing `]`.
+fn parse_class(class: &[u8]) -> Option<(impl Fn(u8) -> bool, &[u8])> {
+    let (negated, class) = match class {
+        [b'!', rest @ ..] | [b'^', rest @ ..] => (true, rest),
+        _ => (false, class),
+    };
+
+    // A `]` right after the opening bracket is a literal member.
+    let start = match class.first() {
+        Some(b']') => 1,
+        _ => 0,
+    };
+    let end = start + class[start..].iter().position(|char| *char == b']')?;
+
+    let members = class[..end].to_vec();
+    let matches = move |char: u8| {
+        let mut index = 0;
+        let mut found = false;
+        while index < members.len() {
+            if index + 2 < members.len() && members[index + 1] == b'-' {
+                found |= members[index] <= char && char <= members[index + 2];
+                index += 3;
+            } else {
+                found |= members[index] == char;
+                index += 1;
+            }
+        }
+        found != negated
+    };
+    Some((matches, &class[end + 1..]))
+}
+
+#[test]
+fn patterns() {
+    let root = path::Path::new("");
+    let ignore = Ignore {
+        directories: vec![
+            (
+                root.to_path_buf(),
+                Pattern::parse_all("*.log\n!keep.log\nbuild/\n/top\n# comment\n", root),
+            ),
+            (
+                path::PathBuf::from("src"),
+                Pattern::parse_all("gen/**/*.rs\n[a-c]?.tmp\n", path::Path::new("src")),
+            ),
+        ]
+        .into_iter()
+        .collect(),
+        excludes: vec![Pattern::parse_all("secret\n", root)],
+    };
+
+    let check =
+        |path: &str, is_directory: bool| ignore.is_ignored(path::Path::new(path), is_directory);
+
+    assert!(check("debug.log", false));
+    assert!(check("a/b/debug.log", false));
+    assert!(!check("keep.log", false));
+    assert!(check("build", true));
+    assert!(!check("build", false));
+    assert!(check("top", false));
+    assert!(!check("a/top", false));
+    assert!(check("src/gen/x.rs", false));
+    assert!(check("src/gen/a/b/x.rs", false));
+    assert!(!check("gen/x.rs", false));
+    assert!(check("src/b1.tmp", false));
+    assert!(!check("src/d1.tmp", false));
+    assert!(check("deep/secret", false));
+    assert!(!check("main.rs", false));
+}
+
+#[test]
+fn precedence() {
+    let root = path::Path::new("");
+    let sub = path::Path::new("sub");
+    let ignore = Ignore {
+        directories: vec![
+            (
+                root.to_path_buf(),
+                Pattern::parse_all("*.log\n!keep.tmp\n", root),
+            ),
+            (
+                sub.to_path_buf(),
+                Pattern::parse_all("!important.log\n", sub),
+            ),
+        ]
+        .into_iter()
+        .collect(),
+        excludes: vec![
+            Pattern::parse_all("*.tmp\n!keep.bak\n", root),
+            Pattern::parse_all("*.bak\n*.log\n", root),
+        ],
+    };
+
+    let check = |path: &str| ignore.is_ignored(path::Path::new(path), false);
+
+    // Deeper `.gitignore` files override shallower ones.
+    assert!(check("important.log"));
+    assert!(!check("sub/important.log"));
+    assert!(check("sub/other.log"));
+
+    // `.gitignore` files override `.git/info/exclude`, which overrides
+    // `core.excludesFile`.
+    assert!(check("scratch.tmp"));
+    assert!(!check("keep.tmp"));
+    assert!(check("old.bak"));
+    assert!(!check("keep.bak"));
+}
+
+/// Cases adapted from `git help gitignore` and `git`'s own ignore tests.
+#[test]
+fn reinclude() {
+    let root = path::Path::new("");
+    let ignore = |text: &str| Ignore {
+        directories: vec![(root.to_path_buf(), Pattern::parse_all(text, root))]
+            .into_iter()
+            .collect(),
+        excludes: Vec::new(),
+    };
+    let check = |ignore: &Ignore, path: &str, is_directory: bool| {
+        ignore.is_ignored(path::Path::new(path), is_directory)
+    };
+
+    // A file can't be re-included if its directory is excluded...
+    let build = ignore("build/\n!build/keep.txt\n");
+    assert!(check(&build, "build", true));
+    assert!(check(&build, "build/keep.txt", false));
+
+    // ...but can be if only the directory's contents are.
+    let contents = ignore("build/*\n!build/keep.txt\n");
+    assert!(!check(&contents, "build", true));
+    assert!(check(&contents, "build/other.txt", false));
+    assert!(!check(&contents, "build/keep.txt", false));
+
+    // Exclude everything except `foo/bar`.
+    let only = ignore("/*\n!/foo\n/foo/*\n!/foo/bar\n");
+    assert!(check(&only, "top", false));
+    assert!(check(&only, "other", true));
+    assert!(check(&only, "other/file", false));
+    assert!(!check(&only, "foo", true));
+    assert!(check(&only, "foo/baz", false));
+    assert!(!check(&only, "foo/bar", true));
+    assert!(!check(&only, "foo/bar/file", false));
+
+    // A name without a slash excludes matching directories at any depth,
+    // along with everything inside them.
+    let name = ignore("target\n");
+    assert!(check(&name, "a/target", true));
+    assert!(check(&name, "a/target/debug/grit", false));
+    assert!(!check(&name, "a/targets/file", false));
+
+    // Directory-only patterns don't exclude files of the same name.
+    let frotz = ignore("doc/frotz/\nfrotz/\n");
+    assert!(check(&frotz, "doc/frotz", true));
+    assert!(check(&frotz, "a/frotz", true));
+    assert!(check(&frotz, "a/frotz/file", false));
+    assert!(!check(&frotz, "a/frotz", false));
+
+    // Leading, trailing, and middle `**`.
+    let double = ignore("**/foo\nabc/**\na/**/b\n");
+    assert!(check(&double, "x/y/foo", false));
+    assert!(check(&double, "abc/x/y", false));
+    assert!(!check(&double, "abc", false));
+    assert!(check(&double, "a/b", false));
+    assert!(check(&double, "a/x/y/b", false));
+
+    // Escaped special characters are literal.
+    let escaped = ignore("\\#hash\n\\!bang\ntrailing\\ \n");
+    assert!(check(&escaped, "#hash", false));
+    assert!(check(&escaped, "!bang", false));
+    assert!(check(&escaped, "trailing ", false));
+    assert!(!check(&escaped, "trailing", false));
+}
@@ -1,16 +1,17 @@
+use std::cell;
 use std::cmp;
 use std::collections::btree_map;
 use std::collections::BTreeMap;
 use std::collections::VecDeque;
 use std::convert::TryFrom as _;
 use std::ffi;
+use std::fs;
 use std::io;
 use std::io::Read as _;
-use std::io::Write as _;
 use std::ops;
-use std::os::unix::ffi::OsStrExt as _;
-use std::os::unix::ffi::OsStringExt as _;
 use std::path;
+use std::rc::Rc;
+use std::time;
 
 use anyhow::anyhow;
 use byteorder::BigEndian;
@@ -20,31 +21,110 @@ use byteorder::WriteBytesExt as _;
 use crate::file;
 use crate::meta;
 use crate::object;
+use crate::platform::OsStrExt as _;
+use crate::platform::OsStringExt as _;
 use crate::util;
 use crate::util::Tap as _;
 
+mod cache_tree;
+
+pub use cache_tree::CacheTree;
+
 pub struct Index {
-    lock: file::Checksum<file::WriteLock>,
+    storage: Storage,
+    version: u32,
     entries: BTreeMap<util::PathBuf, Entry>,
+    /// Unmerged entries for stages 1 (base), 2 (ours), and 3 (theirs).
+    conflicts: BTreeMap<util::PathBuf, Stages>,
+    /// Extensions read from the index file, in order, which are written
+    /// back byte-for-byte unless [`Extension::is_derived`] from the entries.
+    /// The `sdir` extension is recomputed on commit instead, and `TREE` is
+    /// written from `cache_tree`.
+    extensions: Vec<Extension>,
+    /// Tree ids of directories unchanged since they were last written,
+    /// from the `TREE` extension.
+    cache_tree: Option<CacheTree>,
+    /// When the index file was last committed, in whole seconds, from its
+    /// modification time.
+    timestamp: Option<u32>,
+    /// Sparse directories expanded since loading, innermost last.
+    expansions: Vec<Expansion>,
     changed: bool,
 }
 
+/// Unmerged versions of a single path, indexed by stage minus one.
+pub type Stages = [Option<Entry>; 3];
+
+/// A sparse directory entry that was expanded in memory, along with the
+/// entries it was replaced by. If those are untouched, it is collapsed
+/// again on commit.
+#[derive(Debug)]
+struct Expansion {
+    directory: Entry,
+    entries: Vec<Entry>,
+}
+
+/// An optional section after the entries of an index file, identified by a
+/// four-byte signature such as `TREE` for the cache tree.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Extension {
+    signature: [u8; 4],
+    data: Vec<u8>,
+}
+
+impl Extension {
+    pub fn new(signature: [u8; 4], data: Vec<u8>) -> Self {
+        Extension { signature, data }
+    }
+
+    pub fn signature(&self) -> &[u8; 4] {
+        &self.signature
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Whether readers that don't understand this extension may ignore it,
+    /// which `git` marks with an uppercase first letter in the signature.
+    pub fn is_optional(&self) -> bool {
+        self.signature[0].is_ascii_uppercase()
+    }
+
+    /// Whether this extension caches information about the entries or their
+    /// layout in the file, like the cache tree or the offset table, and so
+    /// is stale once the index is rewritten. Such extensions are dropped on
+    /// commit rather than written back.
+    pub fn is_derived(&self) -> bool {
+        DERIVED_EXTENSIONS.contains(&&self.signature)
+    }
+}
+
 impl Index {
+    const MIN_VERSION: u32 = 2;
+    const MAX_VERSION: u32 = 4;
+    const DEFAULT_VERSION: u32 = 2;
+
     pub fn lock(path: path::PathBuf) -> anyhow::Result<Self> {
-        let lock = file::WriteLock::new(path)?;
+        let lock = file::WriteLock::new(path.clone())?;
+        let mut timestamp = None;
 
-        let (entries, lock) = match lock.upgrade()? {
-            file::Lock::Write(lock) => (BTreeMap::new(), file::Checksum::new(lock)),
+        let ((version, entries, conflicts, extensions), lock) = match lock.upgrade()? {
+            file::Lock::Write(lock) => (
+                (
+                    Self::DEFAULT_VERSION,
+                    BTreeMap::new(),
+                    BTreeMap::new(),
+                    Vec::new(),
+                ),
+                file::Checksum::new(lock),
+            ),
             file::Lock::ReadWrite(mut lock) => {
                 let mut buffer = Vec::new();
                 lock.read_to_end(&mut buffer)?;
+                timestamp = Some(meta::Metadata::from(fs::metadata(&path)?).mtime);
 
                 let entries = Self::read(&buffer)?;
-                let checksum = buffer.len() - 20;
-                let actual = sha1::Sha1::from(&buffer[..checksum]).digest().bytes();
-                let expected = &buffer[checksum..];
-                assert_eq!(actual, expected);
-
                 let lock = lock
                     .tap(file::ReadWriteLock::downgrade)
                     .tap(file::Checksum::new);
@@ -53,13 +133,90 @@ impl Index {
         };
 
         Ok(Index {
-            lock,
+            storage: Storage::File(lock),
+            version,
             entries,
+            conflicts,
+            cache_tree: read_cache_tree(&extensions),
+            extensions,
+            timestamp,
+            expansions: Vec::new(),
             changed: false,
         })
     }
 
-    fn read(buffer: &[u8]) -> anyhow::Result<BTreeMap<util::PathBuf, Entry>> {
+    /// Load an index from the shared in-memory `buffer`, which is empty for a
+    /// new index. Committing writes back to the same buffer.
+    pub fn memory(buffer: Rc<cell::RefCell<Vec<u8>>>) -> anyhow::Result<Self> {
+        let (version, entries, conflicts, extensions) = match buffer.borrow().as_slice() {
+            [] => (
+                Self::DEFAULT_VERSION,
+                BTreeMap::new(),
+                BTreeMap::new(),
+                Vec::new(),
+            ),
+            bytes => Self::read(bytes)?,
+        };
+
+        Ok(Index {
+            storage: Storage::Memory(buffer),
+            version,
+            entries,
+            conflicts,
+            cache_tree: read_cache_tree(&extensions),
+            extensions,
+            timestamp: None,
+            expansions: Vec::new(),
+            changed: false,
+        })
+    }
+
+    /// When the index file was last committed, or `None` for a new or
+    /// in-memory index, to check entries with [`Entry::is_racy`].
+    pub fn timestamp(&self) -> Option<u32> {
+        self.timestamp
+    }
+
+    /// Format version of the index file, which is preserved on commit.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Change the format version written on commit, e.g. from `index.version`.
+    /// Version 4 compresses each path against the previous one.
+    pub fn set_version(&mut self, version: u32) -> anyhow::Result<()> {
+        if !(Self::MIN_VERSION..=Self::MAX_VERSION).contains(&version) {
+            return Err(anyhow!("Unsupported index version: {}", version));
+        }
+        self.changed |= self.version != version;
+        self.version = version;
+        Ok(())
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn read(
+        buffer: &[u8],
+    ) -> anyhow::Result<(
+        u32,
+        BTreeMap<util::PathBuf, Entry>,
+        BTreeMap<util::PathBuf, Stages>,
+        Vec<Extension>,
+    )> {
+        let checksum = buffer
+            .len()
+            .checked_sub(20)
+            .filter(|checksum| *checksum >= 12)
+            .ok_or_else(|| anyhow!("Index is truncated at {} bytes", buffer.len()))?;
+
+        // With `index.skipHash`, `git` leaves the checksum as all zeros.
+        let expected = &buffer[checksum..];
+        if expected != [0; 20] {
+            let actual = sha1::Sha1::from(&buffer[..checksum]).digest().bytes();
+            if actual != expected {
+                return Err(anyhow!("Index checksum mismatch"));
+            }
+        }
+
         let signature = &buffer[0..4];
         if signature != b"DIRC" {
             return Err(anyhow!(
@@ -69,8 +226,13 @@ impl Index {
         }
 
         let version = <[u8; 4]>::try_from(&buffer[4..8]).map(u32::from_be_bytes)?;
-        if version != 2 {
-            return Err(anyhow!("Expected version 2, but found version {}", version));
+        if !(Self::MIN_VERSION..=Self::MAX_VERSION).contains(&version) {
+            return Err(anyhow!(
+                "Expected version {} through {}, but found version {}",
+                Self::MIN_VERSION,
+                Self::MAX_VERSION,
+                version,
+            ));
         }
 
         let count = <[u8; 4]>::try_from(&buffer[8..12])
@@ -78,22 +240,94 @@ impl Index {
             .map(usize::try_from)??;
 
         let mut entries = BTreeMap::new();
-        let mut cursor = io::Cursor::new(&buffer[12..]);
+        let mut conflicts = BTreeMap::<_, Stages>::new();
+        let mut cursor = io::Cursor::new(&buffer[12..checksum]);
+        let mut previous = path::PathBuf::new();
         for _ in 0..count {
-            let entry = Entry::read(&mut cursor)?;
+            let entry = Entry::read(&mut cursor, version, &previous)?;
+            previous = entry.path.to_path_buf();
             let key = entry.path.to_path_buf().tap(util::PathBuf);
-            entries.insert(key, entry);
+            match entry.stage() {
+                0 => {
+                    entries.insert(key, entry);
+                }
+                stage => {
+                    conflicts.entry(key).or_default()[stage as usize - 1] = Some(entry);
+                }
+            }
+        }
+
+        let mut extensions = Vec::new();
+        while (cursor.position() as usize) < cursor.get_ref().len() {
+            let mut signature = [0; 4];
+            cursor.read_exact(&mut signature)?;
+            let len = cursor.read_u32::<BigEndian>()?;
+            let mut data = Vec::new();
+            cursor.by_ref().take(len as u64).read_to_end(&mut data)?;
+            if data.len() != len as usize {
+                return Err(anyhow!(
+                    "Truncated `{}` extension in index",
+                    String::from_utf8_lossy(&signature),
+                ));
+            }
+            let extension = Extension { signature, data };
+            if !extension.is_optional() && extension.signature() != SPARSE_DIRECTORIES {
+                return Err(anyhow!(
+                    "Index uses `{}` extension, which is not supported",
+                    String::from_utf8_lossy(&signature),
+                ));
+            }
+            extensions.push(extension);
+        }
+
+        Ok((version, entries, conflicts, extensions))
+    }
+
+    /// Extensions read from the index file, in order.
+    pub fn extensions(&self) -> &[Extension] {
+        &self.extensions
+    }
+
+    /// The extension with signature `signature`, if there is one.
+    pub fn extension(&self, signature: &[u8; 4]) -> Option<&Extension> {
+        self.extensions
+            .iter()
+            .find(|extension| extension.signature() == signature)
+    }
+
+    /// Replace any extension with the same signature as `extension`, or
+    /// append it after the others.
+    pub fn set_extension(&mut self, extension: Extension) {
+        match self
+            .extensions
+            .iter_mut()
+            .find(|existing| existing.signature == extension.signature)
+        {
+            Some(existing) if *existing == extension => return,
+            Some(existing) => *existing = extension,
+            None => self.extensions.push(extension),
         }
+        self.changed = true;
+    }
 
-        Ok(entries)
+    /// Remove the extension with signature `signature`, returning it.
+    pub fn remove_extension(&mut self, signature: &[u8; 4]) -> Option<Extension> {
+        let index = self
+            .extensions
+            .iter()
+            .position(|extension| extension.signature() == signature)?;
+        self.changed = true;
+        Some(self.extensions.remove(index))
     }
 
     pub fn contains(&self, path: &path::Path) -> bool {
         self.contains_file(path) || self.contains_directory(path)
     }
 
+    /// Whether `path` is a tracked file, including unmerged files.
     pub fn contains_file(&self, path: &path::Path) -> bool {
         self.entries.contains_key(&path as &dyn util::Key)
+            || self.conflicts.contains_key(&path as &dyn util::Key)
     }
 
     pub fn contains_directory(&self, path: &path::Path) -> bool {
@@ -104,18 +338,211 @@ impl Index {
         self.entries.get(&path as &dyn util::Key)
     }
 
-    pub fn entries_mut(&mut self) -> impl Iterator<Item = &mut Entry> {
-        self.entries.values_mut()
+    /// Whether any paths are unmerged.
+    pub fn is_conflicted(&self) -> bool {
+        !self.conflicts.is_empty()
+    }
+
+    /// Iterate over unmerged paths and their stages, in sorted order.
+    pub fn conflicts(&self) -> impl Iterator<Item = (&path::Path, &Stages)> {
+        self.conflicts
+            .iter()
+            .map(|(util::PathBuf(path), stages)| (path.as_path(), stages))
+    }
+
+    /// Replace any entry for `path` with unmerged stages, given as the base,
+    /// ours, and theirs versions of the file.
+    pub fn insert_conflict(
+        &mut self,
+        path: path::PathBuf,
+        stages: [Option<(object::Id, meta::Mode)>; 3],
+    ) {
+        self.entries.remove(&path.as_path() as &dyn util::Key);
+
+        let mut conflict = Stages::default();
+        for (stage, (slot, version)) in conflict.iter_mut().zip(stages).enumerate() {
+            *slot = version.map(|(id, mode)| Entry::unmerged(mode, id, path.clone(), stage + 1));
+        }
+
+        self.invalidate(&path);
+        self.conflicts.insert(util::PathBuf(path), conflict);
+        self.changed = true;
+    }
+
+    /// Tree id of `directory` if none of its entries have changed since the
+    /// index was last written as trees, e.g. to skip comparing it with a
+    /// tree of the same id.
+    pub fn cached_tree(&self, directory: &path::Path) -> Option<&object::Id> {
+        self.cache_tree.as_ref()?.get(directory)
+    }
+
+    /// Ids of every cached tree that is still valid.
+    pub fn cached_trees(&self) -> Vec<object::Id> {
+        self.cache_tree
+            .as_ref()
+            .map(CacheTree::ids)
+            .unwrap_or_default()
+    }
+
+    /// Forget the cached trees of every directory containing `path`.
+    fn invalidate(&mut self, path: &path::Path) {
+        if let Some(cache_tree) = &mut self.cache_tree {
+            cache_tree.invalidate(path);
+        }
+    }
+
+    /// Whether any directories are collapsed into sparse directory entries.
+    pub fn is_sparse(&self) -> bool {
+        self.entries.values().any(Entry::is_sparse_directory)
+    }
+
+    /// Replace every sparse directory entry with the files in its tree,
+    /// marked skip-worktree, for commands that need a full index.
+    pub fn expand(&mut self, database: &crate::Database) -> anyhow::Result<()> {
+        let directories = self
+            .entries
+            .values()
+            .filter(|entry| entry.is_sparse_directory())
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for directory in directories {
+            let mut entries = Vec::new();
+            for (path, file) in crate::diff::tree::flatten(database, &directory.id)? {
+                // Reading every blob's size would defeat the purpose of a
+                // sparse index, and these files aren't in the workspace.
+                let metadata = meta::Metadata::unknown(file.mode, 0);
+                let mut entry = Entry::new(metadata, file.id, directory.path.join(&*path));
+                entry.set_skip_worktree();
+                entries.push(entry);
+            }
+            self.replace(directory, entries);
+        }
+        Ok(())
+    }
+
+    /// Expand only the sparse directories containing `path`, one level at a
+    /// time, so that it can be looked up or modified while the rest of the
+    /// index stays collapsed. Callers working with a sparse index must do
+    /// this before inserting or removing `path`.
+    pub fn expand_to(
+        &mut self,
+        database: &crate::Database,
+        path: &path::Path,
+    ) -> anyhow::Result<()> {
+        while let Some(directory) = path
+            .ancestors()
+            .take_while(|ancestor| *ancestor != path::Path::new(""))
+            .find_map(|ancestor| self.entries.get(&sparse_key(ancestor)))
+            .cloned()
+        {
+            let mut entries = Vec::new();
+            for node in &database.load_tree(&directory.id)? {
+                let path = directory.path.join(&node.path);
+                let entry = match node.mode {
+                    meta::Mode::Directory => Entry::sparse(&path, node.id),
+                    mode => {
+                        let mut entry = Entry::new(meta::Metadata::unknown(mode, 0), node.id, path);
+                        entry.set_skip_worktree();
+                        entry
+                    }
+                };
+                entries.push(entry);
+            }
+            self.replace(directory, entries);
+        }
+        Ok(())
     }
 
-    // TODO: is it possible to keep this hidden?
-    pub fn touch(&mut self) {
+    /// Replace every entry below `directory` with a single sparse directory
+    /// entry for their tree, which is stored in `database`. Its files are
+    /// expected to be missing from the workspace, e.g. because they are
+    /// outside the sparse-checkout cone.
+    pub fn collapse(
+        &mut self,
+        database: &crate::Database,
+        directory: &path::Path,
+    ) -> anyhow::Result<()> {
+        if let Some((util::PathBuf(path), _)) = self
+            .conflicts
+            .iter()
+            .find(|(util::PathBuf(path), _)| path.starts_with(directory))
+        {
+            return Err(anyhow!(
+                "Cannot collapse directory with unmerged path: {}",
+                path.display()
+            ));
+        }
+
+        let files = self
+            .remove(directory)
+            .into_iter()
+            .filter_map(|entry| {
+                let path = entry.path.strip_prefix(directory).ok()?.to_path_buf();
+                let file = crate::diff::tree::Entry {
+                    id: entry.id,
+                    mode: entry.metadata.mode,
+                };
+                Some((util::PathBuf(path), file))
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        if files.is_empty() {
+            return Err(anyhow!("No tracked files under {}", directory.display()));
+        }
+
+        let tree = crate::diff::tree::unflatten(database, &files)?;
+        let entry = Entry::sparse(directory, tree);
+        self.entries
+            .insert(util::PathBuf(entry.path.clone()), entry);
         self.changed = true;
+        Ok(())
+    }
+
+    /// Swap sparse directory entry `directory` for `entries`, remembering
+    /// how to undo it.
+    fn replace(&mut self, directory: Entry, entries: Vec<Entry>) {
+        self.entries.remove(&directory.path() as &dyn util::Key);
+        for entry in &entries {
+            self.entries
+                .insert(util::PathBuf(entry.path.clone()), entry.clone());
+        }
+        self.expansions.push(Expansion { directory, entries });
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &Entry> {
+        self.entries.values()
+    }
+
+    /// Iterate over the entries below `directory`, in sorted order.
+    pub fn entries_under<'a>(
+        &'a self,
+        directory: &'a path::Path,
+    ) -> impl Iterator<Item = &'a Entry> {
+        self.descendants(directory)
+            .filter_map(move |path| self.entries.get(&path as &dyn util::Key))
+    }
+
+    /// Refresh the stat information of the entry at `path`, e.g. after
+    /// finding that its file's contents still match despite a changed stat,
+    /// so that it doesn't need to be hashed again. Returns whether there was
+    /// such an entry.
+    pub fn update_metadata(&mut self, path: &path::Path, metadata: meta::Metadata) -> bool {
+        match self.entries.get_mut(&path as &dyn util::Key) {
+            None => false,
+            Some(entry) => {
+                self.changed |= entry.metadata != metadata;
+                entry.touch(metadata);
+                true
+            }
+        }
     }
 
     pub fn insert(&mut self, metadata: meta::Metadata, id: object::Id, path: path::PathBuf) {
         let entry = Entry::new(metadata, id, path);
 
+        let mut changed = false;
+
         entry
             .path()
             .ancestors()
@@ -123,6 +550,7 @@ impl Index {
             .take_while(|ancestor| *ancestor != path::Path::new(""))
             .filter_map(|ancestor| self.entries.remove(&ancestor as &dyn util::Key))
             .for_each(|entry| {
+                changed = true;
                 log::debug!("Removing conflicting ancestor: {}", entry.path().display())
             });
 
@@ -135,19 +563,102 @@ impl Index {
             .into_iter()
             .filter_map(|descendant| self.entries.remove(&descendant as &dyn util::Key))
             .for_each(|entry| {
+                changed = true;
                 log::debug!(
                     "Removing conflicting descendant: {}",
                     entry.path().display(),
                 )
             });
 
+        // Staging a file resolves its conflict.
+        changed |= self
+            .conflicts
+            .remove(&entry.path() as &dyn util::Key)
+            .is_some();
+
         let key = entry.path().to_path_buf().tap(util::PathBuf);
-        self.changed |= self.entries.insert(key, entry).is_none();
+        let previous = self.entries.insert(key, entry.clone());
+        if changed
+            || previous
+                .as_ref()
+                .map(|previous| (previous.id, previous.metadata.mode))
+                != Some((entry.id, entry.metadata.mode))
+        {
+            self.invalidate(entry.path());
+        }
+        self.changed |= changed || previous.as_ref() != Some(&entry);
+    }
+
+    /// Overwrite the entry for `path` with a file of `size` bytes from a
+    /// tree. Its stat information is unknown, so the workspace file will be
+    /// rehashed the next time they are compared.
+    pub fn reset(&mut self, path: path::PathBuf, id: object::Id, mode: meta::Mode, size: u32) {
+        self.insert(meta::Metadata::unknown(mode, size), id, path);
+    }
+
+    /// Replace every entry, including unmerged ones, with the files in tree
+    /// `tree`. Entries whose contents and mode are unchanged keep their stat
+    /// information, so they are not rehashed.
+    pub fn load_tree(
+        &mut self,
+        database: &crate::Database,
+        tree: &object::Id,
+    ) -> anyhow::Result<()> {
+        let files = crate::diff::tree::flatten(database, tree)?;
+        let mut entries = BTreeMap::new();
+
+        for (path, file) in files {
+            let entry = match self.entries.remove(&path) {
+                Some(entry) if *entry.id() == file.id && *entry.metadata().mode() == file.mode => {
+                    entry
+                }
+                _ => {
+                    self.changed = true;
+                    let size = database.read_header(&file.id)?.len;
+                    let metadata = meta::Metadata::unknown(file.mode, size as u32);
+                    Entry::new(metadata, file.id, path.to_path_buf())
+                }
+            };
+            entries.insert(path, entry);
+        }
+
+        // Anything left over is not in the tree.
+        self.changed |= !self.entries.is_empty() || !self.conflicts.is_empty();
+        self.entries = entries;
+        self.conflicts.clear();
+        self.cache_tree = None;
+        Ok(())
+    }
+
+    /// Remove the entry for file `path`, or all entries below directory
+    /// `path`, returning the removed entries in index order.
+    pub fn remove(&mut self, path: &path::Path) -> Vec<Entry> {
+        let removed = self
+            .entries
+            .remove(&path as &dyn util::Key)
+            .into_iter()
+            .chain(
+                self.descendants(path)
+                    .map(path::PathBuf::from)
+                    .map(util::PathBuf)
+                    .collect::<Vec<_>>()
+                    .into_iter()
+                    .filter_map(|descendant| self.entries.remove(&descendant as &dyn util::Key)),
+            )
+            .collect::<Vec<_>>();
+        let conflicts = self.conflicts.len();
+        self.conflicts
+            .retain(|util::PathBuf(conflict), _| !conflict.starts_with(path));
+        if !removed.is_empty() || conflicts != self.conflicts.len() {
+            self.invalidate(path);
+            self.changed = true;
+        }
+        removed
     }
 
     /// If `path` is a directory, then return all existing index entries
     /// below it in the directory tree, exclduing `path` itself.
-    fn descendants<'a>(&'a self, path: &'a path::Path) -> impl Iterator<Item = &path::Path> {
+    fn descendants<'a>(&'a self, path: &'a path::Path) -> impl Iterator<Item = &'a path::Path> {
         self.entries
             // We exclude the lower bound here instead of using a symmetric
             // `.skip(1)` because `path` may or may not be in the index.
@@ -178,27 +689,238 @@ impl Index {
             .map(|(_, entry)| entry.path())
     }
 
+    /// Store the tree objects described by this index in `database`,
+    /// returning the id of the root tree.
+    ///
+    /// Directories with a cached tree id aren't stored again. The ids of the
+    /// others are cached, to be written with the index on commit.
+    pub fn write_tree(&mut self, database: &crate::Database) -> anyhow::Result<object::Id> {
+        if let Some((path, _)) = self.conflicts().next() {
+            return Err(anyhow!(
+                "Cannot write tree with unmerged path: {}",
+                path.display()
+            ));
+        }
+
+        let mut stack = Vec::new();
+        let mut count = Vec::new();
+        // Number of entries below each directory on the stack, like `count`.
+        let mut files = Vec::new();
+        let mut cache_tree = CacheTree::default();
+
+        for node in &*self {
+            let path = node.path();
+            let depth = path.components().count();
+            let name = path
+                .file_name()
+                .unwrap_or_default()
+                .to_os_string()
+                .tap(path::PathBuf::from);
+
+            let (id, total) = match node {
+                Node::File(entry) => {
+                    count.resize(depth, 0);
+                    files.resize(depth, 0);
+                    (*entry.id(), 1)
+                }
+                Node::Directory(_) => {
+                    count.resize(depth + 1, 0);
+                    files.resize(depth + 1, 0);
+                    let total = files.pop().unwrap_or_default();
+                    let index = match count.pop() {
+                        None => unreachable!(),
+                        Some(0) => continue,
+                        Some(count) => stack.len() - count,
+                    };
+                    let children = stack.split_off(index);
+                    let id = match self.cached_tree(path) {
+                        Some(id) => *id,
+                        None => children
+                            .tap(object::tree::Root::new)
+                            .tap(crate::Object::Tree)
+                            .tap(|tree| database.store(&tree))?,
+                    };
+                    cache_tree.insert(path, total, id);
+                    (id, total)
+                }
+            };
+
+            let mode = node.mode();
+            let node = object::tree::Node::new(name, id, *mode);
+
+            stack.push(node);
+
+            match count.last_mut() {
+                None if path == path::Path::new("") => (),
+                None => unreachable!(),
+                Some(count) => *count += 1,
+            }
+            if let Some(files) = files.last_mut() {
+                *files += total;
+            }
+        }
+
+        if self.cache_tree.as_ref() != Some(&cache_tree) {
+            self.cache_tree = Some(cache_tree);
+            self.changed = true;
+        }
+
+        let tree_id = stack
+            .pop()
+            .expect("[INTERNAL ERROR]: index must contain at least root directory")
+            .id;
+
+        Ok(tree_id)
+    }
+
     pub fn commit(mut self) -> io::Result<()> {
         if !self.changed {
             return Ok(());
         }
 
-        let len = self
+        // Collapse expanded directories whose entries are untouched.
+        for Expansion { directory, entries } in self.expansions.iter().rev() {
+            let conflicted = self
+                .conflicts
+                .keys()
+                .any(|util::PathBuf(path)| path.starts_with(directory.path()));
+            let current = self
+                .descendants(directory.path())
+                .filter_map(|path| self.entries.get(&path as &dyn util::Key))
+                .collect::<Vec<_>>();
+            let untouched = current.len() == entries.len()
+                && current.iter().zip(entries).all(|(current, entry)| {
+                    (
+                        &current.path,
+                        current.id,
+                        current.metadata.mode,
+                        current.extended,
+                    ) == (&entry.path, entry.id, entry.metadata.mode, entry.extended)
+                });
+            if conflicted || !untouched {
+                continue;
+            }
+
+            for entry in entries {
+                self.entries.remove(&entry.path() as &dyn util::Key);
+            }
+            self.entries
+                .insert(util::PathBuf(directory.path.clone()), directory.clone());
+        }
+
+        // Like `git`, smudge entries that are racily clean with respect to
+        // this write by zeroing their size, so that they are still compared
+        // by content once a later write moves the timestamp past them.
+        let now = time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs() as u32);
+        for entry in self.entries.values_mut() {
+            if entry.is_racy(Some(now)) {
+                entry.metadata.size = 0;
+            }
+        }
+
+        // Unmerged stages sort after any stage 0 entry for the same path, but
+        // a path is never both merged and unmerged.
+        let mut entries = self
             .entries
+            .values()
+            .chain(self.conflicts.values().flatten().flatten())
+            .collect::<Vec<_>>();
+        entries.sort_by(|a, b| {
+            (a.path.as_os_str().as_bytes(), a.stage())
+                .cmp(&(b.path.as_os_str().as_bytes(), b.stage()))
+        });
+
+        let len = entries
             .len()
             .tap(u32::try_from)
             .expect("[INTERNAL ERROR]: more than 2^32 - 1 entries");
 
-        self.lock.write_all(b"DIRC")?;
-        self.lock.write_u32::<BigEndian>(2)?;
-        self.lock.write_u32::<BigEndian>(len)?;
-        for entry in self.entries.values() {
-            entry.write(&mut self.lock)?;
+        let extensions = self
+            .extensions
+            .iter()
+            .filter(|extension| !extension.is_derived())
+            .filter(|extension| extension.signature() != SPARSE_DIRECTORIES)
+            .collect::<Vec<_>>();
+
+        match self.storage {
+            Storage::File(mut lock) => {
+                Self::write(
+                    &entries,
+                    self.cache_tree.as_ref(),
+                    &extensions,
+                    self.version,
+                    len,
+                    &mut lock,
+                )?;
+                lock.write_checksum()?.commit()
+            }
+            Storage::Memory(buffer) => {
+                let mut writer = file::Checksum::new(Vec::new());
+                Self::write(
+                    &entries,
+                    self.cache_tree.as_ref(),
+                    &extensions,
+                    self.version,
+                    len,
+                    &mut writer,
+                )?;
+                *buffer.borrow_mut() = writer.write_checksum()?;
+                Ok(())
+            }
+        }
+    }
+
+    fn write<W: io::Write>(
+        entries: &[&Entry],
+        cache_tree: Option<&CacheTree>,
+        extensions: &[&Extension],
+        version: u32,
+        len: u32,
+        writer: &mut W,
+    ) -> io::Result<()> {
+        // Extended flags require at least version 3.
+        let version = match entries.iter().any(|entry| entry.extended.is_some()) {
+            true => cmp::max(version, 3),
+            false => version,
+        };
+
+        writer.write_all(b"DIRC")?;
+        writer.write_u32::<BigEndian>(version)?;
+        writer.write_u32::<BigEndian>(len)?;
+        let mut previous = path::Path::new("");
+        for entry in entries {
+            entry.write(writer, version, previous)?;
+            previous = entry.path();
+        }
+
+        let cache_tree = cache_tree.map(|cache_tree| Extension {
+            signature: *CacheTree::SIGNATURE,
+            data: cache_tree.write(),
+        });
+        for extension in cache_tree.iter().chain(extensions.iter().copied()) {
+            writer.write_all(&extension.signature)?;
+            writer.write_u32::<BigEndian>(extension.data.len() as u32)?;
+            writer.write_all(&extension.data)?;
+        }
+
+        // An empty `sdir` extension tells readers to expect sparse
+        // directory entries.
+        if entries.iter().any(|entry| entry.is_sparse_directory()) {
+            writer.write_all(SPARSE_DIRECTORIES)?;
+            writer.write_u32::<BigEndian>(0)?;
         }
-        self.lock.write_checksum()?.commit()
+        Ok(())
     }
 }
 
+/// Backing storage for the serialized index.
+enum Storage {
+    File(file::Checksum<file::WriteLock>),
+    Memory(Rc<cell::RefCell<Vec<u8>>>),
+}
+
 impl<'a> IntoIterator for &'a Index {
     type IntoIter = Iter<'a>;
     type Item = Node<'a>;
@@ -318,7 +1040,7 @@ impl<'a> Iterator for Iter<'a> {
         prev.path
             .ancestors()
             .skip(1)
-            .take_while(|ancestor| next.map_or(true, |next| !next.path.starts_with(ancestor)))
+            .take_while(|ancestor| next.is_none_or(|next| !next.path.starts_with(ancestor)))
             .for_each(|ancestor| self.queue.push_back(ancestor));
 
         self.state = next.map(State::Yield);
@@ -331,6 +1053,8 @@ pub struct Entry {
     metadata: meta::Metadata,
     id: object::Id,
     flag: u16,
+    /// Additional flags (e.g. `skip-worktree`), present in version 3 and later.
+    extended: Option<u16>,
     path: path::PathBuf,
 }
 
@@ -341,18 +1065,74 @@ impl Entry {
             metadata,
             id,
             flag,
+            extended: None,
             path,
         }
     }
 
+    /// Create a sparse directory entry standing in for every file in tree
+    /// `tree`, whose path is `directory` with a trailing slash.
+    fn sparse(directory: &path::Path, tree: object::Id) -> Self {
+        let mut path = directory.as_os_str().as_bytes().to_vec();
+        path.push(b'/');
+        let path = path.tap(ffi::OsString::from_vec).tap(path::PathBuf::from);
+        let mut entry = Entry::new(
+            meta::Metadata::unknown(meta::Mode::Directory, 0),
+            tree,
+            path,
+        );
+        entry.set_skip_worktree();
+        entry
+    }
+
+    /// Create an entry for one side of a conflict, with no stat information.
+    fn unmerged(mode: meta::Mode, id: object::Id, path: path::PathBuf, stage: usize) -> Self {
+        let mut entry = Entry::new(meta::Metadata::unknown(mode, 0), id, path);
+        entry.flag |= (stage as u16) << STAGE_SHIFT;
+        entry
+    }
+
     pub fn metadata(&self) -> &meta::Metadata {
         &self.metadata
     }
 
+    /// Merge stage: 0 for normal entries, or 1 through 3 for the base, ours,
+    /// and theirs versions of an unmerged path.
+    pub fn stage(&self) -> u8 {
+        ((self.flag & STAGE) >> STAGE_SHIFT) as u8
+    }
+
     pub fn id(&self) -> &object::Id {
         &self.id
     }
 
+    /// Flags in the layout `git` uses in memory, for debugging: the on-disk
+    /// flags without the path length, and any extended flags shifted into
+    /// the upper half.
+    pub fn flags(&self) -> u32 {
+        let flag = (self.flag & !0xFFF & !EXTENDED) as u32;
+        match self.extended {
+            None => flag,
+            Some(extended) => flag | EXTENDED as u32 | (extended as u32) << 16,
+        }
+    }
+
+    /// Whether this entry stands for a whole directory outside the
+    /// sparse-checkout cone, identified by its tree.
+    pub fn is_sparse_directory(&self) -> bool {
+        self.metadata.mode.is_directory()
+    }
+
+    /// Whether this entry's file is absent from the workspace on purpose,
+    /// and should not be treated as deleted.
+    pub fn skip_worktree(&self) -> bool {
+        self.extended.unwrap_or(0) & SKIP_WORKTREE != 0
+    }
+
+    fn set_skip_worktree(&mut self) {
+        self.extended = Some(self.extended.unwrap_or(0) | SKIP_WORKTREE);
+    }
+
     pub fn path(&self) -> &path::Path {
         &self.path
     }
@@ -361,16 +1141,56 @@ impl Entry {
         self.metadata = metadata;
     }
 
-    fn read<R: io::Read>(reader: &mut R) -> anyhow::Result<Self> {
+    /// Whether this entry is racily clean: its file was modified no earlier
+    /// than the second the index was committed at `timestamp`, so it may
+    /// have changed again without its stat information showing it. Like
+    /// `git`, such entries should be compared by content.
+    pub fn is_racy(&self, timestamp: Option<u32>) -> bool {
+        timestamp.is_some_and(|timestamp| self.metadata.mtime >= timestamp)
+    }
+
+    fn read<R: io::BufRead>(
+        reader: &mut R,
+        version: u32,
+        previous: &path::Path,
+    ) -> anyhow::Result<Self> {
         let metadata = meta::Metadata::read(reader)?;
         let id = object::Id::read_bytes(reader)?;
         let flag = reader.read_u16::<BigEndian>()?;
+        let extended = match flag & EXTENDED {
+            0 => None,
+            _ if version < 3 => return Err(anyhow!("Extended flags in version {}", version)),
+            _ => Some(reader.read_u16::<BigEndian>()?),
+        };
 
         let mut buffer = Vec::new();
-        reader.by_ref().take(2).read_to_end(&mut buffer)?;
 
-        while !buffer.ends_with(&[0]) {
-            reader.by_ref().take(8).read_to_end(&mut buffer)?;
+        if version >= 4 {
+            // Path is stored as the number of bytes to remove from the end
+            // of the previous path, followed by a NUL-terminated suffix.
+            let previous = previous.as_os_str().as_bytes();
+            let strip = usize::try_from(read_varint(reader)?)?;
+            let keep = previous
+                .len()
+                .checked_sub(strip)
+                .ok_or_else(|| anyhow!("Invalid path compression in index"))?;
+            buffer.extend_from_slice(&previous[..keep]);
+            reader.read_until(0, &mut buffer)?;
+        } else {
+            // Path is NUL-padded so that the entry's length is a multiple
+            // of eight bytes.
+            let fixed = metadata.len() + 20 + 2 + if extended.is_some() { 2 } else { 0 };
+            let mut chunk = ((fixed + 8) & !7) as u64 - fixed as u64;
+            while !buffer.ends_with(&[0]) {
+                if reader.by_ref().take(chunk).read_to_end(&mut buffer)? == 0 {
+                    break;
+                }
+                chunk = 8;
+            }
+        }
+
+        if !buffer.ends_with(&[0]) {
+            return Err(anyhow!("Truncated path in index entry"));
         }
 
         while buffer.ends_with(&[0]) {
@@ -381,15 +1201,43 @@ impl Entry {
             metadata,
             id,
             flag,
+            extended,
             path: buffer.tap(ffi::OsString::from_vec).tap(path::PathBuf::from),
         })
     }
 
-    fn write<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
+    fn write<W: io::Write>(
+        &self,
+        writer: &mut W,
+        version: u32,
+        previous: &path::Path,
+    ) -> io::Result<()> {
         self.metadata.write(writer)?;
         writer.write_all(self.id.as_bytes())?;
-        writer.write_u16::<BigEndian>(self.flag)?;
-        writer.write_all(self.path.as_os_str().as_bytes())?;
+
+        match self.extended {
+            None => writer.write_u16::<BigEndian>(self.flag & !EXTENDED)?,
+            Some(extended) => {
+                writer.write_u16::<BigEndian>(self.flag | EXTENDED)?;
+                writer.write_u16::<BigEndian>(extended)?;
+            }
+        }
+
+        let path = self.path.as_os_str().as_bytes();
+
+        if version >= 4 {
+            let previous = previous.as_os_str().as_bytes();
+            let common = previous
+                .iter()
+                .zip(path)
+                .take_while(|(a, b)| a == b)
+                .count();
+            write_varint(writer, (previous.len() - common) as u64)?;
+            writer.write_all(&path[common..])?;
+            return writer.write_u8(0);
+        }
+
+        writer.write_all(path)?;
         for _ in 0..self.padding() {
             writer.write_u8(0)?;
         }
@@ -398,10 +1246,394 @@ impl Entry {
     }
 
     fn len(&self) -> usize {
-        self.metadata.len() + self.id.as_bytes().len() + 2 + self.path.as_os_str().as_bytes().len()
+        self.metadata.len()
+            + self.id.as_bytes().len()
+            + 2
+            + if self.extended.is_some() { 2 } else { 0 }
+            + self.path.as_os_str().as_bytes().len()
     }
 
     fn padding(&self) -> usize {
         0b1000 - (self.len() & 0b0111)
     }
 }
+
+/// Flag bit indicating that an entry has extended flags.
+const EXTENDED: u16 = 0x4000;
+
+/// Extended flag bit marking an entry as skip-worktree.
+const SKIP_WORKTREE: u16 = 0x4000;
+
+/// Signature of the extension marking a sparse index.
+const SPARSE_DIRECTORIES: &[u8; 4] = b"sdir";
+
+/// Parse the cache tree from the `TREE` extension among `extensions`, if any.
+/// A malformed one is ignored, since it can always be recomputed.
+fn read_cache_tree(extensions: &[Extension]) -> Option<CacheTree> {
+    extensions
+        .iter()
+        .find(|extension| extension.signature() == CacheTree::SIGNATURE)
+        .and_then(|extension| CacheTree::read(extension.data()).ok())
+}
+
+/// Signatures of extensions that are stale once the index is rewritten: the
+/// cache tree, untracked cache, file system monitor token, and the entry
+/// offset tables.
+const DERIVED_EXTENSIONS: &[&[u8; 4]] = &[b"TREE", b"UNTR", b"FSMN", b"EOIE", b"IEOT"];
+
+/// Key of the sparse directory entry for `directory`, if there is one.
+fn sparse_key(directory: &path::Path) -> util::PathBuf {
+    let mut path = directory.as_os_str().as_bytes().to_vec();
+    path.push(b'/');
+    path.tap(ffi::OsString::from_vec)
+        .tap(path::PathBuf::from)
+        .tap(util::PathBuf)
+}
+
+/// Flag bits holding an entry's merge stage.
+const STAGE: u16 = 0x3000;
+const STAGE_SHIFT: u16 = 12;
+
+/// Read a version 4 path prefix length, which uses the same offset encoding
+/// as packfiles.
+fn read_varint<R: io::Read>(reader: &mut R) -> io::Result<u64> {
+    let mut byte = reader.read_u8()?;
+    let mut value = (byte & 0x7f) as u64;
+    while byte & 0x80 != 0 {
+        byte = reader.read_u8()?;
+        value = value
+            .checked_add(1)
+            .filter(|value| value.leading_zeros() >= 7)
+            .map(|value| (value << 7) | (byte & 0x7f) as u64)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Overlong varint"))?;
+    }
+    Ok(value)
+}
+
+fn write_varint<W: io::Write>(writer: &mut W, mut value: u64) -> io::Result<()> {
+    let mut bytes = vec![(value & 0x7f) as u8];
+    value >>= 7;
+    while value > 0 {
+        value -= 1;
+        bytes.push(0x80 | (value & 0x7f) as u8);
+        value >>= 7;
+    }
+    bytes.reverse();
+    writer.write_all(&bytes)
+}
+
+#[test]
+fn version_4_round_trip() -> anyhow::Result<()> {
+    let metadata = meta::Metadata {
+        ctime: 1,
+        ctime_nsec: 2,
+        mtime: 3,
+        mtime_nsec: 4,
+        dev: 5,
+        ino: 6,
+        mode: meta::Mode::Regular,
+        uid: 7,
+        gid: 8,
+        size: 9,
+    };
+
+    let buffer = Rc::new(cell::RefCell::new(Vec::new()));
+    let mut index = Index::memory(Rc::clone(&buffer))?;
+    index.set_version(4)?;
+    for path in &["dir/sub/aaa", "dir/sub/aab", "top"] {
+        index.insert(metadata, object::Id::hash(path.as_bytes()), path.into());
+    }
+    let expected = index.entries().cloned().collect::<Vec<_>>();
+    index.commit()?;
+
+    let index = Index::memory(buffer)?;
+    assert_eq!(index.version(), 4);
+    assert_eq!(index.entries().cloned().collect::<Vec<_>>(), expected);
+    Ok(())
+}
+
+#[test]
+fn corrupt() -> anyhow::Result<()> {
+    let buffer = Rc::new(cell::RefCell::new(Vec::new()));
+    let mut index = Index::memory(Rc::clone(&buffer))?;
+    let metadata = meta::Metadata::unknown(meta::Mode::Regular, 4);
+    index.insert(metadata, object::Id::hash(b"file"), "file".into());
+    index.commit()?;
+    let valid = buffer.borrow().clone();
+
+    let read = |bytes: &[u8]| Index::memory(Rc::new(cell::RefCell::new(bytes.to_vec())));
+    for len in &[1, 19, 20, 31, valid.len() - 1] {
+        assert!(read(&valid[..*len]).is_err());
+    }
+
+    let mut damaged = valid.clone();
+    damaged[14] ^= 0xFF;
+    assert!(read(&damaged).is_err());
+
+    // Like `index.skipHash`, a zero checksum is never verified.
+    let checksum = valid.len() - 20;
+    let mut skipped = valid.clone();
+    skipped[checksum..].fill(0);
+    assert_eq!(read(&skipped)?.entries().count(), 1);
+
+    // Entries running past the end fail instead of reading forever, even
+    // with the path's NUL terminators and padding missing.
+    let mut unterminated = valid[..checksum].to_vec();
+    while unterminated.ends_with(&[0]) {
+        unterminated.pop();
+    }
+    unterminated.extend_from_slice(&[0; 20]);
+    assert!(read(&unterminated).is_err());
+    Ok(())
+}
+
+#[test]
+fn extensions() -> anyhow::Result<()> {
+    let buffer = Rc::new(cell::RefCell::new(Vec::new()));
+    let mut index = Index::memory(Rc::clone(&buffer))?;
+    let metadata = meta::Metadata::unknown(meta::Mode::Regular, 4);
+    index.insert(metadata, object::Id::hash(b"file"), "file".into());
+    index.set_extension(Extension::new(*b"REUC", vec![1, 2, 3]));
+    index.set_extension(Extension::new(*b"TREE", vec![4, 5, 6]));
+    index.set_extension(Extension::new(*b"ZZZZ", Vec::new()));
+    index.commit()?;
+
+    // Unknown extensions survive, but the stale cache tree doesn't.
+    let mut index = Index::memory(Rc::clone(&buffer))?;
+    let signatures = index
+        .extensions()
+        .iter()
+        .map(|extension| *extension.signature())
+        .collect::<Vec<_>>();
+    assert_eq!(signatures, vec![*b"REUC", *b"ZZZZ"]);
+    assert_eq!(
+        index.extension(b"REUC").map(Extension::data),
+        Some(&[1, 2, 3][..])
+    );
+
+    // Required extensions can't be ignored.
+    assert_eq!(
+        index.remove_extension(b"ZZZZ").map(|zzzz| zzzz.data),
+        Some(Vec::new())
+    );
+    index.set_extension(Extension::new(*b"link", vec![0; 20]));
+    index.commit()?;
+    assert!(Index::memory(buffer).is_err());
+    Ok(())
+}
+
+#[test]
+fn cache_tree() -> anyhow::Result<()> {
+    let repository = crate::Repository::memory(path::PathBuf::new());
+    let database = repository.database()?;
+    let metadata = meta::Metadata::unknown(meta::Mode::Regular, 0);
+    let blob = |data: &[u8]| database.store(&crate::Object::Blob(object::Blob::new(data.to_vec())));
+
+    let mut index = repository.index()?;
+    for path in &["a/1", "a/2", "b/3"] {
+        index.insert(metadata, blob(path.as_bytes())?, path.into());
+    }
+    let root = index.write_tree(&database)?;
+    index.commit()?;
+
+    // Cached trees survive a round trip through the index file.
+    let mut index = repository.index()?;
+    assert_eq!(index.cached_tree(path::Path::new("")), Some(&root));
+    let a = *index.cached_tree(path::Path::new("a")).unwrap();
+    assert_eq!(
+        index.entries_under(path::Path::new("a")).count(),
+        database.load_tree(&a)?.into_iter().count(),
+    );
+
+    index.insert(metadata, blob(b"changed")?, "b/3".into());
+    assert_eq!(index.cached_tree(path::Path::new("")), None);
+    assert_eq!(index.cached_tree(path::Path::new("a")), Some(&a));
+    assert_eq!(index.cached_tree(path::Path::new("b")), None);
+
+    let changed = index.write_tree(&database)?;
+    let mut uncached = Index::memory(Rc::default())?;
+    for entry in index.entries() {
+        uncached.insert(*entry.metadata(), *entry.id(), entry.path().to_path_buf());
+    }
+    assert_eq!(uncached.write_tree(&database)?, changed);
+    assert_eq!(index.cached_tree(path::Path::new("")), Some(&changed));
+
+    index.remove(path::Path::new("a"));
+    assert_eq!(index.cached_tree(path::Path::new("a")), None);
+    Ok(())
+}
+
+#[test]
+fn remove_directory() -> anyhow::Result<()> {
+    let metadata = meta::Metadata {
+        ctime: 0,
+        ctime_nsec: 0,
+        mtime: 0,
+        mtime_nsec: 0,
+        dev: 0,
+        ino: 0,
+        mode: meta::Mode::Regular,
+        uid: 0,
+        gid: 0,
+        size: 0,
+    };
+
+    let mut index = Index::memory(Rc::new(cell::RefCell::new(Vec::new())))?;
+    for path in &["dir.txt", "dir/a", "dir/sub/b", "other"] {
+        index.insert(metadata, object::Id::hash(path.as_bytes()), path.into());
+    }
+
+    let removed = index
+        .remove(path::Path::new("dir"))
+        .iter()
+        .map(|entry| entry.path().to_path_buf())
+        .collect::<Vec<_>>();
+    assert_eq!(removed, ["dir/a", "dir/sub/b"].map(path::PathBuf::from));
+    assert_eq!(index.remove(path::Path::new("other")).len(), 1);
+    assert!(index.remove(path::Path::new("missing")).is_empty());
+    assert!(index.contains_file(path::Path::new("dir.txt")));
+    Ok(())
+}
+
+#[test]
+fn load_tree() -> anyhow::Result<()> {
+    let repository = crate::Repository::memory(path::PathBuf::new());
+    let database = repository.database()?;
+    let blob = |data: &[u8]| database.store(&crate::Object::Blob(object::Blob::new(data.to_vec())));
+    let (kept, changed) = (blob(b"kept")?, blob(b"changed")?);
+    let tree = database.store(&crate::Object::Tree(object::tree::Root::new(vec![
+        object::tree::Node::new(path::PathBuf::from("changed"), changed, meta::Mode::Regular),
+        object::tree::Node::new(path::PathBuf::from("kept"), kept, meta::Mode::Regular),
+    ])))?;
+
+    let metadata = meta::Metadata {
+        mtime: 1,
+        size: 4,
+        ..meta::Metadata::unknown(meta::Mode::Regular, 0)
+    };
+    let mut index = Index::memory(Rc::new(cell::RefCell::new(Vec::new())))?;
+    index.insert(metadata, kept, "kept".into());
+    index.insert(metadata, kept, "changed".into());
+    index.insert(metadata, kept, "removed".into());
+    index.insert_conflict(
+        "conflict".into(),
+        [None, Some((kept, meta::Mode::Regular)), None],
+    );
+
+    index.load_tree(&database, &tree)?;
+    let entries = index
+        .entries()
+        .map(|entry| {
+            (
+                entry.path().to_path_buf(),
+                *entry.id(),
+                entry.metadata().mtime,
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        entries,
+        [("changed".into(), changed, 0), ("kept".into(), kept, 1)],
+    );
+    assert!(!index.is_conflicted());
+    Ok(())
+}
+
+#[test]
+fn sparse_directory() -> anyhow::Result<()> {
+    let repository = crate::Repository::memory(path::PathBuf::new());
+    let database = repository.database()?;
+    let metadata = meta::Metadata::unknown(meta::Mode::Regular, 0);
+
+    let mut index = repository.index()?;
+    for path in &["dir.txt", "dir/a", "dir/sub/b", "top"] {
+        let blob = crate::Object::Blob(object::Blob::new(path.as_bytes().to_vec()));
+        index.insert(metadata, database.store(&blob)?, path.into());
+    }
+    let tree = index.write_tree(&database)?;
+    index.collapse(&database, path::Path::new("dir"))?;
+    assert_eq!(index.write_tree(&database)?, tree);
+    index.commit()?;
+
+    let paths = |index: &Index| {
+        index
+            .entries()
+            .map(|entry| entry.path().to_string_lossy().into_owned())
+            .collect::<Vec<_>>()
+    };
+
+    let mut index = repository.sparse_index()?;
+    assert_eq!(paths(&index), vec!["dir.txt", "dir/", "top"]);
+    index.expand_to(&database, path::Path::new("dir/sub/b"))?;
+    assert_eq!(paths(&index), vec!["dir.txt", "dir/a", "dir/sub/b", "top"]);
+    assert!(index.get(path::Path::new("dir/a")).unwrap().skip_worktree());
+
+    // Untouched directories are collapsed again.
+    index.remove(path::Path::new("top"));
+    index.commit()?;
+    let index = repository.sparse_index()?;
+    assert_eq!(paths(&index), vec!["dir.txt", "dir/"]);
+    assert_eq!(
+        paths(&repository.index()?),
+        vec!["dir.txt", "dir/a", "dir/sub/b"]
+    );
+    Ok(())
+}
+
+#[test]
+fn smudge_racy() -> anyhow::Result<()> {
+    let now = time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)?
+        .as_secs() as u32;
+    let metadata = |mtime| meta::Metadata {
+        mtime,
+        ..meta::Metadata::unknown(meta::Mode::Regular, 4)
+    };
+
+    let buffer = Rc::new(cell::RefCell::new(Vec::new()));
+    let mut index = Index::memory(Rc::clone(&buffer))?;
+    index.insert(metadata(1), object::Id::hash(b"old"), "old".into());
+    index.insert(metadata(now), object::Id::hash(b"racy"), "racy".into());
+    index.commit()?;
+
+    let index = Index::memory(buffer)?;
+    let size = |path: &str| {
+        index
+            .get(path::Path::new(path))
+            .map(|entry| entry.metadata().size)
+    };
+    assert_eq!(size("old"), Some(4));
+    assert_eq!(size("racy"), Some(0));
+    assert!(index
+        .get(path::Path::new("racy"))
+        .is_some_and(|entry| entry.is_racy(Some(now))));
+    assert!(!index
+        .get(path::Path::new("old"))
+        .is_some_and(|entry| entry.is_racy(Some(now))));
+    Ok(())
+}
+
+#[test]
+fn update_metadata() -> anyhow::Result<()> {
+    let buffer = Rc::new(cell::RefCell::new(Vec::new()));
+    let mut index = Index::memory(Rc::clone(&buffer))?;
+    let metadata = meta::Metadata::unknown(meta::Mode::Regular, 4);
+    index.insert(metadata, object::Id::hash(b"file"), "file".into());
+    index.commit()?;
+
+    let mut index = Index::memory(Rc::clone(&buffer))?;
+    let refreshed = meta::Metadata {
+        ino: 42,
+        ..metadata
+    };
+    assert!(!index.update_metadata(path::Path::new("missing"), refreshed));
+    assert!(index.update_metadata(path::Path::new("file"), refreshed));
+    index.commit()?;
+
+    let index = Index::memory(buffer)?;
+    assert_eq!(
+        index.get(path::Path::new("file")).map(Entry::metadata),
+        Some(&refreshed),
+    );
+    Ok(())
+}
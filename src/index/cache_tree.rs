@@ -0,0 +1,172 @@
+//! The cache tree, stored in the `TREE` index extension, which remembers the
+//! tree ids of directories whose entries haven't changed since they were last
+//! written as tree objects. Writing the index as trees can reuse these ids
+//! instead of hashing and storing identical trees again, and comparing the
+//! index with a tree can skip directories whose ids match.
+//!
+//! Each directory is stored as its NUL-terminated name, ASCII counts of the
+//! index entries below it and of its subdirectories, and its tree id unless
+//! the entry count is negative, meaning it was invalidated. Subdirectories
+//! follow their parent, depth first.
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom as _;
+use std::io::Write as _;
+use std::path;
+use std::str;
+
+use anyhow::anyhow;
+
+use crate::object;
+use crate::platform::OsStrExt as _;
+
+/// Cached tree of a single directory, along with its subdirectories.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CacheTree {
+    /// Number of index entries below this directory and its tree id, or
+    /// `None` if an entry below it has changed.
+    valid: Option<(usize, object::Id)>,
+    /// Subdirectories by name.
+    children: BTreeMap<Vec<u8>, CacheTree>,
+}
+
+impl CacheTree {
+    pub const SIGNATURE: &'static [u8; 4] = b"TREE";
+
+    pub fn read(mut data: &[u8]) -> anyhow::Result<Self> {
+        let (name, tree) = Self::read_directory(&mut data)?;
+        if !name.is_empty() || !data.is_empty() {
+            return Err(anyhow!("Invalid cache tree in index"));
+        }
+        Ok(tree)
+    }
+
+    fn read_directory(data: &mut &[u8]) -> anyhow::Result<(Vec<u8>, Self)> {
+        let invalid = || anyhow!("Invalid cache tree in index");
+
+        let nul = data
+            .iter()
+            .position(|byte| *byte == 0)
+            .ok_or_else(invalid)?;
+        let name = data[..nul].to_vec();
+        let newline = nul
+            + data[nul..]
+                .iter()
+                .position(|byte| *byte == b'\n')
+                .ok_or_else(invalid)?;
+        let (entries, subtrees) = str::from_utf8(&data[nul + 1..newline])?
+            .split_once(' ')
+            .ok_or_else(invalid)?;
+        let entries = entries.parse::<i64>()?;
+        let subtrees = subtrees.parse::<usize>()?;
+        *data = &data[newline + 1..];
+
+        let valid = match usize::try_from(entries) {
+            Err(_) => None,
+            Ok(entries) => Some((entries, object::Id::read_bytes(data)?)),
+        };
+
+        let mut children = BTreeMap::new();
+        for _ in 0..subtrees {
+            let (name, child) = Self::read_directory(data)?;
+            children.insert(name, child);
+        }
+
+        Ok((name, CacheTree { valid, children }))
+    }
+
+    pub fn write(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        self.write_directory(b"", &mut buffer);
+        buffer
+    }
+
+    fn write_directory(&self, name: &[u8], buffer: &mut Vec<u8>) {
+        buffer.extend_from_slice(name);
+        buffer.push(0);
+        let entries = self.valid.map_or(-1, |(entries, _)| entries as i64);
+        writeln!(buffer, "{} {}", entries, self.children.len()).expect("writing to vector");
+        if let Some((_, id)) = &self.valid {
+            buffer.extend_from_slice(id.as_bytes());
+        }
+        for (name, child) in &self.children {
+            child.write_directory(name, buffer);
+        }
+    }
+
+    /// Tree id of `directory`, relative to the root, if it is still valid.
+    pub fn get(&self, directory: &path::Path) -> Option<&object::Id> {
+        let mut tree = self;
+        for component in directory.iter() {
+            tree = tree.children.get(component.as_bytes())?;
+        }
+        tree.valid.as_ref().map(|(_, id)| id)
+    }
+
+    /// Ids of every valid cached tree.
+    pub fn ids(&self) -> Vec<object::Id> {
+        let mut ids = Vec::new();
+        let mut stack = vec![self];
+        while let Some(tree) = stack.pop() {
+            ids.extend(tree.valid.map(|(_, id)| id));
+            stack.extend(tree.children.values());
+        }
+        ids
+    }
+
+    /// Record that `directory` has tree id `id`, covering `entries` index
+    /// entries.
+    pub fn insert(&mut self, directory: &path::Path, entries: usize, id: object::Id) {
+        let mut tree = self;
+        for component in directory.iter() {
+            tree = tree
+                .children
+                .entry(component.as_bytes().to_vec())
+                .or_default();
+        }
+        tree.valid = Some((entries, id));
+    }
+
+    /// Invalidate every directory containing `path`, and forget `path`
+    /// itself in case it was a directory, after an entry at or below it
+    /// changed.
+    pub fn invalidate(&mut self, path: &path::Path) {
+        let mut tree = self;
+        let mut components = path.iter().peekable();
+        while let Some(component) = components.next() {
+            tree.valid = None;
+            if components.peek().is_none() {
+                tree.children.remove(component.as_bytes());
+                return;
+            }
+            tree = match tree.children.get_mut(component.as_bytes()) {
+                Some(child) => child,
+                None => return,
+            };
+        }
+        tree.valid = None;
+    }
+}
+
+#[test]
+fn round_trip() -> anyhow::Result<()> {
+    let id = |byte: u8| object::Id::read_bytes(&mut &[byte; 20][..]);
+
+    let mut tree = CacheTree::default();
+    tree.insert(path::Path::new(""), 3, id(1)?);
+    tree.insert(path::Path::new("a"), 2, id(2)?);
+    tree.insert(path::Path::new("a/b"), 1, id(3)?);
+    tree.insert(path::Path::new("c"), 1, id(4)?);
+    assert_eq!(CacheTree::read(&tree.write())?, tree);
+
+    tree.invalidate(path::Path::new("a/b/file"));
+    assert_eq!(tree.get(path::Path::new("")), None);
+    assert_eq!(tree.get(path::Path::new("a")), None);
+    assert_eq!(tree.get(path::Path::new("a/b")), None);
+    assert_eq!(tree.get(path::Path::new("c")), Some(&id(4)?));
+
+    let data = tree.write();
+    assert!(data.starts_with(b"\0-1 2\n"));
+    assert_eq!(CacheTree::read(&data)?, tree);
+    Ok(())
+}
@@ -0,0 +1,197 @@
+//! Clean up after commands interrupted by SIGINT or SIGTERM.
+//!
+//! Once [`install`]ed, an interrupt removes every temporary file and lock
+//! file still pending (see [`crate::file`]), along with any path registered
+//! by [`remove_on_interrupt`], before exiting with status `128 + signal`.
+//! Work that must not be cut short, like committing all the locks in a
+//! reference transaction, can hold off the exit with [`defer`].
+//!
+//! Library users that don't install the handler keep the default behavior,
+//! which may leave `.lock` files behind.
+
+#[cfg(unix)]
+use std::fs;
+use std::io;
+use std::path;
+#[cfg(unix)]
+use std::process;
+use std::sync;
+#[cfg(unix)]
+use std::sync::atomic;
+#[cfg(unix)]
+use std::thread;
+
+/// Paths to remove on interrupt, in the order they were registered.
+static PENDING: sync::Mutex<Vec<path::PathBuf>> = sync::Mutex::new(Vec::new());
+
+/// Held for reading by [`Deferred`] sections, and for writing by cleanup.
+static DEFER: sync::RwLock<()> = sync::RwLock::new(());
+
+/// Write end of the pipe that wakes the cleanup thread.
+#[cfg(unix)]
+static PIPE: atomic::AtomicI32 = atomic::AtomicI32::new(-1);
+
+static INSTALL: sync::Once = sync::Once::new();
+
+/// Handle SIGINT and SIGTERM by cleaning up and exiting.
+///
+/// Only async-signal-safe work happens in the handler itself, which passes
+/// the signal through a pipe to a cleanup thread. Installing more than once
+/// has no further effect.
+pub fn install() -> io::Result<()> {
+    let mut result = Ok(());
+    INSTALL.call_once(|| result = install_once());
+    result
+}
+
+#[cfg(unix)]
+fn install_once() -> io::Result<()> {
+    let mut fds = [0; 2];
+
+    // SAFETY: `fds` has room for the two descriptors `pipe` writes.
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let [read, write] = fds;
+    PIPE.store(write, atomic::Ordering::SeqCst);
+
+    thread::Builder::new()
+        .name(String::from("interrupt"))
+        .spawn(move || {
+            let mut signal = 0u8;
+
+            // SAFETY: `read` stays open for the life of the process, and
+            // `signal` has room for the single byte requested.
+            let count = unsafe { libc::read(read, (&mut signal as *mut u8).cast(), 1) };
+            if count == 1 {
+                cleanup(signal);
+            }
+        })?;
+
+    for signal in &[libc::SIGINT, libc::SIGTERM] {
+        // SAFETY: `handle` only calls `write`, which is async-signal-safe,
+        // and `action` is fully initialized before `sigaction` reads it.
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = handle as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            if libc::sigaction(*signal, &action, std::ptr::null_mut()) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Without signals to catch, interrupts keep the default behavior, as for
+/// library users that don't install the handler.
+#[cfg(not(unix))]
+fn install_once() -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+extern "C" fn handle(signal: libc::c_int) {
+    let byte = signal as u8;
+
+    // SAFETY: `write` is async-signal-safe, and a failed write (e.g. the
+    // pipe is full from repeated interrupts) is harmless.
+    unsafe {
+        libc::write(
+            PIPE.load(atomic::Ordering::SeqCst),
+            (&byte as *const u8).cast(),
+            1,
+        );
+    }
+}
+
+/// Remove every pending path and exit, waiting for deferred sections to
+/// finish and keeping any more from starting in the meantime.
+#[cfg(unix)]
+fn cleanup(signal: u8) -> ! {
+    let _deferred = DEFER.write().unwrap_or_else(sync::PoisonError::into_inner);
+    let pending = pending();
+    for path in pending.iter().rev() {
+        let _ = match fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path),
+            Ok(_) => fs::remove_file(path),
+            Err(_) => continue,
+        };
+    }
+    process::exit(128 + i32::from(signal))
+}
+
+fn pending() -> sync::MutexGuard<'static, Vec<path::PathBuf>> {
+    PENDING.lock().unwrap_or_else(sync::PoisonError::into_inner)
+}
+
+/// Remove `path` if interrupted.
+fn register(path: &path::Path) {
+    pending().push(path.to_path_buf());
+}
+
+/// Run `apply`, e.g. to create `path`, and remove `path` on interrupt if it
+/// succeeds, without an interrupt in between.
+pub(crate) fn register_after<T, E>(
+    path: &path::Path,
+    apply: impl FnOnce() -> Result<T, E>,
+) -> Result<T, E> {
+    let mut pending = pending();
+    let result = apply();
+    if result.is_ok() {
+        pending.push(path.to_path_buf());
+    }
+    result
+}
+
+/// Stop removing `path` on interrupt, after running `apply`, e.g. to rename
+/// or remove it, without an interrupt in between.
+pub(crate) fn unregister_after<T>(path: &path::Path, apply: impl FnOnce() -> T) -> T {
+    let mut pending = pending();
+    let result = apply();
+    if let Some(index) = pending.iter().rposition(|pending| pending == path) {
+        pending.remove(index);
+    }
+    result
+}
+
+/// Remove `path`, which may be a directory, if interrupted before the
+/// returned guard is dropped.
+pub fn remove_on_interrupt(path: path::PathBuf) -> Removal {
+    register(&path);
+    Removal(path)
+}
+
+/// Guard returned by [`remove_on_interrupt`].
+#[derive(Debug)]
+pub struct Removal(path::PathBuf);
+
+impl Drop for Removal {
+    fn drop(&mut self) {
+        unregister_after(&self.0, || ());
+    }
+}
+
+/// Hold off interrupts until the returned guard is dropped.
+pub fn defer() -> Deferred {
+    Deferred {
+        _guard: DEFER.read().unwrap_or_else(sync::PoisonError::into_inner),
+    }
+}
+
+/// Guard returned by [`defer`].
+pub struct Deferred {
+    _guard: sync::RwLockReadGuard<'static, ()>,
+}
+
+#[test]
+fn pending_paths() {
+    let path = path::PathBuf::from("grit-interrupt-test");
+    {
+        let _removal = remove_on_interrupt(path.clone());
+        assert!(pending().contains(&path));
+    }
+    assert!(!pending().contains(&path));
+}
@@ -1,16 +1,37 @@
+pub mod attributes;
+pub mod blame;
+pub mod bundle;
 pub mod command;
+pub mod config;
 pub mod database;
-mod diff;
+pub mod diff;
+pub mod fast_import;
 pub mod file;
+pub mod fsck;
+pub mod gc;
+pub mod help;
+pub mod ignore;
 pub mod index;
+pub mod interrupt;
+pub mod merge;
 pub mod meta;
+pub mod migration;
 pub mod object;
+pub mod platform;
+pub mod prefix;
+pub mod protocol;
 pub mod references;
+pub mod refspec;
 pub mod repository;
+pub mod revision;
+pub mod revwalk;
+pub mod state;
+pub mod transport;
 pub mod util;
 pub mod workspace;
 
 pub use database::Database;
+pub use file as fs;
 pub use index::Index;
 pub use object::Object;
 pub use references::References;
@@ -1,23 +1,154 @@
+use std::io;
+
 use grit::command;
+use grit::help;
+use structopt::clap::AppSettings;
 use structopt::StructOpt;
 
 #[derive(StructOpt)]
+#[structopt(setting = AppSettings::DisableHelpSubcommand)]
 enum Command {
+    #[structopt(after_help = help::ADD.config)]
     Add(command::Add),
+    Blame(command::Blame),
+    #[structopt(after_help = help::BRANCH.config)]
+    Branch(command::Branch),
+    CatFile(command::CatFile),
+    #[structopt(after_help = help::CHECKOUT.config)]
+    Checkout(command::Checkout),
+    #[structopt(after_help = help::CLONE.config)]
+    Clone(command::Clone),
+    #[structopt(after_help = help::COMMIT.config)]
     Commit(command::Commit),
+    CommitGraph(command::CommitGraph),
+    Config(command::Config),
+    #[structopt(after_help = help::DIFF.config)]
+    Diff(command::Diff),
+    Doctor(command::Doctor),
+    DumpIndex(command::DumpIndex),
+    FastExport(command::FastExport),
+    FastImport(command::FastImport),
+    #[structopt(after_help = help::FETCH.config)]
+    Fetch(command::Fetch),
+    Filter(command::Filter),
+    Fsck(command::Fsck),
+    #[structopt(after_help = help::GC.config)]
+    Gc(command::Gc),
+    #[structopt(after_help = help::GREP.config)]
+    Grep(command::Grep),
+    Help(command::Help),
     Init(command::Init),
+    #[structopt(after_help = help::LOG.config)]
+    Log(command::Log),
+    #[structopt(after_help = help::LS_FILES.config)]
+    LsFiles(command::LsFiles),
+    LsTree(command::LsTree),
+    #[structopt(after_help = help::MERGE.config)]
+    Merge(command::Merge),
+    MergeTree(command::MergeTree),
+    PackObjects(command::PackObjects),
+    #[structopt(after_help = help::PUSH.config)]
+    Push(command::Push),
+    Reflog(command::Reflog),
+    #[structopt(after_help = help::REMOTE.config)]
+    Remote(command::Remote),
+    #[structopt(after_help = help::RESET.config)]
+    Reset(command::Reset),
+    Restore(command::Restore),
+    RevList(command::RevList),
+    #[structopt(after_help = help::REV_PARSE.config)]
+    RevParse(command::RevParse),
+    #[structopt(after_help = help::RM.config)]
+    Rm(command::Rm),
+    #[structopt(after_help = help::SHOW.config)]
     Show(command::Show),
+    #[structopt(after_help = help::SHOW_BRANCH.config)]
+    ShowBranch(command::ShowBranch),
+    #[structopt(after_help = help::STASH.config)]
+    Stash(command::Stash),
+    #[structopt(after_help = help::STATUS.config)]
     Status(command::Status),
+    #[structopt(after_help = help::TAG.config)]
+    Tag(command::Tag),
 }
 
 fn main() -> anyhow::Result<()> {
     env_logger::init();
+    grit::interrupt::install()?;
 
-    match Command::from_args() {
+    match run(Command::from_args()) {
+        // Like `git`, stop quietly once whatever reads standard output has
+        // gone away, as in `grit log | head`.
+        Err(error) if is_broken_pipe(&error) => Ok(()),
+        result => result,
+    }
+}
+
+/// Whether `error` was caused by writing to a pipe with no reader.
+fn is_broken_pipe(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause
+            .downcast_ref::<io::Error>()
+            .is_some_and(|error| error.kind() == io::ErrorKind::BrokenPipe)
+    })
+}
+
+fn run(command: Command) -> anyhow::Result<()> {
+    match command {
         Command::Add(add) => add.run(),
+        Command::Blame(blame) => blame.run(),
+        Command::Branch(branch) => branch.run(),
+        Command::CatFile(cat_file) => cat_file.run(),
+        Command::Checkout(checkout) => checkout.run(),
+        Command::Clone(clone) => clone.run(),
         Command::Commit(commit) => commit.run(),
+        Command::CommitGraph(commit_graph) => commit_graph.run(),
+        Command::Config(config) => config.run(),
+        Command::Diff(diff) => diff.run(),
+        Command::Doctor(doctor) => doctor.run(),
+        Command::DumpIndex(dump_index) => dump_index.run(),
+        Command::FastExport(fast_export) => fast_export.run(),
+        Command::FastImport(fast_import) => fast_import.run(),
+        Command::Fetch(fetch) => fetch.run(),
+        Command::Filter(filter) => filter.run(),
+        Command::Fsck(fsck) => fsck.run(),
+        Command::Gc(gc) => gc.run(),
+        Command::Grep(grep) => grep.run(),
+        Command::Help(help) => help.run(),
         Command::Init(init) => init.run(),
+        Command::Log(log) => log.run(),
+        Command::LsFiles(ls_files) => ls_files.run(),
+        Command::LsTree(ls_tree) => ls_tree.run(),
+        Command::Merge(merge) => merge.run(),
+        Command::MergeTree(merge_tree) => merge_tree.run(),
+        Command::PackObjects(pack_objects) => pack_objects.run(),
+        Command::Push(push) => push.run(),
+        Command::Reflog(reflog) => reflog.run(),
+        Command::Remote(remote) => remote.run(),
+        Command::Reset(reset) => reset.run(),
+        Command::Restore(restore) => restore.run(),
+        Command::RevList(rev_list) => rev_list.run(),
+        Command::RevParse(rev_parse) => rev_parse.run(),
+        Command::Rm(rm) => rm.run(),
         Command::Show(show) => show.run(),
+        Command::ShowBranch(show_branch) => show_branch.run(),
+        Command::Stash(stash) => stash.run(),
         Command::Status(status) => status.run(),
+        Command::Tag(tag) => tag.run(),
+    }
+}
+
+#[test]
+fn pages() {
+    for page in help::PAGES {
+        let error = Command::from_iter_safe(&["grit", page.name, "--help"])
+            .err()
+            .expect("--help exits early");
+        assert_eq!(
+            error.kind,
+            structopt::clap::ErrorKind::HelpDisplayed,
+            "{}",
+            page.name
+        );
     }
 }
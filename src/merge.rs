@@ -0,0 +1,327 @@
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::BinaryHeap;
+use std::collections::HashMap;
+use std::fmt;
+use std::path;
+
+use anyhow::anyhow;
+
+use crate::diff::tree;
+use crate::object;
+use crate::util;
+
+pub mod diff3;
+
+/// Find the best common ancestor of commits `a` and `b`, i.e. one that is
+/// not an ancestor of any other common ancestor.
+pub fn base(
+    database: &crate::Database,
+    a: &object::Id,
+    b: &object::Id,
+) -> anyhow::Result<Option<object::Id>> {
+    Ok(bases(database, a, b)?.into_iter().next())
+}
+
+/// Find all best common ancestors of commits `a` and `b`, newest first.
+/// There is more than one only after criss-cross merges.
+pub fn bases(
+    database: &crate::Database,
+    a: &object::Id,
+    b: &object::Id,
+) -> anyhow::Result<Vec<object::Id>> {
+    const A: u8 = 0b001;
+    const B: u8 = 0b010;
+    const STALE: u8 = 0b100;
+
+    let mut flags = HashMap::<object::Id, u8>::new();
+    let mut queue = BinaryHeap::new();
+    let mut results = Vec::new();
+
+    // Visit commits from newest to oldest, so that every descendant of a
+    // commit is visited before it.
+    let push = |queue: &mut BinaryHeap<(i64, object::Id)>,
+                flags: &mut HashMap<object::Id, u8>,
+                id: object::Id,
+                flag: u8|
+     -> anyhow::Result<()> {
+        let current = flags.entry(id).or_default();
+        if *current & flag == flag {
+            return Ok(());
+        }
+        *current |= flag;
+        queue.push((
+            database.load_commit(&id)?.committer().time().timestamp(),
+            id,
+        ));
+        Ok(())
+    };
+
+    push(&mut queue, &mut flags, *a, A)?;
+    push(&mut queue, &mut flags, *b, B)?;
+
+    while queue.iter().any(|(_, id)| flags[id] & STALE == 0) {
+        let (_, id) = queue.pop().expect("[INTERNAL ERROR]: non-empty queue");
+        let mut flag = flags[&id];
+
+        if flag & (A | B) == A | B {
+            if flag & STALE == 0 {
+                results.push(id);
+            }
+            // Ancestors of a common ancestor are never the best one.
+            flag |= STALE;
+        }
+
+        for parent in database.load_commit(&id)?.parents() {
+            push(&mut queue, &mut flags, *parent, flag)?;
+        }
+    }
+
+    Ok(results
+        .into_iter()
+        .filter(|id| flags[id] & STALE == 0)
+        .collect())
+}
+
+/// Result of merging two trees against their common base.
+#[derive(Clone, Debug, Default)]
+pub struct Outcome {
+    /// Merged files, including the workspace versions of conflicted files.
+    pub files: BTreeMap<util::PathBuf, tree::Entry>,
+    /// Files merged at the line level rather than taken from one side.
+    pub merged: BTreeSet<util::PathBuf>,
+    pub conflicts: BTreeMap<util::PathBuf, Conflict>,
+}
+
+impl Outcome {
+    pub fn is_clean(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+
+    /// Store the tree of merged files in `database`, with conflicted files
+    /// as they would be left in the workspace, returning the root tree id.
+    pub fn write_tree(&self, database: &crate::Database) -> anyhow::Result<object::Id> {
+        tree::unflatten(database, &self.files)
+    }
+}
+
+/// A file that could not be merged automatically.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Conflict {
+    pub base: Option<tree::Entry>,
+    pub ours: Option<tree::Entry>,
+    pub theirs: Option<tree::Entry>,
+}
+
+impl Conflict {
+    /// Base, ours, and theirs versions, for conflict stages 1 through 3.
+    pub fn stages(&self) -> [Option<tree::Entry>; 3] {
+        [self.base, self.ours, self.theirs]
+    }
+
+    /// Describe this conflict in the style of `git merge`, where `ours` and
+    /// `theirs` name the merged commits, e.g. `HEAD` and a branch.
+    pub fn describe<'a>(
+        &'a self,
+        path: &'a path::Path,
+        ours: &'a str,
+        theirs: &'a str,
+    ) -> impl fmt::Display + 'a {
+        Describe {
+            conflict: self,
+            path,
+            ours,
+            theirs,
+        }
+    }
+}
+
+struct Describe<'a> {
+    conflict: &'a Conflict,
+    path: &'a path::Path,
+    ours: &'a str,
+    theirs: &'a str,
+}
+
+impl fmt::Display for Describe<'_> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let path = self.path.display();
+        match (self.conflict.base, self.conflict.ours, self.conflict.theirs) {
+            (None, Some(_), Some(_)) => {
+                write!(fmt, "CONFLICT (add/add): Merge conflict in {}", path)
+            }
+            (_, Some(_), Some(_)) => {
+                write!(fmt, "CONFLICT (content): Merge conflict in {}", path)
+            }
+            (_, Some(_), None) => write!(
+                fmt,
+                "CONFLICT (modify/delete): {} deleted in {} and modified in {}. \
+                 Version {} of {} left in tree.",
+                path, self.theirs, self.ours, self.ours, path,
+            ),
+            (_, None, _) => write!(
+                fmt,
+                "CONFLICT (modify/delete): {} deleted in {} and modified in {}. \
+                 Version {} of {} left in tree.",
+                path, self.ours, self.theirs, self.theirs, path,
+            ),
+        }
+    }
+}
+
+/// Merge trees `ours` and `theirs` given their common `base`, storing the
+/// line-level merge of each file changed on both sides in `database`.
+///
+/// Conflict markers are labeled `ours_label` and `theirs_label`.
+pub fn trees(
+    database: &crate::Database,
+    base: Option<&object::Id>,
+    ours: &object::Id,
+    theirs: &object::Id,
+    ours_label: &str,
+    theirs_label: &str,
+) -> anyhow::Result<Outcome> {
+    let base = match base {
+        None => BTreeMap::new(),
+        Some(base) => tree::flatten(database, base)?,
+    };
+    let ours = tree::flatten(database, ours)?;
+    let theirs = tree::flatten(database, theirs)?;
+
+    let paths = base
+        .keys()
+        .chain(ours.keys())
+        .chain(theirs.keys())
+        .collect::<BTreeSet<_>>();
+
+    let mut outcome = Outcome::default();
+
+    for path in paths {
+        let o = base.get(path).copied();
+        let a = ours.get(path).copied();
+        let b = theirs.get(path).copied();
+
+        let resolved = if a == b || o == b {
+            Some(a)
+        } else if o == a {
+            Some(b)
+        } else {
+            None
+        };
+
+        if let Some(resolved) = resolved {
+            if let Some(entry) = resolved {
+                outcome.files.insert(path.clone(), entry);
+            }
+            continue;
+        }
+
+        let conflict = Conflict {
+            base: o,
+            ours: a,
+            theirs: b,
+        };
+
+        let (a, b) = match (a, b) {
+            (Some(a), Some(b)) => (a, b),
+            // Keep whichever side modified the file.
+            (Some(entry), None) | (None, Some(entry)) => {
+                outcome.files.insert(path.clone(), entry);
+                outcome.conflicts.insert(path.clone(), conflict);
+                continue;
+            }
+            (None, None) => unreachable!(),
+        };
+
+        let (mode, clean_mode) = match o.map(|o| o.mode) {
+            Some(mode) if mode == a.mode => (b.mode, true),
+            Some(mode) if mode == b.mode => (a.mode, true),
+            _ => (a.mode, a.mode == b.mode),
+        };
+
+        let (id, clean_data) = if a.id == b.id {
+            (a.id, true)
+        } else if a.mode.is_symlink() || b.mode.is_symlink() {
+            // Link targets cannot be merged line by line.
+            (a.id, false)
+        } else {
+            let base = match o {
+                None => Vec::new(),
+                Some(o) => database.load_blob(&o.id)?.into_data(),
+            };
+            let merged = diff3::merge(
+                &base,
+                database.load_blob(&a.id)?.data(),
+                database.load_blob(&b.id)?.data(),
+                ours_label,
+                theirs_label,
+            );
+            let id = database.store(&crate::Object::Blob(object::Blob::new(merged.data)))?;
+            outcome.merged.insert(path.clone());
+            (id, merged.clean)
+        };
+
+        outcome.files.insert(path.clone(), tree::Entry { id, mode });
+        if !clean_mode || !clean_data {
+            outcome.conflicts.insert(path.clone(), conflict);
+        }
+    }
+
+    // A file on one side may now sit where the other side has a directory.
+    for path in outcome.files.keys() {
+        for ancestor in path.ancestors().skip(1) {
+            if ancestor != path::Path::new("")
+                && outcome.files.contains_key(&ancestor as &dyn util::Key)
+            {
+                return Err(anyhow!(
+                    "Merging a file with a directory is not supported: {}",
+                    ancestor.display(),
+                ));
+            }
+        }
+    }
+
+    Ok(outcome)
+}
+
+#[test]
+fn write_tree() -> anyhow::Result<()> {
+    let database = crate::Repository::memory(path::PathBuf::new()).database()?;
+    let tree = |files: &[(&str, &[u8])]| -> anyhow::Result<object::Id> {
+        let mut nodes = Vec::new();
+        for (path, data) in files {
+            let id = database.store(&crate::Object::Blob(object::Blob::new(data.to_vec())))?;
+            nodes.push(object::tree::Node::new(
+                path::PathBuf::from(path),
+                id,
+                crate::meta::Mode::Regular,
+            ));
+        }
+        database
+            .store(&crate::Object::Tree(object::tree::Root::new(nodes)))
+            .map_err(anyhow::Error::from)
+    };
+
+    let base = tree(&[("a", b"1\n2\n3\n"), ("b", b"b\n")])?;
+    let ours = tree(&[("a", b"one\n2\n3\n"), ("b", b"b\n")])?;
+    let theirs = tree(&[("a", b"1\n2\nthree\n"), ("c", b"c\n")])?;
+    let outcome = trees(&database, Some(&base), &ours, &theirs, "ours", "theirs")?;
+    assert!(outcome.is_clean());
+    assert_eq!(
+        outcome.write_tree(&database)?,
+        tree(&[("a", b"one\n2\nthree\n"), ("c", b"c\n")])?,
+    );
+
+    let theirs = tree(&[("a", b"uno\n2\n3\n"), ("b", b"b\n")])?;
+    let outcome = trees(&database, Some(&base), &ours, &theirs, "ours", "theirs")?;
+    assert_eq!(
+        outcome.conflicts.keys().collect::<Vec<_>>(),
+        [&util::PathBuf(path::PathBuf::from("a"))],
+    );
+    let merged = tree::flatten(&database, &outcome.write_tree(&database)?)?;
+    let data = database
+        .load_blob(&merged[&util::PathBuf(path::PathBuf::from("a"))].id)?
+        .into_data();
+    assert!(data.starts_with(b"<<<<<<< ours\n"));
+    Ok(())
+}
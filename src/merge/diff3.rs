@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+
+use crate::diff;
+
+/// A region of a line-level three-way merge.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Chunk<'a> {
+    /// Lines that either side changed without the other disagreeing.
+    Clean(Vec<&'a [u8]>),
+    /// Lines that both sides changed differently.
+    Conflict {
+        base: Vec<&'a [u8]>,
+        ours: Vec<&'a [u8]>,
+        theirs: Vec<&'a [u8]>,
+    },
+}
+
+/// Result of merging three versions of a file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Merged {
+    /// Merged contents, with conflict markers around any conflicts.
+    pub data: Vec<u8>,
+    pub clean: bool,
+}
+
+/// Merge the changes from `base` to `ours` and from `base` to `theirs`,
+/// labeling conflict markers with `ours_label` and `theirs_label`.
+pub fn merge(
+    base: &[u8],
+    ours: &[u8],
+    theirs: &[u8],
+    ours_label: &str,
+    theirs_label: &str,
+) -> Merged {
+    let base = diff::lines(base);
+    let ours = diff::lines(ours);
+    let theirs = diff::lines(theirs);
+
+    let mut data = Vec::new();
+    let mut clean = true;
+
+    for chunk in chunks(&base, &ours, &theirs) {
+        match chunk {
+            Chunk::Clean(lines) => lines.iter().for_each(|line| data.extend_from_slice(line)),
+            Chunk::Conflict { ours, theirs, .. } => {
+                clean = false;
+                data.extend_from_slice(format!("<<<<<<< {}\n", ours_label).as_bytes());
+                extend_lines(&mut data, &ours);
+                data.extend_from_slice(b"=======\n");
+                extend_lines(&mut data, &theirs);
+                data.extend_from_slice(format!(">>>>>>> {}\n", theirs_label).as_bytes());
+            }
+        }
+    }
+
+    Merged { data, clean }
+}
+
+/// Append `lines`, terminating the last one so that a marker can follow.
+fn extend_lines(data: &mut Vec<u8>, lines: &[&[u8]]) {
+    lines.iter().for_each(|line| data.extend_from_slice(line));
+    if lines.last().is_some_and(|line| !line.ends_with(b"\n")) {
+        data.push(b'\n');
+    }
+}
+
+/// Split the three versions into alternating stable and unstable chunks,
+/// where stable chunks are lines that all three versions share.
+pub fn chunks<'a>(base: &[&'a [u8]], ours: &[&'a [u8]], theirs: &[&'a [u8]]) -> Vec<Chunk<'a>> {
+    Diff3 {
+        base,
+        ours,
+        theirs,
+        match_ours: matches(base, ours),
+        match_theirs: matches(base, theirs),
+        line_base: 0,
+        line_ours: 0,
+        line_theirs: 0,
+        chunks: Vec::new(),
+    }
+    .run()
+}
+
+/// Map each line of `a` that survives into `b` to its position in `b`.
+fn matches(a: &[&[u8]], b: &[&[u8]]) -> HashMap<usize, usize> {
+    diff::edits(a, b)
+        .into_iter()
+        .filter_map(|edit| match edit {
+            diff::Edit::Equal { a, b } => Some((a, b)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// State of the diff3 algorithm, which walks all three versions in lock
+/// step. Each `line_*` is the number of lines already emitted.
+struct Diff3<'a, 'b> {
+    base: &'b [&'a [u8]],
+    ours: &'b [&'a [u8]],
+    theirs: &'b [&'a [u8]],
+    match_ours: HashMap<usize, usize>,
+    match_theirs: HashMap<usize, usize>,
+    line_base: usize,
+    line_ours: usize,
+    line_theirs: usize,
+    chunks: Vec<Chunk<'a>>,
+}
+
+impl<'a> Diff3<'a, '_> {
+    fn run(mut self) -> Vec<Chunk<'a>> {
+        loop {
+            match self.find_mismatch() {
+                // The next line already differs, so skip to where all three
+                // versions agree again.
+                Some(0) => match self.find_match() {
+                    Some((base, ours, theirs)) => self.emit(base, ours, theirs),
+                    None => return self.finish(),
+                },
+                Some(offset) => self.emit(
+                    self.line_base + offset,
+                    self.line_ours + offset,
+                    self.line_theirs + offset,
+                ),
+                None => return self.finish(),
+            }
+        }
+    }
+
+    /// Find how many lines from the current position all three versions
+    /// share, or `None` if they share the rest of their lines.
+    fn find_mismatch(&self) -> Option<usize> {
+        let mut offset = 0;
+        while self.in_bounds(offset)
+            && self.match_ours.get(&(self.line_base + offset)) == Some(&(self.line_ours + offset))
+            && self.match_theirs.get(&(self.line_base + offset))
+                == Some(&(self.line_theirs + offset))
+        {
+            offset += 1;
+        }
+        match self.in_bounds(offset) {
+            true => Some(offset),
+            false => None,
+        }
+    }
+
+    fn in_bounds(&self, offset: usize) -> bool {
+        self.line_base + offset < self.base.len()
+            || self.line_ours + offset < self.ours.len()
+            || self.line_theirs + offset < self.theirs.len()
+    }
+
+    /// Find the next base line that survives into both other versions.
+    fn find_match(&self) -> Option<(usize, usize, usize)> {
+        (self.line_base..self.base.len()).find_map(|base| {
+            let ours = self.match_ours.get(&base)?;
+            let theirs = self.match_theirs.get(&base)?;
+            Some((base, *ours, *theirs))
+        })
+    }
+
+    fn emit(&mut self, base: usize, ours: usize, theirs: usize) {
+        let chunk = Self::chunk(
+            &self.base[self.line_base..base],
+            &self.ours[self.line_ours..ours],
+            &self.theirs[self.line_theirs..theirs],
+        );
+        self.chunks.push(chunk);
+        self.line_base = base;
+        self.line_ours = ours;
+        self.line_theirs = theirs;
+    }
+
+    fn finish(mut self) -> Vec<Chunk<'a>> {
+        let chunk = Self::chunk(
+            &self.base[self.line_base..],
+            &self.ours[self.line_ours..],
+            &self.theirs[self.line_theirs..],
+        );
+        self.chunks.push(chunk);
+        self.chunks
+    }
+
+    fn chunk(base: &[&'a [u8]], ours: &[&'a [u8]], theirs: &[&'a [u8]]) -> Chunk<'a> {
+        if ours == base || ours == theirs {
+            Chunk::Clean(theirs.to_vec())
+        } else if theirs == base {
+            Chunk::Clean(ours.to_vec())
+        } else {
+            Chunk::Conflict {
+                base: base.to_vec(),
+                ours: ours.to_vec(),
+                theirs: theirs.to_vec(),
+            }
+        }
+    }
+}
+
+#[test]
+fn clean() {
+    let merged = merge(
+        b"1\n2\n3\n4\n5\n6\n",
+        b"one\n2\n3\n4\n5\n6\n",
+        b"1\n2\n3\n4\n6\nseven\n",
+        "HEAD",
+        "topic",
+    );
+    assert!(merged.clean);
+    assert_eq!(merged.data, b"one\n2\n3\n4\n6\nseven\n");
+}
+
+#[test]
+fn conflict() {
+    let merged = merge(
+        b"a\nb\nc\n",
+        b"a\nours\nc\n",
+        b"a\ntheirs\nc\n",
+        "HEAD",
+        "topic",
+    );
+    assert!(!merged.clean);
+    assert_eq!(
+        merged.data,
+        &b"a\n<<<<<<< HEAD\nours\n=======\ntheirs\n>>>>>>> topic\nc\n"[..],
+    );
+}
@@ -4,14 +4,13 @@ use std::error;
 use std::fmt;
 use std::fs;
 use std::io;
-use std::num;
-use std::os::unix::fs::MetadataExt as _;
-use std::os::unix::fs::PermissionsExt as _;
+use std::str;
 
 use byteorder::BigEndian;
 use byteorder::ReadBytesExt as _;
 use byteorder::WriteBytesExt as _;
 
+use crate::platform;
 use crate::util::Tap as _;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -39,6 +38,23 @@ pub struct Metadata {
 }
 
 impl Metadata {
+    /// Metadata for a file whose stat information is unknown, e.g. one read
+    /// from a tree rather than the workspace.
+    pub fn unknown(mode: Mode, size: u32) -> Self {
+        Metadata {
+            ctime: 0,
+            ctime_nsec: 0,
+            mtime: 0,
+            mtime_nsec: 0,
+            dev: 0,
+            ino: 0,
+            mode,
+            uid: 0,
+            gid: 0,
+            size,
+        }
+    }
+
     pub fn mode(&self) -> &Mode {
         &self.mode
     }
@@ -72,6 +88,31 @@ impl Metadata {
         Ok(())
     }
 
+    /// Check whether a file with metadata `self` is unchanged since metadata
+    /// `indexed` was recorded, without reading its contents.
+    ///
+    /// Sub-second timestamps are ignored when either side is zero, since
+    /// some platforms and older index writers do not record them.
+    pub fn is_stat_clean(&self, indexed: &Metadata, check: CheckStat) -> bool {
+        let nsec = |a: u32, b: u32| a == b || a == 0 || b == 0;
+        let minimal = self.mode == indexed.mode
+            && self.size == indexed.size
+            && self.ctime == indexed.ctime
+            && self.mtime == indexed.mtime;
+
+        match check {
+            CheckStat::Minimal => minimal,
+            CheckStat::Default => {
+                minimal
+                    && nsec(self.ctime_nsec, indexed.ctime_nsec)
+                    && nsec(self.mtime_nsec, indexed.mtime_nsec)
+                    && self.ino == indexed.ino
+                    && self.uid == indexed.uid
+                    && self.gid == indexed.gid
+            }
+        }
+    }
+
     #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> usize {
         40
@@ -85,22 +126,49 @@ impl From<fs::Metadata> for Metadata {
 }
 
 impl From<&'_ fs::Metadata> for Metadata {
+    /// Like `git`, keep only the low 32 bits of each field, since the index
+    /// cannot represent more. Timestamps past 2106, large files, and 64-bit
+    /// inode numbers still compare consistently, as both sides are truncated.
     fn from(metadata: &fs::Metadata) -> Self {
-        (|| -> Result<Self, num::TryFromIntError> {
-            Ok(Self {
-                ctime: metadata.ctime().tap(u32::try_from)?,
-                ctime_nsec: metadata.ctime_nsec().tap(u32::try_from)?,
-                mtime: metadata.mtime().tap(u32::try_from)?,
-                mtime_nsec: metadata.mtime_nsec().tap(u32::try_from)?,
-                dev: metadata.dev().tap(u32::try_from)?,
-                ino: metadata.ino().tap(u32::try_from)?,
-                mode: Mode::from(metadata),
-                uid: metadata.uid(),
-                gid: metadata.gid(),
-                size: metadata.size().tap(u32::try_from)?,
-            })
-        })()
-        .expect("[INTERNAL ERROR]: could not cast `stat` field(s) to u32")
+        let stat = platform::stat(metadata);
+        Self {
+            ctime: stat.ctime as u32,
+            ctime_nsec: stat.ctime_nsec as u32,
+            mtime: stat.mtime as u32,
+            mtime_nsec: stat.mtime_nsec as u32,
+            dev: stat.dev as u32,
+            ino: stat.ino as u32,
+            mode: Mode::from(metadata),
+            uid: stat.uid,
+            gid: stat.gid,
+            size: stat.size as u32,
+        }
+    }
+}
+
+/// Which `stat` fields to trust when deciding whether a file may have
+/// changed since it was indexed, as configured by `core.checkStat`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Default)]
+pub enum CheckStat {
+    /// Compare all timestamps, inode number, owner, mode, and size.
+    #[default]
+    Default,
+    /// Compare only whole-second timestamps, mode, and size, for
+    /// filesystems that report unstable inode numbers or sub-second times.
+    Minimal,
+}
+
+impl str::FromStr for CheckStat {
+    type Err = anyhow::Error;
+    fn from_str(check: &str) -> Result<Self, Self::Err> {
+        match check {
+            "default" => Ok(CheckStat::Default),
+            "minimal" => Ok(CheckStat::Minimal),
+            _ => Err(anyhow::anyhow!(
+                "Invalid value for core.checkStat: {}",
+                check
+            )),
+        }
     }
 }
 
@@ -109,6 +177,8 @@ pub enum Mode {
     Directory,
     Regular,
     Executable,
+    /// Symbolic link, whose blob contains the link target.
+    Symlink,
 }
 
 impl Mode {
@@ -117,6 +187,7 @@ impl Mode {
             Mode::Directory => "40000",
             Mode::Regular => "100644",
             Mode::Executable => "100755",
+            Mode::Symlink => "120000",
         }
     }
 
@@ -125,6 +196,7 @@ impl Mode {
             Mode::Directory => 0o040000,
             Mode::Regular => 0o100644,
             Mode::Executable => 0o100755,
+            Mode::Symlink => 0o120000,
         }
     }
 
@@ -132,8 +204,13 @@ impl Mode {
         matches!(self, Self::Directory)
     }
 
+    /// Whether this is a leaf entry, i.e. anything but a directory.
     pub fn is_file(&self) -> bool {
-        matches!(self, Self::Regular | Self::Executable)
+        matches!(self, Self::Regular | Self::Executable | Self::Symlink)
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        matches!(self, Self::Symlink)
     }
 }
 
@@ -149,7 +226,10 @@ impl fmt::Display for InvalidMode {
             InvalidMode::Octal(mode) => write!(fmt, "Invalid value {}", mode)?,
             InvalidMode::Value(mode) => write!(fmt, "Invalid value {:#o}", mode)?,
         }
-        write!(fmt, ", expected 0o040000 or 0o100644 or 0o100755",)
+        write!(
+            fmt,
+            ", expected 0o040000 or 0o100644 or 0o100755 or 0o120000",
+        )
     }
 }
 
@@ -162,6 +242,7 @@ impl convert::TryFrom<u32> for Mode {
             0o040000 => Ok(Mode::Directory),
             0o100644 => Ok(Mode::Regular),
             0o100755 => Ok(Mode::Executable),
+            0o120000 => Ok(Mode::Symlink),
             invalid => Err(InvalidMode::Value(invalid)),
         }
     }
@@ -180,7 +261,9 @@ impl From<&'_ fs::Metadata> for Mode {
     fn from(metadata: &fs::Metadata) -> Self {
         if metadata.file_type().is_dir() {
             Mode::Directory
-        } else if metadata.permissions().mode() & 0o111 > 0 {
+        } else if metadata.file_type().is_symlink() {
+            Mode::Symlink
+        } else if platform::is_executable(metadata) {
             Mode::Executable
         } else {
             Mode::Regular
@@ -0,0 +1,579 @@
+use std::cmp;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::io;
+use std::panic;
+use std::path;
+use std::sync::atomic;
+use std::thread;
+
+use anyhow::anyhow;
+
+use crate::diff::tree;
+use crate::meta;
+use crate::object;
+use crate::util;
+
+/// Moves the index and workspace from one tree to another, given the
+/// changes between them.
+///
+/// Changes are only applied if none of them would clobber uncommitted work,
+/// so a failed migration leaves the index and workspace untouched.
+pub struct Migration<'a> {
+    database: &'a crate::Database,
+    index: &'a mut crate::Index,
+    workspace: &'a crate::Workspace,
+    check_stat: meta::CheckStat,
+    changes: tree::Changes,
+    operation: Operation,
+}
+
+/// Command performing a migration, named in conflict messages.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Operation {
+    #[default]
+    Checkout,
+    Merge,
+}
+
+impl Operation {
+    fn name(&self) -> &'static str {
+        match self {
+            Operation::Checkout => "checkout",
+            Operation::Merge => "merge",
+        }
+    }
+
+    fn action(&self) -> &'static str {
+        match self {
+            Operation::Checkout => "switch branches",
+            Operation::Merge => "merge",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Conflicts {
+    /// Tracked files whose index or workspace contents differ from the old tree.
+    modified: BTreeSet<util::PathBuf>,
+    /// Untracked files that the new tree would overwrite.
+    untracked: BTreeSet<util::PathBuf>,
+}
+
+impl<'a> Migration<'a> {
+    pub fn new(
+        database: &'a crate::Database,
+        index: &'a mut crate::Index,
+        workspace: &'a crate::Workspace,
+        check_stat: meta::CheckStat,
+        changes: tree::Changes,
+    ) -> Self {
+        Migration {
+            database,
+            index,
+            workspace,
+            check_stat,
+            changes,
+            operation: Operation::default(),
+        }
+    }
+
+    /// Describe conflicts as blocking `operation` rather than a checkout.
+    pub fn operation(mut self, operation: Operation) -> Self {
+        self.operation = operation;
+        self
+    }
+
+    /// Check for conflicts, then update the workspace and index.
+    /// The caller is responsible for committing the index.
+    pub fn apply(self) -> anyhow::Result<()> {
+        self.check()?;
+
+        for (path, _) in self
+            .changes
+            .iter()
+            .rev()
+            .filter(|(_, (old, _))| old.is_some())
+        {
+            self.workspace.remove(path)?;
+            if let Some(parent) = path.parent() {
+                self.workspace.remove_empty_directories(parent);
+            }
+            self.index.remove(path);
+        }
+
+        let files = self
+            .changes
+            .iter()
+            .filter_map(|(path, (_, new))| new.map(|new| (path.to_path_buf(), new.id, new.mode)))
+            .collect::<Vec<_>>();
+        let written = materialize(self.database, self.workspace, &files)?;
+        for ((path, id, _), metadata) in files.into_iter().zip(written) {
+            self.index.insert(metadata, id, path);
+        }
+
+        Ok(())
+    }
+
+    fn check(&self) -> anyhow::Result<()> {
+        let mut conflicts = Conflicts::default();
+
+        for (path, (old, new)) in &self.changes {
+            let staged = self.index.get(path).map(|entry| tree::Entry {
+                id: *entry.id(),
+                mode: *entry.metadata().mode(),
+            });
+
+            if staged != *old && staged != *new {
+                conflicts.modified.insert(path.clone());
+                continue;
+            }
+
+            match self.index.get(path) {
+                Some(entry) => {
+                    if !self.is_clean(entry)? && !self.matches(path, *new)? {
+                        conflicts.modified.insert(path.clone());
+                    }
+                }
+                None => {
+                    if self.is_untracked(path)? {
+                        conflicts.untracked.insert(path.clone());
+                    }
+                }
+            }
+
+            // An untracked file would block the creation of a parent directory.
+            if new.is_some() {
+                for ancestor in path.ancestors().skip(1) {
+                    if ancestor == path::Path::new("") {
+                        break;
+                    }
+                    if self.is_file(ancestor)? && !self.index.contains_file(ancestor) {
+                        conflicts
+                            .untracked
+                            .insert(util::PathBuf(ancestor.to_path_buf()));
+                    }
+                }
+            }
+        }
+
+        if conflicts.modified.is_empty() && conflicts.untracked.is_empty() {
+            return Ok(());
+        }
+
+        let mut message = String::new();
+        let mut describe = |paths: &BTreeSet<util::PathBuf>, header: &str, footer: &str| {
+            if paths.is_empty() {
+                return;
+            }
+            message.push_str(header);
+            message.push('\n');
+            for path in paths {
+                message.push('\t');
+                message.push_str(&path.display().to_string());
+                message.push('\n');
+            }
+            message.push_str(footer);
+            message.push('\n');
+        };
+
+        describe(
+            &conflicts.modified,
+            &format!(
+                "Your local changes to the following files would be overwritten by {}:",
+                self.operation.name(),
+            ),
+            &format!(
+                "Please commit your changes or stash them before you {}.",
+                self.operation.action(),
+            ),
+        );
+        describe(
+            &conflicts.untracked,
+            &format!(
+                "The following untracked working tree files would be overwritten by {}:",
+                self.operation.name(),
+            ),
+            &format!(
+                "Please move or remove them before you {}.",
+                self.operation.action(),
+            ),
+        );
+        message.push_str("Aborting");
+
+        Err(anyhow!(message))
+    }
+
+    /// Check whether the workspace file for `entry` matches the index,
+    /// treating a missing file as clean.
+    fn is_clean(&self, entry: &crate::index::Entry) -> anyhow::Result<bool> {
+        let metadata = match self.metadata(entry.path())? {
+            None => return Ok(true),
+            Some(metadata) => metadata,
+        };
+
+        let old = entry.metadata();
+        let metadata = self.workspace.normalize(metadata, old.mode);
+        // A size of zero may have been smudged by a racy write.
+        if metadata.mode.is_directory()
+            || metadata.mode != old.mode
+            || (metadata.size != old.size && old.size != 0)
+        {
+            return Ok(false);
+        }
+
+        if metadata.is_stat_clean(old, self.check_stat) {
+            return Ok(true);
+        }
+
+        Ok(self.hash(entry.path())? == *entry.id())
+    }
+
+    /// Check whether the workspace file at `path` already matches `new`.
+    fn matches(&self, path: &path::Path, new: Option<tree::Entry>) -> anyhow::Result<bool> {
+        let new = match new {
+            None => return Ok(false),
+            Some(new) => new,
+        };
+        match self.metadata(path)? {
+            Some(metadata) if self.workspace.normalize(metadata, new.mode).mode == new.mode => {
+                Ok(self.hash(path)? == new.id)
+            }
+            Some(_) | None => Ok(false),
+        }
+    }
+
+    /// Check whether `path` holds a file, or a directory containing files,
+    /// that the index does not know about. Empty directories don't count,
+    /// since there is nothing in them to lose.
+    fn is_untracked(&self, path: &path::Path) -> anyhow::Result<bool> {
+        match self.metadata(path)? {
+            Some(metadata) if metadata.mode.is_directory() => {
+                Ok(!self.index.contains_directory(path) && self.workspace.contains_files(path)?)
+            }
+            Some(_) => Ok(true),
+            None => Ok(false),
+        }
+    }
+
+    fn is_file(&self, path: &path::Path) -> anyhow::Result<bool> {
+        Ok(self
+            .metadata(path)?
+            .is_some_and(|metadata| metadata.mode.is_file()))
+    }
+
+    /// Read metadata for `path`, or `None` if nothing exists there.
+    fn metadata(&self, path: &path::Path) -> io::Result<Option<meta::Metadata>> {
+        match self.workspace.metadata(path) {
+            Ok(metadata) => Ok(Some(metadata)),
+            Err(error)
+                if error.kind() == io::ErrorKind::NotFound
+                    || error.kind() == io::ErrorKind::NotADirectory =>
+            {
+                Ok(None)
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    fn hash(&self, path: &path::Path) -> anyhow::Result<object::Id> {
+        let blob = self
+            .workspace
+            .read(path)
+            .map(object::Blob::new)
+            .map(crate::Object::Blob)?;
+        Ok(object::Id::hash(&blob.to_bytes()))
+    }
+}
+
+/// Forcibly move the index and workspace to tree `tree`, discarding changes
+/// to tracked files like `git reset --hard`. Untracked files are left alone.
+/// The caller is responsible for committing the index.
+pub fn reset(
+    database: &crate::Database,
+    index: &mut crate::Index,
+    workspace: &crate::Workspace,
+    check_stat: meta::CheckStat,
+    tree: &object::Id,
+) -> anyhow::Result<()> {
+    // Tracked files, including unmerged ones, that the workspace should no
+    // longer contain.
+    let tracked = index
+        .entries()
+        .map(|entry| entry.path())
+        .chain(index.conflicts().map(|(path, _)| path))
+        .map(path::Path::to_path_buf)
+        .collect::<Vec<_>>();
+
+    index.load_tree(database, tree)?;
+
+    for path in tracked.iter().rev() {
+        if index.get(path).is_none() {
+            workspace.remove(path)?;
+            if let Some(parent) = path.parent() {
+                workspace.remove_empty_directories(parent);
+            }
+        }
+    }
+
+    // Write every indexed file whose workspace copy may differ, refreshing
+    // its stat information.
+    let stale = index
+        .entries()
+        .filter(|entry| {
+            let indexed = entry.metadata();
+            match workspace.metadata(entry.path()) {
+                Err(_) => true,
+                Ok(metadata) => !workspace
+                    .normalize(metadata, indexed.mode)
+                    .is_stat_clean(indexed, check_stat),
+            }
+        })
+        .map(|entry| {
+            let mode = *entry.metadata().mode();
+            (entry.path().to_path_buf(), *entry.id(), mode)
+        })
+        .collect::<Vec<_>>();
+
+    let written = materialize(database, workspace, &stale)?;
+    for ((path, id, _), metadata) in stale.into_iter().zip(written) {
+        index.insert(metadata, id, path);
+    }
+
+    Ok(())
+}
+
+/// Fewest files worth splitting between workers, like `git`'s default
+/// `checkout.thresholdForParallelism`.
+const PARALLEL_THRESHOLD: usize = 100;
+
+/// Write blobs `files` into the workspace, returning the resulting metadata
+/// of each in order.
+///
+/// Large checkouts are split between the workspace's workers, which each
+/// open their own handle to the database, and inflate and write one blob at
+/// a time to bound memory use.
+fn materialize(
+    database: &crate::Database,
+    workspace: &crate::Workspace,
+    files: &[(path::PathBuf, object::Id, meta::Mode)],
+) -> anyhow::Result<Vec<meta::Metadata>> {
+    let write = |database: &crate::Database,
+                 (path, id, mode): &(path::PathBuf, object::Id, meta::Mode)|
+     -> anyhow::Result<meta::Metadata> {
+        let data = database.load_blob(id)?.into_data();
+        {
+            // Finish the file in progress, but write no more, on interrupt.
+            let _deferred = crate::interrupt::defer();
+            workspace.write(path, &data, *mode)?;
+        }
+        let metadata = workspace.metadata(path)?;
+        Ok(workspace.normalize(metadata, *mode))
+    };
+
+    let workers = cmp::min(workspace.workers(), files.len());
+    let root = match database.root() {
+        Some(root) if workers > 1 && files.len() >= PARALLEL_THRESHOLD => root,
+        _ => return files.iter().map(|file| write(database, file)).collect(),
+    };
+
+    let next = atomic::AtomicUsize::new(0);
+    let (next, write) = (&next, &write);
+    let written = thread::scope(|scope| {
+        (0..workers)
+            .map(|_| {
+                scope.spawn(move || {
+                    let database = crate::Database::open(root.to_path_buf());
+                    let mut written = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, atomic::Ordering::Relaxed);
+                        let file = match files.get(index) {
+                            None => return Ok(written),
+                            Some(file) => file,
+                        };
+                        match write(&database, file) {
+                            Ok(metadata) => written.push((index, metadata)),
+                            Err(error) => {
+                                // Stop the other workers early.
+                                next.store(files.len(), atomic::Ordering::Relaxed);
+                                return Err(error);
+                            }
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|worker| {
+                worker
+                    .join()
+                    .unwrap_or_else(|panic| panic::resume_unwind(panic))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()
+    })?;
+
+    let mut ordered = vec![None; files.len()];
+    for (index, metadata) in written.into_iter().flatten() {
+        ordered[index] = Some(metadata);
+    }
+    ordered
+        .into_iter()
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| anyhow!("[INTERNAL ERROR]: checkout worker skipped a file"))
+}
+
+/// Store tracked files as they currently exist in the workspace, returning
+/// the id of their tree. Files deleted from the workspace are left out, and
+/// untracked files are ignored.
+pub fn snapshot(
+    database: &crate::Database,
+    index: &crate::Index,
+    workspace: &crate::Workspace,
+    check_stat: meta::CheckStat,
+) -> anyhow::Result<object::Id> {
+    let mut files = BTreeMap::new();
+    for entry in index.entries() {
+        let indexed = entry.metadata();
+        let metadata = match workspace.metadata(entry.path()) {
+            Ok(metadata) => workspace.normalize(metadata, indexed.mode),
+            Err(error)
+                if error.kind() == io::ErrorKind::NotFound
+                    || error.kind() == io::ErrorKind::NotADirectory =>
+            {
+                continue
+            }
+            Err(error) => return Err(error.into()),
+        };
+
+        let file = match metadata.mode {
+            // Replaced by a directory, whose files are untracked.
+            meta::Mode::Directory => continue,
+            _ if metadata.is_stat_clean(indexed, check_stat) => tree::Entry {
+                id: *entry.id(),
+                mode: indexed.mode,
+            },
+            mode => tree::Entry {
+                id: database.store(&crate::Object::Blob(object::Blob::new(
+                    workspace.read(entry.path())?,
+                )))?,
+                mode,
+            },
+        };
+        files.insert(util::PathBuf(entry.path().to_path_buf()), file);
+    }
+    tree::unflatten(database, &files)
+}
+
+#[test]
+fn parallel_checkout() -> anyhow::Result<()> {
+    let root = crate::util::TempDir::new();
+    let mut repository = crate::Repository::new(root.to_path_buf());
+    repository.init()?;
+
+    let database = repository.database()?;
+    let mut workspace = repository.workspace()?;
+    workspace.set_workers(4);
+
+    let files = (0..PARALLEL_THRESHOLD * 2)
+        .map(|file| {
+            let blob = object::Blob::new(format!("file {}\n", file).into_bytes());
+            let id = database.store(&crate::Object::Blob(blob))?;
+            let path = path::PathBuf::from(format!("dir{}/{}", file % 7, file));
+            Ok((path, id, meta::Mode::Regular))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let written = materialize(&database, &workspace, &files);
+    let contents = files
+        .iter()
+        .map(|(path, _, _)| workspace.read(path))
+        .collect::<io::Result<Vec<_>>>();
+
+    for (((_, _, mode), metadata), (file, data)) in files
+        .iter()
+        .zip(written?)
+        .zip(contents?.into_iter().enumerate())
+    {
+        assert_eq!(data, format!("file {}\n", file).into_bytes());
+        assert_eq!(metadata.mode, *mode);
+        assert_eq!(metadata.size as usize, data.len());
+    }
+    Ok(())
+}
+
+#[test]
+fn empty_directory() -> anyhow::Result<()> {
+    let root = crate::util::TempDir::new();
+    let mut repository = crate::Repository::new(root.to_path_buf());
+    repository.init()?;
+
+    let database = repository.database()?;
+    let workspace = repository.workspace()?;
+    let id = database.store(&crate::Object::Blob(object::Blob::new(b"file\n".to_vec())))?;
+
+    // Empty directories are replaced, but not directories holding files.
+    std::fs::create_dir_all(root.join("empty/nested"))?;
+    std::fs::create_dir_all(root.join("full/nested"))?;
+    std::fs::write(root.join("full/nested/file"), b"")?;
+    let contains = [
+        workspace.contains_files(path::Path::new("empty"))?,
+        workspace.contains_files(path::Path::new("full"))?,
+    ];
+    let empty = materialize(
+        &database,
+        &workspace,
+        &[(path::PathBuf::from("empty"), id, meta::Mode::Regular)],
+    );
+    let full = materialize(
+        &database,
+        &workspace,
+        &[(path::PathBuf::from("full"), id, meta::Mode::Regular)],
+    );
+    let data = workspace.read(path::Path::new("empty"));
+
+    assert_eq!(contains, [false, true]);
+    assert!(empty.is_ok());
+    assert!(full.is_err());
+    assert_eq!(data?, b"file\n");
+    Ok(())
+}
+
+#[test]
+fn prune_directories() -> anyhow::Result<()> {
+    let root = crate::util::TempDir::new();
+    let mut repository = crate::Repository::new(root.to_path_buf());
+    repository.init()?;
+
+    let database = repository.database()?;
+    let workspace = repository.workspace()?;
+    let mut index = repository.index()?;
+    let id = database.store(&crate::Object::Blob(object::Blob::new(b"file\n".to_vec())))?;
+
+    let files = ["a/b/c/file", "a/file", "kept/file", "file"]
+        .iter()
+        .map(|path| (path::PathBuf::from(path), id, meta::Mode::Regular))
+        .collect::<Vec<_>>();
+    let written = materialize(&database, &workspace, &files)?;
+    for ((path, id, _), metadata) in files.into_iter().zip(written) {
+        index.insert(metadata, id, path);
+    }
+    std::fs::write(root.join("kept/untracked"), b"")?;
+
+    // Removing every tracked file prunes directories left empty, but not
+    // those holding untracked files, or the root.
+    let empty = database.store(&crate::Object::Tree(object::tree::Root::new(Vec::new())))?;
+    reset(
+        &database,
+        &mut index,
+        &workspace,
+        meta::CheckStat::default(),
+        &empty,
+    )?;
+    let exists = ["a", "kept", "kept/untracked"].map(|path| root.join(path).exists());
+    let root_exists = root.exists();
+    drop(index);
+
+    assert_eq!(exists, [false, true, true]);
+    assert!(root_exists);
+    Ok(())
+}
@@ -5,6 +5,7 @@ use std::io;
 use std::path;
 use std::str;
 
+use anyhow::anyhow;
 use sha1::Sha1;
 
 use crate::util::hex;
@@ -13,17 +14,21 @@ use crate::util::Tap as _;
 mod blob;
 mod commit;
 mod person;
+mod tag;
 pub mod tree;
 
 pub use blob::Blob;
 pub use commit::Commit;
+pub use person::DateFormat;
 pub use person::Person;
+pub use tag::Tag;
 
 #[derive(Clone, Debug)]
 pub enum Object {
     Blob(Blob),
     Commit(Commit),
     Tree(tree::Root),
+    Tag(Tag),
 }
 
 impl Object {
@@ -36,21 +41,24 @@ impl Object {
     }
 
     pub fn read<R: io::BufRead>(reader: &mut R) -> anyhow::Result<Self> {
-        let mut r#type = Vec::new();
-        reader.read_until(b' ', &mut r#type)?;
-        assert_eq!(r#type.pop(), Some(b' '));
+        let header = Header::read(reader)?;
 
-        // TODO: validate length when parsing
-        let mut len = Vec::new();
-        reader.read_until(0, &mut len)?;
-        assert_eq!(len.pop(), Some(0));
-        let _len = String::from_utf8(len).unwrap().parse::<usize>().unwrap();
-
-        match &*r#type {
-            Blob::TYPE => Blob::read(reader).map(Object::Blob),
-            Commit::TYPE => Commit::read(reader).map(Object::Commit),
-            tree::Root::TYPE => tree::Root::read(reader).map(Object::Tree),
-            _ => unreachable!(),
+        let mut payload = Vec::with_capacity(header.len);
+        reader.read_to_end(&mut payload)?;
+        if payload.len() != header.len {
+            return Err(anyhow!(
+                "Expected {} object of length {}, but found {} bytes",
+                header.r#type,
+                header.len,
+                payload.len(),
+            ));
+        }
+
+        match header.r#type {
+            Type::Blob => Ok(Object::Blob(Blob::new(payload))),
+            Type::Commit => Commit::read(&mut &*payload).map(Object::Commit),
+            Type::Tree => tree::Root::read(&mut &*payload).map(Object::Tree),
+            Type::Tag => Tag::read(&mut &*payload).map(Object::Tag),
         }
     }
 
@@ -64,6 +72,7 @@ impl Object {
             Object::Blob(blob) => blob.write(writer),
             Object::Commit(commit) => commit.write(writer),
             Object::Tree(tree) => tree.write(writer),
+            Object::Tag(tag) => tag.write(writer),
         }
     }
 
@@ -72,6 +81,7 @@ impl Object {
             Object::Blob(_) => Blob::TYPE,
             Object::Commit(_) => Commit::TYPE,
             Object::Tree(_) => tree::Root::TYPE,
+            Object::Tag(_) => Tag::TYPE,
         }
     }
 
@@ -80,10 +90,81 @@ impl Object {
             Object::Blob(blob) => blob.len(),
             Object::Commit(commit) => commit.len(),
             Object::Tree(tree) => tree.len(),
+            Object::Tag(tag) => tag.len(),
+        }
+    }
+}
+
+/// Kind of object named in a serialized object's header.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Type {
+    Blob,
+    Commit,
+    Tree,
+    Tag,
+}
+
+impl Type {
+    pub fn as_bytes(&self) -> &'static [u8] {
+        match self {
+            Type::Blob => Blob::TYPE,
+            Type::Commit => Commit::TYPE,
+            Type::Tree => tree::Root::TYPE,
+            Type::Tag => Tag::TYPE,
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let r#type = str::from_utf8(self.as_bytes()).expect("[INTERNAL ERROR]: non-UTF-8 type");
+        write!(fmt, "{}", r#type)
+    }
+}
+
+impl str::FromStr for Type {
+    type Err = anyhow::Error;
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string.as_bytes() {
+            Blob::TYPE => Ok(Type::Blob),
+            Commit::TYPE => Ok(Type::Commit),
+            tree::Root::TYPE => Ok(Type::Tree),
+            Tag::TYPE => Ok(Type::Tag),
+            _ => Err(anyhow!("Unknown object type: {}", string)),
         }
     }
 }
 
+/// The `<type> <len>\0` prefix of a serialized object.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Header {
+    pub r#type: Type,
+    /// Length of the payload following the header, in bytes.
+    pub len: usize,
+}
+
+impl Header {
+    /// Read only the header from the start of a serialized object.
+    pub fn read<R: io::BufRead>(reader: &mut R) -> anyhow::Result<Self> {
+        let mut r#type = Vec::new();
+        reader.read_until(b' ', &mut r#type)?;
+        if r#type.pop() != Some(b' ') {
+            return Err(anyhow!("Unterminated object type"));
+        }
+
+        let mut len = Vec::new();
+        reader.read_until(0, &mut len)?;
+        if len.pop() != Some(0) {
+            return Err(anyhow!("Unterminated object length"));
+        }
+
+        Ok(Header {
+            r#type: str::from_utf8(&r#type)?.parse()?,
+            len: str::from_utf8(&len)?.parse()?,
+        })
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Id([u8; 20]);
 
@@ -92,6 +173,10 @@ impl Id {
         Self(Sha1::from(bytes).digest().bytes())
     }
 
+    pub fn from_bytes(bytes: [u8; 20]) -> Self {
+        Self(bytes)
+    }
+
     pub fn as_bytes(&self) -> &[u8; 20] {
         &self.0
     }
@@ -130,6 +215,30 @@ impl Id {
         path::PathBuf::from(buffer)
     }
 
+    /// Resolve this id to a commit, following annotated tags, and failing
+    /// if it names any other object.
+    pub fn peel_to_commit(&self, database: &crate::Database) -> anyhow::Result<Id> {
+        match database.kind(self)? {
+            Type::Commit => Ok(*self),
+            Type::Tag => database.load_tag(self)?.object().peel_to_commit(database),
+            r#type => Err(anyhow!("Expected commit object: {} is a {}", self, r#type)),
+        }
+    }
+
+    /// Resolve this id to a tree, following annotated tags and commits.
+    pub fn peel_to_tree(&self, database: &crate::Database) -> anyhow::Result<Id> {
+        match database.kind(self)? {
+            Type::Tree => Ok(*self),
+            Type::Commit => Ok(*database.load_commit(self)?.tree()),
+            Type::Tag => database.load_tag(self)?.object().peel_to_tree(database),
+            r#type => Err(anyhow!(
+                "Expected tree-ish object: {} is a {}",
+                self,
+                r#type
+            )),
+        }
+    }
+
     pub fn write_bytes<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
         writer.write_all(&self.0)
     }
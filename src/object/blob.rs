@@ -10,6 +10,14 @@ impl Blob {
         Blob(data)
     }
 
+    pub fn data(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn into_data(self) -> Vec<u8> {
+        self.0
+    }
+
     pub fn read<R: io::Read>(reader: &mut R) -> anyhow::Result<Self> {
         let mut buffer = Vec::new();
         reader.read_to_end(&mut buffer)?;
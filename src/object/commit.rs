@@ -9,8 +9,9 @@ use crate::object::Person;
 #[derive(Clone, Debug)]
 pub struct Commit {
     tree: object::Id,
-    parent: Option<object::Id>,
+    parents: Vec<object::Id>,
     author: Person,
+    committer: Person,
     message: String,
 }
 
@@ -19,14 +20,16 @@ impl Commit {
 
     pub fn new(
         tree: object::Id,
-        parent: Option<object::Id>,
+        parents: Vec<object::Id>,
         author: Person,
+        committer: Person,
         message: String,
     ) -> Self {
         Commit {
             tree,
-            parent,
+            parents,
             author,
+            committer,
             message,
         }
     }
@@ -39,6 +42,32 @@ impl Commit {
         &self.tree
     }
 
+    /// First parent, i.e. the commit this one was made on top of.
+    pub fn parent(&self) -> Option<&object::Id> {
+        self.parents.first()
+    }
+
+    /// All parents, in order. Merge commits have more than one.
+    pub fn parents(&self) -> &[object::Id] {
+        &self.parents
+    }
+
+    /// Person who originally wrote the change.
+    pub fn author(&self) -> &Person {
+        &self.author
+    }
+
+    /// Person who last recorded the change, e.g. when applying someone
+    /// else's patch.
+    pub fn committer(&self) -> &Person {
+        &self.committer
+    }
+
+    /// First line of the commit message.
+    pub fn title(&self) -> &str {
+        self.message.lines().next().unwrap_or_default()
+    }
+
     pub fn read<R: io::BufRead>(reader: &mut R) -> anyhow::Result<Self> {
         let mut tag = Vec::new();
         reader.read_until(b' ', &mut tag)?;
@@ -50,15 +79,13 @@ impl Commit {
         tag.clear();
         reader.read_until(b' ', &mut tag)?;
 
-        let parent = if tag == b"parent " {
-            let parent = object::Id::read_hex(reader)?;
+        let mut parents = Vec::new();
+        while tag == b"parent " {
+            parents.push(object::Id::read_hex(reader)?);
             assert_eq!(reader.read_u8()?, b'\n');
             tag.clear();
             reader.read_until(b' ', &mut tag)?;
-            Some(parent)
-        } else {
-            None
-        };
+        }
 
         assert_eq!(tag, b"author ");
         let author = Person::read(reader)?;
@@ -67,9 +94,8 @@ impl Commit {
         tag.clear();
         reader.read_until(b' ', &mut tag)?;
 
-        // TODO: store committer separately
         assert_eq!(tag, b"committer ");
-        let _committer = Person::read(reader)?;
+        let committer = Person::read(reader)?;
         assert_eq!(reader.read_u8()?, b'\n');
         assert_eq!(reader.read_u8()?, b'\n');
 
@@ -77,8 +103,9 @@ impl Commit {
         reader.read_to_string(&mut message)?;
         Ok(Commit {
             tree,
-            parent,
+            parents,
             author,
+            committer,
             message,
         })
     }
@@ -87,7 +114,7 @@ impl Commit {
         writer.write_all(b"tree ")?;
         self.tree.write_hex(writer)?;
 
-        if let Some(parent) = self.parent {
+        for parent in &self.parents {
             writer.write_all(b"\nparent ")?;
             parent.write_hex(writer)?;
         }
@@ -96,7 +123,7 @@ impl Commit {
         self.author.write(writer)?;
 
         writer.write_all(b"\ncommitter ")?;
-        self.author.write(writer)?;
+        self.committer.write(writer)?;
 
         writer.write_all(b"\n\n")?;
         writer.write_all(self.message.as_bytes())
@@ -104,16 +131,68 @@ impl Commit {
 
     pub fn len(&self) -> usize {
         5 + self.tree.as_bytes().len() * 2
-            + if let Some(parent) = self.parent {
-                8 + parent.as_bytes().len() * 2
-            } else {
-                0
-            }
+            + self
+                .parents
+                .iter()
+                .map(|parent| 8 + parent.as_bytes().len() * 2)
+                .sum::<usize>()
             + 8
             + self.author.len()
             + 11
-            + self.author.len()
+            + self.committer.len()
             + 2
             + self.message.len()
     }
 }
+
+#[test]
+fn octopus() -> anyhow::Result<()> {
+    use chrono::TimeZone as _;
+
+    let parents = (0..3u8)
+        .map(|byte| object::Id::hash(&[byte]))
+        .collect::<Vec<_>>();
+    let author = Person::new(
+        String::from("A U Thor"),
+        String::from("author@example.com"),
+        chrono::FixedOffset::east_opt(2 * 3600)
+            .unwrap()
+            .timestamp_opt(1_600_000_000, 0)
+            .unwrap(),
+    );
+    let committer = Person::new(
+        String::from("C O Mitter"),
+        String::from("committer@example.com"),
+        Person::parse_time("@1600000100 -0500")?,
+    );
+    let commit = Commit::new(
+        object::Id::hash(b"tree"),
+        parents.clone(),
+        author,
+        committer,
+        String::from("Merge branches 'a', 'b', and 'c'\n"),
+    );
+
+    let mut buffer = Vec::new();
+    commit.write(&mut buffer)?;
+    assert_eq!(buffer.len(), commit.len());
+    assert_eq!(
+        buffer
+            .split(|byte| *byte == b'\n')
+            .filter(|line| line.starts_with(b"parent "))
+            .count(),
+        3,
+    );
+
+    let read = Commit::read(&mut &*buffer)?;
+    assert_eq!(read.parents(), &*parents);
+    assert_eq!(read.parent(), parents.first());
+    assert_eq!(read.tree(), commit.tree());
+    assert_eq!(read.author().time(), commit.author().time());
+    assert_eq!(read.committer().name(), "C O Mitter");
+    assert_eq!(
+        read.committer().time().to_rfc3339(),
+        "2020-09-13T07:28:20-05:00"
+    );
+    Ok(())
+}
@@ -2,18 +2,46 @@ use std::io;
 use std::io::Write as _;
 use std::str;
 
+use anyhow::anyhow;
+
 #[derive(Clone, Debug)]
 pub struct Person {
     name: String,
     email: String,
-    time: chrono::DateTime<chrono::Local>,
+    time: chrono::DateTime<chrono::FixedOffset>,
 }
 
 impl Person {
-    pub fn new(name: String, email: String, time: chrono::DateTime<chrono::Local>) -> Self {
+    pub fn new(name: String, email: String, time: chrono::DateTime<chrono::FixedOffset>) -> Self {
         Person { name, email, time }
     }
 
+    /// Parse a date as given in `GIT_AUTHOR_DATE` or `GIT_COMMITTER_DATE`:
+    /// either `git`'s internal `[@]<seconds> <+hhmm>` format, RFC 2822, or
+    /// ISO 8601.
+    pub fn parse_time(string: &str) -> anyhow::Result<chrono::DateTime<chrono::FixedOffset>> {
+        let string = string.trim();
+        let internal = string.strip_prefix('@').unwrap_or(string);
+
+        chrono::DateTime::parse_from_str(internal, "%s %z")
+            .or_else(|_| chrono::DateTime::parse_from_rfc2822(string))
+            .or_else(|_| chrono::DateTime::parse_from_rfc3339(string))
+            .or_else(|_| chrono::DateTime::parse_from_str(string, "%Y-%m-%d %H:%M:%S %z"))
+            .map_err(|_| anyhow!("Invalid date format: {}", string))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn email(&self) -> &str {
+        &self.email
+    }
+
+    pub fn time(&self) -> &chrono::DateTime<chrono::FixedOffset> {
+        &self.time
+    }
+
     pub fn read<R: io::BufRead>(reader: &mut R) -> anyhow::Result<Self> {
         let mut name = Vec::new();
         reader.read_until(b'<', &mut name)?;
@@ -32,9 +60,8 @@ impl Person {
         let lo = time.len();
         time.extend([0; 5]);
         reader.read_exact(&mut time[lo..])?;
-        let time = str::from_utf8(&time)
-            .map(|time| chrono::DateTime::parse_from_str(time, "%s %z"))?
-            .map(|time| time.with_timezone(&chrono::Local))?;
+        let time =
+            str::from_utf8(&time).map(|time| chrono::DateTime::parse_from_str(time, "%s %z"))??;
 
         Ok(Self { name, email, time })
     }
@@ -58,3 +85,117 @@ impl Person {
         self.name.len() + 2 + self.email.len() + 2 + cursor.position() as usize + 1 + 5
     }
 }
+
+/// How to show dates, as chosen by `--date` or `log.date`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum DateFormat {
+    /// `Thu Oct 15 21:05:02 2026 +0000`
+    #[default]
+    Default,
+    /// [`DateFormat::Default`] in the local time zone, without an offset.
+    Local,
+    /// `2026-10-15 21:05:02 +0000`
+    Iso,
+    /// `2026-10-15T21:05:02+00:00`
+    IsoStrict,
+    /// `Thu, 15 Oct 2026 21:05:02 +0000`
+    Rfc,
+    /// `2026-10-15`
+    Short,
+    /// `1792098302 +0000`
+    Raw,
+    /// `1792098302`
+    Unix,
+    /// `2 hours ago`
+    Relative,
+}
+
+impl DateFormat {
+    pub fn format(&self, time: &chrono::DateTime<chrono::FixedOffset>) -> String {
+        match self {
+            DateFormat::Default => time.format("%a %b %-d %H:%M:%S %Y %z").to_string(),
+            DateFormat::Local => time
+                .with_timezone(&chrono::Local)
+                .format("%a %b %-d %H:%M:%S %Y")
+                .to_string(),
+            DateFormat::Iso => time.format("%Y-%m-%d %H:%M:%S %z").to_string(),
+            DateFormat::IsoStrict => time.format("%Y-%m-%dT%H:%M:%S%:z").to_string(),
+            DateFormat::Rfc => time.format("%a, %-d %b %Y %H:%M:%S %z").to_string(),
+            DateFormat::Short => time.format("%Y-%m-%d").to_string(),
+            DateFormat::Raw => time.format("%s %z").to_string(),
+            DateFormat::Unix => time.format("%s").to_string(),
+            DateFormat::Relative => relative(chrono::Utc::now().timestamp() - time.timestamp()),
+        }
+    }
+}
+
+impl str::FromStr for DateFormat {
+    type Err = anyhow::Error;
+    fn from_str(format: &str) -> anyhow::Result<Self> {
+        match format {
+            "default" => Ok(DateFormat::Default),
+            "local" | "default-local" => Ok(DateFormat::Local),
+            "iso" | "iso8601" => Ok(DateFormat::Iso),
+            "iso-strict" | "iso8601-strict" => Ok(DateFormat::IsoStrict),
+            "rfc" | "rfc2822" => Ok(DateFormat::Rfc),
+            "short" => Ok(DateFormat::Short),
+            "raw" => Ok(DateFormat::Raw),
+            "unix" => Ok(DateFormat::Unix),
+            "relative" => Ok(DateFormat::Relative),
+            _ => Err(anyhow!("Unknown date format: {}", format)),
+        }
+    }
+}
+
+/// Describe a date `seconds` in the past, rounding like `git`.
+fn relative(seconds: i64) -> String {
+    let unit = |count: i64, unit: &str| match count {
+        1 => format!("1 {}", unit),
+        count => format!("{} {}s", count, unit),
+    };
+
+    if seconds < 0 {
+        return String::from("in the future");
+    }
+    if seconds < 90 {
+        return format!("{} ago", unit(seconds, "second"));
+    }
+    let minutes = (seconds + 30) / 60;
+    if minutes < 90 {
+        return format!("{} ago", unit(minutes, "minute"));
+    }
+    let hours = (minutes + 30) / 60;
+    if hours < 36 {
+        return format!("{} ago", unit(hours, "hour"));
+    }
+    let days = (hours + 12) / 24;
+    if days < 14 {
+        return format!("{} ago", unit(days, "day"));
+    }
+    if days < 70 {
+        return format!("{} ago", unit((days + 3) / 7, "week"));
+    }
+    if days < 365 {
+        return format!("{} ago", unit((days + 15) / 30, "month"));
+    }
+    if days < 1825 {
+        let months = (days * 12 * 2 + 365) / (365 * 2);
+        return match months % 12 {
+            0 => format!("{} ago", unit(months / 12, "year")),
+            rest => format!("{}, {} ago", unit(months / 12, "year"), unit(rest, "month")),
+        };
+    }
+    format!("{} ago", unit((days + 183) / 365, "year"))
+}
+
+#[test]
+fn relative_dates() {
+    assert_eq!(relative(1), "1 second ago");
+    assert_eq!(relative(89), "89 seconds ago");
+    assert_eq!(relative(90), "2 minutes ago");
+    assert_eq!(relative(36 * 3600), "2 days ago");
+    assert_eq!(relative(20 * 86400), "3 weeks ago");
+    assert_eq!(relative(400 * 86400), "1 year, 1 month ago");
+    assert_eq!(relative(3000 * 86400), "8 years ago");
+    assert_eq!(relative(-5), "in the future");
+}
@@ -0,0 +1,173 @@
+use std::io;
+use std::str;
+
+use anyhow::anyhow;
+
+use crate::object;
+use crate::object::Person;
+
+/// An annotated tag, which names another object along with who tagged it
+/// and why.
+#[derive(Clone, Debug)]
+pub struct Tag {
+    object: object::Id,
+    r#type: object::Type,
+    name: String,
+    /// Missing from some tags made by very old versions of `git`.
+    tagger: Option<Person>,
+    message: String,
+}
+
+impl Tag {
+    pub const TYPE: &'static [u8] = b"tag";
+
+    pub fn new(
+        object: object::Id,
+        r#type: object::Type,
+        name: String,
+        tagger: Option<Person>,
+        message: String,
+    ) -> Self {
+        Tag {
+            object,
+            r#type,
+            name,
+            tagger,
+            message,
+        }
+    }
+
+    /// Object being tagged, usually a commit.
+    pub fn object(&self) -> &object::Id {
+        &self.object
+    }
+
+    pub fn r#type(&self) -> object::Type {
+        self.r#type
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn tagger(&self) -> Option<&Person> {
+        self.tagger.as_ref()
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// First line of the tag message.
+    pub fn title(&self) -> &str {
+        self.message.lines().next().unwrap_or_default()
+    }
+
+    pub fn read<R: io::BufRead>(reader: &mut R) -> anyhow::Result<Self> {
+        let mut object = None;
+        let mut r#type = None;
+        let mut name = None;
+        let mut tagger = None;
+
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            reader.read_until(b'\n', &mut line)?;
+            match line.pop() {
+                Some(b'\n') => (),
+                _ => return Err(anyhow!("Unterminated tag header")),
+            }
+            if line.is_empty() {
+                break;
+            }
+
+            let split = line
+                .iter()
+                .position(|byte| *byte == b' ')
+                .ok_or_else(|| anyhow!("Malformed tag header"))?;
+            let (key, value) = (&line[..split], &line[split + 1..]);
+            match key {
+                b"object" => object = Some(object::Id::read_hex(&mut &*value)?),
+                b"type" => r#type = Some(str::from_utf8(value)?.parse()?),
+                b"tag" => name = Some(String::from_utf8(value.to_vec())?),
+                b"tagger" => tagger = Some(Person::read(&mut &*value)?),
+                // Ignore unknown headers, like `git`.
+                _ => (),
+            }
+        }
+
+        let mut message = String::new();
+        reader.read_to_string(&mut message)?;
+
+        Ok(Tag {
+            object: object.ok_or_else(|| anyhow!("Missing tag object"))?,
+            r#type: r#type.ok_or_else(|| anyhow!("Missing tag type"))?,
+            name: name.ok_or_else(|| anyhow!("Missing tag name"))?,
+            tagger,
+            message,
+        })
+    }
+
+    pub fn write<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(b"object ")?;
+        self.object.write_hex(writer)?;
+
+        writer.write_all(b"\ntype ")?;
+        writer.write_all(self.r#type.as_bytes())?;
+
+        writer.write_all(b"\ntag ")?;
+        writer.write_all(self.name.as_bytes())?;
+
+        if let Some(tagger) = &self.tagger {
+            writer.write_all(b"\ntagger ")?;
+            tagger.write(writer)?;
+        }
+
+        writer.write_all(b"\n\n")?;
+        writer.write_all(self.message.as_bytes())
+    }
+
+    pub fn len(&self) -> usize {
+        7 + self.object.as_bytes().len() * 2
+            + 6
+            + self.r#type.as_bytes().len()
+            + 5
+            + self.name.len()
+            + self
+                .tagger
+                .as_ref()
+                .map(|tagger| 8 + tagger.len())
+                .unwrap_or(0)
+            + 2
+            + self.message.len()
+    }
+}
+
+#[test]
+fn round_trip() -> anyhow::Result<()> {
+    let tagger = Person::new(
+        String::from("T A Gger"),
+        String::from("tagger@example.com"),
+        Person::parse_time("@1600000000 +0100")?,
+    );
+    let tag = Tag::new(
+        object::Id::hash(b"commit"),
+        object::Type::Commit,
+        String::from("v1.0"),
+        Some(tagger),
+        String::from("Release 1.0\n"),
+    );
+
+    let mut buffer = Vec::new();
+    tag.write(&mut buffer)?;
+    assert_eq!(buffer.len(), tag.len());
+    assert!(buffer.starts_with(b"object "));
+
+    let read = Tag::read(&mut &*buffer)?;
+    assert_eq!(read.object(), tag.object());
+    assert_eq!(read.r#type(), object::Type::Commit);
+    assert_eq!(read.name(), "v1.0");
+    assert_eq!(read.tagger().map(Person::email), Some("tagger@example.com"));
+    assert_eq!(read.title(), "Release 1.0");
+    Ok(())
+}
@@ -3,14 +3,14 @@ use std::convert::TryFrom as _;
 use std::ffi;
 use std::io;
 use std::iter;
-use std::os::unix::ffi::OsStrExt as _;
-use std::os::unix::ffi::OsStringExt as _;
 use std::path;
 use std::slice;
 use std::vec;
 
 use crate::meta;
 use crate::object;
+use crate::platform::OsStrExt as _;
+use crate::platform::OsStringExt as _;
 use crate::util::Tap as _;
 
 // Invariant: sorted
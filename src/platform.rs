@@ -0,0 +1,203 @@
+//! Platform-specific access to file metadata, path bytes, permissions, and
+//! symbolic links.
+//!
+//! `git` stores paths as raw bytes and records `stat` fields in the index.
+//! On Unix both come straight from the operating system. Elsewhere, paths
+//! are converted to and from UTF-8, and `stat` fields without an equivalent
+//! are zero, as in Git for Windows. Permission bits are ignored and symbolic
+//! links are unsupported there, as if `core.fileMode` and `core.symlinks`
+//! were false.
+
+use std::borrow::Cow;
+use std::ffi;
+use std::fs;
+use std::io;
+use std::path;
+
+/// Access to the bytes of an [`ffi::OsStr`], like
+/// `std::os::unix::ffi::OsStrExt`, except that `from_bytes` may need to
+/// copy.
+pub trait OsStrExt: ToOwned {
+    fn from_bytes(bytes: &[u8]) -> Cow<'_, Self>;
+    fn as_bytes(&self) -> &[u8];
+}
+
+/// Construction of an [`ffi::OsString`] from bytes, like
+/// `std::os::unix::ffi::OsStringExt`.
+pub trait OsStringExt {
+    fn from_vec(bytes: Vec<u8>) -> Self;
+    fn into_vec(self) -> Vec<u8>;
+}
+
+/// Setting the permission bits of a new file, like
+/// `std::os::unix::fs::OpenOptionsExt`.
+pub trait OpenOptionsExt {
+    fn mode(&mut self, mode: u32) -> &mut Self;
+}
+
+#[cfg(unix)]
+impl OsStrExt for ffi::OsStr {
+    fn from_bytes(bytes: &[u8]) -> Cow<'_, Self> {
+        Cow::Borrowed(std::os::unix::ffi::OsStrExt::from_bytes(bytes))
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        std::os::unix::ffi::OsStrExt::as_bytes(self)
+    }
+}
+
+#[cfg(not(unix))]
+impl OsStrExt for ffi::OsStr {
+    /// Bytes that aren't valid UTF-8 are replaced, as in `from_vec`.
+    fn from_bytes(bytes: &[u8]) -> Cow<'_, Self> {
+        match String::from_utf8_lossy(bytes) {
+            Cow::Borrowed(valid) => Cow::Borrowed(ffi::OsStr::new(valid)),
+            Cow::Owned(replaced) => Cow::Owned(ffi::OsString::from(replaced)),
+        }
+    }
+
+    /// Paths that came from the index are valid UTF-8, for which the
+    /// encoded bytes are the same.
+    fn as_bytes(&self) -> &[u8] {
+        self.as_encoded_bytes()
+    }
+}
+
+#[cfg(unix)]
+impl OsStringExt for ffi::OsString {
+    fn from_vec(bytes: Vec<u8>) -> Self {
+        std::os::unix::ffi::OsStringExt::from_vec(bytes)
+    }
+
+    fn into_vec(self) -> Vec<u8> {
+        std::os::unix::ffi::OsStringExt::into_vec(self)
+    }
+}
+
+#[cfg(not(unix))]
+impl OsStringExt for ffi::OsString {
+    fn from_vec(bytes: Vec<u8>) -> Self {
+        match String::from_utf8(bytes) {
+            Ok(string) => ffi::OsString::from(string),
+            Err(error) => {
+                ffi::OsString::from(String::from_utf8_lossy(error.as_bytes()).into_owned())
+            }
+        }
+    }
+
+    fn into_vec(self) -> Vec<u8> {
+        self.into_encoded_bytes()
+    }
+}
+
+#[cfg(unix)]
+impl OpenOptionsExt for fs::OpenOptions {
+    fn mode(&mut self, mode: u32) -> &mut Self {
+        std::os::unix::fs::OpenOptionsExt::mode(self, mode)
+    }
+}
+
+#[cfg(not(unix))]
+impl OpenOptionsExt for fs::OpenOptions {
+    fn mode(&mut self, _: u32) -> &mut Self {
+        self
+    }
+}
+
+/// Fields of `stat` recorded in the index, before truncation to 32 bits.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Stat {
+    pub ctime: i64,
+    pub ctime_nsec: i64,
+    pub mtime: i64,
+    pub mtime_nsec: i64,
+    pub dev: u64,
+    pub ino: u64,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u64,
+}
+
+#[cfg(unix)]
+pub fn stat(metadata: &fs::Metadata) -> Stat {
+    use std::os::unix::fs::MetadataExt as _;
+    Stat {
+        ctime: metadata.ctime(),
+        ctime_nsec: metadata.ctime_nsec(),
+        mtime: metadata.mtime(),
+        mtime_nsec: metadata.mtime_nsec(),
+        dev: metadata.dev(),
+        ino: metadata.ino(),
+        uid: metadata.uid(),
+        gid: metadata.gid(),
+        size: metadata.size(),
+    }
+}
+
+/// Like Git for Windows, use the creation time as the change time, and
+/// leave the device, inode number, and owner as zero.
+#[cfg(not(unix))]
+pub fn stat(metadata: &fs::Metadata) -> Stat {
+    let since_epoch = |time: std::io::Result<std::time::SystemTime>| {
+        time.ok()
+            .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+            .map_or((0, 0), |duration| {
+                (duration.as_secs() as i64, duration.subsec_nanos() as i64)
+            })
+    };
+    let (ctime, ctime_nsec) = since_epoch(metadata.created());
+    let (mtime, mtime_nsec) = since_epoch(metadata.modified());
+    Stat {
+        ctime,
+        ctime_nsec,
+        mtime,
+        mtime_nsec,
+        size: metadata.len(),
+        ..Stat::default()
+    }
+}
+
+/// Whether any execute permission bit is set.
+#[cfg(unix)]
+pub fn is_executable(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt as _;
+    metadata.permissions().mode() & 0o111 > 0
+}
+
+/// Without execute permissions, every file is regular, as if
+/// `core.fileMode` were false.
+#[cfg(not(unix))]
+pub fn is_executable(_: &fs::Metadata) -> bool {
+    false
+}
+
+/// Set the permission bits of the file at `path` to `0o755` if
+/// `executable`, or `0o644` otherwise.
+#[cfg(unix)]
+pub fn set_executable(path: &path::Path, executable: bool) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt as _;
+    let mode = if executable { 0o755 } else { 0o644 };
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+pub fn set_executable(_: &path::Path, _: bool) -> io::Result<()> {
+    Ok(())
+}
+
+/// Create a symbolic link at `path` pointing to `target`.
+#[cfg(unix)]
+pub fn symlink(target: &ffi::OsStr, path: &path::Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, path)
+}
+
+/// Creating symbolic links on Windows needs extra privileges and knowing
+/// whether the target is a directory, so like Git for Windows by default,
+/// check them out as plain files instead.
+#[cfg(not(unix))]
+pub fn symlink(_: &ffi::OsStr, _: &path::Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "symbolic links are not supported on this platform",
+    ))
+}
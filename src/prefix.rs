@@ -0,0 +1,131 @@
+//! Translation between paths relative to the current directory, as typed on
+//! the command line and printed for the user, and paths relative to the
+//! workspace root, as stored in the index and in trees.
+
+use std::path;
+
+use anyhow::anyhow;
+
+use crate::platform::OsStrExt as _;
+
+/// Location of the current directory within a workspace.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Prefix {
+    /// Absolute path of the workspace root.
+    root: path::PathBuf,
+    /// Current directory relative to `root`, which is empty at the root.
+    prefix: path::PathBuf,
+}
+
+impl Prefix {
+    /// Locate the absolute path `cwd` within the workspace at `root`.
+    pub fn new(root: &path::Path, cwd: &path::Path) -> anyhow::Result<Self> {
+        let prefix = cwd.strip_prefix(root).map_err(|_| {
+            anyhow!(
+                "'{}' is outside repository at '{}'",
+                cwd.display(),
+                root.display()
+            )
+        })?;
+        Ok(Prefix {
+            root: root.to_path_buf(),
+            prefix: prefix.to_path_buf(),
+        })
+    }
+
+    /// Current directory relative to the workspace root.
+    pub fn path(&self) -> &path::Path {
+        &self.prefix
+    }
+
+    /// Translate `path`, which is absolute or relative to the current
+    /// directory, into a path relative to the workspace root, resolving
+    /// `.` and `..` without touching the filesystem.
+    ///
+    /// The workspace root itself is the empty path.
+    pub fn resolve(&self, path: &path::Path) -> anyhow::Result<path::PathBuf> {
+        let outside = || anyhow!("'{}' is outside repository", path.display());
+        let (base, path) = match path.strip_prefix(&self.root) {
+            Ok(relative) => (path::Path::new(""), relative),
+            Err(_) if path.is_absolute() => return Err(outside()),
+            Err(_) => (self.prefix.as_path(), path),
+        };
+
+        let mut resolved = base.to_path_buf();
+        for component in path.components() {
+            match component {
+                path::Component::Normal(name) => resolved.push(name),
+                path::Component::CurDir => (),
+                path::Component::ParentDir if resolved.pop() => (),
+                path::Component::ParentDir
+                | path::Component::RootDir
+                | path::Component::Prefix(_) => return Err(outside()),
+            }
+        }
+        Ok(resolved)
+    }
+
+    /// Whether `relative`, a path relative to the workspace root, is inside
+    /// the current directory.
+    pub fn contains(&self, relative: &path::Path) -> bool {
+        relative.starts_with(&self.prefix)
+    }
+
+    /// Translate `relative`, a path relative to the workspace root, into a
+    /// path relative to the current directory, climbing out with `..` if
+    /// necessary. A trailing slash marking a directory is kept.
+    pub fn display(&self, relative: &path::Path) -> path::PathBuf {
+        let shared = self
+            .prefix
+            .components()
+            .zip(relative.components())
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        let mut display = path::PathBuf::new();
+        for _ in self.prefix.components().skip(shared) {
+            display.push("..");
+        }
+        display.extend(relative.components().skip(shared));
+        if display.as_os_str().is_empty() {
+            display.push(".");
+        }
+        if relative.as_os_str().as_bytes().ends_with(b"/") {
+            display.as_mut_os_string().push("/");
+        }
+        display
+    }
+}
+
+#[test]
+fn translate() -> anyhow::Result<()> {
+    let root = path::Path::new("/repo");
+    let prefix = Prefix::new(root, &root.join("a/b"))?;
+
+    assert_eq!(prefix.resolve("c".as_ref())?, path::Path::new("a/b/c"));
+    assert_eq!(
+        prefix.resolve("./c/../d".as_ref())?,
+        path::Path::new("a/b/d")
+    );
+    assert_eq!(prefix.resolve("../../x".as_ref())?, path::Path::new("x"));
+    assert_eq!(prefix.resolve("../..".as_ref())?, path::Path::new(""));
+    assert_eq!(
+        prefix.resolve("/repo/x/y".as_ref())?,
+        path::Path::new("x/y")
+    );
+    assert!(prefix.resolve("../../..".as_ref()).is_err());
+    assert!(prefix.resolve("/elsewhere".as_ref()).is_err());
+
+    assert_eq!(prefix.display("a/b/c".as_ref()), path::Path::new("c"));
+    assert_eq!(prefix.display("a/x".as_ref()), path::Path::new("../x"));
+    assert_eq!(prefix.display("y".as_ref()), path::Path::new("../../y"));
+    assert_eq!(prefix.display("a/d/".as_ref()).as_os_str(), "../d/");
+    assert_eq!(prefix.display("a/b/".as_ref()).as_os_str(), "./");
+    assert!(prefix.contains("a/b/c".as_ref()));
+    assert!(!prefix.contains("a/bc".as_ref()));
+
+    let root = Prefix::new(root, root)?;
+    assert_eq!(root.display("a/b".as_ref()), path::Path::new("a/b"));
+    assert_eq!(root.resolve(".".as_ref())?, path::Path::new(""));
+    Ok(())
+}
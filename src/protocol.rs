@@ -0,0 +1,4 @@
+//! Wire formats shared by git's network protocols, independent of how
+//! bytes reach the remote. See [`crate::transport`] for smart HTTP.
+
+pub mod pktline;
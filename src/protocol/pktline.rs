@@ -0,0 +1,369 @@
+//! pkt-line framing, in which each packet is prefixed by its length as
+//! four hex digits, including the prefix itself. The lengths 0000, 0001,
+//! and 0002 are reserved for the flush, delimiter, and response-end
+//! packets that separate sections of a conversation.
+//!
+//! With the `side-band-64k` capability, the remote multiplexes pack data,
+//! progress messages, and errors over pkt-lines by prefixing each with a
+//! band number, which [`Sideband`] separates as they stream in. See
+//! `git help gitprotocol-common` for the format.
+
+use std::io;
+use std::str;
+
+use anyhow::anyhow;
+
+/// Length of the longest pkt-line, including its four-byte length prefix.
+pub const MAX_PACKET: usize = 65520;
+
+/// Most data in a single side-band packet, after the prefix and band.
+const MAX_BAND: usize = MAX_PACKET - 5;
+
+/// Band carrying pack data or other payload.
+pub const BAND_DATA: u8 = 1;
+
+/// Band carrying progress messages meant for the user.
+pub const BAND_PROGRESS: u8 = 2;
+
+/// Band carrying a fatal error, which ends the stream.
+pub const BAND_ERROR: u8 = 3;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Packet {
+    Data(Vec<u8>),
+    /// `0000`, which ends a list of packets.
+    Flush,
+    /// `0001`, which separates sections of a protocol v2 message.
+    Delim,
+    /// `0002`, which ends a protocol v2 response.
+    ResponseEnd,
+}
+
+/// Strip the newline that ends most textual pkt-lines, if present.
+pub fn trim_newline(line: &[u8]) -> &[u8] {
+    line.strip_suffix(b"\n").unwrap_or(line)
+}
+
+/// Decodes pkt-lines from an underlying stream.
+#[derive(Debug)]
+pub struct Reader<R> {
+    inner: R,
+    /// Bytes read ahead by [`Reader::peek_pack`].
+    prefix: Option<[u8; 4]>,
+    /// Packet read ahead by [`Reader::peek`].
+    peeked: Option<Packet>,
+}
+
+impl<R: io::Read> Reader<R> {
+    pub fn new(inner: R) -> Self {
+        Reader {
+            inner,
+            prefix: None,
+            peeked: None,
+        }
+    }
+
+    /// Read the next packet.
+    pub fn read(&mut self) -> anyhow::Result<Packet> {
+        if let Some(packet) = self.peeked.take() {
+            return Ok(packet);
+        }
+
+        let prefix = match self.prefix.take() {
+            Some(prefix) => prefix,
+            None => {
+                let mut prefix = [0; 4];
+                self.inner.read_exact(&mut prefix)?;
+                prefix
+            }
+        };
+
+        let len = str::from_utf8(&prefix)
+            .ok()
+            .filter(|prefix| prefix.bytes().all(|byte| byte.is_ascii_hexdigit()))
+            .and_then(|prefix| usize::from_str_radix(prefix, 16).ok())
+            .ok_or_else(|| {
+                anyhow!(
+                    "Invalid pkt-line length: {:?}",
+                    String::from_utf8_lossy(&prefix)
+                )
+            })?;
+
+        match len {
+            0 => Ok(Packet::Flush),
+            1 => Ok(Packet::Delim),
+            2 => Ok(Packet::ResponseEnd),
+            3 => Err(anyhow!("Invalid pkt-line length: 0003")),
+            _ => {
+                let mut data = vec![0; len - 4];
+                self.inner.read_exact(&mut data)?;
+                Ok(Packet::Data(data))
+            }
+        }
+    }
+
+    /// Look at the next packet without consuming it.
+    pub fn peek(&mut self) -> anyhow::Result<&Packet> {
+        if self.peeked.is_none() {
+            self.peeked = Some(self.read()?);
+        }
+        Ok(self.peeked.as_ref().expect("[UNREACHABLE]: just peeked"))
+    }
+
+    /// Read the next data packet, returning `None` for a flush packet and
+    /// failing on any other special packet.
+    pub fn read_data(&mut self) -> anyhow::Result<Option<Vec<u8>>> {
+        match self.read()? {
+            Packet::Data(data) => Ok(Some(data)),
+            Packet::Flush => Ok(None),
+            packet => Err(anyhow!("Unexpected special packet: {:?}", packet)),
+        }
+    }
+
+    /// Check whether the stream continues with a raw packfile, which some
+    /// responses send without pkt-line framing, rather than a packet.
+    pub fn peek_pack(&mut self) -> io::Result<bool> {
+        if self.peeked.is_some() {
+            return Ok(false);
+        }
+        let prefix = match self.prefix {
+            Some(prefix) => prefix,
+            None => {
+                let mut prefix = [0; 4];
+                self.inner.read_exact(&mut prefix)?;
+                *self.prefix.insert(prefix)
+            }
+        };
+        Ok(&prefix == b"PACK")
+    }
+
+    /// Read the rest of the stream without pkt-line framing, e.g. after
+    /// [`Reader::peek_pack`].
+    pub fn read_raw_to_end(&mut self, buffer: &mut Vec<u8>) -> anyhow::Result<usize> {
+        if self.peeked.is_some() {
+            return Err(anyhow!("Expected raw data, but found a pkt-line"));
+        }
+        let prefix = self.prefix.take();
+        let prefix = prefix
+            .as_ref()
+            .map(|prefix| &prefix[..])
+            .unwrap_or_default();
+        buffer.extend_from_slice(prefix);
+        Ok(prefix.len() + self.inner.read_to_end(buffer)?)
+    }
+
+    /// Stream the data band of the side-band packets that follow, up to
+    /// the next flush packet, copying progress messages to `progress`.
+    pub fn sideband<'a>(&'a mut self, progress: &'a mut dyn io::Write) -> Sideband<'a, R> {
+        Sideband {
+            reader: self,
+            progress,
+            buffer: Vec::new(),
+            offset: 0,
+            start: true,
+            done: false,
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+/// Data band of a side-band stream, read by [`Reader::sideband`].
+pub struct Sideband<'a, R> {
+    reader: &'a mut Reader<R>,
+    progress: &'a mut dyn io::Write,
+    /// Current data packet, consumed up to `offset`.
+    buffer: Vec<u8>,
+    offset: usize,
+    /// Whether the next progress byte starts a new line.
+    start: bool,
+    done: bool,
+}
+
+impl<R: io::Read> Sideband<'_, R> {
+    /// Read packets until one carries data, or the stream ends.
+    fn fill(&mut self) -> anyhow::Result<()> {
+        while self.offset == self.buffer.len() && !self.done {
+            let packet = match self.reader.read_data()? {
+                None => {
+                    self.done = true;
+                    return Ok(());
+                }
+                Some(packet) => packet,
+            };
+
+            match packet.split_first() {
+                Some((&BAND_DATA, _)) => {
+                    self.buffer = packet;
+                    self.offset = 1;
+                }
+                // Progress messages may be split anywhere, and use `\r` to
+                // redraw the current line.
+                Some((&BAND_PROGRESS, message)) => {
+                    for line in message.split_inclusive(|byte| *byte == b'\r' || *byte == b'\n') {
+                        if self.start {
+                            self.progress.write_all(b"remote: ")?;
+                        }
+                        self.progress.write_all(line)?;
+                        self.start = line.ends_with(b"\r") || line.ends_with(b"\n");
+                    }
+                }
+                Some((&BAND_ERROR, message)) => {
+                    return Err(anyhow!(
+                        "Remote error: {}",
+                        String::from_utf8_lossy(trim_newline(message))
+                    ))
+                }
+                _ => return Err(anyhow!("Invalid side-band packet")),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: io::Read> io::Read for Sideband<'_, R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        self.fill()
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error.to_string()))?;
+        let available = &self.buffer[self.offset..];
+        let len = available.len().min(buffer.len());
+        buffer[..len].copy_from_slice(&available[..len]);
+        self.offset += len;
+        Ok(len)
+    }
+}
+
+/// Encodes pkt-lines into an underlying stream.
+#[derive(Debug)]
+pub struct Writer<W> {
+    inner: W,
+}
+
+impl<W: io::Write> Writer<W> {
+    pub fn new(inner: W) -> Self {
+        Writer { inner }
+    }
+
+    /// Write `data` as a single data packet.
+    pub fn write(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        if data.len() + 4 > MAX_PACKET {
+            return Err(anyhow!("pkt-line is too long: {} bytes", data.len()));
+        }
+        write!(self.inner, "{:04x}", data.len() + 4)?;
+        self.inner.write_all(data)?;
+        Ok(())
+    }
+
+    /// Write a flush packet, which ends a list of packets.
+    pub fn write_flush(&mut self) -> io::Result<()> {
+        self.inner.write_all(b"0000")
+    }
+
+    /// Write a delimiter packet, which separates sections of a message.
+    pub fn write_delim(&mut self) -> io::Result<()> {
+        self.inner.write_all(b"0001")
+    }
+
+    /// Write a response-end packet.
+    pub fn write_response_end(&mut self) -> io::Result<()> {
+        self.inner.write_all(b"0002")
+    }
+
+    /// Write `data` on side-band `band`, split across as many packets as
+    /// needed.
+    pub fn write_band(&mut self, band: u8, data: &[u8]) -> anyhow::Result<()> {
+        let mut packet = Vec::with_capacity(data.len().min(MAX_BAND) + 1);
+        for chunk in data.chunks(MAX_BAND) {
+            packet.clear();
+            packet.push(band);
+            packet.extend_from_slice(chunk);
+            self.write(&packet)?;
+        }
+        Ok(())
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+#[test]
+fn framing() -> anyhow::Result<()> {
+    let mut writer = Writer::new(Vec::new());
+    writer.write(b"hello\n")?;
+    writer.write_delim()?;
+    writer.write(b"")?;
+    writer.write_response_end()?;
+    writer.write_flush()?;
+    let stream = writer.into_inner();
+    assert_eq!(stream, b"000ahello\n0001000400020000");
+
+    let mut reader = Reader::new(&stream[..]);
+    assert_eq!(reader.peek()?, &Packet::Data(b"hello\n".to_vec()));
+    assert_eq!(reader.read_data()?.as_deref(), Some(&b"hello\n"[..]));
+    assert_eq!(reader.read()?, Packet::Delim);
+    assert_eq!(reader.read()?, Packet::Data(Vec::new()));
+    assert!(reader.read_data().is_err());
+    assert_eq!(reader.read_data()?, None);
+
+    assert!(Writer::new(Vec::new()).write(&[0; MAX_PACKET - 3]).is_err());
+    assert!(Reader::new(&b"0003"[..]).read().is_err());
+    assert!(Reader::new(&b"00zz"[..]).read().is_err());
+    Ok(())
+}
+
+#[test]
+fn sideband() -> anyhow::Result<()> {
+    use std::io::Read as _;
+
+    let data = (0..MAX_PACKET * 2)
+        .map(|index| index as u8)
+        .collect::<Vec<_>>();
+
+    let mut writer = Writer::new(Vec::new());
+    writer.write_band(BAND_PROGRESS, b"Counting: 1%\rCount")?;
+    writer.write_band(BAND_DATA, &data)?;
+    writer.write_band(BAND_PROGRESS, b"ing: done.\n")?;
+    writer.write_flush()?;
+    writer.write(b"after\n")?;
+    let stream = writer.into_inner();
+
+    let mut reader = Reader::new(&stream[..]);
+    let mut progress = Vec::new();
+    let mut read = Vec::new();
+    reader.sideband(&mut progress).read_to_end(&mut read)?;
+
+    assert_eq!(read, data);
+    assert_eq!(progress, b"remote: Counting: 1%\rremote: Counting: done.\n");
+    assert_eq!(reader.read_data()?.as_deref(), Some(&b"after\n"[..]));
+
+    let mut writer = Writer::new(Vec::new());
+    writer.write_band(BAND_ERROR, b"oops\n")?;
+    let stream = writer.into_inner();
+    let error = Reader::new(&stream[..])
+        .sideband(&mut io::sink())
+        .read_to_end(&mut Vec::new())
+        .unwrap_err();
+    assert!(error.to_string().contains("oops"));
+    Ok(())
+}
+
+#[test]
+fn raw() -> anyhow::Result<()> {
+    let stream = b"0008NAK\nPACK....";
+    let mut reader = Reader::new(&stream[..]);
+    assert!(!reader.peek_pack()?);
+    assert_eq!(reader.read_data()?.as_deref(), Some(&b"NAK\n"[..]));
+    assert!(reader.peek_pack()?);
+
+    let mut pack = Vec::new();
+    reader.read_raw_to_end(&mut pack)?;
+    assert_eq!(pack, b"PACK....");
+    Ok(())
+}
@@ -1,33 +1,887 @@
+use std::cell;
+use std::collections::BTreeMap;
+use std::fmt;
 use std::io;
-use std::io::Write as _;
 use std::path;
+use std::rc::Rc;
+use std::str;
+
+use anyhow::anyhow;
 
-use crate::file;
 use crate::object;
 
+mod files;
+pub mod reftable;
+
+pub use files::Files;
+pub use reftable::Reftable;
+
+/// Maximum number of symbolic references followed before giving up.
+const MAX_SYMBOLIC_DEPTH: usize = 5;
+
+/// Prefixes and suffixes that a short reference name is expanded with, in
+/// the order `git` searches them.
+const RULES: [(&str, &str); 6] = [
+    ("", ""),
+    ("refs/", ""),
+    (References::TAGS, ""),
+    (References::HEADS, ""),
+    (References::REMOTES, ""),
+    (References::REMOTES, "/HEAD"),
+];
+
+/// Contents of a single reference.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Target {
+    Direct(object::Id),
+    /// Full name of another reference, e.g. `refs/heads/main`.
+    Symbolic(String),
+}
+
+impl Target {
+    /// Convert a value read from a reftable, where deletions hide any older
+    /// value.
+    fn from_reftable(value: reftable::Value) -> Option<Self> {
+        match value {
+            reftable::Value::Deletion => None,
+            reftable::Value::Id(id) | reftable::Value::Peeled(id, _) => Some(Target::Direct(id)),
+            reftable::Value::Symbolic(name) => Some(Target::Symbolic(name)),
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Target::Direct(id) => write!(fmt, "{}", id),
+            Target::Symbolic(name) => write!(fmt, "ref: {}", name),
+        }
+    }
+}
+
+impl str::FromStr for Target {
+    type Err = anyhow::Error;
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let string = string.trim_end();
+        match string.strip_prefix("ref: ") {
+            Some(name) => Ok(Target::Symbolic(name.to_owned())),
+            None => string.parse().map(Target::Direct),
+        }
+    }
+}
+
+/// Storage backend for references, addressed by full name (e.g. `HEAD` or
+/// `refs/heads/main`).
+pub trait RefStore: fmt::Debug {
+    /// Read the raw contents of reference `name`, without following
+    /// symbolic references.
+    fn read(&self, name: &str) -> anyhow::Result<Option<Target>>;
+
+    fn write(&self, name: &str, target: &Target) -> anyhow::Result<()>;
+
+    /// Remove reference `name`, returning whether it existed.
+    fn delete(&self, name: &str) -> anyhow::Result<bool>;
+
+    /// Iterate over all direct references whose full name starts with
+    /// `prefix`, in byte-wise sorted order.
+    #[allow(clippy::type_complexity)]
+    fn iter_prefix<'a>(
+        &'a self,
+        prefix: &str,
+    ) -> anyhow::Result<Box<dyn Iterator<Item = anyhow::Result<(String, object::Id)>> + 'a>>;
+
+    /// Apply every update in `updates`, or none of them if any reference no
+    /// longer has its expected old value.
+    ///
+    /// The default implementation checks every update before writing any,
+    /// which is only atomic for stores without concurrent writers.
+    fn update(&self, updates: &[Update]) -> anyhow::Result<()> {
+        for update in updates {
+            update.check(self.read(&update.name)?)?;
+        }
+        for update in updates {
+            match update.new {
+                Some(new) => self.write(&update.name, &Target::Direct(new))?,
+                None => {
+                    self.delete(&update.name)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Append `entry` to the reflog of reference `name`.
+    ///
+    /// The default implementation discards it, for stores without reflogs.
+    fn append_log(&self, _name: &str, _entry: &LogEntry) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Read the reflog of reference `name`, oldest entry first.
+    fn read_log(&self, _name: &str) -> anyhow::Result<Vec<LogEntry>> {
+        Ok(Vec::new())
+    }
+
+    /// Replace the reflog of reference `name`, e.g. to drop an entry.
+    fn write_log(&self, _name: &str, _entries: &[LogEntry]) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// Change of the direct reference `name` from `old` to `new`, as part of
+/// [`RefStore::update`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Update {
+    pub name: String,
+    /// Expected current value, or `None` if the reference must not exist.
+    pub old: Option<object::Id>,
+    /// New value, or `None` to delete the reference.
+    pub new: Option<object::Id>,
+}
+
+impl Update {
+    /// Verify that `current`, the raw contents of the reference, is still
+    /// the expected old value.
+    fn check(&self, current: Option<Target>) -> anyhow::Result<()> {
+        match current {
+            Some(Target::Direct(id)) if Some(id) == self.old => Ok(()),
+            None if self.old.is_none() => Ok(()),
+            Some(Target::Symbolic(_)) => {
+                Err(anyhow!("Cannot update symbolic reference {}", self.name))
+            }
+            _ => Err(anyhow!(
+                "Cannot lock ref '{}': reference was updated concurrently",
+                self.name
+            )),
+        }
+    }
+}
+
+/// A single reflog record: reference moving from `old` to `new`, along with
+/// who moved it, when, and why.
 #[derive(Clone, Debug)]
+pub struct LogEntry {
+    /// `None` if the reference was just created.
+    pub old: Option<object::Id>,
+    pub new: object::Id,
+    pub committer: object::Person,
+    pub message: String,
+}
+
+impl LogEntry {
+    /// Id written in place of a missing old value.
+    const NULL: [u8; 20] = [0; 20];
+
+    /// Parse a single line `<old> <new> <committer>\t<message>`, without its
+    /// trailing newline.
+    pub fn read(line: &[u8]) -> anyhow::Result<Self> {
+        let mut reader = line;
+        let old = object::Id::read_hex(&mut reader)?;
+        let new = match reader.split_first() {
+            Some((b' ', rest)) => {
+                reader = rest;
+                object::Id::read_hex(&mut reader)?
+            }
+            _ => return Err(anyhow!("Malformed reflog entry")),
+        };
+        let committer = match reader.split_first() {
+            Some((b' ', rest)) => {
+                reader = rest;
+                object::Person::read(&mut reader)?
+            }
+            _ => return Err(anyhow!("Malformed reflog entry")),
+        };
+        // `git` omits the tab when there is no message.
+        let message = reader.strip_prefix(b"\t").unwrap_or(reader);
+
+        Ok(LogEntry {
+            old: Some(old).filter(|old| old.as_bytes() != &Self::NULL),
+            new,
+            committer,
+            message: String::from_utf8(message.to_vec())?,
+        })
+    }
+
+    /// Write this entry as a single line, including its trailing newline.
+    pub fn write<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
+        match &self.old {
+            Some(old) => old.write_hex(writer)?,
+            None => writer.write_all(&[b'0'; 40])?,
+        }
+        writer.write_all(b" ")?;
+        self.new.write_hex(writer)?;
+        writer.write_all(b" ")?;
+        self.committer.write(writer)?;
+        // Entries are line-based, so fold multi-line messages like `git`.
+        let message = self.message.trim().replace('\n', " ");
+        writeln!(writer, "\t{}", message)
+    }
+}
+
+/// High-level reference operations, layered over any [`RefStore`].
+#[derive(Debug)]
 pub struct References {
-    root: path::PathBuf,
-    head: path::PathBuf,
+    store: Box<dyn RefStore>,
 }
 
 impl References {
-    pub fn new(root: path::PathBuf, head: path::PathBuf) -> Self {
-        References { root, head }
+    pub const HEADS: &'static str = "refs/heads/";
+    pub const TAGS: &'static str = "refs/tags/";
+    pub const REMOTES: &'static str = "refs/remotes/";
+
+    pub fn new(store: Box<dyn RefStore>) -> Self {
+        References { store }
+    }
+
+    /// Open the references of the `.git` directory `git`, using the reftable
+    /// backend if `.git/reftable` exists and loose files otherwise.
+    pub fn open(git: path::PathBuf) -> Self {
+        let reftable = git.join("reftable");
+        if reftable.is_dir() {
+            Self::new(Box::new(Reftable::new(reftable)))
+        } else {
+            Self::new(Box::new(Files::new(git)))
+        }
+    }
+
+    pub fn store(&self) -> &dyn RefStore {
+        &*self.store
+    }
+
+    /// Read reference `name`, following symbolic references.
+    pub fn read(&self, name: &str) -> anyhow::Result<Option<object::Id>> {
+        Ok(self.peel(name)?.1)
+    }
+
+    /// Follow symbolic references starting from `name`, returning the final
+    /// direct reference name and its id (if it exists yet).
+    fn peel(&self, name: &str) -> anyhow::Result<(String, Option<object::Id>)> {
+        let mut name = name.to_owned();
+        for _ in 0..MAX_SYMBOLIC_DEPTH {
+            match self.store.read(&name)? {
+                None => return Ok((name, None)),
+                Some(Target::Direct(id)) => return Ok((name, Some(id))),
+                Some(Target::Symbolic(target)) => name = target,
+            }
+        }
+        Err(anyhow!("Too many levels of symbolic references: {}", name))
     }
 
     pub fn read_head(&self) -> anyhow::Result<Option<object::Id>> {
-        let mut head = match file::WriteLock::new(self.head.clone())?.upgrade()? {
-            file::Lock::ReadWrite(lock) => lock,
-            file::Lock::Write(_) => return Ok(None),
+        self.read("HEAD")
+    }
+
+    /// Point `HEAD` at `id`, updating the current branch if `HEAD` is
+    /// symbolic.
+    pub fn write_head(&self, id: &object::Id) -> anyhow::Result<()> {
+        let (name, _) = self.peel("HEAD")?;
+        self.store.write(&name, &Target::Direct(*id))
+    }
+
+    /// Point `HEAD` at `id` like [`References::write_head`], recording the
+    /// update in the reflogs of both `HEAD` and the current branch.
+    pub fn update_head(
+        &self,
+        id: &object::Id,
+        committer: &object::Person,
+        message: &str,
+    ) -> anyhow::Result<()> {
+        let (name, old) = self.peel("HEAD")?;
+        self.store.write(&name, &Target::Direct(*id))?;
+
+        let entry = LogEntry {
+            old,
+            new: *id,
+            committer: committer.clone(),
+            message: message.to_owned(),
         };
+        if name != "HEAD" {
+            self.store.append_log(&name, &entry)?;
+        }
+        self.store.append_log("HEAD", &entry)
+    }
 
-        object::Id::read_hex(&mut head).map(Option::Some)
+    /// Point the direct reference `name` at `id`, recording the update in
+    /// its reflog.
+    pub fn update_ref(
+        &self,
+        name: &str,
+        id: &object::Id,
+        committer: &object::Person,
+        message: &str,
+    ) -> anyhow::Result<()> {
+        let old = self.read(name)?;
+        self.store.write(name, &Target::Direct(*id))?;
+        self.store.append_log(
+            name,
+            &LogEntry {
+                old,
+                new: *id,
+                committer: committer.clone(),
+                message: message.to_owned(),
+            },
+        )
     }
 
-    pub fn write_head(&self, id: &object::Id) -> io::Result<()> {
-        let mut head = file::WriteLock::new(self.head.clone())?;
-        write!(&mut head, "{}", id)?;
-        head.commit()
+    /// Apply `updates` atomically like [`RefStore::update`], recording each
+    /// in its reflog. Deleted references lose their reflogs instead.
+    pub fn transaction(
+        &self,
+        updates: &[Update],
+        committer: &object::Person,
+        message: &str,
+    ) -> anyhow::Result<()> {
+        // Don't leave some references updated and others not.
+        let _deferred = crate::interrupt::defer();
+        self.store.update(updates)?;
+        for update in updates {
+            let new = match update.new {
+                Some(new) => new,
+                None => continue,
+            };
+            self.store.append_log(
+                &update.name,
+                &LogEntry {
+                    old: update.old,
+                    new,
+                    committer: committer.clone(),
+                    message: message.to_owned(),
+                },
+            )?;
+        }
+        Ok(())
     }
+
+    /// Overwrite `HEAD` itself, e.g. to switch branches or detach.
+    pub fn set_head(&self, target: &Target) -> anyhow::Result<()> {
+        self.store.write("HEAD", target)
+    }
+
+    /// Overwrite `HEAD` like [`References::set_head`], recording the move in
+    /// the reflog of `HEAD` unless it now points to an unborn branch.
+    pub fn switch_head(
+        &self,
+        target: &Target,
+        committer: &object::Person,
+        message: &str,
+    ) -> anyhow::Result<()> {
+        let old = self.read_head()?;
+        self.set_head(target)?;
+        match self.read_head()? {
+            None => Ok(()),
+            Some(new) => self.store.append_log(
+                "HEAD",
+                &LogEntry {
+                    old,
+                    new,
+                    committer: committer.clone(),
+                    message: message.to_owned(),
+                },
+            ),
+        }
+    }
+
+    /// Read the reflog of the full reference name `name`, oldest entry first.
+    pub fn read_log(&self, name: &str) -> anyhow::Result<Vec<LogEntry>> {
+        self.store.read_log(name)
+    }
+
+    /// Short name of the branch `HEAD` points to, or `None` if detached.
+    pub fn current_branch(&self) -> anyhow::Result<Option<String>> {
+        match self.store.read("HEAD")? {
+            Some(Target::Symbolic(name)) => Ok(name.strip_prefix(Self::HEADS).map(String::from)),
+            _ => Ok(None),
+        }
+    }
+
+    #[allow(clippy::type_complexity)]
+    pub fn iter_prefix<'a>(
+        &'a self,
+        prefix: &str,
+    ) -> anyhow::Result<Box<dyn Iterator<Item = anyhow::Result<(String, object::Id)>> + 'a>> {
+        self.store.iter_prefix(prefix)
+    }
+
+    /// Iterate over the direct references under `namespace` (e.g.
+    /// `refs/remotes/origin/`) as pairs of their name relative to it and id.
+    pub fn iter_namespace<'a>(
+        &'a self,
+        namespace: &str,
+    ) -> anyhow::Result<impl Iterator<Item = anyhow::Result<(String, object::Id)>> + 'a> {
+        let len = namespace.len();
+        Ok(self
+            .iter_prefix(namespace)?
+            .map(move |reference| reference.map(|(name, id)| (name[len..].to_owned(), id))))
+    }
+
+    /// Iterate over local branches as `(short name, id)` pairs.
+    pub fn branches(
+        &self,
+    ) -> anyhow::Result<impl Iterator<Item = anyhow::Result<(String, object::Id)>> + '_> {
+        self.iter_namespace(Self::HEADS)
+    }
+
+    /// Iterate over the remote-tracking branches of `remote` as
+    /// `(short name, id)` pairs.
+    pub fn remote_branches<'a>(
+        &'a self,
+        remote: &str,
+    ) -> anyhow::Result<impl Iterator<Item = anyhow::Result<(String, object::Id)>> + 'a> {
+        self.iter_namespace(&format!("{}{}/", Self::REMOTES, remote))
+    }
+
+    pub fn create_branch(&self, name: &str, id: &object::Id) -> anyhow::Result<()> {
+        validate(name, "branch")?;
+        let full = format!("{}{}", Self::HEADS, name);
+        if self.store.read(&full)?.is_some() {
+            return Err(anyhow!("A branch named '{}' already exists", name));
+        }
+        self.store.write(&full, &Target::Direct(*id))
+    }
+
+    /// Delete branch `name`, returning the id it pointed to.
+    pub fn delete_branch(&self, name: &str) -> anyhow::Result<object::Id> {
+        let full = format!("{}{}", Self::HEADS, name);
+        let id = match self.store.read(&full)? {
+            Some(Target::Direct(id)) => id,
+            _ => return Err(anyhow!("Branch '{}' not found", name)),
+        };
+        self.store.delete(&full)?;
+        Ok(id)
+    }
+
+    /// Iterate over tags as `(short name, id)` pairs, where `id` is the
+    /// annotated tag object, if any, rather than the tagged object.
+    pub fn tags(
+        &self,
+    ) -> anyhow::Result<impl Iterator<Item = anyhow::Result<(String, object::Id)>> + '_> {
+        self.iter_namespace(Self::TAGS)
+    }
+
+    /// Point tag `name` at `id`, replacing any existing tag only if `force`
+    /// is set. Returns the id the tag previously pointed to.
+    pub fn create_tag(
+        &self,
+        name: &str,
+        id: &object::Id,
+        force: bool,
+    ) -> anyhow::Result<Option<object::Id>> {
+        validate(name, "tag")?;
+        let full = format!("{}{}", Self::TAGS, name);
+        let previous = self.read(&full)?;
+        if previous.is_some() && !force {
+            return Err(anyhow!("tag '{}' already exists", name));
+        }
+        self.store.write(&full, &Target::Direct(*id))?;
+        Ok(previous)
+    }
+
+    /// Delete tag `name`, returning the id it pointed to.
+    pub fn delete_tag(&self, name: &str) -> anyhow::Result<object::Id> {
+        let full = format!("{}{}", Self::TAGS, name);
+        let id = match self.store.read(&full)? {
+            Some(Target::Direct(id)) => id,
+            _ => return Err(anyhow!("tag '{}' not found.", name)),
+        };
+        self.store.delete(&full)?;
+        Ok(id)
+    }
+
+    /// Resolve a short or full reference name (e.g. `HEAD`, `main`, or
+    /// `refs/tags/v1.0`) using the same search order as `git`.
+    pub fn resolve(&self, name: &str) -> anyhow::Result<Option<object::Id>> {
+        match self.expand(name)? {
+            Some(name) => self.read(&name),
+            None => Ok(None),
+        }
+    }
+
+    /// Find the full name of the existing reference that `name` refers to,
+    /// using the same search order as [`References::resolve`].
+    pub fn expand(&self, name: &str) -> anyhow::Result<Option<String>> {
+        if name == "HEAD" {
+            return Ok(Some(name.to_owned()));
+        }
+
+        // Like `git`, only look directly in `.git` for pseudo-refs such as
+        // `FETCH_HEAD`, so that e.g. `config` is never mistaken for a ref.
+        let pseudo = name.starts_with("refs/")
+            || name
+                .chars()
+                .all(|char| char.is_ascii_uppercase() || char == '_');
+
+        for candidate in Self::expansions(name) {
+            if candidate == name && !pseudo {
+                continue;
+            }
+            if self.read(&candidate)?.is_some() {
+                return Ok(Some(candidate));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Full names that short name `name` could refer to, in the order `git`
+    /// searches them.
+    pub fn expansions(name: &str) -> impl Iterator<Item = String> + '_ {
+        RULES
+            .iter()
+            .map(move |(prefix, suffix)| format!("{}{}{}", prefix, name, suffix))
+    }
+
+    /// Strip the namespace from a full reference name, reversing the search
+    /// order of [`References::expand`]: `refs/heads/main` becomes `main`,
+    /// and `refs/remotes/origin/HEAD` becomes `origin`.
+    pub fn shorten(name: &str) -> &str {
+        RULES[1..]
+            .iter()
+            .rev()
+            .find_map(|(prefix, suffix)| {
+                name.strip_prefix(prefix)?
+                    .strip_suffix(suffix)
+                    .filter(|short| !short.is_empty())
+            })
+            .unwrap_or(name)
+    }
+}
+
+/// Check `name` against the subset of `git check-ref-format` rules that
+/// apply to branch and tag names, where `kind` names which it is.
+fn validate(name: &str, kind: &str) -> anyhow::Result<()> {
+    let invalid = name.is_empty()
+        || name == "HEAD"
+        || name.starts_with('-')
+        || name.starts_with('.')
+        || name.starts_with('/')
+        || name.ends_with('/')
+        || name.ends_with('.')
+        || name.ends_with(".lock")
+        || name.contains("..")
+        || name.contains("//")
+        || name.contains("/.")
+        || name.contains("@{")
+        || name
+            .chars()
+            .any(|char| char.is_ascii_control() || " ~^:?*[\\".contains(char));
+
+    match invalid {
+        true => Err(anyhow!("'{}' is not a valid {} name", name, kind)),
+        false => Ok(()),
+    }
+}
+
+/// Volatile reference storage for tests and embedding.
+///
+/// Clones share the same underlying references.
+#[derive(Clone, Debug, Default)]
+pub struct Memory {
+    references: Rc<cell::RefCell<BTreeMap<String, Target>>>,
+    logs: Rc<cell::RefCell<BTreeMap<String, Vec<LogEntry>>>>,
+}
+
+impl Memory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl RefStore for Memory {
+    fn read(&self, name: &str) -> anyhow::Result<Option<Target>> {
+        Ok(self.references.borrow().get(name).cloned())
+    }
+
+    fn write(&self, name: &str, target: &Target) -> anyhow::Result<()> {
+        self.references
+            .borrow_mut()
+            .insert(name.to_owned(), target.clone());
+        Ok(())
+    }
+
+    fn delete(&self, name: &str) -> anyhow::Result<bool> {
+        self.logs.borrow_mut().remove(name);
+        Ok(self.references.borrow_mut().remove(name).is_some())
+    }
+
+    fn iter_prefix<'a>(
+        &'a self,
+        prefix: &str,
+    ) -> anyhow::Result<Box<dyn Iterator<Item = anyhow::Result<(String, object::Id)>> + 'a>> {
+        let references = self
+            .references
+            .borrow()
+            .range(prefix.to_owned()..)
+            .take_while(|(name, _)| name.starts_with(prefix))
+            .filter_map(|(name, target)| match target {
+                Target::Direct(id) => Some(Ok((name.clone(), *id))),
+                Target::Symbolic(_) => None,
+            })
+            .collect::<Vec<_>>();
+        Ok(Box::new(references.into_iter()))
+    }
+
+    fn update(&self, updates: &[Update]) -> anyhow::Result<()> {
+        let mut references = self.references.borrow_mut();
+        for update in updates {
+            update.check(references.get(&update.name).cloned())?;
+        }
+        for update in updates {
+            match update.new {
+                Some(new) => {
+                    references.insert(update.name.clone(), Target::Direct(new));
+                }
+                None => {
+                    references.remove(&update.name);
+                    self.logs.borrow_mut().remove(&update.name);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn append_log(&self, name: &str, entry: &LogEntry) -> anyhow::Result<()> {
+        self.logs
+            .borrow_mut()
+            .entry(name.to_owned())
+            .or_default()
+            .push(entry.clone());
+        Ok(())
+    }
+
+    fn read_log(&self, name: &str) -> anyhow::Result<Vec<LogEntry>> {
+        Ok(self.logs.borrow().get(name).cloned().unwrap_or_default())
+    }
+
+    fn write_log(&self, name: &str, entries: &[LogEntry]) -> anyhow::Result<()> {
+        self.logs
+            .borrow_mut()
+            .insert(name.to_owned(), entries.to_vec());
+        Ok(())
+    }
+}
+
+impl RefStore for Reftable {
+    fn read(&self, name: &str) -> anyhow::Result<Option<Target>> {
+        self.stack()?
+            .lookup(name)
+            .map(|value| value.and_then(Target::from_reftable))
+    }
+
+    fn write(&self, name: &str, target: &Target) -> anyhow::Result<()> {
+        let value = match target {
+            Target::Direct(id) => reftable::Value::Id(*id),
+            Target::Symbolic(name) => reftable::Value::Symbolic(name.clone()),
+        };
+        Reftable::write(self, &[(name.to_owned(), value)], |_| Ok(()))
+    }
+
+    /// Appends all updates as a single table, so readers see either all or
+    /// none of them, after checking them under the lock on the stack.
+    fn update(&self, updates: &[Update]) -> anyhow::Result<()> {
+        let values = updates
+            .iter()
+            .map(|update| {
+                let value = match update.new {
+                    Some(new) => reftable::Value::Id(new),
+                    None => reftable::Value::Deletion,
+                };
+                (update.name.clone(), value)
+            })
+            .collect::<Vec<_>>();
+        Reftable::write(self, &values, |stack| {
+            updates.iter().try_for_each(|update| {
+                update.check(stack.lookup(&update.name)?.and_then(Target::from_reftable))
+            })
+        })
+    }
+
+    fn delete(&self, name: &str) -> anyhow::Result<bool> {
+        let existed = self.stack()?.lookup(name)?.is_some();
+        if existed {
+            Reftable::write(
+                self,
+                &[(name.to_owned(), reftable::Value::Deletion)],
+                |_| Ok(()),
+            )?;
+        }
+        Ok(existed)
+    }
+
+    fn iter_prefix<'a>(
+        &'a self,
+        prefix: &str,
+    ) -> anyhow::Result<Box<dyn Iterator<Item = anyhow::Result<(String, object::Id)>> + 'a>> {
+        let references = self
+            .stack()?
+            .scan(prefix)?
+            .into_iter()
+            .filter_map(|(name, value)| match value {
+                reftable::Value::Id(id) | reftable::Value::Peeled(id, _) => Some(Ok((name, id))),
+                reftable::Value::Symbolic(_) | reftable::Value::Deletion => None,
+            })
+            .collect::<Vec<_>>();
+        Ok(Box::new(references.into_iter()))
+    }
+
+    fn append_log(&self, name: &str, entry: &LogEntry) -> anyhow::Result<()> {
+        Reftable::append_log(self, name, entry)
+    }
+
+    fn read_log(&self, name: &str) -> anyhow::Result<Vec<LogEntry>> {
+        self.stack()?.read_log(name)
+    }
+
+    fn write_log(&self, name: &str, entries: &[LogEntry]) -> anyhow::Result<()> {
+        Reftable::write_log(self, name, entries)
+    }
+}
+
+#[test]
+fn resolve() -> anyhow::Result<()> {
+    let id = |byte: u8| object::Id::read_bytes(&mut &[byte; 20][..]);
+    let references = References::new(Box::new(Memory::new()));
+
+    references.set_head(&Target::Symbolic(String::from("refs/heads/main")))?;
+    assert_eq!(references.read_head()?, None);
+
+    references.write_head(&id(1)?)?;
+    references
+        .store()
+        .write("refs/tags/main", &Target::Direct(id(2)?))?;
+    references
+        .store()
+        .write("refs/tags/v1", &Target::Direct(id(3)?))?;
+
+    assert_eq!(references.current_branch()?.as_deref(), Some("main"));
+    assert_eq!(references.resolve("HEAD")?, Some(id(1)?));
+    assert_eq!(references.resolve("main")?, Some(id(2)?));
+    assert_eq!(references.resolve("heads/main")?, Some(id(1)?));
+    assert_eq!(references.resolve("v1")?, Some(id(3)?));
+    assert_eq!(references.resolve("v2")?, None);
+    assert_eq!(
+        references
+            .iter_prefix("refs/tags/")?
+            .map(|reference| reference.map(|(name, _)| name))
+            .collect::<anyhow::Result<Vec<_>>>()?,
+        vec!["refs/tags/main", "refs/tags/v1"],
+    );
+    Ok(())
+}
+
+#[test]
+fn remotes() -> anyhow::Result<()> {
+    let id = |byte: u8| object::Id::read_bytes(&mut &[byte; 20][..]);
+    let references = References::new(Box::new(Memory::new()));
+
+    let store = references.store();
+    store.write("refs/remotes/origin/main", &Target::Direct(id(1)?))?;
+    store.write(
+        "refs/remotes/origin/HEAD",
+        &Target::Symbolic(String::from("refs/remotes/origin/main")),
+    )?;
+    store.write("refs/remotes/main", &Target::Direct(id(2)?))?;
+    store.write("refs/heads/main", &Target::Direct(id(3)?))?;
+
+    assert_eq!(
+        references.expand("origin/main")?.as_deref(),
+        Some("refs/remotes/origin/main"),
+    );
+    assert_eq!(references.resolve("origin/main")?, Some(id(1)?));
+    assert_eq!(references.resolve("origin")?, Some(id(1)?));
+    assert_eq!(references.resolve("main")?, Some(id(3)?));
+
+    assert_eq!(References::shorten("refs/heads/main"), "main");
+    assert_eq!(References::shorten("refs/tags/v1"), "v1");
+    assert_eq!(
+        References::shorten("refs/remotes/origin/main"),
+        "origin/main"
+    );
+    assert_eq!(References::shorten("refs/remotes/origin/HEAD"), "origin");
+    assert_eq!(References::shorten("refs/notes/commits"), "notes/commits");
+    assert_eq!(References::shorten("HEAD"), "HEAD");
+    Ok(())
+}
+
+#[test]
+fn branches() -> anyhow::Result<()> {
+    let id = object::Id::read_bytes(&mut &[1; 20][..])?;
+    let references = References::new(Box::new(Memory::new()));
+
+    references.create_branch("topic/a", &id)?;
+    assert!(references.create_branch("topic/a", &id).is_err());
+    assert!(references.create_branch("bad..name", &id).is_err());
+    assert!(references.create_branch("-x", &id).is_err());
+
+    let branches = references.branches()?.collect::<anyhow::Result<Vec<_>>>()?;
+    assert_eq!(branches, vec![(String::from("topic/a"), id)]);
+
+    assert_eq!(references.delete_branch("topic/a")?, id);
+    assert!(references.delete_branch("topic/a").is_err());
+    Ok(())
+}
+
+#[test]
+fn tags() -> anyhow::Result<()> {
+    let id = |byte: u8| object::Id::read_bytes(&mut &[byte; 20][..]);
+    let references = References::new(Box::new(Memory::new()));
+
+    assert_eq!(references.create_tag("v1.0", &id(1)?, false)?, None);
+    assert!(references.create_tag("v1.0", &id(2)?, false).is_err());
+    assert_eq!(references.create_tag("v1.0", &id(2)?, true)?, Some(id(1)?));
+    assert!(references.create_tag("v1.0^", &id(2)?, false).is_err());
+
+    let tags = references.tags()?.collect::<anyhow::Result<Vec<_>>>()?;
+    assert_eq!(tags, vec![(String::from("v1.0"), id(2)?)]);
+    assert_eq!(references.resolve("v1.0")?, Some(id(2)?));
+
+    assert_eq!(references.delete_tag("v1.0")?, id(2)?);
+    assert!(references.delete_tag("v1.0").is_err());
+    Ok(())
+}
+
+#[test]
+fn reflog() -> anyhow::Result<()> {
+    let id = |byte: u8| object::Id::read_bytes(&mut &[byte; 20][..]);
+    let person = object::Person::new(
+        String::from("C O Mitter"),
+        String::from("committer@example.com"),
+        object::Person::parse_time("@1600000000 +0100")?,
+    );
+    let references = References::new(Box::new(Memory::new()));
+    references.set_head(&Target::Symbolic(String::from("refs/heads/main")))?;
+
+    references.update_head(&id(1)?, &person, "commit (initial): one")?;
+    references.update_head(&id(2)?, &person, "commit: two\n")?;
+    references.create_branch("side", &id(3)?)?;
+    references.switch_head(
+        &Target::Symbolic(String::from("refs/heads/side")),
+        &person,
+        "checkout: moving from main to side",
+    )?;
+
+    let main = references.read_log("refs/heads/main")?;
+    assert_eq!(main.len(), 2);
+    assert_eq!((main[0].old, main[0].new), (None, id(1)?));
+    assert_eq!((main[1].old, main[1].new), (Some(id(1)?), id(2)?));
+
+    let head = references.read_log("HEAD")?;
+    assert_eq!(head.len(), 3);
+    assert_eq!((head[2].old, head[2].new), (Some(id(2)?), id(3)?));
+
+    let mut buffer = Vec::new();
+    main[0].write(&mut buffer)?;
+    assert!(buffer.starts_with(&[b'0'; 40]));
+    assert!(buffer.ends_with(b"+0100\tcommit (initial): one\n"));
+    let entry = LogEntry::read(&buffer[..buffer.len() - 1])?;
+    assert_eq!((entry.old, entry.new), (None, id(1)?));
+    assert_eq!(entry.committer.email(), "committer@example.com");
+    assert_eq!(entry.message, "commit (initial): one");
+
+    buffer.clear();
+    main[1].write(&mut buffer)?;
+    assert!(buffer.ends_with(b"\tcommit: two\n"));
+
+    references.delete_branch("main")?;
+    assert!(references.read_log("refs/heads/main")?.is_empty());
+    Ok(())
 }
@@ -0,0 +1,518 @@
+use std::cmp;
+use std::fs;
+use std::io;
+use std::io::BufRead as _;
+use std::io::Write as _;
+use std::iter;
+use std::path;
+
+use anyhow::anyhow;
+
+use crate::file;
+use crate::object;
+use crate::references::LogEntry;
+use crate::references::RefStore;
+use crate::references::Target;
+use crate::references::Update;
+
+/// Default reference storage, using one loose file per reference plus an
+/// optional `packed-refs` file.
+#[derive(Clone, Debug)]
+pub struct Files {
+    git: path::PathBuf,
+}
+
+impl Files {
+    /// Create a store rooted at the `.git` directory `git`.
+    pub fn new(git: path::PathBuf) -> Self {
+        Files { git }
+    }
+
+    /// Iterate over all references whose full name (e.g. `refs/tags/v1.0`)
+    /// starts with `prefix`, in byte-wise sorted order.
+    ///
+    /// Loose and packed references are merged lazily, with loose references
+    /// taking precedence, so only one directory listing and one line of
+    /// `packed-refs` are held in memory at a time.
+    pub fn iter(&self, prefix: &str) -> io::Result<IterPrefix> {
+        Ok(IterPrefix {
+            loose: Loose::new(&self.git, prefix)?.peekable(),
+            packed: Packed::new(&self.git.join("packed-refs"), prefix)?.peekable(),
+        })
+    }
+
+    /// Rewrite `packed-refs` without `name`, returning whether it was present.
+    fn delete_packed(&self, name: &str) -> anyhow::Result<bool> {
+        let mut lock = match file::WriteLock::new(self.git.join("packed-refs"))?.upgrade()? {
+            file::Lock::Write(_) => return Ok(false),
+            file::Lock::ReadWrite(lock) => lock,
+        };
+
+        let mut contents = String::new();
+        io::Read::read_to_string(&mut lock, &mut contents)?;
+
+        let mut found = false;
+        let mut skip_peeled = false;
+        let mut lock = lock.downgrade();
+
+        for line in contents.lines() {
+            if line.starts_with('^') && skip_peeled {
+                continue;
+            }
+            skip_peeled = line.split_once(' ').map(|(_, reference)| reference) == Some(name);
+            found |= skip_peeled;
+            if !skip_peeled {
+                writeln!(&mut lock, "{}", line)?;
+            }
+        }
+
+        if found {
+            lock.commit()?;
+        }
+        Ok(found)
+    }
+
+    /// Remove directories left empty by deleting `path`, stopping before the
+    /// `refs/<namespace>` level under `root`.
+    fn prune(root: &path::Path, path: &path::Path) {
+        for ancestor in path.ancestors().skip(1) {
+            match ancestor.strip_prefix(root) {
+                Ok(relative) if relative.components().count() > 2 => (),
+                _ => break,
+            }
+            if fs::remove_dir(ancestor).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+impl RefStore for Files {
+    fn read(&self, name: &str) -> anyhow::Result<Option<Target>> {
+        let path = self.git.join(name);
+        if path.is_file() {
+            return fs::read_to_string(path)?
+                .parse::<Target>()
+                .map(Option::Some);
+        }
+
+        for reference in self.iter(name)? {
+            let (reference, id) = reference?;
+            if reference == name {
+                return Ok(Some(Target::Direct(id)));
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn write(&self, name: &str, target: &Target) -> anyhow::Result<()> {
+        let mut reference = file::WriteLock::new(self.git.join(name))?;
+        writeln!(&mut reference, "{}", target)?;
+        reference.commit()?;
+        Ok(())
+    }
+
+    /// Holds the locks of every updated reference until all are checked and
+    /// written, so concurrent writers can't interleave.
+    fn update(&self, updates: &[Update]) -> anyhow::Result<()> {
+        let mut locks = Vec::with_capacity(updates.len());
+        for update in updates {
+            let lock = file::WriteLock::new(self.git.join(&update.name))
+                .map_err(|error| anyhow!("Cannot lock ref '{}': {}", update.name, error))?;
+            locks.push(lock);
+        }
+
+        for (update, lock) in updates.iter().zip(&mut locks) {
+            update.check(self.read(&update.name)?)?;
+            if let Some(new) = update.new {
+                writeln!(lock, "{}", Target::Direct(new))?;
+            }
+        }
+
+        // Delete references while still holding their locks, which are then
+        // released without replacing anything.
+        for (update, lock) in updates.iter().zip(locks) {
+            match update.new {
+                Some(_) => lock.commit()?,
+                None => {
+                    self.delete(&update.name)?;
+                    drop(lock);
+                    Self::prune(&self.git, &self.git.join(&update.name));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn delete(&self, name: &str) -> anyhow::Result<bool> {
+        let path = self.git.join(name);
+        let loose = match fs::remove_file(&path) {
+            Ok(()) => true,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => false,
+            Err(error) => return Err(error.into()),
+        };
+
+        Self::prune(&self.git, &path);
+
+        // Like `git`, forget the history of deleted references.
+        let logs = self.git.join("logs");
+        let log = logs.join(name);
+        match fs::remove_file(&log) {
+            Ok(()) => Self::prune(&logs, &log),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => (),
+            Err(error) => return Err(error.into()),
+        }
+
+        Ok(self.delete_packed(name)? || loose)
+    }
+
+    fn iter_prefix<'a>(
+        &'a self,
+        prefix: &str,
+    ) -> anyhow::Result<Box<dyn Iterator<Item = anyhow::Result<(String, object::Id)>> + 'a>> {
+        self.iter(prefix)
+            .map(|iter| Box::new(iter) as Box<dyn Iterator<Item = _>>)
+            .map_err(anyhow::Error::from)
+    }
+
+    fn append_log(&self, name: &str, entry: &LogEntry) -> anyhow::Result<()> {
+        let path = self.git.join("logs").join(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // Write each entry in one call so concurrent appends don't interleave.
+        let mut buffer = Vec::new();
+        entry.write(&mut buffer)?;
+        fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?
+            .write_all(&buffer)?;
+        Ok(())
+    }
+
+    fn read_log(&self, name: &str) -> anyhow::Result<Vec<LogEntry>> {
+        let bytes = match fs::read(self.git.join("logs").join(name)) {
+            Ok(bytes) => bytes,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(error) => return Err(error.into()),
+        };
+        bytes
+            .split(|byte| *byte == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(LogEntry::read)
+            .collect()
+    }
+
+    fn write_log(&self, name: &str, entries: &[LogEntry]) -> anyhow::Result<()> {
+        let mut log = file::WriteLock::new(self.git.join("logs").join(name))?;
+        for entry in entries {
+            entry.write(&mut log)?;
+        }
+        log.commit()?;
+        Ok(())
+    }
+}
+
+/// Streaming iterator over references matching a prefix.
+/// See [`Files::iter`].
+#[derive(Debug)]
+pub struct IterPrefix {
+    loose: iter::Peekable<Loose>,
+    packed: iter::Peekable<Packed>,
+}
+
+impl Iterator for IterPrefix {
+    type Item = anyhow::Result<(String, object::Id)>;
+    fn next(&mut self) -> Option<Self::Item> {
+        let ordering = match (self.loose.peek(), self.packed.peek()) {
+            (None, None) => return None,
+            (Some(_), None) | (Some(Err(_)), _) => cmp::Ordering::Less,
+            (None, Some(_)) | (_, Some(Err(_))) => cmp::Ordering::Greater,
+            (Some(Ok((loose, _))), Some(Ok((packed, _)))) => loose.cmp(packed),
+        };
+
+        match ordering {
+            cmp::Ordering::Less => self.loose.next(),
+            cmp::Ordering::Greater => self.packed.next(),
+            cmp::Ordering::Equal => {
+                self.packed.next();
+                self.loose.next()
+            }
+        }
+    }
+}
+
+/// Depth-first walk over loose reference files below `.git/refs`.
+#[derive(Debug)]
+struct Loose {
+    git: path::PathBuf,
+    prefix: String,
+    /// Stack of sorted, unvisited directory entries in reverse order.
+    stack: Vec<Vec<(String, bool)>>,
+}
+
+impl Loose {
+    fn new(git: &path::Path, prefix: &str) -> io::Result<Self> {
+        let mut loose = Loose {
+            git: git.to_path_buf(),
+            prefix: prefix.to_owned(),
+            stack: Vec::new(),
+        };
+
+        // Start from the deepest directory fully named by `prefix`, but never
+        // leave the `refs` namespace.
+        let start = match prefix.rfind('/') {
+            Some(index) if prefix.starts_with("refs/") => &prefix[..index],
+            _ if "refs/".starts_with(prefix) => "refs",
+            _ => return Ok(loose),
+        };
+
+        match fs::metadata(git.join(start)) {
+            Ok(metadata) if metadata.is_dir() => {
+                let entries = loose.list(start)?;
+                loose.stack.push(entries);
+            }
+            Ok(_) => (),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => (),
+            Err(error) => return Err(error),
+        }
+
+        Ok(loose)
+    }
+
+    fn list(&self, directory: &str) -> io::Result<Vec<(String, bool)>> {
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(self.git.join(directory))? {
+            let entry = entry?;
+            let name = match entry.file_name().into_string() {
+                Ok(name) if !name.ends_with(".lock") => name,
+                _ => continue,
+            };
+            let name = match directory {
+                "" => name,
+                _ => format!("{}/{}", directory, name),
+            };
+            entries.push((name, entry.file_type()?.is_dir()));
+        }
+
+        // Directories sort as though they had a trailing slash, so that
+        // `refs/heads/a-b` is visited before `refs/heads/a/c`.
+        entries.sort_by(|(a, a_dir), (b, b_dir)| {
+            let a = a.bytes().chain(a_dir.then_some(b'/'));
+            let b = b.bytes().chain(b_dir.then_some(b'/'));
+            b.cmp(a)
+        });
+
+        Ok(entries)
+    }
+}
+
+impl Iterator for Loose {
+    type Item = anyhow::Result<(String, object::Id)>;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (name, directory) = match self.stack.last_mut()?.pop() {
+                Some(entry) => entry,
+                None => {
+                    self.stack.pop();
+                    continue;
+                }
+            };
+
+            if directory {
+                let descend = format!("{}/", name);
+                if !descend.starts_with(&self.prefix) && !self.prefix.starts_with(&descend) {
+                    continue;
+                }
+                match self.list(&name) {
+                    Ok(entries) => self.stack.push(entries),
+                    Err(error) => return Some(Err(error.into())),
+                }
+                continue;
+            }
+
+            if !name.starts_with(&self.prefix) {
+                continue;
+            }
+
+            let target = fs::read_to_string(self.git.join(&name))
+                .map_err(anyhow::Error::from)
+                .and_then(|target| target.parse::<Target>());
+
+            // Symbolic references such as `refs/remotes/origin/HEAD` are
+            // only visible through `RefStore::read`.
+            match target {
+                Ok(Target::Direct(id)) => return Some(Ok((name, id))),
+                Ok(Target::Symbolic(_)) => continue,
+                Err(error) => return Some(Err(error)),
+            }
+        }
+    }
+}
+
+/// Line-by-line reader over `.git/packed-refs`.
+#[derive(Debug)]
+struct Packed {
+    lines: Option<io::Lines<io::BufReader<fs::File>>>,
+    prefix: String,
+    sorted: bool,
+}
+
+impl Packed {
+    fn new(path: &path::Path, prefix: &str) -> io::Result<Self> {
+        let lines = match fs::File::open(path) {
+            Ok(file) => Some(io::BufReader::new(file).lines()),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => None,
+            Err(error) => return Err(error),
+        };
+
+        Ok(Packed {
+            lines,
+            prefix: prefix.to_owned(),
+            sorted: false,
+        })
+    }
+}
+
+impl Iterator for Packed {
+    type Item = anyhow::Result<(String, object::Id)>;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.as_mut()?.next()? {
+                Ok(line) => line,
+                Err(error) => return Some(Err(error.into())),
+            };
+
+            // Header line, e.g. `# pack-refs with: peeled fully-peeled sorted `
+            if let Some(header) = line.strip_prefix('#') {
+                self.sorted = header.split_whitespace().any(|trait_| trait_ == "sorted");
+                continue;
+            }
+
+            // Peeled tag target of the previous line.
+            if line.starts_with('^') {
+                continue;
+            }
+
+            let (id, name) = match line.split_once(' ') {
+                Some(split) => split,
+                None => {
+                    return Some(Err(anyhow::anyhow!(
+                        "Malformed line in packed-refs: `{}`",
+                        line
+                    )))
+                }
+            };
+
+            if name.starts_with(&self.prefix) {
+                return Some(id.parse().map(|id| (name.to_owned(), id)));
+            }
+
+            if self.sorted && *name > *self.prefix {
+                self.lines = None;
+                return None;
+            }
+        }
+    }
+}
+
+#[test]
+fn iter_prefix() -> anyhow::Result<()> {
+    let git = crate::util::TempDir::new();
+
+    let a = "1111111111111111111111111111111111111111";
+    let b = "2222222222222222222222222222222222222222";
+
+    for name in &["refs/heads/a/c", "refs/heads/a-b", "refs/tags/v1"] {
+        let path = git.join(name);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(path, a)?;
+    }
+
+    fs::write(
+        git.join("packed-refs"),
+        format!(
+            "# pack-refs with: peeled fully-peeled sorted \n\
+             {b} refs/heads/a-b\n\
+             {b} refs/heads/z\n\
+             {b} refs/tags/v0\n\
+             ^{a}\n",
+            a = a,
+            b = b,
+        ),
+    )?;
+
+    let files = Files::new(git.to_path_buf());
+    let heads = files
+        .iter("refs/heads/")?
+        .collect::<anyhow::Result<Vec<_>>>()?
+        .into_iter()
+        .map(|(name, id)| (name, id.to_string()))
+        .collect::<Vec<_>>();
+
+    let tags = files
+        .iter("refs/t")?
+        .map(|reference| reference.map(|(name, _)| name))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    assert_eq!(
+        heads,
+        vec![
+            (String::from("refs/heads/a-b"), String::from(a)),
+            (String::from("refs/heads/a/c"), String::from(a)),
+            (String::from("refs/heads/z"), String::from(b)),
+        ]
+    );
+    assert_eq!(tags, vec!["refs/tags/v0", "refs/tags/v1"]);
+    Ok(())
+}
+
+#[test]
+fn update() -> anyhow::Result<()> {
+    let git = crate::util::TempDir::new();
+    fs::create_dir_all(&git)?;
+
+    let a = "1111111111111111111111111111111111111111".parse::<object::Id>()?;
+    let b = "2222222222222222222222222222222222222222".parse::<object::Id>()?;
+    fs::write(
+        git.join("packed-refs"),
+        format!("{} refs/remotes/origin/main\n", a),
+    )?;
+
+    let files = Files::new(git.to_path_buf());
+    let update = |name: &str, old, new| Update {
+        name: String::from(name),
+        old,
+        new,
+    };
+
+    // A stale old value leaves every reference untouched.
+    let stale = files.update(&[
+        update("refs/remotes/origin/side", None, Some(a)),
+        update("refs/remotes/origin/main", Some(b), Some(b)),
+    ]);
+    let untouched = files.read("refs/remotes/origin/side")?;
+
+    files.update(&[
+        update("refs/remotes/origin/side", None, Some(a)),
+        update("refs/remotes/origin/main", Some(a), Some(b)),
+    ])?;
+    let main = files.read("refs/remotes/origin/main")?;
+    let locked = git.join("refs/remotes/origin/main.lock").exists();
+
+    // Deleting removes both the loose and packed copies.
+    files.update(&[update("refs/remotes/origin/main", Some(b), None)])?;
+    let deleted = files.read("refs/remotes/origin/main")?;
+    let side = files.read("refs/remotes/origin/side")?;
+
+    assert!(stale.is_err());
+    assert_eq!(untouched, None);
+    assert_eq!(main, Some(Target::Direct(b)));
+    assert!(!locked);
+    assert_eq!(deleted, None);
+    assert_eq!(side, Some(Target::Direct(a)));
+    Ok(())
+}
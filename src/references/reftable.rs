@@ -0,0 +1,1265 @@
+//! Reader and writer for the [reftable][rt] reference storage format.
+//!
+//! Tables are stored under `.git/reftable/` and stacked in the order given by
+//! `tables.list`, with later tables overriding earlier ones. Ref blocks, log
+//! blocks, and the indexes over them are supported; object blocks are
+//! skipped when reading and never emitted when writing.
+//!
+//! Lookups seek through each table's index (or its block headers, for small
+//! tables without one) instead of parsing every record, and writes compact
+//! the newest tables together whenever the stack stops shrinking
+//! geometrically, like `git`, so that it stays logarithmic in length.
+//!
+//! [rt]: https://git-scm.com/docs/reftable
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::io::Read as _;
+use std::io::Write as _;
+use std::mem;
+use std::path;
+use std::str;
+
+use anyhow::anyhow;
+use byteorder::BigEndian;
+use byteorder::ByteOrder as _;
+use chrono::TimeZone as _;
+use rand::Rng as _;
+
+use crate::file;
+use crate::object;
+use crate::references::LogEntry;
+
+const MAGIC: &[u8; 4] = b"REFT";
+/// Length of the version 1 header, which is the only version written.
+const HEADER_LEN: usize = 24;
+const BLOCK_SIZE: usize = 4096;
+const RESTART_INTERVAL: usize = 16;
+
+/// Each table in a compacted stack is at least this many times larger than
+/// all the newer tables above it combined.
+const COMPACTION_FACTOR: u64 = 2;
+
+/// Times to reload `tables.list` when a concurrent compaction removes one of
+/// its tables before it can be opened.
+const RELOAD_ATTEMPTS: usize = 5;
+
+const REF: u8 = b'r';
+const INDEX: u8 = b'i';
+const LOG: u8 = b'g';
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Value {
+    Deletion,
+    Id(object::Id),
+    /// Annotated tag and the object it peels to.
+    Peeled(object::Id, object::Id),
+    Symbolic(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct Reftable {
+    root: path::PathBuf,
+}
+
+impl Reftable {
+    pub fn new(root: path::PathBuf) -> Self {
+        Reftable { root }
+    }
+
+    /// Open a consistent snapshot of the current stack for reading.
+    pub fn stack(&self) -> anyhow::Result<Stack> {
+        Stack::open(&self.root)
+    }
+
+    /// Append a new table containing `updates` to the stack, once `check`
+    /// accepts the stack as it is under the lock. Deleting a reference also
+    /// deletes its reflog.
+    pub fn write<F>(&self, updates: &[(String, Value)], check: F) -> anyhow::Result<()>
+    where
+        F: FnOnce(&Stack) -> anyhow::Result<()>,
+    {
+        // Later updates to the same reference take precedence.
+        let refs = updates
+            .iter()
+            .cloned()
+            .collect::<BTreeMap<_, _>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+
+        self.append(|stack, _| {
+            check(stack)?;
+            let mut logs = Vec::new();
+            for (name, _) in refs.iter().filter(|(_, value)| *value == Value::Deletion) {
+                for index in stack.logs(name)?.into_keys() {
+                    logs.push((log_key(name, index), None));
+                }
+            }
+            Ok((refs, logs))
+        })
+    }
+
+    /// Append `entry` to the reflog of reference `name`.
+    pub fn append_log(&self, name: &str, entry: &LogEntry) -> anyhow::Result<()> {
+        self.append(|_, update_index| {
+            Ok((
+                Vec::new(),
+                vec![(log_key(name, update_index), Some(entry.clone()))],
+            ))
+        })
+    }
+
+    /// Replace the reflog of reference `name`, deleting every existing entry
+    /// and appending `entries` in order.
+    pub fn write_log(&self, name: &str, entries: &[LogEntry]) -> anyhow::Result<()> {
+        self.append(|stack, update_index| {
+            let mut logs = stack
+                .logs(name)?
+                .into_keys()
+                .map(|index| (log_key(name, index), None))
+                .collect::<Vec<_>>();
+            logs.extend(
+                entries
+                    .iter()
+                    .zip(update_index..)
+                    .map(|(entry, index)| (log_key(name, index), Some(entry.clone()))),
+            );
+            logs.sort_by(|(a, _), (b, _)| a.cmp(b));
+            Ok((Vec::new(), logs))
+        })
+    }
+
+    /// Write the records chosen by `prepare`, given the locked stack and the
+    /// next update index, as a new table, then compact the stack.
+    fn append<F>(&self, prepare: F) -> anyhow::Result<()>
+    where
+        F: FnOnce(&Stack, u64) -> anyhow::Result<(Vec<(String, Value)>, Vec<LogRecord>)>,
+    {
+        fs::create_dir_all(&self.root)?;
+
+        let mut list = file::WriteLock::new(self.root.join("tables.list"))?;
+        let stack = Stack::open(&self.root)?;
+        let update_index = stack.max_update_index() + 1;
+        let (refs, logs) = prepare(&stack, update_index)?;
+        if refs.is_empty() && logs.is_empty() {
+            return Ok(());
+        }
+
+        let (min, max) = logs
+            .iter()
+            .map(|(key, _)| log_index(key))
+            .fold((update_index, update_index), |(min, max), index| {
+                (min.min(index), max.max(index))
+            });
+
+        let mut names = stack.names();
+        drop(stack);
+        names.push(self.create(&refs, &logs, min, max)?);
+
+        let removed = self.compact(&mut names)?;
+        for name in &names {
+            writeln!(&mut list, "{}", name)?;
+        }
+        list.commit()?;
+
+        // Readers that listed the removed tables before the commit reload
+        // the stack when they find them missing.
+        for name in removed {
+            let _ = fs::remove_file(self.root.join(name));
+        }
+        Ok(())
+    }
+
+    /// Write a new table file, returning its name.
+    fn create(
+        &self,
+        refs: &[(String, Value)],
+        logs: &[LogRecord],
+        min: u64,
+        max: u64,
+    ) -> anyhow::Result<String> {
+        let name = format!(
+            "0x{:012x}-0x{:012x}-{:08x}.ref",
+            min,
+            max,
+            rand::thread_rng().gen::<u32>(),
+        );
+        let mut table = file::Temp::new(self.root.join(&name))?;
+        table.write_all(&write_table(refs, logs, min, max))?;
+        table.commit()?;
+        Ok(name)
+    }
+
+    /// Merge the newest tables in `names` into one until each table is at
+    /// least [`COMPACTION_FACTOR`] times larger than all newer tables
+    /// combined, returning the names of the tables merged away.
+    fn compact(&self, names: &mut Vec<String>) -> anyhow::Result<Vec<String>> {
+        let sizes = names
+            .iter()
+            .map(|name| fs::metadata(self.root.join(name)).map(|metadata| metadata.len()))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let mut start = names.len() - 1;
+        let mut newer = sizes[start];
+        while start > 0 && sizes[start - 1] < COMPACTION_FACTOR * newer {
+            start -= 1;
+            newer += sizes[start];
+        }
+        if start + 1 >= names.len() {
+            return Ok(Vec::new());
+        }
+
+        let mut refs = BTreeMap::new();
+        let mut logs = BTreeMap::new();
+        let (mut min, mut max) = (u64::MAX, 0);
+        for name in &names[start..] {
+            let table = Table::open(name.clone(), fs::File::open(self.root.join(name))?)?;
+            min = min.min(table.header.min_update_index);
+            max = max.max(table.header.max_update_index);
+
+            let mut records = table.seek(REF, b"")?;
+            while let Some((key, record)) = records.next()? {
+                if let Record::Ref(value) = record {
+                    refs.insert(String::from_utf8(key)?, value);
+                }
+            }
+            let mut records = table.seek(LOG, b"")?;
+            while let Some((key, record)) = records.next()? {
+                if let Record::Log(entry) = record {
+                    logs.insert(key, entry);
+                }
+            }
+        }
+
+        // Deletions only need to hide records in older tables.
+        if start == 0 {
+            refs.retain(|_, value| *value != Value::Deletion);
+            logs.retain(|_, entry| entry.is_some());
+        }
+
+        let refs = refs.into_iter().collect::<Vec<_>>();
+        let logs = logs.into_iter().collect::<Vec<_>>();
+        let merged = self.create(&refs, &logs, min, max)?;
+        Ok(names.splice(start.., Some(merged)).collect())
+    }
+}
+
+/// Key of a reflog record, and its entry or `None` for a deletion.
+type LogRecord = (Vec<u8>, Option<LogEntry>);
+
+/// Log records are sorted by reference name, then newest first.
+fn log_key(name: &str, update_index: u64) -> Vec<u8> {
+    let mut key = Vec::with_capacity(name.len() + 9);
+    key.extend_from_slice(name.as_bytes());
+    key.push(0);
+    key.extend_from_slice(&(u64::MAX - update_index).to_be_bytes());
+    key
+}
+
+fn log_index(key: &[u8]) -> u64 {
+    u64::MAX - BigEndian::read_u64(&key[key.len() - 8..])
+}
+
+/// Snapshot of every table in the stack, oldest first.
+#[derive(Debug)]
+pub struct Stack {
+    tables: Vec<Table>,
+}
+
+impl Stack {
+    fn open(root: &path::Path) -> anyhow::Result<Self> {
+        let mut attempts = 0;
+        'reload: loop {
+            let names = match fs::read_to_string(root.join("tables.list")) {
+                Ok(list) => list.lines().map(String::from).collect::<Vec<_>>(),
+                Err(error) if error.kind() == io::ErrorKind::NotFound => Vec::new(),
+                Err(error) => return Err(error.into()),
+            };
+
+            let mut tables = Vec::with_capacity(names.len());
+            for name in names {
+                match fs::File::open(root.join(&name)) {
+                    Ok(file) => tables.push(Table::open(name, file)?),
+                    Err(error)
+                        if error.kind() == io::ErrorKind::NotFound
+                            && attempts < RELOAD_ATTEMPTS =>
+                    {
+                        attempts += 1;
+                        continue 'reload;
+                    }
+                    Err(error) => return Err(error.into()),
+                }
+            }
+            return Ok(Stack { tables });
+        }
+    }
+
+    fn names(&self) -> Vec<String> {
+        self.tables.iter().map(|table| table.name.clone()).collect()
+    }
+
+    /// Update indexes only grow up the stack, so the newest table has the
+    /// largest.
+    fn max_update_index(&self) -> u64 {
+        self.tables
+            .last()
+            .map_or(0, |table| table.header.max_update_index)
+    }
+
+    /// Look up reference `name` in the newest table that mentions it.
+    pub fn lookup(&self, name: &str) -> anyhow::Result<Option<Value>> {
+        for table in self.tables.iter().rev() {
+            match table.seek(REF, name.as_bytes())?.next()? {
+                Some((key, Record::Ref(Value::Deletion))) if key == name.as_bytes() => {
+                    return Ok(None)
+                }
+                Some((key, Record::Ref(value))) if key == name.as_bytes() => {
+                    return Ok(Some(value))
+                }
+                _ => (),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Collect every reference whose name starts with `prefix`, with
+    /// deletions removed.
+    pub fn scan(&self, prefix: &str) -> anyhow::Result<BTreeMap<String, Value>> {
+        let mut merged = BTreeMap::new();
+        for table in &self.tables {
+            let mut records = table.seek(REF, prefix.as_bytes())?;
+            while let Some((key, record)) = records.next()? {
+                if !key.starts_with(prefix.as_bytes()) {
+                    break;
+                }
+                match record {
+                    Record::Ref(Value::Deletion) => merged.remove(str::from_utf8(&key)?),
+                    Record::Ref(value) => merged.insert(String::from_utf8(key)?, value),
+                    _ => return Err(anyhow!("Unexpected record in reftable ref block")),
+                };
+            }
+        }
+        Ok(merged)
+    }
+
+    /// Read the reflog of reference `name`, oldest entry first.
+    pub fn read_log(&self, name: &str) -> anyhow::Result<Vec<LogEntry>> {
+        Ok(self.logs(name)?.into_values().collect())
+    }
+
+    /// Reflog entries of `name` by update index, with deletions applied.
+    fn logs(&self, name: &str) -> anyhow::Result<BTreeMap<u64, LogEntry>> {
+        let mut prefix = name.as_bytes().to_vec();
+        prefix.push(0);
+
+        let mut logs = BTreeMap::new();
+        for table in &self.tables {
+            let mut records = table.seek(LOG, &prefix)?;
+            while let Some((key, record)) = records.next()? {
+                if !key.starts_with(&prefix) || key.len() != prefix.len() + 8 {
+                    break;
+                }
+                match record {
+                    Record::Log(Some(entry)) => logs.insert(log_index(&key), entry),
+                    Record::Log(None) => logs.remove(&log_index(&key)),
+                    _ => return Err(anyhow!("Unexpected record in reftable log block")),
+                };
+            }
+        }
+        Ok(logs)
+    }
+}
+
+/// Read-only, memory-mapped table, located by its header and footer.
+#[derive(Debug)]
+struct Table {
+    name: String,
+    map: memmap2::Mmap,
+    header: Header,
+    /// Position of the footer, where the last section ends.
+    footer: usize,
+    /// Position of the first ref block, if any.
+    refs: Option<usize>,
+    ref_index: Option<usize>,
+    /// Position of the first log block, if any.
+    logs: Option<usize>,
+    log_index: Option<usize>,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Header {
+    version: u8,
+    block_size: usize,
+    min_update_index: u64,
+    max_update_index: u64,
+}
+
+impl Header {
+    fn len(&self) -> usize {
+        match self.version {
+            1 => 24,
+            _ => 28,
+        }
+    }
+
+    fn footer_len(&self) -> usize {
+        self.len() + 44
+    }
+}
+
+fn read_header(buffer: &[u8]) -> anyhow::Result<Header> {
+    if buffer.len() < 24 || &buffer[..4] != MAGIC {
+        return Err(anyhow!("Expected `REFT` signature bytes"));
+    }
+
+    let version = buffer[4];
+    if version != 1 && version != 2 {
+        return Err(anyhow!("Unsupported reftable version {}", version));
+    }
+
+    Ok(Header {
+        version,
+        block_size: BigEndian::read_u24(&buffer[5..8]) as usize,
+        min_update_index: BigEndian::read_u64(&buffer[8..16]),
+        max_update_index: BigEndian::read_u64(&buffer[16..24]),
+    })
+}
+
+impl Table {
+    fn open(name: String, file: fs::File) -> anyhow::Result<Self> {
+        // SAFETY: tables are never modified once renamed into place, only
+        // deleted, which leaves existing mappings intact.
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        Self::new(name, map)
+    }
+
+    fn new(name: String, map: memmap2::Mmap) -> anyhow::Result<Self> {
+        let header = read_header(&map)?;
+        let footer = map
+            .len()
+            .checked_sub(header.footer_len())
+            .filter(|footer| *footer >= header.len())
+            .ok_or_else(|| anyhow!("Truncated reftable {}", name))?;
+
+        let checksum = BigEndian::read_u32(&map[map.len() - 4..]);
+        let mut crc = flate2::Crc::new();
+        crc.update(&map[footer..map.len() - 4]);
+        if crc.sum() != checksum {
+            return Err(anyhow!("Reftable footer checksum mismatch in {}", name));
+        }
+
+        // Ref index, object, object index, log, and log index positions.
+        let sections = &map[footer + header.len()..];
+        let position = |index: usize| {
+            Some(BigEndian::read_u64(&sections[index * 8..]) as usize)
+                .filter(|position| *position > 0)
+        };
+
+        // The first block starts after the header, but its position is zero,
+        // which the footer can't distinguish from a missing section.
+        let first = map.get(header.len()).copied();
+        let refs = Some(0).filter(|_| footer > header.len() && first == Some(REF));
+        let logs = match first {
+            Some(LOG) if footer > header.len() => Some(0),
+            _ => position(3),
+        };
+
+        Ok(Table {
+            ref_index: position(0),
+            log_index: position(4),
+            name,
+            map,
+            header,
+            footer,
+            refs,
+            logs,
+        })
+    }
+
+    /// Read the block starting at `position`, or `None` past the last one.
+    fn block(&self, position: usize) -> anyhow::Result<Option<Block<'_>>> {
+        let corrupt = || anyhow!("Corrupt block at {} in reftable {}", position, self.name);
+
+        // The first block contains the file header.
+        let skip = match position {
+            0 => self.header.len(),
+            _ => 0,
+        };
+        let start = position.saturating_add(skip);
+        if start.saturating_add(4) > self.footer {
+            return Ok(None);
+        }
+
+        let kind = self.map[start];
+        let len = BigEndian::read_u24(&self.map[start + 1..start + 4]) as usize;
+        if len < skip + 4 + 2 {
+            return Err(corrupt());
+        }
+
+        let (data, next) = match kind {
+            // Log blocks are compressed after their header, and their length
+            // is that of the inflated block.
+            LOG => {
+                let mut data = Vec::with_capacity(len);
+                data.extend_from_slice(&self.map[position..start + 4]);
+                let mut decoder =
+                    flate2::bufread::ZlibDecoder::new(&self.map[start + 4..self.footer]);
+                decoder.read_to_end(&mut data).map_err(|_| corrupt())?;
+                if data.len() != len {
+                    return Err(corrupt());
+                }
+                let next = start + 4 + decoder.total_in() as usize;
+                (Cow::Owned(data), next)
+            }
+            REF | INDEX | b'o' => {
+                let end = position + len;
+                let data = self.map.get(position..end).ok_or_else(corrupt)?;
+
+                // Blocks are either padded with zeros up to the block size,
+                // or immediately followed by the next block.
+                let padded = self.header.block_size > 0
+                    && len < self.header.block_size
+                    && self.map.get(end) == Some(&0);
+                let next = match padded {
+                    true => position + self.header.block_size,
+                    false => end,
+                };
+                (Cow::Borrowed(data), next)
+            }
+            _ => return Ok(None),
+        };
+
+        let restarts = BigEndian::read_u16(&data[len - 2..]) as usize;
+        let end = (len - 2)
+            .checked_sub(restarts * 3)
+            .filter(|end| *end >= skip + 4)
+            .ok_or_else(corrupt)?;
+
+        Ok(Some(Block {
+            kind,
+            data,
+            first: skip + 4,
+            end,
+            restarts,
+            next,
+        }))
+    }
+
+    /// Iterate over the records of the section of `kind` blocks, starting
+    /// from the first whose key is at least `key`.
+    fn seek(&self, kind: u8, key: &[u8]) -> anyhow::Result<Records<'_>> {
+        let (start, index) = match kind {
+            REF => (self.refs, self.ref_index),
+            _ => (self.logs, self.log_index),
+        };
+        let mut position = match start {
+            Some(position) => position,
+            None => return Ok(Records::empty(self, kind)),
+        };
+
+        match index {
+            // Each index record holds the last key of the block it points
+            // to, which may be another index block.
+            Some(mut index) => loop {
+                let block = self
+                    .block(index)?
+                    .ok_or_else(|| anyhow!("Missing index block in reftable {}", self.name))?;
+                if block.kind != INDEX {
+                    position = index;
+                    break;
+                }
+                let (cursor, mut previous) = block.find(key)?;
+                if cursor >= block.end {
+                    return Ok(Records::empty(self, kind));
+                }
+                match block.record(cursor, &mut previous)?.0 {
+                    // Index blocks follow the blocks they point to.
+                    Record::Index(child) if child < index => index = child,
+                    _ => return Err(anyhow!("Invalid record in reftable index block")),
+                }
+            },
+            // Without an index, skip over every block whose successor starts
+            // at or before `key`.
+            None => {
+                let mut block = self.block(position)?;
+                while let Some(current) = block.take().filter(|block| block.kind == kind) {
+                    match self.block(current.next)? {
+                        Some(next) if next.kind == kind && next.first_key()?.as_slice() <= key => {
+                            position = current.next;
+                            block = Some(next);
+                        }
+                        _ => break,
+                    }
+                }
+            }
+        }
+
+        let block = self.block(position)?.filter(|block| block.kind == kind);
+        let (cursor, previous) = match &block {
+            Some(block) => block.find(key)?,
+            None => (0, Vec::new()),
+        };
+        Ok(Records {
+            table: self,
+            kind,
+            block,
+            cursor,
+            key: previous,
+        })
+    }
+}
+
+/// Single block, with records up to `end` followed by `restarts` 3-byte
+/// restart offsets.
+struct Block<'a> {
+    kind: u8,
+    /// Contents from the start of the block, inflated for log blocks.
+    data: Cow<'a, [u8]>,
+    /// Offset of the first record.
+    first: usize,
+    end: usize,
+    restarts: usize,
+    /// Position of the following block in the table.
+    next: usize,
+}
+
+/// Record decoded from any kind of block.
+enum Record {
+    Ref(Value),
+    /// Position of the block whose last key is this record's.
+    Index(usize),
+    /// Reflog entry, or `None` for a deletion.
+    Log(Option<LogEntry>),
+}
+
+impl Block<'_> {
+    fn restart(&self, index: usize) -> anyhow::Result<usize> {
+        let offset = self.end + index * 3;
+        let restart = BigEndian::read_u24(&self.data[offset..offset + 3]) as usize;
+        match restart >= self.first && restart < self.end {
+            true => Ok(restart),
+            false => Err(anyhow!("Invalid restart offset in reftable block")),
+        }
+    }
+
+    /// Key of the record at restart offset `restart`, which is stored
+    /// without a shared prefix.
+    fn restart_key(&self, restart: usize) -> anyhow::Result<Vec<u8>> {
+        let mut key = Vec::new();
+        read_key(&self.data[..self.end], restart, &mut key)?;
+        Ok(key)
+    }
+
+    fn first_key(&self) -> anyhow::Result<Vec<u8>> {
+        self.restart_key(self.first)
+    }
+
+    /// Find the first record whose key is at least `key`, returning its
+    /// offset (or `end` if there is none) and the key of the record before
+    /// it, which the next record's key is relative to.
+    fn find(&self, key: &[u8]) -> anyhow::Result<(usize, Vec<u8>)> {
+        // Binary search for the last restart point at or before `key`.
+        let (mut lo, mut hi) = (0, self.restarts);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            match self.restart_key(self.restart(mid)?)?.as_slice() <= key {
+                true => lo = mid + 1,
+                false => hi = mid,
+            }
+        }
+
+        let mut cursor = match lo {
+            0 => self.first,
+            _ => self.restart(lo - 1)?,
+        };
+        let mut current = Vec::new();
+        while cursor < self.end {
+            let previous = current.clone();
+            let (_, next) = self.record(cursor, &mut current)?;
+            if current.as_slice() >= key {
+                return Ok((cursor, previous));
+            }
+            cursor = next;
+        }
+        Ok((cursor, current))
+    }
+
+    /// Decode the record at offset `cursor`, whose key is relative to `key`,
+    /// returning the offset of the next record.
+    fn record(&self, cursor: usize, key: &mut Vec<u8>) -> anyhow::Result<(Record, usize)> {
+        let records = &self.data[..self.end];
+        let (r#type, cursor) = read_key(records, cursor, key)?;
+        match self.kind {
+            REF => {
+                read_ref(records, cursor, r#type).map(|(value, next)| (Record::Ref(value), next))
+            }
+            INDEX => {
+                let (position, next) = read_varint(records, cursor)?;
+                Ok((Record::Index(position as usize), next))
+            }
+            LOG => read_log_entry(records, cursor, r#type)
+                .map(|(entry, next)| (Record::Log(entry), next)),
+            kind => Err(anyhow!("Unexpected reftable block type {}", kind)),
+        }
+    }
+}
+
+/// Records of one section of a table, in key order across its blocks.
+struct Records<'a> {
+    table: &'a Table,
+    kind: u8,
+    block: Option<Block<'a>>,
+    cursor: usize,
+    key: Vec<u8>,
+}
+
+impl<'a> Records<'a> {
+    fn empty(table: &'a Table, kind: u8) -> Self {
+        Records {
+            table,
+            kind,
+            block: None,
+            cursor: 0,
+            key: Vec::new(),
+        }
+    }
+
+    fn next(&mut self) -> anyhow::Result<Option<(Vec<u8>, Record)>> {
+        loop {
+            let block = match &self.block {
+                None => return Ok(None),
+                Some(block) => block,
+            };
+
+            if self.cursor < block.end {
+                let (record, next) = block.record(self.cursor, &mut self.key)?;
+                self.cursor = next;
+                return Ok(Some((self.key.clone(), record)));
+            }
+
+            let next = block.next;
+            self.block = self
+                .table
+                .block(next)?
+                .filter(|block| block.kind == self.kind);
+            if let Some(block) = &self.block {
+                self.cursor = block.first;
+                self.key.clear();
+            }
+        }
+    }
+}
+
+/// Return the slice of `len` bytes at `cursor`, or an error if `buffer` is
+/// too short.
+fn slice(buffer: &[u8], cursor: usize, len: usize) -> anyhow::Result<&[u8]> {
+    cursor
+        .checked_add(len)
+        .and_then(|end| buffer.get(cursor..end))
+        .ok_or_else(|| anyhow!("Truncated record in reftable"))
+}
+
+/// Decode the key at `cursor` into `key`, which holds the previous record's
+/// key, returning the value type and the offset of the value.
+fn read_key(buffer: &[u8], cursor: usize, key: &mut Vec<u8>) -> anyhow::Result<(u8, usize)> {
+    let (prefix, cursor) = read_varint(buffer, cursor)?;
+    let (suffix, cursor) = read_varint(buffer, cursor)?;
+    let (suffix, r#type) = (suffix as usize >> 3, (suffix & 0b111) as u8);
+
+    if prefix as usize > key.len() {
+        return Err(anyhow!("Invalid key prefix in reftable"));
+    }
+    key.truncate(prefix as usize);
+    key.extend_from_slice(slice(buffer, cursor, suffix)?);
+    Ok((r#type, cursor + suffix))
+}
+
+fn read_id(buffer: &[u8], cursor: &mut usize) -> anyhow::Result<object::Id> {
+    let id = object::Id::read_bytes(&mut slice(buffer, *cursor, 20)?)?;
+    *cursor += 20;
+    Ok(id)
+}
+
+fn read_string(buffer: &[u8], cursor: &mut usize) -> anyhow::Result<String> {
+    let (len, next) = read_varint(buffer, *cursor)?;
+    let string = String::from_utf8(slice(buffer, next, len as usize)?.to_vec())?;
+    *cursor = next + len as usize;
+    Ok(string)
+}
+
+fn read_ref(buffer: &[u8], cursor: usize, r#type: u8) -> anyhow::Result<(Value, usize)> {
+    let (_update_index_delta, mut cursor) = read_varint(buffer, cursor)?;
+    let value = match r#type {
+        0 => Value::Deletion,
+        1 => Value::Id(read_id(buffer, &mut cursor)?),
+        2 => {
+            let id = read_id(buffer, &mut cursor)?;
+            Value::Peeled(id, read_id(buffer, &mut cursor)?)
+        }
+        3 => Value::Symbolic(read_string(buffer, &mut cursor)?),
+        unknown => return Err(anyhow!("Unknown reftable value type {}", unknown)),
+    };
+    Ok((value, cursor))
+}
+
+fn read_log_entry(
+    buffer: &[u8],
+    mut cursor: usize,
+    r#type: u8,
+) -> anyhow::Result<(Option<LogEntry>, usize)> {
+    match r#type {
+        0 => return Ok((None, cursor)),
+        1 => (),
+        unknown => return Err(anyhow!("Unknown reftable log type {}", unknown)),
+    }
+
+    let old = read_id(buffer, &mut cursor)?;
+    let new = read_id(buffer, &mut cursor)?;
+    let name = read_string(buffer, &mut cursor)?;
+    let email = read_string(buffer, &mut cursor)?;
+    let (seconds, next) = read_varint(buffer, cursor)?;
+    let offset = BigEndian::read_i16(slice(buffer, next, 2)?);
+    cursor = next + 2;
+    let message = read_string(buffer, &mut cursor)?;
+
+    let time = chrono::FixedOffset::east_opt(i32::from(offset) * 60)
+        .and_then(|offset| offset.timestamp_opt(seconds as i64, 0).single())
+        .ok_or_else(|| anyhow!("Invalid reflog time in reftable"))?;
+
+    let entry = LogEntry {
+        old: Some(old).filter(|old| old.as_bytes() != &LogEntry::NULL),
+        new,
+        committer: object::Person::new(name, email, time),
+        message: message.strip_suffix('\n').unwrap_or(&message).to_owned(),
+    };
+    Ok((Some(entry), cursor))
+}
+
+fn write_table(refs: &[(String, Value)], logs: &[LogRecord], min: u64, max: u64) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    write_header(&mut buffer, min, max);
+
+    let refs = refs
+        .iter()
+        .map(|(name, value)| {
+            let mut record = Vec::new();
+            let r#type = write_ref(&mut record, value);
+            (name.as_bytes().to_vec(), r#type, record)
+        })
+        .collect::<Vec<_>>();
+    let blocks = write_blocks(&mut buffer, REF, &refs);
+    let ref_index = write_index(&mut buffer, blocks);
+
+    let logs = logs
+        .iter()
+        .map(|(key, entry)| {
+            let mut record = Vec::new();
+            let r#type = write_log_entry(&mut record, entry.as_ref());
+            (key.clone(), r#type, record)
+        })
+        .collect::<Vec<_>>();
+    // A log block first in the file has position zero, which readers
+    // recognize by its type instead.
+    let log = match logs.is_empty() || buffer.len() == HEADER_LEN {
+        true => 0,
+        false => buffer.len(),
+    };
+    let blocks = write_blocks(&mut buffer, LOG, &logs);
+    let log_index = write_index(&mut buffer, blocks);
+
+    let footer = buffer.len();
+    write_header(&mut buffer, min, max);
+    for position in &[ref_index, 0, 0, log, log_index] {
+        buffer.extend_from_slice(&(*position as u64).to_be_bytes());
+    }
+
+    let mut crc = flate2::Crc::new();
+    crc.update(&buffer[footer..]);
+    buffer.extend_from_slice(&crc.sum().to_be_bytes());
+    buffer
+}
+
+/// Write `records` of `(key, type, value)` as blocks of `kind`, returning
+/// the last key and position of each block.
+fn write_blocks(
+    buffer: &mut Vec<u8>,
+    kind: u8,
+    records: &[(Vec<u8>, u8, Vec<u8>)],
+) -> Vec<(Vec<u8>, usize)> {
+    let mut blocks = Vec::new();
+    let mut iter = records.iter().peekable();
+
+    while iter.peek().is_some() {
+        // The first block contains the file header.
+        let start = match buffer.len() == HEADER_LEN {
+            true => 0,
+            false => buffer.len(),
+        };
+        let mut block = buffer.split_off(start);
+        let skip = block.len();
+        block.push(kind);
+        block.extend_from_slice(&[0; 3]);
+
+        let mut restarts = Vec::new();
+        let mut previous: &[u8] = &[];
+        let mut written = 0;
+
+        while let Some((key, r#type, value)) = iter.peek() {
+            let restart = written % RESTART_INTERVAL == 0;
+            let prefix = match restart {
+                true => 0,
+                false => common_prefix(previous, key),
+            };
+            let mut record = Vec::new();
+            write_varint(&mut record, prefix as u64);
+            write_varint(
+                &mut record,
+                ((key.len() - prefix) as u64) << 3 | u64::from(*r#type),
+            );
+            record.extend_from_slice(&key[prefix..]);
+            record.extend_from_slice(value);
+
+            // Leave room for this block's restart table.
+            let restart_len = (restarts.len() + restart as usize) * 3 + 2;
+            if !restarts.is_empty() && block.len() + record.len() + restart_len > BLOCK_SIZE {
+                break;
+            }
+
+            if restart {
+                restarts.push(block.len());
+            }
+
+            block.extend_from_slice(&record);
+            previous = key;
+            written += 1;
+            iter.next();
+        }
+
+        for restart in &restarts {
+            block.extend_from_slice(&(*restart as u32).to_be_bytes()[1..]);
+        }
+        block.extend_from_slice(&(restarts.len() as u16).to_be_bytes());
+
+        let len = block.len();
+        BigEndian::write_u24(&mut block[skip + 1..skip + 4], len as u32);
+
+        match kind {
+            LOG => {
+                buffer.extend_from_slice(&block[..skip + 4]);
+                let mut encoder = flate2::write::ZlibEncoder::new(
+                    mem::take(buffer),
+                    flate2::Compression::default(),
+                );
+                encoder
+                    .write_all(&block[skip + 4..])
+                    .expect("[UNREACHABLE]: writing to `Vec` cannot fail");
+                *buffer = encoder
+                    .finish()
+                    .expect("[UNREACHABLE]: writing to `Vec` cannot fail");
+            }
+            _ => buffer.extend_from_slice(&block),
+        }
+
+        blocks.push((previous.to_vec(), start));
+    }
+
+    blocks
+}
+
+/// Write index blocks over `blocks` if there is more than one, adding
+/// levels until a single block covers the level below, and return the
+/// position of the top level (or zero for no index).
+fn write_index(buffer: &mut Vec<u8>, mut blocks: Vec<(Vec<u8>, usize)>) -> usize {
+    let mut position = 0;
+    while blocks.len() > 1 {
+        let records = blocks
+            .into_iter()
+            .map(|(key, position)| {
+                let mut value = Vec::new();
+                write_varint(&mut value, position as u64);
+                (key, 0, value)
+            })
+            .collect::<Vec<_>>();
+        blocks = write_blocks(buffer, INDEX, &records);
+        position = blocks[0].1;
+    }
+    position
+}
+
+fn write_header(buffer: &mut Vec<u8>, min: u64, max: u64) {
+    buffer.extend_from_slice(MAGIC);
+    buffer.push(1);
+    buffer.extend_from_slice(&(BLOCK_SIZE as u32).to_be_bytes()[1..]);
+    buffer.extend_from_slice(&min.to_be_bytes());
+    buffer.extend_from_slice(&max.to_be_bytes());
+}
+
+/// Write the value of a ref record, returning its type.
+fn write_ref(buffer: &mut Vec<u8>, value: &Value) -> u8 {
+    write_varint(buffer, 0);
+    match value {
+        Value::Deletion => 0,
+        Value::Id(id) => {
+            buffer.extend_from_slice(id.as_bytes());
+            1
+        }
+        Value::Peeled(id, peeled) => {
+            buffer.extend_from_slice(id.as_bytes());
+            buffer.extend_from_slice(peeled.as_bytes());
+            2
+        }
+        Value::Symbolic(target) => {
+            write_varint(buffer, target.len() as u64);
+            buffer.extend_from_slice(target.as_bytes());
+            3
+        }
+    }
+}
+
+/// Write the value of a log record, returning its type.
+fn write_log_entry(buffer: &mut Vec<u8>, entry: Option<&LogEntry>) -> u8 {
+    let entry = match entry {
+        None => return 0,
+        Some(entry) => entry,
+    };
+
+    match &entry.old {
+        Some(old) => buffer.extend_from_slice(old.as_bytes()),
+        None => buffer.extend_from_slice(&LogEntry::NULL),
+    }
+    buffer.extend_from_slice(entry.new.as_bytes());
+
+    let committer = &entry.committer;
+    for string in &[committer.name(), committer.email()] {
+        write_varint(buffer, string.len() as u64);
+        buffer.extend_from_slice(string.as_bytes());
+    }
+    write_varint(buffer, committer.time().timestamp().max(0) as u64);
+    let offset = committer.time().offset().local_minus_utc() / 60;
+    buffer.extend_from_slice(&(offset as i16).to_be_bytes());
+
+    // Like `git`, store messages as a single line ending in a newline.
+    let message = format!("{}\n", entry.message.trim().replace('\n', " "));
+    write_varint(buffer, message.len() as u64);
+    buffer.extend_from_slice(message.as_bytes());
+    1
+}
+
+fn common_prefix(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+/// Decode `git`'s offset varint, in which each continuation adds one before
+/// shifting so that every value has a unique encoding.
+fn read_varint(buffer: &[u8], mut cursor: usize) -> anyhow::Result<(u64, usize)> {
+    let byte = |cursor: usize| {
+        buffer
+            .get(cursor)
+            .copied()
+            .ok_or_else(|| anyhow!("Truncated varint in reftable"))
+    };
+
+    let mut next = byte(cursor)?;
+    let mut value = u64::from(next & 0x7F);
+    while next & 0x80 > 0 {
+        cursor += 1;
+        next = byte(cursor)?;
+        value = value
+            .checked_add(1)
+            .filter(|value| value.leading_zeros() >= 7)
+            .map(|value| (value << 7) | u64::from(next & 0x7F))
+            .ok_or_else(|| anyhow!("Overlong varint in reftable"))?;
+    }
+    Ok((value, cursor + 1))
+}
+
+fn write_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    let mut bytes = [0u8; 10];
+    let mut index = bytes.len() - 1;
+    bytes[index] = (value & 0x7F) as u8;
+    while {
+        value >>= 7;
+        value > 0
+    } {
+        value -= 1;
+        index -= 1;
+        bytes[index] = 0x80 | (value & 0x7F) as u8;
+    }
+    buffer.extend_from_slice(&bytes[index..]);
+}
+
+#[test]
+fn varint() -> anyhow::Result<()> {
+    for value in &[0, 1, 127, 128, 255, 16511, 16512, u64::from(u32::MAX)] {
+        let mut buffer = Vec::new();
+        write_varint(&mut buffer, *value);
+        assert_eq!(read_varint(&buffer, 0)?, (*value, buffer.len()));
+    }
+    assert!(read_varint(&[0xFF; 12], 0).is_err());
+    Ok(())
+}
+
+#[cfg(test)]
+fn table(buffer: &[u8]) -> anyhow::Result<Table> {
+    let mut map = memmap2::MmapMut::map_anon(buffer.len())?;
+    map.copy_from_slice(buffer);
+    Table::new(String::from("test.ref"), map.make_read_only()?)
+}
+
+#[test]
+fn round_trip() -> anyhow::Result<()> {
+    let id = |byte: u8| object::Id::read_bytes(&mut &[byte; 20][..]);
+    let mut records = (0..5000)
+        .map(|index| format!("refs/heads/branch-{:04}", index))
+        .map(|name| id(1).map(|id| (name, Value::Id(id))))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    records.push((String::from("refs/tags/v1"), Value::Peeled(id(2)?, id(3)?)));
+    records.push((
+        String::from("refs/zzz"),
+        Value::Symbolic(String::from("refs/heads/branch-0000")),
+    ));
+
+    let person = object::Person::new(
+        String::from("C O Mitter"),
+        String::from("committer@example.com"),
+        object::Person::parse_time("@1600000000 +0130")?,
+    );
+    let logs = (1..=300)
+        .rev()
+        .map(|index| {
+            let entry = LogEntry {
+                old: None,
+                new: id(index as u8)?,
+                committer: person.clone(),
+                message: format!("commit: {}", index),
+            };
+            Ok((log_key("refs/heads/main", index), Some(entry)))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let buffer = write_table(&records, &logs, 1, 300);
+    assert_eq!(&buffer[..4], MAGIC);
+    assert!(buffer.len() > BLOCK_SIZE);
+
+    let table = table(&buffer)?;
+    assert_eq!(table.header.max_update_index, 300);
+    assert!(table.ref_index.is_some());
+    assert!(table.log_index.is_some());
+
+    let mut read = Vec::new();
+    let mut iter = table.seek(REF, b"")?;
+    while let Some((key, record)) = iter.next()? {
+        match record {
+            Record::Ref(value) => read.push((String::from_utf8(key)?, value)),
+            _ => panic!("unexpected record"),
+        }
+    }
+    assert_eq!(read, records);
+
+    let stack = Stack {
+        tables: vec![table],
+    };
+    for (name, value) in records.iter().step_by(97) {
+        assert_eq!(stack.lookup(name)?.as_ref(), Some(value));
+    }
+    assert_eq!(stack.lookup("refs/heads/branch")?, None);
+    assert_eq!(stack.lookup("refs/zzzz")?, None);
+    assert_eq!(stack.scan("refs/tags/")?.len(), 1);
+
+    let log = stack.read_log("refs/heads/main")?;
+    assert_eq!(log.len(), 300);
+    assert_eq!(log[0].new, id(1)?);
+    assert_eq!(log[0].message, "commit: 1");
+    assert_eq!(log[299].committer.time(), person.time());
+    assert!(stack.read_log("refs/heads/other")?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn corrupt() -> anyhow::Result<()> {
+    let id = object::Id::read_bytes(&mut &[1; 20][..])?;
+    let records = (0..100)
+        .map(|index| (format!("refs/heads/{}", index), Value::Id(id)))
+        .collect::<Vec<_>>();
+    let buffer = write_table(&records, &[], 1, 1);
+
+    assert!(table(&buffer[..buffer.len() - 1]).is_err());
+    assert!(table(&buffer[..40]).is_err());
+
+    // Damage every byte of the records in turn, which may fail to read
+    // but must not panic.
+    for offset in HEADER_LEN..buffer.len() - 68 {
+        let mut damaged = buffer.clone();
+        damaged[offset] ^= 0xFF;
+        let table = table(&damaged)?;
+        let _ = table.seek(REF, b"refs/heads/50").and_then(|mut records| {
+            while records.next()?.is_some() {}
+            Ok(())
+        });
+    }
+    Ok(())
+}
+
+#[test]
+fn stack() -> anyhow::Result<()> {
+    let id = |byte: u8| object::Id::read_bytes(&mut &[byte; 20][..]);
+    let root = crate::util::TempDir::new();
+    let reftable = Reftable::new(root.to_path_buf());
+
+    let person = object::Person::new(
+        String::from("C O Mitter"),
+        String::from("committer@example.com"),
+        object::Person::parse_time("@1600000000 +0000")?,
+    );
+    for index in 0..100u8 {
+        let name = format!("refs/heads/branch-{}", index % 10);
+        reftable.write(&[(name.clone(), Value::Id(id(index)?))], |_| Ok(()))?;
+        let entry = LogEntry {
+            old: None,
+            new: id(index)?,
+            committer: person.clone(),
+            message: String::from("update"),
+        };
+        reftable.append_log(&name, &entry)?;
+    }
+
+    let stack = reftable.stack()?;
+    let tables = stack.tables.len();
+    let scanned = stack.scan("refs/heads/")?;
+    let lookup = stack.lookup("refs/heads/branch-3")?;
+    let log = stack.read_log("refs/heads/branch-3")?;
+    drop(stack);
+
+    let conflict = reftable.write(&[(String::from("refs/heads/x"), Value::Deletion)], |_| {
+        Err(anyhow!("conflict"))
+    });
+    reftable.write(
+        &[(String::from("refs/heads/branch-3"), Value::Deletion)],
+        |_| Ok(()),
+    )?;
+    reftable.write_log("refs/heads/branch-4", &log[..2])?;
+
+    let stack = reftable.stack()?;
+    let deleted = stack.lookup("refs/heads/branch-3")?;
+    let deleted_log = stack.read_log("refs/heads/branch-3")?;
+    let rewritten = stack.read_log("refs/heads/branch-4")?;
+    let files = fs::read_dir(&root)?.count();
+    drop(stack);
+
+    // Two hundred writes compact down to a logarithmic number of tables.
+    assert!(tables <= 8, "{} tables", tables);
+    assert_eq!(files, tables + 1);
+    assert_eq!(scanned.len(), 10);
+    assert_eq!(lookup, Some(Value::Id(id(93)?)));
+    assert_eq!(log.len(), 10);
+    assert_eq!(log[9].new, id(93)?);
+
+    assert!(conflict.is_err());
+    assert_eq!(deleted, None);
+    assert!(deleted_log.is_empty());
+    assert_eq!(rewritten.len(), 2);
+    assert_eq!(rewritten[1].new, id(13)?);
+    Ok(())
+}
@@ -0,0 +1,289 @@
+//! Refspecs, which map reference names in one repository to names in
+//! another, like `+refs/heads/*:refs/remotes/origin/*`. Shared by `fetch`,
+//! which maps remote references to local ones, `push`, which maps the other
+//! way, and `clone`, which sets up the default mapping.
+//!
+//! A `*` in the source matches any part of a name, including `/`, and is
+//! replaced by the same part in the destination. A leading `^` makes a
+//! negative refspec, which excludes the names it matches from every other
+//! refspec in the same [`Refspecs`]. See `git help fetch` for details.
+
+use std::fmt;
+use std::str;
+
+use anyhow::anyhow;
+
+/// A single positive refspec.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Refspec {
+    /// Allow updates that aren't fast-forwards.
+    pub force: bool,
+    /// Name or pattern to match, with at most one `*`, or empty to delete
+    /// the destination when pushing.
+    pub source: String,
+    /// Name or pattern to map to, with a `*` if and only if `source` has
+    /// one. Matched references aren't stored anywhere without one.
+    pub destination: Option<String>,
+}
+
+impl Refspec {
+    /// The default fetch refspec for `remote`, which tracks all of its
+    /// branches under `refs/remotes/<remote>`.
+    pub fn tracking(remote: &str) -> Self {
+        Refspec {
+            force: true,
+            source: format!("{}*", crate::References::HEADS),
+            destination: Some(format!("{}{}/*", crate::References::REMOTES, remote)),
+        }
+    }
+
+    /// The refspec of mirrors, which maps every reference to the same name.
+    pub fn mirror() -> Self {
+        Refspec {
+            force: true,
+            source: String::from("refs/*"),
+            destination: Some(String::from("refs/*")),
+        }
+    }
+
+    /// Whether this refspec deletes its destination when pushing.
+    pub fn deletes(&self) -> bool {
+        self.source.is_empty()
+    }
+
+    /// Whether reference `name` matches the source of this refspec.
+    pub fn matches(&self, name: &str) -> bool {
+        glob(self.pattern(), name).is_some()
+    }
+
+    /// Destination for reference `name`, if this refspec matches it and
+    /// has a destination.
+    pub fn map(&self, name: &str) -> Option<String> {
+        let matched = glob(self.pattern(), name)?;
+        let destination = self.destination.as_ref()?;
+        Some(destination.replacen('*', matched, 1))
+    }
+
+    /// Source that this refspec maps to reference `name`, if it matches
+    /// the destination, e.g. to find which remote reference a
+    /// remote-tracking branch came from.
+    pub fn unmap(&self, name: &str) -> Option<String> {
+        let matched = glob(self.destination.as_ref()?, name)?;
+        Some(self.pattern().replacen('*', matched, 1))
+    }
+
+    /// Source to match remote references against when fetching.
+    fn pattern(&self) -> &str {
+        match self.source.is_empty() {
+            true => "HEAD",
+            false => &self.source,
+        }
+    }
+}
+
+impl fmt::Display for Refspec {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        if self.force {
+            write!(fmt, "+")?;
+        }
+        write!(fmt, "{}", self.source)?;
+        if let Some(destination) = &self.destination {
+            write!(fmt, ":{}", destination)?;
+        }
+        Ok(())
+    }
+}
+
+impl str::FromStr for Refspec {
+    type Err = anyhow::Error;
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let invalid = || anyhow!("Invalid refspec '{}'", string);
+
+        let (force, refspec) = match string.strip_prefix('+') {
+            Some(refspec) => (true, refspec),
+            None => (false, string),
+        };
+        let (source, destination) = match refspec.split_once(':') {
+            Some((source, "")) => (source, None),
+            Some((source, destination)) => (source, Some(destination)),
+            None => (refspec, None),
+        };
+
+        if (source.is_empty() && destination.is_none()) || source.starts_with('^') {
+            return Err(invalid());
+        }
+
+        let patterns = (
+            source.matches('*').count(),
+            destination.map(|destination| destination.matches('*').count()),
+        );
+        match patterns {
+            (0, None) | (1, None) | (0, Some(0)) | (1, Some(1)) => (),
+            _ => return Err(invalid()),
+        }
+
+        Ok(Refspec {
+            force,
+            source: source.to_owned(),
+            destination: destination.map(String::from),
+        })
+    }
+}
+
+/// A list of refspecs, where negative refspecs exclude names from all of
+/// the positive ones.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Refspecs {
+    positive: Vec<Refspec>,
+    /// Names or patterns excluded by `^` refspecs.
+    negative: Vec<String>,
+}
+
+impl Refspecs {
+    /// Parse each of `refspecs`, e.g. the values of `remote.<name>.fetch`.
+    pub fn parse<'a, I>(refspecs: I) -> anyhow::Result<Self>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let mut parsed = Refspecs::default();
+        for refspec in refspecs {
+            match refspec.strip_prefix('^') {
+                Some(pattern) if pattern.is_empty() || pattern.contains(':') => {
+                    return Err(anyhow!("Invalid negative refspec '{}'", refspec))
+                }
+                Some(pattern) if pattern.matches('*').count() > 1 => {
+                    return Err(anyhow!("Invalid negative refspec '{}'", refspec))
+                }
+                Some(pattern) => parsed.negative.push(pattern.to_owned()),
+                None => parsed.positive.push(refspec.parse()?),
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// Whether reference `name` is excluded by a negative refspec.
+    pub fn excludes(&self, name: &str) -> bool {
+        self.negative
+            .iter()
+            .any(|pattern| glob(pattern, name).is_some())
+    }
+
+    /// Every positive refspec that maps reference `name`, along with its
+    /// destination, unless a negative refspec excludes it.
+    pub fn map<'a>(&'a self, name: &str) -> Vec<(&'a Refspec, String)> {
+        if self.excludes(name) {
+            return Vec::new();
+        }
+        self.positive
+            .iter()
+            .filter_map(|refspec| refspec.map(name).map(|destination| (refspec, destination)))
+            .collect()
+    }
+
+    /// Whether reference `name` matches a positive refspec without a
+    /// destination, so is fetched without being stored, unless a negative
+    /// refspec excludes it.
+    pub fn fetches(&self, name: &str) -> bool {
+        !self.excludes(name)
+            && self
+                .positive
+                .iter()
+                .any(|refspec| refspec.destination.is_none() && refspec.matches(name))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Refspec> {
+        self.positive.iter()
+    }
+}
+
+/// Match `name` against `pattern`, returning the part matched by its `*`,
+/// or the empty string if it has none.
+fn glob<'a>(pattern: &str, name: &'a str) -> Option<&'a str> {
+    match pattern.split_once('*') {
+        None if pattern == name => Some(""),
+        None => None,
+        Some((prefix, suffix)) => name.strip_prefix(prefix)?.strip_suffix(suffix),
+    }
+}
+
+#[test]
+fn refspec() -> anyhow::Result<()> {
+    let refspec = "+refs/heads/*:refs/remotes/origin/*".parse::<Refspec>()?;
+    assert!(refspec.force);
+    assert_eq!(refspec, Refspec::tracking("origin"));
+    assert_eq!(refspec.to_string(), "+refs/heads/*:refs/remotes/origin/*");
+    assert_eq!(
+        refspec.map("refs/heads/feature/a").as_deref(),
+        Some("refs/remotes/origin/feature/a"),
+    );
+    assert_eq!(refspec.map("refs/tags/v1"), None);
+    assert_eq!(
+        refspec.unmap("refs/remotes/origin/feature/a").as_deref(),
+        Some("refs/heads/feature/a"),
+    );
+    assert_eq!(refspec.unmap("refs/remotes/upstream/main"), None);
+
+    let mirror = Refspec::mirror();
+    assert_eq!(mirror.to_string(), "+refs/*:refs/*");
+    assert_eq!(mirror.map("refs/tags/v1").as_deref(), Some("refs/tags/v1"));
+
+    let exact = "refs/heads/main:refs/heads/upstream".parse::<Refspec>()?;
+    assert!(!exact.force);
+    assert_eq!(
+        exact.map("refs/heads/main").as_deref(),
+        Some("refs/heads/upstream")
+    );
+    assert_eq!(exact.map("refs/heads/mainline"), None);
+
+    let source = "refs/heads/main".parse::<Refspec>()?;
+    assert!(source.matches("refs/heads/main"));
+    assert_eq!(source.map("refs/heads/main"), None);
+
+    assert!("refs/heads/*:refs/heads/main".parse::<Refspec>().is_err());
+    assert!("refs/heads/main:refs/heads/*".parse::<Refspec>().is_err());
+    assert!(":refs/heads/*".parse::<Refspec>().is_err());
+    assert!(":".parse::<Refspec>().is_err());
+    assert!("".parse::<Refspec>().is_err());
+
+    let delete = ":refs/heads/main".parse::<Refspec>()?;
+    assert!(delete.deletes());
+    assert_eq!(delete.to_string(), ":refs/heads/main");
+    assert_eq!(delete.map("HEAD").as_deref(), Some("refs/heads/main"));
+    assert_eq!(delete.unmap("refs/heads/main").as_deref(), Some("HEAD"));
+
+    let unstored = "refs/heads/main:".parse::<Refspec>()?;
+    assert_eq!(unstored, source);
+    assert!(!unstored.deletes());
+    let refspecs = Refspecs::parse(vec!["refs/heads/main:", "refs/tags/*:refs/tags/*"])?;
+    assert!(refspecs.fetches("refs/heads/main"));
+    assert!(!refspecs.fetches("refs/tags/v1"));
+    Ok(())
+}
+
+#[test]
+fn negative() -> anyhow::Result<()> {
+    let refspecs = Refspecs::parse(vec![
+        "+refs/heads/*:refs/remotes/origin/*",
+        "^refs/heads/wip/*",
+        "^refs/heads/scratch",
+    ])?;
+
+    let mapped = |name| {
+        refspecs
+            .map(name)
+            .into_iter()
+            .map(|(_, destination)| destination)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(mapped("refs/heads/main"), vec!["refs/remotes/origin/main"]);
+    assert!(mapped("refs/heads/wip/a").is_empty());
+    assert!(mapped("refs/heads/scratch").is_empty());
+    assert_eq!(
+        mapped("refs/heads/scratchpad"),
+        vec!["refs/remotes/origin/scratchpad"]
+    );
+
+    assert!(Refspecs::parse(vec!["^refs/heads/a:refs/heads/b"]).is_err());
+    assert!(Refspecs::parse(vec!["^"]).is_err());
+    Ok(())
+}
@@ -1,44 +1,488 @@
+use std::cell;
+use std::ffi;
 use std::fs;
+use std::io;
+use std::num;
 use std::path;
+use std::rc::Rc;
+use std::thread;
+
+use anyhow::anyhow;
+
+use crate::attributes;
+use crate::config;
+use crate::database;
+use crate::fsck;
+use crate::platform;
+use crate::prefix;
+use crate::references;
+use crate::state;
 
 #[derive(Clone, Debug)]
 pub struct Repository {
     root: path::PathBuf,
+    /// Whether `root` is itself the `.git` directory, with no workspace.
+    bare: bool,
+    storage: Storage,
+}
+
+/// Where the repository keeps its objects, references, and index.
+#[derive(Clone, Debug)]
+enum Storage {
+    /// Files under `<root>/.git`, or directly under `<root>` if bare.
+    Disk,
+    /// Volatile state shared between all handles cloned from one repository.
+    Memory {
+        objects: database::Memory,
+        references: references::Memory,
+        index: Rc<cell::RefCell<Vec<u8>>>,
+    },
 }
 
 impl Repository {
+    pub const DEFAULT_BRANCH: &'static str = "master";
+
     pub fn new(root: path::PathBuf) -> Self {
-        Repository { root }
+        Repository {
+            root,
+            bare: false,
+            storage: Storage::Disk,
+        }
+    }
+
+    /// Create a handle to the bare repository `root`, which keeps its
+    /// objects and references directly under `root` and has no workspace,
+    /// e.g. for mirror clones.
+    pub fn bare(root: path::PathBuf) -> Self {
+        Repository {
+            root,
+            bare: true,
+            storage: Storage::Disk,
+        }
+    }
+
+    /// Open the repository at `root`, which is bare if it has no `.git`
+    /// directory but looks like one itself.
+    pub fn open(root: path::PathBuf) -> Self {
+        match !root.join(".git").exists() && Self::is_git_directory(&root) {
+            true => Self::bare(root),
+            false => Self::new(root),
+        }
+    }
+
+    fn is_git_directory(directory: &path::Path) -> bool {
+        directory.join("HEAD").is_file()
+            && directory.join("objects").is_dir()
+            && directory.join("refs").is_dir()
+    }
+
+    pub fn is_bare(&self) -> bool {
+        self.bare
+    }
+
+    /// Directory holding the repository's files, like `<root>/.git`.
+    pub(crate) fn git(&self) -> path::PathBuf {
+        match self.bare {
+            true => self.root.clone(),
+            false => self.root.join(".git"),
+        }
+    }
+
+    /// Find the repository containing the absolute path `cwd` by searching
+    /// upward for a `.git` directory, like `git`, along with the location of
+    /// `cwd` within its workspace.
+    pub fn discover(cwd: &path::Path) -> anyhow::Result<(Self, prefix::Prefix)> {
+        let root = cwd
+            .ancestors()
+            .find(|directory| directory.join(".git").exists())
+            .ok_or_else(|| {
+                anyhow!("Not a git repository (or any of the parent directories): .git")
+            })?;
+        let prefix = prefix::Prefix::new(root, cwd)?;
+        Ok((Self::new(root.to_path_buf()), prefix))
+    }
+
+    /// Create an empty repository whose objects, references, and index live
+    /// only in memory, e.g. for servers or tests that script `git` semantics.
+    ///
+    /// Like a bare repository, it has no workspace of its own: `workspace`
+    /// is rooted at `root`, which is never written by the other operations.
+    pub fn memory(root: path::PathBuf) -> Self {
+        Repository {
+            root,
+            bare: false,
+            storage: Storage::Memory {
+                objects: database::Memory::new(),
+                references: references::Memory::new(),
+                index: Rc::default(),
+            },
+        }
     }
 
     pub fn root(&self) -> &path::Path {
         &self.root
     }
 
-    pub fn database(&self) -> crate::Database {
-        crate::Database::new(self.root.join(".git/objects"))
+    /// Open the object database, flushing new objects to disk as configured
+    /// by `core.fsync` and `core.fsyncMethod`.
+    pub fn database(&self) -> anyhow::Result<crate::Database> {
+        match &self.storage {
+            Storage::Disk => {
+                let config = self.config()?;
+                let fsync =
+                    database::Fsync::new(config.get("core.fsync"), config.get("core.fsyncMethod"))?;
+                Ok(crate::Database::open_with_fsync(
+                    self.git().join("objects"),
+                    fsync,
+                ))
+            }
+            Storage::Memory { objects, .. } => Ok(crate::Database::new(Box::new(objects.clone()))),
+        }
     }
 
+    /// Read the system, global, and repository configuration, where
+    /// `.git/config` takes precedence.
+    pub fn config(&self) -> anyhow::Result<config::Config> {
+        match &self.storage {
+            Storage::Disk => config::Config::layered(&self.git()),
+            Storage::Memory { .. } => Ok(config::Config::default()),
+        }
+    }
+
+    /// Lock the index, initializing new indices with the format version
+    /// from `index.version`.
+    ///
+    /// Sparse directories are expanded, so commands see every tracked file.
+    /// Those left untouched are collapsed again on commit.
     pub fn index(&self) -> anyhow::Result<crate::Index> {
-        crate::Index::lock(self.root.join(".git/index"))
+        let mut index = self.sparse_index()?;
+        if index.is_sparse() {
+            index.expand(&self.database()?)?;
+        }
+        Ok(index)
+    }
+
+    /// Lock the index like [`Repository::index`], but leave sparse
+    /// directories collapsed, for commands that can handle them or expand
+    /// them on demand with [`crate::Index::expand_to`].
+    pub fn sparse_index(&self) -> anyhow::Result<crate::Index> {
+        let (mut index, new) = match &self.storage {
+            Storage::Disk => {
+                if self.bare {
+                    return Err(anyhow!("This operation must be run in a work tree"));
+                }
+                let path = self.root.join(".git/index");
+                let new = !path.exists();
+                (crate::Index::lock(path)?, new)
+            }
+            Storage::Memory { index, .. } => {
+                let new = index.borrow().is_empty();
+                (crate::Index::memory(Rc::clone(index))?, new)
+            }
+        };
+
+        if new {
+            if let Some(version) = self.config()?.parse::<u32>("index.version")? {
+                index.set_version(version)?;
+            }
+        }
+
+        Ok(index)
     }
 
     pub fn references(&self) -> crate::References {
-        crate::References::new(self.root.join(".git/refs"), self.root.join(".git/HEAD"))
+        match &self.storage {
+            Storage::Disk => crate::References::open(self.git()),
+            Storage::Memory { references, .. } => {
+                crate::References::new(Box::new(references.clone()))
+            }
+        }
+    }
+
+    pub fn workspace(&self) -> anyhow::Result<crate::Workspace> {
+        let config = self.config()?;
+        let mut workspace = crate::Workspace::new(self.root.clone());
+        if let Some(symlinks) = config.get_bool("core.symlinks")? {
+            workspace.set_symlinks(symlinks);
+        }
+        if let Some(filemode) = config.get_bool("core.fileMode")? {
+            workspace.set_filemode(filemode);
+        }
+        workspace.set_attributes(self.attributes()?);
+
+        // Like `git`, values below one mean one worker per logical core.
+        match config.parse::<i64>("checkout.workers")? {
+            None => (),
+            Some(workers) if workers >= 1 => workspace.set_workers(workers as usize),
+            Some(_) => workspace
+                .set_workers(thread::available_parallelism().map_or(1, num::NonZeroUsize::get)),
+        }
+        Ok(workspace)
     }
 
-    pub fn workspace(&self) -> crate::Workspace {
-        crate::Workspace::new(self.root.clone())
+    /// Read the attributes of workspace files. In-memory repositories have
+    /// none, since they never read their workspace.
+    pub fn attributes(&self) -> anyhow::Result<attributes::Attributes> {
+        match &self.storage {
+            Storage::Disk => attributes::Attributes::open(&self.root, &self.config()?),
+            Storage::Memory { .. } => Ok(attributes::Attributes::default()),
+        }
+    }
+
+    /// Detect state left behind by interrupted operations.
+    /// In-memory repositories never have any.
+    pub fn audit(&self) -> io::Result<Vec<state::Leftover>> {
+        match &self.storage {
+            Storage::Disk => state::audit(&self.git()),
+            Storage::Memory { .. } => Ok(Vec::new()),
+        }
+    }
+
+    /// Check every object and what references, reflogs, and the index
+    /// point at, like `git fsck`. Dangling objects are reported too, but
+    /// aren't damage; see [`fsck::Problem::is_error`].
+    pub fn verify(&self) -> anyhow::Result<Vec<fsck::Problem>> {
+        fsck::verify(self)
+    }
+
+    /// Probe whether the filesystem containing `directory` supports
+    /// symbolic links.
+    fn supports_symlinks(directory: &path::Path) -> bool {
+        let probe = directory.join("symlink-probe");
+        let supported = platform::symlink(ffi::OsStr::new("target"), &probe).is_ok();
+        let _ = fs::remove_file(&probe);
+        supported
+    }
+
+    /// Probe whether the filesystem containing `directory` preserves
+    /// changes to the executable bit.
+    #[cfg(unix)]
+    fn supports_filemode(directory: &path::Path) -> bool {
+        use std::os::unix::fs::PermissionsExt as _;
+
+        let probe = directory.join("filemode-probe");
+        let mode = |probe: &path::Path| {
+            fs::metadata(probe).map(|metadata| metadata.permissions().mode() & 0o111)
+        };
+        // Like `git`, toggle the bit, since some filesystems report every
+        // file as executable.
+        let supported = fs::write(&probe, b"").is_ok()
+            && mode(&probe).is_ok_and(|before| {
+                let toggled = fs::Permissions::from_mode(0o644 | (before ^ 0o100));
+                fs::set_permissions(&probe, toggled).is_ok()
+                    && mode(&probe).is_ok_and(|after| after != before)
+            });
+        let _ = fs::remove_file(&probe);
+        supported
+    }
+
+    /// Without permission bits, the executable bit is never preserved.
+    #[cfg(not(unix))]
+    fn supports_filemode(_: &path::Path) -> bool {
+        false
     }
 
     pub fn init(&mut self) -> anyhow::Result<()> {
-        self.root.push(".git");
-        for directory in &["objects", "refs"] {
-            self.root.push(directory);
-            fs::create_dir_all(&self.root)?;
-            self.root.pop();
+        if let Storage::Disk = self.storage {
+            let git = self.git();
+            for directory in &["objects/info", "objects/pack", "refs/heads", "refs/tags"] {
+                fs::create_dir_all(git.join(directory))?;
+            }
+
+            let config = git.join("config");
+            if !config.exists() {
+                let mut text = format!(
+                    "[core]\n\
+                     \trepositoryformatversion = 0\n\
+                     \tfilemode = {}\n\
+                     \tbare = {}\n",
+                    Self::supports_filemode(&git),
+                    self.bare,
+                );
+                // Like `git`, leave `logallrefupdates` unset in bare repositories.
+                if !self.bare {
+                    text.push_str("\tlogallrefupdates = true\n");
+                }
+                if !Self::supports_symlinks(&git) {
+                    text.push_str("\tsymlinks = false\n");
+                }
+                fs::write(config, text)?;
+            }
         }
-        self.root.pop();
+
+        let references = self.references();
+        if references.store().read("HEAD")?.is_none() {
+            references.set_head(&references::Target::Symbolic(format!(
+                "{}{}",
+                crate::References::HEADS,
+                Self::DEFAULT_BRANCH,
+            )))?;
+        }
+
         Ok(())
     }
 }
+
+#[test]
+fn memory() -> anyhow::Result<()> {
+    use crate::diff::tree;
+    use crate::meta;
+    use crate::object;
+
+    let mut repository = Repository::memory(path::PathBuf::new());
+    repository.init()?;
+
+    let metadata = meta::Metadata {
+        ctime: 0,
+        ctime_nsec: 0,
+        mtime: 0,
+        mtime_nsec: 0,
+        dev: 0,
+        ino: 0,
+        mode: meta::Mode::Regular,
+        uid: 0,
+        gid: 0,
+        size: 0,
+    };
+
+    let commit = |files: &[(&str, &[u8])]| -> anyhow::Result<object::Id> {
+        let database = repository.database()?;
+        let references = repository.references();
+        let mut index = repository.index()?;
+        for (path, data) in files {
+            let id = database.store(&crate::Object::Blob(object::Blob::new(data.to_vec())))?;
+            index.insert(metadata, id, path::PathBuf::from(path));
+        }
+        let tree = index.write_tree(&database)?;
+        index.commit()?;
+
+        let author = object::Person::new(
+            String::from("A U Thor"),
+            String::from("author@example.com"),
+            chrono::Local::now().into(),
+        );
+        let parents = references.read_head()?.into_iter().collect();
+        let commit = object::Commit::new(
+            tree,
+            parents,
+            author.clone(),
+            author,
+            String::from("message\n"),
+        );
+        let id = database.store(&crate::Object::Commit(commit))?;
+        references.write_head(&id)?;
+        Ok(tree)
+    };
+
+    let a = commit(&[("a", b"1"), ("b/c", b"2")])?;
+    let b = commit(&[("a", b"3")])?;
+
+    let head = repository.references().resolve("master")?;
+    let changes = tree::diff(&repository.database()?, Some(&a), Some(&b))?;
+
+    assert!(head.is_some());
+    assert_eq!(repository.index()?.entries().count(), 2);
+    assert_eq!(
+        changes
+            .keys()
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>(),
+        vec!["a"],
+    );
+    Ok(())
+}
+
+#[test]
+fn layout() -> anyhow::Result<()> {
+    let root = crate::util::TempDir::new();
+    let mut repository = Repository::new(root.to_path_buf());
+    repository.init()?;
+
+    let created = ["objects/info", "objects/pack", "refs/heads", "refs/tags"]
+        .iter()
+        .all(|directory| root.join(".git").join(directory).is_dir());
+
+    // Repositories created by older versions lack these directories.
+    fs::remove_dir(root.join(".git/objects/info"))?;
+    fs::remove_dir(root.join(".git/objects/pack"))?;
+    let database = repository.database()?;
+    let blob = crate::Object::Blob(crate::object::Blob::new(b"blob".to_vec()));
+    let id = database.store(&blob)?;
+    let found = database.contains(&id)?;
+    database.pack(&[id])?;
+    let packed = root.join(".git/objects/pack").is_dir();
+
+    assert!(created);
+    assert!(found);
+    assert!(packed);
+    Ok(())
+}
+
+#[test]
+fn bare() -> anyhow::Result<()> {
+    let temp = crate::util::TempDir::new();
+    let root = temp.join("repository.git");
+    Repository::bare(root.clone()).init()?;
+
+    let repository = Repository::open(root.clone());
+    let config = repository.config()?.get_bool("core.bare")?;
+    let head = repository.references().current_branch()?;
+    let index = repository.index().is_err();
+    let nested = root.join(".git").exists();
+
+    assert!(repository.is_bare());
+    assert_eq!(config, Some(true));
+    assert_eq!(head.as_deref(), Some(Repository::DEFAULT_BRANCH));
+    assert!(index);
+    assert!(!nested);
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinks_disabled() -> anyhow::Result<()> {
+    use crate::diff::tree;
+    use crate::meta;
+    use crate::object;
+
+    let root = crate::util::TempDir::new();
+    let mut repository = Repository::new(root.to_path_buf());
+    repository.init()?;
+    let mut document = config::Document::open(root.join(".git/config"))?;
+    document.set("core.symlinks", "false")?;
+    document.commit()?;
+
+    let database = repository.database()?;
+    let workspace = repository.workspace()?;
+    let mut index = repository.index()?;
+    let check_stat = meta::CheckStat::default();
+
+    let link = path::Path::new("link");
+    let id = database.store(&crate::Object::Blob(object::Blob::new(b"target".to_vec())))?;
+    let mut files = std::collections::BTreeMap::new();
+    files.insert(
+        crate::util::PathBuf(link.to_path_buf()),
+        tree::Entry {
+            id,
+            mode: meta::Mode::Symlink,
+        },
+    );
+    let tree = tree::unflatten(&database, &files)?;
+    crate::migration::reset(&database, &mut index, &workspace, check_stat, &tree)?;
+
+    // Checked out as a plain file holding the target, which still matches
+    // the link in the index.
+    let metadata = fs::symlink_metadata(root.join(link))?;
+    let data = fs::read(root.join(link))?;
+    let indexed = *index.get(link).expect("checked out").metadata();
+    let clean = workspace
+        .normalize(workspace.metadata(link)?, indexed.mode)
+        .is_stat_clean(&indexed, check_stat);
+    let unchanged = crate::migration::snapshot(&database, &index, &workspace, check_stat)? == tree;
+
+    assert!(metadata.file_type().is_file());
+    assert_eq!(data, b"target");
+    assert!(clean);
+    assert!(unchanged);
+    Ok(())
+}
@@ -0,0 +1,440 @@
+//! Revision expressions such as `HEAD~2`, `main^2`, `@{u}`, `HEAD@{1}`,
+//! and `v1.0:src/lib.rs`, following the syntax of `git rev-parse`.
+
+use std::path;
+use std::str;
+
+use anyhow::anyhow;
+
+use crate::object;
+
+/// A parsed revision expression.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Revision {
+    /// Reference name, or full or abbreviated object id.
+    Name(String),
+    /// Upstream of the named branch, or of the current branch if `None`:
+    /// `main@{u}`.
+    Upstream(Option<String>),
+    /// `n`th most recent reflog entry of the named reference, or of the
+    /// current branch if `None`: `HEAD@{n}`.
+    Reflog(Option<String>, usize),
+    /// `n`th parent, where zero means the commit itself: `rev^n`.
+    Parent(Box<Revision>, usize),
+    /// `n`th generation ancestor, following first parents: `rev~n`.
+    Ancestor(Box<Revision>, usize),
+    /// Object peeled to a commit or tree: `rev^{tree}`.
+    Peel(Box<Revision>, object::Type),
+    /// File or directory within a tree: `rev:path`.
+    Path(Box<Revision>, path::PathBuf),
+    /// File staged in the index: `:path`.
+    Index(path::PathBuf),
+}
+
+/// Minimum length of an abbreviated object id.
+const MIN_ABBREVIATION: usize = 4;
+
+impl Revision {
+    /// Resolve this revision to an object id, or `None` if the reference or
+    /// object it starts from does not exist (e.g. an unborn `HEAD`).
+    pub fn resolve(&self, repository: &crate::Repository) -> anyhow::Result<Option<object::Id>> {
+        let database = repository.database()?;
+        let references = repository.references();
+        self.resolve_with(repository, &database, &references)
+    }
+
+    /// Find the full name of the reference this revision names, following
+    /// `HEAD` to its branch unless detached, or `None` if it does not name
+    /// an existing reference (e.g. an object id or `HEAD~2`).
+    pub fn reference(&self, repository: &crate::Repository) -> anyhow::Result<Option<String>> {
+        let references = repository.references();
+        match self {
+            Revision::Name(name) if name == "HEAD" => match references.store().read("HEAD")? {
+                Some(crate::references::Target::Symbolic(name)) => Ok(Some(name)),
+                _ => Ok(Some(name.clone())),
+            },
+            Revision::Name(name) => references.expand(name),
+            Revision::Upstream(branch) => {
+                upstream(repository, &references, branch.as_deref()).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn resolve_with(
+        &self,
+        repository: &crate::Repository,
+        database: &crate::Database,
+        references: &crate::References,
+    ) -> anyhow::Result<Option<object::Id>> {
+        let base = |revision: &Revision| revision.resolve_with(repository, database, references);
+
+        let id = match self {
+            Revision::Name(name) => return resolve_name(database, references, name),
+            Revision::Upstream(branch) => {
+                let reference = upstream(repository, references, branch.as_deref())?;
+                return references.read(&reference);
+            }
+            Revision::Reflog(name, n) => {
+                let (name, full) = match name {
+                    Some(name) => (
+                        name.clone(),
+                        references
+                            .expand(name)?
+                            .ok_or_else(|| anyhow!("No such reference: {}", name))?,
+                    ),
+                    None => {
+                        let branch = references
+                            .current_branch()?
+                            .ok_or_else(|| anyhow!("HEAD does not point to a branch"))?;
+                        let full = format!("{}{}", crate::References::HEADS, branch);
+                        (branch, full)
+                    }
+                };
+                let log = references.read_log(&full)?;
+                return match log.len().checked_sub(n + 1) {
+                    Some(index) => Ok(Some(log[index].new)),
+                    None => Err(anyhow!("Log for '{}' only has {} entries", name, log.len())),
+                };
+            }
+            Revision::Index(path) => {
+                return match repository.index()?.get(path) {
+                    Some(entry) => Ok(Some(*entry.id())),
+                    None => Err(anyhow!("Path '{}' is not in the index", path.display())),
+                };
+            }
+            Revision::Parent(revision, _)
+            | Revision::Ancestor(revision, _)
+            | Revision::Peel(revision, _)
+            | Revision::Path(revision, _) => match base(revision)? {
+                Some(id) => id,
+                None => return Ok(None),
+            },
+        };
+
+        match self {
+            Revision::Name(_)
+            | Revision::Upstream(_)
+            | Revision::Reflog(_, _)
+            | Revision::Index(_) => unreachable!(),
+            Revision::Parent(_, 0) => id.peel_to_commit(database).map(Some),
+            Revision::Parent(_, n) => {
+                let commit = database.load_commit(&id.peel_to_commit(database)?)?;
+                match commit.parents().get(n - 1) {
+                    Some(parent) => Ok(Some(*parent)),
+                    None => Err(anyhow!("Commit {} has no parent {}", id, n)),
+                }
+            }
+            Revision::Ancestor(_, n) => {
+                let mut id = id.peel_to_commit(database)?;
+                for _ in 0..*n {
+                    id = match database.load_commit(&id)?.parent() {
+                        Some(parent) => *parent,
+                        None => return Err(anyhow!("Commit {} has no parent", id)),
+                    };
+                }
+                Ok(Some(id))
+            }
+            Revision::Peel(_, object::Type::Commit) => id.peel_to_commit(database).map(Some),
+            Revision::Peel(_, object::Type::Tree) => id.peel_to_tree(database).map(Some),
+            Revision::Peel(_, r#type) => Err(anyhow!("Cannot peel {} to {}", id, r#type)),
+            Revision::Path(_, path) => {
+                let mut id = id.peel_to_tree(database)?;
+                for component in path.components() {
+                    // Check the kind first to avoid loading a blob in the
+                    // middle of the path.
+                    id = Some(id)
+                        .filter(|id| matches!(database.kind(id), Ok(object::Type::Tree)))
+                        .and_then(|id| database.load_tree(&id).ok())
+                        .and_then(|tree| {
+                            tree.into_iter()
+                                .find(|node| node.path.as_os_str() == component.as_os_str())
+                        })
+                        .map(|node| node.id)
+                        .ok_or_else(|| anyhow!("Path '{}' does not exist", path.display()))?;
+                }
+                Ok(Some(id))
+            }
+        }
+    }
+}
+
+/// Resolve a reference name, falling back to an abbreviated object id.
+fn resolve_name(
+    database: &crate::Database,
+    references: &crate::References,
+    name: &str,
+) -> anyhow::Result<Option<object::Id>> {
+    if name.len() == 40 {
+        if let Ok(id) = name.parse::<object::Id>() {
+            return Ok(Some(id));
+        }
+    }
+
+    if let Some(id) = references.resolve(name)? {
+        return Ok(Some(id));
+    }
+
+    if name.len() < MIN_ABBREVIATION || !name.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return Ok(None);
+    }
+
+    let prefix = name.to_ascii_lowercase();
+    let mut matches = database
+        .iter()?
+        .filter(|id| id.to_string().starts_with(&prefix));
+
+    match (matches.next(), matches.next()) {
+        (Some(_), Some(_)) => Err(anyhow!("Short object id {} is ambiguous", name)),
+        (id, _) => Ok(id),
+    }
+}
+
+/// Find the remote-tracking reference that `branch` (or the current branch)
+/// merges from, according to `branch.<name>.remote` and `.merge`.
+fn upstream(
+    repository: &crate::Repository,
+    references: &crate::References,
+    branch: Option<&str>,
+) -> anyhow::Result<String> {
+    let branch = match branch {
+        Some(branch) => branch.to_owned(),
+        None => references
+            .current_branch()?
+            .ok_or_else(|| anyhow!("HEAD does not point to a branch"))?,
+    };
+
+    let config = repository.config()?;
+    let remote = config.get(&format!("branch.{}.remote", branch));
+    let merge = config.get(&format!("branch.{}.merge", branch));
+
+    match (remote, merge) {
+        // A remote of `.` means the upstream is another local branch.
+        (Some("."), Some(merge)) => Ok(merge.to_owned()),
+        (Some(remote), Some(merge)) => Ok(format!(
+            "refs/remotes/{}/{}",
+            remote,
+            merge
+                .strip_prefix(crate::References::HEADS)
+                .unwrap_or(merge),
+        )),
+        _ => Err(anyhow!("No upstream configured for branch '{}'", branch)),
+    }
+}
+
+impl str::FromStr for Revision {
+    type Err = anyhow::Error;
+    fn from_str(text: &str) -> anyhow::Result<Self> {
+        let invalid = || anyhow!("Invalid revision: {}", text);
+
+        if let Some(path) = text.strip_prefix(':') {
+            return match path.is_empty() {
+                true => Err(invalid()),
+                false => Ok(Revision::Index(path::PathBuf::from(path))),
+            };
+        }
+
+        if let Some((revision, path)) = text.split_once(':') {
+            return Ok(Revision::Path(
+                Box::new(revision.parse().map_err(|_| invalid())?),
+                path::PathBuf::from(path),
+            ));
+        }
+
+        let split = text.find(['^', '~']).unwrap_or(text.len());
+        let (base, mut suffixes) = text.split_at(split);
+
+        let mut revision = match base {
+            "" => return Err(invalid()),
+            "@" => Revision::Name(String::from("HEAD")),
+            _ => match base
+                .strip_suffix("@{u}")
+                .or_else(|| base.strip_suffix("@{upstream}"))
+            {
+                Some("") => Revision::Upstream(None),
+                Some(branch) => Revision::Upstream(Some(branch.to_owned())),
+                None if base.contains("@{") => {
+                    let (name, n) = base
+                        .strip_suffix('}')
+                        .and_then(|base| base.rsplit_once("@{"))
+                        .filter(|(_, n)| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+                        .ok_or_else(invalid)?;
+                    Revision::Reflog(
+                        Some(name).filter(|name| !name.is_empty()).map(String::from),
+                        n.parse().map_err(|_| invalid())?,
+                    )
+                }
+                None => Revision::Name(base.to_owned()),
+            },
+        };
+
+        while let Some(operator) = suffixes.chars().next() {
+            suffixes = &suffixes[1..];
+
+            if operator == '^' && suffixes.starts_with('{') {
+                let end = suffixes.find('}').ok_or_else(invalid)?;
+                let r#type = match &suffixes[1..end] {
+                    "commit" => object::Type::Commit,
+                    "tree" => object::Type::Tree,
+                    _ => return Err(invalid()),
+                };
+                revision = Revision::Peel(Box::new(revision), r#type);
+                suffixes = &suffixes[end + 1..];
+                continue;
+            }
+
+            let digits = suffixes
+                .find(|char: char| !char.is_ascii_digit())
+                .unwrap_or(suffixes.len());
+            let n = match digits {
+                0 => 1,
+                _ => suffixes[..digits].parse().map_err(|_| invalid())?,
+            };
+            suffixes = &suffixes[digits..];
+
+            revision = match operator {
+                '^' => Revision::Parent(Box::new(revision), n),
+                '~' => Revision::Ancestor(Box::new(revision), n),
+                _ => return Err(invalid()),
+            };
+        }
+
+        Ok(revision)
+    }
+}
+
+#[test]
+fn parse() -> anyhow::Result<()> {
+    let name = |name: &str| Box::new(Revision::Name(name.to_owned()));
+
+    assert_eq!("main".parse::<Revision>()?, *name("main"));
+    assert_eq!("@".parse::<Revision>()?, *name("HEAD"));
+    assert_eq!(
+        "HEAD~2^2".parse::<Revision>()?,
+        Revision::Parent(Box::new(Revision::Ancestor(name("HEAD"), 2)), 2),
+    );
+    assert_eq!(
+        "main^^{tree}".parse::<Revision>()?,
+        Revision::Peel(
+            Box::new(Revision::Parent(name("main"), 1)),
+            object::Type::Tree
+        ),
+    );
+    assert_eq!("@{u}".parse::<Revision>()?, Revision::Upstream(None));
+    assert_eq!(
+        "main@{upstream}~".parse::<Revision>()?,
+        Revision::Ancestor(Box::new(Revision::Upstream(Some(String::from("main")))), 1),
+    );
+    assert_eq!(
+        "v1.0:src/lib.rs".parse::<Revision>()?,
+        Revision::Path(name("v1.0"), path::PathBuf::from("src/lib.rs")),
+    );
+    assert_eq!(
+        ":README.md".parse::<Revision>()?,
+        Revision::Index(path::PathBuf::from("README.md")),
+    );
+
+    assert_eq!(
+        "HEAD@{2}^".parse::<Revision>()?,
+        Revision::Parent(Box::new(Revision::Reflog(Some(String::from("HEAD")), 2)), 1),
+    );
+    assert_eq!("@{0}".parse::<Revision>()?, Revision::Reflog(None, 0));
+
+    for invalid in &[
+        "",
+        "^",
+        "main^{blob}",
+        "main@{x}",
+        "main@{}",
+        "HEAD^{tree",
+        ":",
+    ] {
+        assert!(invalid.parse::<Revision>().is_err(), "{}", invalid);
+    }
+    Ok(())
+}
+
+#[test]
+fn resolve() -> anyhow::Result<()> {
+    let mut repository = crate::Repository::memory(path::PathBuf::new());
+    repository.init()?;
+
+    let database = repository.database()?;
+    let references = repository.references();
+    let blob = database.store(&crate::Object::Blob(object::Blob::new(b"data".to_vec())))?;
+    let inner = database.store(&crate::Object::Tree(object::tree::Root::new(vec![
+        object::tree::Node::new(
+            path::PathBuf::from("file"),
+            blob,
+            crate::meta::Mode::Regular,
+        ),
+    ])))?;
+    let tree = database.store(&crate::Object::Tree(object::tree::Root::new(vec![
+        object::tree::Node::new(
+            path::PathBuf::from("dir"),
+            inner,
+            crate::meta::Mode::Directory,
+        ),
+    ])))?;
+
+    let commit = |parents: Vec<object::Id>| {
+        let person = object::Person::new(
+            String::from("A U Thor"),
+            String::from("author@example.com"),
+            object::Person::parse_time("@1600000000 +0000")?,
+        );
+        let commit = object::Commit::new(
+            tree,
+            parents,
+            person.clone(),
+            person,
+            String::from("message\n"),
+        );
+        database
+            .store(&crate::Object::Commit(commit))
+            .map_err(anyhow::Error::from)
+    };
+
+    let root = commit(Vec::new())?;
+    let side = commit(vec![root])?;
+    let merge = commit(vec![root, side])?;
+    let person = object::Person::new(
+        String::from("C O Mitter"),
+        String::from("committer@example.com"),
+        object::Person::parse_time("@1600000000 +0000")?,
+    );
+    references.update_head(&root, &person, "commit (initial): message")?;
+    references.update_head(&merge, &person, "commit: message")?;
+    references.create_branch("side", &side)?;
+
+    let resolve = |text: &str| text.parse::<Revision>()?.resolve(&repository);
+
+    assert_eq!(resolve("HEAD")?, Some(merge));
+    assert_eq!(resolve("HEAD^2")?, Some(side));
+    assert_eq!(resolve("HEAD^2~")?, Some(root));
+    assert_eq!(resolve("side~1")?, Some(root));
+    assert_eq!(resolve("@^0")?, Some(merge));
+    assert_eq!(resolve(&merge.to_string()[..7])?, Some(merge));
+    assert_eq!(resolve("HEAD^{tree}")?, Some(tree));
+    assert_eq!(resolve("HEAD:dir/file")?, Some(blob));
+    assert_eq!(resolve("side:dir")?, Some(inner));
+    assert_eq!(resolve("missing~2")?, None);
+    assert!(resolve("HEAD~3").is_err());
+    assert!(resolve("HEAD:nope").is_err());
+    assert!(resolve("@{u}").is_err());
+    assert_eq!(resolve("HEAD@{0}")?, Some(merge));
+    assert_eq!(resolve("master@{1}")?, Some(root));
+    assert_eq!(resolve("@{1}~0")?, Some(root));
+    assert!(resolve("HEAD@{2}").is_err());
+    assert!(resolve("side@{0}").is_err());
+
+    let reference = |text: &str| text.parse::<Revision>()?.reference(&repository);
+
+    assert_eq!(reference("@")?.as_deref(), Some("refs/heads/master"));
+    assert_eq!(reference("side")?.as_deref(), Some("refs/heads/side"));
+    assert_eq!(reference("missing")?, None);
+    assert_eq!(reference("HEAD~1")?, None);
+    references.set_head(&crate::references::Target::Direct(side))?;
+    assert_eq!(reference("HEAD")?.as_deref(), Some("HEAD"));
+    Ok(())
+}
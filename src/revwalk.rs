@@ -0,0 +1,421 @@
+//! Commit graph traversal, selecting the commits reachable from some
+//! commits but not from others, like `git rev-list ^a b`.
+
+use std::collections::hash_map::Entry;
+use std::collections::BinaryHeap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::path;
+
+use crate::database::commit_graph;
+use crate::object;
+
+/// Traversal from included and excluded commits.
+///
+/// Commits in the commit-graph are visited without loading them from the
+/// object database.
+pub struct Walk<'a> {
+    database: &'a crate::Database,
+    graph: commit_graph::Graph,
+    /// Whether each queued or visited commit is reachable from an excluded
+    /// commit.
+    uninteresting: HashMap<object::Id, bool>,
+    queue: BinaryHeap<(i64, object::Id)>,
+}
+
+/// Result of a [`Walk`].
+#[derive(Clone, Debug, Default)]
+pub struct Selection {
+    /// Commits reachable from included commits but not from excluded ones,
+    /// newest first.
+    pub commits: Vec<object::Id>,
+    /// Excluded commits visited while selecting `commits`, whose contents
+    /// are shared with the selected history.
+    pub boundary: Vec<object::Id>,
+}
+
+impl<'a> Walk<'a> {
+    pub fn new(database: &'a crate::Database) -> Self {
+        // Like `git`, fall back to loading commits if the graph is unusable.
+        let graph = database.commit_graph().unwrap_or_else(|error| {
+            log::warn!("Ignoring commit-graph: {}", error);
+            commit_graph::Graph::default()
+        });
+        Walk {
+            database,
+            graph,
+            uninteresting: HashMap::new(),
+            queue: BinaryHeap::new(),
+        }
+    }
+
+    /// Select commit `id` and its ancestors.
+    pub fn include(&mut self, id: object::Id) -> anyhow::Result<()> {
+        self.push(id, false)
+    }
+
+    /// Exclude commit `id` and its ancestors, even if they are reachable
+    /// from included commits.
+    pub fn exclude(&mut self, id: object::Id) -> anyhow::Result<()> {
+        self.push(id, true)
+    }
+
+    fn push(&mut self, id: object::Id, flag: bool) -> anyhow::Result<()> {
+        match self.uninteresting.entry(id) {
+            Entry::Occupied(mut entry) => *entry.get_mut() |= flag,
+            Entry::Vacant(entry) => {
+                entry.insert(flag);
+                let time = match self.graph.get(&id) {
+                    Some(entry) => entry.time as i64,
+                    None => self
+                        .database
+                        .load_commit(&id)?
+                        .committer()
+                        .time()
+                        .timestamp(),
+                };
+                self.queue.push((time, id));
+            }
+        }
+        Ok(())
+    }
+
+    /// Visit commits newest first, so that shared history is usually marked
+    /// uninteresting before it would be visited from included commits, and
+    /// stop once only excluded commits remain.
+    pub fn run(mut self) -> anyhow::Result<Selection> {
+        let mut visited = Vec::new();
+        while self.queue.iter().any(|(_, id)| !self.uninteresting[id]) {
+            let (_, id) = self.queue.pop().expect("[INTERNAL ERROR]: non-empty queue");
+            let flag = self.uninteresting[&id];
+            let parents = match self.graph.get(&id) {
+                Some(entry) => entry.parents.clone(),
+                None => self.database.load_commit(&id)?.parents().to_vec(),
+            };
+            for parent in parents {
+                self.push(parent, flag)?;
+            }
+            visited.push(id);
+        }
+
+        // Commits can be marked uninteresting after they are visited if
+        // their timestamps are out of order.
+        let uninteresting = self.uninteresting;
+        let (boundary, commits) = visited
+            .into_iter()
+            .chain(self.queue.into_iter().map(|(_, id)| id))
+            .partition(|id| uninteresting[id]);
+
+        Ok(Selection { commits, boundary })
+    }
+}
+
+/// References to start a walk from, as selected by `git log --all`,
+/// `--branches`, `--tags`, `--remotes`, and `--glob`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Refs {
+    /// `HEAD` and every reference under `refs/`.
+    All,
+    /// Local branches, optionally only those matching a glob.
+    Branches(Option<String>),
+    /// Tags, optionally only those matching a glob.
+    Tags(Option<String>),
+    /// Remote-tracking branches, optionally only those matching a glob.
+    Remotes(Option<String>),
+    /// References matching a glob, relative to `refs/` unless it starts with
+    /// `refs/`.
+    Glob(String),
+}
+
+impl Refs {
+    /// Find the names and ids of the selected references.
+    ///
+    /// Like `git`, a pattern without glob characters matches everything
+    /// under it, so `--branches=feature` matches `refs/heads/feature/*`.
+    pub fn resolve(
+        &self,
+        references: &crate::References,
+    ) -> anyhow::Result<Vec<(String, object::Id)>> {
+        let (prefix, pattern) = match self {
+            Refs::All => {
+                let mut all = references
+                    .iter_prefix("refs/")?
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                if let Some(head) = references.read_head()? {
+                    all.insert(0, (String::from("HEAD"), head));
+                }
+                return Ok(all);
+            }
+            Refs::Branches(pattern) => (crate::References::HEADS, pattern.as_deref()),
+            Refs::Tags(pattern) => (crate::References::TAGS, pattern.as_deref()),
+            Refs::Remotes(pattern) => (crate::References::REMOTES, pattern.as_deref()),
+            Refs::Glob(pattern) => match pattern.strip_prefix("refs/") {
+                Some(pattern) => ("refs/", Some(pattern)),
+                None => ("refs/", Some(pattern.as_str())),
+            },
+        };
+
+        // Unlike in `.gitignore`, `*` matches across slashes here.
+        let pattern = match pattern {
+            None => None,
+            Some(pattern) if pattern.contains(['*', '?', '[']) => {
+                Some(format!("{}{}", prefix, pattern.replace('*', "**")))
+            }
+            Some(pattern) => Some(format!("{}{}/**", prefix, pattern.trim_end_matches('/'))),
+        };
+
+        references
+            .iter_prefix(prefix)?
+            .filter(|reference| match (reference, &pattern) {
+                (Ok((name, _)), Some(pattern)) => {
+                    crate::ignore::wildmatch(pattern.as_bytes(), name.as_bytes())
+                }
+                _ => true,
+            })
+            .collect()
+    }
+}
+
+/// List the trees and blobs reachable from `selection`'s commits but not
+/// from its boundary, each with its path relative to the root tree.
+pub fn objects(
+    database: &crate::Database,
+    selection: &Selection,
+) -> anyhow::Result<Vec<(object::Id, path::PathBuf)>> {
+    let mut shared = HashSet::new();
+    for id in &selection.boundary {
+        let tree = *database.load_commit(id)?.tree();
+        walk(database, tree, path::PathBuf::new(), &mut |_, id| {
+            shared.insert(id)
+        })?;
+    }
+
+    let mut objects = Vec::new();
+    for id in &selection.commits {
+        let tree = *database.load_commit(id)?.tree();
+        walk(database, tree, path::PathBuf::new(), &mut |path, id| {
+            if !shared.insert(id) {
+                return false;
+            }
+            objects.push((id, path.to_path_buf()));
+            true
+        })?;
+    }
+    Ok(objects)
+}
+
+/// Visit tree `id` at `path` and everything under it, skipping subtrees
+/// for which `visit` returns false.
+pub fn walk(
+    database: &crate::Database,
+    id: object::Id,
+    path: path::PathBuf,
+    visit: &mut dyn FnMut(&path::Path, object::Id) -> bool,
+) -> anyhow::Result<()> {
+    if !visit(&path, id) {
+        return Ok(());
+    }
+    for node in &database.load_tree(&id)? {
+        let child = path.join(&node.path);
+        match node.mode.is_directory() {
+            true => walk(database, node.id, child, visit)?,
+            false => {
+                visit(&child, node.id);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Find every object reachable from `roots`: commits and their history,
+/// the objects tags point to, and trees and everything in them.
+///
+/// Blobs are never loaded, so roots like index entries are cheap.
+pub fn reachable(
+    database: &crate::Database,
+    roots: impl IntoIterator<Item = object::Id>,
+) -> anyhow::Result<HashSet<object::Id>> {
+    let mut reachable = HashSet::new();
+    let mut stack = roots.into_iter().collect::<Vec<_>>();
+    while let Some(id) = stack.pop() {
+        if !reachable.insert(id) {
+            continue;
+        }
+        match database.kind(&id)? {
+            object::Type::Blob => (),
+            object::Type::Tag => stack.push(*database.load_tag(&id)?.object()),
+            object::Type::Commit => {
+                let commit = database.load_commit(&id)?;
+                stack.push(*commit.tree());
+                stack.extend(commit.parents());
+            }
+            object::Type::Tree => {
+                for node in &database.load_tree(&id)? {
+                    match node.mode.is_directory() {
+                        true => stack.push(node.id),
+                        false => {
+                            reachable.insert(node.id);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Ok(reachable)
+}
+
+#[test]
+fn select() -> anyhow::Result<()> {
+    let mut repository = crate::Repository::memory(path::PathBuf::new());
+    repository.init()?;
+    let database = repository.database()?;
+
+    let blob = |data: &str| {
+        database.store(&crate::Object::Blob(object::Blob::new(
+            data.as_bytes().to_vec(),
+        )))
+    };
+    let tree = |id: object::Id| {
+        database.store(&crate::Object::Tree(object::tree::Root::new(vec![
+            object::tree::Node::new(path::PathBuf::from("file"), id, crate::meta::Mode::Regular),
+        ])))
+    };
+    let commit = |tree: object::Id, parents: Vec<object::Id>, time: i64| {
+        let person = object::Person::new(
+            String::from("A U Thor"),
+            String::from("author@example.com"),
+            object::Person::parse_time(&format!("@{} +0000", time))?,
+        );
+        database
+            .store(&crate::Object::Commit(object::Commit::new(
+                tree,
+                parents,
+                person.clone(),
+                person,
+                String::from("message\n"),
+            )))
+            .map_err(anyhow::Error::from)
+    };
+
+    // root <- left <- merge
+    //      <- right <-
+    let (a, b, c) = (blob("a")?, blob("b")?, blob("c")?);
+    let root = commit(tree(a)?, Vec::new(), 1)?;
+    let left = commit(tree(b)?, vec![root], 2)?;
+    let right = commit(tree(c)?, vec![root], 3)?;
+    let merge = commit(tree(c)?, vec![left, right], 4)?;
+
+    let select = |include: &[object::Id], exclude: &[object::Id]| {
+        let mut walk = Walk::new(&database);
+        for id in include {
+            walk.include(*id)?;
+        }
+        for id in exclude {
+            walk.exclude(*id)?;
+        }
+        walk.run()
+    };
+
+    assert_eq!(select(&[merge], &[])?.commits, [merge, right, left, root]);
+    assert_eq!(select(&[merge], &[left])?.commits, [merge, right]);
+    assert_eq!(select(&[left, right], &[root])?.commits, [right, left]);
+    assert!(select(&[left], &[merge])?.commits.is_empty());
+
+    let selection = select(&[merge], &[right])?;
+    let objects = objects(&database, &selection)?
+        .into_iter()
+        .map(|(id, _)| id)
+        .collect::<Vec<_>>();
+    assert_eq!(objects, [tree(b)?, b]);
+    Ok(())
+}
+
+#[test]
+fn refs() -> anyhow::Result<()> {
+    let mut repository = crate::Repository::memory(path::PathBuf::new());
+    repository.init()?;
+    let database = repository.database()?;
+    let references = repository.references();
+
+    let tree = database.store(&crate::Object::Tree(object::tree::Root::new(Vec::new())))?;
+    let person = object::Person::new(
+        String::from("A U Thor"),
+        String::from("author@example.com"),
+        object::Person::parse_time("@1600000000 +0000")?,
+    );
+    let commit = database.store(&crate::Object::Commit(object::Commit::new(
+        tree,
+        Vec::new(),
+        person.clone(),
+        person,
+        String::from("message\n"),
+    )))?;
+    for branch in ["main", "feature/a", "feature/b/c", "featured"] {
+        references.create_branch(branch, &commit)?;
+    }
+    references.create_tag("v1", &commit, false)?;
+
+    let names = |refs: Refs| -> anyhow::Result<Vec<String>> {
+        Ok(refs
+            .resolve(&references)?
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect())
+    };
+    let pattern = |pattern: &str| Some(String::from(pattern));
+
+    assert_eq!(names(Refs::Branches(None))?.len(), 4);
+    assert_eq!(
+        names(Refs::Branches(pattern("feature")))?,
+        ["refs/heads/feature/a", "refs/heads/feature/b/c"]
+    );
+    assert_eq!(names(Refs::Branches(pattern("feature*")))?.len(), 3);
+    assert_eq!(
+        names(Refs::Glob(String::from("tags/v?")))?,
+        ["refs/tags/v1"]
+    );
+    assert!(names(Refs::Remotes(None))?.is_empty());
+    assert_eq!(names(Refs::All)?.len(), 5);
+    Ok(())
+}
+
+#[test]
+fn commit_graph() -> anyhow::Result<()> {
+    let root = crate::util::TempDir::new();
+    let database = crate::Database::open(root.to_path_buf());
+
+    let tree = database.store(&crate::Object::Tree(object::tree::Root::new(Vec::new())))?;
+    let mut commits = Vec::new();
+    for time in 0..3 {
+        let person = object::Person::new(
+            String::from("A U Thor"),
+            String::from("author@example.com"),
+            object::Person::parse_time(&format!("@{} +0000", time))?,
+        );
+        commits.push(database.store(&crate::Object::Commit(object::Commit::new(
+            tree,
+            commits.last().into_iter().copied().collect(),
+            person.clone(),
+            person,
+            String::from("message\n"),
+        )))?);
+    }
+    commit_graph::Graph::open(&root.join("info"))?.write(
+        &database,
+        &root.join("info"),
+        &commits[2..],
+        false,
+    )?;
+
+    // Walking must not need the commit objects themselves.
+    for id in &commits {
+        std::fs::remove_file(root.join(id.to_path_buf()))?;
+    }
+    let mut walk = Walk::new(&database);
+    walk.include(commits[2])?;
+    walk.exclude(commits[0])?;
+    let selection = walk.run()?;
+    assert_eq!(selection.commits, [commits[2], commits[1]]);
+    assert_eq!(selection.boundary, [commits[0]]);
+    Ok(())
+}
@@ -0,0 +1,219 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path;
+use std::time;
+
+/// Locks older than this are assumed to belong to a crashed process.
+pub const STALE_LOCK: time::Duration = time::Duration::from_secs(10 * 60);
+
+/// State left behind in the `.git` directory by an operation that was
+/// interrupted or is still in progress.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Leftover {
+    /// `MERGE_HEAD` exists.
+    Merge,
+    /// `rebase-merge` (interactive) or `rebase-apply` exists.
+    Rebase { interactive: bool },
+    /// `CHERRY_PICK_HEAD` exists.
+    CherryPick,
+    /// `REVERT_HEAD` exists.
+    Revert,
+    /// `BISECT_START` exists.
+    Bisect,
+    /// A `*.lock` file exists, relative to the `.git` directory.
+    Lock {
+        path: path::PathBuf,
+        age: time::Duration,
+    },
+}
+
+impl Leftover {
+    /// Suggest how to proceed, in the style of `git status` hints.
+    pub fn hint(&self) -> &'static str {
+        match self {
+            Leftover::Merge => {
+                "(commit to conclude the merge, or run \"grit doctor --clean\" to abandon it)"
+            }
+            Leftover::Rebase { .. } => {
+                "(run \"grit doctor --clean\" to discard the leftover rebase state)"
+            }
+            Leftover::CherryPick => {
+                "(commit to conclude the cherry-pick, or run \"grit doctor --clean\" to abandon it)"
+            }
+            Leftover::Revert => {
+                "(commit to conclude the revert, or run \"grit doctor --clean\" to abandon it)"
+            }
+            Leftover::Bisect => {
+                "(run \"git bisect reset\" to return to the original branch, or \"grit doctor --clean\" to discard the bisect state)"
+            }
+            Leftover::Lock { .. } => {
+                "(another process may be running; if not, run \"grit doctor --clean --force\" to remove it)"
+            }
+        }
+    }
+
+    /// Whether this state is safe to remove without `--force`.
+    pub fn is_stale(&self) -> bool {
+        match self {
+            Leftover::Lock { age, .. } => *age >= STALE_LOCK,
+            _ => true,
+        }
+    }
+
+    /// Remove this state from the `.git` directory `git`, leaving HEAD, the
+    /// index, and the workspace as they are.
+    pub fn clean(&self, git: &path::Path) -> io::Result<()> {
+        let files: &[&str] = match self {
+            Leftover::Merge => &["MERGE_HEAD", "MERGE_MSG", "MERGE_MODE"],
+            Leftover::Rebase { interactive: true } => {
+                return remove_dir_all(&git.join("rebase-merge"))
+            }
+            Leftover::Rebase { interactive: false } => {
+                return remove_dir_all(&git.join("rebase-apply"))
+            }
+            Leftover::CherryPick => &["CHERRY_PICK_HEAD", "MERGE_MSG"],
+            Leftover::Revert => &["REVERT_HEAD", "MERGE_MSG"],
+            Leftover::Bisect => {
+                remove_dir_all(&git.join("refs/bisect"))?;
+                &[
+                    "BISECT_START",
+                    "BISECT_LOG",
+                    "BISECT_NAMES",
+                    "BISECT_TERMS",
+                    "BISECT_EXPECTED_REV",
+                    "BISECT_ANCESTORS_OK",
+                    "BISECT_RUN",
+                    "BISECT_HEAD",
+                ]
+            }
+            Leftover::Lock { path, .. } => return remove_file(&git.join(path)),
+        };
+
+        for file in files {
+            remove_file(&git.join(file))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Leftover {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Leftover::Merge => write!(fmt, "You are in the middle of a merge."),
+            Leftover::Rebase { interactive: true } => {
+                write!(fmt, "You are in the middle of an interactive rebase.")
+            }
+            Leftover::Rebase { interactive: false } => {
+                write!(fmt, "You are in the middle of a rebase.")
+            }
+            Leftover::CherryPick => write!(fmt, "You are in the middle of a cherry-pick."),
+            Leftover::Revert => write!(fmt, "You are in the middle of a revert."),
+            Leftover::Bisect => write!(fmt, "You are in the middle of a bisect."),
+            Leftover::Lock { path, age } => write!(
+                fmt,
+                "Lock file .git/{} was last modified {} seconds ago.",
+                path.display(),
+                age.as_secs(),
+            ),
+        }
+    }
+}
+
+/// Detect leftover operation state and lock files in the `.git` directory
+/// `git`.
+pub fn audit(git: &path::Path) -> io::Result<Vec<Leftover>> {
+    let mut leftovers = Vec::new();
+
+    for (file, leftover) in [
+        ("MERGE_HEAD", Leftover::Merge),
+        ("rebase-merge", Leftover::Rebase { interactive: true }),
+        ("rebase-apply", Leftover::Rebase { interactive: false }),
+        ("CHERRY_PICK_HEAD", Leftover::CherryPick),
+        ("REVERT_HEAD", Leftover::Revert),
+        ("BISECT_START", Leftover::Bisect),
+    ] {
+        if git.join(file).exists() {
+            leftovers.push(leftover);
+        }
+    }
+
+    let now = time::SystemTime::now();
+    let mut stack = vec![path::PathBuf::new()];
+
+    while let Some(directory) = stack.pop() {
+        let entries = match fs::read_dir(git.join(&directory)) {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => continue,
+            Err(error) => return Err(error),
+        };
+
+        for entry in entries {
+            let entry = entry?;
+            let path = directory.join(entry.file_name());
+            let metadata = entry.metadata()?;
+
+            // Object files are never locked, and there may be many of them.
+            if metadata.is_dir() && path != path::Path::new("objects") {
+                stack.push(path);
+            } else if metadata.is_file() && path.extension() == Some("lock".as_ref()) {
+                let age = metadata
+                    .modified()
+                    .ok()
+                    .and_then(|modified| now.duration_since(modified).ok())
+                    .unwrap_or_default();
+                leftovers.push(Leftover::Lock { path, age });
+            }
+        }
+    }
+
+    Ok(leftovers)
+}
+
+fn remove_file(path: &path::Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+        _ => Ok(()),
+    }
+}
+
+fn remove_dir_all(path: &path::Path) -> io::Result<()> {
+    match fs::remove_dir_all(path) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+        _ => Ok(()),
+    }
+}
+
+#[test]
+fn audit_and_clean() -> anyhow::Result<()> {
+    let git = crate::util::TempDir::new();
+
+    fs::create_dir_all(git.join("refs/heads"))?;
+    fs::write(git.join("MERGE_HEAD"), "")?;
+    fs::write(git.join("MERGE_MSG"), "")?;
+    fs::write(git.join("BISECT_START"), "master")?;
+    fs::create_dir_all(git.join("refs/bisect"))?;
+    fs::write(git.join("refs/bisect/bad"), "")?;
+    fs::write(git.join("refs/heads/master.lock"), "")?;
+
+    let leftovers = audit(&git)?;
+    for leftover in &leftovers {
+        leftover.clean(&git)?;
+    }
+    let remaining = audit(&git)?;
+    let merge_msg = git.join("MERGE_MSG").exists();
+    let bisect = git.join("refs/bisect").exists();
+
+    assert_eq!(leftovers.len(), 3);
+    assert_eq!(leftovers[0], Leftover::Merge);
+    assert_eq!(leftovers[1], Leftover::Bisect);
+    assert!(matches!(
+        &leftovers[2],
+        Leftover::Lock { path, .. } if path == path::Path::new("refs/heads/master.lock")
+    ));
+    assert!(!leftovers[2].is_stale());
+    assert!(remaining.is_empty());
+    assert!(!merge_msg);
+    assert!(!bisect);
+    Ok(())
+}
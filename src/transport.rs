@@ -0,0 +1,753 @@
+//! Client side of git's smart HTTP protocol, which fetches from a remote
+//! `git-upload-pack` in two requests: one to discover the remote's
+//! references, and one to download a packfile of the objects wanted. Pushes
+//! to `git-receive-pack` likewise send reference updates and a packfile in
+//! a single request after discovery.
+//!
+//! Speaks protocol version 0, sending every `have` in a single round so that
+//! the remote can leave out history shared with the local repository. See
+//! `git help gitprotocol-http` and `git help gitprotocol-pack` for the format.
+//!
+//! The same requests are sent over `ssh` by the [`ssh`] backend for `ssh://`
+//! and scp-like `host:path` URLs. Remotes given as a path or `file://` URL
+//! are instead read and written directly, without spawning `git`, by the
+//! [`local`] backend. HTTP requests honor the proxy, certificate, and header
+//! settings described in [`http`].
+//!
+//! Requests that fail in ways that might not happen again, like a dropped
+//! connection, are retried `transfer.retries` times, and fetches cut off
+//! partway through the pack resume as described in [`resume`].
+
+mod http;
+mod local;
+mod resume;
+mod ssh;
+
+use std::cmp;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::io::Read as _;
+use std::path;
+use std::str;
+use std::thread;
+use std::time;
+
+use anyhow::anyhow;
+use anyhow::Context as _;
+
+use crate::config;
+use crate::object;
+use crate::protocol::pktline;
+use crate::references;
+use crate::revwalk;
+
+/// Sent as both the `User-Agent` header and the `agent` capability.
+const AGENT: &str = concat!("grit/", env!("CARGO_PKG_VERSION"));
+
+/// Capabilities requested from `git-upload-pack` if it offers them.
+const FETCH_CAPABILITIES: &[&str] = &["ofs-delta", "side-band-64k"];
+
+/// Capabilities requested from `git-receive-pack` if it offers them.
+const PUSH_CAPABILITIES: &[&str] = &["report-status", "delete-refs", "side-band-64k"];
+
+/// Wait before the first retry, doubling for each one after up to
+/// [`MAX_RETRY_DELAY`].
+const RETRY_DELAY: time::Duration = time::Duration::from_secs(1);
+const MAX_RETRY_DELAY: time::Duration = time::Duration::from_secs(32);
+
+const UPLOAD_PACK: &str = "git-upload-pack";
+const RECEIVE_PACK: &str = "git-receive-pack";
+
+/// References and capabilities advertised by a remote.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Advertisement {
+    /// Full reference names in the order sent, excluding peeled tags.
+    pub refs: Vec<(String, object::Id)>,
+    pub capabilities: Vec<String>,
+}
+
+impl Advertisement {
+    /// Parse the list of references that follows the service announcement,
+    /// where the first carries the remote's capabilities after a NUL byte.
+    pub fn read<R: io::Read>(reader: &mut pktline::Reader<R>) -> anyhow::Result<Self> {
+        let mut advertisement = Advertisement::default();
+        while let Some(line) = reader.read_data()? {
+            let line = pktline::trim_newline(&line);
+            let line = match line.iter().position(|byte| *byte == 0) {
+                None => line,
+                Some(index) => {
+                    advertisement.capabilities = str::from_utf8(&line[index + 1..])?
+                        .split(' ')
+                        .filter(|capability| !capability.is_empty())
+                        .map(String::from)
+                        .collect();
+                    &line[..index]
+                }
+            };
+
+            let line = str::from_utf8(line)?;
+            let (id, name) = line
+                .split_once(' ')
+                .ok_or_else(|| anyhow!("Invalid reference advertisement: {}", line))?;
+
+            // An empty repository advertises only `capabilities^{}`.
+            if name.ends_with("^{}") {
+                continue;
+            }
+            advertisement.refs.push((name.to_owned(), id.parse()?));
+        }
+        Ok(advertisement)
+    }
+
+    /// Check whether the remote offers `capability`, ignoring any value.
+    pub fn supports(&self, capability: &str) -> bool {
+        self.capabilities
+            .iter()
+            .any(|offered| offered.split('=').next() == Some(capability))
+    }
+
+    /// Full name of the branch the remote's `HEAD` points to, if any.
+    ///
+    /// Falls back to a branch at the same commit for remotes that don't
+    /// send the `symref` capability.
+    pub fn head(&self) -> Option<&str> {
+        if let Some(head) = self
+            .capabilities
+            .iter()
+            .find_map(|capability| capability.strip_prefix("symref=HEAD:"))
+        {
+            return Some(head);
+        }
+
+        let (_, head) = self.refs.iter().find(|(name, _)| name == "HEAD")?;
+        self.refs
+            .iter()
+            .find(|(name, id)| id == head && name.starts_with(crate::References::HEADS))
+            .map(|(name, _)| name.as_str())
+    }
+}
+
+/// A repository served over smart HTTP or `ssh`, or on the local
+/// filesystem.
+pub struct Remote {
+    url: String,
+    agent: ureq::Agent,
+    /// Sent with every HTTP request, from `http.extraHeader`.
+    headers: Vec<(String, String)>,
+    /// Times to retry a request that failed transiently.
+    retries: usize,
+    ssh: Option<ssh::Ssh>,
+    local: Option<local::Local>,
+}
+
+impl Remote {
+    /// Connect to `url`, configuring HTTP from `config`.
+    pub fn new(url: &str, config: &config::Config) -> anyhow::Result<Self> {
+        let ssh = ssh::Ssh::parse(url);
+        let local = match ssh {
+            Some(_) => None,
+            None => local::Local::parse(url),
+        };
+        let url = match &local {
+            Some(local) => local.path().display().to_string(),
+            None => url.trim_end_matches('/').to_owned(),
+        };
+        let (agent, headers) = match (&ssh, &local) {
+            (None, None) => (http::agent(&url, config)?, http::headers(&url, config)?),
+            _ => (ureq::agent(), Vec::new()),
+        };
+        Ok(Remote {
+            url,
+            agent,
+            headers,
+            retries: config.parse("transfer.retries")?.unwrap_or(0),
+            ssh,
+            local,
+        })
+    }
+
+    /// The remote's URL, or its absolute path if it's local.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Discover the remote's references and capabilities for fetching.
+    pub fn advertise(&self) -> anyhow::Result<Advertisement> {
+        self.retry(|_| match (&self.ssh, &self.local) {
+            (Some(ssh), _) => ssh
+                .advertise(UPLOAD_PACK)
+                .with_context(|| format!("Unable to access '{}'", self.url)),
+            (_, Some(local)) => local.advertise(false),
+            (None, None) => self.discover(UPLOAD_PACK),
+        })
+    }
+
+    /// Discover the remote's references and capabilities for pushing.
+    pub fn advertise_push(&self) -> anyhow::Result<Advertisement> {
+        self.retry(|_| match (&self.ssh, &self.local) {
+            (Some(ssh), _) => ssh
+                .advertise(RECEIVE_PACK)
+                .with_context(|| format!("Unable to access '{}'", self.url)),
+            (_, Some(local)) => local.advertise(true),
+            (None, None) => self.discover(RECEIVE_PACK),
+        })
+    }
+
+    /// Run `attempt`, given the number of retries left, until it succeeds,
+    /// fails in a way that would happen again, or has been retried
+    /// `transfer.retries` times, waiting longer before each retry.
+    fn retry<T>(&self, mut attempt: impl FnMut(usize) -> anyhow::Result<T>) -> anyhow::Result<T> {
+        let mut delay = RETRY_DELAY;
+        let mut retries = 0;
+        loop {
+            match attempt(self.retries - retries) {
+                Err(error) if retries < self.retries && is_transient(&error) => {
+                    eprintln!("warning: {:#}", error);
+                    eprintln!("Retrying in {}s...", delay.as_secs());
+                    thread::sleep(delay);
+                    delay = cmp::min(delay * 2, MAX_RETRY_DELAY);
+                    retries += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn discover(&self, service: &str) -> anyhow::Result<Advertisement> {
+        let response = self
+            .request(
+                "GET",
+                &format!("{}/info/refs?service={}", self.url, service),
+            )
+            .call()
+            .with_context(|| format!("Unable to access '{}'", self.url))?;
+
+        if response.content_type() != format!("application/x-{}-advertisement", service) {
+            return Err(anyhow!(
+                "'{}' does not support the smart HTTP protocol",
+                self.url
+            ));
+        }
+
+        let mut reader = pktline::Reader::new(response.into_reader());
+        match reader.read_data()? {
+            Some(line)
+                if pktline::trim_newline(&line) == format!("# service={}", service).as_bytes() => {}
+            _ => return Err(anyhow!("Invalid service announcement from '{}'", self.url)),
+        }
+        if reader.read_data()?.is_some() {
+            return Err(anyhow!("Invalid service announcement from '{}'", self.url));
+        }
+
+        Advertisement::read(&mut reader)
+            .with_context(|| format!("Invalid reference advertisement from '{}'", self.url))
+    }
+
+    /// Ask the remote where to download bundles of its history, using the
+    /// protocol version 2 `bundle-uri` command, in the order to apply them.
+    ///
+    /// Only HTTP remotes that offer the command are asked: others have no
+    /// bundles.
+    pub fn bundle_uris(&self) -> anyhow::Result<Vec<String>> {
+        if self.ssh.is_some() || self.local.is_some() {
+            return Ok(Vec::new());
+        }
+
+        let response = self.retry(|_| {
+            self.request(
+                "GET",
+                &format!("{}/info/refs?service={}", self.url, UPLOAD_PACK),
+            )
+            .set("Git-Protocol", "version=2")
+            .call()
+            .with_context(|| format!("Unable to access '{}'", self.url))
+        })?;
+
+        // Unlike version 0, the service announcement is optional.
+        let mut reader = pktline::Reader::new(response.into_reader());
+        let mut line = reader.read_data()?;
+        if let Some(announcement) = &line {
+            if announcement.starts_with(b"# service=") {
+                reader.read_data()?;
+                line = reader.read_data()?;
+            }
+        }
+        if line.as_deref().map(pktline::trim_newline) != Some(b"version 2") {
+            return Ok(Vec::new());
+        }
+        let mut offered = false;
+        while let Some(capability) = reader.read_data()? {
+            offered |= pktline::trim_newline(&capability) == b"bundle-uri";
+        }
+        if !offered {
+            return Ok(Vec::new());
+        }
+
+        let mut request = pktline::Writer::new(Vec::new());
+        request.write(b"command=bundle-uri\n")?;
+        request.write(format!("agent={}\n", AGENT).as_bytes())?;
+        request.write_delim()?;
+        request.write_flush()?;
+        let request = request.into_inner();
+        let response = self.retry(|_| {
+            self.request("POST", &format!("{}/{}", self.url, UPLOAD_PACK))
+                .set("Git-Protocol", "version=2")
+                .set(
+                    "Content-Type",
+                    &format!("application/x-{}-request", UPLOAD_PACK),
+                )
+                .send_bytes(&request)
+                .with_context(|| format!("Unable to access '{}'", self.url))
+        })?;
+
+        // Each line is a `bundle.<id>.<key>=<value>` setting of a bundle
+        // list, as in `git help gitformat-bundle`.
+        let mut reader = pktline::Reader::new(response.into_reader());
+        let mut bundles = Vec::<(String, Option<u64>, Option<String>)>::new();
+        while let Some(line) = reader.read_data()? {
+            let line = str::from_utf8(pktline::trim_newline(&line))?;
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Invalid bundle list from '{}'", self.url))?;
+            let (id, key) = match key
+                .strip_prefix("bundle.")
+                .and_then(|key| key.rsplit_once('.'))
+            {
+                Some(setting) => setting,
+                None => continue,
+            };
+            let index = match bundles.iter().position(|(known, _, _)| known == id) {
+                Some(index) => index,
+                None => {
+                    bundles.push((id.to_owned(), None, None));
+                    bundles.len() - 1
+                }
+            };
+            match key.to_ascii_lowercase().as_str() {
+                "creationtoken" => bundles[index].1 = value.parse().ok(),
+                "uri" => bundles[index].2 = Some(self.resolve(value)),
+                _ => (),
+            }
+        }
+
+        // Bundles with creation tokens build on those with lower ones.
+        bundles.sort_by_key(|(_, token, _)| *token);
+        Ok(bundles.into_iter().filter_map(|(_, _, uri)| uri).collect())
+    }
+
+    /// Resolve `uri` from a bundle list, which may be relative to the
+    /// remote's URL.
+    fn resolve(&self, uri: &str) -> String {
+        if uri.contains("://") {
+            return uri.to_owned();
+        }
+        match uri.strip_prefix('/') {
+            None => format!("{}/{}", self.url, uri),
+            Some(path) => {
+                let (scheme, rest) = self.url.split_once("://").unwrap_or(("", &self.url));
+                let host = rest.split('/').next().unwrap_or_default();
+                format!("{}://{}/{}", scheme, host, path)
+            }
+        }
+    }
+
+    /// Download the file at `uri`, e.g. a bundle, over HTTP or from the
+    /// local filesystem.
+    ///
+    /// Configured extra headers aren't sent, since they may hold secrets
+    /// meant for the remote rather than another host.
+    pub fn download(&self, uri: &str) -> anyhow::Result<Vec<u8>> {
+        if !uri.starts_with("http://") && !uri.starts_with("https://") {
+            let path = uri.strip_prefix("file://").unwrap_or(uri);
+            return fs::read(path).with_context(|| format!("Unable to read '{}'", path));
+        }
+
+        self.retry(|_| {
+            let mut data = Vec::new();
+            self.agent
+                .get(uri)
+                .call()
+                .with_context(|| format!("Unable to access '{}'", uri))?
+                .into_reader()
+                .read_to_end(&mut data)
+                .with_context(|| format!("Unable to download '{}'", uri))?;
+            Ok(data)
+        })
+    }
+
+    /// Start an HTTP request carrying the configured extra headers.
+    fn request(&self, method: &str, url: &str) -> ureq::Request {
+        self.headers
+            .iter()
+            .fold(self.agent.request(method, url), |request, (name, value)| {
+                request.set(name, value)
+            })
+    }
+
+    /// Send `request` to `service`, returning a reader for the response.
+    fn post(
+        &self,
+        service: &'static str,
+        request: &[u8],
+    ) -> anyhow::Result<pktline::Reader<Box<dyn io::Read + Send>>> {
+        if let Some(ssh) = &self.ssh {
+            return ssh.post(service, request);
+        }
+
+        let response = self
+            .request("POST", &format!("{}/{}", self.url, service))
+            .set(
+                "Content-Type",
+                &format!("application/x-{}-request", service),
+            )
+            .set("Accept", &format!("application/x-{}-result", service))
+            .send_bytes(request)
+            .with_context(|| format!("Unable to access '{}'", self.url))?;
+        Ok(pktline::Reader::new(Box::new(response.into_reader())))
+    }
+
+    /// Download and save into `database` a packfile holding `wants`, which
+    /// must not be empty, and every object they reach that isn't reachable
+    /// from `haves`, copying the remote's progress messages to `progress`.
+    ///
+    /// `haves` should be ordered newest first, since the remote stops
+    /// acknowledging after the first commit it recognizes.
+    ///
+    /// If the transfer is cut off, the objects received so far are kept,
+    /// and the next retry asks only for what they don't already complete.
+    pub fn fetch(
+        &self,
+        database: &crate::Database,
+        advertisement: &Advertisement,
+        wants: &[object::Id],
+        haves: &[object::Id],
+        progress: &mut dyn io::Write,
+    ) -> anyhow::Result<()> {
+        let mut received = resume::Received::default();
+        let mut wants = wants.to_vec();
+        let mut resumed = haves.to_vec();
+        self.retry(|remaining| {
+            let mut pack = Vec::new();
+            let error = match self.receive(advertisement, &wants, &resumed, &mut pack, progress) {
+                Ok(()) => return database.index_pack(pack).map(drop),
+                Err(error) if remaining == 0 || pack.is_empty() || !is_transient(&error) => {
+                    return Err(error)
+                }
+                Err(error) => error,
+            };
+
+            let kept = received.keep(database, &pack)?;
+            let complete = received.haves(database)?;
+            eprintln!(
+                "Kept {} objects, completing {} commits, before the transfer failed",
+                kept,
+                complete.len(),
+            );
+            wants.retain(|want| !complete.contains(want));
+            if wants.is_empty() {
+                return Ok(());
+            }
+            resumed = complete.iter().chain(haves).copied().collect();
+            Err(error)
+        })
+    }
+
+    /// Download a packfile like [`Remote::fetch`] into `pack`, which holds
+    /// whatever was received if the transfer fails.
+    fn receive(
+        &self,
+        advertisement: &Advertisement,
+        wants: &[object::Id],
+        haves: &[object::Id],
+        pack: &mut Vec<u8>,
+        progress: &mut dyn io::Write,
+    ) -> anyhow::Result<()> {
+        if let Some(local) = &self.local {
+            *pack = local.fetch(wants, haves)?;
+            return Ok(());
+        }
+
+        let capabilities = capabilities(advertisement, FETCH_CAPABILITIES);
+        let sideband = advertisement.supports("side-band-64k");
+
+        let mut request = pktline::Writer::new(Vec::new());
+        for (index, want) in wants.iter().enumerate() {
+            let line = match index {
+                0 => format!("want {} {}\n", want, capabilities),
+                _ => format!("want {}\n", want),
+            };
+            request.write(line.as_bytes())?;
+        }
+        request.write_flush()?;
+        for have in haves {
+            request.write(format!("have {}\n", have).as_bytes())?;
+        }
+        request.write(b"done\n")?;
+
+        // Over stateless HTTP, the remote acknowledges every common commit
+        // it recognizes, or sends a single NAK, before the pack.
+        let mut reader = self.post(UPLOAD_PACK, &request.into_inner())?;
+        let mut acknowledged = false;
+        loop {
+            if acknowledged && !sideband && reader.peek_pack()? {
+                reader.read_raw_to_end(pack)?;
+                return Ok(());
+            }
+
+            match reader.peek()? {
+                pktline::Packet::Data(line)
+                    if line.starts_with(b"ACK ") || pktline::trim_newline(line) == b"NAK" =>
+                {
+                    acknowledged = true
+                }
+                pktline::Packet::Data(line) if line.starts_with(b"ERR ") => {
+                    return Err(anyhow!(
+                        "Remote error: {}",
+                        String::from_utf8_lossy(pktline::trim_newline(&line[4..]))
+                    ))
+                }
+                _ if !acknowledged || !sideband => {
+                    return Err(anyhow!("Expected ACK or NAK from '{}'", self.url))
+                }
+                _ => break,
+            }
+            reader.read()?;
+        }
+
+        reader
+            .sideband(progress)
+            .read_to_end(pack)
+            .with_context(|| format!("Invalid pack from '{}'", self.url))?;
+        Ok(())
+    }
+
+    /// Ask the remote to apply `updates`, sending `pack` with the objects
+    /// they need, and return its verdict on each reference: `Err` holds the
+    /// reason it was rejected.
+    pub fn push(
+        &self,
+        advertisement: &Advertisement,
+        updates: &[references::Update],
+        pack: &[u8],
+        progress: &mut dyn io::Write,
+    ) -> anyhow::Result<Vec<(String, Result<(), String>)>> {
+        if let Some(local) = &self.local {
+            return local.push(updates, pack);
+        }
+        if !advertisement.supports("report-status") {
+            return Err(anyhow!("'{}' does not report push status", self.url));
+        }
+        if updates.iter().any(|update| update.new.is_none())
+            && !advertisement.supports("delete-refs")
+        {
+            return Err(anyhow!(
+                "'{}' does not support deleting references",
+                self.url
+            ));
+        }
+        let capabilities = capabilities(advertisement, PUSH_CAPABILITIES);
+        let sideband = advertisement.supports("side-band-64k");
+
+        let mut request = pktline::Writer::new(Vec::new());
+        for (index, update) in updates.iter().enumerate() {
+            let old = update
+                .old
+                .map(|old| old.to_string())
+                .unwrap_or_else(|| "0".repeat(40));
+            let new = update
+                .new
+                .map(|new| new.to_string())
+                .unwrap_or_else(|| "0".repeat(40));
+            let line = match index {
+                0 => format!("{} {} {}\0{}\n", old, new, update.name, capabilities),
+                _ => format!("{} {} {}\n", old, new, update.name),
+            };
+            request.write(line.as_bytes())?;
+        }
+        request.write_flush()?;
+        // The remote expects no pack when only deleting references.
+        if updates.iter().any(|update| update.new.is_some()) {
+            request.get_mut().extend_from_slice(pack);
+        }
+
+        // The report is itself a list of pkt-lines, which side-band wraps
+        // in another layer.
+        let mut reader = self.post(RECEIVE_PACK, &request.into_inner())?;
+        let report = match sideband {
+            true => {
+                let mut report = Vec::new();
+                reader
+                    .sideband(progress)
+                    .read_to_end(&mut report)
+                    .with_context(|| format!("Invalid status report from '{}'", self.url))?;
+                report
+            }
+            false => {
+                let mut report = Vec::new();
+                reader.read_raw_to_end(&mut report)?;
+                report
+            }
+        };
+
+        let mut report = pktline::Reader::new(&report[..]);
+        match report.read_data()? {
+            Some(line) if pktline::trim_newline(&line) == b"unpack ok" => (),
+            Some(line) => {
+                return Err(anyhow!(
+                    "Remote failed to unpack objects: {}",
+                    String::from_utf8_lossy(pktline::trim_newline(&line))
+                ))
+            }
+            None => return Err(anyhow!("Missing status report from '{}'", self.url)),
+        }
+
+        let mut statuses = Vec::new();
+        while let Some(line) = report.read_data()? {
+            let line = str::from_utf8(pktline::trim_newline(&line))?;
+            let status = match line.split_once(' ') {
+                Some(("ok", name)) => (name.to_owned(), Ok(())),
+                Some(("ng", rest)) => {
+                    let (name, reason) = rest.split_once(' ').unwrap_or((rest, "failed"));
+                    (name.to_owned(), Err(reason.to_owned()))
+                }
+                _ => return Err(anyhow!("Invalid status report from '{}'", self.url)),
+            };
+            statuses.push(status);
+        }
+        Ok(statuses)
+    }
+}
+
+/// Check whether `error` might not happen again, like a dropped connection
+/// or a server error, unlike a missing repository.
+fn is_transient(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .any(|cause| match cause.downcast_ref::<ureq::Error>() {
+            Some(ureq::Error::Status(status, _)) => *status == 429 || *status >= 500,
+            Some(ureq::Error::Transport(transport)) => matches!(
+                transport.kind(),
+                ureq::ErrorKind::Dns | ureq::ErrorKind::ConnectionFailed | ureq::ErrorKind::Io
+            ),
+            None => cause.is::<io::Error>(),
+        })
+}
+
+/// Those of `requested` that the remote offers, plus our agent, as sent
+/// after the first command.
+fn capabilities(advertisement: &Advertisement, requested: &[&str]) -> String {
+    let mut capabilities = requested
+        .iter()
+        .filter(|capability| advertisement.supports(capability))
+        .map(|capability| capability.to_string())
+        .collect::<Vec<_>>();
+    capabilities.push(format!("agent={}", AGENT));
+    capabilities.join(" ")
+}
+
+/// Find the objects reachable from `wants` but not from `haves`, which the
+/// remote is known to have, along with objects the remote has at the same
+/// paths to use as delta bases.
+pub fn missing(
+    database: &crate::Database,
+    wants: &[object::Id],
+    haves: &[object::Id],
+) -> anyhow::Result<(Vec<object::Id>, Vec<object::Id>)> {
+    let mut objects = Vec::new();
+    let mut walk = revwalk::Walk::new(database);
+
+    for have in haves {
+        if let Ok(have) = have.peel_to_commit(database) {
+            walk.exclude(have)?;
+        }
+    }
+
+    for want in wants {
+        // Annotated tags are sent along with the commits they point to.
+        let mut want = *want;
+        while let object::Type::Tag = database.kind(&want)? {
+            objects.push(want);
+            want = *database.load_tag(&want)?.object();
+        }
+        match database.kind(&want)? {
+            object::Type::Commit => walk.include(want)?,
+            _ => objects.push(want),
+        }
+    }
+
+    let selection = walk.run()?;
+
+    // Everything in the remote's trees is shared, and its version of each
+    // path makes a good delta base for ours.
+    let mut shared = HashSet::new();
+    let mut remote = HashMap::new();
+    for id in &selection.boundary {
+        let tree = *database.load_commit(id)?.tree();
+        revwalk::walk(database, tree, path::PathBuf::new(), &mut |path, id| {
+            remote.entry(path.to_path_buf()).or_insert(id);
+            shared.insert(id)
+        })?;
+    }
+
+    let mut bases = HashSet::new();
+    for id in selection.commits {
+        let tree = *database.load_commit(&id)?.tree();
+        objects.push(id);
+        revwalk::walk(database, tree, path::PathBuf::new(), &mut |path, id| {
+            if !shared.insert(id) {
+                return false;
+            }
+            objects.push(id);
+            if let Some(base) = remote.get(path) {
+                bases.insert(*base);
+            }
+            true
+        })?;
+    }
+
+    Ok((objects, bases.into_iter().collect()))
+}
+
+#[test]
+fn advertisement() -> anyhow::Result<()> {
+    let main = "ce013625030ba8dba906f756967f9e9ca394464a";
+    let tag = "a1d3f9c0e0ba8d8e1ba1dcfa1f84a1e3b4c2a6f1";
+
+    let mut stream = pktline::Writer::new(Vec::new());
+    stream.write(
+        format!(
+            "{} HEAD\0multi_ack side-band-64k symref=HEAD:refs/heads/main\n",
+            main
+        )
+        .as_bytes(),
+    )?;
+    stream.write(format!("{} refs/heads/main\n", main).as_bytes())?;
+    stream.write(format!("{} refs/tags/v1\n", tag).as_bytes())?;
+    stream.write(format!("{} refs/tags/v1^{{}}\n", main).as_bytes())?;
+    stream.write_flush()?;
+    let stream = stream.into_inner();
+    assert_eq!(&stream[..4], b"0066");
+
+    let advertisement = Advertisement::read(&mut pktline::Reader::new(&*stream))?;
+    assert_eq!(
+        advertisement.refs,
+        vec![
+            (String::from("HEAD"), main.parse()?),
+            (String::from("refs/heads/main"), main.parse()?),
+            (String::from("refs/tags/v1"), tag.parse()?),
+        ],
+    );
+    assert!(advertisement.supports("side-band-64k"));
+    assert!(advertisement.supports("symref"));
+    assert!(!advertisement.supports("ofs-delta"));
+    assert_eq!(advertisement.head(), Some("refs/heads/main"));
+
+    let empty = Advertisement {
+        capabilities: Vec::new(),
+        ..advertisement
+    };
+    assert_eq!(empty.head(), Some("refs/heads/main"));
+    Ok(())
+}
@@ -0,0 +1,222 @@
+//! Transport to another repository on the same filesystem, which reads and
+//! writes its objects and references directly rather than speaking the pack
+//! protocol to a `git` process.
+
+use std::path;
+use std::slice;
+
+use anyhow::anyhow;
+
+use crate::config;
+use crate::object;
+use crate::references;
+use crate::transport::Advertisement;
+
+/// Capabilities of the local transport, which can't accept thin packs
+/// because it indexes pushed packs as they are.
+const CAPABILITIES: &[&str] = &["ofs-delta", "report-status", "delete-refs", "no-thin"];
+
+#[derive(Clone, Debug)]
+pub(super) struct Local {
+    path: path::PathBuf,
+}
+
+impl Local {
+    /// Interpret `url` as a local path, either `file://<path>` or a plain
+    /// path, made absolute if it exists.
+    pub(super) fn parse(url: &str) -> Option<Self> {
+        let path = match url.strip_prefix("file://") {
+            Some(path) => path,
+            None if url.contains("://") => return None,
+            None => url,
+        };
+        let path = path::Path::new(path);
+        Some(Local {
+            path: path.canonicalize().unwrap_or_else(|_| path.to_path_buf()),
+        })
+    }
+
+    pub(super) fn path(&self) -> &path::Path {
+        &self.path
+    }
+
+    /// Find the remote's `.git` directory, which is the path itself for a
+    /// bare repository, and whether it is bare.
+    fn git(&self) -> anyhow::Result<(path::PathBuf, bool)> {
+        let git = self.path.join(".git");
+        if git.is_dir() {
+            Ok((git, false))
+        } else if self.path.join("objects").is_dir() && self.path.join("HEAD").is_file() {
+            Ok((self.path.clone(), true))
+        } else {
+            Err(anyhow!(
+                "'{}' does not appear to be a git repository",
+                self.path.display()
+            ))
+        }
+    }
+
+    /// List the remote's references like `git-upload-pack` or, with
+    /// `push`, `git-receive-pack`, which leaves out `HEAD`.
+    pub(super) fn advertise(&self, push: bool) -> anyhow::Result<Advertisement> {
+        let (git, _) = self.git()?;
+        let references = crate::References::open(git);
+
+        let mut advertisement = Advertisement {
+            refs: Vec::new(),
+            capabilities: CAPABILITIES
+                .iter()
+                .map(|capability| capability.to_string())
+                .collect(),
+        };
+
+        if !push {
+            if let Some(head) = references.read_head()? {
+                advertisement.refs.push((String::from("HEAD"), head));
+            }
+            if let Some(references::Target::Symbolic(branch)) = references.store().read("HEAD")? {
+                advertisement
+                    .capabilities
+                    .push(format!("symref=HEAD:{}", branch));
+            }
+        }
+
+        for reference in references.iter_prefix("refs/")? {
+            advertisement.refs.push(reference?);
+        }
+        Ok(advertisement)
+    }
+
+    /// Build a packfile holding `wants` and everything they reach that isn't
+    /// reachable from `haves`, straight from the remote's database.
+    pub(super) fn fetch(
+        &self,
+        wants: &[object::Id],
+        haves: &[object::Id],
+    ) -> anyhow::Result<Vec<u8>> {
+        let (git, _) = self.git()?;
+        let database = crate::Database::open(git.join("objects"));
+        let (objects, _) = super::missing(&database, wants, haves)?;
+        Ok(database.build_pack(&objects)?.pack)
+    }
+
+    /// Store `pack` in the remote's database and apply each of `updates`
+    /// separately, like `git-receive-pack`.
+    ///
+    /// Like `git`, refuses to update the branch checked out in a non-bare
+    /// repository, which would leave its index and workspace out of date.
+    pub(super) fn push(
+        &self,
+        updates: &[references::Update],
+        pack: &[u8],
+    ) -> anyhow::Result<Vec<(String, Result<(), String>)>> {
+        let (git, bare) = self.git()?;
+
+        // Skip empty packs, e.g. when only creating a branch.
+        if pack.get(8..12).is_some_and(|count| count != [0; 4]) {
+            crate::Database::open(git.join("objects")).index_pack(pack.to_vec())?;
+        }
+
+        let references = crate::References::open(git.clone());
+        let checked_out = match references.store().read("HEAD")? {
+            Some(references::Target::Symbolic(branch)) if !bare => Some(branch),
+            _ => None,
+        };
+        let committer = config::Config::layered(&git)?.committer()?;
+
+        let mut statuses = Vec::new();
+        for update in updates {
+            let status = match checked_out.as_ref() == Some(&update.name) {
+                true => Err(String::from("branch is currently checked out")),
+                false => references
+                    .transaction(slice::from_ref(update), &committer, "push")
+                    .map_err(|error| error.to_string()),
+            };
+            statuses.push((update.name.clone(), status));
+        }
+        Ok(statuses)
+    }
+}
+
+#[test]
+fn local() -> anyhow::Result<()> {
+    let root = crate::util::TempDir::new();
+    let mut source = crate::Repository::new(root.join("source"));
+    let mut target = crate::Repository::new(root.join("target"));
+    source.init()?;
+    target.init()?;
+
+    let person = object::Person::new(
+        String::from("A U Thor"),
+        String::from("author@example.com"),
+        chrono::Local::now().into(),
+    );
+    let commit = |repository: &crate::Repository, data: &[u8]| -> anyhow::Result<object::Id> {
+        let database = repository.database()?;
+        let blob = database.store(&crate::Object::Blob(object::Blob::new(data.to_vec())))?;
+        let tree = object::tree::Root::new(vec![object::tree::Node {
+            path: path::PathBuf::from("file"),
+            mode: crate::meta::Mode::Regular,
+            id: blob,
+        }]);
+        let tree = database.store(&crate::Object::Tree(tree))?;
+        let parents = repository.references().read_head()?.into_iter().collect();
+        let commit = object::Commit::new(
+            tree,
+            parents,
+            person.clone(),
+            person.clone(),
+            String::from("message\n"),
+        );
+        let id = database.store(&crate::Object::Commit(commit))?;
+        repository.references().write_head(&id)?;
+        Ok(id)
+    };
+
+    let first = commit(&source, b"1")?;
+    let remote = super::Remote::new(
+        &format!("file://{}", root.join("source").display()),
+        &crate::config::Config::default(),
+    )?;
+    let advertisement = remote.advertise()?;
+    remote.fetch(
+        &target.database()?,
+        &advertisement,
+        &[first],
+        &[],
+        &mut std::io::sink(),
+    )?;
+    let fetched = target.database()?.contains(&first)?;
+    target.references().write_head(&first)?;
+
+    // Pushing to the checked-out branch is refused, but other branches
+    // are fine.
+    let second = commit(&target, b"2")?;
+    let advertisement = remote.advertise_push()?;
+    let pushes = ["refs/heads/master", "refs/heads/other"]
+        .iter()
+        .map(|name| references::Update {
+            name: name.to_string(),
+            old: advertisement
+                .refs
+                .iter()
+                .find(|(advertised, _)| advertised == name)
+                .map(|(_, id)| *id),
+            new: Some(second),
+        })
+        .collect::<Vec<_>>();
+    let (objects, _) = super::missing(&target.database()?, &[second], &[first])?;
+    let pack = target.database()?.build_pack(&objects)?.pack;
+    let statuses = remote.push(&advertisement, &pushes, &pack, &mut std::io::sink())?;
+    let pushed = source.references().read("refs/heads/other")?;
+    let contains = source.database()?.contains(&second)?;
+
+    assert!(fetched);
+    assert_eq!(advertisement.refs.len(), 1);
+    assert_eq!(objects.len(), 3);
+    assert!(statuses[0].1.is_err());
+    assert_eq!(statuses[1], (String::from("refs/heads/other"), Ok(())));
+    assert_eq!(pushed, Some(second));
+    assert!(contains);
+    Ok(())
+}
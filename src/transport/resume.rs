@@ -0,0 +1,172 @@
+//! Resumption of fetches cut off partway through the pack. The objects
+//! received so far are kept, and commits whose whole history they complete
+//! are offered as haves when retrying, so that the remote leaves them out.
+//!
+//! Other objects received are kept too, but protocol version 0 has no way
+//! to tell the remote about them, so they may be sent again.
+
+use std::collections::HashSet;
+
+use crate::database::pack;
+use crate::object;
+
+/// Objects kept from interrupted attempts at one fetch.
+#[derive(Debug, Default)]
+pub(super) struct Received {
+    /// Every object kept.
+    objects: HashSet<object::Id>,
+    /// Commits kept, in the order received.
+    commits: Vec<object::Id>,
+    /// Commits and trees kept whose every reachable object is present.
+    complete: HashSet<object::Id>,
+}
+
+impl Received {
+    /// Store the objects at the start of `pack`, an interrupted transfer,
+    /// returning how many were recovered.
+    pub(super) fn keep(
+        &mut self,
+        database: &crate::Database,
+        pack: &[u8],
+    ) -> anyhow::Result<usize> {
+        let salvaged = pack::salvage(pack);
+        let batch = database.batch();
+        for (id, bytes) in &salvaged {
+            database.backend().write(id, bytes)?;
+            if !self.objects.insert(*id) {
+                continue;
+            }
+            if bytes.starts_with(b"commit ") {
+                self.commits.push(*id);
+            }
+        }
+        batch.commit()?;
+        Ok(salvaged.len())
+    }
+
+    /// Commits kept so far whose history is complete, in the order received,
+    /// which for packs sent by `git` is newest first.
+    pub(super) fn haves(&mut self, database: &crate::Database) -> anyhow::Result<Vec<object::Id>> {
+        // Parents usually arrive after their children, so visit commits in
+        // reverse, repeating until nothing changes in case they didn't.
+        loop {
+            let mut changed = false;
+            for index in (0..self.commits.len()).rev() {
+                let id = self.commits[index];
+                if self.complete.contains(&id) {
+                    continue;
+                }
+                let commit = database.load_commit(&id)?;
+                let mut complete = self.is_complete_tree(database, *commit.tree())?;
+                for parent in commit.parents() {
+                    complete &= self.is_complete_commit(database, parent)?;
+                }
+                if complete {
+                    self.complete.insert(id);
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        Ok(self
+            .commits
+            .iter()
+            .filter(|id| self.complete.contains(id))
+            .copied()
+            .collect())
+    }
+
+    /// Check whether the history of `id` is complete, assuming that of
+    /// commits the repository had before this fetch is.
+    fn is_complete_commit(
+        &self,
+        database: &crate::Database,
+        id: &object::Id,
+    ) -> anyhow::Result<bool> {
+        match self.objects.contains(id) {
+            true => Ok(self.complete.contains(id)),
+            false => database.contains(id),
+        }
+    }
+
+    fn is_complete_tree(
+        &mut self,
+        database: &crate::Database,
+        id: object::Id,
+    ) -> anyhow::Result<bool> {
+        if self.complete.contains(&id) || !self.objects.contains(&id) && database.contains(&id)? {
+            return Ok(true);
+        }
+        if !database.contains(&id)? {
+            return Ok(false);
+        }
+        for node in &database.load_tree(&id)? {
+            let complete = match node.mode.is_directory() {
+                true => self.is_complete_tree(database, node.id)?,
+                false => database.contains(&node.id)?,
+            };
+            if !complete {
+                return Ok(false);
+            }
+        }
+        self.complete.insert(id);
+        Ok(true)
+    }
+}
+
+#[test]
+fn complete() -> anyhow::Result<()> {
+    use std::path;
+
+    let mut repository = crate::Repository::memory(path::PathBuf::new());
+    repository.init()?;
+    let database = repository.database()?;
+
+    let mut objects = Vec::new();
+    let mut store = |object: crate::Object| {
+        let bytes = object.to_bytes();
+        let id = object::Id::hash(&bytes);
+        objects.push((id, bytes));
+        id
+    };
+    let person = object::Person::new(
+        String::from("A U Thor"),
+        String::from("author@example.com"),
+        object::Person::parse_time("@1 +0000")?,
+    );
+    let mut commit = |data: &str, parents: Vec<object::Id>| {
+        let blob = store(crate::Object::Blob(object::Blob::new(
+            data.as_bytes().to_vec(),
+        )));
+        let tree = store(crate::Object::Tree(object::tree::Root::new(vec![
+            object::tree::Node::new(
+                path::PathBuf::from("file"),
+                blob,
+                crate::meta::Mode::Regular,
+            ),
+        ])));
+        store(crate::Object::Commit(object::Commit::new(
+            tree,
+            parents,
+            person.clone(),
+            person.clone(),
+            String::from("message\n"),
+        )))
+    };
+
+    // Only the second commit's blob is missing.
+    let first = commit("first", Vec::new());
+    let second = commit("second", vec![first]);
+    let missing = objects.remove(3).0;
+    let pack = pack::Packfile::build(&objects)?.pack;
+
+    let mut received = Received::default();
+    assert_eq!(received.keep(&database, &pack)?, objects.len());
+    assert_eq!(received.haves(&database)?, vec![first]);
+    assert!(!database.contains(&missing)?);
+    assert!(database.contains(&second)?);
+    Ok(())
+}
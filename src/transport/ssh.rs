@@ -0,0 +1,231 @@
+//! Transport that runs `git-upload-pack` or `git-receive-pack` on a remote
+//! host over `ssh`, speaking the pack protocol over the child's standard
+//! input and output.
+//!
+//! Unlike smart HTTP, the connection is stateful: the advertisement and the
+//! request that follows it share a single `ssh` session, which is kept
+//! between [`Ssh::advertise`] and [`Ssh::post`].
+//!
+//! The `ssh` command can be overridden with `GIT_SSH_COMMAND`, which is run
+//! by the shell, or `GIT_SSH`, which is run directly.
+
+use std::env;
+use std::io;
+use std::io::Write as _;
+use std::process;
+use std::sync;
+use std::thread;
+
+use anyhow::anyhow;
+
+use crate::protocol::pktline;
+use crate::transport::Advertisement;
+
+pub(super) struct Ssh {
+    /// Host to connect to, including any `user@` prefix.
+    host: String,
+    port: Option<String>,
+    path: String,
+    /// Session whose advertisement has been read, waiting for a request.
+    session: sync::Mutex<Option<Session>>,
+}
+
+struct Session {
+    service: &'static str,
+    stdin: process::ChildStdin,
+    reader: pktline::Reader<Box<dyn io::Read + Send>>,
+}
+
+impl Ssh {
+    /// Interpret `url` as `ssh://[user@]host[:port]/path`, or the scp-like
+    /// `[user@]host:path`, which has no slash before its first colon.
+    pub(super) fn parse(url: &str) -> Option<Self> {
+        let stripped = ["ssh://", "git+ssh://", "ssh+git://"]
+            .iter()
+            .find_map(|scheme| url.strip_prefix(scheme));
+
+        let (host, port, path) = match stripped {
+            Some(rest) => {
+                let (authority, path) = rest.split_at(rest.find('/')?);
+                // Like `git`, `ssh://host/~user/path` is relative to a home
+                // directory.
+                let path = path
+                    .strip_prefix("/~")
+                    .map_or(path.to_owned(), |home| format!("~{}", home));
+                match authority.rsplit_once(':') {
+                    Some((host, port)) => (host, Some(port.to_owned()), path),
+                    None => (authority, None, path),
+                }
+            }
+            None if url.contains("://") => return None,
+            None => {
+                let (host, path) = url.split_once(':')?;
+                if host.is_empty() || host.contains('/') {
+                    return None;
+                }
+                (host, None, path.to_owned())
+            }
+        };
+
+        if host.is_empty() || host.starts_with('-') || path.is_empty() {
+            return None;
+        }
+
+        Some(Ssh {
+            host: host.to_owned(),
+            port,
+            path,
+            session: sync::Mutex::new(None),
+        })
+    }
+
+    /// Start `service` on the remote and read its advertisement, keeping
+    /// the session open for [`Ssh::post`].
+    pub(super) fn advertise(&self, service: &'static str) -> anyhow::Result<Advertisement> {
+        let mut session = self.connect(service)?;
+        let advertisement = Advertisement::read(&mut session.reader)?;
+        *self
+            .session
+            .lock()
+            .expect("[INTERNAL ERROR]: poisoned ssh session") = Some(session);
+        Ok(advertisement)
+    }
+
+    /// Send `request` to `service`, returning a reader for the response.
+    /// Reuses the session left by [`Ssh::advertise`] if it ran the same
+    /// service, and otherwise starts one and skips its advertisement.
+    pub(super) fn post(
+        &self,
+        service: &'static str,
+        request: &[u8],
+    ) -> anyhow::Result<pktline::Reader<Box<dyn io::Read + Send>>> {
+        let session = self
+            .session
+            .lock()
+            .expect("[INTERNAL ERROR]: poisoned ssh session")
+            .take()
+            .filter(|session| session.service == service);
+
+        let Session {
+            mut stdin, reader, ..
+        } = match session {
+            Some(session) => session,
+            None => {
+                let mut session = self.connect(service)?;
+                Advertisement::read(&mut session.reader)?;
+                session
+            }
+        };
+
+        // Write from another thread, so that neither side blocks on a full
+        // pipe if the remote responds before reading the whole request.
+        // Closing standard input afterward ends the session.
+        let request = request.to_vec();
+        thread::spawn(move || stdin.write_all(&request));
+        Ok(reader)
+    }
+
+    fn connect(&self, service: &'static str) -> anyhow::Result<Session> {
+        let remote = format!("{} {}", service, quote(&self.path));
+
+        let mut command = match (env::var("GIT_SSH_COMMAND"), env::var_os("GIT_SSH")) {
+            (Ok(ssh), _) => {
+                // Pass the arguments as positional parameters to the shell.
+                let mut command = process::Command::new("sh");
+                command.arg("-c").arg(format!("{} \"$@\"", ssh)).arg(ssh);
+                command
+            }
+            (Err(_), Some(ssh)) => process::Command::new(ssh),
+            (Err(_), None) => process::Command::new("ssh"),
+        };
+        if let Some(port) = &self.port {
+            command.arg("-p").arg(port);
+        }
+        command.arg(&self.host).arg(remote);
+
+        let mut child = command
+            .stdin(process::Stdio::piped())
+            .stdout(process::Stdio::piped())
+            .spawn()
+            .map_err(|error| anyhow!("Unable to run ssh: {}", error))?;
+
+        let stdin = child
+            .stdin
+            .take()
+            .expect("[INTERNAL ERROR]: missing ssh stdin");
+        let stdout = child
+            .stdout
+            .take()
+            .expect("[INTERNAL ERROR]: missing ssh stdout");
+        Ok(Session {
+            service,
+            stdin,
+            reader: pktline::Reader::new(Box::new(Stdout { child, stdout })),
+        })
+    }
+}
+
+impl Drop for Ssh {
+    fn drop(&mut self) {
+        // Tell the remote that no request is coming, like `git` does when
+        // there is nothing to fetch or push.
+        if let Some(mut session) = self.session.get_mut().ok().and_then(Option::take) {
+            pktline::Writer::new(&mut session.stdin).write_flush().ok();
+        }
+    }
+}
+
+/// Output of an `ssh` process, which reports its exit status at the end.
+struct Stdout {
+    child: process::Child,
+    stdout: process::ChildStdout,
+}
+
+impl io::Read for Stdout {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let read = self.stdout.read(buffer)?;
+        if read == 0 && !buffer.is_empty() {
+            let status = self.child.wait()?;
+            if !status.success() {
+                return Err(io::Error::other(format!("ssh exited with {}", status)));
+            }
+        }
+        Ok(read)
+    }
+}
+
+/// Quote `path` for the remote shell, like `git`'s `sq_quote`.
+fn quote(path: &str) -> String {
+    format!("'{}'", path.replace('\'', "'\\''"))
+}
+
+#[test]
+fn parse() {
+    let parsed =
+        |url| Ssh::parse(url).map(|ssh| (ssh.host.clone(), ssh.port.clone(), ssh.path.clone()));
+    let expected = |host: &str, port: Option<&str>, path: &str| {
+        Some((host.to_owned(), port.map(String::from), path.to_owned()))
+    };
+
+    assert_eq!(
+        parsed("ssh://git@example.com/user/repo.git"),
+        expected("git@example.com", None, "/user/repo.git"),
+    );
+    assert_eq!(
+        parsed("git+ssh://example.com:2222/repo"),
+        expected("example.com", Some("2222"), "/repo"),
+    );
+    assert_eq!(
+        parsed("ssh://example.com/~user/repo"),
+        expected("example.com", None, "~user/repo"),
+    );
+    assert_eq!(
+        parsed("git@example.com:user/repo.git"),
+        expected("git@example.com", None, "user/repo.git"),
+    );
+    assert_eq!(parsed("https://example.com/repo"), None);
+    assert_eq!(parsed("relative/path:with/colon"), None);
+    assert_eq!(parsed("/absolute/path"), None);
+    assert_eq!(parsed("-oProxyCommand=evil:repo"), None);
+    assert_eq!(quote("it's"), "'it'\\''s'");
+}
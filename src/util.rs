@@ -2,9 +2,10 @@ use std::borrow;
 use std::cmp;
 use std::hash;
 use std::ops;
-use std::os::unix::ffi::OsStrExt as _;
 use std::path;
 
+use crate::platform::OsStrExt as _;
+
 pub mod hex;
 
 pub trait Tap: Sized {
@@ -69,7 +70,7 @@ impl<'a> PartialOrd for dyn Key + 'a {
 
 impl<'a> Ord for dyn Key + 'a {
     fn cmp(&self, other: &Self) -> cmp::Ordering {
-        self.key().cmp(&other.key())
+        self.key().cmp(other.key())
     }
 }
 
@@ -99,7 +100,7 @@ impl PartialOrd for PathBuf {
 
 impl Ord for PathBuf {
     fn cmp(&self, other: &Self) -> cmp::Ordering {
-        self.key().cmp(&other.key())
+        self.key().cmp(other.key())
     }
 }
 
@@ -127,3 +128,54 @@ impl ops::DerefMut for PathBuf {
         &mut self.0
     }
 }
+
+/// Uniquely named path under the system temporary directory for tests,
+/// removed along with everything in it when dropped, even if the test
+/// fails partway through. The directory itself is left for the test to
+/// create, if needed.
+#[cfg(test)]
+#[derive(Debug)]
+pub struct TempDir(path::PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[cfg(test)]
+impl Default for TempDir {
+    fn default() -> Self {
+        use rand::Rng as _;
+
+        let name = rand::thread_rng()
+            .sample_iter(rand::distributions::Alphanumeric)
+            .take(10)
+            .map(char::from)
+            .collect::<String>();
+        TempDir(std::env::temp_dir().join(format!("grit-{}", name)))
+    }
+}
+
+#[cfg(test)]
+impl ops::Deref for TempDir {
+    type Target = path::Path;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl AsRef<path::Path> for TempDir {
+    fn as_ref(&self) -> &path::Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
@@ -1,26 +1,186 @@
+use std::borrow::Cow;
+use std::cmp;
+use std::ffi;
 use std::fs;
 use std::io;
 use std::path;
-use std::rc::Rc;
+use std::sync::Arc;
 
+use crate::attributes;
 use crate::meta;
+use crate::platform;
+use crate::platform::OsStrExt as _;
+use crate::platform::OsStringExt as _;
 use crate::util;
 use crate::util::Tap as _;
 
 #[derive(Debug)]
 pub struct Workspace {
-    root: Rc<path::Path>,
+    root: Arc<path::Path>,
+    /// Whether the filesystem supports symbolic links (`core.symlinks`).
+    symlinks: bool,
+    /// Whether the filesystem preserves the executable bit
+    /// (`core.fileMode`).
+    filemode: bool,
+    /// Number of threads writing files during large checkouts
+    /// (`checkout.workers`).
+    workers: usize,
+    /// Attributes converting line endings between the workspace and the
+    /// repository.
+    attributes: attributes::Attributes,
 }
 
 impl Workspace {
     pub fn new(root: path::PathBuf) -> Self {
         Workspace {
-            root: Rc::from(root),
+            root: Arc::from(root),
+            symlinks: true,
+            filemode: true,
+            workers: 1,
+            attributes: attributes::Attributes::default(),
         }
     }
 
+    /// Convert files with `attributes` as they are read and written.
+    pub fn set_attributes(&mut self, attributes: attributes::Attributes) {
+        self.attributes = attributes;
+    }
+
+    pub fn attributes(&self) -> &attributes::Attributes {
+        &self.attributes
+    }
+
+    /// Check out symbolic links as plain files containing their target,
+    /// for filesystems without symbolic link support.
+    pub fn set_symlinks(&mut self, symlinks: bool) {
+        self.symlinks = symlinks;
+    }
+
+    /// Ignore the executable bit of files in the workspace, for filesystems
+    /// that don't preserve it.
+    pub fn set_filemode(&mut self, filemode: bool) {
+        self.filemode = filemode;
+    }
+
+    /// Split large checkouts between `workers` threads, or write files
+    /// sequentially if `workers` is one.
+    pub fn set_workers(&mut self, workers: usize) {
+        self.workers = cmp::max(workers, 1);
+    }
+
+    pub fn workers(&self) -> usize {
+        self.workers
+    }
+
+    /// Adjust workspace `metadata` for a file recorded in the index with
+    /// mode `indexed`. Without symbolic link support, links are checked out
+    /// as regular files but keep their symbolic link mode, like `git`.
+    /// Likewise, without `core.fileMode`, files keep their indexed
+    /// executable bit.
+    pub fn normalize(&self, mut metadata: meta::Metadata, indexed: meta::Mode) -> meta::Metadata {
+        let executable = |mode| matches!(mode, meta::Mode::Regular | meta::Mode::Executable);
+        if !self.symlinks && indexed.is_symlink() && metadata.mode == meta::Mode::Regular {
+            metadata.mode = meta::Mode::Symlink;
+        } else if !self.filemode && executable(indexed) && executable(metadata.mode) {
+            metadata.mode = indexed;
+        }
+        metadata
+    }
+
+    /// Read the contents of the file at `relative` as they would be stored,
+    /// with line endings normalized according to its attributes, or the
+    /// target of the symbolic link at `relative`.
     pub fn read(&self, relative: &path::Path) -> io::Result<Vec<u8>> {
-        fs::read(self.root.join(relative))
+        let path = self.root.join(relative);
+        match fs::symlink_metadata(&path)?.file_type().is_symlink() {
+            true => fs::read_link(&path).map(|target| target.into_os_string().into_vec()),
+            false => self.clean(relative, fs::read(path)?),
+        }
+    }
+
+    /// Convert the contents `data` of the regular file at `relative` as
+    /// they would be stored, normalizing line endings according to its
+    /// attributes.
+    pub fn clean(&self, relative: &path::Path, data: Vec<u8>) -> io::Result<Vec<u8>> {
+        let clean = match self.attributes.clean(relative, &data)? {
+            Cow::Borrowed(_) => None,
+            Cow::Owned(clean) => Some(clean),
+        };
+        Ok(clean.unwrap_or(data))
+    }
+
+    /// Read metadata for `relative`, without following symbolic links.
+    pub fn metadata(&self, relative: &path::Path) -> io::Result<meta::Metadata> {
+        fs::symlink_metadata(self.root.join(relative)).map(meta::Metadata::from)
+    }
+
+    /// Write `data` to the file at `relative`, creating parent directories
+    /// and setting the executable bit according to `mode`, and converting
+    /// line endings according to its attributes. Symbolic links are created
+    /// with `data` as their target.
+    pub fn write(&self, relative: &path::Path, data: &[u8], mode: meta::Mode) -> io::Result<()> {
+        let path = self.root.join(relative);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // Never write through an existing symbolic link.
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.file_type().is_symlink() || mode.is_symlink() => {
+                fs::remove_file(&path)?
+            }
+            // Empty directories hold nothing trackable, so `git` replaces
+            // them too. Fails if any files turn out to be inside.
+            Ok(metadata) if metadata.is_dir() => remove_empty(&path)?,
+            Ok(_) => (),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => (),
+            Err(error) => return Err(error),
+        }
+
+        if mode.is_symlink() && self.symlinks {
+            return platform::symlink(&ffi::OsStr::from_bytes(data), &path);
+        }
+
+        match mode.is_symlink() {
+            true => fs::write(&path, data)?,
+            false => fs::write(&path, self.attributes.smudge(relative, data)?)?,
+        }
+        platform::set_executable(&path, mode == meta::Mode::Executable)
+    }
+
+    /// Remove the file at `relative`, ignoring files that are already gone.
+    pub fn remove(&self, relative: &path::Path) -> io::Result<()> {
+        match fs::remove_file(self.root.join(relative)) {
+            Ok(()) => Ok(()),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Remove `relative` and its ancestors for as long as they are empty
+    /// directories, never removing the workspace root.
+    pub fn remove_empty_directories(&self, relative: &path::Path) {
+        for ancestor in relative.ancestors() {
+            if ancestor == path::Path::new("") || fs::remove_dir(self.root.join(ancestor)).is_err()
+            {
+                break;
+            }
+        }
+    }
+
+    /// Whether directory `relative` or any directory beneath it holds a
+    /// file. Directories without files can't be tracked.
+    pub fn contains_files(&self, relative: &path::Path) -> io::Result<bool> {
+        fn recurse(path: &path::Path) -> io::Result<bool> {
+            for entry in fs::read_dir(path)? {
+                let entry = entry?;
+                if !entry.file_type()?.is_dir() || recurse(&entry.path())? {
+                    return Ok(true);
+                }
+            }
+            Ok(false)
+        }
+        recurse(&self.root.join(relative))
     }
 
     pub fn root(&self) -> &path::Path {
@@ -35,17 +195,17 @@ impl Workspace {
         self.walk(WalkTree::new, relative)
     }
 
-    fn walk<F: for<'a> FnOnce(Rc<path::Path>, &'a path::Path) -> io::Result<W>, W>(
+    fn walk<F: for<'a> FnOnce(Arc<path::Path>, &'a path::Path) -> io::Result<W>, W>(
         &self,
         walker: F,
         relative: &path::Path,
     ) -> io::Result<util::Or<WalkFile, W>> {
-        let root = Rc::clone(&self.root);
+        let root = Arc::clone(&self.root);
         let path = root.join(relative);
-        let metadata = fs::metadata(&path)?;
+        let metadata = fs::symlink_metadata(&path)?;
         let file_type = metadata.file_type();
 
-        if file_type.is_file() {
+        if file_type.is_file() || file_type.is_symlink() {
             Entry {
                 root,
                 path,
@@ -58,14 +218,17 @@ impl Workspace {
         } else if file_type.is_dir() {
             walker(root, &path).map(util::Or::R)
         } else {
-            unimplemented!("Unsupported file type: {:?}", file_type);
+            Err(io::Error::other(format!(
+                "Unsupported file type: {}",
+                path.display()
+            )))
         }
     }
 }
 
 #[derive(Clone, Debug)]
 pub struct Entry {
-    root: Rc<path::Path>,
+    root: Arc<path::Path>,
     pub path: path::PathBuf,
     pub metadata: meta::Metadata,
 }
@@ -88,14 +251,14 @@ impl Entry {
 
 #[derive(Debug)]
 pub struct WalkList {
-    root: Rc<path::Path>,
+    root: Arc<path::Path>,
     iter: fs::ReadDir,
 }
 
 impl WalkList {
-    pub fn new(root: Rc<path::Path>, path: &path::Path) -> io::Result<Self> {
+    pub fn new(root: Arc<path::Path>, path: &path::Path) -> io::Result<Self> {
         Ok(WalkList {
-            root: Rc::clone(&root),
+            root: Arc::clone(&root),
             iter: fs::read_dir(path)?,
         })
     }
@@ -116,6 +279,9 @@ impl Iterator for WalkList {
                 {
                     continue;
                 }
+                // Like `git`, skip FIFOs, sockets, and devices, which can't
+                // be tracked.
+                Ok(entry) if entry.file_type().is_ok_and(|kind| !is_supported(kind)) => continue,
                 Ok(entry) => break entry,
                 Err(error) => return Some(Err(error)),
             };
@@ -127,7 +293,7 @@ impl Iterator for WalkList {
         };
 
         Some(Ok(Entry {
-            root: Rc::clone(&self.root),
+            root: Arc::clone(&self.root),
             path: entry.path(),
             metadata: meta::Metadata::from(metadata),
         }))
@@ -146,14 +312,14 @@ impl Iterator for WalkFile {
 
 #[derive(Debug)]
 pub struct WalkTree {
-    root: Rc<path::Path>,
+    root: Arc<path::Path>,
     stack: Vec<fs::ReadDir>,
 }
 
 impl WalkTree {
-    fn new(root: Rc<path::Path>, path: &path::Path) -> io::Result<Self> {
+    fn new(root: Arc<path::Path>, path: &path::Path) -> io::Result<Self> {
         Ok(WalkTree {
-            root: Rc::clone(&root),
+            root: Arc::clone(&root),
             stack: vec![fs::read_dir(path)?],
         })
     }
@@ -173,6 +339,11 @@ impl Iterator for WalkTree {
                 {
                     continue;
                 }
+                // Like `git`, skip FIFOs, sockets, and devices, which can't
+                // be tracked.
+                Some(Ok(entry)) if entry.file_type().is_ok_and(|kind| !is_supported(kind)) => {
+                    continue
+                }
                 Some(Ok(entry)) => break entry,
                 Some(Err(error)) => return Some(Err(error)),
                 None => {
@@ -189,19 +360,15 @@ impl Iterator for WalkTree {
         let file_type = metadata.file_type();
 
         let entry = Entry {
-            root: Rc::clone(&self.root),
+            root: Arc::clone(&self.root),
             path: entry.path(),
             metadata: meta::Metadata::from(&metadata),
         };
 
-        if file_type.is_file() {
+        if file_type.is_file() || file_type.is_symlink() {
             return Some(Ok(entry));
         }
 
-        if !file_type.is_dir() {
-            unimplemented!("Unsupported file type: {:?}", file_type);
-        }
-
         match fs::read_dir(&entry.path) {
             Ok(iter) => self.stack.push(iter),
             Err(error) => return Some(Err(error)),
@@ -210,3 +377,95 @@ impl Iterator for WalkTree {
         Some(Ok(entry))
     }
 }
+
+/// Whether files of `file_type` can be tracked, or walked into.
+fn is_supported(file_type: fs::FileType) -> bool {
+    file_type.is_file() || file_type.is_symlink() || file_type.is_dir()
+}
+
+/// Remove directory `path` and every directory beneath it, failing if any
+/// of them hold files.
+fn remove_empty(path: &path::Path) -> io::Result<()> {
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            remove_empty(&entry.path())?;
+        }
+    }
+    fs::remove_dir(path)
+}
+
+#[cfg(unix)]
+#[test]
+fn unsupported() -> anyhow::Result<()> {
+    fn paths(entries: impl Iterator<Item = io::Result<Entry>>) -> io::Result<Vec<path::PathBuf>> {
+        let mut paths = entries
+            .map(|entry| Ok(entry?.relative_path().to_path_buf()))
+            .collect::<io::Result<Vec<_>>>()?;
+        paths.sort();
+        Ok(paths)
+    }
+
+    let root = crate::util::TempDir::new();
+    fs::create_dir_all(root.join("directory"))?;
+    fs::write(root.join("file"), b"file")?;
+    for fifo in ["fifo", "directory/fifo"] {
+        let fifo = ffi::CString::new(root.join(fifo).as_os_str().as_bytes())?;
+        assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o644) }, 0);
+    }
+
+    let workspace = Workspace::new(root.to_path_buf());
+    let walked = paths(workspace.walk_tree(path::Path::new(""))?)?;
+    let listed = paths(workspace.walk_list(path::Path::new(""))?)?;
+    let named = workspace.walk_list(path::Path::new("fifo")).is_err();
+
+    assert_eq!(
+        walked,
+        [path::Path::new("directory"), path::Path::new("file")]
+    );
+    assert_eq!(listed, walked);
+    assert!(named);
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn symlink() -> anyhow::Result<()> {
+    use crate::diff::tree;
+    use crate::object;
+
+    let root = crate::util::TempDir::new();
+    let mut repository = crate::Repository::new(root.to_path_buf());
+    repository.init()?;
+
+    let database = repository.database()?;
+    let workspace = repository.workspace()?;
+    let mut index = repository.index()?;
+    let check_stat = meta::CheckStat::default();
+
+    // Added like `grit add` does, storing the target as the blob.
+    let link = path::Path::new("link");
+    std::os::unix::fs::symlink("target", root.join(link))?;
+    let metadata = workspace.metadata(link)?;
+    let blob = object::Blob::new(workspace.read(link)?);
+    index.insert(
+        metadata,
+        database.store(&crate::Object::Blob(blob))?,
+        link.to_path_buf(),
+    );
+    let tree = index.write_tree(&database)?;
+    let entry = tree::flatten(&database, &tree)?[&util::PathBuf(link.to_path_buf())];
+    let data = database.load_blob(&entry.id)?.into_data();
+    let clean = crate::migration::snapshot(&database, &index, &workspace, check_stat)? == tree;
+
+    // Checking out the tree again recreates the link.
+    fs::remove_file(root.join(link))?;
+    crate::migration::reset(&database, &mut index, &workspace, check_stat, &tree)?;
+    let target = fs::read_link(root.join(link))?;
+
+    assert_eq!(entry.mode.as_str(), "120000");
+    assert_eq!(data, b"target");
+    assert!(clean);
+    assert_eq!(target, path::Path::new("target"));
+    Ok(())
+}